@@ -1,10 +1,48 @@
-use criterion::{criterion_group, criterion_main, Criterion};
-use serde_json::{json, Value};
+use criterion::{Criterion, criterion_group, criterion_main};
+use futures::TryStreamExt;
+use serde_json::{Value, json};
+use std::alloc::{GlobalAlloc, Layout, System};
 use std::hint::black_box;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::runtime::Runtime;
 use tokio::sync::mpsc;
+use tokio_util::codec::FramedRead;
+use tokio_util::sync::CancellationToken;
 
+use lsp_proxy::batch::BatchTracker;
+use lsp_proxy::codec::LspCodec;
 use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::tasks::{HandlerLimiter, receive_data_frontend, send_data_frontend};
+
+/// 统计分配次数的分配器，用来报告每条消息的分配次数。
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// `f` 运行期间平均每条消息的分配次数。
+fn allocations_per_message(messages: usize, f: impl FnOnce()) -> f64 {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / messages as f64
+}
 
 fn bench_json_parsing(c: &mut Criterion) {
     println!("Starting bench_json_parsing");
@@ -27,9 +65,9 @@ fn bench_json_parsing(c: &mut Criterion) {
 fn bench_dispatcher_handle(c: &mut Criterion) {
     println!("Starting bench_dispatcher_handle");
     // 跳过async测试，使用同步模拟
-    let (backend_tx, _) = mpsc::unbounded_channel::<String>();
-    let (frontend_tx, _) = mpsc::unbounded_channel::<String>();
-    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let (backend_tx, _) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _) = mpsc::unbounded_channel::<Message>();
+    let _dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
 
     let rpc = json!({
         "jsonrpc": "2.0",
@@ -80,10 +118,179 @@ fn bench_message_formatting(c: &mut Criterion) {
     });
 }
 
+fn bench_frame_decoding(c: &mut Criterion) {
+    println!("Starting bench_frame_decoding");
+    let body = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "textDocument/hover",
+        "params": {
+            "textDocument": {"uri": "file:///test.cpp"},
+            "position": {"line": 10, "character": 5}
+        }
+    });
+    let frame = Dispatcher::format_lsp_message(&body).unwrap();
+    let (_, body_text) = frame.split_once("\r\n\r\n").unwrap();
+    let input = frame.repeat(100);
+    let runtime = Runtime::new().unwrap();
+
+    let decode_all = || {
+        runtime.block_on(async {
+            let mut reader = FramedRead::new(input.as_bytes(), LspCodec::default());
+            while let Some(message) = reader.try_next().await.unwrap() {
+                black_box(message);
+            }
+        })
+    };
+    // 分帧本身的分配：总数减去解析 JSON 消息体所需的分配
+    let total = allocations_per_message(100, decode_all);
+    let json = allocations_per_message(100, || {
+        for _ in 0..100 {
+            black_box(serde_json::from_str::<Value>(body_text).unwrap());
+        }
+    });
+    println!(
+        "frame_decoding: {:.2} allocations per message, {:.2} of them for framing",
+        total,
+        total - json
+    );
+
+    c.bench_function("frame_decoding_100", |b| b.iter(decode_all));
+}
+
+/// 在内存中运行的完整代理：前端消息经过解析、调度器和写出任务，后端是原样回显参数的空后端。
+///
+/// 测量的是代理本身每条消息的开销（分帧、解析、通道转发），不包含任何真实后端的处理时间。
+struct NullBackendPipeline {
+    client_writer: WriteHalf<DuplexStream>,
+    client_reader: FramedRead<ReadHalf<DuplexStream>, LspCodec>,
+}
+
+impl NullBackendPipeline {
+    async fn start() -> Self {
+        let (client, proxy_frontend) = tokio::io::duplex(1 << 20);
+        let (proxy_read, proxy_write) = tokio::io::split(proxy_frontend);
+        let (client_read, client_writer) = tokio::io::split(client);
+
+        let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+        let (frontend_tx, frontend_rx) = mpsc::unbounded_channel::<Message>();
+        let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+        let batches = Arc::new(BatchTracker::new());
+        tokio::spawn(send_data_frontend(
+            proxy_write,
+            frontend_rx,
+            Arc::clone(&batches),
+            CancellationToken::new(),
+            None,
+        ));
+        tokio::spawn(receive_data_frontend(
+            proxy_read,
+            Arc::clone(&dispatcher),
+            Arc::new(HandlerLimiter::new(15, dispatcher.metrics())),
+            batches,
+            None,
+        ));
+
+        // 空后端：读取代理写出的字节，按请求回显参数
+        let (backend, null_backend) = tokio::io::duplex(1 << 20);
+        let (backend_read, mut backend_write) = tokio::io::split(backend);
+        let (null_read, mut null_write) = tokio::io::split(null_backend);
+        tokio::spawn(async move {
+            while let Some(message) = backend_rx.recv().await {
+                let message = message.to_lsp_string().unwrap();
+                backend_write.write_all(message.as_bytes()).await.unwrap();
+            }
+        });
+        tokio::spawn(async move {
+            let mut reader = FramedRead::new(null_read, LspCodec::default());
+            while let Ok(Some(rpc)) = reader.try_next().await {
+                if let Some(id) = rpc.get("id") {
+                    let response = json!({"jsonrpc": "2.0", "id": id, "result": rpc["params"]});
+                    let message = Dispatcher::format_lsp_message(&response).unwrap();
+                    null_write.write_all(message.as_bytes()).await.unwrap();
+                }
+            }
+        });
+        tokio::spawn(async move {
+            let mut reader = FramedRead::new(backend_read, LspCodec::default());
+            while let Ok(Some(rpc)) = reader.try_next().await {
+                dispatcher.handle_from_shard(0, rpc).await.unwrap();
+            }
+        });
+
+        Self {
+            client_writer,
+            client_reader: FramedRead::new(client_read, LspCodec::default()),
+        }
+    }
+
+    /// 发出 `count` 个请求，再读回全部响应。
+    async fn round_trips(&mut self, first_id: u64, count: u64) {
+        for id in first_id..first_id + count {
+            let request = json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "textDocument/hover",
+                "params": {
+                    "textDocument": {"uri": "file:///test.cpp"},
+                    "position": {"line": 10, "character": 5}
+                }
+            });
+            let message = Dispatcher::format_lsp_message(&request).unwrap();
+            self.client_writer
+                .write_all(message.as_bytes())
+                .await
+                .unwrap();
+        }
+        for _ in 0..count {
+            black_box(self.client_reader.try_next().await.unwrap().unwrap());
+        }
+    }
+}
+
+fn bench_null_backend_pipeline(c: &mut Criterion) {
+    println!("Starting bench_null_backend_pipeline");
+    let runtime = Runtime::new().unwrap();
+    let mut pipeline = runtime.block_on(NullBackendPipeline::start());
+    let mut next_id = 1;
+
+    // 一次一个请求：每条消息的完整往返延迟
+    c.bench_function("pipeline_round_trip", |b| {
+        b.iter_custom(|iters| {
+            runtime.block_on(async {
+                let start = Instant::now();
+                for _ in 0..iters {
+                    pipeline.round_trips(next_id, 1).await;
+                    next_id += 1;
+                }
+                start.elapsed()
+            })
+        });
+    });
+
+    // 连续发出 100 个请求：流水线下每条消息的平均开销
+    c.bench_function("pipeline_100_in_flight", |b| {
+        b.iter_custom(|iters| {
+            runtime.block_on(async {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    pipeline.round_trips(next_id, 100).await;
+                    elapsed += start.elapsed();
+                    next_id += 100;
+                }
+                elapsed
+            })
+        });
+    });
+}
+
 criterion_group!(
     benches,
     bench_json_parsing,
     bench_dispatcher_handle,
-    bench_message_formatting
+    bench_message_formatting,
+    bench_frame_decoding,
+    bench_null_backend_pipeline
 );
-criterion_main!(benches);
\ No newline at end of file
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+//! 任意 JSON 交给调度器的前端和后端入口：处理可以失败，但不能 panic。
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use serde_json::Value;
+use std::sync::{Arc, LazyLock};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+
+static RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+});
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(rpc) = serde_json::from_slice::<Value>(data) else {
+        return;
+    };
+    RUNTIME.block_on(async {
+        // 每个输入使用新的调度器，状态不会在输入之间累积
+        let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+        let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+        let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+        let _ = dispatcher.handle_from_frontend(rpc.clone()).await;
+        let _ = dispatcher.handle_from_backend(rpc).await;
+    });
+});
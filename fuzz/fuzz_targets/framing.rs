@@ -0,0 +1,19 @@
+//! 任意字节作为 LSP 消息流：解析不能 panic，也不能按伪造的 `Content-Length` 分配内存。
+
+#![no_main]
+
+use futures::TryStreamExt;
+use libfuzzer_sys::fuzz_target;
+use lsp_proxy::codec::LspCodec;
+use tokio_util::codec::FramedRead;
+
+fuzz_target!(|data: &[u8]| {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut reader = FramedRead::new(data, LspCodec::default());
+        // 读到 EOF 或者遇到错误为止
+        while let Ok(Some(_)) = reader.try_next().await {}
+    });
+});
@@ -0,0 +1,251 @@
+//! # 管理控制台模块
+//!
+//! 在 unix 套接字上提供按行交互的管理控制台，运维人员用 `codefuse admin attach` 连接运行中的代理，
+//! 查看后端状态、注册的处理器和等待响应的请求，重启后端，或者查看代理保存的文档内容。
+//! 控制台可以重启后端和读取文档，套接字只允许当前用户访问。
+
+use anyhow::{Context, Result};
+use log::{debug, info};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::Arc;
+use tower_lsp::lsp_types::Url;
+
+use crate::dispatcher::Dispatcher;
+use crate::trace::Direction;
+
+/// 每个回复之后显示的提示符。
+pub const PROMPT: &str = "codefuse> ";
+
+/// `help` 列出的命令。
+const HELP: &str = "\
+status              后端状态和排队深度
+handlers            注册了处理器的方法
+pending             等待后端响应的请求
+restart-backend     重启所有分片的后端
+dump doc <uri>      代理保存的文档内容
+quit                断开连接";
+
+/// 一条命令的结果。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// 显示给运维人员的文本
+    Output(String),
+    /// 断开连接
+    Quit,
+}
+
+/// 执行一行命令。
+///
+/// # 参数
+///
+/// * `dispatcher` - 运行中的调度器
+/// * `line` - 一行输入，空白分隔的命令和参数
+pub fn execute(dispatcher: &Dispatcher, line: &str) -> Reply {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let output = match words.as_slice() {
+        [] => String::new(),
+        ["help"] => HELP.to_string(),
+        ["quit"] | ["exit"] => return Reply::Quit,
+        ["status"] => status(dispatcher),
+        ["handlers"] => handlers(dispatcher),
+        ["pending"] => pending(dispatcher),
+        ["restart-backend"] => {
+            dispatcher.request_restart();
+            "已请求重启后端".to_string()
+        }
+        ["dump", "doc", uri] => dump_document(dispatcher, uri),
+        _ => format!("未知命令: {}（输入 help 查看可用的命令）", line.trim()),
+    };
+    Reply::Output(output)
+}
+
+fn status(dispatcher: &Dispatcher) -> String {
+    let report = dispatcher.health().report(dispatcher);
+    let mut output = format!(
+        "状态: {}\n运行时间: {} 秒\n",
+        report.status, report.uptime_secs
+    );
+    for backend in &report.backends {
+        let _ = write!(
+            output,
+            "后端 {}: {}",
+            backend.name,
+            if backend.alive {
+                "运行中"
+            } else {
+                "未运行"
+            }
+        );
+        match backend.last_response_age_ms {
+            Some(age) => {
+                let _ = writeln!(output, "，{} 毫秒前响应", age);
+            }
+            None => output.push('\n'),
+        }
+    }
+    let _ = write!(
+        output,
+        "处理任务: {} 个运行中，{} 个等待\n等待后端响应的请求: {}\n活跃的文档通道: {}\n打开的文档: {}",
+        report.queues.handlers_running,
+        report.queues.handlers_waiting,
+        report.queues.pending_backend_requests,
+        report.queues.active_lanes,
+        dispatcher.documents().len()
+    );
+    output
+}
+
+fn handlers(dispatcher: &Dispatcher) -> String {
+    let mut output = String::new();
+    for (title, direction) in [("前端", Direction::Frontend), ("后端", Direction::Backend)] {
+        let _ = writeln!(output, "{}消息:", title);
+        for (pattern, count) in dispatcher.handler_patterns(direction) {
+            let _ = writeln!(output, "  {:>2}  {}", count, pattern);
+        }
+    }
+    output.pop();
+    output
+}
+
+fn pending(dispatcher: &Dispatcher) -> String {
+    let requests = dispatcher.pending_request_methods();
+    if requests.is_empty() {
+        return "没有等待响应的请求".to_string();
+    }
+    requests
+        .iter()
+        .map(|(id, method)| format!("{:>8}  {}", id, method))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn dump_document(dispatcher: &Dispatcher, uri: &str) -> String {
+    let Ok(url) = Url::parse(uri) else {
+        return format!("无效的 URI: {}", uri);
+    };
+    match dispatcher.documents().get(&url) {
+        Some(document) => format!(
+            "{} ({}, 版本 {})\n{}",
+            document.uri, document.language_id, document.version, document.text
+        ),
+        None => format!("文档没有打开: {}", uri),
+    }
+}
+
+/// 在 `path` 上监听管理控制台的连接。
+///
+/// 已经存在的套接字文件（上次运行留下的）会被删除。
+///
+/// # 返回
+///
+/// 返回处理连接的任务
+///
+/// # 错误
+///
+/// 如果套接字无法绑定，或者当前平台不支持 unix 套接字，返回错误
+#[cfg(unix)]
+pub async fn serve(
+    path: &Path,
+    dispatcher: Arc<Dispatcher>,
+) -> Result<tokio::task::JoinHandle<()>> {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::net::UnixListener;
+
+    match std::fs::remove_file(path) {
+        Ok(()) => debug!("删除旧的管理控制台套接字 {}", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("无法删除 {}", path.display())),
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("无法监听管理控制台套接字 {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    info!("管理控制台: {}", path.display());
+    let task = tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    debug!("接受管理控制台连接失败: {}", e);
+                    continue;
+                }
+            };
+            let dispatcher = Arc::clone(&dispatcher);
+            tokio::spawn(async move {
+                if let Err(e) = session(stream, &dispatcher).await {
+                    debug!("管理控制台连接出错: {:?}", e);
+                }
+            });
+        }
+    });
+    Ok(task)
+}
+
+#[cfg(not(unix))]
+pub async fn serve(
+    path: &Path,
+    _dispatcher: Arc<Dispatcher>,
+) -> Result<tokio::task::JoinHandle<()>> {
+    anyhow::bail!("当前平台不支持管理控制台的 unix 套接字: {}", path.display())
+}
+
+/// 逐行读取命令并回复，直到连接关闭或者收到 `quit`。
+#[cfg(unix)]
+async fn session(stream: tokio::net::UnixStream, dispatcher: &Dispatcher) -> Result<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer.write_all(PROMPT.as_bytes()).await?;
+    while let Some(line) = lines.next_line().await? {
+        match execute(dispatcher, &line) {
+            Reply::Output(output) => {
+                if !output.is_empty() {
+                    writer.write_all(output.as_bytes()).await?;
+                    writer.write_all(b"\n").await?;
+                }
+                writer.write_all(PROMPT.as_bytes()).await?;
+            }
+            Reply::Quit => break,
+        }
+    }
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// `admin attach`：连接管理控制台，在标准输入输出上交互。
+///
+/// # 错误
+///
+/// 如果无法连接套接字，或者当前平台不支持 unix 套接字，返回错误
+#[cfg(unix)]
+pub async fn attach(path: &Path) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let stream = tokio::net::UnixStream::connect(path)
+        .await
+        .with_context(|| {
+            format!(
+                "无法连接管理控制台 {}（代理是否配置了 [admin] socket？）",
+                path.display()
+            )
+        })?;
+    let (mut reader, mut writer) = stream.into_split();
+    let mut stdout = tokio::io::stdout();
+    // 标准输入结束（例如从管道读入命令）时关闭写的一端，继续显示代理的回复，直到代理关闭连接
+    let input = async {
+        tokio::io::copy(&mut tokio::io::stdin(), &mut writer).await?;
+        writer.shutdown().await?;
+        std::future::pending::<std::io::Result<()>>().await
+    };
+    tokio::select! {
+        result = tokio::io::copy(&mut reader, &mut stdout) => { result?; }
+        result = input => { result?; }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn attach(path: &Path) -> Result<()> {
+    anyhow::bail!("当前平台不支持管理控制台的 unix 套接字: {}", path.display())
+}
@@ -0,0 +1,369 @@
+//! # 后端日志模块
+//!
+//! 解析后端标准错误输出的每一行，在内存中为每个分片保留最近的输出，并识别 clangd 日志中常见的问题：
+//! 找不到编译数据库、preamble 无法复用而重建、preamble 构建失败，以及 LLVM 的崩溃信息。
+//! 最近的输出和各类问题出现的次数通过自定义请求 `codefuse/backendLog` 查询，崩溃报告中也包含最近的输出。
+//!
+//! 不同的后端日志格式不同，每种格式由一个 [`LogParser`] 解析，按 `[backend] log_format` 或者后端的程序名选择。
+
+use anyhow::Result;
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+
+use crate::config::LogFormat;
+use crate::dispatcher::{DEFAULT_HANDLER_PRIORITY, Dispatcher};
+use crate::handlers::{self, HandlerCtx, Verdict};
+use crate::trace::Direction;
+
+/// 查询后端日志的自定义请求。
+///
+/// 参数都是可选的：`shard` 只返回这个分片，`limit` 限制每个分片返回的行数。
+/// 结果是 [`ShardLog`] 的数组，按分片的顺序排列。
+pub const BACKEND_LOG: &str = "codefuse/backendLog";
+
+/// 每个分片保留的行数。
+pub const TAIL_LINES: usize = 200;
+
+/// 后端日志的级别。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warning,
+    Error,
+    Fatal,
+}
+
+/// 从 clangd 日志中识别出的问题。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LogIssue {
+    /// 找不到文件的编译数据库，clangd 只能猜测编译参数
+    CompilationDatabaseMissing,
+    /// preamble 无法复用，需要重新构建（PCH 重建）
+    PreambleRebuild,
+    /// preamble 构建失败
+    PreambleFailed,
+    /// LLVM 的崩溃信息（堆栈转储）
+    Crash,
+}
+
+/// 问题和日志中对应的文字，按小写比较。
+const ISSUE_PATTERNS: &[(LogIssue, &[&str])] = &[
+    (
+        LogIssue::CompilationDatabaseMissing,
+        &["failed to find compilation database"],
+    ),
+    (LogIssue::PreambleRebuild, &["preamble", "rebuild"]),
+    (LogIssue::PreambleFailed, &["could not build a preamble"]),
+    (LogIssue::Crash, &["stack dump"]),
+    (LogIssue::Crash, &["please submit a bug report"]),
+];
+
+/// 后端的一行输出。
+///
+/// - `level`: 日志级别，无法识别格式的行为 `null`
+/// - `message`: 去掉级别和时间前缀之后的文字
+/// - `issue`: 这一行对应的问题
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogLine {
+    pub level: Option<LogLevel>,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issue: Option<LogIssue>,
+}
+
+impl LogLine {
+    /// 解析 clangd 格式的一行日志，例如 `I[11:01:38.638] clangd version 21.1.0`。
+    pub fn parse(line: &str) -> Self {
+        let line = line.trim();
+        let (level, message) = match (line.chars().next(), line.get(15..)) {
+            (Some(level), Some(rest)) if line.as_bytes().get(1) == Some(&b'[') => {
+                let level = match level {
+                    'V' | 'D' => Some(LogLevel::Debug),
+                    'I' => Some(LogLevel::Info),
+                    'W' => Some(LogLevel::Warning),
+                    'E' => Some(LogLevel::Error),
+                    'F' => Some(LogLevel::Fatal),
+                    _ => None,
+                };
+                match level {
+                    Some(level) => (Some(level), rest.trim()),
+                    None => (None, line),
+                }
+            }
+            _ => (None, line),
+        };
+        Self {
+            level,
+            message: message.to_string(),
+            issue: classify(message),
+        }
+    }
+}
+
+/// 把后端的一行标准错误输出解析为 [`LogLine`]。
+pub trait LogParser: Send + Sync {
+    fn parse(&self, line: &str) -> LogLine;
+}
+
+/// clangd 的日志，见 [`LogLine::parse`]。
+pub struct ClangdLog;
+
+impl LogParser for ClangdLog {
+    fn parse(&self, line: &str) -> LogLine {
+        LogLine::parse(line)
+    }
+}
+
+/// rust-analyzer 的日志。
+///
+/// 旧版本使用 env_logger 的 `[ERROR rust_analyzer::main_loop] ...`，新版本使用 tracing 的
+/// `2024-05-01T10:00:00.000Z ERROR rust_analyzer::reload: ...`，级别之前可能有时间。
+/// 线程 panic 的信息识别为崩溃。
+pub struct RustAnalyzerLog;
+
+impl LogParser for RustAnalyzerLog {
+    fn parse(&self, line: &str) -> LogLine {
+        let line = line.trim();
+        let mut words = line.split_whitespace();
+        let level = words
+            .by_ref()
+            .take(3)
+            .find_map(|word| match word.trim_start_matches('[') {
+                "TRACE" | "DEBUG" => Some(LogLevel::Debug),
+                "INFO" => Some(LogLevel::Info),
+                "WARN" => Some(LogLevel::Warning),
+                "ERROR" => Some(LogLevel::Error),
+                _ => None,
+            });
+        let message = match level {
+            Some(_) => {
+                let rest: Vec<&str> = words.collect();
+                // 去掉日志的来源模块
+                match rest.split_first() {
+                    Some((target, message))
+                        if target.ends_with(']')
+                            || (target.ends_with(':') && target.contains("::")) =>
+                    {
+                        message.join(" ")
+                    }
+                    _ => rest.join(" "),
+                }
+            }
+            None => line.to_string(),
+        };
+        let issue = line.contains("panicked at").then_some(LogIssue::Crash);
+        LogLine {
+            level: if issue.is_some() {
+                Some(LogLevel::Fatal)
+            } else {
+                level
+            },
+            message,
+            issue,
+        }
+    }
+}
+
+/// pyright 的日志。
+///
+/// 带前缀的行形如 `[Error - 10:00:00 AM] ...`；Node.js 自身的输出形如 `(node:1234) Warning: ...` 和
+/// `Error: ...`，V8 内存耗尽等致命错误形如 `FATAL ERROR: ...`，识别为崩溃。
+pub struct PyrightLog;
+
+impl LogParser for PyrightLog {
+    fn parse(&self, line: &str) -> LogLine {
+        let line = line.trim();
+        let level_of = |word: &str| match word {
+            "Trace" | "Debug" | "Log" => Some(LogLevel::Debug),
+            "Info" => Some(LogLevel::Info),
+            "Warn" | "Warning" => Some(LogLevel::Warning),
+            "Error" => Some(LogLevel::Error),
+            _ => None,
+        };
+        let bracketed = line
+            .strip_prefix('[')
+            .and_then(|rest| rest.split_once(']'))
+            .and_then(|(prefix, message)| {
+                let word = prefix.split([' ', '-']).next()?;
+                Some((level_of(word)?, message.trim()))
+            });
+        let (level, message, issue) = if let Some((level, message)) = bracketed {
+            (Some(level), message, None)
+        } else if let Some(message) = line.strip_prefix("FATAL ERROR:") {
+            (Some(LogLevel::Fatal), message.trim(), Some(LogIssue::Crash))
+        } else {
+            let rest = match line.strip_prefix("(node:") {
+                Some(rest) => rest.split_once(')').map_or(line, |(_, rest)| rest.trim()),
+                None => line,
+            };
+            match rest.split_once(": ") {
+                Some((word, _)) if word.ends_with("Error") => (Some(LogLevel::Error), rest, None),
+                Some((word, message)) => match level_of(word) {
+                    Some(level) => (Some(level), message.trim(), None),
+                    None => (None, line, None),
+                },
+                None => (None, line, None),
+            }
+        };
+        LogLine {
+            level,
+            message: message.to_string(),
+            issue,
+        }
+    }
+}
+
+/// 不识别格式的日志：每一行原样保留，没有级别。
+pub struct PlainLog;
+
+impl LogParser for PlainLog {
+    fn parse(&self, line: &str) -> LogLine {
+        LogLine {
+            level: None,
+            message: line.trim().to_string(),
+            issue: None,
+        }
+    }
+}
+
+/// 按程序名判断后端的日志格式，无法判断时按 clangd 处理。
+///
+/// # 参数
+///
+/// * `command` - 后端的启动命令，可以是路径或者 `ssh://` 地址
+pub fn detect_format(command: &str) -> LogFormat {
+    let program = command
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or(command)
+        .to_lowercase();
+    let program = program.strip_suffix(".exe").unwrap_or(&program);
+    if program.contains("rust-analyzer") {
+        LogFormat::RustAnalyzer
+    } else if program.contains("pyright") {
+        LogFormat::Pyright
+    } else {
+        LogFormat::Clangd
+    }
+}
+
+/// 日志格式对应的解析器。
+///
+/// # 参数
+///
+/// * `format` - `[backend] log_format`
+/// * `command` - 后端的启动命令，`format` 为 `auto` 时据此判断格式
+pub fn parser(format: LogFormat, command: &str) -> Arc<dyn LogParser> {
+    let format = match format {
+        LogFormat::Auto => detect_format(command),
+        format => format,
+    };
+    match format {
+        LogFormat::Auto | LogFormat::Clangd => Arc::new(ClangdLog),
+        LogFormat::RustAnalyzer => Arc::new(RustAnalyzerLog),
+        LogFormat::Pyright => Arc::new(PyrightLog),
+        LogFormat::Plain => Arc::new(PlainLog),
+    }
+}
+
+/// 识别一行日志对应的问题。
+pub fn classify(message: &str) -> Option<LogIssue> {
+    let message = message.to_lowercase();
+    ISSUE_PATTERNS
+        .iter()
+        .find(|(_, words)| words.iter().all(|word| message.contains(word)))
+        .map(|(issue, _)| *issue)
+}
+
+/// 一个分片的后端日志。
+///
+/// - `shard`: 分片名称
+/// - `lines`: 最近的输出，最旧的在前
+/// - `issues`: 后端启动以来各类问题出现的次数
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShardLog {
+    pub shard: String,
+    pub lines: Vec<LogLine>,
+    pub issues: BTreeMap<LogIssue, u64>,
+}
+
+#[derive(Default)]
+struct Tail {
+    lines: VecDeque<LogLine>,
+    issues: BTreeMap<LogIssue, u64>,
+}
+
+/// 各分片后端最近的输出。
+#[derive(Default)]
+pub struct BackendLog {
+    shards: DashMap<usize, Tail>,
+}
+
+impl BackendLog {
+    /// 记录分片后端的一行输出，超过 [`TAIL_LINES`] 时丢弃最旧的一行。
+    pub fn record(&self, shard: usize, line: LogLine) {
+        let mut tail = self.shards.entry(shard).or_default();
+        if let Some(issue) = line.issue {
+            *tail.issues.entry(issue).or_default() += 1;
+        }
+        if tail.lines.len() == TAIL_LINES {
+            tail.lines.pop_front();
+        }
+        tail.lines.push_back(line);
+    }
+
+    /// 分片后端最近的输出，最旧的在前。
+    ///
+    /// # 参数
+    ///
+    /// * `shard` - 分片下标
+    /// * `name` - 分片名称
+    /// * `limit` - 最多返回最近的多少行，`None` 表示全部
+    pub fn snapshot(&self, shard: usize, name: &str, limit: Option<usize>) -> ShardLog {
+        let (lines, issues) = match self.shards.get(&shard) {
+            Some(tail) => {
+                let skip = limit.map_or(0, |limit| tail.lines.len().saturating_sub(limit));
+                (
+                    tail.lines.iter().skip(skip).cloned().collect(),
+                    tail.issues.clone(),
+                )
+            }
+            None => Default::default(),
+        };
+        ShardLog {
+            shard: name.to_string(),
+            lines,
+            issues,
+        }
+    }
+}
+
+/// 注册 `codefuse/backendLog` 的处理器，由代理直接应答。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        BACKEND_LOG,
+        DEFAULT_HANDLER_PRIORITY,
+        answer_logs,
+    );
+}
+
+/// 以各分片后端最近的日志应答，`limit` 和 `shard` 参数限制条数和分片。
+fn answer_logs(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let limit = rpc
+            .pointer("/params/limit")
+            .and_then(|limit| limit.as_u64())
+            .map(|limit| limit as usize);
+        let filter = rpc.pointer("/params/shard").and_then(|s| s.as_str());
+        let logs = ctx.dispatcher().backend_logs(limit, filter);
+        Ok(Verdict::Respond(handlers::response(&rpc, Ok(logs))))
+    })
+}
@@ -0,0 +1,184 @@
+//! # 批量消息模块
+//!
+//! JSON-RPC 2.0 允许客户端把多条消息放在一个数组中发送。代理逐条处理数组中的消息，
+//! 并按规范把其中请求的响应收集起来，全部完成后作为一个数组发回前端。
+//! 数组中不是对象的元素各自应答一个 `Invalid Request` 错误；超时仍没有响应的请求以错误应答，
+//! 批量请求不会因为一个丢失的响应而一直不发送。
+
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::message::Message;
+
+/// 一个还没有全部应答的批量请求。
+struct Batch {
+    /// 还在等待响应的请求 id（序列化后的 JSON）
+    waiting: HashSet<String>,
+    responses: Vec<Value>,
+    /// 超过这个时间仍没有响应的请求以错误应答
+    deadline: Instant,
+}
+
+/// 等待批量请求中所有响应的默认时间。
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// LSP 的 `RequestFailed` 错误码。
+const REQUEST_FAILED: i64 = -32803;
+
+/// 发往前端的消息经过这里时的处理结果。
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outgoing {
+    /// 与批量请求无关，原样发送
+    Send(Message),
+    /// 属于一个还没完成的批量请求，暂不发送
+    Hold,
+    /// 批量请求的最后一个响应，发送整个响应数组
+    Flush(Value),
+}
+
+/// 跟踪前端发来的批量请求。
+pub struct BatchTracker {
+    batches: Mutex<Vec<Batch>>,
+    /// 未完成的批量请求数量，没有时跳过对发往前端的消息的解析
+    active: AtomicUsize,
+    timeout: Duration,
+}
+
+impl Default for BatchTracker {
+    fn default() -> Self {
+        Self::with_timeout(DEFAULT_TIMEOUT)
+    }
+}
+
+impl BatchTracker {
+    /// 创建跟踪器，批量请求中的响应最多等待 60 秒。
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 创建跟踪器，批量请求中的响应最多等待 `timeout`。
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            batches: Mutex::new(Vec::new()),
+            active: AtomicUsize::new(0),
+            timeout,
+        }
+    }
+
+    /// 登记一个批量消息中的请求。只有通知和响应的批量消息不需要应答，不会被登记。
+    ///
+    /// 不是对象的元素不会交给调度器，它们的 `Invalid Request` 错误放在批量响应中；
+    /// 批量消息中没有请求时，这些错误需要立即发送，作为返回值返回。
+    ///
+    /// # 参数
+    ///
+    /// * `items` - 批量消息中的所有元素
+    pub fn register(&self, items: &[Value]) -> Option<Value> {
+        let waiting: HashSet<String> = items
+            .iter()
+            .filter(|item| item.get("method").is_some())
+            .filter_map(|item| item.get("id"))
+            .map(|id| id.to_string())
+            .collect();
+        let invalid: Vec<Value> = items
+            .iter()
+            .filter(|item| !item.is_object())
+            .map(|_| invalid_request_error("batch element is not an object"))
+            .collect();
+        if waiting.is_empty() {
+            return (!invalid.is_empty()).then_some(Value::Array(invalid));
+        }
+        self.batches.lock().unwrap().push(Batch {
+            waiting,
+            responses: invalid,
+            deadline: Instant::now() + self.timeout,
+        });
+        self.active.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    /// 最早超时的批量请求的截止时间，没有未完成的批量请求时返回 `None`。
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if self.active.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        let batches = self.batches.lock().unwrap();
+        batches.iter().map(|batch| batch.deadline).min()
+    }
+
+    /// 取出截止时间已过的批量请求，其中还没有响应的请求以错误应答，返回各自的响应数组。
+    pub fn expire(&self, now: Instant) -> Vec<Value> {
+        let mut batches = self.batches.lock().unwrap();
+        let (expired, pending): (Vec<Batch>, Vec<Batch>) = std::mem::take(&mut *batches)
+            .into_iter()
+            .partition(|batch| batch.deadline <= now);
+        *batches = pending;
+        self.active.fetch_sub(expired.len(), Ordering::Relaxed);
+        expired
+            .into_iter()
+            .map(|mut batch| {
+                for id in &batch.waiting {
+                    let id = serde_json::from_str::<Value>(id).unwrap_or(Value::Null);
+                    batch.responses.push(json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {"code": REQUEST_FAILED, "message": "批量请求中的请求超时没有响应"},
+                    }));
+                }
+                Value::Array(batch.responses)
+            })
+            .collect()
+    }
+
+    /// 检查一条发往前端的消息。
+    pub fn outgoing(&self, message: Message) -> Outgoing {
+        if self.active.load(Ordering::Relaxed) == 0 {
+            return Outgoing::Send(message);
+        }
+        let Message::Response { id, .. } = &message else {
+            return Outgoing::Send(message);
+        };
+        let id = id.to_string();
+
+        let mut batches = self.batches.lock().unwrap();
+        let Some(index) = batches.iter().position(|b| b.waiting.contains(&id)) else {
+            return Outgoing::Send(message);
+        };
+        let batch = &mut batches[index];
+        batch.waiting.remove(&id);
+        batch.responses.push(message.into_body());
+        if !batch.waiting.is_empty() {
+            return Outgoing::Hold;
+        }
+
+        let batch = batches.remove(index);
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        Outgoing::Flush(Value::Array(batch.responses))
+    }
+}
+
+/// 把收到的消息拆成单条消息：批量消息返回其中是对象的元素，其他消息原样返回。
+///
+/// 不是对象的元素由 [`BatchTracker::register`] 应答错误。
+pub fn split_batch(body: Value) -> Vec<Value> {
+    match body {
+        Value::Array(items) => items.into_iter().filter(Value::is_object).collect(),
+        body => vec![body],
+    }
+}
+
+/// 空数组是无效的批量请求，按规范应答一个 `Invalid Request` 错误。
+pub fn empty_batch_error() -> Value {
+    invalid_request_error("empty batch")
+}
+
+fn invalid_request_error(reason: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": null,
+        "error": {"code": -32600, "message": format!("Invalid Request: {reason}")},
+    })
+}
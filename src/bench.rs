@@ -0,0 +1,371 @@
+//! # 负载测试模块
+//!
+//! `lsp-proxy bench` 按场景中的请求组合和速率驱动后端，分别测量直连后端和经过代理的延迟，
+//! 报告吞吐量、p50/p95/p99 延迟以及两轮百分位之差。两轮是分别进行的，百分位之差只是代理开销的近似，
+//! 不是同一请求的延迟之差。后端可以是模拟后端或真实的 clangd。
+
+use anyhow::{Result, bail};
+use log::info;
+use serde_json::{Value, json};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::client::LspClient;
+use crate::config::Config;
+
+/// 测试文档中的变量数量，请求的位置在这些行之间轮换，避免命中预取缓存。
+const DOCUMENT_LINES: u32 = 200;
+
+/// 每秒请求数的上限，更高的速率下发出请求的间隔已经小于计时器的精度。
+pub const MAX_RATE: u32 = 100_000;
+
+/// 测量结束后等待未完成请求的最长时间。
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `bench` 子命令的参数，没有指定的值使用场景的默认值。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchCommand {
+    pub scenario: String,
+    pub rate: Option<u32>,
+    pub duration_secs: Option<u64>,
+    /// `completion=9,hover=1` 形式的请求组合
+    pub mix: Option<String>,
+    /// 使用模拟后端而不是 clangd
+    pub mock: bool,
+}
+
+/// 负载场景。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub name: String,
+    /// 方法和权重
+    pub mix: Vec<(String, u32)>,
+    /// 每秒发出的请求数
+    pub rate: u32,
+    pub duration: Duration,
+}
+
+impl Scenario {
+    /// 内置场景：`completion-storm`、`navigation` 和 `mixed`。
+    pub fn builtin(name: &str) -> Option<Self> {
+        let (mix, rate): (&[(&str, u32)], u32) = match name {
+            "completion-storm" => (
+                &[("textDocument/completion", 9), ("textDocument/hover", 1)],
+                200,
+            ),
+            "navigation" => (
+                &[
+                    ("textDocument/hover", 4),
+                    ("textDocument/definition", 3),
+                    ("textDocument/references", 2),
+                    ("textDocument/documentHighlight", 1),
+                ],
+                50,
+            ),
+            "mixed" => (
+                &[
+                    ("textDocument/completion", 3),
+                    ("textDocument/hover", 3),
+                    ("textDocument/definition", 2),
+                    ("textDocument/documentSymbol", 1),
+                    ("textDocument/semanticTokens/full", 1),
+                ],
+                100,
+            ),
+            _ => return None,
+        };
+        Some(Self {
+            name: name.to_string(),
+            mix: mix.iter().map(|(m, w)| (m.to_string(), *w)).collect(),
+            rate,
+            duration: Duration::from_secs(10),
+        })
+    }
+
+    /// 根据命令行参数选择场景并覆盖默认值。
+    ///
+    /// # 错误
+    ///
+    /// 场景不存在、请求组合无法解析或速率不在 1 到 [`MAX_RATE`] 之间时返回错误
+    pub fn from_command(command: &BenchCommand) -> Result<Self> {
+        let Some(mut scenario) = Self::builtin(&command.scenario) else {
+            bail!(
+                "未知的场景: {}（可选 completion-storm、navigation、mixed）",
+                command.scenario
+            );
+        };
+        if let Some(mix) = &command.mix {
+            scenario.mix = parse_mix(mix)?;
+        }
+        if let Some(rate) = command.rate {
+            scenario.rate = rate;
+        }
+        if let Some(secs) = command.duration_secs {
+            scenario.duration = Duration::from_secs(secs);
+        }
+        if !(1..=MAX_RATE).contains(&scenario.rate) {
+            bail!("--rate 必须在 1 到 {} 之间", MAX_RATE);
+        }
+        Ok(scenario)
+    }
+
+    /// 第 `index` 个请求的方法。按权重依次轮换，结果是确定的。
+    pub fn method_at(&self, index: u64) -> &str {
+        let total: u64 = self.mix.iter().map(|(_, w)| *w as u64).sum();
+        let mut slot = index % total.max(1);
+        for (method, weight) in &self.mix {
+            if slot < *weight as u64 {
+                return method;
+            }
+            slot -= *weight as u64;
+        }
+        &self.mix[0].0
+    }
+}
+
+/// 解析 `completion=9,hover=1`，方法名可以省略 `textDocument/` 前缀。
+///
+/// # 错误
+///
+/// 格式错误或者所有权重都为零时返回错误
+pub fn parse_mix(mix: &str) -> Result<Vec<(String, u32)>> {
+    let mut parsed = Vec::new();
+    for item in mix.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+        let Some((method, weight)) = item.split_once('=') else {
+            bail!("请求组合格式错误: {}（应为 method=weight）", item);
+        };
+        let Ok(weight) = weight.trim().parse::<u32>() else {
+            bail!("请求组合的权重不是整数: {}", item);
+        };
+        let method = method.trim();
+        let method = if method.contains('/') {
+            method.to_string()
+        } else {
+            format!("textDocument/{}", method)
+        };
+        parsed.push((method, weight));
+    }
+    if parsed.iter().all(|(_, weight)| *weight == 0) {
+        bail!("请求组合为空: {}", mix);
+    }
+    Ok(parsed)
+}
+
+/// 已排序的延迟中的百分位数。
+pub fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// 一轮测量的结果。
+#[derive(Debug, Clone, Default)]
+pub struct BenchStats {
+    /// 成功请求的延迟，已排序
+    pub latencies: Vec<Duration>,
+    pub errors: usize,
+    pub elapsed: Duration,
+}
+
+impl BenchStats {
+    /// 每秒完成的请求数。
+    pub fn throughput(&self) -> f64 {
+        self.latencies.len() as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn percentile(&self, p: f64) -> Duration {
+        percentile(&self.latencies, p)
+    }
+}
+
+/// 测试文档：每行一个变量，补全和导航请求都落在变量上。
+fn document_text() -> String {
+    let mut text: String = (0..DOCUMENT_LINES)
+        .map(|i| format!("int value_{} = {};\n", i, i))
+        .collect();
+    text.push_str("int main() { return value_0; }\n");
+    text
+}
+
+/// 第 `index` 个请求的参数。
+fn request_params(method: &str, uri: &str, index: u64) -> Value {
+    let line = (index % DOCUMENT_LINES as u64) as u32;
+    let text_document = json!({"uri": uri});
+    match method {
+        "textDocument/documentSymbol" | "textDocument/semanticTokens/full" => {
+            json!({"textDocument": text_document})
+        }
+        // `int valu|e_N`：补全变量名
+        "textDocument/completion" => json!({
+            "textDocument": text_document,
+            "position": {"line": line, "character": 8},
+        }),
+        "textDocument/references" => json!({
+            "textDocument": text_document,
+            "position": {"line": line, "character": 4},
+            "context": {"includeDeclaration": true},
+        }),
+        _ => json!({
+            "textDocument": text_document,
+            "position": {"line": line, "character": 4},
+        }),
+    }
+}
+
+/// 对一个服务器运行场景。
+async fn measure(program: &str, args: &[String], scenario: &Scenario) -> Result<BenchStats> {
+    let client = Arc::new(LspClient::spawn(program, args)?);
+    let root = std::env::temp_dir().join("codefuse-bench");
+    std::fs::create_dir_all(&root)?;
+    let path = root.join("bench.cpp");
+    let text = document_text();
+    std::fs::write(&path, &text)?;
+    let uri = format!("file://{}", path.display());
+
+    client
+        .request(
+            "initialize",
+            json!({"processId": std::process::id(), "rootUri": null, "capabilities": {}}),
+        )
+        .await?;
+    client.notify("initialized", json!({}))?;
+    client.notify(
+        "textDocument/didOpen",
+        json!({"textDocument": {"uri": uri, "languageId": "cpp", "version": 1, "text": text}}),
+    )?;
+    // 等待后端完成第一次解析，不计入结果
+    client
+        .request(
+            "textDocument/hover",
+            request_params("textDocument/hover", &uri, 0),
+        )
+        .await?;
+
+    let latencies = Arc::new(Mutex::new(Vec::new()));
+    let mut tasks = Vec::new();
+    let period = (Duration::from_secs(1) / scenario.rate).max(Duration::from_nanos(1));
+    let mut ticker = tokio::time::interval(period);
+    let start = Instant::now();
+    let mut index = 0u64;
+    while start.elapsed() < scenario.duration {
+        ticker.tick().await;
+        let method = scenario.method_at(index).to_string();
+        let params = request_params(&method, &uri, index);
+        let client = Arc::clone(&client);
+        let latencies = Arc::clone(&latencies);
+        tasks.push(tokio::spawn(async move {
+            let sent = Instant::now();
+            if client.request(&method, params).await.is_ok() {
+                latencies.lock().unwrap().push(sent.elapsed());
+            }
+        }));
+        index += 1;
+    }
+    let _ = tokio::time::timeout(DRAIN_TIMEOUT, futures::future::join_all(tasks)).await;
+    let elapsed = start.elapsed();
+
+    let mut latencies = std::mem::take(&mut *latencies.lock().unwrap());
+    latencies.sort();
+    // 失败的请求和等待结束时仍未完成的请求都计为错误
+    let stats = BenchStats {
+        errors: index as usize - latencies.len(),
+        latencies,
+        elapsed,
+    };
+    if let Ok(client) = Arc::try_unwrap(client) {
+        client.shutdown().await;
+    }
+    Ok(stats)
+}
+
+fn format_ms(duration: Duration) -> String {
+    format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// 经过代理与直连的百分位之差。两轮分别测量，差值可能为负。
+fn format_delta(proxy: Duration, direct: Duration) -> String {
+    let delta = proxy.as_secs_f64() - direct.as_secs_f64();
+    format!("{:+.2}ms", delta * 1000.0)
+}
+
+/// 执行 `lsp-proxy bench`，结果输出到标准输出。
+///
+/// # 错误
+///
+/// 如果场景无效或者服务器无法启动、初始化，返回错误
+///
+/// # 参数
+///
+/// * `command` - 子命令参数
+/// * `config` - 代理配置，直连 clangd 时使用其中的后端命令
+/// * `config_path` - `--config` 指定的配置文件，传给被测的代理
+pub async fn run(
+    command: &BenchCommand,
+    config: &Config,
+    config_path: Option<&Path>,
+) -> Result<()> {
+    let scenario = Scenario::from_command(command)?;
+    let exe = std::env::current_exe()?.to_string_lossy().into_owned();
+
+    let (direct_program, direct_args) = if command.mock {
+        (exe.clone(), vec!["mock-server".to_string()])
+    } else {
+        (config.backend.command.clone(), config.backend.args.clone())
+    };
+    let mut proxy_args = Vec::new();
+    if let Some(path) = config_path {
+        proxy_args.extend(["--config".to_string(), path.to_string_lossy().into_owned()]);
+    }
+    if command.mock {
+        proxy_args.extend(["--backend".to_string(), "mock".to_string()]);
+    }
+
+    let mix: Vec<String> = scenario
+        .mix
+        .iter()
+        .map(|(method, weight)| {
+            format!("{}={}", method.trim_start_matches("textDocument/"), weight)
+        })
+        .collect();
+    println!(
+        "场景 {}：{} 请求/秒，持续 {} 秒，{}",
+        scenario.name,
+        scenario.rate,
+        scenario.duration.as_secs(),
+        mix.join(", ")
+    );
+
+    info!("测量直连后端: {}", direct_program);
+    let direct = measure(&direct_program, &direct_args, &scenario).await?;
+    info!("测量经过代理: {}", exe);
+    let proxy = measure(&exe, &proxy_args, &scenario).await?;
+
+    println!(
+        "{:<10}{:>8}{:>8}{:>12}{:>10}{:>10}{:>10}",
+        "", "完成", "错误", "吞吐/秒", "p50", "p95", "p99"
+    );
+    for (label, stats) in [("直连后端", &direct), ("经过代理", &proxy)] {
+        println!(
+            "{:<10}{:>8}{:>8}{:>12.1}{:>10}{:>10}{:>10}",
+            label,
+            stats.latencies.len(),
+            stats.errors,
+            stats.throughput(),
+            format_ms(stats.percentile(50.0)),
+            format_ms(stats.percentile(95.0)),
+            format_ms(stats.percentile(99.0)),
+        );
+    }
+    // 两轮的请求不是一一对应的，这一行是百分位相减，不是逐个请求的延迟之差
+    println!(
+        "{:<10}{:>38}{:>10}{:>10}",
+        "百分位之差",
+        format_delta(proxy.percentile(50.0), direct.percentile(50.0)),
+        format_delta(proxy.percentile(95.0), direct.percentile(95.0)),
+        format_delta(proxy.percentile(99.0), direct.percentile(99.0)),
+    );
+    Ok(())
+}
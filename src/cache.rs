@@ -0,0 +1,235 @@
+//! # 索引缓存模块
+//!
+//! 管理 clangd 的后台索引缓存：统计各个工作区 `.cache/clangd/index` 的大小、删除长时间没有更新的索引文件。
+//! 代理会记录服务过的工作区，`lsp-proxy cache` 命令默认处理这些工作区。
+//!
+//! `[cache] dir` 通过 `XDG_CACHE_HOME` 传给 clangd，只改变不属于任何编译数据库的文件的索引位置；
+//! clangd 总是把项目索引写在编译数据库所在的工作区中，没有选项可以移走。
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::cli::CacheCommand;
+use crate::config::Config;
+
+/// 工作区中 clangd 索引的位置（clangd 11 及以后）。
+const WORKSPACE_INDEX_DIR: &str = ".cache/clangd/index";
+
+/// clangd 11 之前使用的索引位置。
+const LEGACY_INDEX_DIR: &str = ".clangd/index";
+
+/// 代理自己的缓存目录：`$XDG_CACHE_HOME/codefuse`，没有设置时使用 `~/.cache/codefuse`。
+pub fn proxy_cache_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("codefuse"))
+}
+
+/// 代理服务过的工作区列表。
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KnownWorkspaces {
+    pub workspaces: Vec<PathBuf>,
+}
+
+impl KnownWorkspaces {
+    fn path() -> Option<PathBuf> {
+        Some(proxy_cache_dir()?.join("workspaces.json"))
+    }
+
+    /// 加载列表，文件不存在或无法解析时返回空列表。
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    /// 记录新的工作区根目录。
+    ///
+    /// # 错误
+    ///
+    /// 如果列表无法写入，返回错误
+    pub fn remember(roots: &[PathBuf]) -> Result<()> {
+        let Some(path) = Self::path() else {
+            return Ok(());
+        };
+        let mut known = Self::load();
+        let before = known.workspaces.len();
+        for root in roots {
+            if !known.workspaces.contains(root) {
+                known.workspaces.push(root.clone());
+            }
+        }
+        if known.workspaces.len() == before {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, serde_json::to_string_pretty(&known)?)?;
+        Ok(())
+    }
+}
+
+/// 一个索引目录的统计信息。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexStats {
+    pub path: PathBuf,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// 找出工作区和 clangd 缓存目录中存在的索引目录，按路径排序，同一个目录只出现一次。
+///
+/// # 参数
+///
+/// * `workspaces` - 工作区根目录
+/// * `cache_dir` - 配置的 clangd 缓存目录，其中只有不属于编译数据库的文件的索引
+pub fn index_dirs(workspaces: &[PathBuf], cache_dir: Option<&Path>) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = workspaces
+        .iter()
+        .flat_map(|root| [root.join(WORKSPACE_INDEX_DIR), root.join(LEGACY_INDEX_DIR)])
+        .chain(cache_dir.map(|dir| dir.join("clangd").join("index")))
+        .filter(|dir| dir.is_dir())
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+/// 统计索引目录中的文件数量和总大小。
+///
+/// # 错误
+///
+/// 如果目录无法读取，返回错误
+pub fn measure(dir: &Path) -> Result<IndexStats> {
+    let mut stats = IndexStats {
+        path: dir.to_path_buf(),
+        files: 0,
+        bytes: 0,
+    };
+    let entries = std::fs::read_dir(dir).with_context(|| format!("无法读取 {}", dir.display()))?;
+    for entry in entries {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            stats.files += 1;
+            stats.bytes += metadata.len();
+        }
+    }
+    Ok(stats)
+}
+
+/// 删除索引目录中超过 `max_age` 没有更新的文件。
+///
+/// clangd 会在源文件变化时重写对应的索引文件，长时间没有更新的通常属于已经删除或不再编译的源文件。
+///
+/// # 返回
+///
+/// 返回被删除的文件数量和字节数
+///
+/// # 错误
+///
+/// 如果目录无法读取，返回错误
+pub fn prune(dir: &Path, max_age: Duration) -> Result<(usize, u64)> {
+    let now = SystemTime::now();
+    let mut removed = (0, 0);
+    let entries = std::fs::read_dir(dir).with_context(|| format!("无法读取 {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+        if age <= max_age {
+            continue;
+        }
+        match std::fs::remove_file(entry.path()) {
+            Ok(()) => {
+                removed.0 += 1;
+                removed.1 += metadata.len();
+            }
+            Err(e) => warn!("无法删除 {}: {}", entry.path().display(), e),
+        }
+    }
+    Ok(removed)
+}
+
+/// 以易读的单位显示字节数。
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// 执行 `lsp-proxy cache` 子命令，结果输出到标准输出。
+///
+/// # 错误
+///
+/// 如果索引目录无法读取，返回错误
+pub fn run(command: &CacheCommand, config: &Config) -> Result<()> {
+    let workspaces = |paths: &[PathBuf]| {
+        if paths.is_empty() {
+            KnownWorkspaces::load().workspaces
+        } else {
+            paths.to_vec()
+        }
+    };
+
+    match command {
+        CacheCommand::Size { workspaces: paths } => {
+            let mut total = 0;
+            for dir in index_dirs(&workspaces(paths), config.cache.dir.as_deref()) {
+                let stats = measure(&dir)?;
+                println!(
+                    "{:>10}  {:>6} 个文件  {}",
+                    format_size(stats.bytes),
+                    stats.files,
+                    stats.path.display()
+                );
+                total += stats.bytes;
+            }
+            println!("{:>10}  合计", format_size(total));
+        }
+        CacheCommand::Prune {
+            workspaces: paths,
+            older_than_days,
+        } => {
+            let max_age = Duration::from_secs(older_than_days * 24 * 60 * 60);
+            let mut total = (0, 0);
+            for dir in index_dirs(&workspaces(paths), config.cache.dir.as_deref()) {
+                let (files, bytes) = prune(&dir, max_age)?;
+                if files > 0 {
+                    println!(
+                        "删除 {} 个文件（{}）: {}",
+                        files,
+                        format_size(bytes),
+                        dir.display()
+                    );
+                }
+                total.0 += files;
+                total.1 += bytes;
+            }
+            println!("共删除 {} 个文件，释放 {}", total.0, format_size(total.1));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,185 @@
+//! # 后端能力模块
+//!
+//! 记录后端在 `initialize` 响应中声明的能力（包括 clangd 的扩展）和版本，
+//! 代理的功能据此决定是否启用，而不是假定所有版本的 clangd 都支持。
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::sync::RwLock;
+
+/// 方法与 `ServerCapabilities` 中对应字段的映射。
+const METHOD_CAPABILITIES: &[(&str, &str)] = &[
+    ("textDocument/completion", "completionProvider"),
+    ("textDocument/hover", "hoverProvider"),
+    ("textDocument/signatureHelp", "signatureHelpProvider"),
+    ("textDocument/declaration", "declarationProvider"),
+    ("textDocument/definition", "definitionProvider"),
+    ("textDocument/typeDefinition", "typeDefinitionProvider"),
+    ("textDocument/implementation", "implementationProvider"),
+    ("textDocument/references", "referencesProvider"),
+    (
+        "textDocument/documentHighlight",
+        "documentHighlightProvider",
+    ),
+    ("textDocument/documentSymbol", "documentSymbolProvider"),
+    ("textDocument/codeAction", "codeActionProvider"),
+    ("textDocument/codeLens", "codeLensProvider"),
+    ("textDocument/documentLink", "documentLinkProvider"),
+    ("textDocument/documentColor", "colorProvider"),
+    ("textDocument/colorPresentation", "colorProvider"),
+    ("textDocument/formatting", "documentFormattingProvider"),
+    (
+        "textDocument/rangeFormatting",
+        "documentRangeFormattingProvider",
+    ),
+    (
+        "textDocument/onTypeFormatting",
+        "documentOnTypeFormattingProvider",
+    ),
+    ("textDocument/rename", "renameProvider"),
+    ("textDocument/foldingRange", "foldingRangeProvider"),
+    ("textDocument/selectionRange", "selectionRangeProvider"),
+    ("textDocument/prepareCallHierarchy", "callHierarchyProvider"),
+    ("textDocument/prepareTypeHierarchy", "typeHierarchyProvider"),
+    ("textDocument/semanticTokens/full", "semanticTokensProvider"),
+    (
+        "textDocument/semanticTokens/full/delta",
+        "semanticTokensProvider",
+    ),
+    (
+        "textDocument/semanticTokens/range",
+        "semanticTokensProvider",
+    ),
+    ("textDocument/inlayHint", "inlayHintProvider"),
+    ("textDocument/inlineValue", "inlineValueProvider"),
+    ("textDocument/diagnostic", "diagnosticProvider"),
+    ("workspace/symbol", "workspaceSymbolProvider"),
+    ("workspace/executeCommand", "executeCommandProvider"),
+    // clangd 扩展
+    ("textDocument/ast", "astProvider"),
+    (
+        "textDocument/switchSourceHeader",
+        "switchSourceHeaderProvider",
+    ),
+    ("textDocument/symbolInfo", "symbolInfoProvider"),
+    ("$/memoryUsage", "memoryUsageProvider"),
+    ("clangd/inlayHints", "clangdInlayHintsProvider"),
+];
+
+/// 后端在 `initialize` 响应中声明的信息。
+///
+/// - `name`/`version`: `serverInfo` 中的名称和版本
+/// - `clangd_version`: 从版本字符串中解析出的 clangd 主、次、修订版本号
+/// - `capabilities`: 原始的 `ServerCapabilities`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbedBackend {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub clangd_version: Option<(u32, u32, u32)>,
+    pub capabilities: Map<String, Value>,
+}
+
+/// 后端的实际能力，收到默认分片的 `initialize` 响应之前是未知的。
+///
+/// `serverInfo` 中没有版本时，使用后端日志中的 `clangd version ...` 判断 clangd 版本。
+#[derive(Default)]
+pub struct BackendCapabilities {
+    probed: RwLock<Option<ProbedBackend>>,
+    logged_version: RwLock<Option<(u32, u32, u32)>>,
+}
+
+impl BackendCapabilities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 从 `initialize` 响应的 `result` 中记录后端的能力，之前的记录被替换。
+    pub fn record(&self, result: &Value) {
+        let capabilities = result
+            .get("capabilities")
+            .and_then(|c| c.as_object())
+            .cloned()
+            .unwrap_or_default();
+        let server_info = |field: &str| {
+            result
+                .pointer(&format!("/serverInfo/{}", field))
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+        let version = server_info("version");
+        let probed = ProbedBackend {
+            name: server_info("name"),
+            clangd_version: version.as_deref().and_then(parse_clangd_version),
+            version,
+            capabilities,
+        };
+        *self.probed.write().unwrap() = Some(probed);
+    }
+
+    /// 检查后端的一行日志，记录其中的 clangd 版本。
+    pub fn record_log_line(&self, line: &str) {
+        if let Some(at) = line.find("clangd version ")
+            && let Some(version) = parse_clangd_version(&line[at..])
+        {
+            *self.logged_version.write().unwrap() = Some(version);
+        }
+    }
+
+    /// 是否已经收到后端的能力。
+    pub fn is_known(&self) -> bool {
+        self.probed.read().unwrap().is_some()
+    }
+
+    /// 后端是否支持某个方法。
+    ///
+    /// # 返回
+    ///
+    /// 还没有收到后端的能力、或者方法不对应任何能力字段时返回 `None`，调用方应当按支持处理
+    pub fn supports(&self, method: &str) -> Option<bool> {
+        let (_, field) = METHOD_CAPABILITIES
+            .iter()
+            .find(|(known, _)| *known == method)?;
+        self.has_capability(field)
+    }
+
+    /// 后端是否声明了某个能力字段（包括 clangd 的扩展字段），字段为 `false` 或 `null` 时视为不支持。
+    ///
+    /// 还没有收到后端的能力时返回 `None`。
+    pub fn has_capability(&self, field: &str) -> Option<bool> {
+        let probed = self.probed.read().unwrap();
+        let capability = probed.as_ref()?.capabilities.get(field);
+        Some(!matches!(
+            capability,
+            None | Some(Value::Null) | Some(Value::Bool(false))
+        ))
+    }
+
+    /// 后端的 clangd 版本，后端不是 clangd 或者还不知道时返回 `None`。
+    pub fn clangd_version(&self) -> Option<(u32, u32, u32)> {
+        let probed = self.probed.read().unwrap();
+        probed
+            .as_ref()
+            .and_then(|probed| probed.clangd_version)
+            .or(*self.logged_version.read().unwrap())
+    }
+
+    /// 记录的全部信息。
+    pub fn snapshot(&self) -> Option<ProbedBackend> {
+        self.probed.read().unwrap().clone()
+    }
+}
+
+/// 从 `clangd version 17.0.6 (...)` 这样的版本字符串中解析版本号，缺少的部分按 0 处理。
+pub fn parse_clangd_version(version: &str) -> Option<(u32, u32, u32)> {
+    let numbers = version
+        .split_whitespace()
+        .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))?;
+    let mut parts = numbers
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|part| part.parse::<u32>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().flatten().unwrap_or(0);
+    let patch = parts.next().flatten().unwrap_or(0);
+    Some((major, minor, patch))
+}
@@ -0,0 +1,180 @@
+//! # clangd 启动参数模块
+//!
+//! 把 `[backend.clangd]` 中的常用设置转换成 clangd 的命令行参数，追加在 `[backend] args` 之后。
+//! 配置加载时检查设置是否有效、是否与 `args` 中的原始参数重复；启动前运行 `clangd --version`，
+//! 省略检测到的版本不支持的参数，避免旧版本的 clangd 因为未知参数而无法启动。
+
+use anyhow::{Result, bail};
+use log::{info, warn};
+
+use crate::capabilities::parse_clangd_version;
+use crate::compat::Version;
+use crate::config::{BackendConfig, ClangdFlagsConfig, HeaderInsertion, PchStorage};
+use crate::doctor;
+
+/// 每个参数和支持它的最早的 clangd 版本。
+pub const FLAGS: &[(&str, Version)] = &[
+    ("--background-index", (8, 0, 0)),
+    ("-j", (6, 0, 0)),
+    ("--pch-storage", (7, 0, 0)),
+    ("--query-driver", (9, 0, 0)),
+    ("--header-insertion", (9, 0, 0)),
+];
+
+/// 按版本筛选后的启动参数。
+///
+/// - `args`: 追加到后端命令行的参数
+/// - `omitted`: 因为 clangd 版本过旧而省略的参数名
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LaunchFlags {
+    pub args: Vec<String>,
+    pub omitted: Vec<&'static str>,
+}
+
+/// 配置中设置了的参数，按 [`FLAGS`] 的顺序。
+fn configured(config: &ClangdFlagsConfig) -> Vec<(&'static str, String)> {
+    let mut flags = Vec::new();
+    if let Some(enabled) = config.background_index {
+        flags.push((
+            "--background-index",
+            format!("--background-index={}", enabled),
+        ));
+    }
+    if let Some(jobs) = config.jobs {
+        flags.push(("-j", format!("-j={}", jobs)));
+    }
+    if let Some(storage) = config.pch_storage {
+        let storage = match storage {
+            PchStorage::Disk => "disk",
+            PchStorage::Memory => "memory",
+        };
+        flags.push(("--pch-storage", format!("--pch-storage={}", storage)));
+    }
+    if !config.query_driver.is_empty() {
+        flags.push((
+            "--query-driver",
+            format!("--query-driver={}", config.query_driver.join(",")),
+        ));
+    }
+    if let Some(insertion) = config.header_insertion {
+        let insertion = match insertion {
+            HeaderInsertion::Iwyu => "iwyu",
+            HeaderInsertion::Never => "never",
+        };
+        flags.push((
+            "--header-insertion",
+            format!("--header-insertion={}", insertion),
+        ));
+    }
+    flags
+}
+
+/// `arg` 是否是参数 `flag`（`-j` 也可以直接跟数字，例如 `-j4`）。
+fn is_flag(arg: &str, flag: &str) -> bool {
+    match arg.strip_prefix(flag) {
+        Some(rest) => {
+            rest.is_empty()
+                || rest.starts_with('=')
+                || (flag == "-j" && rest.starts_with(|c: char| c.is_ascii_digit()))
+        }
+        None => false,
+    }
+}
+
+/// 检查 `[backend.clangd]` 的设置。
+///
+/// # 参数
+///
+/// * `config` - `[backend.clangd]`
+/// * `args` - `[backend] args` 中的原始参数
+///
+/// # 错误
+///
+/// `jobs` 为 0、`query_driver` 中有空的模式，或者参数同时出现在 `args` 中时返回错误
+pub fn validate(config: &ClangdFlagsConfig, args: &[String]) -> Result<()> {
+    if config.jobs == Some(0) {
+        bail!("backend.clangd.jobs 必须大于 0");
+    }
+    if config
+        .query_driver
+        .iter()
+        .any(|pattern| pattern.trim().is_empty())
+    {
+        bail!("backend.clangd.query_driver 中不能有空的模式");
+    }
+    for (flag, _) in configured(config) {
+        if let Some(arg) = args.iter().find(|arg| is_flag(arg, flag)) {
+            bail!(
+                "{} 同时出现在 backend.args（{}）和 [backend.clangd] 中，只能保留一处",
+                flag,
+                arg
+            );
+        }
+    }
+    Ok(())
+}
+
+/// 按 clangd 版本生成启动参数。
+///
+/// # 参数
+///
+/// * `config` - `[backend.clangd]`
+/// * `version` - 检测到的 clangd 版本，未知时不省略任何参数
+pub fn launch_flags(config: &ClangdFlagsConfig, version: Option<Version>) -> LaunchFlags {
+    let mut flags = LaunchFlags::default();
+    for (flag, arg) in configured(config) {
+        let since = FLAGS
+            .iter()
+            .find(|(known, _)| *known == flag)
+            .map_or((0, 0, 0), |(_, since)| *since);
+        if version.is_some_and(|version| version < since) {
+            flags.omitted.push(flag);
+        } else {
+            flags.args.push(arg);
+        }
+    }
+    flags
+}
+
+/// 把 `[backend.clangd]` 的参数追加到 `[backend] args`。
+///
+/// 只有设置了参数时才运行 `clangd --version` 检测版本。
+pub fn apply(backend: &mut BackendConfig) {
+    if configured(&backend.clangd).is_empty() {
+        return;
+    }
+    let (_, version) = doctor::probe_backend(backend);
+    let version = version.as_deref().and_then(parse_clangd_version);
+    let flags = launch_flags(&backend.clangd, version);
+    if let Some((major, minor, patch)) = version
+        && !flags.omitted.is_empty()
+    {
+        warn!(
+            "clangd {}.{}.{} 不支持 {}，已省略",
+            major,
+            minor,
+            patch,
+            flags.omitted.join("、")
+        );
+    }
+    info!("[backend.clangd] 启动参数: {}", flags.args.join(" "));
+    backend.args.extend(flags.args);
+}
+
+/// 把 `drivers` 加入启动参数中的 `--query-driver`，没有这个参数时追加一个。
+pub fn add_query_drivers(args: &mut Vec<String>, drivers: &[String]) {
+    if drivers.is_empty() {
+        return;
+    }
+    let drivers = drivers.join(",");
+    match args
+        .iter_mut()
+        .find(|arg| arg.starts_with("--query-driver="))
+    {
+        Some(arg) => {
+            arg.push(',');
+            arg.push_str(&drivers);
+        }
+        None => args.push(format!("--query-driver={}", drivers)),
+    }
+}
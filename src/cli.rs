@@ -0,0 +1,329 @@
+//! # 命令行模块
+//!
+//! 解析代理的命令行参数。代理通常由编辑器直接启动，参数很少，因此这里手工解析而不引入额外依赖。
+
+use anyhow::{Result, bail};
+use std::path::PathBuf;
+
+use crate::bench::BenchCommand;
+use crate::cmake::SetupCommand;
+use crate::frontend::FrontendMode;
+use crate::index::{IndexCommand, IndexFormat};
+use crate::trace_diff::DiffCommand;
+use crate::transport::Transport;
+use crate::trust::TrustCommand;
+use crate::validate::ValidateMode;
+
+/// `cache prune` 默认删除多少天没有更新的索引文件。
+pub const DEFAULT_PRUNE_DAYS: u64 = 30;
+
+/// 命令行参数。
+///
+/// - `config`: `--config <path>` 指定的配置文件
+/// - `profile`: `--profile <name>` 选择的配置档
+/// - `command`: 子命令；没有子命令时作为 LSP 代理运行
+/// - `validate`: `--validate` 或 `--validate=strict` 开启的消息校验
+/// - `mock_backend`: `--backend mock`，用模拟后端代替 clangd
+/// - `mock_fixture`: `--mock-fixture <path>` 指定模拟后端的脚本
+/// - `transport`: 与前端的连接，默认标准输入输出，`--pipe <name>` 或 `--pipe=<name>` 连接管道，
+///   `--socket <addr>` 连接 TCP 地址，`--listen <addr>` 监听 TCP 地址
+/// - `frontend`: `--frontend raw|typed`，默认直接转发 JSON-RPC 消息，`typed` 使用 tower-lsp 实现的前端
+/// - `trust_workspace`: `--trust-workspace`，本次会话信任工作区，使用工作区配置中会启动进程的设置
+#[derive(Debug, Clone, Default)]
+pub struct CliArgs {
+    pub config: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub command: Option<Command>,
+    pub validate: Option<ValidateMode>,
+    pub mock_backend: bool,
+    pub mock_fixture: Option<PathBuf>,
+    pub transport: Transport,
+    pub frontend: FrontendMode,
+    pub trust_workspace: bool,
+}
+
+/// 子命令。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// `cache ...`: 管理 clangd 索引缓存
+    Cache(CacheCommand),
+    /// `mock-server [<fixture>]`: 在标准输入输出上运行模拟后端，其他参数（分片的 clangd 参数）被忽略
+    MockServer { fixture: Option<PathBuf> },
+    /// `bench [--scenario <name>] [--rate <n>] [--duration <secs>] [--mix <m=w,...>] [--backend mock|clangd]`:
+    /// 负载测试
+    Bench(BenchCommand),
+    /// `doctor`: 检查后端程序、clangd 版本和启用的兼容垫片
+    Doctor,
+    /// `relay <addr>`: 远程模式的本地一端，在标准输入输出与远程代理之间转发消息；
+    /// `<addr>` 是 `host:port`（远程代理以 `--listen` 运行）或 `ssh://<host>`
+    Relay { address: String },
+    /// `index [--format scip|lsif] [--output <path>] [--backend mock|clangd] [<root>]`:
+    /// 驱动后端索引工作区，导出 SCIP 或 LSIF 文件
+    Index(IndexCommand),
+    /// `setup [--build-dir <dir>] [<root>]`: 运行 cmake 为工作区生成 `compile_commands.json`
+    Setup(SetupCommand),
+    /// `admin attach [<socket>]`: 连接运行中的代理的管理控制台，没有给出套接字时使用 `[admin] socket`
+    Admin { socket: Option<PathBuf> },
+    /// `trust [--revoke] [<dir>]`: 记录或者撤销对工作区的信任
+    Trust(TrustCommand),
+    /// `diff [--method <method>]... <trace-a> <trace-b>`: 比较两次会话的追踪中相同请求的响应，有不同时退出码为 1
+    Diff(DiffCommand),
+}
+
+/// `cache` 子命令。没有指定工作区时处理代理服务过的所有工作区。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheCommand {
+    /// `cache size [<workspace>...]`: 显示索引大小
+    Size { workspaces: Vec<PathBuf> },
+    /// `cache prune [--older-than <days>] [<workspace>...]`: 删除长时间没有更新的索引文件
+    Prune {
+        workspaces: Vec<PathBuf>,
+        older_than_days: u64,
+    },
+}
+
+impl CliArgs {
+    /// 解析命令行参数（不包含程序名）。
+    ///
+    /// # 错误
+    ///
+    /// 遇到未知参数或缺少参数值时返回错误
+    pub fn parse<I>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => parsed.config = Some(PathBuf::from(expect_value(&mut args, &arg)?)),
+                "--profile" => parsed.profile = Some(expect_value(&mut args, &arg)?),
+                // VSCode 等客户端会附加 --stdio
+                "--stdio" => parsed.transport = Transport::Stdio,
+                "--pipe" => parsed.transport = Transport::Pipe(expect_value(&mut args, &arg)?),
+                _ if arg.starts_with("--pipe=") => {
+                    parsed.transport = Transport::Pipe(arg["--pipe=".len()..].to_string());
+                }
+                "--socket" => parsed.transport = Transport::Socket(expect_value(&mut args, &arg)?),
+                _ if arg.starts_with("--socket=") => {
+                    parsed.transport = Transport::Socket(arg["--socket=".len()..].to_string());
+                }
+                "--listen" => parsed.transport = Transport::Listen(expect_value(&mut args, &arg)?),
+                "--validate" => parsed.validate = Some(ValidateMode::Report),
+                "--validate=strict" => parsed.validate = Some(ValidateMode::Strict),
+                "--backend" => match expect_value(&mut args, &arg)?.as_str() {
+                    "mock" => parsed.mock_backend = true,
+                    "clangd" => parsed.mock_backend = false,
+                    value => bail!("未知的后端: {}", value),
+                },
+                "--frontend" => match expect_value(&mut args, &arg)?.as_str() {
+                    "raw" => parsed.frontend = FrontendMode::Raw,
+                    "typed" => parsed.frontend = FrontendMode::Typed,
+                    value => bail!("未知的前端: {}", value),
+                },
+                "--trust-workspace" => parsed.trust_workspace = true,
+                "--mock-fixture" => {
+                    parsed.mock_fixture = Some(PathBuf::from(expect_value(&mut args, &arg)?));
+                }
+                "cache" => {
+                    parsed.command = Some(Command::Cache(CacheCommand::parse(&mut args)?));
+                }
+                "bench" => {
+                    parsed.command = Some(Command::Bench(parse_bench(&mut args)?));
+                }
+                "doctor" => parsed.command = Some(Command::Doctor),
+                "index" => parsed.command = Some(Command::Index(parse_index(&mut args)?)),
+                "setup" => parsed.command = Some(Command::Setup(parse_setup(&mut args)?)),
+                "admin" => parsed.command = Some(parse_admin(&mut args)?),
+                "trust" => parsed.command = Some(Command::Trust(parse_trust(&mut args)?)),
+                "diff" => parsed.command = Some(Command::Diff(parse_diff(&mut args)?)),
+                "relay" => {
+                    let address = expect_value(&mut args, &arg)?;
+                    parsed.command = Some(Command::Relay { address });
+                }
+                "mock-server" => {
+                    let fixture = args.by_ref().find(|arg| !arg.starts_with('-'));
+                    parsed.command = Some(Command::MockServer {
+                        fixture: fixture.map(PathBuf::from),
+                    });
+                    break;
+                }
+                _ => bail!("未知参数: {}", arg),
+            }
+        }
+        // `--backend mock` 写在子命令之前时同样作用于负载测试
+        if parsed.mock_backend {
+            match &mut parsed.command {
+                Some(Command::Bench(bench)) => bench.mock = true,
+                Some(Command::Index(index)) => index.mock = true,
+                _ => {}
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl CacheCommand {
+    fn parse(args: &mut impl Iterator<Item = String>) -> Result<Self> {
+        let Some(action) = args.next() else {
+            bail!("cache 需要子命令: size 或 prune");
+        };
+        let mut workspaces = Vec::new();
+        let mut older_than_days = DEFAULT_PRUNE_DAYS;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--older-than" if action == "prune" => {
+                    let value = expect_value(args, &arg)?;
+                    older_than_days = match value.parse() {
+                        Ok(days) => days,
+                        Err(_) => bail!("--older-than 需要天数: {}", value),
+                    };
+                }
+                _ if arg.starts_with("--") => bail!("未知参数: {}", arg),
+                _ => workspaces.push(PathBuf::from(arg)),
+            }
+        }
+
+        match action.as_str() {
+            "size" => Ok(Self::Size { workspaces }),
+            "prune" => Ok(Self::Prune {
+                workspaces,
+                older_than_days,
+            }),
+            _ => bail!("未知的 cache 子命令: {}", action),
+        }
+    }
+}
+
+fn parse_bench(args: &mut impl Iterator<Item = String>) -> Result<BenchCommand> {
+    let mut command = BenchCommand {
+        scenario: "completion-storm".to_string(),
+        rate: None,
+        duration_secs: None,
+        mix: None,
+        mock: false,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scenario" => command.scenario = expect_value(args, &arg)?,
+            "--rate" => command.rate = Some(expect_number(args, &arg)?),
+            "--duration" => command.duration_secs = Some(expect_number(args, &arg)?),
+            "--mix" => command.mix = Some(expect_value(args, &arg)?),
+            "--backend" => match expect_value(args, &arg)?.as_str() {
+                "mock" => command.mock = true,
+                "clangd" => command.mock = false,
+                value => bail!("未知的后端: {}", value),
+            },
+            _ => bail!("未知参数: {}", arg),
+        }
+    }
+    Ok(command)
+}
+
+fn parse_index(args: &mut impl Iterator<Item = String>) -> Result<IndexCommand> {
+    let mut command = IndexCommand {
+        format: IndexFormat::Scip,
+        output: None,
+        root: None,
+        mock: false,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => match expect_value(args, &arg)?.as_str() {
+                "scip" => command.format = IndexFormat::Scip,
+                "lsif" => command.format = IndexFormat::Lsif,
+                value => bail!("未知的索引格式: {}（可选 scip、lsif）", value),
+            },
+            "--output" => command.output = Some(PathBuf::from(expect_value(args, &arg)?)),
+            "--backend" => match expect_value(args, &arg)?.as_str() {
+                "mock" => command.mock = true,
+                "clangd" => command.mock = false,
+                value => bail!("未知的后端: {}", value),
+            },
+            _ if !arg.starts_with('-') && command.root.is_none() => {
+                command.root = Some(PathBuf::from(arg));
+            }
+            _ => bail!("未知参数: {}", arg),
+        }
+    }
+    Ok(command)
+}
+
+fn parse_setup(args: &mut impl Iterator<Item = String>) -> Result<SetupCommand> {
+    let mut command = SetupCommand::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--build-dir" => command.build_dir = Some(PathBuf::from(expect_value(args, &arg)?)),
+            _ if !arg.starts_with('-') && command.root.is_none() => {
+                command.root = Some(PathBuf::from(arg));
+            }
+            _ => bail!("未知参数: {}", arg),
+        }
+    }
+    Ok(command)
+}
+
+fn parse_trust(args: &mut impl Iterator<Item = String>) -> Result<TrustCommand> {
+    let mut command = TrustCommand::default();
+    for arg in args {
+        match arg.as_str() {
+            "--revoke" => command.revoke = true,
+            _ if !arg.starts_with('-') && command.root.is_none() => {
+                command.root = Some(PathBuf::from(arg));
+            }
+            _ => bail!("未知参数: {}", arg),
+        }
+    }
+    Ok(command)
+}
+
+fn parse_diff(args: &mut impl Iterator<Item = String>) -> Result<DiffCommand> {
+    let mut traces = Vec::new();
+    let mut methods = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--method" => methods.push(expect_value(args, &arg)?),
+            _ if !arg.starts_with('-') && traces.len() < 2 => traces.push(PathBuf::from(arg)),
+            _ => bail!("未知参数: {}", arg),
+        }
+    }
+    let mut traces = traces.into_iter();
+    let (Some(a), Some(b)) = (traces.next(), traces.next()) else {
+        bail!("diff 需要两个追踪文件");
+    };
+    Ok(DiffCommand { a, b, methods })
+}
+
+fn parse_admin(args: &mut impl Iterator<Item = String>) -> Result<Command> {
+    match args.next().as_deref() {
+        Some("attach") => {}
+        Some(action) => bail!("未知的 admin 子命令: {}", action),
+        None => bail!("admin 需要子命令: attach"),
+    }
+    let mut socket = None;
+    for arg in args {
+        match arg.as_str() {
+            _ if !arg.starts_with('-') && socket.is_none() => socket = Some(PathBuf::from(arg)),
+            _ => bail!("未知参数: {}", arg),
+        }
+    }
+    Ok(Command::Admin { socket })
+}
+
+fn expect_number<T: std::str::FromStr>(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Result<T> {
+    let value = expect_value(args, flag)?;
+    match value.parse() {
+        Ok(number) => Ok(number),
+        Err(_) => bail!("参数 {} 需要数字: {}", flag, value),
+    }
+}
+
+fn expect_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String> {
+    match args.next() {
+        Some(value) => Ok(value),
+        None => bail!("参数 {} 缺少值", flag),
+    }
+}
@@ -0,0 +1,166 @@
+//! # 客户端模块
+//!
+//! 通过标准输入输出驱动一个 LSP 服务器的简单客户端，供负载测试和索引导出这类批处理子命令使用。
+//! 服务器发来的请求一律应答 `null`，通知被忽略。
+
+use anyhow::{Context, Result, bail};
+use dashmap::DashMap;
+use futures::TryStreamExt;
+use serde_json::{Value, json};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio_util::codec::FramedRead;
+
+use crate::codec::LspCodec;
+use crate::dispatcher::Dispatcher;
+
+/// 单个请求的超时时间。
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 通过标准输入输出与一个 LSP 服务器通信的客户端。
+pub struct LspClient {
+    child: Child,
+    writer: UnboundedSender<String>,
+    pending: Arc<DashMap<u64, oneshot::Sender<Value>>>,
+    next_id: AtomicU64,
+}
+
+impl LspClient {
+    /// 启动服务器进程，服务器的标准错误被丢弃。
+    ///
+    /// # 错误
+    ///
+    /// 如果进程无法启动，返回错误
+    pub fn spawn(program: &str, args: &[String]) -> Result<Self> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("无法启动 {}", program))?;
+        let mut stdin = child.stdin.take().context("无法获取标准输入")?;
+        let stdout = child.stdout.take().context("无法获取标准输出")?;
+        let mut stdout = FramedRead::new(stdout, LspCodec::default());
+
+        let (writer, mut rx) = mpsc::unbounded_channel::<String>();
+        tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                if stdin.write_all(message.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let pending: Arc<DashMap<u64, oneshot::Sender<Value>>> = Arc::new(DashMap::new());
+        let responses = Arc::clone(&pending);
+        let replies = writer.clone();
+        tokio::spawn(async move {
+            while let Ok(Some(rpc)) = stdout.try_next().await {
+                match (rpc.get("method"), rpc.get("id")) {
+                    // 服务器的请求（如 workDoneProgress/create）一律应答 null
+                    (Some(_), Some(id)) => {
+                        let reply = json!({"jsonrpc": "2.0", "id": id, "result": null});
+                        if let Ok(message) = Dispatcher::format_lsp_message(&reply) {
+                            let _ = replies.send(message);
+                        }
+                    }
+                    (None, Some(id)) => {
+                        if let Some((_, waiter)) = id.as_u64().and_then(|id| responses.remove(&id))
+                        {
+                            let _ = waiter.send(rpc);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        Ok(Self {
+            child,
+            writer,
+            pending,
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    /// 发出请求并等待响应。
+    ///
+    /// # 错误
+    ///
+    /// 如果连接已关闭、超时没有响应或者服务器返回错误，返回错误
+    pub async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let (id, response) = self.start_request(method, params)?;
+        let response = match tokio::time::timeout(REQUEST_TIMEOUT, response).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => bail!("{} 的连接已关闭", method),
+            Err(_) => {
+                self.pending.remove(&id);
+                bail!("{} 请求超时", method);
+            }
+        };
+        if let Some(error) = response.get("error") {
+            bail!("{} 请求失败: {}", method, error);
+        }
+        Ok(response)
+    }
+
+    /// 发出请求，不等待响应。
+    ///
+    /// 返回时请求已经进入发送队列，之后发出的消息一定排在它的后面；响应没有超时，由调用者决定等待多久。
+    ///
+    /// # 错误
+    ///
+    /// 如果连接已关闭，返回错误
+    pub fn send_request(&self, method: &str, params: Value) -> Result<oneshot::Receiver<Value>> {
+        Ok(self.start_request(method, params)?.1)
+    }
+
+    fn start_request(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<(u64, oneshot::Receiver<Value>)> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        let message = Dispatcher::format_lsp_message(&request)?;
+        let (waiter, response) = oneshot::channel();
+        self.pending.insert(id, waiter);
+        if self.writer.send(message).is_err() {
+            self.pending.remove(&id);
+            bail!("{} 的连接已关闭", method);
+        }
+        Ok((id, response))
+    }
+
+    /// 发出通知。
+    ///
+    /// # 错误
+    ///
+    /// 如果连接已关闭，返回错误
+    pub fn notify(&self, method: &str, params: Value) -> Result<()> {
+        let notification = json!({"jsonrpc": "2.0", "method": method, "params": params});
+        self.writer
+            .send(Dispatcher::format_lsp_message(&notification)?)?;
+        Ok(())
+    }
+
+    /// 按 LSP 的方式结束服务器，没有及时退出时结束进程。
+    pub async fn shutdown(mut self) {
+        let _ = self.request("shutdown", Value::Null).await;
+        let _ = self.notify("exit", Value::Null);
+        if tokio::time::timeout(Duration::from_secs(2), self.child.wait())
+            .await
+            .is_err()
+        {
+            let _ = self.child.start_kill();
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! # CMake 模块
+//!
+//! 检测工作区中的 `CMakeLists.txt`，以 `-DCMAKE_EXPORT_COMPILE_COMMANDS=ON` 运行 cmake 生成编译数据库，
+//! 并在工作区根目录链接生成的 `compile_commands.json`，供 `codefuse setup` 和 `codefuse.generateCompileCommands` 使用。
+
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{CmakeConfig, Config};
+
+/// 编译数据库的文件名。
+pub const DATABASE: &str = "compile_commands.json";
+
+/// `setup` 子命令的参数。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SetupCommand {
+    /// 工作区根目录，默认是当前目录
+    pub root: Option<PathBuf>,
+    /// 构建目录，覆盖 `[cmake] build_dir`
+    pub build_dir: Option<PathBuf>,
+}
+
+/// 工作区是否是 CMake 项目。
+pub fn has_project(root: &Path) -> bool {
+    root.join("CMakeLists.txt").is_file()
+}
+
+/// 工作区的构建目录。
+pub fn build_dir(config: &CmakeConfig, root: &Path) -> PathBuf {
+    root.join(&config.build_dir)
+}
+
+/// 运行 cmake 的参数。
+pub fn configure_args(config: &CmakeConfig, root: &Path) -> Vec<String> {
+    let mut args = vec![
+        "-S".to_string(),
+        root.display().to_string(),
+        "-B".to_string(),
+        build_dir(config, root).display().to_string(),
+        "-DCMAKE_EXPORT_COMPILE_COMMANDS=ON".to_string(),
+    ];
+    args.extend(config.args.iter().cloned());
+    args
+}
+
+/// 运行 cmake 生成编译数据库，按配置在工作区根目录链接生成的文件。
+///
+/// # 返回
+///
+/// 生成的 `compile_commands.json` 的路径
+///
+/// # 错误
+///
+/// 工作区中没有 `CMakeLists.txt`、cmake 无法运行或者失败、没有生成编译数据库时返回错误
+pub fn generate(config: &CmakeConfig, root: &Path) -> Result<PathBuf> {
+    if !has_project(root) {
+        bail!("{} 中没有 CMakeLists.txt", root.display());
+    }
+    let args = configure_args(config, root);
+    info!("运行 {} {}", config.command, args.join(" "));
+    let output = Command::new(&config.command)
+        .args(&args)
+        .current_dir(root)
+        .output()
+        .with_context(|| format!("无法运行 {}", config.command))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let tail: Vec<&str> = stderr.lines().rev().take(10).collect();
+        let tail: Vec<&str> = tail.into_iter().rev().collect();
+        bail!("cmake 失败（{}）:\n{}", output.status, tail.join("\n"));
+    }
+
+    let database = build_dir(config, root).join(DATABASE);
+    if !database.is_file() {
+        bail!(
+            "cmake 没有生成 {}，生成器可能不支持编译数据库",
+            database.display()
+        );
+    }
+    if config.link {
+        link(root, &database)?;
+    }
+    Ok(database)
+}
+
+/// 在工作区根目录创建指向 `database` 的 `compile_commands.json`。
+///
+/// 根目录中已有的普通文件不会被覆盖；不支持符号链接的平台上复制文件。
+fn link(root: &Path, database: &Path) -> Result<()> {
+    let target = root.join(DATABASE);
+    if target == database {
+        return Ok(());
+    }
+    match std::fs::symlink_metadata(&target) {
+        Ok(metadata) if !metadata.file_type().is_symlink() => {
+            warn!(
+                "{} 已经存在，不再链接 {}",
+                target.display(),
+                database.display()
+            );
+            return Ok(());
+        }
+        Ok(_) => std::fs::remove_file(&target)
+            .with_context(|| format!("无法删除 {}", target.display()))?,
+        Err(_) => {}
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(database, &target)
+        .with_context(|| format!("无法创建链接 {}", target.display()))?;
+    #[cfg(not(unix))]
+    std::fs::copy(database, &target)
+        .map(|_| ())
+        .with_context(|| format!("无法复制到 {}", target.display()))?;
+    Ok(())
+}
+
+/// 运行 `setup` 子命令。
+///
+/// # 错误
+///
+/// 工作区无法访问或者生成失败时返回错误
+pub fn run(command: &SetupCommand, config: &Config) -> Result<()> {
+    let root = match &command.root {
+        Some(root) => root.clone(),
+        None => std::env::current_dir()?,
+    };
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("无法访问工作区 {}", root.display()))?;
+    let mut cmake = config.cmake.clone();
+    if let Some(build_dir) = &command.build_dir {
+        cmake.build_dir = build_dir.clone();
+    }
+    let database = generate(&cmake, &root)?;
+    println!("已生成 {}", database.display());
+    Ok(())
+}
@@ -0,0 +1,165 @@
+//! # 消息编解码模块
+//!
+//! 按照 LSP 协议从字节流中切分消息。[`LspCodec`] 配合 `FramedRead` 使用，
+//! 所有消息共用同一个读缓冲区，消息头和消息体都直接在缓冲区中解析，不再为每一行和每个消息体单独分配内存。
+
+use anyhow::{Context, Result, bail};
+use bytes::{Buf, BytesMut};
+use log::warn;
+use serde_json::Value;
+use std::sync::Arc;
+use tokio_util::codec::Decoder;
+
+use crate::compression::FrameCompression;
+use crate::config::FrameEncoding;
+
+/// 单条消息体的最大长度。`Content-Length` 来自对端，不加限制时一个错误的消息头就能让代理分配任意大的内存。
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+const CONTENT_LENGTH: &[u8] = b"Content-Length:";
+const CONTENT_ENCODING: &[u8] = b"Content-Encoding:";
+const ACCEPT_ENCODING: &[u8] = b"Accept-Encoding:";
+
+/// LSP 消息解码器，每次产出一条解析后的 JSON 消息。
+///
+/// 先读取消息头直到空行，取出其中的 `Content-Length`，再读取相应长度的消息体并解析为 JSON。
+/// 消息头的行尾可以是 `\r\n`、`\n` 或者 Windows 文本模式转换出的 `\r\r\n`，头字段名不区分大小写。
+/// 没有 `Content-Length` 的消息头会被跳过；不是合法 JSON 的消息体被丢弃。
+/// 如果消息被截断，它会吞掉后面一条消息的开头，这条消息也随之丢失；再后面的消息头仍能在行中被找到，读取恢复正常。
+/// 通过 [`LspCodec::with_compression`] 创建时还处理帧压缩的 `Accept-Encoding` 和 `Content-Encoding`。
+#[derive(Debug, Default)]
+pub struct LspCodec {
+    /// 当前消息头中读到的 `Content-Length`
+    content_length: Option<usize>,
+    /// 当前消息头中读到的 `Content-Encoding`
+    content_encoding: Option<FrameEncoding>,
+    /// 消息头已经结束，正在等待的消息体长度
+    body_length: Option<usize>,
+    /// 正在等待的消息体的压缩算法
+    body_encoding: Option<FrameEncoding>,
+    /// 套接字传输上的帧压缩
+    compression: Option<Arc<FrameCompression>>,
+    /// 缓冲区中已经确认没有换行符的前缀长度
+    scanned: usize,
+}
+
+impl LspCodec {
+    /// 创建处理帧压缩的解码器，`compression` 为 `None` 时与 [`LspCodec::default`] 相同。
+    pub fn with_compression(compression: Option<Arc<FrameCompression>>) -> Self {
+        Self {
+            compression,
+            ..Self::default()
+        }
+    }
+
+    /// 解析消息体，压缩的消息体先解压。
+    fn parse_body(&self, encoding: Option<FrameEncoding>, body: &[u8]) -> Result<Value> {
+        match (encoding, &self.compression) {
+            (Some(encoding), Some(compression)) => {
+                let body = compression.decode_body(encoding, body)?;
+                Ok(serde_json::from_slice(&body)?)
+            }
+            _ => Ok(serde_json::from_slice(body)?),
+        }
+    }
+}
+
+impl Decoder for LspCodec {
+    type Item = Value;
+    type Error = anyhow::Error;
+
+    /// # 错误
+    ///
+    /// 如果 `Content-Length` 无法解析、超过 [`MAX_MESSAGE_SIZE`]，或者一行消息头超过这个长度，返回错误
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Value>> {
+        loop {
+            if let Some(length) = self.body_length {
+                if src.len() < length {
+                    src.reserve(length - src.len());
+                    return Ok(None);
+                }
+                self.body_length = None;
+                let encoding = self.body_encoding.take();
+                let parsed = self.parse_body(encoding, &src[..length]);
+                src.advance(length);
+                match parsed {
+                    Ok(json_body) => return Ok(Some(json_body)),
+                    Err(e) => {
+                        warn!("丢弃无法解析的消息: {}", e);
+                        continue;
+                    }
+                }
+            }
+
+            let Some(offset) = src[self.scanned..].iter().position(|b| *b == b'\n') else {
+                if src.len() > MAX_MESSAGE_SIZE {
+                    bail!("消息头过长: {} 字节", src.len());
+                }
+                self.scanned = src.len();
+                return Ok(None);
+            };
+            let end = self.scanned + offset;
+            self.scanned = 0;
+
+            let line = src[..end].trim_ascii();
+            if line.is_empty() {
+                // 消息头结束；没有 Content-Length 的消息头被跳过
+                if let Some(length) = self.content_length.take() {
+                    if length > MAX_MESSAGE_SIZE {
+                        bail!("消息过大: {} 字节", length);
+                    }
+                    self.body_length = Some(length);
+                    self.body_encoding = self.content_encoding.take();
+                }
+                self.content_encoding = None;
+            } else if let Some(start) = line
+                .windows(CONTENT_LENGTH.len())
+                .position(|w| w.eq_ignore_ascii_case(CONTENT_LENGTH))
+            {
+                // 前一条消息被截断时，消息头前面会粘着剩余的字节
+                self.content_length = Some(parse_length(&line[start + CONTENT_LENGTH.len()..])?);
+            } else if self.compression.is_some()
+                && let Some(value) = header_value(line, CONTENT_ENCODING)
+            {
+                self.content_encoding = FrameEncoding::from_name(value);
+            } else if let Some(compression) = &self.compression
+                && let Some(value) = header_value(line, ACCEPT_ENCODING)
+            {
+                compression.peer_accepts(value);
+            }
+            src.advance(end + 1);
+        }
+    }
+
+    /// # 错误
+    ///
+    /// 除了 [`decode`](Self::decode) 的错误，如果流在消息体中途结束，也返回错误
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Value>> {
+        if let Some(json_body) = self.decode(src)? {
+            return Ok(Some(json_body));
+        }
+        if self.body_length.is_some() {
+            bail!("消息体不完整: 流在 {} 字节处结束", src.len());
+        }
+        // 剩下的只是不完整的消息头
+        src.clear();
+        self.scanned = 0;
+        Ok(None)
+    }
+}
+
+/// 名称为 `name` 的头字段的值，名称不区分大小写。
+fn header_value<'a>(line: &'a [u8], name: &[u8]) -> Option<&'a str> {
+    let value = line
+        .get(..name.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(name))
+        .map(|_| &line[name.len()..])?;
+    std::str::from_utf8(value).ok()
+}
+
+fn parse_length(value: &[u8]) -> Result<usize> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .context("Content-Length 解析失败")
+}
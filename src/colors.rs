@@ -0,0 +1,209 @@
+//! # 颜色模块
+//!
+//! 后端不提供颜色时的本地实现：在源代码中查找 `#rrggbb`、`0xrrggbb` 和 `rgb()`/`rgba()` 形式的颜色字面量，
+//! 应答 `textDocument/documentColor`，并为编辑器的取色器生成同样形式的 `textDocument/colorPresentation`。
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use regex::{Captures, Regex};
+use serde_json::Value;
+use std::ops::Range;
+use std::sync::LazyLock;
+use tower_lsp::lsp_types::request::{ColorPresentationRequest, DocumentColor, Request};
+use tower_lsp::lsp_types::{self, Color, ColorInformation, ColorPresentation, TextEdit};
+
+use crate::dispatcher::Dispatcher;
+use crate::document_store::{offset_at, position_at};
+use crate::handlers::{self, HandlerCtx, LOCAL_PROVIDER_PRIORITY, Verdict};
+use crate::trace::Direction;
+
+/// `#rrggbb` 或 `#rrggbbaa`，常见于字符串中的样式。
+static HASH: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"#([0-9a-fA-F]{8}|[0-9a-fA-F]{6})\b").unwrap());
+
+/// `0xrrggbb` 或 `0xaarrggbb`，常见于嵌入式界面库的颜色常量。
+static INTEGER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b0[xX]([0-9a-fA-F]{8}|[0-9a-fA-F]{6})\b").unwrap());
+
+/// `rgb(r, g, b)` 或 `rgba(r, g, b, a)`，分量是 0–255，透明度是 0–1。
+static FUNCTION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\brgba?\(\s*(\d{1,3})\s*,\s*(\d{1,3})\s*,\s*(\d{1,3})\s*(?:,\s*(\d*\.?\d+)\s*)?\)")
+        .unwrap()
+});
+
+/// 颜色字面量的写法，生成颜色表示时保持原来的写法。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// `#rrggbb`，透明度在最后
+    Hash,
+    /// `0xrrggbb`，透明度在最前；`upper` 表示十六进制数字是大写
+    Integer { upper: bool },
+    /// `rgb()`/`rgba()`
+    Function,
+}
+
+impl Style {
+    /// 文本的写法，不是颜色字面量时返回 `None`。
+    pub fn of(literal: &str) -> Option<Self> {
+        if literal.starts_with('#') {
+            Some(Self::Hash)
+        } else if let Some(digits) = literal
+            .strip_prefix("0x")
+            .or_else(|| literal.strip_prefix("0X"))
+        {
+            Some(Self::Integer {
+                upper: digits.chars().any(|c| c.is_ascii_uppercase()),
+            })
+        } else if literal.starts_with("rgb") {
+            Some(Self::Function)
+        } else {
+            None
+        }
+    }
+
+    /// 用这种写法表示颜色。
+    pub fn format(self, color: &Color) -> String {
+        let [r, g, b, a] = [color.red, color.green, color.blue, color.alpha].map(to_byte);
+        let opaque = a == u8::MAX;
+        match self {
+            Self::Hash if opaque => format!("#{:02x}{:02x}{:02x}", r, g, b),
+            Self::Hash => format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a),
+            Self::Integer { upper } => {
+                let digits = if opaque {
+                    format!("{:02x}{:02x}{:02x}", r, g, b)
+                } else {
+                    format!("{:02x}{:02x}{:02x}{:02x}", a, r, g, b)
+                };
+                if upper {
+                    format!("0x{}", digits.to_ascii_uppercase())
+                } else {
+                    format!("0x{}", digits)
+                }
+            }
+            Self::Function if opaque => format!("rgb({}, {}, {})", r, g, b),
+            Self::Function => {
+                let alpha = format!("{:.2}", color.alpha.clamp(0.0, 1.0));
+                let alpha = alpha.trim_end_matches('0').trim_end_matches('.');
+                format!("rgba({}, {}, {}, {})", r, g, b, alpha)
+            }
+        }
+    }
+}
+
+fn to_byte(component: f32) -> u8 {
+    (component.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn hex_color(digits: &str, alpha_first: bool) -> Option<Color> {
+    let value = u32::from_str_radix(digits, 16).ok()?;
+    let (rgb, alpha) = match (digits.len(), alpha_first) {
+        (6, _) => (value, 0xff),
+        (_, true) => (value & 0xff_ffff, value >> 24),
+        (_, false) => (value >> 8, value & 0xff),
+    };
+    let channel = |shift: u32| ((rgb >> shift) & 0xff) as f32 / 255.0;
+    Some(Color {
+        red: channel(16),
+        green: channel(8),
+        blue: channel(0),
+        alpha: alpha as f32 / 255.0,
+    })
+}
+
+fn function_color(captures: &Captures) -> Option<Color> {
+    let channel = |i: usize| -> Option<f32> {
+        let value: u16 = captures.get(i)?.as_str().parse().ok()?;
+        (value <= 255).then_some(value as f32 / 255.0)
+    };
+    let alpha = match captures.get(4) {
+        Some(alpha) => alpha.as_str().parse::<f32>().ok().filter(|a| *a <= 1.0)?,
+        None => 1.0,
+    };
+    Some(Color {
+        red: channel(1)?,
+        green: channel(2)?,
+        blue: channel(3)?,
+        alpha,
+    })
+}
+
+/// 文本中的颜色字面量，按位置排列。
+pub fn find_colors(text: &str) -> Vec<(Range<usize>, Color)> {
+    let mut found: Vec<(Range<usize>, Color)> = HASH
+        .captures_iter(text)
+        .filter_map(|c| Some((c.get(0)?.range(), hex_color(&c[1], false)?)))
+        .chain(
+            INTEGER
+                .captures_iter(text)
+                .filter_map(|c| Some((c.get(0)?.range(), hex_color(&c[1], true)?))),
+        )
+        .chain(
+            FUNCTION
+                .captures_iter(text)
+                .filter_map(|c| Some((c.get(0)?.range(), function_color(&c)?))),
+        )
+        .collect();
+    found.sort_by_key(|(range, _)| range.start);
+    found
+}
+
+/// `textDocument/documentColor` 的结果。
+pub fn document_colors(text: &str) -> Vec<ColorInformation> {
+    find_colors(text)
+        .into_iter()
+        .map(|(range, color)| ColorInformation {
+            range: lsp_types::Range::new(
+                position_at(text, range.start),
+                position_at(text, range.end),
+            ),
+            color,
+        })
+        .collect()
+}
+
+/// `textDocument/colorPresentation` 的结果：替换 `range` 中的颜色，原来的写法排在最前面。
+pub fn presentations(text: &str, color: &Color, range: lsp_types::Range) -> Vec<ColorPresentation> {
+    let start = offset_at(text, range.start);
+    let end = offset_at(text, range.end).max(start);
+    let original = Style::of(text.get(start..end).unwrap_or_default());
+    let upper = matches!(original, Some(Style::Integer { upper: true }));
+    let mut styles = vec![Style::Hash, Style::Integer { upper }, Style::Function];
+    if let Some(original) = original {
+        styles.retain(|style| *style != original);
+        styles.insert(0, original);
+    }
+    styles
+        .into_iter()
+        .map(|style| {
+            let label = style.format(color);
+            ColorPresentation {
+                text_edit: Some(TextEdit::new(range, label.clone())),
+                label,
+                additional_text_edits: None,
+            }
+        })
+        .collect()
+}
+
+/// 注册颜色的处理器，开启了本地查找并且后端不提供颜色时由代理应答。
+pub fn register(dispatcher: &mut Dispatcher) {
+    for method in [DocumentColor::METHOD, ColorPresentationRequest::METHOD] {
+        dispatcher.register_handler(
+            Direction::Frontend,
+            method,
+            LOCAL_PROVIDER_PRIORITY,
+            answer_colors,
+        );
+    }
+}
+
+/// 以本地查找的颜色字面量应答 `textDocument/documentColor` 和 `textDocument/colorPresentation`。
+fn answer_colors(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let method = rpc.get("method").and_then(|m| m.as_str()).unwrap_or("");
+        match ctx.dispatcher().local_colors(method, &rpc) {
+            Some(result) => Ok(Verdict::Respond(handlers::response(&rpc, result))),
+            None => Ok(Verdict::Continue(rpc)),
+        }
+    })
+}
@@ -0,0 +1,43 @@
+//! # 自定义命令模块
+//!
+//! 代理自己实现的 `workspace/executeCommand` 命令。这些命令与 clangd 的命令一起出现在 initialize 响应的
+//! `executeCommandProvider` 中，其他命令照常转发给后端。
+
+use tower_lsp::lsp_types::{ExecuteCommandOptions, ServerCapabilities};
+
+/// 重启所有后端：启用了备用后端时切换到备用后端，否则启动新进程并重放打开的文档。
+pub const RESTART_BACKEND: &str = "codefuse.restartBackend";
+
+/// 把最近的消息追踪导出为 NDJSON 文件，参数是可选的文件路径。
+pub const DUMP_TRACE: &str = "codefuse.dumpTrace";
+
+/// 应用当前诊断附带的所有修复，参数是可选的文档 URI，省略时处理所有有诊断的文档。
+pub const APPLY_ALL_FIXITS: &str = "codefuse.applyAllFixits";
+
+/// 运行 cmake 生成 `compile_commands.json` 并重启后端，参数是可选的工作区根目录，省略时使用第一个工作区。
+pub const GENERATE_COMPILE_COMMANDS: &str = "codefuse.generateCompileCommands";
+
+/// 代理实现的所有命令。
+pub const PROXY_COMMANDS: &[&str] = &[
+    RESTART_BACKEND,
+    DUMP_TRACE,
+    APPLY_ALL_FIXITS,
+    GENERATE_COMPILE_COMMANDS,
+];
+
+/// 命令是否由代理实现。
+pub fn is_proxy_command(command: &str) -> bool {
+    PROXY_COMMANDS.contains(&command)
+}
+
+/// 把代理的命令合并到后端声明的能力中。
+pub fn advertise(capabilities: &mut ServerCapabilities) {
+    let provider = capabilities
+        .execute_command_provider
+        .get_or_insert_with(ExecuteCommandOptions::default);
+    for command in PROXY_COMMANDS {
+        if !provider.commands.iter().any(|c| c == command) {
+            provider.commands.push(command.to_string());
+        }
+    }
+}
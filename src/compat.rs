@@ -0,0 +1,205 @@
+//! # 兼容模块
+//!
+//! 旧版本 clangd 与新版 LSP 之间的差异由兼容垫片抹平：根据后端的 clangd 版本（来自 `initialize`
+//! 响应的 `serverInfo` 或后端日志）启用对应的垫片，改写请求或补全响应中缺少的字段。
+//! 当前启用的垫片可以用 `codefuse doctor` 查看。
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde_json::{Value, json};
+
+use crate::dispatcher::Dispatcher;
+use crate::handlers::{HandlerCtx, Verdict};
+use crate::trace::Direction;
+
+/// clangd 的主、次、修订版本号。
+pub type Version = (u32, u32, u32);
+
+/// 支持的最早的 clangd 版本，更早的版本不保证可用。
+pub const OLDEST_SUPPORTED: Version = (12, 0, 0);
+
+/// 兼容垫片。
+///
+/// - `name`: 垫片名称
+/// - `description`: 垫片做了什么
+/// - `since`/`until`: 适用的 clangd 版本范围，包含 `since`，不包含 `until`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shim {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub since: Version,
+    pub until: Version,
+}
+
+impl Shim {
+    /// 垫片是否适用于某个 clangd 版本。
+    pub fn applies(&self, version: Version) -> bool {
+        self.since <= version && version < self.until
+    }
+}
+
+/// 把 `textDocument/inlayHint` 转成 clangd 14 的扩展方法 `clangd/inlayHints`。
+pub const LEGACY_INLAY_HINTS: &str = "legacy-inlay-hints";
+
+/// 用 clangd 扩展的 `offsetEncoding` 补全 `positionEncoding`。
+pub const POSITION_ENCODING: &str = "position-encoding";
+
+/// 所有兼容垫片。
+pub const SHIMS: &[Shim] = &[
+    Shim {
+        name: LEGACY_INLAY_HINTS,
+        description: "textDocument/inlayHint 转发为 clangd/inlayHints，并转换响应的格式",
+        since: (14, 0, 0),
+        until: (15, 0, 0),
+    },
+    Shim {
+        name: POSITION_ENCODING,
+        description: "用 initialize 响应中的 offsetEncoding 补全 capabilities.positionEncoding",
+        since: (0, 0, 0),
+        until: (15, 0, 0),
+    },
+];
+
+/// clangd 14 中与 `textDocument/inlayHint` 对应的扩展方法。
+pub const LEGACY_INLAY_HINTS_METHOD: &str = "clangd/inlayHints";
+
+/// 改写请求的垫片的优先级，低于默认优先级，其他处理器看到的仍然是标准的请求。
+pub const REQUEST_SHIM_PRIORITY: i32 = -50;
+
+/// 改写响应的垫片的优先级，高于默认优先级，其他处理器看到的已经是标准的响应。
+pub const RESPONSE_SHIM_PRIORITY: i32 = 100;
+
+/// 某个 clangd 版本启用的垫片，版本未知时不启用任何垫片。
+pub fn active_shims(version: Option<Version>) -> Vec<&'static Shim> {
+    let Some(version) = version else {
+        return Vec::new();
+    };
+    SHIMS.iter().filter(|shim| shim.applies(version)).collect()
+}
+
+/// 某个垫片对某个 clangd 版本是否启用。
+pub fn is_active(name: &str, version: Option<Version>) -> bool {
+    active_shims(version).iter().any(|shim| shim.name == name)
+}
+
+/// 把 `clangd/inlayHints` 的结果转成 `textDocument/inlayHint` 的结果。
+///
+/// 旧格式的 `kind` 是字符串，`type`、`parameter` 分别对应标准的 1 和 2，其他种类不设置 `kind`；
+/// 没有 `position` 时类型提示取范围的结尾，其他提示取范围的开始。已经是标准格式的提示保持不变。
+pub fn legacy_inlay_hints_to_standard(hints: &Value) -> Value {
+    let Some(hints) = hints.as_array() else {
+        return hints.clone();
+    };
+    let hints = hints
+        .iter()
+        .map(|hint| {
+            let Some(kind) = hint.get("kind").and_then(|k| k.as_str()) else {
+                return hint.clone();
+            };
+            let position = hint.get("position").cloned().or_else(|| {
+                let end = if kind == "type" { "end" } else { "start" };
+                hint.pointer(&format!("/range/{}", end)).cloned()
+            });
+            let mut standard = json!({
+                "position": position,
+                "label": hint.get("label").cloned().unwrap_or(json!("")),
+            });
+            match kind {
+                "type" => standard["kind"] = json!(1),
+                "parameter" => standard["kind"] = json!(2),
+                _ => {}
+            }
+            standard
+        })
+        .collect();
+    Value::Array(hints)
+}
+
+/// 用 `initialize` 结果中的 `offsetEncoding` 补全 `capabilities.positionEncoding`。
+///
+/// # 返回
+///
+/// 是否补全了字段
+pub fn fill_position_encoding(result: &mut Value) -> bool {
+    let Some(encoding) = result.get("offsetEncoding").cloned() else {
+        return false;
+    };
+    let Some(capabilities) = result
+        .get_mut("capabilities")
+        .and_then(|c| c.as_object_mut())
+    else {
+        return false;
+    };
+    if capabilities.contains_key("positionEncoding") {
+        return false;
+    }
+    capabilities.insert("positionEncoding".to_string(), encoding);
+    true
+}
+
+/// 注册兼容垫片的处理器，垫片在运行时按后端的版本决定是否生效。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        "textDocument/inlayHint",
+        REQUEST_SHIM_PRIORITY,
+        shim_inlay_hint_request,
+    );
+    dispatcher.register_handler(
+        Direction::Backend,
+        "textDocument/inlayHint",
+        RESPONSE_SHIM_PRIORITY,
+        shim_inlay_hint_response,
+    );
+    dispatcher.register_handler(
+        Direction::Backend,
+        "initialize",
+        RESPONSE_SHIM_PRIORITY,
+        shim_initialize_response,
+    );
+}
+
+fn shim_inlay_hint_request(mut rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let version = ctx.backend_capabilities().clangd_version();
+        if is_active(LEGACY_INLAY_HINTS, version) {
+            rpc["method"] = json!(LEGACY_INLAY_HINTS_METHOD);
+        }
+        Ok(Verdict::Continue(rpc))
+    })
+}
+
+fn shim_inlay_hint_response(mut rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let version = ctx.backend_capabilities().clangd_version();
+        if is_active(LEGACY_INLAY_HINTS, version)
+            && let Some(result) = rpc.get_mut("result")
+        {
+            *result = legacy_inlay_hints_to_standard(result);
+        }
+        Ok(Verdict::Continue(rpc))
+    })
+}
+
+fn shim_initialize_response(mut rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let version = ctx.backend_capabilities().clangd_version();
+        let Some(result) = rpc.get_mut("result") else {
+            return Ok(Verdict::Continue(rpc));
+        };
+        if is_active(POSITION_ENCODING, version) {
+            fill_position_encoding(result);
+        }
+        // 转换后的 inlayHint 请求由 clangd/inlayHints 应答，向前端声明标准的能力
+        if is_active(LEGACY_INLAY_HINTS, version)
+            && let Some(capabilities) = result
+                .get_mut("capabilities")
+                .and_then(|c| c.as_object_mut())
+        {
+            capabilities
+                .entry("inlayHintProvider")
+                .or_insert(json!(true));
+        }
+        Ok(Verdict::Continue(rpc))
+    })
+}
@@ -0,0 +1,216 @@
+//! # 编译参数模块
+//!
+//! 没有 `compile_commands.json` 的工作区（例如 Bazel 单体仓库）中，代理为打开的文件生成编译命令：
+//! 优先使用 `bazel aquery` 给出的参数，其次展开 `compile_flags.txt` 模板，
+//! 再通过 `workspace/didChangeConfiguration` 的 `compilationDatabaseChanges` 交给 clangd。
+
+use anyhow::{Context, Result, bail};
+use log::{debug, info};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::RwLock;
+use tower_lsp::lsp_types::notification::{DidChangeConfiguration, Notification};
+
+use crate::cmake;
+use crate::config::FlagsConfig;
+
+/// clangd 直接读取的编译参数文件。
+pub const FLAGS_FILE: &str = "compile_flags.txt";
+
+/// 模板中可以使用的变量。
+const TEMPLATE_VARIABLES: &[&str] = &["${file}", "${dir}", "${root}"];
+
+/// 一个文件的编译命令。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCommand {
+    /// 编译命令的工作目录
+    pub directory: PathBuf,
+    /// 完整的编译命令，第一个元素是编译器
+    pub arguments: Vec<String>,
+}
+
+/// 生成交给 clangd 的 `workspace/didChangeConfiguration` 通知，替换一个文件的编译命令。
+pub fn database_change(file: &Path, command: &FileCommand) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": DidChangeConfiguration::METHOD,
+        "params": {"settings": {"compilationDatabaseChanges": {
+            file.display().to_string(): {
+                "workingDirectory": command.directory,
+                "compilationCommand": command.arguments,
+            },
+        }}},
+    })
+}
+
+/// 工作区根目录或者 `build` 目录中是否已有编译数据库，此时不需要代理生成编译参数。
+pub fn has_database(root: &Path) -> bool {
+    root.join(cmake::DATABASE).is_file() || root.join("build").join(cmake::DATABASE).is_file()
+}
+
+/// 从 `file` 所在目录向上查找 `compile_flags.txt` 模板，展开其中的变量。
+///
+/// # 返回
+///
+/// 模板生成的编译命令；找到的 `compile_flags.txt` 不含模板变量或者没有找到时返回 `None`
+pub fn from_template(file: &Path, compiler: &str) -> Option<FileCommand> {
+    let (root, text) = file.ancestors().skip(1).find_map(|dir| {
+        let text = std::fs::read_to_string(dir.join(FLAGS_FILE)).ok()?;
+        Some((dir, text))
+    })?;
+    if !TEMPLATE_VARIABLES
+        .iter()
+        .any(|variable| text.contains(variable))
+    {
+        return None;
+    }
+    let dir = file.parent()?;
+    let mut arguments = vec![compiler.to_string()];
+    arguments.extend(
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                line.replace("${file}", &file.display().to_string())
+                    .replace("${dir}", &dir.display().to_string())
+                    .replace("${root}", &root.display().to_string())
+            }),
+    );
+    arguments.push(file.display().to_string());
+    Some(FileCommand {
+        directory: root.to_path_buf(),
+        arguments,
+    })
+}
+
+/// 解析 `bazel aquery --output=jsonproto` 的输出。
+///
+/// # 参数
+///
+/// * `output` - aquery 的输出
+/// * `root` - Bazel 工作区根目录，动作中的源文件路径相对于它
+/// * `execution_root` - `bazel info execution_root`，编译命令在这个目录中运行
+///
+/// # 返回
+///
+/// 源文件的绝对路径到编译命令的映射
+///
+/// # 错误
+///
+/// 输出不是合法的 JSON 时返回错误
+pub fn parse_aquery(
+    output: &str,
+    root: &Path,
+    execution_root: &Path,
+) -> Result<HashMap<PathBuf, FileCommand>> {
+    let output: Value = serde_json::from_str(output).context("无法解析 bazel aquery 的输出")?;
+    let mut commands = HashMap::new();
+    for action in output
+        .get("actions")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let arguments: Vec<String> = action
+            .get("arguments")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|argument| argument.as_str().map(str::to_string))
+            .collect();
+        let Some(source) = arguments
+            .iter()
+            .position(|argument| argument == "-c")
+            .and_then(|index| arguments.get(index + 1))
+        else {
+            continue;
+        };
+        commands.insert(
+            root.join(source),
+            FileCommand {
+                directory: execution_root.to_path_buf(),
+                arguments,
+            },
+        );
+    }
+    Ok(commands)
+}
+
+/// 为没有编译数据库的工作区生成文件的编译命令。
+#[derive(Default)]
+pub struct CompileFlags {
+    compiler: String,
+    bazel: Option<(String, String)>,
+    /// `bazel aquery` 给出的编译命令
+    bazel_commands: RwLock<HashMap<PathBuf, FileCommand>>,
+}
+
+impl CompileFlags {
+    pub fn new(config: &FlagsConfig) -> Self {
+        Self {
+            compiler: config.compiler.clone(),
+            bazel: config
+                .bazel
+                .then(|| (config.bazel_command.clone(), config.bazel_query.clone())),
+            bazel_commands: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// 是否在初始化时运行 `bazel aquery`。
+    pub fn uses_bazel(&self) -> bool {
+        self.bazel.is_some()
+    }
+
+    /// 在 Bazel 工作区中运行 `bazel aquery`，记录每个源文件的编译命令。会阻塞直到 bazel 结束。
+    ///
+    /// # 错误
+    ///
+    /// 不是 Bazel 工作区、bazel 无法运行或者失败时返回错误
+    pub fn load_bazel(&self, root: &Path) -> Result<()> {
+        let Some((bazel, query)) = &self.bazel else {
+            return Ok(());
+        };
+        if !["WORKSPACE", "WORKSPACE.bazel", "MODULE.bazel"]
+            .iter()
+            .any(|file| root.join(file).is_file())
+        {
+            bail!("{} 不是 Bazel 工作区", root.display());
+        }
+        let run = |args: &[&str]| -> Result<String> {
+            let output = Command::new(bazel)
+                .args(args)
+                .current_dir(root)
+                .output()
+                .with_context(|| format!("无法运行 {}", bazel))?;
+            if !output.status.success() {
+                bail!(
+                    "{} {} 失败: {}",
+                    bazel,
+                    args[0],
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        };
+        let execution_root = PathBuf::from(run(&["info", "execution_root"])?.trim());
+        let output = run(&["aquery", "--output=jsonproto", query])?;
+        let commands = parse_aquery(&output, root, &execution_root)?;
+        info!("bazel aquery 给出 {} 个文件的编译参数", commands.len());
+        self.bazel_commands.write().unwrap().extend(commands);
+        Ok(())
+    }
+
+    /// 文件的编译命令：`bazel aquery` 给出的优先，其次是 `compile_flags.txt` 模板。
+    pub fn command_for(&self, file: &Path) -> Option<FileCommand> {
+        if let Some(command) = self.bazel_commands.read().unwrap().get(file) {
+            return Some(command.clone());
+        }
+        let command = from_template(file, &self.compiler);
+        if command.is_some() {
+            debug!("由 {} 模板生成 {} 的编译参数", FLAGS_FILE, file.display());
+        }
+        command
+    }
+}
@@ -0,0 +1,287 @@
+//! # 补全来源模块
+//!
+//! 除了后端之外，代理内的功能（代码片段、文档中的单词等）也可以提供补全，每个来源实现 [`CompletionProvider`]。
+//! 前端的补全请求到达时所有来源同时开始计算，每个来源最多等待 `[completion] budgets` 中的时间（默认 100 毫秒），
+//! 后端响应后把按时完成的来源的补全项追加在后端的结果之后：与前面的补全项标签相同的去掉，
+//! `labelDetails.description` 标上来源的名称。有来源超时时结果标为 `isIncomplete`，编辑器继续输入时会重新请求。
+//!
+//! 后端也可以设置等待时间（来源名称为 `clangd`）：后端超时时代理先以其他来源的补全应答，
+//! 取消后端的请求，后端迟到的响应丢弃。
+
+use anyhow::Result;
+use futures::future::{BoxFuture, join_all};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tower_lsp::lsp_types::CompletionParams;
+use tower_lsp::lsp_types::request::{Completion, Request};
+
+use crate::config::CompletionConfig;
+use crate::dispatcher::Dispatcher;
+use crate::document_store::Document;
+use crate::fallback::{Arrival, FallbackRequests};
+use crate::handlers::{FALLBACK_PRIORITY, HandlerCtx, Verdict};
+use crate::trace::Direction;
+
+/// 后端提供的补全的来源名称。
+pub const BACKEND_SOURCE: &str = "clangd";
+
+/// 补全项 `data` 中记录来源的字段，带有这个字段的补全项由代理解析。
+pub const SOURCE_FIELD: &str = "codefuseSource";
+
+/// 代理内的来源没有配置等待时间时的默认值。
+const DEFAULT_BUDGET: Duration = Duration::from_millis(100);
+
+/// 交给补全来源的请求。
+///
+/// - `params`: 前端的补全参数
+/// - `document`: 请求针对的文档，没有打开时为 `None`
+/// - `backend_ready`: 后端正在运行并且已经完成后台索引
+#[derive(Debug, Clone)]
+pub struct CompletionRequest {
+    pub params: CompletionParams,
+    pub document: Option<Document>,
+    pub backend_ready: bool,
+}
+
+/// 代理内的补全来源。
+pub trait CompletionProvider: Send + Sync {
+    /// 来源的名称，用于配置等待时间和标注补全项。
+    fn name(&self) -> &str;
+
+    /// 计算补全项（LSP 的 `CompletionItem`）。超过等待时间的结果被丢弃，来源不需要自己处理超时。
+    fn complete(&self, request: &CompletionRequest) -> BoxFuture<'static, Vec<Value>>;
+}
+
+/// 一个来源的结果，超过等待时间时 `found` 为 `None`。
+struct Gathered {
+    source: String,
+    found: Option<Vec<Value>>,
+}
+
+/// 合并各来源的补全。没有注册来源时补全原样转发。
+pub struct CompletionSources {
+    providers: Vec<Arc<dyn CompletionProvider>>,
+    budgets: HashMap<String, u64>,
+    annotate: bool,
+    /// 等待后端响应的补全请求和代理内来源正在进行的计算
+    pending: FallbackRequests<JoinHandle<Vec<Gathered>>>,
+}
+
+impl Default for CompletionSources {
+    fn default() -> Self {
+        Self::new(&CompletionConfig::default())
+    }
+}
+
+impl CompletionSources {
+    /// 按 `[completion]` 配置创建。
+    pub fn new(config: &CompletionConfig) -> Self {
+        Self {
+            providers: Vec::new(),
+            budgets: config.budgets.clone(),
+            annotate: config.annotate,
+            pending: FallbackRequests::default(),
+        }
+    }
+
+    /// 换成新的配置，已经注册的来源保留。
+    pub fn set_config(&mut self, config: &CompletionConfig) {
+        self.budgets = config.budgets.clone();
+        self.annotate = config.annotate;
+    }
+
+    /// 注册一个来源，追加的补全项按注册顺序排列。
+    pub fn register(&mut self, provider: Arc<dyn CompletionProvider>) {
+        self.providers.push(provider);
+    }
+
+    /// 是否有代理内的来源。
+    pub fn is_enabled(&self) -> bool {
+        !self.providers.is_empty()
+    }
+
+    /// 来源的等待时间。
+    pub fn budget(&self, source: &str) -> Duration {
+        self.budgets
+            .get(source)
+            .map_or(DEFAULT_BUDGET, |ms| Duration::from_millis(*ms))
+    }
+
+    /// 后端的等待时间，没有配置时一直等待后端。
+    pub fn backend_budget(&self) -> Option<Duration> {
+        self.budgets
+            .get(BACKEND_SOURCE)
+            .map(|ms| Duration::from_millis(*ms))
+    }
+
+    /// 前端的补全请求到达，所有来源在后台开始计算。参数无法解析时不计算，后端的响应原样转发。
+    ///
+    /// # 参数
+    ///
+    /// * `id` - 请求的 id
+    /// * `params` - 请求的参数
+    /// * `document` - 请求针对的文档
+    /// * `backend_ready` - 后端正在运行并且已经完成后台索引
+    pub fn request(
+        &self,
+        id: &Value,
+        params: &Value,
+        document: Option<Document>,
+        backend_ready: bool,
+    ) {
+        if !self.is_enabled() {
+            return;
+        }
+        let Ok(params) = serde_json::from_value(params.clone()) else {
+            return;
+        };
+        let request = CompletionRequest {
+            params,
+            document,
+            backend_ready,
+        };
+        let computing: Vec<_> = self
+            .providers
+            .iter()
+            .map(|provider| {
+                let source = provider.name().to_string();
+                let budget = self.budget(&source);
+                let items = provider.complete(&request);
+                async move {
+                    let found = tokio::time::timeout(budget, items).await.ok();
+                    Gathered { source, found }
+                }
+            })
+            .collect();
+        let handle = tokio::spawn(join_all(computing));
+        self.pending.insert(id, handle);
+    }
+
+    /// 后端的补全响应到达，追加其他来源的补全项。后端返回错误时原样转发。
+    ///
+    /// # 返回
+    ///
+    /// 后端超过等待时间、代理已经应答时返回 `false`，响应应当丢弃
+    pub async fn merge(&self, rpc: &mut Value) -> bool {
+        let Some(id) = rpc.get("id") else {
+            return true;
+        };
+        let handle = match self.pending.arrive(id) {
+            Arrival::Waiting(handle) => handle,
+            Arrival::Late => return false,
+            Arrival::Untracked => return true,
+        };
+        let gathered = handle.await.unwrap_or_default();
+        if let Some(result) = rpc.get_mut("result") {
+            *result = self.combine(result.take(), gathered);
+        }
+        true
+    }
+
+    /// 后端超过了等待时间，改由代理以其他来源的补全应答。
+    ///
+    /// # 返回
+    ///
+    /// 应答前端的补全结果；后端已经响应时返回 `None`
+    pub async fn expire(&self, id: &Value, shard: usize) -> Option<Value> {
+        let handle = self.pending.expire(id, shard)?;
+        let gathered = handle.await.unwrap_or_default();
+        let mut result = self.combine(Value::Null, gathered);
+        result["isIncomplete"] = json!(true);
+        Some(result)
+    }
+
+    /// `shard` 分片的后端退出，不再等待它的响应。
+    pub fn backend_exited(&self, shard: usize) {
+        self.pending.backend_exited(shard);
+    }
+
+    /// 把各来源的补全项追加在后端的结果之后，结果总是 `CompletionList`。
+    fn combine(&self, backend: Value, gathered: Vec<Gathered>) -> Value {
+        let (mut incomplete, mut items) = match backend {
+            Value::Array(items) => (false, items),
+            Value::Object(mut list) => (
+                list.get("isIncomplete")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false),
+                match list.remove("items") {
+                    Some(Value::Array(items)) => items,
+                    _ => Vec::new(),
+                },
+            ),
+            _ => (false, Vec::new()),
+        };
+        let mut labels: HashSet<String> = items.iter().filter_map(label).collect();
+        for Gathered { source, found } in gathered {
+            let Some(found) = found else {
+                incomplete = true;
+                continue;
+            };
+            for mut item in found {
+                let Some(text) = label(&item) else {
+                    continue;
+                };
+                if !labels.insert(text) {
+                    continue;
+                }
+                self.tag(&mut item, &source);
+                items.push(item);
+            }
+        }
+        json!({"isIncomplete": incomplete, "items": items})
+    }
+
+    /// 在补全项的 `data` 中记录来源，开启 `annotate` 时在 `labelDetails.description` 中标上来源。
+    fn tag(&self, item: &mut Value, source: &str) {
+        let Some(fields) = item.as_object_mut() else {
+            return;
+        };
+        let data = fields.remove("data").unwrap_or(Value::Null);
+        fields.insert(
+            "data".to_string(),
+            json!({SOURCE_FIELD: source, "data": data}),
+        );
+        if self.annotate {
+            let details = fields.entry("labelDetails").or_insert_with(|| json!({}));
+            if let Some(details) = details.as_object_mut() {
+                details
+                    .entry("description")
+                    .or_insert_with(|| json!(source));
+            }
+        }
+    }
+}
+
+/// 补全项的标签。
+fn label(item: &Value) -> Option<String> {
+    item.get("label")
+        .and_then(|label| label.as_str())
+        .map(str::to_string)
+}
+
+/// 补全项来自哪个代理内的来源，后端的补全项返回 `None`。
+pub fn source_of(item: &Value) -> Option<&str> {
+    item.pointer(&format!("/data/{}", SOURCE_FIELD))
+        .and_then(|source| source.as_str())
+}
+
+/// 注册补全来源的处理器，补全请求转发给后端时同时交给代理内的来源。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        Completion::METHOD,
+        FALLBACK_PRIORITY,
+        start_sources,
+    );
+}
+
+/// 开始收集代理内来源的补全，后端超过等待时间时先以它们的结果应答。
+fn start_sources(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        ctx.dispatcher().start_completion_sources(&rpc);
+        Ok(Verdict::Continue(rpc))
+    })
+}
@@ -0,0 +1,143 @@
+//! # 帧压缩模块
+//!
+//! 两端都是代理时（例如本地的 `relay` 与远程机器上的代理），在套接字传输上压缩消息体。
+//! 每一端在发出的第一条消息的消息头中以 `Accept-Encoding` 列出支持的算法，收到对端的列表后选出
+//! 自己最优先、对端也支持的算法，之后超过长度下限的消息体压缩后发送，并以 `Content-Encoding` 标明。
+//! 编辑器不会发送 `Accept-Encoding`，因此发给编辑器的消息不会被压缩。
+
+use anyhow::{Result, bail};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use crate::codec::MAX_MESSAGE_SIZE;
+use crate::config::{FrameEncoding, TransportConfig};
+use crate::metrics::Metrics;
+
+/// 一个连接上的压缩协商和压缩统计，读写两个方向共用。
+#[derive(Debug)]
+pub struct FrameCompression {
+    /// 本端支持的算法，按优先顺序
+    supported: Vec<FrameEncoding>,
+    min_bytes: usize,
+    /// 对端也支持、本端发送时使用的算法
+    chosen: OnceLock<FrameEncoding>,
+    /// 已经在消息头中列出本端支持的算法
+    advertised: AtomicBool,
+    metrics: Arc<Metrics>,
+}
+
+impl FrameCompression {
+    /// 按 `[transport]` 配置创建，没有配置压缩算法时返回 `None`。
+    pub fn new(config: &TransportConfig, metrics: Arc<Metrics>) -> Option<Arc<Self>> {
+        if config.compression.is_empty() {
+            return None;
+        }
+        Some(Arc::new(Self {
+            supported: config.compression.clone(),
+            min_bytes: config.compression_min_bytes,
+            chosen: OnceLock::new(),
+            advertised: AtomicBool::new(false),
+            metrics,
+        }))
+    }
+
+    /// 发送时使用的算法，对端还没有列出支持的算法时为 `None`。
+    pub fn encoding(&self) -> Option<FrameEncoding> {
+        self.chosen.get().copied()
+    }
+
+    /// 收到对端的 `Accept-Encoding`，选出发送时使用的算法。只有第一次协商有效。
+    pub fn peer_accepts(&self, value: &str) {
+        let accepted: Vec<_> = value
+            .split(',')
+            .filter_map(FrameEncoding::from_name)
+            .collect();
+        if let Some(encoding) = self.supported.iter().find(|e| accepted.contains(e)) {
+            let _ = self.chosen.set(*encoding);
+        }
+    }
+
+    /// 把消息格式化为带消息头的帧，协商出算法后压缩足够长的消息体。
+    ///
+    /// # 错误
+    ///
+    /// 如果消息无法序列化或压缩失败，返回错误
+    pub fn encode_frame(&self, message: &Value) -> Result<Vec<u8>> {
+        let body = serde_json::to_vec(message)?;
+        let mut headers = String::new();
+        if !self.advertised.swap(true, Ordering::Relaxed) {
+            let names: Vec<_> = self.supported.iter().map(|e| e.name()).collect();
+            headers.push_str(&format!("Accept-Encoding: {}\r\n", names.join(", ")));
+        }
+
+        let mut payload = body;
+        if let Some(encoding) = self.encoding()
+            && payload.len() >= self.min_bytes
+        {
+            let compressed = compress(encoding, &payload)?;
+            // 压缩后没有变小的消息体原样发送
+            if compressed.len() < payload.len() {
+                self.metrics
+                    .frame_compressed(payload.len(), compressed.len());
+                headers.push_str(&format!("Content-Encoding: {}\r\n", encoding.name()));
+                payload = compressed;
+            }
+        }
+
+        let mut frame =
+            format!("Content-Length: {}\r\n{}\r\n", payload.len(), headers).into_bytes();
+        frame.extend_from_slice(&payload);
+        Ok(frame)
+    }
+
+    /// 解压收到的消息体。
+    ///
+    /// # 错误
+    ///
+    /// 如果消息体无法解压，或者解压后超过 [`MAX_MESSAGE_SIZE`]，返回错误
+    pub fn decode_body(&self, encoding: FrameEncoding, body: &[u8]) -> Result<Vec<u8>> {
+        let decompressed = decompress(encoding, body)?;
+        self.metrics
+            .frame_decompressed(body.len(), decompressed.len());
+        Ok(decompressed)
+    }
+}
+
+/// 压缩消息体。
+pub fn compress(encoding: FrameEncoding, data: &[u8]) -> Result<Vec<u8>> {
+    Ok(match encoding {
+        FrameEncoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        FrameEncoding::Zstd => zstd::encode_all(data, 3)?,
+    })
+}
+
+/// 解压消息体，解压后的长度不超过 [`MAX_MESSAGE_SIZE`]。
+///
+/// # 错误
+///
+/// 如果数据无法解压或解压后过大，返回错误
+pub fn decompress(encoding: FrameEncoding, data: &[u8]) -> Result<Vec<u8>> {
+    let limit = MAX_MESSAGE_SIZE as u64 + 1;
+    let mut decompressed = Vec::new();
+    match encoding {
+        FrameEncoding::Gzip => GzDecoder::new(data)
+            .take(limit)
+            .read_to_end(&mut decompressed)?,
+        FrameEncoding::Zstd => zstd::Decoder::new(data)?
+            .take(limit)
+            .read_to_end(&mut decompressed)?,
+    };
+    if decompressed.len() > MAX_MESSAGE_SIZE {
+        bail!("解压后的消息过大: 超过 {} 字节", MAX_MESSAGE_SIZE);
+    }
+    Ok(decompressed)
+}
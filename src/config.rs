@@ -0,0 +1,1367 @@
+//! # 配置模块
+//!
+//! 代理的配置来自 TOML 文件：命令行 `--config` 指定的文件，或当前目录下的 `.codefuse.toml`。
+//! 所有字段都有默认值，没有配置文件时代理的行为与之前完全一致。
+//! 路径和命令中的 `${env:VAR}`、`${workspaceFolder}` 和开头的 `~` 在加载时展开（见 [`crate::variables`]）。
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::clangd_flags;
+use crate::idle;
+use crate::platform;
+use crate::profiles;
+use crate::sandbox;
+use crate::shadow;
+use crate::telemetry;
+use crate::variables::Variables;
+
+/// 默认的配置文件名。
+pub const CONFIG_FILE_NAME: &str = ".codefuse.toml";
+
+/// 代理配置。
+///
+/// - `backend`: 主后端的启动方式
+/// - `shards`: 按子目录拆分的额外后端实例
+/// - `warmup`: 会话开始时的 preamble 预热
+/// - `prefetch`: 悬停和跳转定义的预取
+/// - `cache`: clangd 索引缓存的位置
+/// - `tidy`: 按目录配置的 clang-tidy 检查集合
+/// - `includes`: 补全和代码操作插入头文件的策略
+/// - `concurrency`: 消息处理任务的并发上限
+/// - `protocol`: 对后端不符合协议的消息的处理，以及后端不支持的方法
+/// - `health`: 健康检查端点
+/// - `telemetry`: 请求跨度的 OTLP 导出和代理事件的通知
+/// - `logging`: 日志级别和日志中的附加信息
+/// - `transport`: 套接字传输上的帧压缩和认证
+/// - `remote`: 远程模式下本地 `relay` 的路径映射和文件同步
+/// - `session`: 编辑器断开后保留会话
+/// - `diagnostics`: 多个来源的诊断的开关
+/// - `spellcheck`: 注释和字符串字面量的拼写检查
+/// - `todos`: 注释中的 TODO/FIXME 标记
+/// - `colors`: 后端不提供颜色时在源代码中查找颜色字面量
+/// - `notebooks`: 笔记本文档的同步
+/// - `embedded`: 宿主文件中嵌入的 C/C++ 代码
+/// - `languages`: 语言 id 和扩展名的映射，以及按语言选择分片
+/// - `modules`: C++20 模块的支持
+/// - `cmake`: 用 CMake 生成 `compile_commands.json`
+/// - `flags`: 没有 `compile_commands.json` 的工作区的编译参数
+/// - `rate_limits`: 按方法的请求速率限制，键是方法
+/// - `history`: `codefuse/history` 保留的最近请求
+/// - `messages`: 后端重复发送的 `window/showMessage` 和 `window/logMessage` 的去重
+/// - `admin`: 运维人员调试运行中会话的管理控制台
+/// - `liveness`: 前端的存活检测，编辑器消失后结束后端并退出
+/// - `crash_reports`: 代理 panic 或者后端异常退出时生成的崩溃报告
+/// - `index_progress`: 合并各分片 clangd 的后台索引进度
+/// - `file_status`: clangd 的文件状态（`textDocument/clangd.fileStatus`）的汇总和进度
+/// - `shadow`: 把部分只读请求同时发给影子后端，比较两个后端的响应
+/// - `completion`: 合并后端和代理内各来源的补全
+/// - `snippets`: 项目中共享的代码片段，作为补全来源
+/// - `signature_help`: 后端没有应答签名帮助时由代理合成签名
+/// - `document_highlight`: 后端的文档高亮超时时按文本匹配高亮
+/// - `state`: 在工作区中保存会话状态，下一次启动时恢复
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub backend: BackendConfig,
+    pub shards: Vec<ShardConfig>,
+    pub warmup: WarmupConfig,
+    pub prefetch: PrefetchConfig,
+    pub cache: CacheConfig,
+    pub tidy: Vec<TidyRule>,
+    pub includes: IncludeConfig,
+    pub concurrency: ConcurrencyConfig,
+    pub protocol: ProtocolConfig,
+    pub health: HealthConfig,
+    pub telemetry: TelemetryConfig,
+    pub logging: LoggingConfig,
+    pub transport: TransportConfig,
+    pub remote: RemoteConfig,
+    pub session: SessionConfig,
+    pub diagnostics: DiagnosticsConfig,
+    pub spellcheck: SpellcheckConfig,
+    pub todos: TodoConfig,
+    pub colors: ColorConfig,
+    pub notebooks: NotebookConfig,
+    pub embedded: Vec<EmbeddedRule>,
+    pub languages: LanguageConfig,
+    pub modules: ModuleConfig,
+    pub cmake: CmakeConfig,
+    pub flags: FlagsConfig,
+    pub rate_limits: HashMap<String, RateLimitRule>,
+    pub messages: MessagesConfig,
+    pub history: HistoryConfig,
+    pub admin: AdminConfig,
+    pub liveness: LivenessConfig,
+    pub crash_reports: CrashReportConfig,
+    pub index_progress: IndexProgressConfig,
+    pub file_status: FileStatusConfig,
+    pub shadow: ShadowConfig,
+    pub completion: CompletionConfig,
+    pub snippets: SnippetsConfig,
+    pub signature_help: SignatureHelpConfig,
+    pub document_highlight: DocumentHighlightConfig,
+    pub state: StateConfig,
+}
+
+/// 后端进程的启动方式。
+///
+/// - `command`/`args`: 启动命令和参数；`command` 是 `ssh://user@host//usr/bin/clangd` 时通过 ssh 在远程机器上启动
+/// - `standby`: 是否维护一个预先初始化的备用后端，主后端需要重启时无缝切换
+/// - `max_memory_mb`: 主后端常驻内存超过这个值时切换到备用后端并回收主后端（需要启用 `standby`）
+/// - `limits`: 后端进程的资源限制（`[backend.limits]`）
+/// - `ssh`: 通过 ssh 启动后端时的连接选项（`[backend.ssh]`）
+/// - `container`: 在容器中运行后端（`[backend.container]`），此时 `command` 是容器中的程序
+/// - `sandbox`: 在 bwrap 或 firejail 沙箱中运行后端（`[backend.sandbox]`），只能访问工作区和缓存目录
+/// - `idle`: 长时间没有消息时让后端休眠（`[backend.idle]`）
+/// - `log_format`: 后端标准错误输出的日志格式，默认按 `command` 的程序名判断
+/// - `clangd`: 常用的 clangd 启动参数（`[backend.clangd]`），追加在 `args` 之后
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BackendConfig {
+    pub command: String,
+    pub args: Vec<String>,
+    pub standby: bool,
+    pub max_memory_mb: Option<u64>,
+    pub limits: ResourceLimits,
+    pub ssh: SshConfig,
+    pub container: Option<ContainerConfig>,
+    pub sandbox: Option<SandboxConfig>,
+    pub idle: IdleConfig,
+    pub log_format: LogFormat,
+    pub clangd: ClangdFlagsConfig,
+}
+
+/// 在容器中运行后端，主机上不需要安装工具链。
+///
+/// - `image`: 容器镜像
+/// - `engine`: 容器引擎的命令，`docker` 或 `podman`
+/// - `workspace`: 挂载进容器的工作区目录，默认是代理的当前目录
+/// - `mount`: 工作区在容器中的路径，默认与主机上相同；不同时代理在消息中替换两边的路径
+/// - `args`: 传给 `run` 的额外参数，例如 `["--network=none"]`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ContainerConfig {
+    pub image: String,
+    pub engine: String,
+    pub workspace: Option<PathBuf>,
+    pub mount: Option<PathBuf>,
+    pub args: Vec<String>,
+}
+
+impl Default for ContainerConfig {
+    fn default() -> Self {
+        Self {
+            image: String::new(),
+            engine: "docker".to_string(),
+            workspace: None,
+            mount: None,
+            args: Vec::new(),
+        }
+    }
+}
+
+/// 在沙箱中运行后端（只支持 Linux），供审阅不可信代码时使用。后端只能读写工作区和 clangd 的缓存目录，
+/// 系统目录只读，默认不能访问网络。
+///
+/// - `tool`: 沙箱程序，`bwrap`（bubblewrap）或 `firejail`
+/// - `workspace`: 后端可以读写的工作区，必须设置，不能是家目录或它的上级目录
+/// - `writable`: 额外允许读写的目录，例如工作区之外的构建目录
+/// - `read_only`: 额外允许读取的目录，例如工作区之外的第三方库
+/// - `network`: 是否允许访问网络（例如使用远程索引），默认不允许
+/// - `args`: 传给沙箱程序的额外选项，每一项都必须以 `-` 开头
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    pub tool: SandboxTool,
+    pub workspace: Option<PathBuf>,
+    pub writable: Vec<PathBuf>,
+    pub read_only: Vec<PathBuf>,
+    pub network: bool,
+    pub args: Vec<String>,
+}
+
+/// 运行后端的沙箱程序。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxTool {
+    /// bubblewrap：只挂载允许的目录，其他文件在沙箱中不存在
+    #[default]
+    Bwrap,
+    /// firejail：家目录中只保留允许的目录
+    Firejail,
+}
+
+impl SandboxTool {
+    /// 沙箱程序的命令。
+    pub fn program(self) -> &'static str {
+        match self {
+            SandboxTool::Bwrap => "bwrap",
+            SandboxTool::Firejail => "firejail",
+        }
+    }
+}
+
+/// 通过 ssh 启动后端时的连接选项。
+///
+/// - `control_path`: ControlMaster 的套接字路径，已有的主连接会被复用，没有时建立一个并保持 10 分钟
+/// - `args`: 传给 ssh 的额外参数，例如 `["-p", "2222"]`
+/// - `reconnect_attempts`: 连接断开后重新连接的次数，每次失败后等待的时间加倍，最多 30 秒
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SshConfig {
+    pub control_path: Option<String>,
+    pub args: Vec<String>,
+    pub reconnect_attempts: u32,
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        Self {
+            control_path: None,
+            args: Vec::new(),
+            reconnect_attempts: 5,
+        }
+    }
+}
+
+/// 编辑器在后台时让空闲的后端休眠，不再占用 CPU。
+///
+/// - `after_mins`: 分片连续这么多分钟（1 到 10080）没有收到消息时休眠；不设置时不休眠
+/// - `action`: 休眠的方式，下一条发给该分片的消息到达时唤醒
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct IdleConfig {
+    pub after_mins: Option<u64>,
+    pub action: IdleAction,
+}
+
+/// 空闲后端的休眠方式。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleAction {
+    /// 暂停进程（SIGSTOP），唤醒时继续（SIGCONT）。只支持 Unix 上直接启动的后端，其他后端按 `shutdown` 处理
+    #[default]
+    Suspend,
+    /// 结束进程，唤醒时重新启动并重放打开的文档
+    Shutdown,
+}
+
+/// 后端进程的资源限制，防止建立大型索引时失控的 clangd 拖垮整台机器。
+///
+/// - `nice`: 进程优先级（Unix 的 nice 值，-20 到 19）；Windows 上大于 0 时使用低于正常的优先级，不小于 10 时使用空闲优先级
+/// - `cpus`: 允许使用的 CPU 编号（Linux 和 Windows），小于 1024
+/// - `memory_mb`: 内存上限（MB），超过时分配失败。Unix 上通过 `RLIMIT_DATA` 限制，Windows 上是作业对象的进程内存上限
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    pub nice: Option<i32>,
+    pub cpus: Vec<usize>,
+    pub memory_mb: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// 是否没有任何限制。
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        Self {
+            command: "clangd".to_string(),
+            args: Vec::new(),
+            standby: false,
+            max_memory_mb: None,
+            limits: ResourceLimits::default(),
+            ssh: SshConfig::default(),
+            container: None,
+            sandbox: None,
+            idle: IdleConfig::default(),
+            log_format: LogFormat::Auto,
+            clangd: ClangdFlagsConfig::default(),
+        }
+    }
+}
+
+/// 常用的 clangd 启动参数，不需要记住原始的命令行参数。代理按检测到的 clangd 版本省略它不支持的参数。
+///
+/// - `background_index`: 是否在后台为整个项目建立索引（`--background-index`）
+/// - `jobs`: 后台索引和构建 AST 使用的线程数（`-j`），必须大于 0
+/// - `pch_storage`: preamble 保存在内存还是磁盘（`--pch-storage`）
+/// - `query_driver`: 允许 clangd 运行以获取系统头文件路径的编译器，glob 形式（`--query-driver`）
+/// - `header_insertion`: 补全时是否自动插入头文件（`--header-insertion`）
+/// - `auto_query_driver`: 是否把 `compile_commands.json` 中的编译器加入 `--query-driver`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ClangdFlagsConfig {
+    pub background_index: Option<bool>,
+    pub jobs: Option<u32>,
+    pub pch_storage: Option<PchStorage>,
+    pub query_driver: Vec<String>,
+    pub header_insertion: Option<HeaderInsertion>,
+    pub auto_query_driver: QueryDriverMode,
+}
+
+/// 如何把编译数据库中的编译器加入 `--query-driver`。`--query-driver` 允许 clangd 运行这些程序，
+/// 不可信的工作区可能借此运行任意程序。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryDriverMode {
+    /// 不检测
+    #[default]
+    Off,
+    /// 发现新的编译器时询问用户
+    Ask,
+    /// 直接允许，只用于可信的工作区
+    On,
+}
+
+/// preamble 的保存位置。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PchStorage {
+    /// 保存在临时文件中，占用内存少
+    Disk,
+    /// 保存在内存中，更快但占用更多内存
+    Memory,
+}
+
+/// 补全时插入头文件的方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderInsertion {
+    /// 按 include-what-you-use 的方式插入缺少的头文件
+    Iwyu,
+    /// 从不插入
+    Never,
+}
+
+/// 后端标准错误输出的日志格式，决定如何从每一行中识别日志级别。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// 按 `command` 的程序名判断，无法判断时按 clangd 处理
+    #[default]
+    Auto,
+    /// clangd：`I[11:01:38.638] clangd version 21.1.0`
+    Clangd,
+    /// rust-analyzer：`[ERROR rust_analyzer::main_loop] ...` 或 `ERROR rust_analyzer::reload: ...`
+    RustAnalyzer,
+    /// pyright：`[Error - 10:00:00 AM] ...`，以及 Node.js 输出的 `Error: ...`
+    Pyright,
+    /// 不识别级别，每一行都按 debug 记录
+    Plain,
+}
+
+/// preamble 预热。
+///
+/// - `enabled`: 是否在会话开始时为最近编辑的文件发送合成的 `didOpen`
+/// - `max_files`: 记录和预热的最近文件数量
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WarmupConfig {
+    pub enabled: bool,
+    pub max_files: usize,
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_files: 8,
+        }
+    }
+}
+
+/// 悬停和跳转定义的预取。
+///
+/// - `enabled`: 是否为光标附近的标识符预取悬停和定义
+/// - `max_identifiers`: 每次最多预取的标识符数量
+/// - `max_concurrent`: 同时进行的预取请求数量
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PrefetchConfig {
+    pub enabled: bool,
+    pub max_identifiers: usize,
+    pub max_concurrent: usize,
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_identifiers: 4,
+            max_concurrent: 2,
+        }
+    }
+}
+
+/// 消息处理任务的并发。
+///
+/// - `max_handlers`: 同时运行的请求和通知处理任务数量，达到上限时暂停读取新消息；响应不受限制
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ConcurrencyConfig {
+    pub max_handlers: usize,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self { max_handlers: 15 }
+    }
+}
+
+/// 对后端不符合协议的消息的处理。
+///
+/// - `drop_unexpected_responses`: 丢弃重复或 id 未知的响应，而不是转发给前端；无论是否丢弃都会记录日志和指标
+/// - `stub_methods`: 后端不支持的方法，请求由代理以空结果应答，通知直接丢弃，不转发给后端
+/// - `content_modified`: 响应到达时文档已经被修改的话，以 `ContentModified` 错误代替响应的方法
+/// - `max_message_mb`: 发给前端的响应和通知的大小上限（MB）；不设置时不限制
+/// - `oversize_reply`: 超过上限的响应的替代，默认是错误响应；超过上限的通知总是被丢弃
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ProtocolConfig {
+    pub drop_unexpected_responses: bool,
+    pub stub_methods: Vec<String>,
+    pub content_modified: Vec<String>,
+    pub max_message_mb: Option<u64>,
+    pub oversize_reply: OversizeReply,
+}
+
+/// 超过大小上限的响应的替代。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizeReply {
+    /// `InternalError` 错误响应
+    #[default]
+    Error,
+    /// 方法的空结果
+    Empty,
+}
+
+/// 一个方法的请求速率限制（令牌桶），超过限制的请求以上一次相同请求的结果或空结果应答，不转发给后端。
+///
+/// - `per_second`: 每秒补充的令牌数，也就是长期的请求速率上限
+/// - `burst`: 令牌桶的容量，允许短时间内连续发出的请求数量，默认是 `per_second` 向上取整（至少 1）
+/// - `per_document`: 每个文档单独计算，默认开启；关闭时这个方法的所有请求共用一个令牌桶
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitRule {
+    pub per_second: f64,
+    pub burst: Option<u32>,
+    pub per_document: bool,
+}
+
+impl Default for RateLimitRule {
+    fn default() -> Self {
+        Self {
+            per_second: 1.0,
+            burst: None,
+            per_document: true,
+        }
+    }
+}
+
+impl RateLimitRule {
+    /// 令牌桶的容量。
+    pub fn capacity(&self) -> f64 {
+        match self.burst {
+            Some(burst) => f64::from(burst.max(1)),
+            None => self.per_second.ceil().max(1.0),
+        }
+    }
+}
+
+/// 后端发来的 `window/showMessage` 和 `window/logMessage` 的去重。
+///
+/// - `window_secs`: 时间窗口的长度（秒）；不设置时不去重
+/// - `max_repeats`: 类型和文字都相同的消息在一个窗口内最多转发的次数，默认 1
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MessagesConfig {
+    pub window_secs: Option<u64>,
+    pub max_repeats: usize,
+}
+
+impl Default for MessagesConfig {
+    fn default() -> Self {
+        Self {
+            window_secs: None,
+            max_repeats: 1,
+        }
+    }
+}
+
+/// 最近请求的历史，通过 `codefuse/history` 查询。
+///
+/// - `capacity`: 保留的最近请求数量，为 0 时不记录
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub capacity: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self { capacity: 256 }
+    }
+}
+
+/// 管理控制台。
+///
+/// - `socket`: 在这个 unix 套接字上提供管理控制台，`codefuse admin attach` 连接；不设置时不监听
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    pub socket: Option<PathBuf>,
+}
+
+/// 前端的存活检测。
+///
+/// - `interval_secs`: 每隔多少秒检查一次前端是否还在，为 0 时不检查。使用标准输入输出时检查标准输出的管道是否已经关闭，
+///   其他传输向前端发送 `$/codefuse/ping` 请求
+/// - `timeout_secs`: 等待 ping 响应的最长时间，超时认为编辑器已经不在
+/// - `parent_process`: 监视编辑器在 `initialize` 中给出的 `processId`，这个进程结束时退出，默认开启；
+///   `--listen` 时编辑器在另一台机器上，不监视
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LivenessConfig {
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+    pub parent_process: bool,
+}
+
+impl Default for LivenessConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 30,
+            timeout_secs: 30,
+            parent_process: true,
+        }
+    }
+}
+
+/// 崩溃报告。
+///
+/// - `enabled`: 代理 panic 或者后端异常退出时生成崩溃报告，默认开启
+/// - `dir`: 保存崩溃报告的目录，默认是系统临时目录下的 `codefuse-crash`
+/// - `redact`: 隐去报告中消息的文档内容和编辑文本，默认开启
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CrashReportConfig {
+    pub enabled: bool,
+    pub dir: Option<PathBuf>,
+    pub redact: bool,
+}
+
+impl Default for CrashReportConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: None,
+            redact: true,
+        }
+    }
+}
+
+/// 后台索引进度。
+///
+/// - `enabled`: 代理接管 clangd 的后台索引进度，把各分片的进度合并成一个带百分比和文件数的进度，默认关闭
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct IndexProgressConfig {
+    pub enabled: bool,
+}
+
+/// clangd 的文件状态。
+///
+/// - `enabled`: 让 clangd 报告每个文件的状态（解析头文件、构建 preamble、构建 AST 等），由 `codefuse/status` 汇总，默认关闭
+/// - `progress`: 同时把正在处理的文件显示为编辑器的进度，默认关闭
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FileStatusConfig {
+    pub enabled: bool,
+    pub progress: bool,
+}
+
+/// 影子后端。
+///
+/// - `command`/`args`: 影子后端的启动命令和参数，例如新版本的 clangd；不设置时不镜像请求
+/// - `sample_rate`: 镜像的请求占只读请求的比例，0 到 1，默认 0.1
+/// - `methods`: 镜像的方法，默认是所有可以重放的只读请求
+/// - `report`: 写入响应差异的文件（每行一个 JSON），默认是系统临时目录下的 `codefuse-shadow.ndjson`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShadowConfig {
+    pub command: Option<String>,
+    pub args: Vec<String>,
+    pub sample_rate: f64,
+    pub methods: Vec<String>,
+    pub report: Option<PathBuf>,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            command: None,
+            args: Vec::new(),
+            sample_rate: 0.1,
+            methods: Vec::new(),
+            report: None,
+        }
+    }
+}
+
+/// 多个来源的补全。
+///
+/// - `budgets`: 每个来源最多等待的毫秒数，键是来源的名称；代理内的来源默认 100 毫秒，
+///   后端（`clangd`）默认一直等待
+/// - `annotate`: 在代理内来源的补全项的 `labelDetails.description` 中标上来源，默认开启
+/// - `words`: 后端正在重启或者还没有完成索引时，以打开的文档中的标识符补全，默认关闭
+/// - `keywords`: 补全 C/C++ 关键字、标准属性和预处理指令，用于 clangd 不提供它们的环境，默认关闭
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CompletionConfig {
+    pub budgets: HashMap<String, u64>,
+    pub annotate: bool,
+    pub words: bool,
+    pub keywords: bool,
+}
+
+impl Default for CompletionConfig {
+    fn default() -> Self {
+        Self {
+            budgets: HashMap::new(),
+            annotate: true,
+            words: false,
+            keywords: false,
+        }
+    }
+}
+
+/// 项目中共享的代码片段。
+///
+/// - `files`: 代码片段文件，`.json`/`.code-snippets` 是 VSCode 的格式，`.toml` 是代理的格式
+///   （见 [`crate::snippets`]）；相对路径相对于配置文件所在的目录
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SnippetsConfig {
+    pub files: Vec<PathBuf>,
+}
+
+/// 后端没有应答签名帮助时由代理合成签名（见 [`crate::signature_help`]）。
+///
+/// - `fallback`: 开启合成，默认关闭
+/// - `timeout_ms`: 等待后端的时间（毫秒），超过后以合成的签名应答并取消后端的请求，默认 500；
+///   后端没有运行时不等待
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SignatureHelpConfig {
+    pub fallback: bool,
+    pub timeout_ms: u64,
+}
+
+impl Default for SignatureHelpConfig {
+    fn default() -> Self {
+        Self {
+            fallback: false,
+            timeout_ms: 500,
+        }
+    }
+}
+
+/// 后端的文档高亮超时时按文本匹配高亮（见 [`crate::document_highlight`]）。
+///
+/// - `fallback`: 开启文本匹配，默认关闭
+/// - `timeout_ms`: 等待后端的时间（毫秒），超过后以文本匹配的结果应答并取消后端的请求，默认 300；
+///   后端没有运行时不等待
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DocumentHighlightConfig {
+    pub fallback: bool,
+    pub timeout_ms: u64,
+}
+
+impl Default for DocumentHighlightConfig {
+    fn default() -> Self {
+        Self {
+            fallback: false,
+            timeout_ms: 300,
+        }
+    }
+}
+
+/// 会话状态的保存和恢复（见 [`crate::state`]）。
+///
+/// - `enabled`: 在第一个工作区根目录的 `.cache/codefuse/state.json` 中保存后端的能力、补全项的使用次数和索引的元数据，
+///   下一次启动时恢复，默认关闭
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StateConfig {
+    pub enabled: bool,
+}
+
+/// 健康检查端点。
+///
+/// - `port`: 在 127.0.0.1 的这个端口上提供 `GET /healthz`；不设置时不监听
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    pub port: Option<u16>,
+}
+
+/// 日志级别和日志中的附加信息。
+///
+/// - `level`: 日志级别，`error`、`warn`、`info`、`debug` 或 `trace`，默认 `info`
+/// - `slow_request_ms`: 往返时间超过这个值的前端请求写入警告日志；不设置时不记录
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub level: Option<String>,
+    pub slow_request_ms: Option<u64>,
+}
+
+impl LoggingConfig {
+    /// 配置的日志级别，无法识别的级别按 `info` 处理。
+    pub fn level_filter(&self) -> log::LevelFilter {
+        match &self.level {
+            Some(level) => level.parse().unwrap_or_else(|_| {
+                log::warn!("无法识别的日志级别 {}，使用 info", level);
+                log::LevelFilter::Info
+            }),
+            None => log::LevelFilter::Info,
+        }
+    }
+}
+
+/// 套接字传输（`--socket`、`--listen` 和 `relay`）上的帧压缩和认证，标准输入输出和管道不压缩。
+///
+/// - `compression`: 按优先顺序列出支持的压缩算法；不设置时不压缩。两端都是代理时协商出双方都支持的算法
+/// - `compression_min_bytes`: 小于这个长度的消息体不压缩
+/// - `tls_cert`/`tls_key`: PEM 格式的证书链和私钥；设置后 `--listen` 只接受 TLS 连接
+/// - `tls_ca`: `relay` 信任的 PEM 格式证书（CA 或者远程代理的自签名证书）；设置后 `relay` 通过 TLS 连接
+/// - `tls_server_name`: `relay` 校验的证书名称，默认是连接地址中的主机名
+/// - `token_file`: 保存共享令牌的文件；设置后 `--listen` 只接受先发送了这个令牌的连接，`relay` 连接后先发送令牌
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TransportConfig {
+    pub compression: Vec<FrameEncoding>,
+    pub compression_min_bytes: usize,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_ca: Option<PathBuf>,
+    pub tls_server_name: Option<String>,
+    pub token_file: Option<PathBuf>,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            compression: Vec::new(),
+            compression_min_bytes: 1024,
+            tls_cert: None,
+            tls_key: None,
+            tls_ca: None,
+            tls_server_name: None,
+            token_file: None,
+        }
+    }
+}
+
+/// 编辑器断开后保留会话，重新加载窗口的编辑器不必等待 clangd 重新建立索引。
+///
+/// - `grace_secs`: 以 `--listen` 监听时，编辑器断开后保留后端和文档状态的秒数，编辑器在此期间重新连接即可继续会话；
+///   不设置时编辑器断开后代理退出
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    pub grace_secs: Option<u64>,
+}
+
+/// 多个来源的诊断合并后发给编辑器。
+///
+/// - `sources`: 按来源开关诊断。键是提供诊断的来源（后端是 `clangd`）或者诊断自带的 `source`（例如 `clang-tidy`），
+///   值为 `false` 时不显示这个来源的诊断；没有列出的来源照常显示
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DiagnosticsConfig {
+    pub sources: HashMap<String, bool>,
+}
+
+/// 内置的拼写检查：检查注释和字符串字面量中的单词，拼错的单词显示为提示级别的诊断，快速修复替换为建议的拼写。
+///
+/// - `enabled`: 是否启用，默认关闭
+/// - `dictionary`: 单词列表文件，每行一个单词，默认是 `/usr/share/dict/words`
+/// - `words`: 额外认为拼写正确的单词，例如项目中的术语
+/// - `min_length`: 短于这个长度的单词不检查，默认 4
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpellcheckConfig {
+    pub enabled: bool,
+    pub dictionary: Option<PathBuf>,
+    pub words: Vec<String>,
+    pub min_length: usize,
+}
+
+impl Default for SpellcheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dictionary: None,
+            words: Vec::new(),
+            min_length: 4,
+        }
+    }
+}
+
+/// 注释中的 TODO/FIXME 等标记，显示为代码透镜，也可以通过 `codefuse/todos` 请求汇总。
+///
+/// - `code_lens`: 在标记所在行显示代码透镜，默认关闭
+/// - `patterns`: 标记的正则表达式，匹配到的文字是标记的种类，默认是 `TODO`、`FIXME` 和 `HACK`
+/// - `workspace`: `codefuse/todos` 除了打开的文档，还扫描工作区中的 C/C++ 文件
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TodoConfig {
+    pub code_lens: bool,
+    pub patterns: Vec<String>,
+    pub workspace: bool,
+}
+
+impl Default for TodoConfig {
+    fn default() -> Self {
+        Self {
+            code_lens: false,
+            patterns: vec![
+                r"\bTODO\b".to_string(),
+                r"\bFIXME\b".to_string(),
+                r"\bHACK\b".to_string(),
+            ],
+            workspace: false,
+        }
+    }
+}
+
+/// 颜色字面量。
+///
+/// - `fallback`: 后端不提供 `textDocument/documentColor` 时，代理在源代码中查找十六进制和 `rgb()` 颜色，
+///   让编辑器显示颜色色块，默认关闭
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ColorConfig {
+    pub fallback: bool,
+}
+
+/// 笔记本文档（例如 xeus-cling 的 C++ Jupyter 笔记本）。
+///
+/// - `enabled`: 声明 `notebookDocumentSync`，把笔记本的代码单元拼成一个虚拟文档交给后端，默认开启
+/// - `languages`: 同步的单元语言，默认是 `cpp`、`c++` 和 `c`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NotebookConfig {
+    pub enabled: bool,
+    pub languages: Vec<String>,
+}
+
+impl Default for NotebookConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            languages: vec!["cpp".to_string(), "c++".to_string(), "c".to_string()],
+        }
+    }
+}
+
+/// C++20 模块。
+///
+/// - `enabled`: 启动 clangd 时加上 `--experimental-modules-support`，识别模块接口单元
+///   （`.cppm` 等扩展名或者 `export module` 声明），默认关闭
+/// - `flags`: 模块接口单元的编译参数，打开时通过 `compilationDatabaseChanges` 交给后端，
+///   适合编译数据库中没有的接口单元；为空时不注入
+/// - `compiler`: 注入的编译命令使用的编译器
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ModuleConfig {
+    pub enabled: bool,
+    pub flags: Vec<String>,
+    pub compiler: String,
+}
+
+impl Default for ModuleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            flags: vec!["-std=c++20".to_string()],
+            compiler: "clang++".to_string(),
+        }
+    }
+}
+
+/// `codefuse setup` 和 `codefuse.generateCompileCommands` 命令运行 CMake 生成 `compile_commands.json`。
+///
+/// - `command`: cmake 程序
+/// - `build_dir`: 构建目录，相对路径相对于工作区根目录，默认是 `build`
+/// - `args`: 追加的配置参数，例如 `-G Ninja`、`-DCMAKE_BUILD_TYPE=Debug`
+/// - `link`: 在工作区根目录创建指向生成结果的 `compile_commands.json` 链接，让 clangd 找到它，默认开启
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CmakeConfig {
+    pub command: String,
+    pub build_dir: PathBuf,
+    pub args: Vec<String>,
+    pub link: bool,
+}
+
+impl Default for CmakeConfig {
+    fn default() -> Self {
+        Self {
+            command: "cmake".to_string(),
+            build_dir: PathBuf::from("build"),
+            args: Vec::new(),
+            link: true,
+        }
+    }
+}
+
+/// 工作区没有 `compile_commands.json` 时，代理为打开的文件生成编译参数，通过 `compilationDatabaseChanges` 交给后端。
+///
+/// - `compiler`: 由 `compile_flags.txt` 模板生成的编译命令使用的编译器。模板是含有 `${file}`（文件路径）、
+///   `${dir}`（文件所在目录）或 `${root}`（`compile_flags.txt` 所在目录）的 `compile_flags.txt`；
+///   没有这些变量的 `compile_flags.txt` 由 clangd 自己读取
+/// - `bazel`: 初始化时运行 `bazel aquery` 取得每个源文件的编译参数，默认关闭
+/// - `bazel_command`: bazel 程序
+/// - `bazel_query`: aquery 的查询表达式
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FlagsConfig {
+    pub compiler: String,
+    pub bazel: bool,
+    pub bazel_command: String,
+    pub bazel_query: String,
+}
+
+impl Default for FlagsConfig {
+    fn default() -> Self {
+        Self {
+            compiler: "clang++".to_string(),
+            bazel: false,
+            bazel_command: "bazel".to_string(),
+            bazel_query: r#"mnemonic("CppCompile", //...)"#.to_string(),
+        }
+    }
+}
+
+/// 远程模式：本地的 `relay` 把编辑器的消息转发给远程机器上的代理。
+///
+/// - `local_root`/`remote_root`: 工作区在本地和远程机器上的路径，转发时互相替换消息中的 URI 和路径；
+///   不设置时两边路径相同
+/// - `ssh_command`: 通过 `ssh://<host>` 连接时在远程机器上运行的代理命令
+/// - `sync_files`: 编辑器保存文件或报告文件变化时，把本地文件的内容同步到远程机器
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RemoteConfig {
+    pub local_root: Option<PathBuf>,
+    pub remote_root: Option<PathBuf>,
+    pub ssh_command: String,
+    pub sync_files: bool,
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        Self {
+            local_root: None,
+            remote_root: None,
+            ssh_command: "lsp-proxy".to_string(),
+            sync_files: true,
+        }
+    }
+}
+
+/// 帧的压缩算法，对应消息头 `Content-Encoding` 的值。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameEncoding {
+    Gzip,
+    Zstd,
+}
+
+impl FrameEncoding {
+    /// 消息头中的名称。
+    pub fn name(self) -> &'static str {
+        match self {
+            FrameEncoding::Gzip => "gzip",
+            FrameEncoding::Zstd => "zstd",
+        }
+    }
+
+    /// 按消息头中的名称查找，不区分大小写。
+    pub fn from_name(name: &str) -> Option<Self> {
+        [FrameEncoding::Gzip, FrameEncoding::Zstd]
+            .into_iter()
+            .find(|encoding| encoding.name().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+/// 请求跨度的 OTLP 导出。
+///
+/// - `endpoint`: OTLP/HTTP 收集器的地址，例如 `http://127.0.0.1:4318`，没有路径时使用 `/v1/traces`；不设置时不记录跨度
+/// - `service_name`: 导出时的 `service.name`
+/// - `export_interval_ms`: 导出的间隔，必须大于 0
+/// - `events`: 以 `telemetry/event` 通知告诉编辑器代理运行状态的变化（见 [`crate::events`]），默认关闭
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub endpoint: Option<String>,
+    pub service_name: String,
+    pub export_interval_ms: u64,
+    pub events: bool,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            service_name: "codefuse-proxy".to_string(),
+            export_interval_ms: 5000,
+            events: false,
+        }
+    }
+}
+
+/// clangd 索引缓存。
+///
+/// - `dir`: clangd 的缓存目录，启动后端时通过 `XDG_CACHE_HOME` 传给 clangd。
+///   clangd 只把不属于任何编译数据库的文件的索引放在这里；项目索引仍然写在工作区的 `.cache/clangd/index`，
+///   这个设置不会移动项目索引
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub dir: Option<PathBuf>,
+}
+
+impl CacheConfig {
+    /// 启动后端时需要设置的环境变量：设置了 `dir` 时是 `XDG_CACHE_HOME`，只影响不属于编译数据库的文件的索引。
+    pub fn backend_env(&self) -> Vec<(String, String)> {
+        self.dir
+            .iter()
+            .map(|dir| ("XDG_CACHE_HOME".to_string(), dir.display().to_string()))
+            .collect()
+    }
+}
+
+/// 一个目录的 clang-tidy 检查集合。
+///
+/// - `path`: 规则适用的目录，相对路径相对于配置文件所在目录；省略时适用于所有文件
+/// - `checks`: 与 `.clang-tidy` 的 `Checks` 相同的语法，例如 `-*,bugprone-*,-bugprone-easily-swappable-parameters`
+#[derive(Debug, Clone, Deserialize)]
+pub struct TidyRule {
+    pub path: Option<PathBuf>,
+    pub checks: String,
+}
+
+/// 文档的语言。编辑器对少见的扩展名经常猜错语言，代理在转发 `didOpen` 前改正。
+///
+/// - `extensions`: 扩展名到语言 id 的映射，例如 `inl = "cpp"`、`x = "c"`，优先于编辑器给出的语言
+/// - `ids`: 编辑器给出的语言 id 到交给后端的语言 id 的映射，例如 `cuda = "cuda-cpp"`
+/// - `shards`: 语言 id 到分片名称的映射，这些语言的文档交给指定的分片，不再按路径选择
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LanguageConfig {
+    pub extensions: HashMap<String, String>,
+    pub ids: HashMap<String, String>,
+    pub shards: HashMap<String, String>,
+}
+
+/// 一类宿主文件中嵌入的 C/C++ 代码，取出来作为虚拟文档交给后端。
+///
+/// - `extensions`: 宿主文件的扩展名，例如 `["ino", "pde"]`
+/// - `start`/`end`: 代码片段开始和结束标记的正则表达式（多行模式），片段不包含标记本身；
+///   省略 `start` 时整个文件都是代码，省略 `end` 时片段到文件末尾
+/// - `prelude`: 加在虚拟文档开头的代码，例如 Arduino 草图的 `#include <Arduino.h>`
+/// - `language_id`: 虚拟文档的语言，默认是 `cpp`
+/// - `suffix`: 加在宿主文件的 URI 后面作为虚拟文档 URI 的扩展名，默认是 `cpp`，clangd 按它推断语言
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EmbeddedRule {
+    pub extensions: Vec<String>,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub prelude: String,
+    pub language_id: String,
+    pub suffix: String,
+}
+
+impl Default for EmbeddedRule {
+    fn default() -> Self {
+        Self {
+            extensions: Vec::new(),
+            start: None,
+            end: None,
+            prelude: String::new(),
+            language_id: "cpp".to_string(),
+            suffix: "cpp".to_string(),
+        }
+    }
+}
+
+/// 头文件插入策略。
+///
+/// - `style`: 统一使用尖括号（`angle`）或引号（`quote`），省略时保持 clangd 的选择
+/// - `blocked`: 不允许插入的头文件（glob，不含分隔符），例如 `bits/*`
+/// - `mapping_file`: IWYU 映射文件（`.imp`），把私有头文件替换为公开头文件
+/// - `check`: 检查引号形式包含的头文件是否存在以及包含循环，结果作为 `includes` 来源的诊断显示
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct IncludeConfig {
+    pub style: Option<IncludeStyle>,
+    pub blocked: Vec<String>,
+    pub mapping_file: Option<PathBuf>,
+    pub check: bool,
+}
+
+/// `#include` 的分隔符风格。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IncludeStyle {
+    Angle,
+    Quote,
+}
+
+/// 一个分片：负责 `path` 子树内所有文档的独立 clangd 实例。
+///
+/// - `name`: 日志中显示的名字，默认使用目录名
+/// - `path`: 子树根目录，相对路径相对于配置文件所在目录
+/// - `args`: 额外的启动参数，追加在 `backend.args` 之后
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShardConfig {
+    pub name: Option<String>,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl ShardConfig {
+    /// 分片的显示名称。
+    pub fn display_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            self.path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| self.path.display().to_string())
+        })
+    }
+}
+
+impl Config {
+    /// 从指定的 TOML 文件加载配置。
+    ///
+    /// 先展开路径和命令中的变量，再把相对路径解析为相对于配置文件所在目录的绝对路径。
+    ///
+    /// # 错误
+    ///
+    /// 如果文件无法读取、内容不是合法的配置或者其中的变量无法展开，返回错误
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::load_profile(path, None)
+    }
+
+    /// 与 [`Config::load`] 相同，另外应用 `profile` 指定的配置档。
+    ///
+    /// # 错误
+    ///
+    /// 除了 [`Config::load`] 的错误，配置档不存在时也返回错误
+    pub fn load_profile(path: &Path, profile: Option<&str>) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("无法读取配置文件 {}", path.display()))?;
+        let mut config = Self::parse_profile(&text, profile)
+            .with_context(|| format!("配置文件 {} 格式错误", path.display()))?;
+        config
+            .expand_variables(&Variables::from_env()?)
+            .with_context(|| format!("配置文件 {} 中的变量无法展开", path.display()))?;
+
+        let base = path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+        let base = std::path::absolute(&base).unwrap_or(base);
+        config.resolve_paths(&base);
+        Ok(config)
+    }
+
+    /// 解析 TOML 文本，不做路径解析。
+    ///
+    /// # 错误
+    ///
+    /// 如果内容不是合法的配置，返回错误
+    pub fn parse(text: &str) -> Result<Self> {
+        Self::parse_profile(text, None)
+    }
+
+    /// 解析 TOML 文本并应用 `profile` 指定的配置档，不做路径解析。
+    ///
+    /// # 错误
+    ///
+    /// 如果内容不是合法的配置（包括无效的 `[backend.clangd]` 参数），或者配置档不存在，返回错误
+    pub fn parse_profile(text: &str, profile: Option<&str>) -> Result<Self> {
+        // 不使用配置档时直接解析文本，错误信息中保留出错的位置；`[profile]` 不属于任何配置项，解析时被忽略
+        let config: Self = if profile.is_none() {
+            toml::from_str(text)?
+        } else {
+            let mut document: toml::Table = toml::from_str(text)?;
+            profiles::apply(&mut document, profile)?;
+            toml::Value::Table(document).try_into()?
+        };
+        clangd_flags::validate(&config.backend.clangd, &config.backend.args)?;
+        shadow::validate(&config.shadow)?;
+        sandbox::validate(&config.backend.sandbox)?;
+        platform::validate_limits(&config.backend.limits)?;
+        telemetry::validate(&config.telemetry)?;
+        idle::validate(&config.backend.idle)?;
+        Ok(config)
+    }
+
+    /// 按照约定查找并加载配置。
+    ///
+    /// 优先使用 `explicit` 指定的文件；否则尝试当前目录下的 `.codefuse.toml`；都没有时使用默认配置。
+    ///
+    /// # 错误
+    ///
+    /// 如果找到的配置文件无法加载，返回错误
+    pub fn discover(explicit: Option<&Path>) -> Result<Self> {
+        Self::discover_profile(explicit, None)
+    }
+
+    /// 与 [`Config::discover`] 相同，另外应用 `profile` 指定的配置档；没有配置文件时配置档应用于默认配置。
+    ///
+    /// # 错误
+    ///
+    /// 如果找到的配置文件无法加载，或者配置档不存在，返回错误
+    pub fn discover_profile(explicit: Option<&Path>, profile: Option<&str>) -> Result<Self> {
+        match Self::discover_path(explicit) {
+            Some(path) => Self::load_profile(&path, profile),
+            None => Self::parse_profile("", profile),
+        }
+    }
+
+    /// [`Config::discover`] 使用的配置文件，没有配置文件时返回 `None`。
+    pub fn discover_path(explicit: Option<&Path>) -> Option<PathBuf> {
+        if let Some(path) = explicit {
+            return Some(path.to_path_buf());
+        }
+        Some(PathBuf::from(CONFIG_FILE_NAME)).filter(|path| path.is_file())
+    }
+
+    /// 展开所有路径和命令字段中的变量。`remote.remote_root` 是远程机器上的路径，不展开。
+    ///
+    /// # 错误
+    ///
+    /// 任何一个字段无法展开时返回错误，错误信息中包含字段名
+    pub fn expand_variables(&mut self, variables: &Variables) -> Result<()> {
+        let text = |field: &str, value: &mut String| -> Result<()> {
+            *value = variables
+                .expand(value)
+                .with_context(|| format!("配置项 {}", field))?;
+            Ok(())
+        };
+        let texts = |field: &str, values: &mut Vec<String>| -> Result<()> {
+            values.iter_mut().try_for_each(|value| text(field, value))
+        };
+        let path = |field: &str, value: &mut PathBuf| -> Result<()> {
+            *value = variables
+                .expand_path(value)
+                .with_context(|| format!("配置项 {}", field))?;
+            Ok(())
+        };
+        let optional_path = |field: &str, value: &mut Option<PathBuf>| -> Result<()> {
+            value.iter_mut().try_for_each(|value| path(field, value))
+        };
+
+        text("backend.command", &mut self.backend.command)?;
+        texts("backend.args", &mut self.backend.args)?;
+        texts(
+            "backend.clangd.query_driver",
+            &mut self.backend.clangd.query_driver,
+        )?;
+        if let Some(control_path) = &mut self.backend.ssh.control_path {
+            text("backend.ssh.control_path", control_path)?;
+        }
+        texts("backend.ssh.args", &mut self.backend.ssh.args)?;
+        if let Some(container) = &mut self.backend.container {
+            text("backend.container.engine", &mut container.engine)?;
+            optional_path("backend.container.workspace", &mut container.workspace)?;
+            optional_path("backend.container.mount", &mut container.mount)?;
+            texts("backend.container.args", &mut container.args)?;
+        }
+        if let Some(sandbox) = &mut self.backend.sandbox {
+            optional_path("backend.sandbox.workspace", &mut sandbox.workspace)?;
+            for dir in &mut sandbox.writable {
+                path("backend.sandbox.writable", dir)?;
+            }
+            for dir in &mut sandbox.read_only {
+                path("backend.sandbox.read_only", dir)?;
+            }
+            texts("backend.sandbox.args", &mut sandbox.args)?;
+        }
+        for shard in &mut self.shards {
+            path("shards.path", &mut shard.path)?;
+            texts("shards.args", &mut shard.args)?;
+        }
+        optional_path("cache.dir", &mut self.cache.dir)?;
+        for rule in &mut self.tidy {
+            optional_path("tidy.path", &mut rule.path)?;
+        }
+        optional_path("includes.mapping_file", &mut self.includes.mapping_file)?;
+        optional_path("transport.tls_cert", &mut self.transport.tls_cert)?;
+        optional_path("transport.tls_key", &mut self.transport.tls_key)?;
+        optional_path("transport.tls_ca", &mut self.transport.tls_ca)?;
+        optional_path("transport.token_file", &mut self.transport.token_file)?;
+        optional_path("remote.local_root", &mut self.remote.local_root)?;
+        text("remote.ssh_command", &mut self.remote.ssh_command)?;
+        optional_path("spellcheck.dictionary", &mut self.spellcheck.dictionary)?;
+        optional_path("admin.socket", &mut self.admin.socket)?;
+        optional_path("crash_reports.dir", &mut self.crash_reports.dir)?;
+        for file in &mut self.snippets.files {
+            path("snippets.files", file)?;
+        }
+        text("modules.compiler", &mut self.modules.compiler)?;
+        texts("modules.flags", &mut self.modules.flags)?;
+        text("cmake.command", &mut self.cmake.command)?;
+        path("cmake.build_dir", &mut self.cmake.build_dir)?;
+        texts("cmake.args", &mut self.cmake.args)?;
+        text("flags.compiler", &mut self.flags.compiler)?;
+        text("flags.bazel_command", &mut self.flags.bazel_command)?;
+        Ok(())
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        if let Some(dir) = &mut self.cache.dir
+            && dir.is_relative()
+        {
+            *dir = base.join(&*dir);
+        }
+        if let Some(path) = &mut self.includes.mapping_file
+            && path.is_relative()
+        {
+            *path = base.join(&*path);
+        }
+        for rule in &mut self.tidy {
+            if let Some(path) = &mut rule.path
+                && path.is_relative()
+            {
+                *path = base.join(&*path);
+            }
+        }
+        for shard in &mut self.shards {
+            if shard.path.is_relative() {
+                shard.path = base.join(&shard.path);
+            }
+        }
+        for file in &mut self.snippets.files {
+            if file.is_relative() {
+                *file = base.join(&*file);
+            }
+        }
+    }
+}
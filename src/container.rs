@@ -0,0 +1,166 @@
+//! # 容器后端模块
+//!
+//! 配置了 `[backend.container]` 时，后端在容器中运行：工作区以绑定挂载的方式放进容器，
+//! 容器在后端退出时删除，工作区在容器中的路径与主机不同时，消息中的路径由 [`PathMapping`] 替换。
+
+use anyhow::{Context, Result, bail};
+use log::{debug, warn};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config::{ContainerConfig, ResourceLimits};
+use crate::remote::PathMapping;
+
+/// 同一个代理启动的容器的序号，备用后端和重启的后端各有自己的容器。
+static NEXT_CONTAINER: AtomicU64 = AtomicU64::new(1);
+
+/// 运行后端的容器。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerSpec {
+    /// 容器引擎的命令
+    pub engine: String,
+    pub image: String,
+    /// 主机上的工作区
+    pub workspace: PathBuf,
+    /// 工作区在容器中的路径
+    pub mount: PathBuf,
+    /// 传给 `run` 的额外参数
+    pub extra_args: Vec<String>,
+}
+
+impl ContainerSpec {
+    /// 按配置创建，相对的工作区路径相对于代理的当前目录。
+    ///
+    /// # 错误
+    ///
+    /// 如果没有配置镜像，或者无法确定工作区的绝对路径，返回错误
+    pub fn new(config: &ContainerConfig) -> Result<Self> {
+        if config.image.is_empty() {
+            bail!("[backend.container] 没有配置 image");
+        }
+        let current = std::env::current_dir().context("无法获取当前目录")?;
+        let workspace = match &config.workspace {
+            Some(workspace) => current.join(workspace),
+            None => current,
+        };
+        Ok(Self {
+            engine: config.engine.clone(),
+            image: config.image.clone(),
+            mount: config.mount.clone().unwrap_or_else(|| workspace.clone()),
+            workspace,
+            extra_args: config.args.clone(),
+        })
+    }
+
+    /// 为新的后端进程分配容器名。
+    pub fn next_name(&self, shard: usize) -> String {
+        let serial = NEXT_CONTAINER.fetch_add(1, Ordering::Relaxed);
+        format!("codefuse-{}-{}-{}", std::process::id(), shard, serial)
+    }
+
+    /// 在容器中启动后端的 `run` 参数。
+    ///
+    /// 容器以 `--rm` 运行，后端正常退出时由引擎删除；资源限制转成容器的 CPU 和内存限制。
+    ///
+    /// # 参数
+    ///
+    /// * `name` - 容器名
+    /// * `program` - 容器中的后端程序
+    /// * `args` - 后端的参数
+    /// * `envs` - 启动后端时额外设置的环境变量
+    /// * `limits` - 后端进程的资源限制
+    pub fn run_args(
+        &self,
+        name: &str,
+        program: &str,
+        args: &[String],
+        envs: &[(String, String)],
+        limits: &ResourceLimits,
+    ) -> Vec<String> {
+        let mut run = vec![
+            "run".to_string(),
+            "--rm".to_string(),
+            "-i".to_string(),
+            "--name".to_string(),
+            name.to_string(),
+            "-v".to_string(),
+            format!("{}:{}", self.workspace.display(), self.mount.display()),
+            "-w".to_string(),
+            self.mount.display().to_string(),
+        ];
+        for (key, value) in envs {
+            run.push("-e".to_string());
+            run.push(format!("{}={}", key, value));
+        }
+        if !limits.cpus.is_empty() {
+            let cpus: Vec<_> = limits.cpus.iter().map(|cpu| cpu.to_string()).collect();
+            run.push(format!("--cpuset-cpus={}", cpus.join(",")));
+        }
+        if let Some(memory_mb) = limits.memory_mb {
+            run.push(format!("--memory={}m", memory_mb));
+        }
+        run.extend(self.extra_args.iter().cloned());
+        run.push(self.image.clone());
+        run.push(program.to_string());
+        run.extend(args.iter().cloned());
+        run
+    }
+
+    /// 在一次性的容器中运行 `program --version` 的参数，供环境检查使用。
+    pub fn version_args(&self, program: &str) -> Vec<String> {
+        ["run", "--rm", &self.image, program, "--version"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    /// 主机与容器之间的路径映射，工作区挂载在相同路径时返回 `None`。
+    ///
+    /// # 错误
+    ///
+    /// 如果挂载路径不是绝对路径，返回错误
+    pub fn mapping(&self) -> Result<Option<PathMapping>> {
+        if self.mount == self.workspace {
+            return Ok(None);
+        }
+        PathMapping::new(&self.workspace, &self.mount).map(Some)
+    }
+}
+
+/// 持有一个容器，丢弃时强制删除。
+///
+/// 结束 `run` 的客户端进程并不会停止容器，后端被回收或代理退出时需要显式删除。
+#[derive(Debug)]
+pub struct ContainerGuard {
+    engine: String,
+    name: String,
+}
+
+impl ContainerGuard {
+    pub fn new(engine: &str, name: String) -> Self {
+        Self {
+            engine: engine.to_string(),
+            name,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for ContainerGuard {
+    fn drop(&mut self) {
+        debug!("删除容器 {}", self.name);
+        // 不等待删除完成，代理退出时也不会被阻塞
+        let removed = Command::new(&self.engine)
+            .args(["rm", "-f", &self.name])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if let Err(e) = removed {
+            warn!("无法删除容器 {}: {}", self.name, e);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! # 过期响应模块
+//!
+//! 记录配置的方法的请求发出时文档的版本。响应到达时文档已经被修改的话，丢弃响应，
+//! 以 `ContentModified` 错误应答前端，编辑器不会把针对旧版本的编辑或高亮应用到新内容上。
+
+use dashmap::DashMap;
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use tower_lsp::lsp_types::Url;
+
+use crate::document_store::DocumentStore;
+
+/// LSP 的 `ContentModified` 错误码。
+pub const CONTENT_MODIFIED: i64 = -32801;
+
+/// 按文档版本检查响应是否过期。
+#[derive(Default)]
+pub struct VersionCheck {
+    methods: HashSet<String>,
+    requests: DashMap<String, (Url, i32)>,
+}
+
+impl VersionCheck {
+    /// 创建检查，只检查 `methods` 中的方法。
+    pub fn new(methods: &[String]) -> Self {
+        Self {
+            methods: methods.iter().cloned().collect(),
+            requests: DashMap::new(),
+        }
+    }
+
+    /// 记录转发给后端的请求针对的文档版本，不检查的方法和未打开的文档被忽略。
+    ///
+    /// # 参数
+    ///
+    /// * `id` - 请求的 id
+    /// * `method` - 请求的方法
+    /// * `params` - 请求的参数，文档由 `textDocument.uri` 给出
+    /// * `documents` - 打开的文档
+    pub fn request(
+        &self,
+        id: &Value,
+        method: &str,
+        params: Option<&Value>,
+        documents: &DocumentStore,
+    ) {
+        if !self.methods.contains(method) {
+            return;
+        }
+        let Some(uri) = params
+            .and_then(|params| params.pointer("/textDocument/uri"))
+            .and_then(|uri| uri.as_str())
+            .and_then(|uri| Url::parse(uri).ok())
+        else {
+            return;
+        };
+        if let Some(doc) = documents.get(&uri) {
+            self.requests.insert(id.to_string(), (uri, doc.version));
+        }
+    }
+
+    /// 响应到达时，请求针对的文档是否已经被修改或关闭。没有记录的请求不算过期。
+    pub fn is_stale(&self, id: &Value, documents: &DocumentStore) -> bool {
+        let Some((_, (uri, version))) = self.requests.remove(&id.to_string()) else {
+            return false;
+        };
+        documents.get(&uri).is_none_or(|doc| doc.version != version)
+    }
+}
+
+/// 代替过期响应交给前端的 `ContentModified` 错误。
+pub fn content_modified(id: &Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": CONTENT_MODIFIED, "message": "文档在请求之后已被修改"},
+    })
+}
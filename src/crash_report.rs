@@ -0,0 +1,257 @@
+//! # 崩溃报告模块
+//!
+//! 代理 panic 或者后端异常退出时，把排查问题需要的信息打包成一个 zip 文件：最近的消息（隐去文档内容）、
+//! 生效的配置、后端最近的标准错误输出，以及代理、后端和系统的版本，然后通过 `window/showMessage`
+//! 告诉用户文件的位置，用户提交问题时附上这个文件即可。
+
+use anyhow::Result;
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use serde_json::{Map, Value, json};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::config::CrashReportConfig;
+use crate::dispatcher::Dispatcher;
+
+/// 两份崩溃报告之间的最短间隔，后端反复崩溃时不会生成一连串报告。
+const MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 这些字段中的字符串是文档内容或者编辑文本，隐去时只保留长度。
+const REDACTED_FIELDS: &[&str] = &["text", "newText", "insertText", "contents", "value"];
+
+/// 按 `[crash_reports]` 配置决定是否以及在哪里生成崩溃报告。
+#[derive(Default)]
+pub struct CrashReports {
+    config: CrashReportConfig,
+    last: Mutex<Option<Instant>>,
+}
+
+impl CrashReports {
+    /// 按 `[crash_reports]` 配置创建。
+    pub fn new(config: &CrashReportConfig) -> Self {
+        Self {
+            config: config.clone(),
+            last: Mutex::new(None),
+        }
+    }
+
+    /// 现在是否生成崩溃报告：没有开启，或者距离上一份报告不到一分钟时返回 `false`。
+    pub fn due(&self) -> bool {
+        if !self.config.enabled {
+            return false;
+        }
+        let mut last = self.last.lock().unwrap();
+        if last.is_some_and(|last| last.elapsed() < MIN_INTERVAL) {
+            return false;
+        }
+        *last = Some(Instant::now());
+        true
+    }
+
+    /// 保存崩溃报告的目录。
+    pub fn dir(&self) -> PathBuf {
+        self.config
+            .dir
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("codefuse-crash"))
+    }
+
+    /// 是否隐去消息中的文档内容。
+    pub fn redact(&self) -> bool {
+        self.config.redact
+    }
+}
+
+/// 隐去消息中的文档内容和编辑文本，字符串替换为它的长度，其余部分保持不变。
+pub fn redact(message: &Value) -> Value {
+    redact_value(message, false)
+}
+
+fn redact_value(value: &Value, hidden: bool) -> Value {
+    match value {
+        Value::String(text) if hidden => Value::String(format!("<已隐去 {} 字节>", text.len())),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| redact_value(item, hidden))
+                .collect(),
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, field)| {
+                    let hidden = hidden || REDACTED_FIELDS.contains(&key.as_str());
+                    (key.clone(), redact_value(field, hidden))
+                })
+                .collect::<Map<String, Value>>(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// 把崩溃报告写入 `dir` 下的 zip 文件。
+///
+/// # 参数
+///
+/// * `dispatcher` - 提供最近的消息、配置、后端输出和版本
+/// * `dir` - 保存报告的目录，不存在时创建
+/// * `reason` - 生成报告的原因，例如 panic 的信息
+///
+/// # 返回
+///
+/// 返回 zip 文件的路径
+///
+/// # 错误
+///
+/// 如果目录或文件无法写入，返回错误
+pub fn write_bundle(dispatcher: &Dispatcher, dir: &Path, reason: &str) -> Result<PathBuf> {
+    let redacted = dispatcher.crash_reports().redact();
+    let shards = dispatcher.health().report(dispatcher).backends;
+    let backend = dispatcher.backend_capabilities().snapshot();
+    let report = json!({
+        "reason": reason,
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "proxyVersion": env!("CARGO_PKG_VERSION"),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "backend": {
+            "name": backend.as_ref().and_then(|backend| backend.name.clone()),
+            "version": backend.as_ref().and_then(|backend| backend.version.clone()),
+        },
+        "shards": shards,
+        "messagesRedacted": redacted,
+    });
+
+    let mut messages = Vec::new();
+    for mut entry in dispatcher.trace().entries() {
+        if redacted {
+            entry.message = redact(&entry.message);
+        }
+        serde_json::to_writer(&mut messages, &entry)?;
+        messages.push(b'\n');
+    }
+    let mut files = vec![
+        (
+            "report.json".to_string(),
+            serde_json::to_vec_pretty(&report)?,
+        ),
+        (
+            "config.txt".to_string(),
+            format!("{:#?}\n", dispatcher.config()).into_bytes(),
+        ),
+        ("messages.ndjson".to_string(), messages),
+    ];
+    for (shard, backend) in shards.iter().enumerate() {
+        let log = dispatcher
+            .backend_log()
+            .snapshot(shard, &backend.name, None);
+        let mut lines = String::new();
+        for line in &log.lines {
+            lines.push_str(&line.message);
+            lines.push('\n');
+        }
+        files.push((format!("stderr-{}.log", backend.name), lines.into_bytes()));
+    }
+
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!(
+        "codefuse-crash-{}-{}.zip",
+        chrono::Local::now().format("%Y%m%d-%H%M%S"),
+        std::process::id()
+    ));
+    std::fs::write(&path, zip(&files)?)?;
+    Ok(path)
+}
+
+/// 生成 zip 文件的内容，每个文件以 deflate 压缩。
+///
+/// 崩溃报告只需要写 zip，不值得为此引入一个依赖；这里只实现 zip 格式中单个磁盘、没有扩展字段的部分。
+fn zip(files: &[(String, Vec<u8>)]) -> Result<Vec<u8>> {
+    let now = chrono::Local::now().naive_local();
+    let (time, date) = dos_time(now);
+    let mut output = Vec::new();
+    let mut directory = Vec::new();
+    for (name, data) in files {
+        let mut crc = flate2::Crc::new();
+        crc.update(data);
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+        let offset = output.len() as u32;
+
+        // 本地文件头
+        output.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        let common = |buffer: &mut Vec<u8>| {
+            buffer.extend_from_slice(&20u16.to_le_bytes()); // 解压需要的版本 2.0
+            buffer.extend_from_slice(&0x0800u16.to_le_bytes()); // 文件名是 UTF-8
+            buffer.extend_from_slice(&8u16.to_le_bytes()); // deflate
+            buffer.extend_from_slice(&time.to_le_bytes());
+            buffer.extend_from_slice(&date.to_le_bytes());
+            buffer.extend_from_slice(&crc.sum().to_le_bytes());
+            buffer.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            buffer.extend_from_slice(&0u16.to_le_bytes()); // 扩展字段长度
+        };
+        common(&mut output);
+        output.extend_from_slice(name.as_bytes());
+        output.extend_from_slice(&compressed);
+
+        // 中央目录中的记录
+        directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        directory.extend_from_slice(&20u16.to_le_bytes()); // 生成的版本
+        common(&mut directory);
+        directory.extend_from_slice(&0u16.to_le_bytes()); // 注释长度
+        directory.extend_from_slice(&0u16.to_le_bytes()); // 磁盘号
+        directory.extend_from_slice(&0u16.to_le_bytes()); // 内部属性
+        directory.extend_from_slice(&0u32.to_le_bytes()); // 外部属性
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let directory_offset = output.len() as u32;
+    output.extend_from_slice(&directory);
+    // 中央目录结束记录
+    output.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    output.extend_from_slice(&0u16.to_le_bytes());
+    output.extend_from_slice(&0u16.to_le_bytes());
+    output.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    output.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    output.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    output.extend_from_slice(&directory_offset.to_le_bytes());
+    output.extend_from_slice(&0u16.to_le_bytes());
+    Ok(output)
+}
+
+/// zip 使用的 MS-DOS 时间和日期。
+fn dos_time(time: chrono::NaiveDateTime) -> (u16, u16) {
+    use chrono::{Datelike, Timelike};
+
+    let dos_time = (time.hour() << 11 | time.minute() << 5 | (time.second() / 2)) as u16;
+    let year = time.year().clamp(1980, 2107) as u32 - 1980;
+    let dos_date = (year << 9 | time.month() << 5 | time.day()) as u16;
+    (dos_time, dos_date)
+}
+
+static PANIC_DISPATCHER: OnceLock<Weak<Dispatcher>> = OnceLock::new();
+
+/// 安装 panic 钩子，代理 panic 时生成崩溃报告。
+///
+/// 报告在之前安装的钩子（记录 panic 信息、结束后端）之前生成。发布构建中 panic 会直接中止进程，
+/// 通知编辑器的消息可能来不及发出，报告的位置同时写入日志。
+pub fn install_panic_hook(dispatcher: &Arc<Dispatcher>) {
+    if PANIC_DISPATCHER.set(Arc::downgrade(dispatcher)).is_err() {
+        return;
+    }
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if let Some(dispatcher) = PANIC_DISPATCHER.get().and_then(Weak::upgrade) {
+            dispatcher.report_crash(&format!("代理 panic: {}", info));
+        }
+        previous(info);
+    }));
+}
@@ -0,0 +1,131 @@
+//! # 诊断来源模块
+//!
+//! 后端之外的功能也可以为文档提供诊断。每个来源的诊断分别记录，合并成一条 `publishDiagnostics` 发给编辑器：
+//! 没有 `source` 的诊断标上来源的名称，来源按名称排序，`[diagnostics] sources` 关闭的来源不显示。
+//! 子目录的 `.codefuse.toml` 可以为其中的文档改变来源的开关。
+
+use dashmap::DashMap;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use tower_lsp::lsp_types::{DidCloseTextDocumentParams, Url};
+
+use crate::config::DiagnosticsConfig;
+use crate::directory_config::DirectoryConfigs;
+use crate::document_observer::DocumentObserver;
+
+/// 后端提供的诊断的来源名称。
+pub const BACKEND_SOURCE: &str = "clangd";
+
+/// 每个文档各个来源最近的诊断。
+#[derive(Default)]
+pub struct DiagnosticSources {
+    /// 来源的开关，没有列出的来源默认显示
+    enabled: RwLock<HashMap<String, bool>>,
+    /// 文档 → 来源 → 诊断，按来源名称排序保证合并后的顺序稳定
+    published: DashMap<Url, BTreeMap<String, Vec<Value>>>,
+    directories: Option<Arc<DirectoryConfigs>>,
+}
+
+impl DiagnosticSources {
+    pub fn new(config: &DiagnosticsConfig) -> Self {
+        Self {
+            enabled: RwLock::new(config.sources.clone()),
+            published: DashMap::new(),
+            directories: None,
+        }
+    }
+
+    /// 同时应用子目录 `.codefuse.toml` 中的来源开关。
+    pub fn with_directories(mut self, directories: Arc<DirectoryConfigs>) -> Self {
+        self.directories = Some(directories);
+        self
+    }
+
+    /// 替换来源的开关，之后合并的诊断按新的开关显示。
+    pub fn set_sources(&self, config: &DiagnosticsConfig) {
+        *self.enabled.write().unwrap() = config.sources.clone();
+    }
+
+    /// 来源是否显示。
+    pub fn is_enabled(&self, source: &str) -> bool {
+        self.enabled
+            .read()
+            .unwrap()
+            .get(source)
+            .copied()
+            .unwrap_or(true)
+    }
+
+    /// 记录一个来源发布的诊断，把通知中的诊断换成这个文档所有来源合并后的诊断。
+    ///
+    /// # 参数
+    ///
+    /// * `source` - 发布诊断的来源
+    /// * `rpc` - `textDocument/publishDiagnostics` 通知，只包含这个来源的诊断
+    pub fn merge(&self, source: &str, rpc: &mut Value) {
+        let Some(uri) = rpc
+            .pointer("/params/uri")
+            .and_then(|uri| uri.as_str())
+            .and_then(|uri| Url::parse(uri).ok())
+        else {
+            return;
+        };
+        let mut diagnostics = match rpc.pointer_mut("/params/diagnostics") {
+            Some(Value::Array(diagnostics)) => std::mem::take(diagnostics),
+            _ => Vec::new(),
+        };
+        for diagnostic in &mut diagnostics {
+            if diagnostic.get("source").is_none()
+                && let Some(diagnostic) = diagnostic.as_object_mut()
+            {
+                diagnostic.insert("source".to_string(), json!(source));
+            }
+        }
+
+        let mut sources = self.published.entry(uri.clone()).or_default();
+        if diagnostics.is_empty() {
+            sources.remove(source);
+        } else {
+            sources.insert(source.to_string(), diagnostics);
+        }
+        let merged = self.merged(&uri, &sources);
+        let empty = sources.is_empty();
+        drop(sources);
+        if empty {
+            self.published
+                .remove_if(&uri, |_, sources| sources.is_empty());
+        }
+        rpc["params"]["diagnostics"] = Value::Array(merged);
+    }
+
+    /// 按来源名称的顺序合并在文档中开启的来源的诊断。
+    fn merged(&self, uri: &Url, sources: &BTreeMap<String, Vec<Value>>) -> Vec<Value> {
+        let base = self.enabled.read().unwrap();
+        let layered = self.directories.as_ref().and_then(|directories| {
+            let path = uri.to_file_path().ok()?;
+            Some(directories.sources(&path, &base))
+        });
+        let enabled = layered.as_ref().unwrap_or(&base);
+        let is_enabled = |source: &str| enabled.get(source).copied().unwrap_or(true);
+        sources
+            .iter()
+            .filter(|(source, _)| is_enabled(source))
+            .flat_map(|(_, diagnostics)| diagnostics)
+            .filter(|diagnostic| {
+                diagnostic
+                    .get("source")
+                    .and_then(|s| s.as_str())
+                    .is_none_or(is_enabled)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// 关闭的文档不再有任何来源的诊断。
+impl DocumentObserver for DiagnosticSources {
+    fn did_close(&self, params: &DidCloseTextDocumentParams) {
+        self.published.remove(&params.text_document.uri);
+    }
+}
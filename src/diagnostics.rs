@@ -0,0 +1,89 @@
+//! # 诊断存储模块
+//!
+//! 记录每个文档最近一次转发给前端的诊断，供需要了解当前诊断的功能（例如一键应用所有修复）使用。
+//! 快速输入时后端会发来内容相同的诊断和针对旧版本的诊断，这两种都不再转发给前端。
+
+use dashmap::DashMap;
+use serde_json::Value;
+use tower_lsp::lsp_types::{DidCloseTextDocumentParams, Url};
+
+use crate::document_observer::DocumentObserver;
+use crate::document_store::DocumentStore;
+
+/// 每个文档最近的诊断。
+#[derive(Default)]
+pub struct DiagnosticsStore {
+    /// 最近转发给前端的诊断，包括空的诊断列表，文档关闭时删除
+    latest: DashMap<Url, Vec<Value>>,
+}
+
+impl DiagnosticsStore {
+    /// 创建空的诊断存储。
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 根据 `textDocument/publishDiagnostics` 通知更新存储。
+    ///
+    /// # 返回
+    ///
+    /// 诊断与上次转发给前端的相同时返回 `false`，这条通知不必再转发
+    pub fn update(&self, rpc: &Value) -> bool {
+        let Some(uri) = publish_uri(rpc) else {
+            return true;
+        };
+        let diagnostics = rpc
+            .pointer("/params/diagnostics")
+            .and_then(|d| d.as_array())
+            .cloned()
+            .unwrap_or_default();
+        if self
+            .latest
+            .get(&uri)
+            .is_some_and(|latest| *latest == diagnostics)
+        {
+            return false;
+        }
+        self.latest.insert(uri, diagnostics);
+        true
+    }
+
+    /// 文档当前的诊断。
+    pub fn get(&self, uri: &Url) -> Vec<Value> {
+        self.latest.get(uri).map(|d| d.clone()).unwrap_or_default()
+    }
+
+    /// 当前有诊断的所有文档。
+    pub fn uris(&self) -> Vec<Url> {
+        self.latest
+            .iter()
+            .filter(|entry| !entry.value().is_empty())
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+}
+
+/// `textDocument/publishDiagnostics` 通知针对的文档版本是否早于文档当前的版本。
+///
+/// 没有携带版本的诊断和没有打开的文档的诊断不算过时。
+pub fn is_outdated(rpc: &Value, documents: &DocumentStore) -> bool {
+    let Some(version) = rpc.pointer("/params/version").and_then(|v| v.as_i64()) else {
+        return false;
+    };
+    publish_uri(rpc)
+        .and_then(|uri| documents.get(&uri))
+        .is_some_and(|doc| i64::from(doc.version) > version)
+}
+
+fn publish_uri(rpc: &Value) -> Option<Url> {
+    rpc.pointer("/params/uri")
+        .and_then(|uri| uri.as_str())
+        .and_then(|uri| Url::parse(uri).ok())
+}
+
+/// 关闭的文档不再有诊断，不等待后端发来空的诊断列表。
+impl DocumentObserver for DiagnosticsStore {
+    fn did_close(&self, params: &DidCloseTextDocumentParams) {
+        self.latest.remove(&params.text_document.uri);
+    }
+}
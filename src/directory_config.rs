@@ -0,0 +1,143 @@
+//! # 目录配置模块
+//!
+//! 工作区的子目录可以有自己的 `.codefuse.toml`，其中的诊断来源开关和 clang-tidy 检查只作用于这个目录下的文档。
+//! 与 `.clang-format` 一样逐层合并：工作区配置最先应用，离文档越近的文件越靠后，后应用的设置覆盖先应用的。
+
+use anyhow::{Context, Result};
+use dashmap::DashMap;
+use log::{debug, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use crate::config::{CONFIG_FILE_NAME, DiagnosticsConfig, TidyRule};
+
+/// 子目录中 `.codefuse.toml` 生效的部分，其他设置只在工作区配置中有效。
+///
+/// - `diagnostics`: 诊断来源的开关，覆盖外层的同名来源
+/// - `tidy`: clang-tidy 检查集合，`path` 相对于这个文件所在目录，省略时适用于整个目录
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DirectoryLayer {
+    pub diagnostics: DiagnosticsConfig,
+    pub tidy: Vec<TidyRule>,
+}
+
+impl DirectoryLayer {
+    /// 解析一个子目录中的配置，把 clang-tidy 规则的路径解析为绝对路径。
+    ///
+    /// # 参数
+    ///
+    /// * `text` - 配置文件的内容
+    /// * `dir` - 配置文件所在目录
+    ///
+    /// # 错误
+    ///
+    /// 内容不是合法的配置时返回错误
+    pub fn parse(text: &str, dir: &Path) -> Result<Self> {
+        let mut layer: Self = toml::from_str(text)?;
+        for rule in &mut layer.tidy {
+            rule.path = Some(match &rule.path {
+                Some(path) => dir.join(path),
+                None => dir.to_path_buf(),
+            });
+        }
+        Ok(layer)
+    }
+}
+
+/// 工作区中各个子目录的配置，按文件的修改时间和大小缓存。
+#[derive(Default)]
+pub struct DirectoryConfigs {
+    /// 工作区根目录，根目录自己的配置文件就是工作区配置，不再作为一层
+    roots: RwLock<Vec<PathBuf>>,
+    layers: DashMap<PathBuf, ((SystemTime, u64), Arc<DirectoryLayer>)>,
+}
+
+impl DirectoryConfigs {
+    /// 更新工作区根目录，只查找根目录之下的配置文件。
+    pub fn set_roots(&self, roots: Vec<PathBuf>) {
+        *self.roots.write().unwrap() = roots;
+    }
+
+    /// 作用于文件的各层配置，外层在前。文件不在任何工作区中时返回空列表。
+    pub fn layers(&self, path: &Path) -> Vec<Arc<DirectoryLayer>> {
+        let roots = self.roots.read().unwrap();
+        let Some(root) = roots
+            .iter()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+        else {
+            return Vec::new();
+        };
+        let dirs: Vec<&Path> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| *dir != root.as_path() && dir.starts_with(root))
+            .collect();
+        dirs.into_iter()
+            .rev()
+            .filter_map(|dir| self.layer(dir))
+            .collect()
+    }
+
+    /// 文件的诊断来源开关：`base` 是工作区配置，再依次应用各层的设置。
+    pub fn sources(&self, path: &Path, base: &HashMap<String, bool>) -> HashMap<String, bool> {
+        let mut sources = base.clone();
+        for layer in self.layers(path) {
+            sources.extend(
+                layer
+                    .diagnostics
+                    .sources
+                    .iter()
+                    .map(|(source, enabled)| (source.clone(), *enabled)),
+            );
+        }
+        sources
+    }
+
+    /// 各层为文件声明的 clang-tidy 规则。
+    pub fn tidy_rules(&self, path: &Path) -> Vec<TidyRule> {
+        self.layers(path)
+            .iter()
+            .flat_map(|layer| layer.tidy.iter().cloned())
+            .collect()
+    }
+
+    /// 读取目录中的配置文件，文件没有变化时使用缓存。无法解析的文件被忽略并记录警告。
+    fn layer(&self, dir: &Path) -> Option<Arc<DirectoryLayer>> {
+        let file = dir.join(CONFIG_FILE_NAME);
+        let Some(modified) = std::fs::metadata(&file)
+            .ok()
+            .filter(|metadata| metadata.is_file())
+            .and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())))
+        else {
+            self.layers.remove(dir);
+            return None;
+        };
+        if let Some(cached) = self.layers.get(dir)
+            && cached.0 == modified
+        {
+            return Some(Arc::clone(&cached.1));
+        }
+        let layer = std::fs::read_to_string(&file)
+            .with_context(|| format!("无法读取 {}", file.display()))
+            .and_then(|text| DirectoryLayer::parse(&text, dir));
+        match layer {
+            Ok(layer) => {
+                debug!("加载目录配置 {}", file.display());
+                let layer = Arc::new(layer);
+                self.layers
+                    .insert(dir.to_path_buf(), (modified, Arc::clone(&layer)));
+                Some(layer)
+            }
+            Err(e) => {
+                warn!("忽略无效的目录配置 {}: {:?}", file.display(), e);
+                self.layers.remove(dir);
+                None
+            }
+        }
+    }
+}
@@ -1,23 +1,115 @@
-﻿//! # 调度器模块
+//! # 调度器模块
 //!
 //! 这个模块实现了消息调度器，用于在前端（VSCode）和后端（clangd）之间分发和处理 LSP 消息。
 //! 它支持注册自定义处理器来拦截和修改特定类型的消息。
 
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
 use dashmap::DashMap;
-use futures::future::BoxFuture;
+use futures::future::{BoxFuture, join_all};
+use log::{debug, info, warn};
 use serde_json::{Value, json};
-use std::collections::HashMap;
-use tokio::sync::RwLock;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use tokio::sync::mpsc::UnboundedSender;
-use tower_lsp::lsp_types::request;
-use tower_lsp::lsp_types::notification;
+use tokio::sync::{oneshot, watch};
+use tower_lsp::lsp_types::notification::{self, Notification};
+use tower_lsp::lsp_types::request::{self, Request, Shutdown};
+use tower_lsp::lsp_types::{
+    ColorPresentationParams, DidChangeWatchedFilesParams, DidChangeWorkspaceFoldersParams,
+    InlineValueParams, MessageType, Range, TextDocumentPositionParams, Url,
+};
+
+use crate::backend_log::BackendLog;
+use crate::cache::KnownWorkspaces;
+use crate::capabilities::BackendCapabilities;
+use crate::clangd_flags;
+use crate::cmake;
+use crate::colors;
+use crate::commands;
+use crate::compile_flags::{self, CompileFlags};
+use crate::completion_sources::{self, CompletionProvider, CompletionSources};
+use crate::config::Config;
+use crate::content_modified::{self, VersionCheck};
+use crate::crash_report::{self, CrashReports};
+use crate::diagnostic_sources::{self, DiagnosticSources};
+use crate::diagnostics::{self, DiagnosticsStore};
+use crate::directory_config::DirectoryConfigs;
+use crate::document_highlight::HighlightFallback;
+use crate::document_observer::{DocumentEvent, DocumentObserver};
+use crate::document_store::{Document, DocumentStore};
+use crate::embedded::EmbeddedDocuments;
+use crate::events::ProxyEvent;
+use crate::fallback::Cursor;
+use crate::features::{self, Feature, FeatureSwitches};
+use crate::file_status::{self, FileStatus};
+use crate::file_watcher::FileWatcher;
+use crate::fixits;
+use crate::handlers::{self, HandlerCtx, HandlerTable, MethodPattern, Verdict};
+use crate::health::Health;
+use crate::history::RequestHistory;
+use crate::include_check::{self, IncludeCheck};
+use crate::include_policy::IncludePolicy;
+use crate::index_progress::{self, IndexProgress, Step};
+use crate::inline_values;
+use crate::keywords::Keywords;
+use crate::lanes::DocumentLanes;
+use crate::languages::Languages;
+use crate::lifecycle::{QueueDepth, RequestLifecycle, RequestObserver};
+use crate::message::Message;
+use crate::message_throttle::MessageThrottle;
+use crate::metrics::Metrics;
+use crate::modules::{self, Modules};
+use crate::notebooks::{self, Notebooks};
+use crate::prefetch::{self, CacheKey, Prefetcher};
+use crate::rate_limit::RateLimits;
+use crate::reload::{BackendLaunch, Changes, ConfigSource};
+use crate::rename;
+use crate::replay::RequestJournal;
+use crate::responses::{Resolution, ResponseTracker};
+use crate::session::SessionState;
+use crate::shadow::Shadow;
+use crate::shard::{self, Route, Shard, ShardDiagnostics};
+use crate::signature_help::SignatureFallback;
+use crate::size_limit::SizeLimit;
+use crate::slow_requests::SlowRequests;
+use crate::snippets::Snippets;
+use crate::spellcheck::{self, Spellcheck};
+use crate::state::{self, ProxyState};
+use crate::stats::SessionStats;
+use crate::supervisor;
+use crate::symbol_index::{self, SymbolIndex};
+use crate::telemetry::Telemetry;
+use crate::tidy_policy::TidyPolicy;
+use crate::todos::TodoScanner;
+use crate::trace::{Direction, MessageTrace};
+use crate::validate::{self, ValidateMode};
+use crate::virtual_documents::{Layouts, VirtualDocuments};
+use crate::warmup::Warmup;
+use crate::word_completion::WordCompletion;
+use crate::workspace::WorkspaceFolders;
 
 /// 调度器函数类型别名。
 ///
-/// 这个类型表示一个异步处理器函数，它接收一个 JSON 值和一个发送器，
-/// 返回一个表示操作结果的 `BoxFuture`。
-type DispatcherFn = fn(Value, UnboundedSender<String>) -> BoxFuture<'static, Result<()>>;
+/// 这个类型表示一个异步处理器函数，它接收一个 JSON 值和处理器上下文，
+/// 返回一个包含处理结果的 `BoxFuture`。
+pub type DispatcherFn = for<'a> fn(Value, HandlerCtx<'a>) -> BoxFuture<'a, Result<Verdict>>;
+
+/// 类型化注册方法使用的优先级。
+pub const DEFAULT_HANDLER_PRIORITY: i32 = 0;
+
+/// 代理主动向后端发起的请求等待响应的最长时间。
+const INTERNAL_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 退出时等待后端响应 `shutdown` 的最长时间。
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 用户停止操作多久之后开始预取。
+const PREFETCH_IDLE_DELAY: Duration = Duration::from_millis(300);
+
+/// 文档停止变化多久之后运行代理自己的检查。
+const LOCAL_CHECK_DELAY: Duration = Duration::from_millis(500);
 
 /// 消息调度器结构体。
 ///
@@ -26,12 +118,117 @@ type DispatcherFn = fn(Value, UnboundedSender<String>) -> BoxFuture<'static, Res
 /// - 处理传入的消息
 /// - 转发未处理的消息
 /// - 管理待处理的请求
+/// - 在多个后端分片之间路由消息
 pub struct Dispatcher {
-    handlers_from_frontend: RwLock<HashMap<String, DispatcherFn>>,
-    handlers_from_backend: RwLock<HashMap<String, DispatcherFn>>,
-    backend_sender: UnboundedSender<String>,
-    frontend_sender: UnboundedSender<String>,
-    pending_requests: DashMap<u64, String>,
+    /// 处理器表只在调度器放进 `Arc` 之前注册，之后只读，查找不需要加锁
+    handlers_from_frontend: HandlerTable,
+    handlers_from_backend: HandlerTable,
+    /// 注册的文档观察者，与处理器表一样只在放进 `Arc` 之前注册
+    document_observers: Vec<Arc<dyn DocumentObserver>>,
+    shards: Vec<Shard>,
+    frontend_sender: UnboundedSender<Message>,
+    /// 转发给后端、等待响应的前端请求
+    responses: ResponseTracker,
+    /// 等待响应的只读请求，后端崩溃后重新发给新的后端
+    journal: RequestJournal,
+    /// 配置的方法的请求发出时文档的版本，响应到达时文档已被修改则以 ContentModified 应答
+    version_check: VersionCheck,
+    /// 发给前端的消息的大小上限
+    size_limit: SizeLimit,
+    /// 按方法和文档的请求速率限制，重新加载配置时替换规则
+    rate_limits: RateLimits,
+    /// 后端重复发送的 showMessage 和 logMessage 的去重
+    message_throttle: MessageThrottle,
+    /// 代理主动发起的请求：请求 id → 等待响应的通道
+    internal_requests: DashMap<String, oneshot::Sender<Value>>,
+    /// 非默认分片发起的请求：转发给前端时使用的 id → (分片, 原始 id)
+    shard_requests: DashMap<String, (usize, Value)>,
+    /// 各分片对同一个文件发布的诊断，合并后交给前端
+    shard_diagnostics: ShardDiagnostics,
+    request_counter: AtomicU64,
+    symbol_index: Arc<SymbolIndex>,
+    file_watcher: Arc<FileWatcher>,
+    workspace: WorkspaceFolders,
+    /// 打开的文档，与补全的单词来源共享
+    documents: Arc<DocumentStore>,
+    /// 前端发送的 initialize 参数（改写之后），备用后端用它完成初始化
+    initialize_params: watch::Sender<Option<Value>>,
+    warmup: Warmup,
+    prefetcher: Prefetcher,
+    /// clang-tidy 策略，重新加载配置时替换
+    tidy_policy: RwLock<TidyPolicy>,
+    /// 子目录中的 `.codefuse.toml`，与工作区配置合并
+    directory_configs: Arc<DirectoryConfigs>,
+    include_policy: IncludePolicy,
+    trace: MessageTrace,
+    diagnostics: DiagnosticsStore,
+    /// 各个来源的诊断，合并后发给前端
+    diagnostic_sources: DiagnosticSources,
+    /// 注释和字符串字面量的拼写检查，也是一个诊断来源
+    spellcheck: Spellcheck,
+    /// 找不到的头文件和包含循环的检查，也是一个诊断来源
+    include_check: IncludeCheck,
+    /// 注释中的 TODO 标记，显示为代码透镜
+    todos: TodoScanner,
+    /// 打开的笔记本，代码单元拼成交给后端的虚拟文档
+    notebooks: Notebooks,
+    /// 按 `[[embedded]]` 规则打开的宿主文件，其中的 C/C++ 代码作为虚拟文档交给后端
+    embedded: EmbeddedDocuments,
+    /// 改正编辑器给出的语言 id，按语言选择分片
+    languages: Languages,
+    /// C++20 模块接口单元的编译参数、构建进度和诊断分组
+    modules: Modules,
+    /// 没有编译数据库的工作区中由代理生成的编译参数
+    compile_flags: Arc<CompileFlags>,
+    /// 换成虚拟文档位置、等待响应的请求
+    virtual_documents: VirtualDocuments,
+    /// 编辑器重新连接时恢复会话所需的状态
+    session: SessionState,
+    /// 每次请求重启后端时加一，各分片的监管者订阅它
+    restart: watch::Sender<u64>,
+    /// 重新加载的后端配置，监管者在重启后端时使用
+    backend_launch: watch::Sender<Option<BackendLaunch>>,
+    /// 用户允许加入 `--query-driver` 的编译器，重新加载配置后仍然保留
+    query_drivers: RwLock<Vec<String>>,
+    /// 处理前端消息的并发上限，重新加载配置时修改
+    handler_limit: watch::Sender<usize>,
+    /// 配置文件和选中的配置档，切换配置档时从这里重新加载
+    config_source: Option<Arc<ConfigSource>>,
+    validation: Option<ValidateMode>,
+    metrics: Arc<Metrics>,
+    lanes: DocumentLanes,
+    health: Health,
+    /// 正在进行的前端请求，应答后交给遥测、慢请求日志、会话统计和请求历史
+    lifecycle: RequestLifecycle,
+    telemetry: Arc<Telemetry>,
+    slow_requests: SlowRequests,
+    stats: SessionStats,
+    /// 最近的前端请求，`codefuse/history` 查询
+    history: RequestHistory,
+    /// 各分片后端最近的标准错误输出，`codefuse/backendLog` 查询，也写入崩溃报告
+    backend_log: BackendLog,
+    crash_reports: CrashReports,
+    index_progress: IndexProgress,
+    file_status: FileStatus,
+    /// 镜像只读请求的影子后端，没有配置时为 `None`
+    shadow: Option<Arc<Shadow>>,
+    /// 代理内的补全来源，与处理器表一样只在放进 `Arc` 之前注册
+    completion_sources: CompletionSources,
+    /// 后端没有应答签名帮助时合成的签名
+    signature_help: SignatureFallback,
+    /// 编辑器设置中 `codefuse.*` 关闭的功能
+    features: FeatureSwitches,
+    /// 跨会话保存的状态
+    state: ProxyState,
+    /// 后端超时时按文本匹配的文档高亮
+    document_highlight: HighlightFallback,
+    capabilities: BackendCapabilities,
+    config: Config,
+    drop_unexpected_responses: bool,
+    /// 后端不提供颜色时由代理查找颜色字面量
+    color_fallback: bool,
+    /// 以 `telemetry/event` 通知编辑器代理运行状态的变化
+    events: bool,
 }
 
 impl Dispatcher {
@@ -46,88 +243,352 @@ impl Dispatcher {
     ///
     /// 返回初始化后的 `Dispatcher` 实例
     pub fn new(
-        backend_sender: UnboundedSender<String>,
-        frontend_sender: UnboundedSender<String>,
+        backend_sender: UnboundedSender<Message>,
+        frontend_sender: UnboundedSender<Message>,
     ) -> Self {
-        Self {
-            handlers_from_frontend: RwLock::new(HashMap::new()),
-            handlers_from_backend: RwLock::new(HashMap::new()),
-            backend_sender,
+        Self::with_shards(vec![Shard::default_shard(backend_sender)], frontend_sender)
+    }
+
+    /// 创建连接多个后端分片的调度器实例。
+    ///
+    /// # 参数
+    ///
+    /// * `shards` - 后端分片，第一个是默认分片，负责不属于任何子树的文档
+    /// * `frontend_sender` - 向前端发送消息的通道发送器
+    ///
+    /// # 返回
+    ///
+    /// 返回初始化后的 `Dispatcher` 实例
+    ///
+    /// # Panics
+    ///
+    /// 如果 `shards` 为空则 panic
+    pub fn with_shards(shards: Vec<Shard>, frontend_sender: UnboundedSender<Message>) -> Self {
+        assert!(!shards.is_empty(), "至少需要一个后端");
+        let directory_configs = Arc::new(DirectoryConfigs::default());
+        let mut dispatcher = Self {
+            handlers_from_frontend: HandlerTable::default(),
+            handlers_from_backend: HandlerTable::default(),
+            document_observers: Vec::new(),
+            file_watcher: Arc::new(FileWatcher::for_shards(&shards)),
+            health: Health::new(shards.iter().map(|shard| shard.name.clone())),
+            shards,
             frontend_sender,
-            pending_requests: DashMap::new(),
+            responses: ResponseTracker::new(),
+            journal: RequestJournal::new(),
+            version_check: VersionCheck::default(),
+            size_limit: SizeLimit::default(),
+            rate_limits: RateLimits::default(),
+            message_throttle: MessageThrottle::default(),
+            internal_requests: DashMap::new(),
+            shard_requests: DashMap::new(),
+            shard_diagnostics: ShardDiagnostics::new(),
+            request_counter: AtomicU64::new(1),
+            symbol_index: Arc::new(SymbolIndex::new("ctags")),
+            workspace: WorkspaceFolders::new(),
+            documents: Arc::new(DocumentStore::new()),
+            initialize_params: watch::channel(None).0,
+            warmup: Warmup::new(Default::default()),
+            prefetcher: Prefetcher::new(Default::default()),
+            tidy_policy: RwLock::new(
+                TidyPolicy::default().with_directories(Arc::clone(&directory_configs)),
+            ),
+            include_policy: IncludePolicy::default(),
+            trace: MessageTrace::default(),
+            diagnostics: DiagnosticsStore::new(),
+            diagnostic_sources: DiagnosticSources::default()
+                .with_directories(Arc::clone(&directory_configs)),
+            directory_configs,
+            spellcheck: Spellcheck::default(),
+            include_check: IncludeCheck::default(),
+            todos: TodoScanner::default(),
+            notebooks: Notebooks::default(),
+            embedded: EmbeddedDocuments::default(),
+            languages: Languages::default(),
+            modules: Modules::default(),
+            compile_flags: Arc::new(CompileFlags::default()),
+            virtual_documents: VirtualDocuments::new(),
+            session: SessionState::new(),
+            restart: watch::channel(0).0,
+            backend_launch: watch::channel(None).0,
+            query_drivers: RwLock::new(Vec::new()),
+            handler_limit: watch::channel(Config::default().concurrency.max_handlers).0,
+            config_source: None,
+            validation: None,
+            metrics: Arc::new(Metrics::new()),
+            lanes: DocumentLanes::new(),
+            lifecycle: RequestLifecycle::new(false),
+            telemetry: Arc::new(Telemetry::default()),
+            slow_requests: SlowRequests::default(),
+            stats: SessionStats::new(),
+            history: RequestHistory::new(&Default::default()),
+            backend_log: BackendLog::default(),
+            crash_reports: CrashReports::default(),
+            index_progress: IndexProgress::default(),
+            file_status: FileStatus::default(),
+            shadow: None,
+            completion_sources: CompletionSources::default(),
+            signature_help: SignatureFallback::default(),
+            features: FeatureSwitches::new(),
+            state: ProxyState::default(),
+            document_highlight: HighlightFallback::default(),
+            capabilities: BackendCapabilities::new(),
+            config: Config::default(),
+            drop_unexpected_responses: false,
+            color_fallback: false,
+            events: false,
+        };
+        handlers::register_builtin(&mut dispatcher);
+        dispatcher
+    }
+
+    /// 使用配置文件中的设置，替换默认设置。
+    ///
+    /// # 参数
+    ///
+    /// * `config` - 代理配置
+    ///
+    /// # 返回
+    ///
+    /// 返回使用了新配置的 `Dispatcher` 实例
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config.clone();
+        self.warmup = Warmup::new(config.warmup);
+        self.prefetcher = Prefetcher::new(config.prefetch);
+        self.tidy_policy = RwLock::new(
+            TidyPolicy::new(&config.tidy).with_directories(Arc::clone(&self.directory_configs)),
+        );
+        self.include_policy = IncludePolicy::new(&config.includes);
+        self.diagnostic_sources = DiagnosticSources::new(&config.diagnostics)
+            .with_directories(Arc::clone(&self.directory_configs));
+        self.spellcheck = Spellcheck::new(&config.spellcheck);
+        self.include_check = IncludeCheck::new(config.includes.check);
+        self.todos = TodoScanner::new(&config.todos);
+        self.notebooks = Notebooks::new(&config.notebooks);
+        self.embedded = EmbeddedDocuments::new(&config.embedded);
+        self.languages = Languages::new(&config.languages);
+        self.modules = Modules::new(&config.modules);
+        self.compile_flags = Arc::new(CompileFlags::new(&config.flags));
+        self.drop_unexpected_responses = config.protocol.drop_unexpected_responses;
+        self.color_fallback = config.colors.fallback;
+        self.events = config.telemetry.events;
+        self.version_check = VersionCheck::new(&config.protocol.content_modified);
+        self.size_limit = SizeLimit::new(&config.protocol);
+        self.rate_limits = RateLimits::new(&config.rate_limits);
+        self.message_throttle = MessageThrottle::new(&config.messages);
+        self.telemetry = Arc::new(Telemetry::new(config.telemetry.endpoint.is_some()));
+        self.slow_requests =
+            SlowRequests::new(config.logging.slow_request_ms.map(Duration::from_millis));
+        self.handler_limit = watch::channel(config.concurrency.max_handlers).0;
+        self.history = RequestHistory::new(&config.history);
+        self.lifecycle = RequestLifecycle::new(self.history.is_enabled());
+        self.crash_reports = CrashReports::new(&config.crash_reports);
+        self.index_progress = IndexProgress::new(&config.index_progress);
+        self.file_status = FileStatus::new(&config.file_status);
+        self.completion_sources.set_config(&config.completion);
+        let snippets = Snippets::new(&config.snippets);
+        if !snippets.is_empty() {
+            self.completion_sources.register(Arc::new(snippets));
+        }
+        if config.completion.words {
+            let words = WordCompletion::new(Arc::clone(&self.documents));
+            self.completion_sources.register(Arc::new(words));
         }
+        if config.completion.keywords {
+            self.completion_sources.register(Arc::new(Keywords));
+        }
+        self.signature_help = SignatureFallback::new(&config.signature_help);
+        self.document_highlight = HighlightFallback::new(&config.document_highlight);
+        self.state = ProxyState::new(&config.state);
+        self
+    }
+
+    /// 记录配置的来源，之后可以通过 `codefuse/setProfile` 切换配置档。
+    ///
+    /// # 参数
+    ///
+    /// * `source` - 配置文件和选中的配置档
+    pub fn with_config_source(mut self, source: Arc<ConfigSource>) -> Self {
+        self.config_source = Some(source);
+        self
+    }
+
+    /// 把只读请求镜像给影子后端。
+    ///
+    /// # 参数
+    ///
+    /// * `shadow` - 运行中的影子后端，`None` 表示不镜像
+    pub fn with_shadow(mut self, shadow: Option<Arc<Shadow>>) -> Self {
+        self.shadow = shadow;
+        self
+    }
+
+    /// 开启消息校验，按 lsp_types 检查经过代理的消息。
+    ///
+    /// # 参数
+    ///
+    /// * `mode` - 校验模式，`None` 表示不校验
+    pub fn with_validation(mut self, mode: Option<ValidateMode>) -> Self {
+        self.validation = mode;
+        self
     }
 
     /// 注册来自前端的处理器。
     ///
     /// 这个方法允许为特定的 LSP 方法注册异步处理器函数。
     /// 当从前端接收到匹配该方法的消息时，将调用注册的处理器。
+    /// 注册只能在调度器放进 `Arc` 之前进行，运行期间处理器表是只读的。
+    /// 同一方法可以注册多个处理器，使用 [`DEFAULT_HANDLER_PRIORITY`]，按注册顺序执行。
     ///
     /// # 参数
     ///
-    /// * `handler` - 处理函数，接收消息和后端发送器
+    /// * `handler` - 处理函数，接收消息和处理器上下文
     ///
     /// # 类型参数
     ///
     /// * `T` - LSP 请求类型，必须实现 Request trait
     #[allow(dead_code)]
-    pub async fn register_req_from_frontend<T>(&self, handler: DispatcherFn)
+    pub fn register_req_from_frontend<T>(&mut self, handler: DispatcherFn)
     where
         T: request::Request,
     {
-        let method = T::METHOD;
-        self.handlers_from_frontend
-            .write()
-            .await
-            .insert(method.to_string(), handler);
+        self.register_handler(
+            Direction::Frontend,
+            T::METHOD,
+            DEFAULT_HANDLER_PRIORITY,
+            handler,
+        );
     }
 
     #[allow(dead_code)]
-    pub async fn register_notify_from_frontend<T>(&self, handler: DispatcherFn)
+    pub fn register_notify_from_frontend<T>(&mut self, handler: DispatcherFn)
     where
         T: notification::Notification,
     {
-        let method = T::METHOD;
-        self.handlers_from_frontend
-            .write()
-            .await
-            .insert(method.to_string(), handler);
+        self.register_handler(
+            Direction::Frontend,
+            T::METHOD,
+            DEFAULT_HANDLER_PRIORITY,
+            handler,
+        );
     }
 
     /// 注册来自后端的处理器。
     ///
     /// 这个方法允许为特定的 LSP 方法注册异步处理器函数。
     /// 当从后端接收到匹配该方法的消息时，将调用注册的处理器。
+    /// 注册只能在调度器放进 `Arc` 之前进行，运行期间处理器表是只读的。
+    /// 同一方法可以注册多个处理器，使用 [`DEFAULT_HANDLER_PRIORITY`]，按注册顺序执行。
     ///
     /// # 参数
     ///
-    /// * `handler` - 处理函数，接收消息和前端发送器
+    /// * `handler` - 处理函数，接收消息和处理器上下文
     ///
     /// # 类型参数
     ///
     /// * `T` - LSP 请求类型，必须实现 Request trait
     #[allow(dead_code)]
-    pub async fn register_resp_from_backend<T>(&self, handler: DispatcherFn)
+    pub fn register_resp_from_backend<T>(&mut self, handler: DispatcherFn)
     where
         T: request::Request,
     {
-        let method = T::METHOD;
-        self.handlers_from_backend
-            .write()
-            .await
-            .insert(method.to_string(), handler);
+        self.register_handler(
+            Direction::Backend,
+            T::METHOD,
+            DEFAULT_HANDLER_PRIORITY,
+            handler,
+        );
     }
 
     #[allow(dead_code)]
-    pub async fn register_notify_from_backend<T>(&self, handler: DispatcherFn)
+    pub fn register_notify_from_backend<T>(&mut self, handler: DispatcherFn)
     where
         T: notification::Notification,
     {
-        let method = T::METHOD;
-        self.handlers_from_backend
-            .write()
-            .await
-            .insert(method.to_string(), handler);
+        self.register_handler(
+            Direction::Backend,
+            T::METHOD,
+            DEFAULT_HANDLER_PRIORITY,
+            handler,
+        );
+    }
+
+    /// 以指定的优先级为方法注册处理器。
+    ///
+    /// 同一方法的处理器按优先级从高到低执行，优先级相同时按注册顺序执行。
+    /// 处理器返回 [`Verdict::Continue`] 时把消息交给下一个处理器，最后一个处理器之后照常转发；
+    /// 返回 [`Verdict::Respond`] 或 [`Verdict::Drop`] 时之后的处理器不再执行。
+    ///
+    /// # 参数
+    ///
+    /// * `direction` - 消息来自前端还是后端
+    /// * `method` - LSP 方法；后端的响应使用对应请求的方法
+    /// * `priority` - 优先级，越大越先执行
+    /// * `handler` - 处理函数，接收消息和处理器上下文
+    pub fn register_handler(
+        &mut self,
+        direction: Direction,
+        method: &str,
+        priority: i32,
+        handler: DispatcherFn,
+    ) {
+        let pattern = MethodPattern::Exact(method.to_string());
+        self.register_pattern(direction, pattern, priority, handler);
+    }
+
+    /// 为匹配模式的所有方法注册处理器，用于 URI 改写这类对所有方法都生效的功能。
+    ///
+    /// 模式处理器与完整方法名的处理器一起按优先级排序，规则与 [`Dispatcher::register_handler`] 相同。
+    ///
+    /// # 参数
+    ///
+    /// * `direction` - 消息来自前端还是后端
+    /// * `pattern` - 方法模式，见 [`MethodPattern::parse`]
+    /// * `priority` - 优先级，越大越先执行
+    /// * `handler` - 处理函数，接收消息和处理器上下文
+    pub fn register_pattern(
+        &mut self,
+        direction: Direction,
+        pattern: MethodPattern,
+        priority: i32,
+        handler: DispatcherFn,
+    ) {
+        let handlers = match direction {
+            Direction::Frontend => &mut self.handlers_from_frontend,
+            Direction::Backend => &mut self.handlers_from_backend,
+        };
+        handlers.insert(pattern, priority, handler);
+    }
+
+    /// 订阅前端的文档生命周期通知。
+    ///
+    /// 观察者在文档存储、预取缓存和诊断存储之后按注册顺序调用，调用时文档存储已经是最新内容。
+    /// 注册只能在调度器放进 `Arc` 之前进行。
+    pub fn register_document_observer(&mut self, observer: Arc<dyn DocumentObserver>) {
+        self.document_observers.push(observer);
+    }
+
+    /// 注册代理内的补全来源，补全项追加在后端的结果之后。
+    ///
+    /// 注册只能在调度器放进 `Arc` 之前进行。
+    pub fn register_completion_provider(&mut self, provider: Arc<dyn CompletionProvider>) {
+        self.completion_sources.register(provider);
+    }
+
+    /// 依次执行方法的处理器，直到某个处理器不再继续。没有处理器时消息原样继续。
+    async fn run_handlers(
+        self: &Arc<Self>,
+        handlers: Vec<DispatcherFn>,
+        shard: usize,
+        mut rpc: Value,
+    ) -> Result<Verdict> {
+        for handler in handlers {
+            match handler(rpc, HandlerCtx::new(self, shard)).await? {
+                Verdict::Continue(next) => rpc = next,
+                verdict => return Ok(verdict),
+            }
+        }
+        Ok(Verdict::Continue(rpc))
     }
 
     /// 处理来自前端的消息。
@@ -143,62 +604,2363 @@ impl Dispatcher {
     /// # 返回
     ///
     /// 返回 `Result<()>`，表示处理是否成功
-    pub async fn handle_from_frontend(&self, rpc: Value) -> Result<()> {
-        // 如果是请求（有 id 和 method），记录到字典
-        if let (Some(id_val), Some(method_val)) = (rpc.get("id"), rpc.get("method"))
-            && let (Some(id), Some(method)) = (id_val.as_u64(), method_val.as_str()) {
-                self.pending_requests.insert(id, method.to_string());
+    pub async fn handle_from_frontend(self: &Arc<Self>, mut rpc: Value) -> Result<()> {
+        self.trace.record(Direction::Frontend, None, &rpc);
+        let mut method = rpc
+            .get("method")
+            .and_then(|m| m.as_str())
+            .unwrap_or("")
+            .to_string();
+        if !method.is_empty() {
+            self.request_started(&rpc, &method);
+        }
+
+        // 严格校验模式下，无法解析的请求直接以 InvalidParams 应答
+        if let Some(violation) = self.validate(&method, &rpc)
+            && rpc.get("id").is_some()
+        {
+            let error = json!({
+                "jsonrpc": "2.0",
+                "id": rpc["id"],
+                "error": {"code": -32602, "message": violation.report()},
+            });
+            return self.send_to_frontend(&error);
+        }
+
+        // 取消的请求不再重放
+        if method == notification::Cancel::METHOD
+            && let Some(id) = rpc.pointer("/params/id")
+        {
+            self.journal.forget(id);
+        }
+
+        // 宿主文件的同步通知换成其中代码的虚拟文档的通知
+        if let Some(params) = rpc.get("params")
+            && self.embedded.is_host_sync(&method, params)
+        {
+            let Some(notification) = self.embedded.to_virtual_document(&method, params) else {
+                return Ok(());
+            };
+            rpc = notification;
+        }
+        // 笔记本的同步通知换成虚拟文档的文本同步通知，之后照常处理
+        if self.notebooks.is_enabled() && notebooks::SYNC_METHODS.contains(&method.as_str()) {
+            let params = rpc.get("params").cloned().unwrap_or(json!(null));
+            let Some(notification) = self.notebooks.to_text_document(&method, &params) else {
+                return Ok(());
+            };
+            method = notification["method"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            rpc = notification;
+        }
+        // 针对笔记本单元和宿主文件的请求换成虚拟文档中的位置
+        if rpc.get("id").is_some()
+            && let Some(uri) = rpc
+                .pointer("/params/textDocument/uri")
+                .and_then(|uri| uri.as_str())
+                .and_then(|uri| Url::parse(uri).ok())
+            && let Some((virtual_uri, start)) = self
+                .notebooks
+                .locate(&uri)
+                .or_else(|| self.embedded.locate(&uri))
+        {
+            self.virtual_documents
+                .to_virtual(&mut rpc, &virtual_uri, start);
+        }
+        if method == notification::DidOpenTextDocument::METHOD {
+            self.languages.apply(&mut rpc);
+        }
+
+        // 保留会话时，编辑器的 shutdown 和重新连接后的初始化由代理处理
+        if self.continue_session(&method, &rpc)? {
+            return Ok(());
+        }
+
+        if method == request::Initialize::METHOD {
+            self.on_initialize(&mut rpc);
+        } else if method == notification::DidChangeWorkspaceFolders::METHOD {
+            self.on_workspace_folders_changed(&rpc);
+        } else if method == notification::DidChangeConfiguration::METHOD {
+            self.on_configuration_changed(&rpc);
+            self.tidy_policy
+                .read()
+                .unwrap()
+                .on_configuration_changed(&mut rpc);
+        } else if let Some(params) = rpc.get("params") {
+            self.on_text_document_sync(&method, params)?;
+            if method == notification::DidCloseTextDocument::METHOD {
+                let step = self.file_status.refresh();
+                self.report_file_status(step).await?;
             }
+            if method == notification::DidOpenTextDocument::METHOD {
+                let injected = self.inject_compile_flags(params)?;
+                if self.modules.is_enabled() {
+                    self.on_module_open(params, injected)?;
+                }
+            }
+            if (self.spellcheck.is_enabled() || self.include_check.is_enabled())
+                && (method == notification::DidOpenTextDocument::METHOD
+                    || method == notification::DidChangeTextDocument::METHOD)
+            {
+                self.schedule_local_checks(params);
+            }
+        }
+
+        // 代理自己实现的命令不转发给后端
+        if method == request::ExecuteCommand::METHOD
+            && let Some(command) = rpc.pointer("/params/command").and_then(|c| c.as_str())
+            && commands::is_proxy_command(command)
+        {
+            let command = command.to_string();
+            return self.execute_proxy_command(&command, &rpc).await;
+        }
 
+        // 编辑器解析的补全项记入使用次数
+        if method == request::ResolveCompletionItem::METHOD
+            && self.state.is_enabled()
+            && let Some(label) = rpc
+                .pointer("/params/label")
+                .and_then(|label| label.as_str())
+        {
+            self.state.record_completion_use(label);
+        }
 
-        let method = rpc.get("method").and_then(|m| m.as_str()).unwrap_or("");
-        if let Some(handler) = self.handlers_from_frontend.read().await.get(method) {
-            handler(rpc, self.backend_sender.clone()).await
-        } else {
-            let message = Self::format_lsp_message(&rpc)?;
-            self.backend_sender.send(message)?;
-            Ok(())
+        // 代理内来源的补全项不需要后端解析，原样返回
+        if method == request::ResolveCompletionItem::METHOD
+            && let Some(item) = rpc
+                .get("params")
+                .filter(|item| completion_sources::source_of(item).is_some())
+        {
+            return self.respond_to_frontend(&rpc, Ok(item.clone()));
+        }
+
+        // 预取命中时直接应答，否则以这次请求的位置为中心开始新的预取
+        if self.prefetcher.is_enabled()
+            && self.features.is_enabled(Feature::Prefetch)
+            && prefetch::TRIGGER_METHODS.contains(&method.as_str())
+        {
+            if prefetch::PREFETCH_METHODS.contains(&method.as_str())
+                && let Some(id) = rpc.get("id").cloned()
+            {
+                let cached = rpc
+                    .get("params")
+                    .and_then(|params| self.prefetcher.cached(&method, params, &self.documents));
+                self.stats.prefetch(cached.is_some());
+                if let Some(result) = cached {
+                    debug!("{} 命中预取缓存", method);
+                    let response = json!({"jsonrpc": "2.0", "id": id, "result": result});
+                    let shard = match self.route(&method, &rpc) {
+                        Route::Shard(shard) => shard,
+                        Route::Broadcast | Route::FanOut => 0,
+                    };
+                    self.forward_to_frontend(shard, Some(&method), response)
+                        .await?;
+                    self.request_finished(&id, false);
+                    return Ok(());
+                }
+            }
+            self.schedule_prefetch(&rpc);
+        }
+
+        // 前端对代理所发请求的响应
+        if rpc.get("method").is_none()
+            && let Some(id) = rpc.get("id").and_then(|id| id.as_str())
+            && let Some((_, waiter)) = self.internal_requests.remove(id)
+        {
+            let _ = waiter.send(rpc);
+            return Ok(());
         }
+
+        // 前端对非默认分片所发请求的响应，还原 id 后交给原来的分片
+        if rpc.get("method").is_none()
+            && let Some(id) = rpc.get("id").and_then(|id| id.as_str())
+            && let Some((_, (shard, original_id))) = self.shard_requests.remove(id)
+        {
+            rpc["id"] = original_id;
+            self.shards[shard].sender.send(Message::new(rpc))?;
+            return Ok(());
+        }
+
+        let closed = rpc
+            .pointer("/params/textDocument/uri")
+            .and_then(|uri| uri.as_str())
+            .filter(|_| method == notification::DidCloseTextDocument::METHOD)
+            .and_then(|uri| Url::parse(uri).ok());
+        match self.route(&method, &rpc) {
+            Route::Shard(shard) => self.dispatch_to_shard(shard, &method, rpc).await?,
+            Route::Broadcast if method == notification::DidChangeWatchedFiles::METHOD => {
+                self.split_file_changes(rpc).await?
+            }
+            Route::Broadcast => {
+                for shard in 1..self.shards.len() {
+                    self.broadcast_to_shard(shard, &method, &rpc)?;
+                }
+                self.dispatch_to_shard(0, &method, rpc).await?
+            }
+            Route::FanOut => self.fan_out(&method, rpc).await?,
+        }
+
+        // 后端初始化完成后再预热最近编辑的文件
+        if method == notification::Initialized::METHOD {
+            self.warm_up()?;
+        }
+        if let Some(uri) = closed {
+            self.languages.forget(&uri);
+        }
+        Ok(())
     }
 
-    /// 处理来自后端的消息。
+    /// 处理保留会话时的 `shutdown`/`exit` 和重新连接的编辑器的初始化。
     ///
-    /// 这个方法接收来自后端的 JSON-RPC 消息，确定消息类型（响应或通知），
-    /// 检查是否有注册的处理器，如果有则调用处理器，否则将消息转发给前端。
+    /// 后端在编辑器断开后继续运行，编辑器的 `shutdown` 由代理应答，`exit` 被丢弃。重新连接的编辑器的
+    /// `initialize` 以上次的结果应答，`initialized` 之后重新注册后端动态注册的能力；它重新打开的文档内容
+    /// 没有变化时不交给后端，而是重放最近的诊断。
     ///
-    /// # 参数
+    /// # 返回
     ///
-    /// * `rpc` - 接收到的 JSON-RPC 消息
+    /// 消息已由代理处理、不再转发给后端时返回 `true`
+    fn continue_session(self: &Arc<Self>, method: &str, rpc: &Value) -> Result<bool> {
+        match method {
+            request::Shutdown::METHOD if self.session.is_kept_alive() => {
+                self.respond_to_frontend(rpc, Ok(json!(null)))?;
+            }
+            notification::Exit::METHOD if self.session.is_kept_alive() => {}
+            request::Initialize::METHOD if self.session.is_resumed() => {
+                let Some(result) = self.session.initialize_result() else {
+                    return Ok(false);
+                };
+                let roots = |params: Option<&Value>| {
+                    params.map(|p| {
+                        (
+                            p.get("rootUri").cloned(),
+                            p.get("workspaceFolders").cloned(),
+                        )
+                    })
+                };
+                if roots(self.initialize_params.borrow().as_ref()) != roots(rpc.get("params")) {
+                    warn!("重新连接的编辑器打开了不同的工作区，后端继续使用原来的工作区");
+                }
+                self.respond_to_frontend(rpc, Ok(result))?;
+            }
+            notification::Initialized::METHOD if self.session.is_resumed() => {
+                let registrations = self.session.registrations();
+                if !registrations.is_empty() {
+                    let dispatcher = Arc::clone(self);
+                    tokio::spawn(async move {
+                        let params = json!({"registrations": registrations});
+                        let registered = dispatcher
+                            .request_frontend(request::RegisterCapability::METHOD, params)
+                            .await;
+                        if let Err(e) = registered {
+                            warn!("无法向重新连接的编辑器注册能力: {:?}", e);
+                        }
+                    });
+                }
+            }
+            notification::DidOpenTextDocument::METHOD if self.session.is_resumed() => {
+                let Some(uri) = rpc
+                    .pointer("/params/textDocument/uri")
+                    .and_then(|uri| uri.as_str())
+                    .and_then(|uri| Url::parse(uri).ok())
+                else {
+                    return Ok(false);
+                };
+                let text = rpc
+                    .pointer("/params/textDocument/text")
+                    .and_then(|t| t.as_str());
+                let unchanged = self
+                    .documents
+                    .get(&uri)
+                    .is_some_and(|doc| Some(doc.text.as_str()) == text);
+                if !unchanged {
+                    return Ok(false);
+                }
+                self.on_text_document_sync(method, &rpc["params"])?;
+                let diagnostics = json!({
+                    "jsonrpc": "2.0",
+                    "method": notification::PublishDiagnostics::METHOD,
+                    "params": {
+                        "uri": uri,
+                        "version": rpc.pointer("/params/textDocument/version"),
+                        "diagnostics": self.diagnostics.get(&uri),
+                    },
+                });
+                self.send_to_frontend(&diagnostics)?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// 通知文档观察者并更新最近文件列表；用户打开预热文档时，先关闭预热文档。
+    fn on_text_document_sync(&self, method: &str, params: &Value) -> Result<()> {
+        let event = match DocumentEvent::parse(method, params) {
+            Some(Ok(event)) => event,
+            Some(Err(e)) => {
+                warn!("无法解析 {}: {}", method, e);
+                return Ok(());
+            }
+            None => return Ok(()),
+        };
+        if matches!(event, DocumentEvent::Open(_) | DocumentEvent::Change(_))
+            && let Some(close) = self.warmup.record(event.uri())
+        {
+            self.shards[self.shard_for_uri(event.uri())]
+                .sender
+                .send(Message::new(close))?;
+        }
+        let builtin: [&dyn DocumentObserver; 6] = [
+            &*self.documents,
+            &self.prefetcher,
+            &self.diagnostics,
+            &self.diagnostic_sources,
+            &self.file_status,
+            &self.rate_limits,
+        ];
+        let registered = self
+            .document_observers
+            .iter()
+            .map(|observer| observer.as_ref());
+        for observer in builtin.into_iter().chain(registered) {
+            event.notify(observer);
+        }
+        Ok(())
+    }
+
+    /// 所在工作区没有编译数据库时，为打开的文件注入 Bazel 或 `compile_flags.txt` 模板生成的编译参数。
     ///
     /// # 返回
     ///
-    /// 返回 `Result<()>`，表示处理是否成功
-    pub async fn handle_from_backend(&self, rpc: Value) -> Result<()> {
-        // 统一获取 method：如果是响应，从字典中查找；如果是通知，从消息中获取
-        let method = if let Some(id_val) = rpc.get("id") {
-            if let Some(id) = id_val.as_u64() {
-                self.pending_requests.remove(&id).map(|(_, v)| v) // 获取并移除
-            } else {
-                None
+    /// 是否注入了编译参数
+    fn inject_compile_flags(&self, params: &Value) -> Result<bool> {
+        let Some(uri) = params
+            .pointer("/textDocument/uri")
+            .and_then(|uri| uri.as_str())
+            .and_then(|uri| Url::parse(uri).ok())
+        else {
+            return Ok(false);
+        };
+        let Ok(path) = uri.to_file_path() else {
+            return Ok(false);
+        };
+        let roots = self.workspace.roots();
+        if roots
+            .iter()
+            .filter(|root| path.starts_with(root))
+            .any(|root| compile_flags::has_database(root))
+        {
+            return Ok(false);
+        }
+        let Some(command) = self.compile_flags.command_for(&path) else {
+            return Ok(false);
+        };
+        self.shards[self.shard_for_uri(&uri)]
+            .sender
+            .send(Message::new(compile_flags::database_change(
+                &path, &command,
+            )))?;
+        Ok(true)
+    }
+
+    /// 打开模块接口单元时注入编译参数，并在编辑器中显示构建进度，直到后端发布它的诊断。
+    ///
+    /// `injected` 表示已经由 [`Dispatcher::inject_compile_flags`] 注入了编译参数，此时不再注入模块的编译参数。
+    fn on_module_open(self: &Arc<Self>, params: &Value, injected: bool) -> Result<()> {
+        let Some(uri) = params
+            .pointer("/textDocument/uri")
+            .and_then(|uri| uri.as_str())
+            .and_then(|uri| Url::parse(uri).ok())
+        else {
+            return Ok(());
+        };
+        let text = params
+            .pointer("/textDocument/text")
+            .and_then(|text| text.as_str())
+            .unwrap_or_default();
+        if !modules::is_interface_unit(Path::new(uri.path()), text) {
+            return Ok(());
+        }
+        if !injected && let Some(command) = self.modules.compile_command(&uri) {
+            self.shards[self.shard_for_uri(&uri)]
+                .sender
+                .send(Message::new(command))?;
+        }
+
+        let work_done_progress = self
+            .initialize_params
+            .borrow()
+            .as_ref()
+            .is_some_and(|params| {
+                params
+                    .pointer("/capabilities/window/workDoneProgress")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false)
+            });
+        if !work_done_progress {
+            return Ok(());
+        }
+        let token = self.modules.start_build(&uri);
+        let title = match modules::module_name(text) {
+            Some(name) => format!("构建模块 {}", name),
+            None => "构建模块".to_string(),
+        };
+        let dispatcher = Arc::clone(self);
+        tokio::spawn(async move {
+            let create = dispatcher
+                .request_frontend(
+                    request::WorkDoneProgressCreate::METHOD,
+                    json!({"token": token}),
+                )
+                .await;
+            if let Err(e) = create {
+                debug!("无法创建模块构建进度: {:?}", e);
+                return;
+            }
+            if dispatcher.modules.begin_build(&uri, &token) {
+                let _ = dispatcher.send_to_frontend(&json!({
+                    "jsonrpc": "2.0",
+                    "method": notification::Progress::METHOD,
+                    "params": {"token": token, "value": {"kind": "begin", "title": title}},
+                }));
             }
+        });
+        Ok(())
+    }
+
+    /// 与模块有关的诊断归入 `modules` 来源，后端发布接口单元的诊断时结束它的构建进度。
+    fn group_module_diagnostics(&self, rpc: &mut Value) -> Result<()> {
+        let Some(uri) = rpc
+            .pointer("/params/uri")
+            .and_then(|uri| uri.as_str())
+            .and_then(|uri| Url::parse(uri).ok())
+        else {
+            return Ok(());
+        };
+        if let Some(token) = self.modules.finish_build(&uri) {
+            self.send_to_frontend(&json!({
+                "jsonrpc": "2.0",
+                "method": notification::Progress::METHOD,
+                "params": {"token": token, "value": {"kind": "end"}},
+            }))?;
+        }
+        let mut grouped = rpc.clone();
+        grouped["params"]["diagnostics"] = json!(self.modules.take_diagnostics(rpc));
+        self.diagnostic_sources.merge(modules::SOURCE, &mut grouped);
+        Ok(())
+    }
+
+    /// 请求所在的文档。
+    fn document_of(&self, rpc: &Value) -> Option<Document> {
+        rpc.pointer("/params/textDocument/uri")
+            .and_then(|uri| uri.as_str())
+            .and_then(|uri| Url::parse(uri).ok())
+            .and_then(|uri| self.documents.get(&uri))
+    }
+
+    /// 后端不提供颜色时，以本地查找的颜色字面量应答 `textDocument/documentColor`
+    /// 和 `textDocument/colorPresentation`；应该交给后端时返回 `None`。
+    pub(crate) fn local_colors(&self, method: &str, rpc: &Value) -> Option<Result<Value>> {
+        if !self.color_fallback
+            || !self.features.is_enabled(Feature::Fallbacks)
+            || self.capabilities.supports(method) == Some(true)
+        {
+            return None;
+        }
+        let Some(doc) = self.document_of(rpc) else {
+            return Some(Ok(json!([])));
+        };
+        if method == request::DocumentColor::METHOD {
+            return Some(Ok(json!(colors::document_colors(&doc.text))));
+        }
+        let params = rpc.get("params").cloned().unwrap_or_default();
+        let result = serde_json::from_value::<ColorPresentationParams>(params)
+            .map(|params| {
+                json!(colors::presentations(
+                    &doc.text,
+                    &params.color,
+                    params.range
+                ))
+            })
+            .map_err(Into::into);
+        Some(result)
+    }
+
+    /// clangd 不提供行内值，以停止的函数中参数和局部变量的引用应答 `textDocument/inlineValue`；
+    /// 后端提供时返回 `None`。
+    pub(crate) fn local_inline_values(&self, rpc: &Value) -> Option<Result<Value>> {
+        let method = request::InlineValueRequest::METHOD;
+        if self.capabilities.supports(method) == Some(true) {
+            return None;
+        }
+        let params = rpc.get("params").cloned().unwrap_or_default();
+        let result = serde_json::from_value::<InlineValueParams>(params)
+            .map_err(Into::into)
+            .map(
+                |params| match self.documents.get(&params.text_document.uri) {
+                    Some(doc) => json!(inline_values::inline_values(&doc.text, &params)),
+                    None => json!([]),
+                },
+            );
+        Some(result)
+    }
+
+    /// 后端不提供代码透镜时，TODO 标记的代码透镜；应该交给后端时返回 `None`。
+    pub(crate) fn local_code_lenses(&self, rpc: &Value) -> Option<Value> {
+        if !self.todos.is_code_lens_enabled()
+            || self.capabilities.supports(request::CodeLensRequest::METHOD) == Some(true)
+        {
+            return None;
+        }
+        let lenses = self
+            .document_of(rpc)
+            .map(|doc| self.todos.code_lenses(&doc.uri, &doc.text))
+            .unwrap_or_default();
+        Some(json!(lenses))
+    }
+
+    /// 后端不提供或者配置为代答文档链接时，`#include` 的文档链接；应该交给后端时返回 `None`。
+    pub(crate) fn local_document_links(&self, rpc: &Value) -> Option<Value> {
+        let method = request::DocumentLinkRequest::METHOD;
+        let stubbed = self
+            .config
+            .protocol
+            .stub_methods
+            .iter()
+            .any(|m| m == method);
+        if self.capabilities.supports(method) == Some(true) && !stubbed {
+            return None;
+        }
+        let links = self
+            .document_of(rpc)
+            .and_then(|doc| {
+                let path = doc.uri.to_file_path().ok()?;
+                Some(
+                    self.include_check
+                        .links(&path, &doc.text, &self.workspace.roots()),
+                )
+            })
+            .unwrap_or_default();
+        Some(json!(links))
+    }
+
+    /// 处理 `codefuse/todos`：汇总 TODO 标记，扫描工作区的文件在阻塞线程上进行。
+    pub(crate) async fn todo_report(self: &Arc<Self>) -> Result<Value> {
+        let dispatcher = Arc::clone(self);
+        let report = tokio::task::spawn_blocking(move || {
+            let documents = dispatcher.documents.documents();
+            dispatcher
+                .todos
+                .report(&documents, &dispatcher.workspace.roots())
+        })
+        .await?;
+        Ok(json!(report))
+    }
+
+    /// 各分片的后端最近输出的日志（`codefuse/backendLog`），`shard` 只取指定名称的分片。
+    pub(crate) fn backend_logs(&self, limit: Option<usize>, shard: Option<&str>) -> Value {
+        let logs: Vec<_> = self
+            .shards
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| shard.is_none_or(|name| s.name == name))
+            .map(|(index, s)| self.backend_log.snapshot(index, &s.name, limit))
+            .collect();
+        json!(logs)
+    }
+
+    /// 前端请求超过速率限制时，以上一次相同请求的结果或空结果应答的结果；没有超过时返回 `None`。
+    pub(crate) fn rate_limited(&self, method: &str, rpc: &Value) -> Option<Value> {
+        let rejection = self
+            .rate_limits
+            .reject(method, rpc.get("params"), &self.documents)?;
+        debug!("{} 超过速率限制", method);
+        self.metrics.rate_limited();
+        if rejection.newly_exhausted {
+            self.emit_event(ProxyEvent::BudgetExhausted {
+                method: method.to_string(),
+            });
+        }
+        Some(rejection.result)
+    }
+
+    /// 补全请求同时交给代理内的来源，后端超过等待时间或者没有运行时先以它们的结果应答。
+    pub(crate) fn start_completion_sources(self: &Arc<Self>, rpc: &Value) {
+        let Some(id) = rpc.get("id") else {
+            return;
+        };
+        if !self.completion_sources.is_enabled()
+            || !self.features.is_enabled(Feature::CompletionSources)
+        {
+            return;
+        }
+        let params = rpc.get("params").cloned().unwrap_or(json!(null));
+        let shard = match self.route(request::Completion::METHOD, rpc) {
+            Route::Shard(shard) => shard,
+            Route::Broadcast | Route::FanOut => 0,
+        };
+        let running = self.health.is_alive(shard);
+        let ready = running && self.symbol_index.is_backend_ready();
+        self.completion_sources
+            .request(id, &params, self.document_of(rpc), ready);
+        // 后端正在重启时不等待它，以其他来源的补全应答
+        let budget = if running {
+            self.completion_sources.backend_budget()
         } else {
-            rpc.get("method")
-                .and_then(|m| m.as_str())
-                .map(|s| s.to_string())
+            Some(Duration::ZERO)
+        };
+        if let Some(budget) = budget {
+            self.answer_fallback_after(budget, rpc, |this, id, shard| async move {
+                this.completion_sources.expire(&id, shard).await
+            });
+        }
+    }
+
+    /// 签名帮助记下请求的位置，后端没有应答时由代理合成签名。
+    pub(crate) fn start_signature_help(self: &Arc<Self>, rpc: &Value) {
+        if !self.signature_help.is_enabled() || !self.features.is_enabled(Feature::Fallbacks) {
+            return;
+        }
+        let (Some(id), Ok(params)) = (
+            rpc.get("id"),
+            serde_json::from_value::<TextDocumentPositionParams>(rpc["params"].clone()),
+        ) else {
+            return;
+        };
+        let cursor = Cursor::new(&self.documents, params.text_document.uri, params.position);
+        self.signature_help.request(id, cursor);
+        let timeout = match self.route(request::SignatureHelpRequest::METHOD, rpc) {
+            Route::Shard(shard) if !self.health.is_alive(shard) => Duration::ZERO,
+            _ => self.signature_help.timeout(),
+        };
+        self.answer_fallback_after(timeout, rpc, |this, id, shard| async move {
+            this.signature_help.expire(&id, shard, &this.documents)
+        });
+    }
+
+    /// 文档高亮同理，后端超时时以文本匹配的结果应答。
+    pub(crate) fn start_document_highlight(self: &Arc<Self>, rpc: &Value) {
+        if !self.document_highlight.is_enabled() || !self.features.is_enabled(Feature::Fallbacks) {
+            return;
+        }
+        let (Some(id), Ok(params)) = (
+            rpc.get("id"),
+            serde_json::from_value::<TextDocumentPositionParams>(rpc["params"].clone()),
+        ) else {
+            return;
         };
+        let cursor = Cursor::new(&self.documents, params.text_document.uri, params.position);
+        self.document_highlight.request(id, cursor);
+        let timeout = match self.route(request::DocumentHighlightRequest::METHOD, rpc) {
+            Route::Shard(shard) if !self.health.is_alive(shard) => Duration::ZERO,
+            _ => self.document_highlight.timeout(),
+        };
+        self.answer_fallback_after(timeout, rpc, |this, id, shard| async move {
+            this.document_highlight.expire(&id, shard, &this.documents)
+        });
+    }
+
+    /// 文档停止变化后运行代理自己的检查（拼写、头文件），把结果作为各自来源的诊断发布。
+    ///
+    /// 检查期间文档又被修改时放弃这次的结果，由最后一次修改安排的检查发布。
+    fn schedule_local_checks(self: &Arc<Self>, params: &Value) {
+        let (Some(uri), Some(version)) = (
+            params
+                .pointer("/textDocument/uri")
+                .and_then(|uri| uri.as_str())
+                .and_then(|uri| Url::parse(uri).ok()),
+            params
+                .pointer("/textDocument/version")
+                .and_then(|v| v.as_i64()),
+        ) else {
+            return;
+        };
+        let dispatcher = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(LOCAL_CHECK_DELAY).await;
+            let current = |dispatcher: &Self| {
+                dispatcher
+                    .documents
+                    .get(&uri)
+                    .filter(|doc| i64::from(doc.version) == version)
+            };
+            let Some(doc) = current(&dispatcher) else {
+                return;
+            };
+            let checks = Arc::clone(&dispatcher);
+            let check = tokio::task::spawn_blocking(move || checks.run_local_checks(&doc));
+            let published = match check.await {
+                Ok(published) => published,
+                Err(e) => {
+                    warn!("本地检查失败: {}", e);
+                    return;
+                }
+            };
+            if current(&dispatcher).is_none() {
+                return;
+            }
+            for (source, diagnostics) in published {
+                if let Err(e) = dispatcher.publish_diagnostics(source, &uri, diagnostics) {
+                    warn!("无法发布 {} 诊断: {:?}", source, e);
+                }
+            }
+        });
+    }
+
+    /// 对文档运行启用的本地检查，返回每个来源的诊断。
+    fn run_local_checks(&self, doc: &Document) -> Vec<(&'static str, Vec<Value>)> {
+        let mut published = Vec::new();
+        // 编辑器设置关闭拼写检查时发布空的诊断，清除已有的拼写诊断
+        if let Some(checker) = self.spellcheck.checker() {
+            let found = if self.features.is_enabled(Feature::Spellcheck) {
+                checker.check(&doc.text)
+            } else {
+                Vec::new()
+            };
+            published.push((spellcheck::SOURCE, found));
+        }
+        if self.include_check.is_enabled()
+            && let Ok(path) = doc.uri.to_file_path()
+        {
+            let diagnostics = self
+                .include_check
+                .check(&path, &doc.text, &self.workspace.roots());
+            published.push((include_check::SOURCE, diagnostics));
+        }
+        published
+    }
+
+    /// 在空闲时为请求位置附近的标识符预取悬停和定义。
+    ///
+    /// 预取的并发受预取器的信号量限制，用户发起新的请求或编辑文档后放弃剩余的预取。
+    fn schedule_prefetch(self: &Arc<Self>, rpc: &Value) {
+        let Some(params) = rpc.get("params").and_then(|params| {
+            serde_json::from_value::<TextDocumentPositionParams>(params.clone()).ok()
+        }) else {
+            return;
+        };
+        let uri = params.text_document.uri;
+        let Some(doc) = self.documents.get(&uri) else {
+            return;
+        };
+        let identifiers: Vec<Range> = prefetch::adjacent_identifiers(
+            &doc.text,
+            params.position,
+            self.prefetcher.max_identifiers(),
+        )
+        .into_iter()
+        .filter_map(|position| prefetch::identifier_at(&doc.text, position))
+        .collect();
+        let generation = self.prefetcher.begin();
+        let shard = self.shard_for_uri(&uri);
+
+        let dispatcher = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(PREFETCH_IDLE_DELAY).await;
+            let permits = dispatcher.prefetcher.permits();
+            for identifier in identifiers {
+                for method in prefetch::PREFETCH_METHODS {
+                    if !dispatcher.prefetcher.is_current(generation) {
+                        return;
+                    }
+                    if dispatcher.capabilities.supports(method) == Some(false) {
+                        continue;
+                    }
+                    let key = CacheKey::new(method, uri.clone(), identifier);
+                    if dispatcher.prefetcher.contains(&key) {
+                        continue;
+                    }
+                    let Ok(_permit) = permits.acquire().await else {
+                        return;
+                    };
+                    let params =
+                        json!({"textDocument": {"uri": uri}, "position": identifier.start});
+                    match dispatcher.request_backend(shard, method, params).await {
+                        Ok(result) => dispatcher
+                            .prefetcher
+                            .insert_if_current(generation, key, result),
+                        Err(e) => debug!("预取失败: {:?}", e),
+                    }
+                }
+            }
+        });
+    }
+
+    /// 为最近编辑的文件发送合成的 `didOpen`。
+    fn warm_up(&self) -> Result<()> {
+        let notifications = self.warmup.warm_up(&self.documents);
+        if !notifications.is_empty() {
+            debug!("预热 {} 个最近编辑的文件", notifications.len());
+        }
+        for (uri, mut did_open) in notifications {
+            self.languages.apply(&mut did_open);
+            self.shards[self.shard_for_uri(&uri)]
+                .sender
+                .send(Message::new(did_open))?;
+        }
+        Ok(())
+    }
+
+    /// 把前端消息交给指定分片：有注册的处理器时调用处理器，否则直接转发。
+    async fn dispatch_to_shard(
+        self: &Arc<Self>,
+        shard: usize,
+        method: &str,
+        rpc: Value,
+    ) -> Result<()> {
+        let handlers = self.handlers_from_frontend.handlers(method);
+        let rpc = match self.run_handlers(handlers, shard, rpc).await? {
+            Verdict::Continue(rpc) => rpc,
+            Verdict::Respond(response) => return self.send_to_frontend(&response),
+            Verdict::Drop => return Ok(()),
+        };
+
+        // 如果是请求（有 id 和 method），记录下来等待响应。
+        // 记录前端请求的方法，处理器改写了方法时，响应仍然由原方法的处理器处理
+        if let Some(id) = rpc.get("id")
+            && rpc.get("method").is_some()
+        {
+            self.responses.request(id, method);
+            self.journal.record(shard, method, &rpc);
+            self.version_check
+                .request(id, method, rpc.get("params"), &self.documents);
+            self.size_limit.request(id, rpc.get("params"));
+            self.rate_limits
+                .request(id, method, rpc.get("params"), &self.documents);
+            if method == request::CodeActionRequest::METHOD {
+                self.spellcheck.request(id, rpc.get("params"));
+            }
+            if method == request::CodeLensRequest::METHOD {
+                self.todos.request(id, rpc.get("params"), &self.documents);
+            }
+            self.lifecycle.forwarded(id, shard);
+        }
+
+        if let Some(shadow) = &self.shadow {
+            shadow.mirror(&rpc);
+        }
+        self.shards[shard].sender.send(Message::new(rpc))?;
+        Ok(())
+    }
+
+    /// 把生命周期消息发送给非默认分片。
+    ///
+    /// 通知原样转发；请求改由代理发起，响应被代理消化，不会返回给前端。
+    fn broadcast_to_shard(&self, shard: usize, method: &str, rpc: &Value) -> Result<()> {
+        if rpc.get("id").is_none() {
+            self.shards[shard].sender.send(Message::new(rpc.clone()))?;
+            return Ok(());
+        }
 
-        // 如果有 method 且注册了处理器，调用；否则直接转发
-        if let Some(method) = method
-            && let Some(handler) = self.handlers_from_backend.read().await.get(&method) {
-                return handler(rpc, self.frontend_sender.clone()).await;
+        let params = rpc.get("params").cloned().unwrap_or(json!(null));
+        let response = self.send_internal_request(&self.shards[shard].sender, method, params)?;
+        let name = self.shards[shard].name.clone();
+        let method = method.to_string();
+        tokio::spawn(async move {
+            match tokio::time::timeout(INTERNAL_REQUEST_TIMEOUT, response).await {
+                Ok(Ok(response)) if response.get("error").is_some() => {
+                    warn!(
+                        "分片 {} 的 {} 请求失败: {}",
+                        name, method, response["error"]
+                    );
+                }
+                Ok(Ok(_)) => debug!("分片 {} 已完成 {}", name, method),
+                _ => warn!("分片 {} 没有响应 {} 请求", name, method),
             }
+        });
+        Ok(())
+    }
 
+    /// 后端在 `timeout` 内没有响应请求时，以代理自己的结果应答前端，取消后端的请求，后端重启后也不再重放。
+    /// 用于补全来源、签名帮助和文档高亮。
+    ///
+    /// # 参数
+    ///
+    /// * `timeout` - 等待后端的时间
+    /// * `rpc` - 前端的请求
+    /// * `fallback` - 以调度器、请求 id 和请求发往的分片计算代理的结果，返回 `None` 表示后端已经响应
+    fn answer_fallback_after<F, Fut>(self: &Arc<Self>, timeout: Duration, rpc: &Value, fallback: F)
+    where
+        F: FnOnce(Arc<Self>, Value, usize) -> Fut + Send + 'static,
+        Fut: Future<Output = Option<Value>> + Send,
+    {
+        let method = rpc["method"].as_str().unwrap_or_default();
+        let shard = match self.route(method, rpc) {
+            Route::Shard(shard) => shard,
+            Route::Broadcast | Route::FanOut => 0,
+        };
+        let rpc = rpc.clone();
+        let this = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            let Some(result) = fallback(Arc::clone(&this), rpc["id"].clone(), shard).await else {
+                return;
+            };
+            debug!(
+                "后端没有在 {:?} 内响应 {} {}，由代理应答",
+                timeout, rpc["method"], rpc["id"]
+            );
+            this.journal.forget(&rpc["id"]);
+            let cancel = json!({
+                "jsonrpc": "2.0",
+                "method": notification::Cancel::METHOD,
+                "params": {"id": rpc["id"]},
+            });
+            let _ = this.shards[shard].sender.send(Message::new(cancel));
+            if let Err(e) = this.respond_to_frontend(&rpc, Ok(result)) {
+                warn!("无法应答请求 {}: {:?}", rpc["id"], e);
+            }
+        });
+    }
 
-        let message = Self::format_lsp_message(&rpc)?;
-        self.frontend_sender.send(message)?;
+    /// 把 `workspace/didChangeWatchedFiles` 中的文件变更分给关心它们的分片，没有相关变更的分片不发送。
+    async fn split_file_changes(self: &Arc<Self>, rpc: Value) -> Result<()> {
+        let method = notification::DidChangeWatchedFiles::METHOD;
+        let params = rpc.get("params").cloned().unwrap_or(json!(null));
+        let params: DidChangeWatchedFilesParams = match serde_json::from_value(params) {
+            Ok(params) => params,
+            Err(e) => {
+                warn!("无法解析 didChangeWatchedFiles: {}", e);
+                return Ok(());
+            }
+        };
+        for (shard, changes) in shard::split_file_changes(&self.shards, &params.changes) {
+            let mut rpc = rpc.clone();
+            rpc["params"]["changes"] = json!(changes);
+            if shard == 0 {
+                self.dispatch_to_shard(0, method, rpc).await?;
+            } else {
+                self.broadcast_to_shard(shard, method, &rpc)?;
+            }
+        }
         Ok(())
     }
 
+    /// 把工作区级请求发送给所有分片，合并结果后返回给前端。
+    ///
+    /// 先以默认分片执行注册的处理器，处理器应答或丢弃时不再发送。
+    /// 部分分片失败时只合并成功的结果；全部失败时把第一个错误返回给前端。
+    async fn fan_out(self: &Arc<Self>, method: &str, rpc: Value) -> Result<()> {
+        let handlers = self.handlers_from_frontend.handlers(method);
+        let rpc = match self.run_handlers(handlers, 0, rpc).await? {
+            Verdict::Continue(rpc) => rpc,
+            Verdict::Respond(response) => return self.send_to_frontend(&response),
+            Verdict::Drop => return Ok(()),
+        };
+
+        let params = rpc.get("params").cloned().unwrap_or(json!(null));
+        let responses = join_all(
+            (0..self.shards.len()).map(|shard| self.request_backend(shard, method, params.clone())),
+        )
+        .await;
+
+        let mut results = Vec::new();
+        let mut first_error = None;
+        for (shard, response) in responses.into_iter().enumerate() {
+            match response {
+                Ok(result) => results.push(result),
+                Err(e) => {
+                    debug!(
+                        "分片 {} 的 {} 请求失败: {:?}",
+                        self.shards[shard].name, method, e
+                    );
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        let id = rpc.get("id").cloned().unwrap_or(json!(null));
+        let response = match (results.is_empty(), first_error) {
+            (true, Some(e)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {"code": -32603, "message": e.to_string()},
+            }),
+            _ => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": shard::merge_results(method, results),
+            }),
+        };
+        // 合并后的结果当作默认分片的响应
+        self.forward_to_frontend(0, Some(method), response).await
+    }
+
+    /// 处理来自后端的消息。
+    ///
+    /// 这个方法接收来自后端的 JSON-RPC 消息，确定消息类型（响应或通知），
+    /// 检查是否有注册的处理器，如果有则调用处理器，否则将消息转发给前端。
+    ///
+    /// # 参数
+    ///
+    /// * `rpc` - 接收到的 JSON-RPC 消息
+    ///
+    /// # 返回
+    ///
+    /// 返回 `Result<()>`，表示处理是否成功
+    pub async fn handle_from_backend(self: &Arc<Self>, rpc: Value) -> Result<()> {
+        self.handle_from_shard(0, rpc).await
+    }
+
+    /// 处理来自指定后端分片的消息。
+    ///
+    /// 除了 `handle_from_backend` 的逻辑之外，还会完成代理主动发起的请求，
+    /// 并为非默认分片发起的请求分配不会冲突的 id。
+    ///
+    /// # 参数
+    ///
+    /// * `shard` - 消息来源的分片下标
+    /// * `rpc` - 接收到的 JSON-RPC 消息
+    ///
+    /// # 返回
+    ///
+    /// 返回 `Result<()>`，表示处理是否成功
+    pub async fn handle_from_shard(self: &Arc<Self>, shard: usize, rpc: Value) -> Result<()> {
+        self.trace.record(Direction::Backend, Some(shard), &rpc);
+        if rpc.get("method").is_none() {
+            self.health.responded(shard);
+        }
+        if symbol_index::is_index_progress_end(&rpc) {
+            self.symbol_index.mark_backend_ready();
+            if self.state.is_enabled() {
+                self.state.record_index_completed(&self.workspace.roots());
+                self.save_state();
+            }
+        }
+
+        // 代理主动发起的请求的响应
+        if rpc.get("method").is_none()
+            && let Some(id) = rpc.get("id").and_then(|id| id.as_str())
+            && let Some((_, waiter)) = self.internal_requests.remove(id)
+        {
+            let _ = waiter.send(rpc);
+            return Ok(());
+        }
+
+        // 统一获取 method：如果是请求或通知，从消息中获取；如果是响应，从等待的请求中查找
+        let method = if let Some(method) = rpc.get("method") {
+            method.as_str().map(|s| s.to_string())
+        } else if let Some(id) = rpc.get("id").filter(|id| !id.is_null()) {
+            match self.responses.resolve(id) {
+                Resolution::Pending(method) => {
+                    self.journal.forget(id);
+                    if let Some(shadow) = &self.shadow {
+                        shadow.primary_responded(id, &rpc);
+                    }
+                    self.rate_limits.responded(id, &rpc);
+                    self.lifecycle.backend_responded(id);
+                    Some(method)
+                }
+                unexpected => {
+                    if unexpected == Resolution::Duplicate {
+                        warn!("分片 {} 对请求 {} 的重复响应", shard, id);
+                        self.metrics.duplicate_response();
+                    } else {
+                        warn!("分片 {} 发来未知请求 {} 的响应", shard, id);
+                        self.metrics.unknown_response();
+                    }
+                    if self.drop_unexpected_responses {
+                        return Ok(());
+                    }
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        // 补全追加代理内其他来源的结果；后端超过等待时间时代理已经应答，迟到的响应丢弃
+        let mut rpc = rpc;
+        if method.as_deref() == Some(request::Completion::METHOD)
+            && !self.completion_sources.merge(&mut rpc).await
+        {
+            debug!("后端对补全 {} 的响应超过了等待时间", rpc["id"]);
+            self.version_check.is_stale(&rpc["id"], &self.documents);
+            self.size_limit.forget(&rpc["id"]);
+            return Ok(());
+        }
+        if method.as_deref() == Some(request::SignatureHelpRequest::METHOD)
+            && !self.signature_help.respond(&mut rpc, &self.documents)
+        {
+            debug!("后端对签名帮助 {} 的响应超过了等待时间", rpc["id"]);
+            self.version_check.is_stale(&rpc["id"], &self.documents);
+            self.size_limit.forget(&rpc["id"]);
+            return Ok(());
+        }
+        if method.as_deref() == Some(request::DocumentHighlightRequest::METHOD)
+            && !self.document_highlight.respond(&rpc["id"])
+        {
+            debug!("后端对文档高亮 {} 的响应超过了等待时间", rpc["id"]);
+            self.version_check.is_stale(&rpc["id"], &self.documents);
+            self.size_limit.forget(&rpc["id"]);
+            return Ok(());
+        }
+
+        // 各分片运行同一个后端程序，以默认分片声明的能力为准
+        if shard == 0
+            && method.as_deref() == Some(request::Initialize::METHOD)
+            && let Some(result) = rpc.get("result")
+        {
+            self.capabilities.record(result);
+            if self.state.is_enabled() {
+                self.state.record_backend(result);
+                self.save_state();
+            }
+            if let Some(probed) = self.capabilities.snapshot() {
+                info!(
+                    "后端 {} {}",
+                    probed.name.as_deref().unwrap_or("-"),
+                    probed.version.as_deref().unwrap_or("-")
+                );
+            }
+        }
+
+        // 请求针对的文档在响应到达之前被修改，响应已经过期
+        if rpc.get("method").is_none()
+            && let Some(id) = rpc.get("id")
+            && self.version_check.is_stale(id, &self.documents)
+        {
+            debug!("文档已被修改，丢弃请求 {} 的响应", id);
+            self.size_limit.forget(id);
+            return self.send_to_frontend(&content_modified::content_modified(id));
+        }
+
+        // 严格校验模式下，无法解析的响应换成 InternalError 交给前端
+        let rpc = match method
+            .as_deref()
+            .and_then(|method| self.validate(method, &rpc))
+        {
+            Some(violation) if rpc.get("method").is_none() => json!({
+                "jsonrpc": "2.0",
+                "id": rpc["id"],
+                "error": {"code": -32603, "message": violation.report()},
+            }),
+            _ => rpc,
+        };
+
+        // 代理代替客户端监视文件时，拦截后端的文件监视注册
+        let is_watch_registration = matches!(
+            method.as_deref(),
+            Some(request::RegisterCapability::METHOD | request::UnregisterCapability::METHOD)
+        );
+        let mut rpc = match method.as_deref() {
+            // 各分片的监视规则相同，由默认分片的注册代表
+            _ if shard != 0 && is_watch_registration && self.file_watcher.is_enabled() => {
+                return self.reply_to_backend(shard, &rpc, json!(null));
+            }
+            Some(request::RegisterCapability::METHOD) if self.file_watcher.is_enabled() => {
+                match self.file_watcher.handle_register_capability(rpc)? {
+                    Some(rpc) => rpc,
+                    None => return Ok(()),
+                }
+            }
+            Some(request::UnregisterCapability::METHOD) if self.file_watcher.is_enabled() => {
+                match self.file_watcher.handle_unregister_capability(rpc)? {
+                    Some(rpc) => rpc,
+                    None => return Ok(()),
+                }
+            }
+            // 预热文档的诊断不属于用户打开的文件，不转发给前端
+            Some(notification::PublishDiagnostics::METHOD)
+                if rpc
+                    .pointer("/params/uri")
+                    .and_then(|uri| uri.as_str())
+                    .and_then(|uri| Url::parse(uri).ok())
+                    .is_some_and(|uri| self.warmup.is_warm(&uri)) =>
+            {
+                return Ok(());
+            }
+            // 接管后台索引进度时由代理创建前端的进度，各分片的进度合并后再交给前端
+            Some(request::WorkDoneProgressCreate::METHOD) if self.index_progress.owns(&rpc) => {
+                return self.reply_to_backend(shard, &rpc, json!(null));
+            }
+            Some(notification::Progress::METHOD) if self.index_progress.owns(&rpc) => {
+                return self
+                    .merge_index_progress(shard, &rpc["params"]["value"])
+                    .await;
+            }
+            // 文件状态先汇总；前端自己没有请求时不转发
+            Some(file_status::FILE_STATUS) if self.file_status.is_enabled() => {
+                let step = self.file_status.observe(&rpc["params"]);
+                self.report_file_status(step).await?;
+                if !self.file_status.forwards() {
+                    return Ok(());
+                }
+                rpc
+            }
+            // 代理掌握完整的工作区文件夹列表，直接应答后端
+            Some(request::WorkspaceFoldersRequest::METHOD) => {
+                return self.reply_to_backend(shard, &rpc, json!(self.workspace.folders()));
+            }
+            _ => rpc,
+        };
+
+        match method.as_deref() {
+            // 过时的诊断和与上次相同的诊断不转发，减少快速输入时编辑器的刷新
+            Some(notification::PublishDiagnostics::METHOD) => {
+                if self.modules.is_enabled() {
+                    self.group_module_diagnostics(&mut rpc)?;
+                }
+                if diagnostics::is_outdated(&rpc, &self.documents) {
+                    debug!("丢弃过时的诊断: {}", rpc["params"]["uri"]);
+                    self.metrics.outdated_diagnostics();
+                    return Ok(());
+                }
+                if self.shards.len() > 1
+                    && let Some(uri) = rpc.pointer("/params/uri").and_then(|uri| uri.as_str())
+                {
+                    let published = rpc["params"]["diagnostics"]
+                        .as_array()
+                        .cloned()
+                        .unwrap_or_default();
+                    let merged = self.shard_diagnostics.merge(shard, uri, published);
+                    rpc["params"]["diagnostics"] = json!(merged);
+                }
+                if self.features.is_enabled(Feature::DiagnosticFilters) {
+                    self.tidy_policy
+                        .read()
+                        .unwrap()
+                        .filter_diagnostics(&mut rpc);
+                }
+                self.diagnostic_sources
+                    .merge(diagnostic_sources::BACKEND_SOURCE, &mut rpc);
+                if !self.diagnostics.update(&rpc) {
+                    debug!("丢弃重复的诊断: {}", rpc["params"]["uri"]);
+                    self.metrics.duplicate_diagnostics();
+                    return Ok(());
+                }
+            }
+            Some(request::Completion::METHOD) => {
+                self.include_policy.rewrite_completion_response(&mut rpc);
+                if self.state.is_enabled()
+                    && let Some(result) = rpc.get_mut("result")
+                {
+                    self.state.preselect_completion(result);
+                }
+            }
+            Some(request::ResolveCompletionItem::METHOD) if !self.include_policy.is_empty() => {
+                if let Some(item) = rpc.get_mut("result") {
+                    self.include_policy.rewrite_completion_item(item);
+                }
+            }
+            Some(request::CodeActionRequest::METHOD) => {
+                self.include_policy.rewrite_code_action_response(&mut rpc);
+                self.spellcheck.rewrite_code_action_response(&mut rpc);
+            }
+            Some(request::CodeLensRequest::METHOD) => {
+                self.todos.rewrite_code_lens_response(&mut rpc);
+            }
+            Some(request::HoverRequest::METHOD) if self.signature_help.is_enabled() => {
+                if let Some(result) = rpc.get("result") {
+                    self.signature_help.learn_hover(result);
+                }
+            }
+            _ => {}
+        }
+
+        // 不同分片的请求 id 可能相同，转发给前端前换成代理分配的 id
+        if shard != 0
+            && rpc.get("method").is_some()
+            && let Some(original_id) = rpc.get("id").cloned()
+        {
+            let id = format!("shard{}:{}", shard, original_id);
+            self.shard_requests.insert(id.clone(), (shard, original_id));
+            rpc["id"] = json!(id);
+        }
+
+        // 前端请求的响应交给前端之后，请求的跨度结束
+        let response_id = rpc
+            .get("method")
+            .is_none()
+            .then(|| rpc.get("id").cloned())
+            .flatten();
+        let failed = rpc.get("error").is_some();
+        self.forward_to_frontend(shard, method.as_deref(), rpc)
+            .await?;
+        if let Some(id) = response_id {
+            self.request_finished(&id, failed);
+        }
+        Ok(())
+    }
+
+    /// 在开启校验时检查消息，记录违规。
+    ///
+    /// # 返回
+    ///
+    /// 严格模式下消息无法解析时返回违规，调用方把它变成协议错误
+    fn validate(&self, method: &str, rpc: &Value) -> Option<validate::Violation> {
+        let mode = self.validation?;
+        let violation = validate::validate_message(method, rpc)?;
+        warn!("{}", violation.report());
+        (mode == ValidateMode::Strict && violation.is_error()).then_some(violation)
+    }
+
+    /// 把 `shard` 分片的后端消息交给前端：先依次执行注册的处理器，再按处理结果转发。
+    async fn forward_to_frontend(
+        self: &Arc<Self>,
+        shard: usize,
+        method: Option<&str>,
+        rpc: Value,
+    ) -> Result<()> {
+        let handlers = method
+            .map(|method| self.handlers_from_backend.handlers(method))
+            .unwrap_or_default();
+        match self.run_handlers(handlers, shard, rpc).await? {
+            Verdict::Continue(rpc) => {
+                self.session.record(method, &rpc);
+                if let Some(rpc) = self.size_limit.enforce(method, rpc) {
+                    self.write_to_frontend(rpc)?;
+                }
+            }
+            Verdict::Respond(reply) => self.shards[shard].sender.send(Message::new(reply))?,
+            Verdict::Drop => {}
+        }
+        Ok(())
+    }
+
+    /// 代替前端应答后端发来的请求。
+    fn reply_to_backend(&self, shard: usize, rpc: &Value, result: Value) -> Result<()> {
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": rpc.get("id").cloned().unwrap_or(json!(null)),
+            "result": result,
+        });
+        self.shards[shard].sender.send(Message::new(response))?;
+        Ok(())
+    }
+
+    /// 由代理向指定分片发起请求并等待结果。
+    ///
+    /// 请求使用代理自己分配的字符串 id，响应不会转发给前端。
+    ///
+    /// # 参数
+    ///
+    /// * `shard` - 目标分片下标
+    /// * `method` - 请求方法
+    /// * `params` - 请求参数
+    ///
+    /// # 返回
+    ///
+    /// 返回响应中的 `result` 字段
+    ///
+    /// # 错误
+    ///
+    /// 如果后端返回错误、通道已关闭或者超时没有响应，返回错误
+    pub async fn request_backend(
+        &self,
+        shard: usize,
+        method: &str,
+        params: Value,
+    ) -> Result<Value> {
+        self.request_via(&self.shards[shard].sender, method, params)
+            .await
+    }
+
+    /// 由代理通过指定的通道发起请求并等待结果，用于还没有接入分片的后端进程（例如备用后端）。
+    ///
+    /// # 参数
+    ///
+    /// * `sender` - 写入后端进程的通道
+    /// * `method` - 请求方法
+    /// * `params` - 请求参数
+    ///
+    /// # 返回
+    ///
+    /// 返回响应中的 `result` 字段
+    ///
+    /// # 错误
+    ///
+    /// 如果后端返回错误、通道已关闭或者超时没有响应，返回错误
+    pub async fn request_via(
+        &self,
+        sender: &UnboundedSender<Message>,
+        method: &str,
+        params: Value,
+    ) -> Result<Value> {
+        let waiter = self.send_internal_request(sender, method, params)?;
+        let response = match tokio::time::timeout(INTERNAL_REQUEST_TIMEOUT, waiter).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => bail!("{} 请求被取消", method),
+            Err(_) => {
+                self.internal_requests
+                    .retain(|_, waiter| !waiter.is_closed());
+                bail!("{} 请求超时", method);
+            }
+        };
+
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!("{} 请求失败: {}", method, error));
+        }
+        Ok(response.get("result").cloned().unwrap_or(json!(null)))
+    }
+
+    /// 按 LSP 的方式结束所有后端：向每个分片发送 `shutdown` 请求，等到响应后发送 `exit` 通知。
+    ///
+    /// 后端已经退出或者没有响应时照样发送 `exit`，错误只记录日志。
+    pub async fn shutdown_backends(&self) {
+        join_all(self.shards.iter().map(|shard| async move {
+            let shutdown = self.request_via(&shard.sender, Shutdown::METHOD, json!(null));
+            match tokio::time::timeout(SHUTDOWN_TIMEOUT, shutdown).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => debug!("分片 {} 的 shutdown 请求失败: {:?}", shard.name, e),
+                Err(_) => warn!("分片 {} 的后端没有响应 shutdown 请求", shard.name),
+            }
+            let exit = json!({"jsonrpc": "2.0", "method": notification::Exit::METHOD});
+            if shard.sender.send(Message::new(exit)).is_err() {
+                debug!("分片 {} 的后端已经退出", shard.name);
+            }
+        }))
+        .await;
+    }
+
+    /// 发送代理自己的请求，返回等待响应的通道。
+    fn send_internal_request(
+        &self,
+        sender: &UnboundedSender<Message>,
+        method: &str,
+        params: Value,
+    ) -> Result<oneshot::Receiver<Value>> {
+        let id = format!(
+            "codefuse-{}",
+            self.request_counter.fetch_add(1, Ordering::Relaxed)
+        );
+        let (waiter, response) = oneshot::channel();
+        self.internal_requests.insert(id.clone(), waiter);
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        if let Err(e) = sender.send(Message::new(request)) {
+            self.internal_requests.remove(&id);
+            return Err(e.into());
+        }
+        Ok(response)
+    }
+
+    /// 处理来自备用后端的消息。
+    ///
+    /// 备用后端还没有接管任何分片：代理发起的请求的响应照常完成，
+    /// 后端发来的请求由代理直接回复，通知被丢弃，都不会转发给前端。
+    ///
+    /// # 参数
+    ///
+    /// * `sender` - 写入备用后端的通道
+    /// * `rpc` - 备用后端发来的 JSON-RPC 消息
+    ///
+    /// # 错误
+    ///
+    /// 如果回复无法发送，返回错误
+    pub async fn handle_from_standby(
+        &self,
+        sender: &UnboundedSender<Message>,
+        rpc: Value,
+    ) -> Result<()> {
+        let Some(method) = rpc.get("method").and_then(|m| m.as_str()) else {
+            if let Some(id) = rpc.get("id").and_then(|id| id.as_str())
+                && let Some((_, waiter)) = self.internal_requests.remove(id)
+            {
+                let _ = waiter.send(rpc);
+            }
+            return Ok(());
+        };
+        let Some(id) = rpc.get("id") else {
+            return Ok(());
+        };
+
+        let result = if method == request::WorkspaceFoldersRequest::METHOD {
+            json!(self.workspace.folders())
+        } else {
+            json!(null)
+        };
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": result,
+        });
+        sender.send(Message::new(response))?;
+        Ok(())
+    }
+
+    /// `shard` 分片的后端已经退出：标记为没有运行，超时后由代理应答过的请求不会再有响应，不再跟踪。
+    pub fn backend_exited(&self, shard: usize) {
+        self.health.set_alive(shard, false);
+        self.completion_sources.backend_exited(shard);
+        self.signature_help.backend_exited(shard);
+        self.document_highlight.backend_exited(shard);
+    }
+
+    /// 订阅后端重启请求，每次请求重启时值加一。
+    pub fn subscribe_restart(&self) -> watch::Receiver<u64> {
+        self.restart.subscribe()
+    }
+
+    /// 请求所有分片重启后端。
+    pub fn request_restart(&self) {
+        self.restart.send_modify(|generation| *generation += 1);
+    }
+
+    /// 订阅重新加载的后端配置，还没有重新加载时值为 `None`。
+    pub fn subscribe_backend_launch(&self) -> watch::Receiver<Option<BackendLaunch>> {
+        self.backend_launch.subscribe()
+    }
+
+    /// 订阅处理前端消息的并发上限。
+    pub fn subscribe_handler_limit(&self) -> watch::Receiver<usize> {
+        self.handler_limit.subscribe()
+    }
+
+    /// 切换配置档（`codefuse/setProfile`），返回新的配置档和按生效方式分组的修改。
+    ///
+    /// # 错误
+    ///
+    /// 没有记录配置的来源、参数无效或者配置档不存在时返回错误
+    pub(crate) fn set_profile(&self, rpc: &Value) -> Result<Value> {
+        let source = self
+            .config_source
+            .as_ref()
+            .ok_or_else(|| anyhow!("代理没有记录配置的来源，无法切换配置档"))?;
+        let profile = match rpc.pointer("/params/profile") {
+            None | Some(Value::Null) => None,
+            Some(Value::String(name)) => Some(name.clone()),
+            Some(_) => bail!("profile 必须是字符串或者 null"),
+        };
+        info!("切换配置档: {}", profile.as_deref().unwrap_or("(无)"));
+        let changes = source.set_profile(self, profile.clone())?;
+        Ok(json!({
+            "profile": profile,
+            "live": changes.live,
+            "restartBackend": changes.restart_backend,
+            "restartProxy": changes.restart_proxy,
+        }))
+    }
+
+    /// 改变工作区的信任状态（`workspace/trust`），返回新的状态和因为不受信任而忽略的设置。
+    ///
+    /// # 错误
+    ///
+    /// 没有记录配置的来源、参数无效或者配置无法重新加载时返回错误
+    pub(crate) fn set_workspace_trust(&self, rpc: &Value) -> Result<Value> {
+        let source = self
+            .config_source
+            .as_ref()
+            .ok_or_else(|| anyhow!("代理没有记录配置的来源，无法改变工作区的信任状态"))?;
+        let Some(trusted) = rpc.pointer("/params/trusted").and_then(|t| t.as_bool()) else {
+            bail!("trusted 必须是布尔值");
+        };
+        source.set_trusted(self, trusted)?;
+        if let Some(message) = source.trust().message() {
+            self.show_message(MessageType::WARNING, &message);
+        }
+        Ok(json!({
+            "trusted": source.trust().is_trusted(),
+            "withheld": source.trust().withheld(),
+        }))
+    }
+
+    /// 应用重新加载的配置：日志、诊断来源、clang-tidy 策略、预取、速率限制、消息去重和并发上限立即生效，后端配置修改时重启所有分片的后端，
+    /// 最后在编辑器中说明哪些修改已经生效。已经发布的诊断在后端下一次发布时按新的设置过滤。
+    ///
+    /// # 参数
+    ///
+    /// * `config` - 新的配置
+    /// * `changes` - 与当前配置相比修改了的部分
+    pub fn apply_config(&self, config: &Config, changes: &Changes) {
+        log::set_max_level(config.logging.level_filter());
+        self.slow_requests
+            .set_threshold(config.logging.slow_request_ms.map(Duration::from_millis));
+        self.diagnostic_sources.set_sources(&config.diagnostics);
+        let tidy_policy =
+            TidyPolicy::new(&config.tidy).with_directories(Arc::clone(&self.directory_configs));
+        if changes.live.contains(&"[[tidy]]")
+            && let Some(notification) = tidy_policy.configuration_notification()
+        {
+            for shard in &self.shards {
+                if let Err(e) = shard.sender.send(Message::new(notification.clone())) {
+                    warn!("无法把 clang-tidy 检查集合发给分片 {}: {:?}", shard.name, e);
+                }
+            }
+        }
+        *self.tidy_policy.write().unwrap() = tidy_policy;
+        if self.prefetcher.is_enabled() && !config.prefetch.enabled {
+            self.emit_event(ProxyEvent::CacheCleared { cache: "prefetch" });
+        }
+        self.prefetcher.set_config(config.prefetch.clone());
+        if changes.live.contains(&"[rate_limits]") {
+            self.emit_event(ProxyEvent::CacheCleared {
+                cache: "rateLimits",
+            });
+        }
+        self.rate_limits.set_rules(&config.rate_limits);
+        self.message_throttle.set_config(&config.messages);
+        self.handler_limit
+            .send_replace(config.concurrency.max_handlers);
+        if !changes.restart_backend.is_empty() {
+            self.relaunch_backends(config);
+        }
+        let kind = if changes.restart_proxy.is_empty() {
+            MessageType::INFO
+        } else {
+            MessageType::WARNING
+        };
+        self.show_message(kind, &changes.message());
+    }
+
+    /// 按 `config` 重启所有分片的后端，启动参数中加入用户允许的 `--query-driver`。
+    fn relaunch_backends(&self, config: &Config) {
+        let drivers = self.query_drivers();
+        let mut args = supervisor::shard_args(config);
+        for args in &mut args {
+            clangd_flags::add_query_drivers(args, &drivers);
+        }
+        self.backend_launch.send_replace(Some(BackendLaunch {
+            config: config.backend.clone(),
+            args,
+        }));
+        self.request_restart();
+    }
+
+    /// 用户允许加入 `--query-driver` 的编译器。
+    pub fn query_drivers(&self) -> Vec<String> {
+        self.query_drivers.read().unwrap().clone()
+    }
+
+    /// 把编译器加入 `--query-driver` 并重启后端，让 clangd 查询它们的系统头文件目录。
+    pub fn allow_query_drivers(&self, drivers: &[String]) {
+        {
+            let mut allowed = self.query_drivers.write().unwrap();
+            for driver in drivers {
+                if !allowed.contains(driver) {
+                    allowed.push(driver.clone());
+                }
+            }
+        }
+        info!("--query-driver 加入 {}，重启后端", drivers.join("、"));
+        let config = match &self.config_source {
+            Some(source) => source.current(),
+            None => self.config.clone(),
+        };
+        self.relaunch_backends(&config);
+    }
+
+    /// 在编辑器中显示一条消息（`window/showMessage`）。
+    pub fn show_message(&self, kind: MessageType, message: &str) {
+        let rpc = json!({
+            "jsonrpc": "2.0",
+            "method": notification::ShowMessage::METHOD,
+            "params": {"type": kind, "message": message},
+        });
+        if let Err(e) = self.send_to_frontend(&rpc) {
+            warn!("无法向编辑器发送消息: {:?}", e);
+        }
+    }
+
+    /// 生成崩溃报告并告诉用户报告的位置。关闭了崩溃报告或者一分钟内已经生成过时不做处理。
+    ///
+    /// # 参数
+    ///
+    /// * `reason` - 生成报告的原因，显示给用户并写入报告
+    pub fn report_crash(&self, reason: &str) {
+        if !self.crash_reports.due() {
+            return;
+        }
+        match crash_report::write_bundle(self, &self.crash_reports.dir(), reason) {
+            Ok(path) => {
+                warn!("{}，崩溃报告: {}", reason, path.display());
+                self.show_message(
+                    MessageType::ERROR,
+                    &format!(
+                        "{}。崩溃报告已保存到 {}，提交问题时请附上这个文件",
+                        reason,
+                        path.display()
+                    ),
+                );
+            }
+            Err(e) => warn!("无法生成崩溃报告: {:?}", e),
+        }
+    }
+
+    /// 开启了 `[telemetry] events` 时，以 `telemetry/event` 通知编辑器代理运行状态的变化。
+    pub fn emit_event(&self, event: ProxyEvent) {
+        if !self.events {
+            return;
+        }
+        debug!("代理事件: {}", event.name());
+        if let Err(e) = self.send_to_frontend(&event.notification()) {
+            warn!("无法向编辑器发送事件 {}: {:?}", event.name(), e);
+        }
+    }
+
+    /// 最近收到的消息。
+    pub fn trace(&self) -> &MessageTrace {
+        &self.trace
+    }
+
+    /// 执行代理自己实现的命令并应答前端。
+    async fn execute_proxy_command(&self, command: &str, rpc: &Value) -> Result<()> {
+        let arguments = rpc
+            .pointer("/params/arguments")
+            .and_then(|a| a.as_array())
+            .cloned()
+            .unwrap_or_default();
+        let result = match command {
+            commands::RESTART_BACKEND => {
+                self.request_restart();
+                Ok(json!(null))
+            }
+            commands::DUMP_TRACE => {
+                let path = match arguments.first().and_then(|a| a.as_str()) {
+                    Some(path) => PathBuf::from(path),
+                    None => std::env::temp_dir()
+                        .join(format!("codefuse-trace-{}.ndjson", std::process::id())),
+                };
+                self.trace
+                    .dump(&path)
+                    .map(|messages| json!({"path": path, "messages": messages}))
+            }
+            commands::APPLY_ALL_FIXITS => {
+                let uri = arguments.first().and_then(|a| a.as_str()).map(Url::parse);
+                match uri.transpose() {
+                    Ok(uri) => self.apply_all_fixits(uri).await,
+                    Err(e) => Err(e.into()),
+                }
+            }
+            commands::GENERATE_COMPILE_COMMANDS => {
+                let root = arguments
+                    .first()
+                    .and_then(|a| a.as_str())
+                    .map(PathBuf::from);
+                self.generate_compile_commands(root).await
+            }
+            _ => Err(anyhow!("未知命令: {}", command)),
+        };
+
+        self.respond_to_frontend(
+            rpc,
+            result.map_err(|e| anyhow!("{} 执行失败: {}", command, e)),
+        )
+    }
+
+    /// 运行 cmake 生成 `compile_commands.json`，期间在编辑器中显示进度，完成后重启后端让 clangd 读取新的编译数据库。
+    ///
+    /// # 参数
+    ///
+    /// * `root` - 工作区根目录；为 `None` 时使用第一个工作区
+    ///
+    /// # 返回
+    ///
+    /// 返回 `{"path": <compile_commands.json 的路径>}`
+    async fn generate_compile_commands(&self, root: Option<PathBuf>) -> Result<Value> {
+        let root = match root.or_else(|| self.workspace.roots().into_iter().next()) {
+            Some(root) => root,
+            None => bail!("没有打开的工作区"),
+        };
+        let token = self.begin_progress("生成 compile_commands.json").await;
+        let config = self.config.cmake.clone();
+        let generated = tokio::task::spawn_blocking(move || cmake::generate(&config, &root)).await;
+        if let Some(token) = token {
+            let message = match &generated {
+                Ok(Ok(_)) => "完成",
+                _ => "失败",
+            };
+            self.send_to_frontend(&json!({
+                "jsonrpc": "2.0",
+                "method": notification::Progress::METHOD,
+                "params": {"token": token, "value": {"kind": "end", "message": message}},
+            }))?;
+        }
+        let database = generated??;
+        self.request_restart();
+        Ok(json!({"path": database}))
+    }
+
+    /// 记录分片的后台索引进度，把合并后的进度交给前端，第一个分片开始索引时创建前端的进度。
+    async fn merge_index_progress(&self, shard: usize, value: &Value) -> Result<()> {
+        match self.index_progress.observe(shard, value) {
+            Step::Create => {
+                let token = self.begin_progress(index_progress::TITLE).await;
+                for progress in self.index_progress.created(token) {
+                    self.send_to_frontend(&progress)?;
+                }
+            }
+            Step::Send(progress) => self.send_to_frontend(&progress)?,
+            Step::Nothing => {}
+        }
+        Ok(())
+    }
+
+    /// 把文件状态的进度交给前端，有文件开始处理时创建前端的进度。
+    async fn report_file_status(&self, step: Step) -> Result<()> {
+        match step {
+            Step::Create => {
+                let token = self.begin_progress(file_status::TITLE).await;
+                for progress in self.file_status.created(token) {
+                    self.send_to_frontend(&progress)?;
+                }
+            }
+            Step::Send(progress) => self.send_to_frontend(&progress)?,
+            Step::Nothing => {}
+        }
+        Ok(())
+    }
+
+    /// 前端支持 `window.workDoneProgress` 时创建进度并报告开始。
+    ///
+    /// # 返回
+    ///
+    /// 创建的进度 token；前端不支持或者创建失败时返回 `None`
+    async fn begin_progress(&self, title: &str) -> Option<String> {
+        let supported = self
+            .initialize_params
+            .borrow()
+            .as_ref()
+            .is_some_and(|params| {
+                params
+                    .pointer("/capabilities/window/workDoneProgress")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false)
+            });
+        if !supported {
+            return None;
+        }
+        let token = format!(
+            "codefuse/progress-{}",
+            self.request_counter.fetch_add(1, Ordering::Relaxed)
+        );
+        if let Err(e) = self
+            .request_frontend(
+                request::WorkDoneProgressCreate::METHOD,
+                json!({"token": token}),
+            )
+            .await
+        {
+            debug!("无法创建进度 {}: {:?}", title, e);
+            return None;
+        }
+        self.send_to_frontend(&json!({
+            "jsonrpc": "2.0",
+            "method": notification::Progress::METHOD,
+            "params": {"token": token, "value": {"kind": "begin", "title": title}},
+        }))
+        .ok()?;
+        Some(token)
+    }
+
+    /// 收集诊断附带的所有修复，合成一个编辑并请求前端应用。
+    ///
+    /// # 参数
+    ///
+    /// * `uri` - 只处理这个文档；为 `None` 时处理所有有诊断的文档
+    ///
+    /// # 返回
+    ///
+    /// 返回 `{"applied": bool, "fixes": n}`
+    async fn apply_all_fixits(&self, uri: Option<Url>) -> Result<Value> {
+        let uris = match uri {
+            Some(uri) => vec![uri],
+            None => self.diagnostics.uris(),
+        };
+
+        let mut fixes = Vec::new();
+        for uri in uris {
+            let shard = self.shard_for_uri(&uri);
+            for diagnostic in self.diagnostics.get(&uri) {
+                let params = fixits::quickfix_params(&uri, &diagnostic);
+                match self
+                    .request_backend(shard, request::CodeActionRequest::METHOD, params)
+                    .await
+                {
+                    Ok(actions) => fixes.push(fixits::preferred_fix(&actions)),
+                    Err(e) => debug!("无法获取 {} 的修复: {:?}", uri, e),
+                }
+            }
+        }
+
+        let (edit, count) = fixits::compose_workspace_edit(fixes);
+        if count == 0 {
+            return Ok(json!({"applied": false, "fixes": 0}));
+        }
+        let response = self
+            .request_frontend(
+                request::ApplyWorkspaceEdit::METHOD,
+                json!({"label": "Apply all fix-its", "edit": edit}),
+            )
+            .await?;
+        let applied = response
+            .get("applied")
+            .and_then(|a| a.as_bool())
+            .unwrap_or(false);
+        Ok(json!({"applied": applied, "fixes": count}))
+    }
+
+    /// 处理 `codefuse/renamePreview`：向后端请求重命名，返回摘要而不应用。
+    pub(crate) async fn rename_preview(&self, rpc: &Value) -> Result<Value> {
+        let params = rpc.get("params").cloned().unwrap_or(json!(null));
+        let new_name = params
+            .get("newName")
+            .and_then(|n| n.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let uri = params
+            .pointer("/textDocument/uri")
+            .and_then(|u| u.as_str())
+            .and_then(|u| Url::parse(u).ok());
+
+        let Some(uri) = uri else {
+            bail!("缺少 textDocument.uri");
+        };
+        let result = self
+            .request_backend(self.shard_for_uri(&uri), request::Rename::METHOD, params)
+            .await?;
+        let files = rename::parse_rename_response(&result)?;
+        let preview =
+            rename::build_preview(&new_name, &files, &self.workspace.roots(), &self.documents);
+        Ok(json!(preview))
+    }
+
+    /// 前端的请求开始：记录到达时间和排队深度。
+    fn request_started(&self, rpc: &Value, method: &str) {
+        if rpc.get("id").is_none() {
+            return;
+        }
+        let queue = QueueDepth {
+            pending_backend_requests: self.responses.pending(),
+            handlers_waiting: self.metrics.snapshot().handlers_waiting,
+        };
+        self.lifecycle.started(rpc, method, queue);
+    }
+
+    /// 前端请求的响应已经交给前端，`failed` 表示是否以错误应答。结束的请求依次交给各个订阅者。
+    fn request_finished(&self, id: &Value, failed: bool) {
+        let Some(request) = self.lifecycle.finished(id, failed) else {
+            return;
+        };
+        let observers: [&dyn RequestObserver; 4] = [
+            &*self.telemetry,
+            &self.stats,
+            &self.history,
+            &self.slow_requests,
+        ];
+        for observer in observers {
+            observer.finished(&request);
+        }
+    }
+
+    /// 代替后端应答前端的请求，错误使用 `InternalError` 错误码。
+    fn respond_to_frontend(&self, rpc: &Value, result: Result<Value>) -> Result<()> {
+        self.send_to_frontend(&handlers::response(rpc, result))
+    }
+
+    /// 直接向前端发送一条代理生成的消息。
+    pub fn send_to_frontend(&self, rpc: &Value) -> Result<()> {
+        self.write_to_frontend(rpc.clone())?;
+        if rpc.get("method").is_none()
+            && let Some(id) = rpc.get("id")
+        {
+            self.request_finished(id, rpc.get("error").is_some());
+        }
+        Ok(())
+    }
+
+    /// 把消息放进发给前端的通道，虚拟文档中的 URI 和位置先换回原文档。
+    fn write_to_frontend(&self, rpc: Value) -> Result<()> {
+        if rpc.get("method").is_none() {
+            self.lifecycle.responding(&rpc);
+        }
+        let mut layouts = Layouts::new();
+        self.notebooks.add_layouts(&mut layouts);
+        self.embedded.add_layouts(&mut layouts);
+        if layouts.is_empty() && !self.virtual_documents.has_pending() {
+            self.frontend_sender.send(Message::new(rpc))?;
+            return Ok(());
+        }
+        for rpc in self.virtual_documents.to_sources(rpc, &layouts) {
+            self.frontend_sender.send(Message::new(rpc))?;
+        }
+        Ok(())
+    }
+
+    /// 由代理向前端发起请求并等待结果。
+    ///
+    /// # 错误
+    ///
+    /// 如果前端返回错误、通道已关闭或者超时没有响应，返回错误
+    pub async fn request_frontend(&self, method: &str, params: Value) -> Result<Value> {
+        self.request_via(&self.frontend_sender, method, params)
+            .await
+    }
+
+    /// 向前端发送请求，只关心前端是否应答：在 `timeout` 内收到任何响应（包括错误）时返回 `true`。
+    ///
+    /// 前端不认识的 `$/` 请求也会以 MethodNotFound 应答，可以用来确认编辑器还在。
+    pub async fn ping_frontend(&self, method: &str, timeout: Duration) -> bool {
+        let Ok(waiter) = self.send_internal_request(&self.frontend_sender, method, json!(null))
+        else {
+            return false;
+        };
+        match tokio::time::timeout(timeout, waiter).await {
+            Ok(response) => response.is_ok(),
+            Err(_) => {
+                self.internal_requests
+                    .retain(|_, waiter| !waiter.is_closed());
+                false
+            }
+        }
+    }
+
+    /// 工作区根目录。
+    pub fn workspace_roots(&self) -> Vec<PathBuf> {
+        self.workspace.roots()
+    }
+
+    /// 订阅前端的 initialize 参数，前端发送 initialize 之前值为 `None`。
+    pub fn subscribe_initialize(&self) -> watch::Receiver<Option<Value>> {
+        self.initialize_params.subscribe()
+    }
+
+    /// 编辑器重新连接时恢复会话所需的状态。
+    pub fn session(&self) -> &SessionState {
+        &self.session
+    }
+
+    /// 发布后端之外的来源为文档提供的诊断，与其他来源的诊断合并后发给前端。
+    ///
+    /// # 参数
+    ///
+    /// * `source` - 诊断的来源，`[diagnostics] sources` 用这个名称开关
+    /// * `uri` - 文档的 URI
+    /// * `diagnostics` - 这个来源当前的全部诊断，空列表清除这个来源的诊断
+    ///
+    /// # 错误
+    ///
+    /// 如果发送给前端失败，返回错误
+    pub fn publish_diagnostics(
+        &self,
+        source: &str,
+        uri: &Url,
+        diagnostics: Vec<Value>,
+    ) -> Result<()> {
+        let mut rpc = json!({
+            "jsonrpc": "2.0",
+            "method": notification::PublishDiagnostics::METHOD,
+            "params": {"uri": uri, "diagnostics": diagnostics},
+        });
+        if let Some(doc) = self.documents.get(uri) {
+            rpc["params"]["version"] = json!(doc.version);
+        }
+        self.diagnostic_sources.merge(source, &mut rpc);
+        if !self.diagnostics.update(&rpc) {
+            return Ok(());
+        }
+        self.send_to_frontend(&rpc)
+    }
+
+    /// 等待后端响应的请求，监管者切换后端时重放只读请求，其他请求以错误应答。
+    pub fn journal(&self) -> &RequestJournal {
+        &self.journal
+    }
+
+    /// 代理的运行指标，读取循环的并发限制器把排队情况记录在这里。
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// 请求跨度的记录器，配置了 OTLP 端点时由导出任务定期取出跨度。
+    pub fn telemetry(&self) -> Arc<Telemetry> {
+        Arc::clone(&self.telemetry)
+    }
+
+    /// 会话统计，监管者在切换后端时记录重启。
+    pub fn stats(&self) -> &SessionStats {
+        &self.stats
+    }
+
+    /// 把会话统计写入第一个工作区根目录，没有工作区时不写入，失败只记录日志。
+    pub fn save_stats(&self) {
+        let Some(root) = self.workspace.roots().into_iter().next() else {
+            return;
+        };
+        match self.stats.save(&root) {
+            Ok(path) => info!("会话统计已写入 {}", path.display()),
+            Err(e) => warn!("无法写入会话统计: {:?}", e),
+        }
+    }
+
+    /// 把会话状态写入第一个工作区根目录，没有开启时不写入，失败只记录日志。
+    pub fn save_state(&self) {
+        match self.state.save() {
+            Ok(Some(path)) => debug!("会话状态已写入 {}", path.display()),
+            Ok(None) => {}
+            Err(e) => warn!("无法写入会话状态: {:?}", e),
+        }
+    }
+
+    /// 各分片后端的运行状态，由监管者更新。
+    pub fn health(&self) -> &Health {
+        &self.health
+    }
+
+    /// 已转发给后端、还没有收到响应的前端请求数量。
+    pub fn pending_requests(&self) -> usize {
+        self.responses.pending()
+    }
+
+    /// 已转发给后端、还没有收到响应的前端请求的 id 和方法。
+    pub fn pending_request_methods(&self) -> Vec<(String, String)> {
+        self.responses.pending_requests()
+    }
+
+    /// 一个方向上注册了处理器的方法和模式，以及各自的处理器数量。
+    pub fn handler_patterns(&self, direction: Direction) -> Vec<(String, usize)> {
+        match direction {
+            Direction::Frontend => self.handlers_from_frontend.patterns(),
+            Direction::Backend => self.handlers_from_backend.patterns(),
+        }
+    }
+
+    /// 顺序敏感的消息按文档排队的通道，读取循环在启动处理任务之前领取凭证。
+    pub fn lanes(&self) -> &DocumentLanes {
+        &self.lanes
+    }
+
+    /// 最近完成的前端请求。
+    pub fn history(&self) -> &RequestHistory {
+        &self.history
+    }
+
+    /// 各分片后端最近的标准错误输出，由监管者记录。
+    pub fn backend_log(&self) -> &BackendLog {
+        &self.backend_log
+    }
+
+    pub fn crash_reports(&self) -> &CrashReports {
+        &self.crash_reports
+    }
+
+    /// 后台索引进度。
+    pub fn index_progress(&self) -> &IndexProgress {
+        &self.index_progress
+    }
+
+    /// 各文件的 clangd 状态。
+    pub fn file_status(&self) -> &FileStatus {
+        &self.file_status
+    }
+
+    /// 影子后端，没有配置时返回 `None`。
+    pub fn shadow(&self) -> Option<&Arc<Shadow>> {
+        self.shadow.as_ref()
+    }
+
+    /// 前端打开的所有文档。
+    pub fn documents(&self) -> &DocumentStore {
+        &self.documents
+    }
+
+    /// 代理的配置，没有调用 [`Dispatcher::with_config`] 时是默认配置。
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// 后端在 `initialize` 响应中声明的能力。
+    pub fn backend_capabilities(&self) -> &BackendCapabilities {
+        &self.capabilities
+    }
+
+    /// 后端发来的 showMessage 和 logMessage 的去重。
+    pub fn message_throttle(&self) -> &MessageThrottle {
+        &self.message_throttle
+    }
+
+    /// 预取的响应缓存。
+    pub fn prefetcher(&self) -> &Prefetcher {
+        &self.prefetcher
+    }
+
+    /// 发送消息到前端的通道。
+    pub fn frontend_sender(&self) -> UnboundedSender<Message> {
+        self.frontend_sender.clone()
+    }
+
+    /// 发送消息到 `shard` 分片后端的通道。
+    pub fn backend_sender(&self, shard: usize) -> UnboundedSender<Message> {
+        self.shards[shard].sender.clone()
+    }
+
+    /// 负责指定文档的分片下标：`[languages.shards]` 为文档的语言指定了分片时使用这个分片，否则按路径选择。
+    pub fn shard_for_uri(&self, uri: &Url) -> usize {
+        self.languages
+            .shard(uri)
+            .and_then(|name| self.shards.iter().position(|shard| shard.name == name))
+            .unwrap_or_else(|| shard::shard_for_uri(&self.shards, uri))
+    }
+
+    /// 决定一条来自前端的消息应该如何路由，针对文档的消息按 [`Dispatcher::shard_for_uri`] 选择分片。
+    fn route(&self, method: &str, rpc: &Value) -> Route {
+        match shard::route(&self.shards, method, rpc) {
+            Route::Shard(_) if self.shards.len() > 1 => rpc
+                .pointer("/params/textDocument/uri")
+                .and_then(|uri| uri.as_str())
+                .and_then(|uri| Url::parse(uri).ok())
+                .map_or(Route::Shard(0), |uri| {
+                    Route::Shard(self.shard_for_uri(&uri))
+                }),
+            route => route,
+        }
+    }
+
+    /// 记录 initialize 请求中的工作区信息，并在后台生成 ctags 索引。
+    ///
+    /// 只有客户端声明支持 `window.workDoneProgress` 时，clangd 才会报告后台索引进度，
+    /// 否则代理无法得知索引何时完成，此时直接把 `workspace/symbol` 交给后端。
+    /// 如果客户端不支持文件监视，还会改写参数，由代理代为监视。
+    fn on_initialize(&self, rpc: &mut Value) {
+        let Some(params) = rpc.get_mut("params") else {
+            return;
+        };
+        self.workspace.set_from_initialize(params);
+        let roots = self.workspace.roots();
+        self.directory_configs.set_roots(roots.clone());
+        self.warmup.load(&roots);
+        // 上次会话的后端能力在本次 initialize 响应到达之前使用
+        let saved = self.state.load(&roots);
+        if let Some(backend) = saved.as_ref().and_then(|saved| saved.backend.as_ref())
+            && self.capabilities.snapshot().is_none()
+        {
+            self.capabilities.record(backend);
+        }
+        if let Err(e) = KnownWorkspaces::remember(&roots) {
+            warn!("无法记录工作区: {:?}", e);
+        }
+        self.file_watcher.enable_for_client(params, roots.clone());
+        self.file_status.on_initialize(params);
+        self.tidy_policy.read().unwrap().on_initialize(params);
+        // 告诉用户工作区配置中哪些设置因为不受信任没有使用
+        if let Some(message) = self
+            .config_source
+            .as_ref()
+            .and_then(|source| source.trust().message())
+        {
+            self.show_message(MessageType::WARNING, &message);
+        }
+        self.initialize_params.send_replace(Some(params.clone()));
+
+        if self.compile_flags.uses_bazel() {
+            let flags = Arc::clone(&self.compile_flags);
+            let bazel_roots = roots.clone();
+            tokio::task::spawn_blocking(move || {
+                for root in bazel_roots
+                    .iter()
+                    .filter(|root| !compile_flags::has_database(root))
+                {
+                    if let Err(e) = flags.load_bazel(root) {
+                        warn!("无法取得 Bazel 编译参数: {:?}", e);
+                    }
+                }
+            });
+        }
+
+        let work_done_progress = params
+            .pointer("/capabilities/window/workDoneProgress")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        if !work_done_progress {
+            self.symbol_index.mark_backend_ready();
+            return;
+        }
+        if saved.is_some_and(|saved| state::index_reusable(&saved, &roots)) {
+            info!("上次会话中 clangd 已完成后台索引，不再生成 ctags 索引");
+            return;
+        }
+
+        let index = Arc::clone(&self.symbol_index);
+        tokio::spawn(async move {
+            if let Err(e) = index.rebuild(&roots).await {
+                warn!(
+                    "ctags 索引生成失败，workspace/symbol 将直接交给后端: {:?}",
+                    e
+                );
+            }
+        });
+    }
+
+    /// 处理 `workspace/didChangeWorkspaceFolders` 通知，更新文件夹列表以及依赖它的组件。
+    ///
+    /// 通知本身仍会照常转发给后端。
+    fn on_workspace_folders_changed(&self, rpc: &Value) {
+        let params = rpc.get("params").cloned().unwrap_or(json!(null));
+        let params: DidChangeWorkspaceFoldersParams = match serde_json::from_value(params) {
+            Ok(params) => params,
+            Err(e) => {
+                warn!("无法解析 didChangeWorkspaceFolders: {}", e);
+                return;
+            }
+        };
+        self.workspace.apply_change(params.event);
+
+        let roots = self.workspace.roots();
+        self.directory_configs.set_roots(roots.clone());
+        if let Err(e) = self.file_watcher.set_roots(roots.clone()) {
+            warn!("无法监视新的工作区文件夹: {:?}", e);
+        }
+        if !self.symbol_index.is_backend_ready() {
+            let index = Arc::clone(&self.symbol_index);
+            tokio::spawn(async move {
+                if let Err(e) = index.rebuild(&roots).await {
+                    warn!("ctags 索引生成失败: {:?}", e);
+                }
+            });
+        }
+    }
+
+    /// 处理 `workspace/didChangeConfiguration` 通知，按编辑器设置中的 `codefuse.*` 切换功能。
+    ///
+    /// 通知本身仍会照常转发给后端。
+    fn on_configuration_changed(self: &Arc<Self>, rpc: &Value) {
+        let settings = rpc.pointer("/params/settings").unwrap_or(&Value::Null);
+        for (feature, enabled) in self.features.apply(settings) {
+            info!(
+                "编辑器设置{}了 {}.{}",
+                if enabled { "打开" } else { "关闭" },
+                features::SECTION,
+                feature.key()
+            );
+            match feature {
+                Feature::Prefetch if !enabled && self.prefetcher.is_enabled() => {
+                    self.prefetcher.invalidate();
+                    self.emit_event(ProxyEvent::CacheCleared { cache: "prefetch" });
+                }
+                // 按新的开关重新检查打开的文档
+                Feature::Spellcheck if self.spellcheck.is_enabled() => {
+                    for doc in self.documents.documents() {
+                        let params =
+                            json!({"textDocument": {"uri": doc.uri, "version": doc.version}});
+                        self.schedule_local_checks(&params);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// 后端索引就绪之前，使用 ctags 索引应答 `workspace/symbol` 请求；应该交给后端时返回 `None`。
+    pub(crate) fn local_workspace_symbols(&self, rpc: &Value) -> Option<Value> {
+        if !self.symbol_index.should_answer() || !self.features.is_enabled(Feature::Fallbacks) {
+            return None;
+        }
+        let query = rpc
+            .pointer("/params/query")
+            .and_then(|q| q.as_str())
+            .unwrap_or("");
+        let symbols = self
+            .symbol_index
+            .query(query, symbol_index::DEFAULT_QUERY_LIMIT);
+        Some(json!(symbols))
+    }
+
     /// 格式化通知或请求消息。
     ///
     /// 根据消息是否包含 `id` 字段，将其格式化为标准的 JSON-RPC 通知或请求。
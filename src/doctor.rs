@@ -0,0 +1,143 @@
+//! # 环境检查模块
+//!
+//! `codefuse doctor` 检查代理的运行环境：使用的配置文件、后端程序及其版本，
+//! 以及代理对这个版本启用的兼容垫片，便于排查编辑器中的问题。
+
+use anyhow::Result;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::capabilities::parse_clangd_version;
+use crate::compat::{self, OLDEST_SUPPORTED, Shim, Version};
+use crate::config::{BackendConfig, Config};
+use crate::container::ContainerSpec;
+use crate::platform;
+use crate::ssh::SshTarget;
+
+/// 环境检查的结果。
+///
+/// - `config`: 使用的配置文件，没有时使用默认配置
+/// - `command`: 配置的后端命令
+/// - `program`: 在 `PATH` 中找到的后端程序；通过 ssh 启动的后端是本地的 ssh，在容器中运行的后端是容器引擎
+/// - `version`: 后端 `--version` 输出的第一行
+/// - `clangd_version`: 从版本输出中解析出的 clangd 版本
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DoctorReport {
+    pub config: Option<PathBuf>,
+    pub command: String,
+    pub program: Option<PathBuf>,
+    pub version: Option<String>,
+    pub clangd_version: Option<Version>,
+}
+
+impl DoctorReport {
+    /// 检查当前环境。
+    ///
+    /// # 参数
+    ///
+    /// * `config` - 加载的配置
+    /// * `config_path` - `--config` 指定的配置文件
+    pub fn collect(config: &Config, config_path: Option<&Path>) -> Self {
+        let config_file = Config::discover_path(config_path);
+        let (program, version) = probe_backend(&config.backend);
+        Self {
+            config: config_file,
+            command: config.backend.command.clone(),
+            program,
+            clangd_version: version.as_deref().and_then(parse_clangd_version),
+            version,
+        }
+    }
+
+    /// 对检测到的 clangd 版本启用的兼容垫片。
+    pub fn shims(&self) -> Vec<&'static Shim> {
+        compat::active_shims(self.clangd_version)
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.config {
+            Some(path) => writeln!(f, "配置文件: {}", path.display())?,
+            None => writeln!(f, "配置文件: 无（使用默认配置）")?,
+        }
+        match &self.program {
+            Some(program) => writeln!(f, "后端程序: {}", program.display())?,
+            None => writeln!(f, "后端程序: 找不到 {}", self.command)?,
+        }
+        writeln!(f, "后端版本: {}", self.version.as_deref().unwrap_or("未知"))?;
+        match self.clangd_version {
+            Some((major, minor, patch)) => {
+                write!(f, "clangd 版本: {}.{}.{}", major, minor, patch)?;
+                if (major, minor, patch) < OLDEST_SUPPORTED {
+                    write!(f, "（早于支持的最早版本 {}）", OLDEST_SUPPORTED.0)?;
+                }
+                writeln!(f)?;
+            }
+            None => writeln!(f, "clangd 版本: 未知")?,
+        }
+        let shims = self.shims();
+        if shims.is_empty() {
+            return writeln!(f, "兼容垫片: 无");
+        }
+        writeln!(f, "兼容垫片:")?;
+        for shim in shims {
+            writeln!(f, "  {}: {}", shim.name, shim.description)?;
+        }
+        Ok(())
+    }
+}
+
+/// 运行 `doctor` 子命令，把检查结果输出到标准输出。
+pub fn run(config: &Config, config_path: Option<&Path>) -> Result<()> {
+    print!("{}", DoctorReport::collect(config, config_path));
+    Ok(())
+}
+
+/// 运行后端的 `--version`。
+///
+/// ssh 后端的版本通过同样的 ssh 连接在远程机器上查询，容器中的后端在一次性的容器中查询。
+///
+/// # 返回
+///
+/// 在 `PATH` 中找到的程序（ssh 或容器引擎），以及版本输出的第一行
+pub fn probe_backend(backend: &BackendConfig) -> (Option<PathBuf>, Option<String>) {
+    let container = backend.container.as_ref();
+    let container = container.and_then(|container| ContainerSpec::new(container).ok());
+    let (program, args) = if let Some(container) = &container {
+        (
+            platform::resolve_program(&container.engine),
+            container.version_args(&backend.command),
+        )
+    } else if let Some(target) = SshTarget::parse(&backend.command) {
+        let version = ["--version".to_string()];
+        let args = target.ssh_args(&version, &[], &backend.ssh);
+        (platform::resolve_program("ssh"), args)
+    } else {
+        (
+            platform::resolve_program(&backend.command),
+            vec!["--version".to_string()],
+        )
+    };
+    let version = program
+        .as_deref()
+        .and_then(|program| backend_version(program, &args));
+    (program, version)
+}
+
+/// 后端 `--version` 输出的第一行，程序无法运行时返回 `None`。
+fn backend_version(program: &Path, args: &[String]) -> Option<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())?;
+    Some(line.to_string())
+}
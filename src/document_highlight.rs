@@ -0,0 +1,153 @@
+//! # 文档高亮模块
+//!
+//! 在很大的翻译单元中 clangd 的 `textDocument/documentHighlight` 经常很慢。开启 `[document_highlight] fallback` 后，
+//! 后端超过 `timeout_ms` 没有应答（或者正在重启）时，代理以文档中与光标处标识符相同的文本作为高亮应答，
+//! 种类为 `Text`，编辑器立即有高亮；后端迟到的响应丢弃。
+//!
+//! 匹配按整个标识符进行（前后不能是字母、数字或下划线），不区分代码、注释和字符串。
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde_json::{Value, json};
+use std::time::Duration;
+use tower_lsp::lsp_types::request::{DocumentHighlightRequest, Request};
+use tower_lsp::lsp_types::{DocumentHighlightKind, Position, Range};
+
+use crate::config::DocumentHighlightConfig;
+use crate::dispatcher::Dispatcher;
+use crate::document_store::{self, DocumentStore};
+use crate::fallback::{Arrival, Cursor, FallbackRequests};
+use crate::handlers::{FALLBACK_PRIORITY, HandlerCtx, Verdict};
+use crate::trace::Direction;
+
+/// 最多返回的高亮数。
+const MAX_HIGHLIGHTS: usize = 1000;
+
+/// 后端超时时按文本匹配的文档高亮。
+pub struct HighlightFallback {
+    enabled: bool,
+    timeout: Duration,
+    /// 等待后端应答的请求和光标位置
+    pending: FallbackRequests<Cursor>,
+}
+
+impl Default for HighlightFallback {
+    fn default() -> Self {
+        Self::new(&DocumentHighlightConfig::default())
+    }
+}
+
+impl HighlightFallback {
+    pub fn new(config: &DocumentHighlightConfig) -> Self {
+        Self {
+            enabled: config.fallback,
+            timeout: Duration::from_millis(config.timeout_ms),
+            pending: FallbackRequests::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 等待后端的时间，超过后以文本匹配的结果应答。
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// 前端的文档高亮请求交给后端之前调用。
+    pub fn request(&self, id: &Value, cursor: Cursor) {
+        self.pending.insert(id, cursor);
+    }
+
+    /// 后端的文档高亮响应到达。
+    ///
+    /// # 返回
+    ///
+    /// 后端超过等待时间、代理已经应答时返回 `false`，响应应当丢弃
+    pub fn respond(&self, id: &Value) -> bool {
+        !matches!(self.pending.arrive(id), Arrival::Late)
+    }
+
+    /// 后端超过了等待时间，改由代理应答。
+    ///
+    /// # 返回
+    ///
+    /// 应答前端的高亮，文档已经关闭或者修改过时为 `null`；后端已经响应时返回 `None`
+    pub fn expire(&self, id: &Value, shard: usize, documents: &DocumentStore) -> Option<Value> {
+        let cursor = self.pending.expire(id, shard)?;
+        match cursor.document(documents) {
+            Some(doc) => Some(json!(highlights(&doc.text, cursor.position))),
+            None => Some(Value::Null),
+        }
+    }
+
+    /// `shard` 分片的后端退出，不再等待它的响应。
+    pub fn backend_exited(&self, shard: usize) {
+        self.pending.backend_exited(shard);
+    }
+}
+
+/// 光标所在（或者紧挨着光标）的标识符，不以数字开头。
+pub fn word_at(text: &str, position: Position) -> Option<&str> {
+    let offset = document_store::offset_at(text, position);
+    let start = offset - document_store::word_before(&text[..offset]).len();
+    let end = text[offset..]
+        .find(|c: char| !is_word_char(c))
+        .map_or(text.len(), |i| offset + i);
+    let word = &text[start..end];
+    word.chars()
+        .next()
+        .is_some_and(|c| !c.is_numeric())
+        .then_some(word)
+}
+
+/// 文档中与光标处标识符相同的所有完整标识符的范围，种类为 `Text`，最多 [`MAX_HIGHLIGHTS`] 个。
+pub fn highlights(text: &str, position: Position) -> Vec<Value> {
+    let Some(word) = word_at(text, position) else {
+        return Vec::new();
+    };
+    let mut found = Vec::new();
+    for (line, content) in text.split('\n').enumerate() {
+        for (start, _) in content.match_indices(word) {
+            let end = start + word.len();
+            if content[..start].ends_with(is_word_char) || content[end..].starts_with(is_word_char)
+            {
+                continue;
+            }
+            let character = content[..start].encode_utf16().count() as u32;
+            let length = word.encode_utf16().count() as u32;
+            let range = Range::new(
+                Position::new(line as u32, character),
+                Position::new(line as u32, character + length),
+            );
+            found.push(json!({"range": range, "kind": DocumentHighlightKind::TEXT}));
+            if found.len() >= MAX_HIGHLIGHTS {
+                return found;
+            }
+        }
+    }
+    found
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// 注册文档高亮的处理器，请求转发给后端时开始计时。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        DocumentHighlightRequest::METHOD,
+        FALLBACK_PRIORITY,
+        start_fallback,
+    );
+}
+
+/// 记下请求的位置，后端超时没有应答时以文本匹配的结果应答。
+fn start_fallback(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        ctx.dispatcher().start_document_highlight(&rpc);
+        Ok(Verdict::Continue(rpc))
+    })
+}
@@ -0,0 +1,82 @@
+//! # 文档观察者模块
+//!
+//! 前端的 `didOpen`/`didChange`/`didSave`/`didClose` 通知经过代理时，调度器解析一次参数，
+//! 依次通知所有订阅了文档生命周期的组件（文档存储、预取缓存、诊断存储以及注册的扩展功能），
+//! 这些组件不需要各自注册处理器来截获这几个方法。
+
+use anyhow::Result;
+use serde_json::Value;
+use tower_lsp::lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    Notification,
+};
+use tower_lsp::lsp_types::{
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DidSaveTextDocumentParams, Url,
+};
+
+/// 文档生命周期的订阅者。
+///
+/// 每个方法对应一种通知，默认什么也不做，订阅者只需要实现关心的事件。
+/// 方法在转发通知之前同步调用，耗时的工作应当交给后台任务。
+pub trait DocumentObserver: Send + Sync {
+    /// 前端打开了文档。
+    fn did_open(&self, _params: &DidOpenTextDocumentParams) {}
+
+    /// 前端修改了文档。
+    fn did_change(&self, _params: &DidChangeTextDocumentParams) {}
+
+    /// 前端保存了文档。
+    fn did_save(&self, _params: &DidSaveTextDocumentParams) {}
+
+    /// 前端关闭了文档。
+    fn did_close(&self, _params: &DidCloseTextDocumentParams) {}
+}
+
+/// 解析后的文档同步通知。
+#[derive(Debug, Clone)]
+pub enum DocumentEvent {
+    Open(DidOpenTextDocumentParams),
+    Change(DidChangeTextDocumentParams),
+    Save(DidSaveTextDocumentParams),
+    Close(DidCloseTextDocumentParams),
+}
+
+impl DocumentEvent {
+    /// 解析文档同步通知。
+    ///
+    /// # 返回
+    ///
+    /// 不是文档同步通知时返回 `None`，参数无法解析时返回错误
+    pub fn parse(method: &str, params: &Value) -> Option<Result<Self>> {
+        let params = params.clone();
+        let event = match method {
+            DidOpenTextDocument::METHOD => serde_json::from_value(params).map(Self::Open),
+            DidChangeTextDocument::METHOD => serde_json::from_value(params).map(Self::Change),
+            DidSaveTextDocument::METHOD => serde_json::from_value(params).map(Self::Save),
+            DidCloseTextDocument::METHOD => serde_json::from_value(params).map(Self::Close),
+            _ => return None,
+        };
+        Some(event.map_err(Into::into))
+    }
+
+    /// 事件所属的文档。
+    pub fn uri(&self) -> &Url {
+        match self {
+            Self::Open(params) => &params.text_document.uri,
+            Self::Change(params) => &params.text_document.uri,
+            Self::Save(params) => &params.text_document.uri,
+            Self::Close(params) => &params.text_document.uri,
+        }
+    }
+
+    /// 调用订阅者中对应这个事件的方法。
+    pub fn notify(&self, observer: &dyn DocumentObserver) {
+        match self {
+            Self::Open(params) => observer.did_open(params),
+            Self::Change(params) => observer.did_change(params),
+            Self::Save(params) => observer.did_save(params),
+            Self::Close(params) => observer.did_close(params),
+        }
+    }
+}
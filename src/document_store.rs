@@ -0,0 +1,191 @@
+//! # 文档存储模块
+//!
+//! 这个模块根据前端发来的 `didOpen`/`didChange`/`didClose` 通知维护所有打开文档的最新内容和版本，
+//! 供需要重放文档（例如切换到备用后端）或在本地分析文档的功能使用。
+
+use dashmap::DashMap;
+use log::warn;
+use serde_json::{Value, json};
+use tower_lsp::lsp_types::notification::{DidOpenTextDocument, Notification};
+use tower_lsp::lsp_types::{
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams, Position,
+    TextDocumentContentChangeEvent, Url,
+};
+
+use crate::document_observer::{DocumentEvent, DocumentObserver};
+
+/// 一个打开的文档。
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub uri: Url,
+    pub language_id: String,
+    pub version: i32,
+    pub text: String,
+}
+
+impl Document {
+    /// 生成重新打开这个文档所需的 `textDocument/didOpen` 通知。
+    pub fn did_open_notification(&self) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": DidOpenTextDocument::METHOD,
+            "params": {
+                "textDocument": {
+                    "uri": self.uri,
+                    "languageId": self.language_id,
+                    "version": self.version,
+                    "text": self.text,
+                }
+            }
+        })
+    }
+}
+
+/// 所有打开文档的存储。
+pub struct DocumentStore {
+    documents: DashMap<Url, Document>,
+}
+
+impl Default for DocumentStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DocumentStore {
+    /// 创建空的文档存储。
+    pub fn new() -> Self {
+        Self {
+            documents: DashMap::new(),
+        }
+    }
+
+    /// 根据前端发来的文档同步通知更新存储，其他消息被忽略。
+    ///
+    /// # 参数
+    ///
+    /// * `method` - 通知的方法名
+    /// * `params` - 通知的参数
+    pub fn apply(&self, method: &str, params: &Value) {
+        match DocumentEvent::parse(method, params) {
+            Some(Ok(event)) => event.notify(self),
+            Some(Err(e)) => warn!("无法解析 {}: {}", method, e),
+            None => {}
+        }
+    }
+
+    /// 获取文档的当前内容。
+    pub fn get(&self, uri: &Url) -> Option<Document> {
+        self.documents.get(uri).map(|doc| doc.clone())
+    }
+
+    /// 所有打开的文档。
+    pub fn documents(&self) -> Vec<Document> {
+        self.documents.iter().map(|doc| doc.clone()).collect()
+    }
+
+    /// 打开的文档数量。
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// 是否没有打开任何文档。
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+}
+
+impl DocumentObserver for DocumentStore {
+    fn did_open(&self, params: &DidOpenTextDocumentParams) {
+        let doc = &params.text_document;
+        self.documents.insert(
+            doc.uri.clone(),
+            Document {
+                uri: doc.uri.clone(),
+                language_id: doc.language_id.clone(),
+                version: doc.version,
+                text: doc.text.clone(),
+            },
+        );
+    }
+
+    fn did_change(&self, params: &DidChangeTextDocumentParams) {
+        let uri = &params.text_document.uri;
+        let Some(mut doc) = self.documents.get_mut(uri) else {
+            warn!("收到未打开文档的 didChange: {}", uri);
+            return;
+        };
+        for change in &params.content_changes {
+            apply_content_change(&mut doc.text, change);
+        }
+        doc.version = params.text_document.version;
+    }
+
+    fn did_close(&self, params: &DidCloseTextDocumentParams) {
+        self.documents.remove(&params.text_document.uri);
+    }
+}
+
+/// 把一次内容变更应用到文本上。
+///
+/// 没有 `range` 的变更表示整个文档被替换；位置使用 LSP 默认的 UTF-16 编码。
+pub fn apply_content_change(text: &mut String, change: &TextDocumentContentChangeEvent) {
+    match change.range {
+        Some(range) => {
+            let start = offset_at(text, range.start);
+            let end = offset_at(text, range.end).max(start);
+            text.replace_range(start..end, &change.text);
+        }
+        None => *text = change.text.clone(),
+    }
+}
+
+/// 文本中的标识符（字母或下划线开头，由字母、数字和下划线组成），按出现顺序。
+pub fn identifiers(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| word.chars().next().is_some_and(|c| !c.is_numeric()))
+}
+
+/// 文本末尾的标识符，通常是光标前正在输入的单词。
+pub fn word_before(text: &str) -> &str {
+    let start = text
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_alphanumeric() || *c == '_')
+        .last()
+        .map_or(text.len(), |(i, _)| i);
+    &text[start..]
+}
+
+/// 把 UTF-16 编码的 LSP 位置转换为字节偏移，超出范围的位置被截断到行尾或文本末尾。
+pub fn offset_at(text: &str, position: Position) -> usize {
+    let mut offset = 0;
+    for _ in 0..position.line {
+        match text[offset..].find('\n') {
+            Some(newline) => offset += newline + 1,
+            None => return text.len(),
+        }
+    }
+
+    let line_end = text[offset..]
+        .find('\n')
+        .map(|i| offset + i)
+        .unwrap_or(text.len());
+    let mut units = 0;
+    for (i, c) in text[offset..line_end].char_indices() {
+        if units >= position.character as usize {
+            return offset + i;
+        }
+        units += c.len_utf16();
+    }
+    line_end
+}
+
+/// 把字节偏移转换为 UTF-16 编码的 LSP 位置，与 [`offset_at`] 相反。
+pub fn position_at(text: &str, offset: usize) -> Position {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() as u32;
+    let character = before[line_start..].encode_utf16().count() as u32;
+    Position::new(line, character)
+}
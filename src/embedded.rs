@@ -0,0 +1,259 @@
+//! # 嵌入代码模块
+//!
+//! Arduino 草图、模板中的 C 代码块这类宿主文件不能直接交给 clangd。代理按 `[[embedded]]` 规则取出其中的 C/C++ 片段，
+//! 片段之外的内容换成空格，加上规则的前导代码后作为虚拟文档交给后端。虚拟文档与宿主文件的行列一一对应，
+//! 只差前导代码的行数，位置的换算由 [`crate::virtual_documents`] 完成。
+
+use dashmap::DashMap;
+use log::warn;
+use regex::{Regex, RegexBuilder};
+use serde_json::{Value, json};
+use std::ops::Range;
+use std::path::Path;
+use tower_lsp::lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    Notification, WillSaveTextDocument,
+};
+use tower_lsp::lsp_types::{TextDocumentContentChangeEvent, Url};
+
+use crate::config::EmbeddedRule;
+use crate::document_store::apply_content_change;
+use crate::virtual_documents::{Layout, Layouts, Slot};
+
+/// 宿主文件的文本同步通知。
+pub const SYNC_METHODS: &[&str] = &[
+    DidOpenTextDocument::METHOD,
+    DidChangeTextDocument::METHOD,
+    WillSaveTextDocument::METHOD,
+    DidSaveTextDocument::METHOD,
+    DidCloseTextDocument::METHOD,
+];
+
+struct Rule {
+    extensions: Vec<String>,
+    start: Option<Regex>,
+    end: Option<Regex>,
+    /// 以换行结束的前导代码
+    prelude: String,
+    language_id: String,
+    suffix: String,
+}
+
+impl Rule {
+    fn new(rule: &EmbeddedRule) -> Result<Self, regex::Error> {
+        let compile = |pattern: &Option<String>| {
+            pattern
+                .as_deref()
+                .map(|pattern| RegexBuilder::new(pattern).multi_line(true).build())
+                .transpose()
+        };
+        let mut prelude = rule.prelude.clone();
+        if !prelude.is_empty() && !prelude.ends_with('\n') {
+            prelude.push('\n');
+        }
+        Ok(Self {
+            extensions: rule.extensions.clone(),
+            start: compile(&rule.start)?,
+            end: compile(&rule.end)?,
+            prelude,
+            language_id: rule.language_id.clone(),
+            suffix: rule.suffix.clone(),
+        })
+    }
+
+    fn prelude_lines(&self) -> u32 {
+        self.prelude.matches('\n').count() as u32
+    }
+
+    /// 宿主文件中代码片段的字节范围。
+    fn regions(&self, text: &str) -> Vec<Range<usize>> {
+        let Some(start) = &self.start else {
+            return std::iter::once(0..text.len()).collect();
+        };
+        let mut regions = Vec::new();
+        let mut pos = 0;
+        while pos < text.len()
+            && let Some(open) = start.find_at(text, pos)
+        {
+            let close = self
+                .end
+                .as_ref()
+                .and_then(|end| end.find_at(text, open.end()));
+            regions.push(open.end()..close.map_or(text.len(), |close| close.start()));
+            let next = close.map_or(text.len(), |close| close.end());
+            // 空的开始和结束标记不能让搜索原地不动
+            if next <= open.start() {
+                break;
+            }
+            pos = next;
+        }
+        regions
+    }
+
+    /// 虚拟文档的内容：前导代码，加上片段之外换成空格的宿主文件。
+    ///
+    /// 每个字符换成与它的 UTF-16 长度相同数量的空格，换行保留，行列位置不变。
+    fn virtual_text(&self, text: &str) -> String {
+        let regions = self.regions(text);
+        let mut virtual_text = self.prelude.clone();
+        for (offset, ch) in text.char_indices() {
+            if matches!(ch, '\n' | '\r') || regions.iter().any(|region| region.contains(&offset)) {
+                virtual_text.push(ch);
+            } else {
+                virtual_text.extend(std::iter::repeat_n(' ', ch.len_utf16()));
+            }
+        }
+        virtual_text
+    }
+}
+
+/// 打开的宿主文件。
+struct Host {
+    rule: usize,
+    text: String,
+    virtual_uri: Url,
+}
+
+/// 按 `[[embedded]]` 规则打开的宿主文件。
+#[derive(Default)]
+pub struct EmbeddedDocuments {
+    rules: Vec<Rule>,
+    hosts: DashMap<Url, Host>,
+}
+
+impl EmbeddedDocuments {
+    /// 编译规则中的正则表达式，无效的规则被忽略。
+    pub fn new(rules: &[EmbeddedRule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|rule| match Rule::new(rule) {
+                Ok(compiled) => Some(compiled),
+                Err(e) => {
+                    warn!("忽略无效的嵌入代码规则 {:?}: {}", rule.extensions, e);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            rules,
+            hosts: DashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    /// 适用于文档的规则的下标，按扩展名匹配。
+    fn rule_for(&self, uri: &Url) -> Option<usize> {
+        let extension = Path::new(uri.path()).extension()?.to_str()?;
+        self.rules.iter().position(|rule| {
+            rule.extensions
+                .iter()
+                .any(|candidate| candidate.eq_ignore_ascii_case(extension))
+        })
+    }
+
+    /// 是否宿主文件的文本同步通知。
+    pub fn is_host_sync(&self, method: &str, params: &Value) -> bool {
+        self.is_enabled()
+            && SYNC_METHODS.contains(&method)
+            && params
+                .pointer("/textDocument/uri")
+                .and_then(|uri| uri.as_str())
+                .and_then(|uri| Url::parse(uri).ok())
+                .is_some_and(|uri| self.rule_for(&uri).is_some())
+    }
+
+    /// 把宿主文件的文本同步通知换成虚拟文档的通知，宿主文件的修改都变成虚拟文档的全文更新。
+    ///
+    /// # 返回
+    ///
+    /// 交给后端的通知；宿主文件没有打开或者参数无法解析时返回 `None`
+    pub fn to_virtual_document(&self, method: &str, params: &Value) -> Option<Value> {
+        let uri = params
+            .pointer("/textDocument/uri")
+            .and_then(|uri| uri.as_str())
+            .and_then(|uri| Url::parse(uri).ok())?;
+        let params = match method {
+            DidOpenTextDocument::METHOD => {
+                let rule = self.rule_for(&uri)?;
+                let text = params.pointer("/textDocument/text")?.as_str()?.to_string();
+                let host = Host {
+                    rule,
+                    virtual_uri: Url::parse(&format!("{}.{}", uri, self.rules[rule].suffix))
+                        .ok()?,
+                    text,
+                };
+                let params = json!({"textDocument": {
+                    "uri": host.virtual_uri,
+                    "languageId": self.rules[rule].language_id,
+                    "version": params.pointer("/textDocument/version"),
+                    "text": self.rules[rule].virtual_text(&host.text),
+                }});
+                self.hosts.insert(uri, host);
+                params
+            }
+            DidChangeTextDocument::METHOD => {
+                let Some(mut host) = self.hosts.get_mut(&uri) else {
+                    warn!("收到未打开的宿主文件的 didChange: {}", uri);
+                    return None;
+                };
+                for change in params
+                    .get("contentChanges")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                {
+                    match serde_json::from_value::<TextDocumentContentChangeEvent>(change.clone()) {
+                        Ok(change) => apply_content_change(&mut host.text, &change),
+                        Err(e) => warn!("无法解析 {} 的修改: {}", uri, e),
+                    }
+                }
+                json!({
+                    "textDocument": {
+                        "uri": host.virtual_uri,
+                        "version": params.pointer("/textDocument/version"),
+                    },
+                    "contentChanges": [{"text": self.rules[host.rule].virtual_text(&host.text)}],
+                })
+            }
+            DidCloseTextDocument::METHOD => {
+                let (_, host) = self.hosts.remove(&uri)?;
+                json!({"textDocument": {"uri": host.virtual_uri}})
+            }
+            // willSave 和 didSave 只替换文档；didSave 附带的宿主文件内容对后端没有意义
+            _ => {
+                let host = self.hosts.get(&uri)?;
+                let mut params = params.clone();
+                params["textDocument"]["uri"] = json!(host.virtual_uri);
+                if let Some(params) = params.as_object_mut() {
+                    params.remove("text");
+                }
+                params
+            }
+        };
+        Some(json!({"jsonrpc": "2.0", "method": method, "params": params}))
+    }
+
+    /// 宿主文件对应的虚拟文档，以及宿主文件在虚拟文档中的起始行（前导代码的行数）。
+    pub fn locate(&self, uri: &Url) -> Option<(Url, u32)> {
+        let host = self.hosts.get(uri)?;
+        Some((
+            host.virtual_uri.clone(),
+            self.rules[host.rule].prelude_lines(),
+        ))
+    }
+
+    /// 把打开的宿主文件的布局加入 `layouts`。前导代码中的位置换到宿主文件的第一行。
+    pub fn add_layouts(&self, layouts: &mut Layouts) {
+        for host in self.hosts.iter() {
+            let slot = Slot {
+                uri: host.key().clone(),
+                start: self.rules[host.rule].prelude_lines(),
+                lines: host.text.matches('\n').count() as u32 + 1,
+            };
+            layouts.insert(host.virtual_uri.to_string(), Layout { slots: vec![slot] });
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! # 代理事件模块
+//!
+//! 代理运行状态的变化（后端退出和重启、缓存被清空、请求超过速率限制）以 LSP 的 `telemetry/event`
+//! 通知告诉编辑器，编辑器扩展可以显示提示或者收集匿名的健康数据。
+//! 事件默认不发送，由 `[telemetry] events` 开启。
+
+use serde_json::{Value, json};
+
+/// 事件通知中 `source` 字段的值，用于和后端发出的 `telemetry/event` 区分。
+pub const SOURCE: &str = "codefuse";
+
+/// 代理运行状态的一次变化。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyEvent {
+    /// 分片的后端进程退出
+    BackendExited { shard: String },
+    /// 分片切换到了新的后端进程，`reason` 是切换的原因
+    BackendRestarted {
+        shard: String,
+        reason: RestartReason,
+    },
+    /// 代理的缓存被清空，`cache` 是缓存的名称
+    CacheCleared { cache: &'static str },
+    /// 方法的请求用完了速率限制的令牌，之后的请求由代理应答，直到令牌恢复
+    BudgetExhausted { method: String },
+}
+
+/// 后端重启的原因。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartReason {
+    /// 后端退出后切换到备用后端或重新连接
+    Exited,
+    /// 后端内存超过上限
+    Memory,
+    /// 命令、配置修改或者生成了新的编译数据库
+    Requested,
+    /// 因空闲而结束的后端在有消息时重新启动
+    Idle,
+}
+
+impl RestartReason {
+    fn name(self) -> &'static str {
+        match self {
+            RestartReason::Exited => "exited",
+            RestartReason::Memory => "memory",
+            RestartReason::Requested => "requested",
+            RestartReason::Idle => "idle",
+        }
+    }
+}
+
+impl ProxyEvent {
+    /// 事件的名称，即通知中的 `event` 字段。
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProxyEvent::BackendExited { .. } => "backendExited",
+            ProxyEvent::BackendRestarted { .. } => "backendRestarted",
+            ProxyEvent::CacheCleared { .. } => "cacheCleared",
+            ProxyEvent::BudgetExhausted { .. } => "budgetExhausted",
+        }
+    }
+
+    /// 发给编辑器的 `telemetry/event` 通知。
+    pub fn notification(&self) -> Value {
+        let properties = match self {
+            ProxyEvent::BackendExited { shard } => json!({"shard": shard}),
+            ProxyEvent::BackendRestarted { shard, reason } => {
+                json!({"shard": shard, "reason": reason.name()})
+            }
+            ProxyEvent::CacheCleared { cache } => json!({"cache": cache}),
+            ProxyEvent::BudgetExhausted { method } => json!({"method": method}),
+        };
+        json!({
+            "jsonrpc": "2.0",
+            "method": "telemetry/event",
+            "params": {
+                "source": SOURCE,
+                "event": self.name(),
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "properties": properties,
+            },
+        })
+    }
+}
@@ -0,0 +1,125 @@
+//! # 超时代答模块
+//!
+//! 补全来源、签名帮助和文档高亮在后端超时后由代理应答，三者共用这里的请求状态：请求转发给后端时记下
+//! 代答需要的信息，后端先应答时取回这些信息，超时后由代理应答时标记为已应答，后端迟到的响应丢弃。
+//!
+//! 已应答的请求在后端的响应到达时清除。后端退出后不会再有响应，该分片上已应答的请求由
+//! [`FallbackRequests::backend_exited`] 清除。
+
+use dashmap::DashMap;
+use serde_json::Value;
+use tower_lsp::lsp_types::{Position, Url};
+
+use crate::document_store::{Document, DocumentStore};
+
+enum State<T> {
+    /// 等待后端应答，保存代答需要的信息
+    Waiting(T),
+    /// 后端超过等待时间，代理已经应答；记录请求发往的分片
+    Answered(usize),
+}
+
+/// 后端的响应到达时请求的状态。
+#[derive(Debug, PartialEq)]
+pub enum Arrival<T> {
+    /// 代理还没有应答，取回请求时记下的信息
+    Waiting(T),
+    /// 代理已经应答，响应应当丢弃
+    Late,
+    /// 不是这里跟踪的请求，响应原样转发
+    Untracked,
+}
+
+/// 等待后端应答、超时后由代理应答的请求，键是请求 id。
+pub struct FallbackRequests<T> {
+    pending: DashMap<String, State<T>>,
+}
+
+impl<T> Default for FallbackRequests<T> {
+    fn default() -> Self {
+        Self {
+            pending: DashMap::new(),
+        }
+    }
+}
+
+impl<T> FallbackRequests<T> {
+    /// 请求转发给后端之前调用，`waiting` 是代答需要的信息。
+    pub fn insert(&self, id: &Value, waiting: T) {
+        self.pending.insert(id.to_string(), State::Waiting(waiting));
+    }
+
+    /// 后端的响应到达，不再跟踪这个请求。
+    pub fn arrive(&self, id: &Value) -> Arrival<T> {
+        match self.pending.remove(&id.to_string()) {
+            Some((_, State::Waiting(waiting))) => Arrival::Waiting(waiting),
+            Some((_, State::Answered(_))) => Arrival::Late,
+            None => Arrival::Untracked,
+        }
+    }
+
+    /// 后端超过了等待时间，改由代理应答。
+    ///
+    /// # 参数
+    ///
+    /// * `id` - 请求的 id
+    /// * `shard` - 请求发往的分片
+    ///
+    /// # 返回
+    ///
+    /// 请求时记下的信息；后端已经响应或者代理已经应答时返回 `None`
+    pub fn expire(&self, id: &Value, shard: usize) -> Option<T> {
+        let mut state = self.pending.get_mut(&id.to_string())?;
+        match std::mem::replace(&mut *state, State::Answered(shard)) {
+            State::Waiting(waiting) => Some(waiting),
+            State::Answered(shard) => {
+                *state = State::Answered(shard);
+                None
+            }
+        }
+    }
+
+    /// `shard` 分片的后端退出，已经应答的请求不会再有响应，不再跟踪。
+    pub fn backend_exited(&self, shard: usize) {
+        self.pending
+            .retain(|_, state| !matches!(state, State::Answered(answered) if *answered == shard));
+    }
+
+    /// 跟踪中的请求数。
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// 是否没有跟踪中的请求。
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// 请求针对的文档版本和光标位置。只记录 URI 和版本，代答时再从文档存储中取出文本。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub uri: Url,
+    /// 请求时文档的版本，文档没有打开时为 `None`
+    pub version: Option<i32>,
+    pub position: Position,
+}
+
+impl Cursor {
+    /// 记下 `uri` 文档当前的版本。
+    pub fn new(documents: &DocumentStore, uri: Url, position: Position) -> Self {
+        let version = documents.get(&uri).map(|doc| doc.version);
+        Self {
+            uri,
+            version,
+            position,
+        }
+    }
+
+    /// 请求时的文档；文档已经关闭或者之后又修改过时返回 `None`，光标位置不再可靠。
+    pub fn document(&self, documents: &DocumentStore) -> Option<Document> {
+        documents
+            .get(&self.uri)
+            .filter(|doc| Some(doc.version) == self.version)
+    }
+}
@@ -0,0 +1,126 @@
+//! # 功能开关模块
+//!
+//! 编辑器通过 `workspace/didChangeConfiguration` 发来 `codefuse` 配置节时，代理按其中的布尔值在运行时关闭或者重新打开
+//! 自己的功能，用户在编辑器设置中就可以切换，不需要修改 TOML 文件。开关只能关闭配置文件中开启的功能；
+//! 设为 `true` 或者删除后恢复配置文件的设置。
+//!
+//! | 设置 | 功能 |
+//! |------|------|
+//! | `codefuse.prefetch` | 悬停和定义的预取及其缓存，关闭时清空缓存 |
+//! | `codefuse.completionSources` | 代理内的补全来源（代码片段、单词、关键字） |
+//! | `codefuse.diagnosticFilters` | clang-tidy 策略对后端诊断的过滤 |
+//! | `codefuse.spellcheck` | 注释和字符串的拼写检查，关闭时清除已有的拼写诊断 |
+//! | `codefuse.fallbacks` | 后端不可用时的兜底：签名帮助、文档高亮、颜色和工作区符号 |
+//!
+//! 设置可以是嵌套的 `{"codefuse": {"prefetch": false}}`，也可以是展开的 `{"codefuse.prefetch": false}`。
+//! 没有 `codefuse` 配置节的通知（例如只包含其他扩展的设置）不改变开关。
+
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+/// 编辑器设置中代理的配置节。
+pub const SECTION: &str = "codefuse";
+
+/// 可以在运行时关闭的功能。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    Prefetch,
+    CompletionSources,
+    DiagnosticFilters,
+    Spellcheck,
+    Fallbacks,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 5] = [
+        Feature::Prefetch,
+        Feature::CompletionSources,
+        Feature::DiagnosticFilters,
+        Feature::Spellcheck,
+        Feature::Fallbacks,
+    ];
+
+    /// 功能在 `codefuse` 配置节中的键。
+    pub fn key(self) -> &'static str {
+        match self {
+            Feature::Prefetch => "prefetch",
+            Feature::CompletionSources => "completionSources",
+            Feature::DiagnosticFilters => "diagnosticFilters",
+            Feature::Spellcheck => "spellcheck",
+            Feature::Fallbacks => "fallbacks",
+        }
+    }
+}
+
+/// 编辑器设置关闭的功能。
+#[derive(Debug, Default)]
+pub struct FeatureSwitches {
+    disabled: RwLock<HashSet<Feature>>,
+}
+
+impl FeatureSwitches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 功能没有被编辑器设置关闭。功能是否在配置文件中开启由各模块自己判断。
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        !self.disabled.read().unwrap().contains(&feature)
+    }
+
+    /// 被关闭的功能，按 [`Feature::ALL`] 的顺序。
+    pub fn disabled(&self) -> Vec<Feature> {
+        let disabled = self.disabled.read().unwrap();
+        Feature::ALL
+            .into_iter()
+            .filter(|feature| disabled.contains(feature))
+            .collect()
+    }
+
+    /// 应用 `workspace/didChangeConfiguration` 的 `settings`。
+    ///
+    /// # 返回
+    ///
+    /// 状态改变了的功能和改变后是否开启；没有 `codefuse` 配置节时为空
+    pub fn apply(&self, settings: &Value) -> Vec<(Feature, bool)> {
+        let Some(section) = section(settings) else {
+            return Vec::new();
+        };
+        let mut disabled = self.disabled.write().unwrap();
+        let mut changed = Vec::new();
+        for feature in Feature::ALL {
+            let enabled = section
+                .get(feature.key())
+                .and_then(|value| value.as_bool())
+                .unwrap_or(true);
+            let updated = if enabled {
+                disabled.remove(&feature)
+            } else {
+                disabled.insert(feature)
+            };
+            if updated {
+                changed.push((feature, enabled));
+            }
+        }
+        changed
+    }
+}
+
+/// 设置中的 `codefuse` 配置节，展开的 `codefuse.*` 键合并成一个配置节。
+fn section(settings: &Value) -> Option<Map<String, Value>> {
+    let settings = settings.as_object()?;
+    let mut section = settings
+        .get(SECTION)
+        .and_then(|section| section.as_object())
+        .cloned();
+    let prefix = format!("{}.", SECTION);
+    for (key, value) in settings {
+        if let Some(key) = key.strip_prefix(&prefix) {
+            section
+                .get_or_insert_with(Map::new)
+                .insert(key.to_string(), value.clone());
+        }
+    }
+    section
+}
@@ -0,0 +1,244 @@
+//! # 文件状态模块
+//!
+//! clangd 在 `initializationOptions.clangdFileStatus` 为 `true` 时，用 `textDocument/clangd.fileStatus`
+//! 通知报告每个打开的文件正在做什么（`parsing includes`、`building preamble`、`idle` 等），
+//! 只有 vscode-clangd 这类专门的插件会显示这些状态。
+//!
+//! 开启 `[file_status]` 后代理总是请求这个通知，记录每个文件最新的状态，由自定义请求 `codefuse/status`
+//! 汇总；开启 `progress` 时还把正在处理的文件显示为标准的 `window/workDoneProgress`，任何编辑器都能看到
+//! “main.cpp：building preamble…”，所有文件都空闲时结束进度。前端自己没有请求这个通知时不转发给前端。
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tower_lsp::lsp_types::DidCloseTextDocumentParams;
+
+use crate::config::FileStatusConfig;
+use crate::dispatcher::{DEFAULT_HANDLER_PRIORITY, Dispatcher};
+use crate::document_observer::DocumentObserver;
+use crate::handlers::{self, HandlerCtx, Verdict};
+use crate::index_progress::{ProxyProgress, Step, Summary};
+use crate::trace::Direction;
+
+/// clangd 报告文件状态的通知。
+pub const FILE_STATUS: &str = "textDocument/clangd.fileStatus";
+
+/// 查询文件状态汇总的自定义请求，没有参数，结果是 [`Status`]。
+pub const STATUS: &str = "codefuse/status";
+
+/// 文件状态的进度在编辑器中显示的标题。
+pub const TITLE: &str = "clangd";
+
+/// clangd 处理完文件之后的状态。
+const IDLE: &str = "idle";
+
+/// 一个正在处理的文件。
+///
+/// - `uri`: 文件
+/// - `state`: clangd 报告的状态
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BusyFile {
+    pub uri: String,
+    pub state: String,
+}
+
+/// `codefuse/status` 的结果。
+///
+/// - `busy`: 正在处理的文件，按 uri 排序
+/// - `states`: 每种状态的文件数，包括 `idle`
+/// - `index`: 各分片合并后的后台索引进度，没有在索引或者没有开启 `[index_progress]` 时为 `null`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Status {
+    pub busy: Vec<BusyFile>,
+    pub states: BTreeMap<String, usize>,
+    pub index: Option<Summary>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// 每个文件最新的状态
+    files: BTreeMap<String, String>,
+    /// 最近一个报告了状态的文件，进度中优先显示
+    latest: Option<String>,
+    progress: ProxyProgress,
+}
+
+impl State {
+    fn busy(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.files
+            .iter()
+            .filter(|(_, state)| state.as_str() != IDLE)
+    }
+
+    /// 进度中显示的文字：最近报告的正在处理的文件，以及其余正在处理的文件数。
+    fn report(&self) -> Value {
+        let busy: Vec<_> = self.busy().collect();
+        let (uri, state) = self
+            .latest
+            .as_ref()
+            .and_then(|latest| busy.iter().find(|(uri, _)| *uri == latest))
+            .or(busy.first())
+            .map_or(("", ""), |(uri, state)| (uri.as_str(), state.as_str()));
+        let mut message = format!("{}：{}…", file_name(uri), state);
+        if busy.len() > 1 {
+            message.push_str(&format!("（另有 {} 个文件）", busy.len() - 1));
+        }
+        json!({"kind": "report", "message": message})
+    }
+}
+
+/// uri 中的文件名。
+fn file_name(uri: &str) -> &str {
+    uri.rsplit('/').next().unwrap_or(uri)
+}
+
+/// 各文件的 clangd 状态。关闭时不请求也不接管这个通知。
+#[derive(Default)]
+pub struct FileStatus {
+    config: FileStatusConfig,
+    /// 前端自己是否请求了文件状态
+    client_requested: AtomicBool,
+    state: Mutex<State>,
+}
+
+impl FileStatus {
+    /// 按 `[file_status]` 配置创建。
+    pub fn new(config: &FileStatusConfig) -> Self {
+        Self {
+            config: config.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// 是否记录文件状态。
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// 记录前端是否请求了文件状态，开启时在 initialize 的参数中请求。
+    pub fn on_initialize(&self, params: &mut Value) {
+        let requested = params
+            .pointer("/initializationOptions/clangdFileStatus")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        self.client_requested.store(requested, Ordering::Relaxed);
+        if !self.config.enabled || requested {
+            return;
+        }
+        if !params
+            .get("initializationOptions")
+            .is_some_and(|options| options.is_object())
+        {
+            params["initializationOptions"] = json!({});
+        }
+        params["initializationOptions"]["clangdFileStatus"] = json!(true);
+    }
+
+    /// 是否把文件状态通知转发给前端：前端自己请求了这个通知，或者代理没有接管时。
+    pub fn forwards(&self) -> bool {
+        !self.config.enabled || self.client_requested.load(Ordering::Relaxed)
+    }
+
+    /// 记录一条 `textDocument/clangd.fileStatus` 通知的参数。
+    ///
+    /// # 返回
+    ///
+    /// 开启 `progress` 时需要对前端的进度做的事
+    pub fn observe(&self, params: &Value) -> Step {
+        let (Some(uri), Some(file_state)) = (
+            params.get("uri").and_then(|uri| uri.as_str()),
+            params.get("state").and_then(|state| state.as_str()),
+        ) else {
+            return Step::Nothing;
+        };
+        let mut state = self.state.lock().unwrap();
+        state.files.insert(uri.to_string(), file_state.to_string());
+        state.latest = Some(uri.to_string());
+        self.progress_step(&mut state)
+    }
+
+    /// 文件关闭之后重新计算进度：关闭的是最后一个正在处理的文件时结束进度。
+    pub fn refresh(&self) -> Step {
+        let mut state = self.state.lock().unwrap();
+        self.progress_step(&mut state)
+    }
+
+    fn progress_step(&self, state: &mut State) -> Step {
+        if !self.config.progress {
+            return Step::Nothing;
+        }
+        if state.busy().next().is_none() {
+            state.latest = None;
+            return state.progress.finish();
+        }
+        let report = state.report();
+        state.progress.update(|| report)
+    }
+
+    /// 前端的进度创建完成。
+    ///
+    /// # 参数
+    ///
+    /// * `token` - 创建的进度，前端不支持时为 `None`
+    ///
+    /// # 返回
+    ///
+    /// 需要发给前端的 `$/progress` 通知
+    pub fn created(&self, token: Option<String>) -> Vec<Value> {
+        let mut state = self.state.lock().unwrap();
+        let current = state.busy().next().is_some().then(|| state.report());
+        state.progress.created(token, current)
+    }
+
+    /// 当前的状态汇总，`index` 由调度器填写。
+    pub fn status(&self) -> Status {
+        let state = self.state.lock().unwrap();
+        let mut states = BTreeMap::new();
+        for file_state in state.files.values() {
+            *states.entry(file_state.clone()).or_default() += 1;
+        }
+        Status {
+            busy: state
+                .busy()
+                .map(|(uri, file_state)| BusyFile {
+                    uri: uri.clone(),
+                    state: file_state.clone(),
+                })
+                .collect(),
+            states,
+            index: None,
+        }
+    }
+}
+
+/// 关闭的文件不再有状态，clangd 也不会再报告它；进度由调度器随后调用 [`FileStatus::refresh`] 更新。
+impl DocumentObserver for FileStatus {
+    fn did_close(&self, params: &DidCloseTextDocumentParams) {
+        let mut state = self.state.lock().unwrap();
+        state.files.remove(params.text_document.uri.as_str());
+    }
+}
+
+/// 注册 `codefuse/status` 的处理器，由代理直接应答。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        STATUS,
+        DEFAULT_HANDLER_PRIORITY,
+        answer_status,
+    );
+}
+
+/// 以文件的处理状态和后台索引的进度应答。
+fn answer_status(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let mut status = ctx.dispatcher().file_status().status();
+        status.index = ctx.dispatcher().index_progress().summary();
+        let response = handlers::response(&rpc, Ok(json!(status)));
+        Ok(Verdict::Respond(response))
+    })
+}
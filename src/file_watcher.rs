@@ -0,0 +1,442 @@
+//! # 文件监视模块
+//!
+//! clangd 通过 `client/registerCapability` 注册需要监视的文件（例如 `compile_commands.json`），
+//! 并依赖客户端发送 `workspace/didChangeWatchedFiles`。对于不支持文件监视的简单客户端，
+//! 这个模块由代理代为注册并基于 `notify` 监视文件，生成相应的通知发送给后端。
+
+use anyhow::Result;
+use globset::{Glob, GlobMatcher};
+use log::{debug, info, warn};
+use notify::event::{EventKind, ModifyKind, RenameMode};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tower_lsp::lsp_types::notification::{DidChangeWatchedFiles, Notification};
+use tower_lsp::lsp_types::{FileChangeType, FileEvent, Url, WatchKind};
+
+use crate::message::Message;
+use crate::shard::{self, Shard};
+
+/// 合并文件事件的时间窗口，避免一次保存产生多条通知。
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// 一条监视规则：glob 匹配器和关心的事件类型。
+struct Watch {
+    matcher: GlobMatcher,
+    kind: WatchKind,
+}
+
+/// 后端通过 `client/registerCapability` 注册的一组监视规则。
+struct Registration {
+    id: String,
+    watches: Vec<Watch>,
+}
+
+struct WatchState {
+    roots: Vec<PathBuf>,
+    registrations: Vec<Registration>,
+    watcher: Option<RecommendedWatcher>,
+}
+
+/// 代替客户端执行文件监视的组件。
+///
+/// - `backend_sender`: 向默认分片的后端应答注册请求的通道
+/// - `shards`: 接收 `didChangeWatchedFiles` 通知的分片，每个分片只收到它关心的文件变更
+/// - `enabled`: 客户端是否缺少文件监视能力、需要由代理代为监视
+/// - `state`: 工作区根目录、已注册的监视规则和底层的 `notify` 监视器
+pub struct FileWatcher {
+    backend_sender: UnboundedSender<Message>,
+    shards: Vec<Shard>,
+    enabled: AtomicBool,
+    state: Mutex<WatchState>,
+}
+
+impl FileWatcher {
+    /// 创建文件监视组件，在 `enable_for_client` 之前不会监视任何文件。
+    pub fn new(backend_sender: UnboundedSender<Message>) -> Self {
+        Self::for_shards(&[Shard::default_shard(backend_sender)])
+    }
+
+    /// 为多个分片创建文件监视组件，文件变更按 [`shard::split_file_changes`] 分给各分片。
+    pub fn for_shards(shards: &[Shard]) -> Self {
+        Self {
+            backend_sender: shards[0].sender.clone(),
+            shards: shards.to_vec(),
+            enabled: AtomicBool::new(false),
+            state: Mutex::new(WatchState {
+                roots: Vec::new(),
+                registrations: Vec::new(),
+                watcher: None,
+            }),
+        }
+    }
+
+    /// 检查客户端的 initialize 参数，必要时由代理接管文件监视。
+    ///
+    /// 如果客户端没有声明 `workspace.didChangeWatchedFiles.dynamicRegistration`，
+    /// 就在转发给后端的参数中把它设为 `true`，让后端照常注册监视规则，再由代理拦截。
+    ///
+    /// # 参数
+    ///
+    /// * `params` - initialize 请求的参数，会被原地修改
+    /// * `roots` - 工作区根目录，监视器会递归监视这些目录
+    ///
+    /// # 返回
+    ///
+    /// 返回代理是否接管了文件监视
+    pub fn enable_for_client(&self, params: &mut Value, roots: Vec<PathBuf>) -> bool {
+        let supported = params
+            .pointer("/capabilities/workspace/didChangeWatchedFiles/dynamicRegistration")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        if supported || roots.is_empty() {
+            return false;
+        }
+
+        let Some(capabilities) = params
+            .get_mut("capabilities")
+            .and_then(|c| c.as_object_mut())
+        else {
+            return false;
+        };
+        let workspace = capabilities.entry("workspace").or_insert_with(|| json!({}));
+        if let Some(workspace) = workspace.as_object_mut() {
+            workspace.insert(
+                "didChangeWatchedFiles".to_string(),
+                json!({"dynamicRegistration": true}),
+            );
+        }
+
+        self.state.lock().unwrap().roots = roots;
+        self.enabled.store(true, Ordering::Relaxed);
+        info!("客户端不支持文件监视，由代理生成 didChangeWatchedFiles");
+        true
+    }
+
+    /// 更新需要监视的工作区根目录，监视器已经启动时同步增删监视的目录。
+    ///
+    /// # 错误
+    ///
+    /// 如果底层监视器无法监视新的目录，返回错误
+    pub fn set_roots(&self, roots: Vec<PathBuf>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let WatchState {
+            roots: current,
+            watcher,
+            ..
+        } = &mut *state;
+        let old_roots = std::mem::replace(current, roots);
+        let Some(watcher) = watcher.as_mut() else {
+            return Ok(());
+        };
+
+        for root in old_roots.iter().filter(|r| !current.contains(r)) {
+            let _ = watcher.unwatch(root);
+        }
+        for root in current.iter().filter(|r| !old_roots.contains(r)) {
+            watcher.watch(root, RecursiveMode::Recursive)?;
+        }
+        Ok(())
+    }
+
+    /// 代理是否接管了文件监视。
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// 处理后端发来的 `client/registerCapability` 请求。
+    ///
+    /// 其中的文件监视注册由代理处理；如果没有其他注册，直接向后端回复，
+    /// 否则返回去掉文件监视注册之后的请求，由调用方转发给客户端。
+    ///
+    /// # 返回
+    ///
+    /// 返回需要继续转发给客户端的请求，`None` 表示请求已经处理完毕
+    ///
+    /// # 错误
+    ///
+    /// 如果启动底层监视器或回复后端失败，返回错误
+    pub fn handle_register_capability(self: &Arc<Self>, mut rpc: Value) -> Result<Option<Value>> {
+        let Some(registrations) = rpc
+            .pointer_mut("/params/registrations")
+            .and_then(|r| r.as_array_mut())
+        else {
+            return Ok(Some(rpc));
+        };
+
+        let mut taken = Vec::new();
+        registrations.retain(|registration| {
+            if registration.get("method").and_then(|m| m.as_str())
+                == Some(DidChangeWatchedFiles::METHOD)
+            {
+                taken.push(registration.clone());
+                false
+            } else {
+                true
+            }
+        });
+        if taken.is_empty() {
+            return Ok(Some(rpc));
+        }
+        let forward = !registrations.is_empty();
+
+        for registration in &taken {
+            self.register(registration);
+        }
+        self.ensure_watching()?;
+
+        if forward {
+            return Ok(Some(rpc));
+        }
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": rpc.get("id").cloned().unwrap_or(json!(null)),
+            "result": null,
+        });
+        self.backend_sender.send(Message::new(response))?;
+        Ok(None)
+    }
+
+    /// 处理后端发来的 `client/unregisterCapability` 请求，规则与注册时相同。
+    ///
+    /// # 返回
+    ///
+    /// 返回需要继续转发给客户端的请求，`None` 表示请求已经处理完毕
+    ///
+    /// # 错误
+    ///
+    /// 如果回复后端失败，返回错误
+    pub fn handle_unregister_capability(&self, mut rpc: Value) -> Result<Option<Value>> {
+        // 协议中的字段名就是拼写错误的 "unregisterations"
+        let Some(unregistrations) = rpc
+            .pointer_mut("/params/unregisterations")
+            .and_then(|r| r.as_array_mut())
+        else {
+            return Ok(Some(rpc));
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let before = unregistrations.len();
+        unregistrations.retain(|unregistration| {
+            let id = unregistration.get("id").and_then(|i| i.as_str());
+            let known = state
+                .registrations
+                .iter()
+                .any(|r| Some(r.id.as_str()) == id);
+            if known {
+                state.registrations.retain(|r| Some(r.id.as_str()) != id);
+            }
+            !known
+        });
+        if unregistrations.len() == before || !unregistrations.is_empty() {
+            return Ok(Some(rpc));
+        }
+        drop(state);
+
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": rpc.get("id").cloned().unwrap_or(json!(null)),
+            "result": null,
+        });
+        self.backend_sender.send(Message::new(response))?;
+        Ok(None)
+    }
+
+    fn register(&self, registration: &Value) {
+        let id = registration
+            .get("id")
+            .and_then(|i| i.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let watchers = registration
+            .pointer("/registerOptions/watchers")
+            .and_then(|w| w.as_array())
+            .cloned()
+            .unwrap_or_default();
+
+        let watches: Vec<Watch> = watchers
+            .iter()
+            .filter_map(|watcher| {
+                let pattern = glob_pattern(watcher.get("globPattern")?)?;
+                let kind = watcher
+                    .get("kind")
+                    .and_then(|k| k.as_u64())
+                    .and_then(|k| WatchKind::from_bits(k as u8))
+                    .unwrap_or(WatchKind::all());
+                match Glob::new(&pattern) {
+                    Ok(glob) => Some(Watch {
+                        matcher: glob.compile_matcher(),
+                        kind,
+                    }),
+                    Err(e) => {
+                        warn!("无效的 glob 模式 {}: {}", pattern, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        debug!("注册文件监视 {}: {} 条规则", id, watches.len());
+        self.state
+            .lock()
+            .unwrap()
+            .registrations
+            .push(Registration { id, watches });
+    }
+
+    /// 启动底层监视器（只启动一次），并开始把事件转换为通知发送给后端。
+    fn ensure_watching(self: &Arc<Self>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.watcher.is_some() {
+            return Ok(());
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        })?;
+        for root in &state.roots {
+            watcher.watch(root, RecursiveMode::Recursive)?;
+        }
+        state.watcher = Some(watcher);
+
+        tokio::spawn(Arc::clone(self).forward_events(rx));
+        Ok(())
+    }
+
+    async fn forward_events(
+        self: Arc<Self>,
+        mut rx: mpsc::UnboundedReceiver<notify::Result<notify::Event>>,
+    ) {
+        while let Some(first) = rx.recv().await {
+            tokio::time::sleep(DEBOUNCE).await;
+            let mut events = vec![first];
+            while let Ok(event) = rx.try_recv() {
+                events.push(event);
+            }
+
+            let mut changes: Vec<FileEvent> = Vec::new();
+            for event in events {
+                match event {
+                    Ok(event) => {
+                        for change in self.changes_for(&event) {
+                            if !changes.contains(&change) {
+                                changes.push(change);
+                            }
+                        }
+                    }
+                    Err(e) => warn!("文件监视出错: {}", e),
+                }
+            }
+            if changes.is_empty() {
+                continue;
+            }
+
+            debug!("生成 didChangeWatchedFiles: {} 个文件", changes.len());
+            for (shard, changes) in shard::split_file_changes(&self.shards, &changes) {
+                let notification = json!({
+                    "jsonrpc": "2.0",
+                    "method": DidChangeWatchedFiles::METHOD,
+                    "params": {"changes": changes},
+                });
+                if self.shards[shard]
+                    .sender
+                    .send(Message::new(notification))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// 把一个 `notify` 事件转换为符合注册规则的文件变更列表，只保留注册时关心的事件类型。
+    pub fn changes_for(&self, event: &notify::Event) -> Vec<FileEvent> {
+        let typed_paths: Vec<(FileChangeType, &Path)> = match event.kind {
+            EventKind::Create(_) => event
+                .paths
+                .iter()
+                .map(|p| (FileChangeType::CREATED, p.as_path()))
+                .collect(),
+            EventKind::Remove(_) => event
+                .paths
+                .iter()
+                .map(|p| (FileChangeType::DELETED, p.as_path()))
+                .collect(),
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                vec![
+                    (FileChangeType::DELETED, event.paths[0].as_path()),
+                    (FileChangeType::CREATED, event.paths[1].as_path()),
+                ]
+            }
+            EventKind::Modify(ModifyKind::Name(_)) => event
+                .paths
+                .iter()
+                .map(|p| {
+                    let typ = if p.exists() {
+                        FileChangeType::CREATED
+                    } else {
+                        FileChangeType::DELETED
+                    };
+                    (typ, p.as_path())
+                })
+                .collect(),
+            EventKind::Modify(_) => event
+                .paths
+                .iter()
+                .map(|p| (FileChangeType::CHANGED, p.as_path()))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let state = self.state.lock().unwrap();
+        typed_paths
+            .into_iter()
+            .filter(|(typ, path)| {
+                let kind = watch_kind(*typ);
+                state.registrations.iter().any(|registration| {
+                    registration
+                        .watches
+                        .iter()
+                        .any(|watch| watch.kind.contains(kind) && watch.matcher.is_match(path))
+                })
+            })
+            .filter_map(|(typ, path)| Some(FileEvent::new(Url::from_file_path(path).ok()?, typ)))
+            .collect()
+    }
+}
+
+/// 把 LSP 的 `GlobPattern`（字符串或 `RelativePattern`）转换为可以匹配绝对路径的 glob。
+///
+/// # 返回
+///
+/// 模式无效（例如 `baseUri` 不是文件 URI）时返回 `None`
+pub fn glob_pattern(pattern: &Value) -> Option<String> {
+    if let Some(pattern) = pattern.as_str() {
+        // 相对的模式默认可以出现在任意目录下
+        if pattern.starts_with('/') || pattern.starts_with("**") {
+            return Some(pattern.to_string());
+        }
+        return Some(format!("**/{}", pattern));
+    }
+
+    let base = match pattern.get("baseUri")? {
+        Value::String(uri) => uri.as_str(),
+        folder => folder.get("uri")?.as_str()?,
+    };
+    let base = Url::parse(base).ok()?.to_file_path().ok()?;
+    let relative = pattern.get("pattern")?.as_str()?;
+    Some(format!("{}/{}", base.display(), relative))
+}
+
+fn watch_kind(typ: FileChangeType) -> WatchKind {
+    match typ {
+        FileChangeType::CREATED => WatchKind::Create,
+        FileChangeType::DELETED => WatchKind::Delete,
+        _ => WatchKind::Change,
+    }
+}
@@ -0,0 +1,122 @@
+//! # 修复合并模块
+//!
+//! 把当前诊断附带的所有修复（fix-it）合成一个 `WorkspaceEdit`，由 `codefuse.applyAllFixits` 命令一次性应用。
+//! 修复通过对每条诊断向后端请求 `quickfix` 代码操作获得，相互重叠的编辑只保留先出现的一个。
+
+use serde_json::{Map, Value, json};
+use std::collections::BTreeMap;
+use tower_lsp::lsp_types::{Position, TextEdit, Url};
+
+/// 为一条诊断生成请求修复的 `textDocument/codeAction` 参数。
+pub fn quickfix_params(uri: &Url, diagnostic: &Value) -> Value {
+    json!({
+        "textDocument": {"uri": uri},
+        "range": diagnostic.get("range").cloned().unwrap_or(Value::Null),
+        "context": {"diagnostics": [diagnostic], "only": ["quickfix"]},
+    })
+}
+
+/// 从 `textDocument/codeAction` 的结果中选出一个修复的编辑。
+///
+/// 优先使用标记为 `isPreferred` 的操作，否则使用第一个带编辑的操作。
+pub fn preferred_fix(actions: &Value) -> Vec<(Url, TextEdit)> {
+    let Some(actions) = actions.as_array() else {
+        return Vec::new();
+    };
+    let with_edit: Vec<&Value> = actions.iter().filter(|a| a.get("edit").is_some()).collect();
+    let Some(action) = with_edit
+        .iter()
+        .find(|a| a.get("isPreferred").and_then(|p| p.as_bool()) == Some(true))
+        .or(with_edit.first())
+    else {
+        return Vec::new();
+    };
+    workspace_edit_edits(&action["edit"])
+}
+
+/// 展开 `WorkspaceEdit` 中的文本编辑（`changes` 和 `documentChanges` 两种形式）。
+pub fn workspace_edit_edits(edit: &Value) -> Vec<(Url, TextEdit)> {
+    let mut edits = Vec::new();
+    let mut push = |uri: &str, list: &Value| {
+        let Ok(uri) = Url::parse(uri) else {
+            return;
+        };
+        for edit in list.as_array().into_iter().flatten() {
+            if let Ok(edit) = serde_json::from_value::<TextEdit>(edit.clone()) {
+                edits.push((uri.clone(), edit));
+            }
+        }
+    };
+    if let Some(changes) = edit.get("changes").and_then(|c| c.as_object()) {
+        for (uri, list) in changes {
+            push(uri, list);
+        }
+    }
+    for change in edit
+        .get("documentChanges")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+    {
+        if let (Some(uri), Some(list)) = (
+            change.pointer("/textDocument/uri").and_then(|u| u.as_str()),
+            change.get("edits"),
+        ) {
+            push(uri, list);
+        }
+    }
+    edits
+}
+
+/// 把多组修复合成一个 `WorkspaceEdit`。
+///
+/// 与已选编辑重叠的修复整组跳过；完全相同的编辑（例如多个修复插入同一个 `#include`）只保留一次。
+///
+/// # 返回
+///
+/// 返回合成的 `WorkspaceEdit` 和被采用的修复数量
+pub fn compose_workspace_edit(fixes: Vec<Vec<(Url, TextEdit)>>) -> (Value, usize) {
+    let mut chosen: BTreeMap<Url, Vec<TextEdit>> = BTreeMap::new();
+    let mut applied = 0;
+    for fix in fixes {
+        if fix.is_empty() {
+            continue;
+        }
+        let conflicts = fix.iter().any(|(uri, edit)| {
+            chosen
+                .get(uri)
+                .is_some_and(|existing| existing.iter().any(|e| e != edit && overlaps(e, edit)))
+        });
+        if conflicts {
+            continue;
+        }
+        for (uri, edit) in fix {
+            let edits = chosen.entry(uri).or_default();
+            if !edits.contains(&edit) {
+                edits.push(edit);
+            }
+        }
+        applied += 1;
+    }
+
+    let mut changes = Map::new();
+    for (uri, mut edits) in chosen {
+        edits.sort_by_key(|e| position_key(e.range.start));
+        changes.insert(uri.to_string(), json!(edits));
+    }
+    (json!({"changes": changes}), applied)
+}
+
+fn position_key(position: Position) -> (u32, u32) {
+    (position.line, position.character)
+}
+
+/// 两个编辑的范围是否重叠；在同一位置插入也视为冲突。
+fn overlaps(a: &TextEdit, b: &TextEdit) -> bool {
+    let (a_start, a_end) = (position_key(a.range.start), position_key(a.range.end));
+    let (b_start, b_end) = (position_key(b.range.start), position_key(b.range.end));
+    if a_start == b_start {
+        return true;
+    }
+    a_start < b_end && b_start < a_end
+}
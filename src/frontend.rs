@@ -0,0 +1,541 @@
+//! # 类型化前端模块
+//!
+//! 基于 `tower_lsp::LspService` 的前端（`--frontend typed`）：代理对编辑器是一个按 lsp_types
+//! 类型实现的 LSP 服务器，消息经过类型检查后仍然交给调度器转发给后端。
+//! 能力可以按类型修改，前端的行为也可以在测试中直接调用 [`LanguageServer`] 的方法验证。
+
+use anyhow::Result;
+use dashmap::DashMap;
+use log::{debug, error, warn};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+use tower_lsp::jsonrpc::{self, ErrorCode};
+use tower_lsp::lsp_types::notification::{self, Notification};
+use tower_lsp::lsp_types::request::{self, Request};
+use tower_lsp::lsp_types::{
+    InitializeParams, InitializeResult, InlineValue, ServerCapabilities, SymbolInformation,
+};
+use tower_lsp::{Client, ClientSocket, LanguageServer, LspService, Server};
+
+use crate::backend_log::BACKEND_LOG;
+use crate::dispatcher::Dispatcher;
+use crate::file_status::STATUS;
+use crate::history::HISTORY;
+use crate::message::Message;
+use crate::metrics::METRICS;
+use crate::profiles::SET_PROFILE;
+use crate::rename::RENAME_PREVIEW;
+use crate::shadow::SHADOW;
+use crate::stats::STATS;
+
+/// 与前端通信的方式。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrontendMode {
+    /// 直接转发 JSON-RPC 消息
+    #[default]
+    Raw,
+    /// 由 tower-lsp 按类型解析消息后再转发
+    Typed,
+}
+
+/// lsp_types 之外的请求（clangd 扩展和代理自己的请求），参数原样转发。
+const CUSTOM_REQUESTS: &[&str] = &[
+    "textDocument/switchSourceHeader",
+    "textDocument/ast",
+    "textDocument/symbolInfo",
+    RENAME_PREVIEW,
+    SET_PROFILE,
+    HISTORY,
+    BACKEND_LOG,
+];
+
+/// 没有参数的自定义请求。
+const PARAMETERLESS_REQUESTS: &[&str] = &[STATS, METRICS, STATUS, SHADOW, "$/memoryUsage"];
+
+/// 修改代理向前端声明的能力。
+pub type CapabilityEditor = Arc<dyn Fn(&mut ServerCapabilities) + Send + Sync>;
+
+/// 前端和转发给调度器的请求共享的状态。
+struct Forwarder {
+    dispatcher: Arc<Dispatcher>,
+    pending: DashMap<String, oneshot::Sender<Value>>,
+    next_id: AtomicI64,
+}
+
+impl Forwarder {
+    /// 把前端的请求交给调度器，等待发回前端的响应。
+    async fn request(self: &Arc<Self>, method: &str, params: Value) -> jsonrpc::Result<Value> {
+        let id = json!(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let (tx, rx) = oneshot::channel();
+        self.pending.insert(id.to_string(), tx);
+        // 前端取消请求时 tower-lsp 丢弃这个 future，由守卫通知后端
+        let mut guard = CancelOnDrop {
+            forwarder: Arc::clone(self),
+            id: Some(id.clone()),
+        };
+
+        let mut rpc = json!({"jsonrpc": "2.0", "id": id, "method": method});
+        if !params.is_null() {
+            rpc["params"] = params;
+        }
+        if let Err(e) = self.dispatcher.handle_from_frontend(rpc).await {
+            error!("转发 {} 请求失败: {:?}", method, e);
+            return Err(jsonrpc::Error::internal_error());
+        }
+        let response = rx.await.map_err(|_| jsonrpc::Error::internal_error())?;
+        guard.id = None;
+
+        if let Some(error) = response.get("error") {
+            return Err(serde_json::from_value(error.clone())
+                .unwrap_or_else(|_| jsonrpc::Error::internal_error()));
+        }
+        Ok(response.get("result").cloned().unwrap_or(Value::Null))
+    }
+
+    /// 把前端的通知交给调度器。
+    async fn notify(&self, method: &str, params: Value) {
+        let mut rpc = json!({"jsonrpc": "2.0", "method": method});
+        if !params.is_null() {
+            rpc["params"] = params;
+        }
+        if let Err(e) = self.dispatcher.handle_from_frontend(rpc).await {
+            error!("转发 {} 通知失败: {:?}", method, e);
+        }
+    }
+}
+
+/// 请求还没有得到响应就被丢弃时，向后端发送 `$/cancelRequest`。
+struct CancelOnDrop {
+    forwarder: Arc<Forwarder>,
+    id: Option<Value>,
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        let Some(id) = self.id.take() else {
+            return;
+        };
+        self.forwarder.pending.remove(&id.to_string());
+        let forwarder = Arc::clone(&self.forwarder);
+        tokio::spawn(async move {
+            forwarder
+                .notify(notification::Cancel::METHOD, json!({"id": id}))
+                .await;
+        });
+    }
+}
+
+/// tower-lsp 前端。
+///
+/// 每个 [`LanguageServer`] 方法把参数序列化后交给调度器，响应再按方法的结果类型解析，
+/// 后端返回的结果不符合协议时以 InternalError 应答前端。
+pub struct TypedFrontend {
+    client: Client,
+    forwarder: Arc<Forwarder>,
+    capability_editors: Vec<CapabilityEditor>,
+}
+
+/// [`TypedFrontend`] 的构建器。
+pub struct TypedFrontendBuilder {
+    dispatcher: Arc<Dispatcher>,
+    frontend_rx: mpsc::UnboundedReceiver<Message>,
+    capability_editors: Vec<CapabilityEditor>,
+}
+
+impl TypedFrontend {
+    /// 开始构建前端。
+    ///
+    /// # 参数
+    ///
+    /// * `dispatcher` - 调度器
+    /// * `frontend_rx` - 调度器发给前端的消息，由 [`ClientRelay`] 交给 tower-lsp 的客户端
+    pub fn builder(
+        dispatcher: Arc<Dispatcher>,
+        frontend_rx: mpsc::UnboundedReceiver<Message>,
+    ) -> TypedFrontendBuilder {
+        TypedFrontendBuilder {
+            dispatcher,
+            frontend_rx,
+            capability_editors: Vec::new(),
+        }
+    }
+
+    async fn request<R: Request>(&self, params: R::Params) -> jsonrpc::Result<R::Result> {
+        let params = serde_json::to_value(params).map_err(|_| jsonrpc::Error::invalid_request())?;
+        let result = self.forwarder.request(R::METHOD, params).await?;
+        parse_result(R::METHOD, result)
+    }
+
+    async fn notify<N: Notification>(&self, params: N::Params) {
+        match serde_json::to_value(params) {
+            Ok(params) => self.forwarder.notify(N::METHOD, params).await,
+            Err(e) => error!("{} 通知无法序列化: {:?}", N::METHOD, e),
+        }
+    }
+}
+
+impl TypedFrontendBuilder {
+    /// 在后端的 `initialize` 响应交给前端之前修改其中的能力，按添加的顺序执行。
+    pub fn with_capabilities(
+        mut self,
+        edit: impl Fn(&mut ServerCapabilities) + Send + Sync + 'static,
+    ) -> Self {
+        self.capability_editors.push(Arc::new(edit));
+        self
+    }
+
+    /// 构建 tower-lsp 服务。
+    ///
+    /// # 返回
+    ///
+    /// 返回服务、服务发往前端的消息通道，以及把调度器的消息交给前端的 [`ClientRelay`]
+    pub fn build(self) -> (LspService<TypedFrontend>, ClientSocket, ClientRelay) {
+        let forwarder = Arc::new(Forwarder {
+            dispatcher: self.dispatcher,
+            pending: DashMap::new(),
+            next_id: AtomicI64::new(1),
+        });
+        let capability_editors = self.capability_editors;
+        let mut builder = LspService::build(|client| TypedFrontend {
+            client,
+            forwarder: Arc::clone(&forwarder),
+            capability_editors,
+        });
+        for &method in CUSTOM_REQUESTS {
+            let forwarder = Arc::clone(&forwarder);
+            builder = builder.custom_method(method, move |_: &TypedFrontend, params: Value| {
+                let forwarder = Arc::clone(&forwarder);
+                async move { forwarder.request(method, params).await }
+            });
+        }
+        for &method in PARAMETERLESS_REQUESTS {
+            let forwarder = Arc::clone(&forwarder);
+            builder = builder.custom_method(method, move |_: &TypedFrontend| {
+                let forwarder = Arc::clone(&forwarder);
+                async move { forwarder.request(method, Value::Null).await }
+            });
+        }
+        let (service, socket) = builder.finish();
+        let relay = ClientRelay {
+            client: service.inner().client.clone(),
+            forwarder,
+            frontend_rx: self.frontend_rx,
+        };
+        (service, socket, relay)
+    }
+}
+
+/// 按结果类型解析后端的响应。
+fn parse_result<T: DeserializeOwned>(method: &str, result: Value) -> jsonrpc::Result<T> {
+    serde_json::from_value(result).map_err(|e| {
+        warn!("后端的 {} 响应不符合协议: {}", method, e);
+        jsonrpc::Error {
+            code: ErrorCode::InternalError,
+            message: format!("后端的 {} 响应不符合协议: {}", method, e).into(),
+            data: None,
+        }
+    })
+}
+
+/// 生成 [`LanguageServer`] 的实现：列出的请求和通知按 lsp_types 中对应的类型转发。
+///
+/// `async_trait` 需要看到展开后的方法，因此整个 `impl` 块由宏生成。
+macro_rules! forward_language_server {
+    (
+        requests { $($request:ident: $request_type:ty,)* }
+        // tower-lsp 的方法签名与 lsp_types 中请求的结果类型不一致的请求
+        requests_as { $($request_as:ident: $request_as_type:ty => $result:ty,)* }
+        notifications { $($notification:ident: $notification_type:ty,)* }
+    ) => {
+        #[tower_lsp::async_trait]
+        impl LanguageServer for TypedFrontend {
+            async fn initialize(&self, params: InitializeParams) -> jsonrpc::Result<InitializeResult> {
+                let mut result: InitializeResult = self.request::<request::Initialize>(params).await?;
+                for edit in &self.capability_editors {
+                    edit(&mut result.capabilities);
+                }
+                Ok(result)
+            }
+
+            async fn shutdown(&self) -> jsonrpc::Result<()> {
+                self.forwarder.request(request::Shutdown::METHOD, Value::Null).await?;
+                Ok(())
+            }
+
+            $(
+                async fn $request(
+                    &self,
+                    params: <$request_type as Request>::Params,
+                ) -> jsonrpc::Result<<$request_type as Request>::Result> {
+                    self.request::<$request_type>(params).await
+                }
+            )*
+
+            $(
+                async fn $request_as(
+                    &self,
+                    params: <$request_as_type as Request>::Params,
+                ) -> jsonrpc::Result<$result> {
+                    let params = serde_json::to_value(params)
+                        .map_err(|_| jsonrpc::Error::invalid_request())?;
+                    let method = <$request_as_type as Request>::METHOD;
+                    parse_result(method, self.forwarder.request(method, params).await?)
+                }
+            )*
+
+            $(
+                async fn $notification(&self, params: <$notification_type as Notification>::Params) {
+                    self.notify::<$notification_type>(params).await
+                }
+            )*
+        }
+    };
+}
+
+forward_language_server! {
+    requests {
+        will_save_wait_until: request::WillSaveWaitUntil,
+        goto_declaration: request::GotoDeclaration,
+        goto_definition: request::GotoDefinition,
+        goto_type_definition: request::GotoTypeDefinition,
+        goto_implementation: request::GotoImplementation,
+        references: request::References,
+        prepare_call_hierarchy: request::CallHierarchyPrepare,
+        incoming_calls: request::CallHierarchyIncomingCalls,
+        outgoing_calls: request::CallHierarchyOutgoingCalls,
+        prepare_type_hierarchy: request::TypeHierarchyPrepare,
+        supertypes: request::TypeHierarchySupertypes,
+        subtypes: request::TypeHierarchySubtypes,
+        document_highlight: request::DocumentHighlightRequest,
+        document_link: request::DocumentLinkRequest,
+        document_link_resolve: request::DocumentLinkResolve,
+        hover: request::HoverRequest,
+        code_lens: request::CodeLensRequest,
+        code_lens_resolve: request::CodeLensResolve,
+        folding_range: request::FoldingRangeRequest,
+        selection_range: request::SelectionRangeRequest,
+        document_symbol: request::DocumentSymbolRequest,
+        semantic_tokens_full: request::SemanticTokensFullRequest,
+        semantic_tokens_full_delta: request::SemanticTokensFullDeltaRequest,
+        semantic_tokens_range: request::SemanticTokensRangeRequest,
+        inlay_hint: request::InlayHintRequest,
+        inlay_hint_resolve: request::InlayHintResolveRequest,
+        moniker: request::MonikerRequest,
+        completion: request::Completion,
+        completion_resolve: request::ResolveCompletionItem,
+        diagnostic: request::DocumentDiagnosticRequest,
+        workspace_diagnostic: request::WorkspaceDiagnosticRequest,
+        signature_help: request::SignatureHelpRequest,
+        code_action: request::CodeActionRequest,
+        code_action_resolve: request::CodeActionResolveRequest,
+        document_color: request::DocumentColor,
+        color_presentation: request::ColorPresentationRequest,
+        formatting: request::Formatting,
+        range_formatting: request::RangeFormatting,
+        on_type_formatting: request::OnTypeFormatting,
+        rename: request::Rename,
+        prepare_rename: request::PrepareRenameRequest,
+        linked_editing_range: request::LinkedEditingRange,
+        symbol_resolve: request::WorkspaceSymbolResolve,
+        will_create_files: request::WillCreateFiles,
+        will_rename_files: request::WillRenameFiles,
+        will_delete_files: request::WillDeleteFiles,
+        execute_command: request::ExecuteCommand,
+    }
+    requests_as {
+        inline_value: request::InlineValueRequest => Option<Vec<InlineValue>>,
+        symbol: request::WorkspaceSymbolRequest => Option<Vec<SymbolInformation>>,
+    }
+    notifications {
+        initialized: notification::Initialized,
+        did_open: notification::DidOpenTextDocument,
+        did_change: notification::DidChangeTextDocument,
+        will_save: notification::WillSaveTextDocument,
+        did_save: notification::DidSaveTextDocument,
+        did_close: notification::DidCloseTextDocument,
+        did_change_configuration: notification::DidChangeConfiguration,
+        did_change_workspace_folders: notification::DidChangeWorkspaceFolders,
+        did_create_files: notification::DidCreateFiles,
+        did_rename_files: notification::DidRenameFiles,
+        did_delete_files: notification::DidDeleteFiles,
+        did_change_watched_files: notification::DidChangeWatchedFiles,
+    }
+}
+
+/// 把调度器发给前端的消息交给 tower-lsp 的客户端。
+///
+/// 响应交给等待它的前端请求；后端的通知和请求按 lsp_types 的类型发给前端，
+/// 协议之外的通知被丢弃，协议之外的请求以 MethodNotFound 应答后端。
+pub struct ClientRelay {
+    client: Client,
+    forwarder: Arc<Forwarder>,
+    frontend_rx: mpsc::UnboundedReceiver<Message>,
+}
+
+impl ClientRelay {
+    /// 持续转发，直到调度器的发送端全部关闭；`closing` 取消后不再接受新消息，转发完通道中已有的消息后返回。
+    pub async fn run(mut self, closing: CancellationToken) -> Result<()> {
+        loop {
+            let message = tokio::select! {
+                message = self.frontend_rx.recv() => message,
+                _ = closing.cancelled(), if !self.frontend_rx.is_closed() => {
+                    self.frontend_rx.close();
+                    continue;
+                }
+            };
+            let Some(message) = message else {
+                break;
+            };
+            let rpc = message.into_body();
+            let id = rpc.get("id").filter(|id| !id.is_null()).cloned();
+            let method = rpc
+                .get("method")
+                .and_then(|m| m.as_str())
+                .map(str::to_string);
+            let params = rpc.get("params").cloned().unwrap_or(Value::Null);
+            match (id, method) {
+                (Some(id), None) => match self.forwarder.pending.remove(&id.to_string()) {
+                    Some((_, waiter)) => {
+                        let _ = waiter.send(rpc);
+                    }
+                    None => debug!("前端已不再等待请求 {} 的响应", id),
+                },
+                (None, Some(method)) => self.notify_client(&method, params).await,
+                (Some(id), Some(method)) => {
+                    let client = self.client.clone();
+                    let forwarder = Arc::clone(&self.forwarder);
+                    tokio::spawn(async move {
+                        let response = request_client(&client, &id, &method, params).await;
+                        if let Err(e) = forwarder.dispatcher.handle_from_frontend(response).await {
+                            error!("转发前端对 {} 的响应失败: {:?}", method, e);
+                        }
+                    });
+                }
+                (None, None) => debug!("忽略无法识别的消息: {}", rpc),
+            }
+        }
+        Ok(())
+    }
+
+    async fn notify_client(&self, method: &str, params: Value) {
+        let client = &self.client;
+        let sent = match method {
+            notification::PublishDiagnostics::METHOD => {
+                forward_notification::<notification::PublishDiagnostics>(client, params).await
+            }
+            notification::LogMessage::METHOD => {
+                forward_notification::<notification::LogMessage>(client, params).await
+            }
+            notification::ShowMessage::METHOD => {
+                forward_notification::<notification::ShowMessage>(client, params).await
+            }
+            notification::Progress::METHOD => {
+                forward_notification::<notification::Progress>(client, params).await
+            }
+            notification::TelemetryEvent::METHOD => {
+                forward_notification::<notification::TelemetryEvent>(client, params).await
+            }
+            notification::LogTrace::METHOD => {
+                forward_notification::<notification::LogTrace>(client, params).await
+            }
+            _ => {
+                debug!("类型化前端不转发 {} 通知", method);
+                return;
+            }
+        };
+        if let Err(e) = sent {
+            warn!("{} 通知无法解析: {}", method, e);
+        }
+    }
+}
+
+async fn forward_notification<N: Notification>(client: &Client, params: Value) -> Result<()> {
+    let params: N::Params = serde_json::from_value(params)?;
+    client.send_notification::<N>(params).await;
+    Ok(())
+}
+
+/// 把后端的请求按类型发给前端，返回交给后端的响应。
+async fn request_client(client: &Client, id: &Value, method: &str, params: Value) -> Value {
+    let result = match method {
+        request::WorkDoneProgressCreate::METHOD => {
+            forward_request::<request::WorkDoneProgressCreate>(client, params).await
+        }
+        request::ApplyWorkspaceEdit::METHOD => {
+            forward_request::<request::ApplyWorkspaceEdit>(client, params).await
+        }
+        request::WorkspaceConfiguration::METHOD => {
+            forward_request::<request::WorkspaceConfiguration>(client, params).await
+        }
+        request::RegisterCapability::METHOD => {
+            forward_request::<request::RegisterCapability>(client, params).await
+        }
+        request::UnregisterCapability::METHOD => {
+            forward_request::<request::UnregisterCapability>(client, params).await
+        }
+        request::ShowMessageRequest::METHOD => {
+            forward_request::<request::ShowMessageRequest>(client, params).await
+        }
+        request::ShowDocument::METHOD => {
+            forward_request::<request::ShowDocument>(client, params).await
+        }
+        request::WorkspaceFoldersRequest::METHOD => {
+            forward_request::<request::WorkspaceFoldersRequest>(client, params).await
+        }
+        request::SemanticTokensRefresh::METHOD => {
+            forward_request::<request::SemanticTokensRefresh>(client, params).await
+        }
+        request::InlayHintRefreshRequest::METHOD => {
+            forward_request::<request::InlayHintRefreshRequest>(client, params).await
+        }
+        request::CodeLensRefresh::METHOD => {
+            forward_request::<request::CodeLensRefresh>(client, params).await
+        }
+        request::WorkspaceDiagnosticRefresh::METHOD => {
+            forward_request::<request::WorkspaceDiagnosticRefresh>(client, params).await
+        }
+        _ => Err(jsonrpc::Error::method_not_found()),
+    };
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(error) => json!({"jsonrpc": "2.0", "id": id, "error": error}),
+    }
+}
+
+async fn forward_request<R>(client: &Client, params: Value) -> jsonrpc::Result<Value>
+where
+    R: Request,
+    R::Result: Serialize,
+{
+    let params: R::Params = serde_json::from_value(params)
+        .map_err(|e| jsonrpc::Error::invalid_params(e.to_string()))?;
+    let result = client.send_request::<R>(params).await?;
+    serde_json::to_value(result).map_err(|_| jsonrpc::Error::internal_error())
+}
+
+/// 在 `reader`/`writer` 上运行类型化前端，前端退出（`exit` 通知）后通知后端退出。
+///
+/// tower-lsp 自己处理前端的 `exit` 通知，不会交给 [`TypedFrontend`]，因此在这里补发给后端。
+pub async fn serve<R, W>(
+    reader: R,
+    writer: W,
+    service: LspService<TypedFrontend>,
+    socket: ClientSocket,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite,
+{
+    let forwarder = Arc::clone(&service.inner().forwarder);
+    Server::new(reader, writer, socket).serve(service).await;
+    forwarder
+        .notify(notification::Exit::METHOD, Value::Null)
+        .await;
+    Ok(())
+}
@@ -1,26 +1,289 @@
+use anyhow::{Context, Result};
 use futures::future::BoxFuture;
+use regex::Regex;
+use serde_json::{Value, json};
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::mpsc;
-use tower_lsp::lsp_types::{request::Initialize, InitializeResult, ServerInfo};
+use tokio::sync::mpsc::UnboundedSender;
+use tower_lsp::lsp_types::{
+    CodeLensOptions, ColorProviderCapability, DocumentLinkOptions, InitializeResult, OneOf,
+    ServerInfo, WorkDoneProgressOptions, WorkspaceFoldersServerCapabilities,
+    WorkspaceServerCapabilities, request::Initialize,
+};
 
-use crate::dispatcher::Dispatcher;
+use crate::backend_log;
+use crate::capabilities::BackendCapabilities;
+use crate::colors;
+use crate::commands;
+use crate::compat;
+use crate::completion_sources;
+use crate::config::Config;
+use crate::dispatcher::{Dispatcher, DispatcherFn};
+use crate::document_highlight;
+use crate::document_store::DocumentStore;
+use crate::file_status;
+use crate::history;
+use crate::include_check;
+use crate::inline_values;
+use crate::message::Message;
+use crate::message_throttle::{self, MessageThrottle};
+use crate::metrics::{self, Metrics};
+use crate::notebooks;
+use crate::prefetch::Prefetcher;
+use crate::profiles;
+use crate::rate_limit;
+use crate::remote;
+use crate::rename;
+use crate::shadow;
+use crate::signature_help;
+use crate::stats;
+use crate::symbol_index;
+use crate::todos;
+use crate::trace::Direction;
+use crate::trust;
+
+/// 处理器的处理结果，决定同一方法的下一个处理器是否执行。
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// 把（可能修改过的）消息交给下一个处理器，没有下一个处理器时照常转发
+    Continue(Value),
+    /// 不再转发，把这条消息发回原消息的来源，例如代替后端应答前端的请求
+    Respond(Value),
+    /// 丢弃消息，之后的处理器不再执行
+    Drop,
+}
+
+/// 处理器匹配的方法。
+#[derive(Debug, Clone)]
+pub enum MethodPattern {
+    /// 完全相同的方法
+    Exact(String),
+    /// 以给定前缀开始的方法
+    Prefix(String),
+    /// 匹配正则表达式的方法
+    Regex(Regex),
+}
+
+impl MethodPattern {
+    /// 解析方法模式：`re:` 开头的是正则表达式，以 `*` 结尾的是前缀（例如 `textDocument/*`），
+    /// 其他是完整的方法名。
+    ///
+    /// # 错误
+    ///
+    /// 如果正则表达式无效，返回错误
+    pub fn parse(pattern: &str) -> Result<Self> {
+        if let Some(regex) = pattern.strip_prefix("re:") {
+            let regex =
+                Regex::new(regex).with_context(|| format!("无效的方法模式: {}", pattern))?;
+            return Ok(Self::Regex(regex));
+        }
+        Ok(match pattern.strip_suffix('*') {
+            Some(prefix) => Self::Prefix(prefix.to_string()),
+            None => Self::Exact(pattern.to_string()),
+        })
+    }
+
+    /// 方法是否匹配这个模式。
+    pub fn matches(&self, method: &str) -> bool {
+        match self {
+            Self::Exact(exact) => method == exact,
+            Self::Prefix(prefix) => method.starts_with(prefix.as_str()),
+            Self::Regex(regex) => regex.is_match(method),
+        }
+    }
+}
+
+impl std::fmt::Display for MethodPattern {
+    /// 按 [`MethodPattern::parse`] 接受的写法显示模式。
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exact(exact) => write!(f, "{}", exact),
+            Self::Prefix(prefix) => write!(f, "{}*", prefix),
+            Self::Regex(regex) => write!(f, "re:{}", regex.as_str()),
+        }
+    }
+}
+
+struct Registered {
+    priority: i32,
+    /// 注册顺序，优先级相同时先注册的先执行
+    order: usize,
+    handler: DispatcherFn,
+}
+
+/// 一个方向的处理器表。
+///
+/// 完整方法名的处理器按方法查找，模式处理器逐个匹配；
+/// 同一方法匹配到的所有处理器合在一起，按优先级从高到低、再按注册顺序执行。
+#[derive(Default)]
+pub struct HandlerTable {
+    exact: HashMap<String, Vec<Registered>>,
+    patterns: Vec<(MethodPattern, Registered)>,
+    registered: usize,
+}
+
+impl HandlerTable {
+    /// 为匹配 `pattern` 的方法注册处理器。
+    pub fn insert(&mut self, pattern: MethodPattern, priority: i32, handler: DispatcherFn) {
+        let registered = Registered {
+            priority,
+            order: self.registered,
+            handler,
+        };
+        self.registered += 1;
+        match pattern {
+            MethodPattern::Exact(method) => self.exact.entry(method).or_default().push(registered),
+            pattern => self.patterns.push((pattern, registered)),
+        }
+    }
+
+    /// 方法的所有处理器，按执行顺序排列。
+    pub fn handlers(&self, method: &str) -> Vec<DispatcherFn> {
+        let mut matched: Vec<&Registered> = self
+            .exact
+            .get(method)
+            .into_iter()
+            .flatten()
+            .chain(
+                self.patterns
+                    .iter()
+                    .filter(|(pattern, _)| pattern.matches(method))
+                    .map(|(_, registered)| registered),
+            )
+            .collect();
+        matched
+            .sort_by_key(|registered| (std::cmp::Reverse(registered.priority), registered.order));
+        matched
+            .into_iter()
+            .map(|registered| registered.handler)
+            .collect()
+    }
+
+    /// 注册了处理器的所有方法和模式，以及各自的处理器数量，按名称排序。
+    pub fn patterns(&self) -> Vec<(String, usize)> {
+        let mut counts: HashMap<String, usize> = self
+            .exact
+            .iter()
+            .map(|(method, handlers)| (method.clone(), handlers.len()))
+            .collect();
+        for (pattern, _) in &self.patterns {
+            *counts.entry(pattern.to_string()).or_default() += 1;
+        }
+        let mut patterns: Vec<(String, usize)> = counts.into_iter().collect();
+        patterns.sort();
+        patterns
+    }
+
+    /// 方法是否有处理器。
+    pub fn contains(&self, method: &str) -> bool {
+        self.exact.contains_key(method)
+            || self
+                .patterns
+                .iter()
+                .any(|(pattern, _)| pattern.matches(method))
+    }
+}
+
+/// 处理器的上下文：消息所属的分片、两个方向的发送器，以及调度器的共享服务。
+///
+/// 处理器需要新的服务时在这里加访问方法，不用修改处理器的签名。
+pub struct HandlerCtx<'a> {
+    /// 消息来自或者发往的分片
+    pub shard: usize,
+    /// 发送消息到前端的通道
+    pub frontend_sender: UnboundedSender<Message>,
+    /// 发送消息到该分片后端的通道
+    pub backend_sender: UnboundedSender<Message>,
+    dispatcher: &'a Arc<Dispatcher>,
+}
+
+impl<'a> HandlerCtx<'a> {
+    /// 为 `shard` 分片的消息创建上下文。
+    pub fn new(dispatcher: &'a Arc<Dispatcher>, shard: usize) -> Self {
+        Self {
+            shard,
+            frontend_sender: dispatcher.frontend_sender(),
+            backend_sender: dispatcher.backend_sender(shard),
+            dispatcher,
+        }
+    }
+
+    /// 前端打开的所有文档。
+    pub fn documents(&self) -> &'a DocumentStore {
+        self.dispatcher.documents()
+    }
+
+    /// 代理的配置。
+    pub fn config(&self) -> &'a Config {
+        self.dispatcher.config()
+    }
+
+    /// 预取的响应缓存。
+    pub fn cache(&self) -> &'a Prefetcher {
+        self.dispatcher.prefetcher()
+    }
+
+    /// 后端实际支持的能力，收到 `initialize` 响应之前是未知的。
+    pub fn backend_capabilities(&self) -> &'a BackendCapabilities {
+        self.dispatcher.backend_capabilities()
+    }
+
+    /// 后端发来的 showMessage 和 logMessage 的去重。
+    pub fn message_throttle(&self) -> &'a MessageThrottle {
+        self.dispatcher.message_throttle()
+    }
+
+    /// 代理的运行指标。
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.dispatcher.metrics()
+    }
+
+    /// 由代理向该分片的后端发起请求并等待结果。
+    ///
+    /// # 错误
+    ///
+    /// 如果后端返回错误、通道已关闭或者超时没有响应，返回错误
+    pub async fn request_backend(&self, method: &str, params: Value) -> Result<Value> {
+        self.dispatcher
+            .request_backend(self.shard, method, params)
+            .await
+    }
+
+    /// 调度器本身，供代理内置的处理器使用。
+    pub(crate) fn dispatcher(&self) -> &'a Arc<Dispatcher> {
+        self.dispatcher
+    }
+}
+
+/// 以 `result` 应答 `rpc` 请求的响应，错误使用 `InternalError` 错误码。
+pub fn response(rpc: &Value, result: Result<Value>) -> Value {
+    let id = rpc.get("id").cloned().unwrap_or(json!(null));
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(e) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": -32603, "message": e.to_string()},
+        }),
+    }
+}
 
 /// 处理 initialize 请求的处理器。
 ///
-/// 这个函数修改 clangd 的初始化响应，设置服务器信息。
+/// 这个函数修改 clangd 的初始化响应，设置服务器信息，
+/// 声明代理支持多工作区文件夹（由代理跟踪，clangd 本身不支持），并加入代理自己实现的命令。
+/// 开启了 TODO 标记的代码透镜时，后端不提供代码透镜也声明支持；`#include` 的文档链接总是由代理补上。
+/// 开启了颜色字面量的本地查找时同样声明颜色。行内值由代理提供。
 ///
 /// # 参数
 ///
 /// * `rpc` - 接收到的 RPC 消息
-/// * `frontend_sender` - 发送消息到前端的通道
+/// * `ctx` - 处理器上下文
 ///
 /// # 返回
 ///
-/// 返回 `BoxFuture` 包装的 `Result<()>`，表示处理是否成功
-fn handle_initialize(
-    rpc: serde_json::Value,
-    frontend_sender: mpsc::UnboundedSender<String>,
-) -> BoxFuture<'static, anyhow::Result<()>> {
+/// 返回修改后的响应，交给之后的处理器或转发给前端
+fn handle_initialize(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
     Box::pin(async move {
         let mut raw_rpc = rpc.clone();
         // Step 1: 转成 tower-lsp
@@ -35,23 +298,135 @@ fn handle_initialize(
             version: Some("0.1.0".into()),
         });
 
-        let edited = serde_json::to_value(init_result)?;
+        let workspace = init_result
+            .capabilities
+            .workspace
+            .get_or_insert_with(WorkspaceServerCapabilities::default);
+        workspace.workspace_folders = Some(WorkspaceFoldersServerCapabilities {
+            supported: Some(true),
+            change_notifications: Some(OneOf::Left(true)),
+        });
+        commands::advertise(&mut init_result.capabilities);
+        if ctx.config().todos.code_lens && init_result.capabilities.code_lens_provider.is_none() {
+            init_result.capabilities.code_lens_provider = Some(CodeLensOptions {
+                resolve_provider: Some(false),
+            });
+        }
+        if ctx.config().colors.fallback && init_result.capabilities.color_provider.is_none() {
+            init_result.capabilities.color_provider = Some(ColorProviderCapability::Simple(true));
+        }
+        init_result
+            .capabilities
+            .inline_value_provider
+            .get_or_insert(OneOf::Left(true));
+        init_result
+            .capabilities
+            .document_link_provider
+            .get_or_insert(DocumentLinkOptions {
+                resolve_provider: Some(false),
+                work_done_progress_options: WorkDoneProgressOptions::default(),
+            });
+
+        let mut edited = serde_json::to_value(init_result)?;
+        // lsp_types 0.94 还没有笔记本的能力
+        if ctx.config().notebooks.enabled {
+            edited["capabilities"]["notebookDocumentSync"] =
+                notebooks::sync_options(&ctx.config().notebooks.languages);
+        }
 
         if let Some(obj) = raw_rpc.as_object_mut() {
             obj.insert("result".to_string(), edited); // 修改字段
         }
 
         // Step 3: 转回 JSON
-        let message = Dispatcher::format_lsp_message(&raw_rpc)?;
-        frontend_sender.send(message)?;
-        Ok(())
+        Ok(Verdict::Continue(raw_rpc))
+    })
+}
+
+/// 代答处理器的优先级，低于默认优先级，其他处理器（例如日志、改写）仍然先看到请求。
+pub const STUB_PRIORITY: i32 = -100;
+
+/// 后端缺少某项功能时由代理在本地应答的处理器（TODO 的代码透镜、`#include` 链接、颜色、行内值、
+/// ctags 的工作区符号）的优先级：在兼容垫片改写请求之后，在代答处理器之前。
+pub const LOCAL_PROVIDER_PRIORITY: i32 = -80;
+
+/// 后端超时后以代理的结果应答的处理器（补全来源、签名帮助、文档高亮）的优先级，低于其他所有处理器，
+/// 请求确实要转发给后端时才开始计时。
+pub const FALLBACK_PRIORITY: i32 = -200;
+
+/// 返回列表的方法，空结果是空数组；其他方法的空结果是 `null`。
+const LIST_METHODS: &[&str] = &[
+    "textDocument/codeAction",
+    "textDocument/codeLens",
+    "textDocument/colorPresentation",
+    "textDocument/documentColor",
+    "textDocument/documentHighlight",
+    "textDocument/documentLink",
+    "textDocument/documentSymbol",
+    "textDocument/foldingRange",
+    "textDocument/inlayHint",
+    "textDocument/references",
+    "textDocument/selectionRange",
+    "workspace/symbol",
+];
+
+/// 方法的空结果，代替后端应答不支持的方法时使用。
+pub fn empty_result(method: &str) -> Value {
+    if LIST_METHODS.contains(&method) {
+        return json!([]);
+    }
+    if method.starts_with("textDocument/semanticTokens/") {
+        return json!({"data": []});
+    }
+    match method {
+        "textDocument/diagnostic" => json!({"kind": "full", "items": []}),
+        "workspace/diagnostic" => json!({"items": []}),
+        _ => json!(null),
+    }
+}
+
+/// 代替后端应答配置为代答的方法：请求以空结果应答，通知直接丢弃。
+fn handle_stub(rpc: Value, _ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let Some(id) = rpc.get("id") else {
+            return Ok(Verdict::Drop);
+        };
+        let method = rpc.get("method").and_then(|m| m.as_str()).unwrap_or("");
+        Ok(Verdict::Respond(
+            json!({"jsonrpc": "2.0", "id": id, "result": empty_result(method)}),
+        ))
     })
 }
 
+/// 注册代理内置的处理器：代理自己的方法（`codefuse/*` 等）、速率限制、本地应答和超时后的代答。
+///
+/// 这些处理器不依赖启动时的设置，由 [`Dispatcher`] 创建时注册，启用与否在处理消息时按当前配置判断。
+pub(crate) fn register_builtin(dispatcher: &mut Dispatcher) {
+    rate_limit::register(dispatcher);
+    rename::register(dispatcher);
+    metrics::register(dispatcher);
+    stats::register(dispatcher);
+    shadow::register(dispatcher);
+    file_status::register(dispatcher);
+    history::register(dispatcher);
+    backend_log::register(dispatcher);
+    profiles::register(dispatcher);
+    trust::register(dispatcher);
+    todos::register(dispatcher);
+    include_check::register(dispatcher);
+    colors::register(dispatcher);
+    inline_values::register(dispatcher);
+    remote::register(dispatcher);
+    symbol_index::register(dispatcher);
+    completion_sources::register(dispatcher);
+    signature_help::register(dispatcher);
+    document_highlight::register(dispatcher);
+}
+
 /// 设置处理器函数，为特定的 LSP 方法注册处理逻辑。
 ///
-/// 这个函数用于注册从后端（clangd）接收到的消息的处理函数。
-/// 目前注册了 `initialize` 方法的处理器，用于修改初始化响应。
+/// 注册了 `initialize` 响应的处理器，用于修改初始化响应；旧版本 clangd 的兼容垫片；
+/// 后端消息的去重；以及配置中 `protocol.stub_methods` 的代答处理器，调用之前需要先设置配置。
 ///
 /// # 参数
 ///
@@ -59,11 +434,16 @@ fn handle_initialize(
 ///
 /// # 示例
 ///
-/// ```rust
-/// setup_handlers(dispatcher.clone()).await;
+/// ```rust,ignore
+/// let mut dispatcher = Dispatcher::new(backend_tx, frontend_tx).with_config(config);
+/// setup_handlers(&mut dispatcher);
+/// let dispatcher = Arc::new(dispatcher);
 /// ```
-pub async fn setup_handlers(dispatcher: Arc<Dispatcher>) {
-    dispatcher
-        .register_resp_from_backend::<Initialize>(handle_initialize)
-        .await;
+pub fn setup_handlers(dispatcher: &mut Dispatcher) {
+    dispatcher.register_resp_from_backend::<Initialize>(handle_initialize);
+    compat::register(dispatcher);
+    message_throttle::register(dispatcher);
+    for method in dispatcher.config().protocol.stub_methods.clone() {
+        dispatcher.register_handler(Direction::Frontend, &method, STUB_PRIORITY, handle_stub);
+    }
 }
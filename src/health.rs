@@ -0,0 +1,241 @@
+//! # 健康检查模块
+//!
+//! 记录每个分片的后端是否在运行、最近一次响应的时间，并可选地在本机端口上提供 `GET /healthz`，
+//! 以 JSON 返回代理的状态，供开发容器编排和远程开发工具看护代理。
+
+use anyhow::{Context, Result};
+use log::{debug, info};
+use serde::Serialize;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::dispatcher::Dispatcher;
+
+/// 请求头的最大长度，超过时直接关闭连接。
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// 读取请求头的最长时间，客户端连接后不发送请求时到期关闭连接。
+pub const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 接受连接失败（例如文件描述符用尽）后，再次接受之前等待的时间。
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// 各分片后端的运行状态。
+pub struct Health {
+    started: Instant,
+    backends: Vec<BackendHealth>,
+}
+
+struct BackendHealth {
+    name: String,
+    alive: AtomicBool,
+    /// 最近一次响应距 `started` 的毫秒数加一，0 表示还没有响应
+    last_response: AtomicU64,
+}
+
+/// `GET /healthz` 返回的状态。
+///
+/// - `status`: 所有后端都在运行时是 `ok`，否则是 `degraded`
+/// - `uptime_secs`: 代理运行的时间
+/// - `backends`: 各分片的后端
+/// - `queues`: 排队和进行中的工作
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReport {
+    pub status: &'static str,
+    pub uptime_secs: u64,
+    pub backends: Vec<BackendReport>,
+    pub queues: QueueReport,
+}
+
+/// 一个分片的后端。
+///
+/// - `last_response_age_ms`: 距离后端最近一次响应的时间，还没有响应时为 `null`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackendReport {
+    pub name: String,
+    pub alive: bool,
+    pub last_response_age_ms: Option<u64>,
+}
+
+/// 排队深度。
+///
+/// - `handlers_running`/`handlers_waiting`: 正在运行和等待处理许可的消息处理任务
+/// - `pending_backend_requests`: 已转发给后端、还没有收到响应的前端请求
+/// - `active_lanes`: 有消息在处理或等待的文档通道
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueReport {
+    pub handlers_running: usize,
+    pub handlers_waiting: usize,
+    pub pending_backend_requests: usize,
+    pub active_lanes: usize,
+}
+
+impl Health {
+    /// 为给定名称的分片创建状态，后端启动之前都不在运行。
+    pub fn new(names: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            started: Instant::now(),
+            backends: names
+                .into_iter()
+                .map(|name| BackendHealth {
+                    name,
+                    alive: AtomicBool::new(false),
+                    last_response: AtomicU64::new(0),
+                })
+                .collect(),
+        }
+    }
+
+    /// 分片的后端开始运行或退出。
+    pub fn set_alive(&self, shard: usize, alive: bool) {
+        if let Some(backend) = self.backends.get(shard) {
+            backend.alive.store(alive, Ordering::Relaxed);
+        }
+    }
+
+    /// 分片的后端是否正在运行。
+    pub fn is_alive(&self, shard: usize) -> bool {
+        self.backends
+            .get(shard)
+            .is_some_and(|backend| backend.alive.load(Ordering::Relaxed))
+    }
+
+    /// 分片的后端发来一个响应。
+    pub fn responded(&self, shard: usize) {
+        if let Some(backend) = self.backends.get(shard) {
+            let elapsed = self.started.elapsed().as_millis() as u64;
+            backend.last_response.store(elapsed + 1, Ordering::Relaxed);
+        }
+    }
+
+    /// 汇总代理当前的状态。
+    pub fn report(&self, dispatcher: &Dispatcher) -> HealthReport {
+        let now = self.started.elapsed().as_millis() as u64;
+        let backends: Vec<BackendReport> = self
+            .backends
+            .iter()
+            .map(|backend| BackendReport {
+                name: backend.name.clone(),
+                alive: backend.alive.load(Ordering::Relaxed),
+                last_response_age_ms: match backend.last_response.load(Ordering::Relaxed) {
+                    0 => None,
+                    at => Some(now.saturating_sub(at - 1)),
+                },
+            })
+            .collect();
+        let metrics = dispatcher.metrics().snapshot();
+        HealthReport {
+            status: if backends.iter().all(|backend| backend.alive) {
+                "ok"
+            } else {
+                "degraded"
+            },
+            uptime_secs: self.started.elapsed().as_secs(),
+            backends,
+            queues: QueueReport {
+                handlers_running: metrics.handlers_running,
+                handlers_waiting: metrics.handlers_waiting,
+                pending_backend_requests: dispatcher.pending_requests(),
+                active_lanes: dispatcher.lanes().active(),
+            },
+        }
+    }
+}
+
+/// 在本机的 `port` 端口上监听健康检查请求。
+///
+/// 只绑定 127.0.0.1，端口为 0 时由系统分配。
+///
+/// # 返回
+///
+/// 返回实际监听的地址和处理连接的任务
+///
+/// # 错误
+///
+/// 如果端口无法绑定，返回错误
+pub async fn serve(
+    port: u16,
+    dispatcher: Arc<Dispatcher>,
+) -> Result<(SocketAddr, tokio::task::JoinHandle<()>)> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .await
+        .with_context(|| format!("无法监听健康检查端口 {}", port))?;
+    let address = listener.local_addr()?;
+    info!("健康检查地址: http://{}/healthz", address);
+    let task = tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    debug!("接受健康检查连接失败: {}", e);
+                    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            let dispatcher = Arc::clone(&dispatcher);
+            tokio::spawn(async move {
+                if let Err(e) = respond(stream, &dispatcher).await {
+                    debug!("健康检查连接出错: {:?}", e);
+                }
+            });
+        }
+    });
+    Ok((address, task))
+}
+
+/// 读取一个 HTTP 请求并应答，之后关闭连接。
+async fn respond(mut stream: TcpStream, dispatcher: &Dispatcher) -> Result<()> {
+    let Ok(request) = tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await else {
+        debug!("健康检查连接在 {:?} 内没有发送请求", READ_TIMEOUT);
+        return Ok(());
+    };
+    let Some(request) = request? else {
+        return Ok(());
+    };
+
+    let request_line = request.split(|b| *b == b'\n').next().unwrap_or_default();
+    let mut parts = std::str::from_utf8(request_line)?.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/healthz")) => {
+            let report = dispatcher.health().report(dispatcher);
+            let status = if report.status == "ok" {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            (status, serde_json::to_string(&report)?)
+        }
+        (Some("GET"), _) => ("404 Not Found", String::new()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// 读取请求头；连接关闭或者请求头过长时返回 `None`。
+async fn read_request(stream: &mut TcpStream) -> Result<Option<Vec<u8>>> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST_SIZE {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    Ok(Some(request))
+}
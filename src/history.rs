@@ -0,0 +1,134 @@
+//! # 请求历史模块
+//!
+//! 在有界的环形缓冲区中保留最近的前端请求摘要（方法、id、文档、耗时、结果和消息大小），
+//! 通过自定义请求 `codefuse/history` 查询。远程机器上排查“刚才发生了什么”时不需要登录去看日志。
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use crate::config::HistoryConfig;
+use crate::dispatcher::{DEFAULT_HANDLER_PRIORITY, Dispatcher};
+use crate::handlers::{self, HandlerCtx, Verdict};
+use crate::lifecycle::{FinishedRequest, RequestObserver};
+use crate::trace::Direction;
+
+/// 查询请求历史的自定义请求。
+///
+/// 参数都是可选的：`limit` 限制返回的条数，`method` 只返回这个方法的请求。
+/// 结果是 [`HistoryEntry`] 的数组，最近应答的请求在前。
+pub const HISTORY: &str = "codefuse/history";
+
+/// 一个已经应答的请求。
+///
+/// - `received_at_ms`: 代理收到请求的时间（Unix 毫秒）
+/// - `duration_ms`: 从收到请求到应答前端的时间
+/// - `status`: `ok` 或 `error`
+/// - `request_bytes`/`response_bytes`: 请求和响应序列化后的字节数
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub id: Value,
+    pub method: String,
+    pub uri: Option<String>,
+    pub received_at_ms: u64,
+    pub duration_ms: f64,
+    pub status: &'static str,
+    pub request_bytes: usize,
+    pub response_bytes: usize,
+}
+
+/// 最近的请求。容量为 0 时不记录。
+#[derive(Default)]
+pub struct RequestHistory {
+    capacity: usize,
+    entries: Mutex<VecDeque<HistoryEntry>>,
+}
+
+impl RequestHistory {
+    /// 按 `[history]` 配置创建。
+    pub fn new(config: &HistoryConfig) -> Self {
+        Self {
+            capacity: config.capacity,
+            entries: Mutex::new(VecDeque::with_capacity(config.capacity)),
+        }
+    }
+
+    /// 是否记录请求。
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// 最近应答的请求，最近的在前。
+    ///
+    /// # 参数
+    ///
+    /// * `limit` - 最多返回的条数，`None` 表示全部
+    /// * `method` - 只返回这个方法的请求
+    pub fn recent(&self, limit: Option<usize>, method: Option<&str>) -> Vec<HistoryEntry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|entry| method.is_none_or(|method| entry.method == method))
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+}
+
+impl RequestObserver for RequestHistory {
+    fn finished(&self, request: &FinishedRequest) {
+        if !self.is_enabled() {
+            return;
+        }
+        let entry = HistoryEntry {
+            id: request.id.clone(),
+            method: request.method.clone(),
+            uri: request.uri.clone(),
+            received_at_ms: request
+                .received_at
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            duration_ms: request.duration.as_secs_f64() * 1000.0,
+            status: if request.failed { "error" } else { "ok" },
+            request_bytes: request.request_bytes,
+            response_bytes: request.response_bytes,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+}
+
+/// 注册 `codefuse/history` 的处理器，由代理直接应答。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        HISTORY,
+        DEFAULT_HANDLER_PRIORITY,
+        answer_history,
+    );
+}
+
+/// 以最近完成的请求应答，`limit` 和 `method` 参数限制条数和方法。
+fn answer_history(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let limit = rpc
+            .pointer("/params/limit")
+            .and_then(|limit| limit.as_u64())
+            .map(|limit| limit as usize);
+        let filter = rpc.pointer("/params/method").and_then(|m| m.as_str());
+        let entries = ctx.dispatcher().history().recent(limit, filter);
+        let response = handlers::response(&rpc, Ok(json!(entries)));
+        Ok(Verdict::Respond(response))
+    })
+}
@@ -0,0 +1,105 @@
+//! # 空闲休眠模块
+//!
+//! 编辑器在后台长时间没有消息时，分片的后端按 `[backend.idle]` 配置休眠：暂停进程或者结束进程。
+//! 下一条发给该分片的消息到达时先唤醒后端（结束的后端重新启动并重放打开的文档），再转发这条消息。
+
+use anyhow::{Result, bail};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, watch};
+
+use crate::config::{IdleAction, IdleConfig};
+
+/// 检查分片是否空闲的最长间隔。
+pub const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// `after_mins` 的上限（一周）。
+pub const MAX_IDLE_MINS: u64 = 7 * 24 * 60;
+
+/// 检查 `[backend.idle]`：`after_mins` 在 1 到 [`MAX_IDLE_MINS`] 之间。
+///
+/// # 错误
+///
+/// 如果 `after_mins` 超出范围，返回错误
+pub fn validate(config: &IdleConfig) -> Result<()> {
+    if let Some(mins) = config.after_mins
+        && !(1..=MAX_IDLE_MINS).contains(&mins)
+    {
+        bail!(
+            "[backend.idle] after_mins 必须在 1 到 {} 之间: {}",
+            MAX_IDLE_MINS,
+            mins
+        );
+    }
+    Ok(())
+}
+
+/// 一个分片最近的消息和后端的休眠状态，由转发消息的任务和监管者共用。
+pub struct IdleTracker {
+    /// 最近一条消息到达的时间，也保护休眠状态的切换
+    last_message: Mutex<Instant>,
+    /// 后端的休眠方式，`None` 表示后端醒着
+    asleep: watch::Sender<Option<IdleAction>>,
+    wake_requested: Notify,
+}
+
+impl Default for IdleTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdleTracker {
+    pub fn new() -> Self {
+        Self {
+            last_message: Mutex::new(Instant::now()),
+            asleep: watch::channel(None).0,
+            wake_requested: Notify::new(),
+        }
+    }
+
+    /// 记录一条发给分片的消息。
+    ///
+    /// # 返回
+    ///
+    /// 返回后端的休眠方式，后端醒着时返回 `None`
+    pub fn touch(&self) -> Option<IdleAction> {
+        let mut last_message = self.last_message.lock().unwrap();
+        *last_message = Instant::now();
+        *self.asleep.borrow()
+    }
+
+    /// 分片空闲了 `after` 时进入休眠。
+    ///
+    /// # 返回
+    ///
+    /// 进入了休眠时返回 `true`，调用方随后让后端休眠并等待 [`wait_for_wake`](Self::wait_for_wake)
+    pub fn try_sleep(&self, after: Duration, action: IdleAction) -> bool {
+        let last_message = self.last_message.lock().unwrap();
+        if last_message.elapsed() < after || self.asleep.borrow().is_some() {
+            return false;
+        }
+        self.asleep.send_replace(Some(action));
+        true
+    }
+
+    /// 等待有消息要发给休眠的后端。
+    pub async fn wait_for_wake(&self) {
+        self.wake_requested.notified().await;
+    }
+
+    /// 后端已经醒来，等待中的消息可以转发了。
+    pub fn woke(&self) {
+        let mut last_message = self.last_message.lock().unwrap();
+        *last_message = Instant::now();
+        self.asleep.send_replace(None);
+    }
+
+    /// 请求唤醒后端并等待它醒来。
+    pub async fn wake(&self) {
+        let mut asleep = self.asleep.subscribe();
+        self.wake_requested.notify_one();
+        // 发送方和接收方都在这里，通道不会关闭
+        let _ = asleep.wait_for(|asleep| asleep.is_none()).await;
+    }
+}
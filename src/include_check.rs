@@ -0,0 +1,369 @@
+//! # 头文件检查模块
+//!
+//! 轻量的本地分析，补充 clangd 的诊断：引号形式的 `#include` 在文件所在目录和 `compile_commands.json`
+//! 中的包含目录里都找不到时给出警告，头文件经过一串包含又回到当前文件时报告包含循环。
+//! 尖括号形式的包含可能来自编译器自带的系统目录，不检查。
+//! 后端不提供文档链接时，代理用同样的包含目录把 `#include` 的头文件名解析成可以点击的链接。
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use log::{debug, warn};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::SystemTime;
+
+use tower_lsp::lsp_types::Url;
+use tower_lsp::lsp_types::request::{DocumentLinkRequest, Request};
+
+use crate::dispatcher::Dispatcher;
+use crate::document_store::position_at;
+use crate::handlers::{self, HandlerCtx, LOCAL_PROVIDER_PRIORITY, Verdict};
+use crate::trace::Direction;
+
+/// 头文件诊断的来源名称。
+pub const SOURCE: &str = "includes";
+
+/// 查找包含循环时最多读取的文件数，避免在大型项目中遍历整个包含图。
+const MAX_VISITED: usize = 256;
+
+/// 诊断的严重级别：警告（DiagnosticSeverity::WARNING）。
+const WARNING: u8 = 2;
+
+/// 在工作区根目录下查找编译数据库的位置。
+pub const DATABASE_PATHS: &[&str] = &["compile_commands.json", "build/compile_commands.json"];
+
+static QUOTED_INCLUDE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?m)^[ \t]*#[ \t]*include[ \t]*"([^"\n]+)""#).unwrap());
+
+static ANY_INCLUDE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?m)^[ \t]*#[ \t]*include[ \t]*(?:"([^"\n]+)"|<([^>\n]+)>)"#).unwrap()
+});
+
+/// 编译数据库中的一条编译命令。
+#[derive(Deserialize)]
+struct CompileCommand {
+    directory: PathBuf,
+    file: PathBuf,
+    #[serde(default)]
+    arguments: Vec<String>,
+    command: Option<String>,
+}
+
+/// `compile_commands.json` 中每个文件的包含目录。
+#[derive(Debug, Default)]
+pub struct CompileDatabase {
+    dirs: HashMap<PathBuf, Vec<PathBuf>>,
+    /// 所有文件的包含目录，用于不在数据库中的文件（通常是头文件）
+    all_dirs: Vec<PathBuf>,
+}
+
+impl CompileDatabase {
+    /// 读取 `compile_commands.json`。
+    ///
+    /// # 错误
+    ///
+    /// 如果文件无法读取或者格式不对，返回错误
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("无法读取 {}", path.display()))?;
+        let commands: Vec<CompileCommand> =
+            serde_json::from_str(&text).with_context(|| format!("无法解析 {}", path.display()))?;
+
+        let mut database = Self::default();
+        for command in commands {
+            let arguments = match &command.command {
+                Some(line) if command.arguments.is_empty() => split_command(line),
+                _ => command.arguments.clone(),
+            };
+            let dirs: Vec<PathBuf> = include_dirs(&arguments)
+                .into_iter()
+                .map(|dir| command.directory.join(dir))
+                .collect();
+            for dir in &dirs {
+                if !database.all_dirs.contains(dir) {
+                    database.all_dirs.push(dir.clone());
+                }
+            }
+            database
+                .dirs
+                .insert(command.directory.join(&command.file), dirs);
+        }
+        Ok(database)
+    }
+
+    /// 文件的包含目录，不在数据库中的文件使用所有文件的包含目录。
+    pub fn include_dirs(&self, file: &Path) -> &[PathBuf] {
+        self.dirs.get(file).unwrap_or(&self.all_dirs)
+    }
+}
+
+/// 编译参数中的包含目录：`-I`、`-iquote`、`-isystem` 和 `-idirafter`，目录可以紧跟选项或者是下一个参数。
+fn include_dirs(arguments: &[String]) -> Vec<String> {
+    const OPTIONS: &[&str] = &["-I", "-iquote", "-isystem", "-idirafter"];
+    let mut dirs = Vec::new();
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        for option in OPTIONS {
+            if argument == option {
+                dirs.extend(arguments.next().cloned());
+                break;
+            }
+            if let Some(dir) = argument.strip_prefix(option) {
+                dirs.push(dir.to_string());
+                break;
+            }
+        }
+    }
+    dirs
+}
+
+/// 把 `command` 形式的命令行按空白拆成参数，支持单引号、双引号和反斜杠转义。
+pub fn split_command(line: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => arguments.extend(current.take()),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                current.get_or_insert_default();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => current.get_or_insert_default().push(c),
+            (_, '\\') => current.get_or_insert_default().extend(chars.next()),
+            (_, c) => current.get_or_insert_default().push(c),
+        }
+    }
+    arguments.extend(current);
+    arguments
+}
+
+/// 引号形式包含的头文件：在包含它的文件所在目录和包含目录中依次查找。
+fn resolve(name: &str, including: &Path, dirs: &[PathBuf]) -> Option<PathBuf> {
+    including
+        .parent()
+        .into_iter()
+        .chain(dirs.iter().map(PathBuf::as_path))
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// 文本中引号形式的包含：头文件名和它在文本中的字节范围。
+fn quoted_includes(text: &str) -> impl Iterator<Item = (&str, std::ops::Range<usize>)> {
+    QUOTED_INCLUDE.captures_iter(text).filter_map(|captures| {
+        let name = captures.get(1)?;
+        Some((name.as_str(), name.range()))
+    })
+}
+
+/// 调度器使用的头文件检查，按需加载并在文件变化时重新加载编译数据库。
+#[derive(Default)]
+pub struct IncludeCheck {
+    enabled: bool,
+    /// 编译数据库的路径、修改时间和内容
+    database: Mutex<Option<(PathBuf, SystemTime, Arc<CompileDatabase>)>>,
+}
+
+impl IncludeCheck {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            database: Mutex::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 检查文档中引号形式的包含。
+    ///
+    /// # 参数
+    ///
+    /// * `path` - 文档的路径
+    /// * `text` - 文档的内容，可能还没有保存
+    /// * `roots` - 工作区根目录，在其中查找 `compile_commands.json`
+    ///
+    /// # 返回
+    ///
+    /// 返回找不到的头文件和包含循环的警告
+    pub fn check(&self, path: &Path, text: &str, roots: &[PathBuf]) -> Vec<Value> {
+        let database = self.database(roots);
+        let dirs = database.include_dirs(path);
+        let mut diagnostics = Vec::new();
+        for (name, range) in quoted_includes(text) {
+            let message = match resolve(name, path, dirs) {
+                None => format!("找不到头文件 \"{}\"", name),
+                Some(header) => match find_cycle(path, &header, &database) {
+                    Some(cycle) => format!("包含循环: {}", describe_cycle(path, &cycle)),
+                    None => continue,
+                },
+            };
+            diagnostics.push(json!({
+                "range": {
+                    "start": position_at(text, range.start),
+                    "end": position_at(text, range.end),
+                },
+                "severity": WARNING,
+                "source": SOURCE,
+                "message": message,
+            }));
+        }
+        diagnostics
+    }
+
+    /// 文档中 `#include` 的文档链接，目标是解析到的头文件，找不到的头文件没有链接。
+    ///
+    /// 引号形式先在文档所在目录查找，尖括号形式只在包含目录中查找。
+    ///
+    /// # 参数
+    ///
+    /// * `path` - 文档的路径
+    /// * `text` - 文档的内容，可能还没有保存
+    /// * `roots` - 工作区根目录，在其中查找 `compile_commands.json`
+    pub fn links(&self, path: &Path, text: &str, roots: &[PathBuf]) -> Vec<Value> {
+        let database = self.database(roots);
+        let dirs = database.include_dirs(path);
+        ANY_INCLUDE
+            .captures_iter(text)
+            .filter_map(|captures| {
+                let (name, header) = match (captures.get(1), captures.get(2)) {
+                    (Some(name), _) => (name, resolve(name.as_str(), path, dirs)?),
+                    (None, Some(name)) => {
+                        let header = dirs
+                            .iter()
+                            .map(|dir| dir.join(name.as_str()))
+                            .find(|header| header.is_file())?;
+                        (name, header)
+                    }
+                    (None, None) => return None,
+                };
+                let target = Url::from_file_path(&header).ok()?;
+                Some(json!({
+                    "range": {
+                        "start": position_at(text, name.start()),
+                        "end": position_at(text, name.end()),
+                    },
+                    "target": target,
+                    "tooltip": header.display().to_string(),
+                }))
+            })
+            .collect()
+    }
+
+    /// 工作区的编译数据库，文件修改后重新读取，找不到时返回空的数据库。
+    fn database(&self, roots: &[PathBuf]) -> Arc<CompileDatabase> {
+        let found = roots
+            .iter()
+            .flat_map(|root| DATABASE_PATHS.iter().map(move |path| root.join(path)))
+            .find_map(|path| {
+                let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                Some((path, modified))
+            });
+        let Some((path, modified)) = found else {
+            return Arc::default();
+        };
+
+        let mut cached = self.database.lock().unwrap();
+        if let Some((cached_path, cached_modified, database)) = cached.as_ref()
+            && *cached_path == path
+            && *cached_modified == modified
+        {
+            return Arc::clone(database);
+        }
+        let database = match CompileDatabase::load(&path) {
+            Ok(database) => {
+                debug!("已读取编译数据库 {}", path.display());
+                Arc::new(database)
+            }
+            Err(e) => {
+                warn!("{:?}", e);
+                Arc::default()
+            }
+        };
+        *cached = Some((path, modified, Arc::clone(&database)));
+        database
+    }
+}
+
+/// 从 `header` 出发沿着引号形式的包含查找回到 `origin` 的路径。
+///
+/// # 返回
+///
+/// 返回从 `header` 到最后一个包含 `origin` 的文件的路径，没有循环时返回 `None`
+fn find_cycle(origin: &Path, header: &Path, database: &CompileDatabase) -> Option<Vec<PathBuf>> {
+    if header == origin {
+        return Some(Vec::new());
+    }
+    let mut parents: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut visited = HashSet::from([header.to_path_buf()]);
+    let mut queue = VecDeque::from([header.to_path_buf()]);
+    while let Some(file) = queue.pop_front() {
+        if visited.len() > MAX_VISITED {
+            break;
+        }
+        let Ok(text) = std::fs::read_to_string(&file) else {
+            continue;
+        };
+        let dirs = database.include_dirs(&file);
+        for (name, _) in quoted_includes(&text) {
+            let Some(next) = resolve(name, &file, dirs) else {
+                continue;
+            };
+            if next == origin {
+                let mut chain = vec![file.clone()];
+                while let Some(parent) = parents.get(chain.last()?) {
+                    chain.push(parent.clone());
+                }
+                chain.reverse();
+                return Some(chain);
+            }
+            if visited.insert(next.clone()) {
+                parents.insert(next.clone(), file.clone());
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+/// 用文件名描述包含循环，例如 `a.h -> b.h -> a.h`。
+fn describe_cycle(origin: &Path, chain: &[PathBuf]) -> String {
+    let name = |path: &Path| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string())
+    };
+    std::iter::once(origin)
+        .chain(chain.iter().map(PathBuf::as_path))
+        .chain(std::iter::once(origin))
+        .map(name)
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+/// 注册 `#include` 文档链接的处理器，后端不提供或者配置为代答文档链接时由代理应答。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        DocumentLinkRequest::METHOD,
+        LOCAL_PROVIDER_PRIORITY,
+        answer_links,
+    );
+}
+
+/// 以 `#include` 指向的文件应答文档链接请求。
+fn answer_links(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        match ctx.dispatcher().local_document_links(&rpc) {
+            Some(result) => Ok(Verdict::Respond(handlers::response(&rpc, Ok(result)))),
+            None => Ok(Verdict::Continue(rpc)),
+        }
+    })
+}
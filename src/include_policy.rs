@@ -0,0 +1,280 @@
+//! # 头文件插入策略模块
+//!
+//! clangd 的补全项和代码操作会附带插入 `#include` 的编辑。代理在编辑器应用它们之前按项目策略改写：
+//! 统一尖括号或引号风格、按 IWYU 映射把私有头文件换成公开头文件、删除被禁止的头文件。
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::warn;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::config::{IncludeConfig, IncludeStyle};
+
+/// 改写一条 `#include` 的结果。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IncludeRewrite {
+    /// 不是 `#include` 编辑，或者策略不需要修改
+    Keep,
+    /// 替换为新的编辑文本
+    Replace(String),
+    /// 头文件被禁止，删除这条编辑
+    Drop,
+}
+
+/// 头文件插入策略。
+#[derive(Default)]
+pub struct IncludePolicy {
+    style: Option<IncludeStyle>,
+    blocked: Option<GlobSet>,
+    /// 不带分隔符的私有头文件（如 `bits/stl_vector.h`）→ 带分隔符的公开头文件
+    mappings: HashMap<String, String>,
+}
+
+impl IncludePolicy {
+    /// 根据配置创建策略，无效的模式和无法读取的映射文件会被忽略并记录警告。
+    pub fn new(config: &IncludeConfig) -> Self {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &config.blocked {
+            match Glob::new(pattern) {
+                Ok(glob) => {
+                    builder.add(glob);
+                }
+                Err(e) => warn!("忽略无效的头文件模式 {}: {}", pattern, e),
+            }
+        }
+        let blocked = match builder.build() {
+            Ok(set) if !set.is_empty() => Some(set),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("无法编译禁止的头文件列表: {}", e);
+                None
+            }
+        };
+
+        let mappings = match &config.mapping_file {
+            Some(path) => load_iwyu_mappings(path),
+            None => HashMap::new(),
+        };
+
+        Self {
+            style: config.style,
+            blocked,
+            mappings,
+        }
+    }
+
+    /// 是否没有配置任何策略。
+    pub fn is_empty(&self) -> bool {
+        self.style.is_none() && self.blocked.is_none() && self.mappings.is_empty()
+    }
+
+    /// 按策略改写一个带分隔符的头文件。
+    ///
+    /// # 返回
+    ///
+    /// 头文件被禁止时返回 `None`
+    pub fn rewrite_header(&self, header: &str) -> Option<String> {
+        let header = self
+            .mappings
+            .get(strip_delimiters(header))
+            .cloned()
+            .unwrap_or_else(|| header.to_string());
+        let Some(name) = header.get(1..header.len().saturating_sub(1)) else {
+            return Some(header);
+        };
+        if self.blocked.as_ref().is_some_and(|set| set.is_match(name)) {
+            return None;
+        }
+        Some(match self.style {
+            Some(IncludeStyle::Angle) => format!("<{}>", name),
+            Some(IncludeStyle::Quote) => format!("\"{}\"", name),
+            None => header,
+        })
+    }
+
+    /// 按策略改写一条文本编辑的内容。
+    ///
+    /// # 参数
+    ///
+    /// * `new_text` - 编辑插入的文本，例如 `#include "foo.h"\n`
+    pub fn rewrite_text(&self, new_text: &str) -> IncludeRewrite {
+        let Some((prefix, header, suffix)) = split_include(new_text) else {
+            return IncludeRewrite::Keep;
+        };
+        match self.rewrite_header(header) {
+            None => IncludeRewrite::Drop,
+            Some(rewritten) if rewritten == header => IncludeRewrite::Keep,
+            Some(rewritten) => {
+                IncludeRewrite::Replace(format!("{}{}{}", prefix, rewritten, suffix))
+            }
+        }
+    }
+
+    /// 改写一组 `TextEdit`，删除被禁止的编辑。
+    ///
+    /// # 返回
+    ///
+    /// 有编辑被删除时返回 `true`
+    fn rewrite_edits(&self, edits: &mut Vec<Value>) -> bool {
+        let before = edits.len();
+        edits.retain_mut(|edit| {
+            let Some(new_text) = edit.get("newText").and_then(|t| t.as_str()) else {
+                return true;
+            };
+            match self.rewrite_text(new_text) {
+                IncludeRewrite::Keep => true,
+                IncludeRewrite::Replace(text) => {
+                    edit["newText"] = Value::String(text);
+                    true
+                }
+                IncludeRewrite::Drop => false,
+            }
+        });
+        edits.len() != before
+    }
+
+    /// 改写补全项的 `additionalTextEdits`。被禁止的头文件只删除插入编辑，补全项本身保留。
+    ///
+    /// # 参数
+    ///
+    /// * `item` - `CompletionItem`
+    pub fn rewrite_completion_item(&self, item: &mut Value) {
+        if let Some(edits) = item
+            .get_mut("additionalTextEdits")
+            .and_then(|e| e.as_array_mut())
+        {
+            self.rewrite_edits(edits);
+        }
+    }
+
+    /// 改写 `textDocument/completion` 的响应。
+    ///
+    /// # 参数
+    ///
+    /// * `rpc` - 后端的响应，结果可以是数组或 `CompletionList`
+    pub fn rewrite_completion_response(&self, rpc: &mut Value) {
+        if self.is_empty() {
+            return;
+        }
+        let Some(result) = rpc.get_mut("result") else {
+            return;
+        };
+        let items = match result {
+            Value::Array(items) => items,
+            Value::Object(list) => match list.get_mut("items").and_then(|i| i.as_array_mut()) {
+                Some(items) => items,
+                None => return,
+            },
+            _ => return,
+        };
+        for item in items {
+            self.rewrite_completion_item(item);
+        }
+    }
+
+    /// 改写 `textDocument/codeAction` 的响应。插入被禁止头文件的代码操作会被整个删除。
+    ///
+    /// # 参数
+    ///
+    /// * `rpc` - 后端的响应
+    pub fn rewrite_code_action_response(&self, rpc: &mut Value) {
+        if self.is_empty() {
+            return;
+        }
+        let Some(actions) = rpc.get_mut("result").and_then(|r| r.as_array_mut()) else {
+            return;
+        };
+        actions.retain_mut(|action| {
+            let Some(edit) = action.get_mut("edit") else {
+                return true;
+            };
+            let mut dropped = false;
+            if let Some(changes) = edit.get_mut("changes").and_then(|c| c.as_object_mut()) {
+                for edits in changes.values_mut().filter_map(|e| e.as_array_mut()) {
+                    dropped |= self.rewrite_edits(edits);
+                }
+            }
+            if let Some(document_changes) = edit
+                .get_mut("documentChanges")
+                .and_then(|c| c.as_array_mut())
+            {
+                for edits in document_changes
+                    .iter_mut()
+                    .filter_map(|c| c.get_mut("edits").and_then(|e| e.as_array_mut()))
+                {
+                    dropped |= self.rewrite_edits(edits);
+                }
+            }
+            !dropped
+        });
+    }
+}
+
+/// 把 `#include "foo.h"\n` 拆成前缀、带分隔符的头文件和后缀。
+fn split_include(text: &str) -> Option<(&str, &str, &str)> {
+    let start = text.find("#include")?;
+    let after = start + "#include".len();
+    let header_start = after + text[after..].find(['"', '<'])?;
+    let close = if text[header_start..].starts_with('<') {
+        '>'
+    } else {
+        '"'
+    };
+    let header_end = header_start + 1 + text[header_start + 1..].find(close)? + 1;
+    if !text[after..header_start].trim().is_empty() {
+        return None;
+    }
+    Some((
+        &text[..header_start],
+        &text[header_start..header_end],
+        &text[header_end..],
+    ))
+}
+
+/// 去掉头文件两端的 `<>` 或 `""`。
+fn strip_delimiters(header: &str) -> &str {
+    header
+        .strip_prefix(['<', '"'])
+        .and_then(|h| h.strip_suffix(['>', '"']))
+        .unwrap_or(header)
+}
+
+/// 加载 IWYU 映射文件中的 `include` 映射。
+///
+/// 映射文件的每一项形如 `{ include: ["<bits/foo.h>", "private", "<foo>", "public"] }`，
+/// 其中的数组是合法的 JSON；`symbol` 和 `ref` 项被忽略。
+pub fn load_iwyu_mappings(path: &Path) -> HashMap<String, String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => parse_iwyu_mappings(&text),
+        Err(e) => {
+            warn!("无法读取 IWYU 映射文件 {}: {}", path.display(), e);
+            HashMap::new()
+        }
+    }
+}
+
+/// 解析 IWYU 映射文件的内容。
+pub fn parse_iwyu_mappings(text: &str) -> HashMap<String, String> {
+    let mut mappings = HashMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let Some(start) = line.find("include:") else {
+            continue;
+        };
+        let rest = &line[start + "include:".len()..];
+        let (Some(open), Some(close)) = (rest.find('['), rest.rfind(']')) else {
+            continue;
+        };
+        match serde_json::from_str::<Vec<String>>(&rest[open..=close]) {
+            Ok(entry) if entry.len() == 4 => {
+                mappings.insert(strip_delimiters(&entry[0]).to_string(), entry[2].clone());
+            }
+            _ => warn!("忽略无法解析的 IWYU 映射: {}", line),
+        }
+    }
+    mappings
+}
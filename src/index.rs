@@ -0,0 +1,736 @@
+//! # 索引导出模块
+//!
+//! `lsp-proxy index` 作为批处理客户端驱动后端：逐个打开工作区中的文件，用 `documentSymbol`、`definition`
+//! 和 `references` 收集符号的定义和引用，导出供代码搜索系统使用的 SCIP 或 LSIF 文件。
+//! 符号名同时作为 LSIF 的 moniker，格式与 SCIP 的符号相同。
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::{Range, SymbolKind, Url};
+
+use crate::client::LspClient;
+use crate::config::Config;
+use crate::warmup::language_id;
+use crate::workspace;
+
+/// 符号名和 moniker 使用的 scheme。
+pub const SCHEME: &str = "codefuse";
+
+/// 最多索引的文件数。
+const MAX_INDEX_FILES: usize = 100_000;
+
+/// 导出格式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexFormat {
+    Scip,
+    Lsif,
+}
+
+impl IndexFormat {
+    /// 没有指定输出文件时使用的文件名。
+    pub fn default_output(self) -> &'static str {
+        match self {
+            Self::Scip => "index.scip",
+            Self::Lsif => "dump.lsif",
+        }
+    }
+}
+
+/// `index` 子命令的参数。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexCommand {
+    pub format: IndexFormat,
+    /// 输出文件，默认在工作区根目录下
+    pub output: Option<PathBuf>,
+    /// 工作区根目录，默认是当前目录
+    pub root: Option<PathBuf>,
+    /// 使用模拟后端而不是 clangd
+    pub mock: bool,
+}
+
+/// 文档中的一次出现。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Occurrence {
+    pub range: Range,
+    pub symbol: String,
+    /// 是否是符号的定义
+    pub definition: bool,
+}
+
+/// 定义在文档中的符号。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolEntry {
+    pub symbol: String,
+    pub name: String,
+    pub kind: SymbolKind,
+}
+
+/// 一个文档的索引。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexedDocument {
+    pub language_id: String,
+    pub occurrences: Vec<Occurrence>,
+    pub symbols: Vec<SymbolEntry>,
+}
+
+/// 整个工作区的索引，文档按相对于根目录的路径排列。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexDump {
+    pub root: PathBuf,
+    pub documents: BTreeMap<String, IndexedDocument>,
+}
+
+impl IndexDump {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            documents: BTreeMap::new(),
+        }
+    }
+
+    /// 记录一次出现，工作区之外的文件和重复的出现被忽略。
+    fn add_occurrence(&mut self, uri: &Url, range: Range, symbol: &str, definition: bool) {
+        let Some(path) = self.relative_path(uri) else {
+            return;
+        };
+        let document = self.document(&path);
+        let occurrence = Occurrence {
+            range,
+            symbol: symbol.to_string(),
+            definition,
+        };
+        match document
+            .occurrences
+            .iter_mut()
+            .find(|o| o.range == range && o.symbol == symbol)
+        {
+            Some(existing) => existing.definition |= definition,
+            None => document.occurrences.push(occurrence),
+        }
+    }
+
+    fn document(&mut self, path: &str) -> &mut IndexedDocument {
+        self.documents
+            .entry(path.to_string())
+            .or_insert_with(|| IndexedDocument {
+                language_id: language_id(Path::new(path)).to_string(),
+                ..Default::default()
+            })
+    }
+
+    /// 工作区中的文件相对于根目录的路径，使用 `/` 分隔。
+    fn relative_path(&self, uri: &Url) -> Option<String> {
+        let path = uri.to_file_path().ok()?;
+        let relative = path.strip_prefix(&self.root).ok()?;
+        Some(
+            relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+        )
+    }
+}
+
+/// 符号名中的描述符：命名空间以 `/` 结尾，类型以 `#` 结尾，函数以 `().` 结尾，其他以 `.` 结尾。
+pub fn descriptor(name: &str, kind: SymbolKind) -> String {
+    let name = escape_name(name);
+    match kind {
+        SymbolKind::NAMESPACE | SymbolKind::MODULE | SymbolKind::PACKAGE => format!("{}/", name),
+        SymbolKind::CLASS
+        | SymbolKind::STRUCT
+        | SymbolKind::INTERFACE
+        | SymbolKind::ENUM
+        | SymbolKind::TYPE_PARAMETER => format!("{}#", name),
+        SymbolKind::FUNCTION
+        | SymbolKind::METHOD
+        | SymbolKind::CONSTRUCTOR
+        | SymbolKind::OPERATOR => format!("{}().", name),
+        _ => format!("{}.", name),
+    }
+}
+
+/// 只含字母、数字和 `_+-$` 的名字原样使用，其他名字用反引号括起来。
+fn escape_name(name: &str) -> String {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '+' | '-' | '$'))
+    {
+        name.to_string()
+    } else {
+        format!("`{}`", name.replace('`', "``"))
+    }
+}
+
+/// 由外层的描述符组成的全局符号名，例如 `codefuse . . . ns/Widget#draw().`。
+pub fn symbol_name(descriptors: &[String]) -> String {
+    format!("{} . . . {}", SCHEME, descriptors.concat())
+}
+
+/// `documentSymbol` 结果中的一个符号：名字、种类、名字的范围和外层的描述符。
+struct FoundSymbol {
+    name: String,
+    kind: SymbolKind,
+    range: Range,
+    descriptors: Vec<String>,
+}
+
+/// 展开层级或扁平的 `documentSymbol` 结果。
+fn document_symbols(result: &Value) -> Vec<FoundSymbol> {
+    fn visit(symbol: &Value, parents: &[String], found: &mut Vec<FoundSymbol>) {
+        let Some(name) = symbol.get("name").and_then(|n| n.as_str()) else {
+            return;
+        };
+        let Ok(kind) = serde_json::from_value::<SymbolKind>(symbol["kind"].clone()) else {
+            return;
+        };
+        let range = symbol
+            .get("selectionRange")
+            .or_else(|| symbol.pointer("/location/range"));
+        let Some(range) = range.and_then(|r| serde_json::from_value::<Range>(r.clone()).ok())
+        else {
+            return;
+        };
+        let mut descriptors = parents.to_vec();
+        if let Some(container) = symbol.get("containerName").and_then(|c| c.as_str())
+            && !container.is_empty()
+            && parents.is_empty()
+        {
+            descriptors.extend(
+                container
+                    .split("::")
+                    .map(|part| descriptor(part, SymbolKind::NAMESPACE)),
+            );
+        }
+        descriptors.push(descriptor(name, kind));
+        for child in symbol
+            .get("children")
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+        {
+            visit(child, &descriptors, found);
+        }
+        found.push(FoundSymbol {
+            name: name.to_string(),
+            kind,
+            range,
+            descriptors,
+        });
+    }
+
+    let mut found = Vec::new();
+    for symbol in result.as_array().into_iter().flatten() {
+        visit(symbol, &[], &mut found);
+    }
+    found.sort_by_key(|symbol| symbol.range.start);
+    found
+}
+
+/// `definition`/`references` 结果中的位置：`Location`、`Location[]` 或 `LocationLink[]`。
+fn locations(result: &Value) -> Vec<(Url, Range)> {
+    let items = match result {
+        Value::Array(items) => items.clone(),
+        Value::Null => Vec::new(),
+        item => vec![item.clone()],
+    };
+    items
+        .iter()
+        .filter_map(|item| {
+            let uri = item.get("uri").or_else(|| item.get("targetUri"))?;
+            let range = item
+                .get("range")
+                .or_else(|| item.get("targetSelectionRange"))?;
+            Some((
+                serde_json::from_value(uri.clone()).ok()?,
+                serde_json::from_value(range.clone()).ok()?,
+            ))
+        })
+        .collect()
+}
+
+/// 逐个打开文件，向后端查询符号、定义和引用，收集工作区的索引。
+///
+/// 同一个符号的声明和定义通过 `definition` 的结果合并：以定义的位置识别符号，已经收集过的符号不再查询引用。
+///
+/// # 参数
+///
+/// * `client` - 已经初始化的后端
+/// * `root` - 工作区根目录，之外的文件不出现在索引中
+/// * `files` - 要打开的文件
+///
+/// # 错误
+///
+/// 如果文件无法读取或者后端连接断开，返回错误；单个请求失败时记录警告后跳过
+pub async fn collect(client: &LspClient, root: &Path, files: &[PathBuf]) -> Result<IndexDump> {
+    let mut dump = IndexDump::new(root);
+    // 定义的位置 → 符号名
+    let mut known: HashMap<(Url, u32, u32), String> = HashMap::new();
+    let mut symbols_seen: HashSet<String> = HashSet::new();
+
+    for (i, path) in files.iter().enumerate() {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("无法读取 {}", path.display()))?;
+        let uri = Url::from_file_path(path)
+            .map_err(|_| anyhow::anyhow!("无效的路径 {}", path.display()))?;
+        let language = language_id(path);
+        client.notify(
+            "textDocument/didOpen",
+            json!({"textDocument": {"uri": uri, "languageId": language, "version": 1, "text": text}}),
+        )?;
+        if let Some(relative) = dump.relative_path(&uri) {
+            dump.document(&relative);
+        }
+
+        let text_document = json!({"uri": uri});
+        let found = match client
+            .request(
+                "textDocument/documentSymbol",
+                json!({"textDocument": text_document}),
+            )
+            .await
+        {
+            Ok(response) => document_symbols(&response["result"]),
+            Err(e) => {
+                warn!("{:?}", e);
+                Vec::new()
+            }
+        };
+
+        for symbol in found {
+            let position = json!({"textDocument": text_document, "position": symbol.range.start});
+            let definition = client
+                .request("textDocument/definition", position.clone())
+                .await
+                .map(|response| locations(&response["result"]))
+                .unwrap_or_default();
+            let (definition_uri, definition_range) = definition
+                .into_iter()
+                .next()
+                .unwrap_or((uri.clone(), symbol.range));
+            let start = definition_range.start;
+            let key = (definition_uri.clone(), start.line, start.character);
+            if let Some(name) = known.get(&key) {
+                // 声明：定义所在的文件已经收集过引用
+                let name = name.clone();
+                dump.add_occurrence(&uri, symbol.range, &name, false);
+                continue;
+            }
+
+            let mut name = symbol_name(&symbol.descriptors);
+            // 同名的重载或者不同文件中的静态函数，加上序号区分
+            if !symbols_seen.insert(name.clone()) {
+                let mut n = 1;
+                while !symbols_seen.insert(format!("{}({})", name, n)) {
+                    n += 1;
+                }
+                name = format!("{}({})", name, n);
+            }
+            known.insert(key, name.clone());
+
+            if let Some(relative) = dump.relative_path(&definition_uri) {
+                dump.document(&relative).symbols.push(SymbolEntry {
+                    symbol: name.clone(),
+                    name: symbol.name.clone(),
+                    kind: symbol.kind,
+                });
+            }
+            dump.add_occurrence(&definition_uri, definition_range, &name, true);
+
+            let mut params = position;
+            params["context"] = json!({"includeDeclaration": true});
+            match client.request("textDocument/references", params).await {
+                Ok(response) => {
+                    for (reference_uri, range) in locations(&response["result"]) {
+                        let definition =
+                            reference_uri == definition_uri && range == definition_range;
+                        dump.add_occurrence(&reference_uri, range, &name, definition);
+                    }
+                }
+                Err(e) => warn!("{:?}", e),
+            }
+        }
+
+        client.notify(
+            "textDocument/didClose",
+            json!({"textDocument": text_document}),
+        )?;
+        if (i + 1) % 100 == 0 {
+            info!("已索引 {}/{} 个文件", i + 1, files.len());
+        }
+    }
+
+    for document in dump.documents.values_mut() {
+        document
+            .occurrences
+            .sort_by_key(|o| (o.range.start, o.range.end));
+    }
+    Ok(dump)
+}
+
+/// LSIF 转储：每行一个顶点或边。
+pub fn to_lsif(dump: &IndexDump) -> String {
+    let mut lines: Vec<Value> = Vec::new();
+    let mut next_id = 0u64;
+    let mut emit = |lines: &mut Vec<Value>, mut element: Value| -> u64 {
+        next_id += 1;
+        element["id"] = json!(next_id);
+        lines.push(element);
+        next_id
+    };
+
+    let root = Url::from_directory_path(&dump.root)
+        .map(|uri| uri.to_string())
+        .unwrap_or_default();
+    emit(
+        &mut lines,
+        json!({"type": "vertex", "label": "metaData", "version": "0.5.0", "projectRoot": root,
+               "positionEncoding": "utf-16",
+               "toolInfo": {"name": "lsp-proxy", "version": env!("CARGO_PKG_VERSION")}}),
+    );
+    let project = emit(
+        &mut lines,
+        json!({"type": "vertex", "label": "project", "kind": "cpp"}),
+    );
+    emit(
+        &mut lines,
+        json!({"type": "vertex", "label": "$event", "kind": "begin", "scope": "project",
+               "data": project}),
+    );
+
+    // 符号 → 定义和引用所在的 (文档, 范围)
+    let mut definitions: BTreeMap<&str, Vec<(u64, u64)>> = BTreeMap::new();
+    let mut references: BTreeMap<&str, Vec<(u64, u64)>> = BTreeMap::new();
+    let mut ranges: Vec<(u64, &str)> = Vec::new();
+    let mut documents = Vec::new();
+    for (path, document) in &dump.documents {
+        let uri = Url::from_file_path(dump.root.join(path))
+            .map(|uri| uri.to_string())
+            .unwrap_or_else(|_| path.clone());
+        let id = emit(
+            &mut lines,
+            json!({"type": "vertex", "label": "document", "uri": uri,
+                   "languageId": document.language_id}),
+        );
+        documents.push(id);
+        emit(
+            &mut lines,
+            json!({"type": "vertex", "label": "$event", "kind": "begin", "scope": "document",
+                   "data": id}),
+        );
+        let mut contained = Vec::new();
+        for occurrence in &document.occurrences {
+            let range = emit(
+                &mut lines,
+                json!({"type": "vertex", "label": "range", "start": occurrence.range.start,
+                       "end": occurrence.range.end}),
+            );
+            contained.push(range);
+            ranges.push((range, &occurrence.symbol));
+            let target = if occurrence.definition {
+                &mut definitions
+            } else {
+                &mut references
+            };
+            target
+                .entry(&occurrence.symbol)
+                .or_default()
+                .push((id, range));
+        }
+        if !contained.is_empty() {
+            emit(
+                &mut lines,
+                json!({"type": "edge", "label": "contains", "outV": id, "ranges": contained}),
+            );
+        }
+    }
+
+    let symbols: HashSet<&str> = ranges.iter().map(|(_, symbol)| *symbol).collect();
+    let mut symbols: Vec<&str> = symbols.into_iter().collect();
+    symbols.sort();
+    let mut result_sets = HashMap::new();
+    for symbol in symbols {
+        let result_set = emit(&mut lines, json!({"type": "vertex", "label": "resultSet"}));
+        result_sets.insert(symbol, result_set);
+        let moniker = emit(
+            &mut lines,
+            json!({"type": "vertex", "label": "moniker", "kind": "export", "scheme": SCHEME,
+                   "identifier": symbol, "unique": "workspace"}),
+        );
+        emit(
+            &mut lines,
+            json!({"type": "edge", "label": "moniker", "outV": result_set, "inV": moniker}),
+        );
+
+        let defined = definitions.get(symbol).cloned().unwrap_or_default();
+        if !defined.is_empty() {
+            let result = emit(
+                &mut lines,
+                json!({"type": "vertex", "label": "definitionResult"}),
+            );
+            emit(
+                &mut lines,
+                json!({"type": "edge", "label": "textDocument/definition", "outV": result_set,
+                       "inV": result}),
+            );
+            for (document, ranges) in group_by_document(&defined) {
+                emit(
+                    &mut lines,
+                    json!({"type": "edge", "label": "item", "outV": result, "ranges": ranges,
+                           "document": document}),
+                );
+            }
+        }
+        let result = emit(
+            &mut lines,
+            json!({"type": "vertex", "label": "referenceResult"}),
+        );
+        emit(
+            &mut lines,
+            json!({"type": "edge", "label": "textDocument/references", "outV": result_set,
+                   "inV": result}),
+        );
+        for (property, items) in [
+            ("definitions", defined),
+            (
+                "references",
+                references.get(symbol).cloned().unwrap_or_default(),
+            ),
+        ] {
+            for (document, ranges) in group_by_document(&items) {
+                emit(
+                    &mut lines,
+                    json!({"type": "edge", "label": "item", "outV": result, "ranges": ranges,
+                           "document": document, "property": property}),
+                );
+            }
+        }
+    }
+    for (range, symbol) in &ranges {
+        emit(
+            &mut lines,
+            json!({"type": "edge", "label": "next", "outV": range, "inV": result_sets[symbol]}),
+        );
+    }
+
+    for document in &documents {
+        emit(
+            &mut lines,
+            json!({"type": "vertex", "label": "$event", "kind": "end", "scope": "document",
+                   "data": document}),
+        );
+    }
+    if !documents.is_empty() {
+        emit(
+            &mut lines,
+            json!({"type": "edge", "label": "contains", "outV": project, "ranges": documents}),
+        );
+    }
+    emit(
+        &mut lines,
+        json!({"type": "vertex", "label": "$event", "kind": "end", "scope": "project",
+               "data": project}),
+    );
+
+    lines.iter().map(|line| line.to_string() + "\n").collect()
+}
+
+/// 按文档分组的范围，保持文档第一次出现的顺序。
+fn group_by_document(items: &[(u64, u64)]) -> Vec<(u64, Vec<u64>)> {
+    let mut grouped: Vec<(u64, Vec<u64>)> = Vec::new();
+    for (document, range) in items {
+        match grouped.iter_mut().find(|(d, _)| d == document) {
+            Some((_, ranges)) => ranges.push(*range),
+            None => grouped.push((*document, vec![*range])),
+        }
+    }
+    grouped
+}
+
+/// 手写的 protobuf 编码，只包含 SCIP 用到的字段类型。
+#[derive(Default)]
+struct Proto(Vec<u8>);
+
+impl Proto {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.key(field, 2);
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, field: u32, value: &str) {
+        if !value.is_empty() {
+            self.bytes(field, value.as_bytes());
+        }
+    }
+
+    fn message(&mut self, field: u32, message: Proto) {
+        self.bytes(field, &message.0);
+    }
+
+    fn int32(&mut self, field: u32, value: i32) {
+        if value != 0 {
+            self.key(field, 0);
+            // 负数按 protobuf 的约定扩展为 64 位
+            self.varint(value as i64 as u64);
+        }
+    }
+
+    fn packed_int32(&mut self, field: u32, values: &[i32]) {
+        let mut packed = Proto::default();
+        for value in values {
+            packed.varint(*value as i64 as u64);
+        }
+        self.bytes(field, &packed.0);
+    }
+}
+
+/// SCIP 的 `Language` 名称。
+fn scip_language(language_id: &str) -> &'static str {
+    match language_id {
+        "c" => "C",
+        "objective-c" => "ObjectiveC",
+        "objective-cpp" => "ObjectiveCPP",
+        "cuda-cpp" => "CUDA",
+        _ => "CPP",
+    }
+}
+
+/// SCIP 的范围：同一行时是 `[行, 起始列, 结束列]`，否则是四个数。
+fn scip_range(range: &Range) -> Vec<i32> {
+    let (start, end) = (range.start, range.end);
+    if start.line == end.line {
+        vec![
+            start.line as i32,
+            start.character as i32,
+            end.character as i32,
+        ]
+    } else {
+        vec![
+            start.line as i32,
+            start.character as i32,
+            end.line as i32,
+            end.character as i32,
+        ]
+    }
+}
+
+/// SCIP 索引（`scip.proto` 的 `Index` 消息）。
+pub fn to_scip(dump: &IndexDump) -> Vec<u8> {
+    let mut index = Proto::default();
+
+    let mut tool = Proto::default();
+    tool.string(1, "lsp-proxy");
+    tool.string(2, env!("CARGO_PKG_VERSION"));
+    let mut metadata = Proto::default();
+    metadata.message(2, tool);
+    let root = Url::from_directory_path(&dump.root)
+        .map(|uri| uri.to_string())
+        .unwrap_or_default();
+    metadata.string(3, &root);
+    // TextEncoding.UTF8；位置按 UTF-16 计算，见 Document.position_encoding
+    metadata.int32(4, 1);
+    index.message(1, metadata);
+
+    for (path, document) in &dump.documents {
+        let mut doc = Proto::default();
+        doc.string(1, path);
+        for occurrence in &document.occurrences {
+            let mut occ = Proto::default();
+            occ.packed_int32(1, &scip_range(&occurrence.range));
+            occ.string(2, &occurrence.symbol);
+            // SymbolRole.Definition
+            occ.int32(3, i32::from(occurrence.definition));
+            doc.message(2, occ);
+        }
+        for symbol in &document.symbols {
+            let mut info = Proto::default();
+            info.string(1, &symbol.symbol);
+            info.string(6, &symbol.name);
+            doc.message(3, info);
+        }
+        doc.string(4, scip_language(&document.language_id));
+        // PositionEncoding.UTF16CodeUnitOffsetFromLineStart
+        doc.int32(6, 2);
+        index.message(2, doc);
+    }
+    index.0
+}
+
+/// 执行 `lsp-proxy index`：启动后端，索引工作区并写出结果。
+///
+/// # 错误
+///
+/// 如果后端无法启动、初始化，或者结果无法写入，返回错误
+pub async fn run(command: &IndexCommand, config: &Config) -> Result<()> {
+    let root = match &command.root {
+        Some(root) => root.clone(),
+        None => std::env::current_dir()?,
+    };
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("无法访问工作区 {}", root.display()))?;
+    let (files, truncated) = workspace::source_files(std::slice::from_ref(&root), MAX_INDEX_FILES);
+    if truncated {
+        warn!("工作区的文件超过 {} 个，只索引前面的部分", MAX_INDEX_FILES);
+    }
+    let mut files = files;
+    files.sort();
+
+    let (program, args) = if command.mock {
+        let exe = std::env::current_exe()?.to_string_lossy().into_owned();
+        (exe, vec!["mock-server".to_string()])
+    } else {
+        (config.backend.command.clone(), config.backend.args.clone())
+    };
+    info!("索引 {} 个文件: {}", files.len(), root.display());
+    let client = LspClient::spawn(&program, &args)?;
+    let root_uri = Url::from_directory_path(&root)
+        .map_err(|_| anyhow::anyhow!("无效的工作区 {}", root.display()))?;
+    client
+        .request(
+            "initialize",
+            json!({"processId": std::process::id(), "rootUri": root_uri, "capabilities": {
+                "textDocument": {"documentSymbol": {"hierarchicalDocumentSymbolSupport": true}},
+            }}),
+        )
+        .await?;
+    client.notify("initialized", json!({}))?;
+    let dump = collect(&client, &root, &files).await;
+    client.shutdown().await;
+    let dump = dump?;
+
+    let output = command
+        .output
+        .clone()
+        .unwrap_or_else(|| root.join(command.format.default_output()));
+    let bytes = match command.format {
+        IndexFormat::Scip => to_scip(&dump),
+        IndexFormat::Lsif => to_lsif(&dump).into_bytes(),
+    };
+    std::fs::write(&output, bytes).with_context(|| format!("无法写入 {}", output.display()))?;
+    let occurrences: usize = dump.documents.values().map(|d| d.occurrences.len()).sum();
+    println!(
+        "已写入 {}：{} 个文档，{} 处出现",
+        output.display(),
+        dump.documents.len(),
+        occurrences
+    );
+    Ok(())
+}
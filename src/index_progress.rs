@@ -0,0 +1,275 @@
+//! # 后台索引进度模块
+//!
+//! clangd 用 `$/progress`（token 为 `backgroundIndexProgress`）报告后台索引的进度，消息形如 `3/120`。
+//! 配置了分片时每个分片的 clangd 各自报告，编辑器看到的是同一个 token 上交错的几组进度。
+//! 开启 `[index_progress]` 后代理接管这些进度：代替前端应答后端创建进度的请求，记录每个分片已经索引和
+//! 需要索引的文件数，合并成一个带百分比和文件数的进度交给前端，所有分片都完成时结束。
+
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tower_lsp::lsp_types::notification::{Notification, Progress};
+use tower_lsp::lsp_types::request::{Request, WorkDoneProgressCreate};
+
+use crate::config::IndexProgressConfig;
+use crate::symbol_index::BACKGROUND_INDEX_TOKEN;
+
+/// 合并后的进度在编辑器中显示的标题。
+pub const TITLE: &str = "后台索引";
+
+/// 所有分片的索引进度之和。
+///
+/// - `indexed`/`total`: 已经索引和需要索引的文件数
+/// - `percentage`: 完成的百分比
+/// - `shards`: 正在索引的分片数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    pub indexed: u64,
+    pub total: u64,
+    pub percentage: u32,
+    pub shards: usize,
+}
+
+impl Summary {
+    /// 进度中显示的文字。
+    pub fn message(&self) -> String {
+        let mut message = format!("{}/{} 个文件", self.indexed, self.total);
+        if self.shards > 1 {
+            message.push_str(&format!("（{} 个分片）", self.shards));
+        }
+        message
+    }
+}
+
+/// 收到后端的进度之后需要对前端做的事。
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// 需要在前端创建进度
+    Create,
+    /// 发送给前端的 `$/progress` 通知
+    Send(Value),
+    /// 不需要通知前端
+    Nothing,
+}
+
+#[derive(Debug, Default)]
+struct ShardIndex {
+    indexed: u64,
+    total: u64,
+    finished: bool,
+}
+
+/// 前端的进度。
+#[derive(Debug, Default)]
+enum Frontend {
+    /// 没有进度
+    #[default]
+    Idle,
+    /// 正在等待前端应答创建进度的请求
+    Creating,
+    /// 进度已经创建
+    Active(String),
+    /// 前端不支持或者拒绝了进度，这一轮不再报告
+    Unsupported,
+}
+
+/// 代理向前端报告的一个进度：第一次更新时创建，结束后下一次更新重新创建。
+///
+/// 创建进度需要等待前端应答，等待期间的更新只保留最新的状态，创建完成后由 [`ProxyProgress::created`] 补发。
+#[derive(Debug, Default)]
+pub struct ProxyProgress {
+    frontend: Frontend,
+}
+
+impl ProxyProgress {
+    /// 进度有了新的状态。
+    ///
+    /// # 参数
+    ///
+    /// * `value` - 生成 `$/progress` 的 `value`（`kind` 为 `report`）
+    pub fn update(&mut self, value: impl FnOnce() -> Value) -> Step {
+        match &self.frontend {
+            Frontend::Idle => {
+                self.frontend = Frontend::Creating;
+                Step::Create
+            }
+            Frontend::Active(token) => Step::Send(progress(token, value())),
+            Frontend::Creating | Frontend::Unsupported => Step::Nothing,
+        }
+    }
+
+    /// 进度结束。还在创建时由 [`ProxyProgress::created`] 结束。
+    pub fn finish(&mut self) -> Step {
+        if matches!(self.frontend, Frontend::Creating) {
+            return Step::Nothing;
+        }
+        match std::mem::take(&mut self.frontend) {
+            Frontend::Active(token) => Step::Send(progress(&token, json!({"kind": "end"}))),
+            _ => Step::Nothing,
+        }
+    }
+
+    /// 前端的进度创建完成。
+    ///
+    /// # 参数
+    ///
+    /// * `token` - 创建的进度，前端不支持时为 `None`
+    /// * `current` - 当前的 `value`，等待期间进度已经结束时为 `None`
+    ///
+    /// # 返回
+    ///
+    /// 需要发给前端的 `$/progress` 通知：当前的进度，或者进度已经结束时结束的通知
+    pub fn created(&mut self, token: Option<String>, current: Option<Value>) -> Vec<Value> {
+        match (token, current) {
+            (None, current) => {
+                self.frontend = match current {
+                    Some(_) => Frontend::Unsupported,
+                    None => Frontend::Idle,
+                };
+                Vec::new()
+            }
+            (Some(token), None) => {
+                self.frontend = Frontend::Idle;
+                vec![progress(&token, json!({"kind": "end"}))]
+            }
+            (Some(token), Some(value)) => {
+                let sent = progress(&token, value);
+                self.frontend = Frontend::Active(token);
+                vec![sent]
+            }
+        }
+    }
+}
+
+/// `token` 上的 `$/progress` 通知。
+fn progress(token: &str, value: Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": Progress::METHOD,
+        "params": {"token": token, "value": value},
+    })
+}
+
+#[derive(Debug, Default)]
+struct State {
+    shards: BTreeMap<usize, ShardIndex>,
+    frontend: ProxyProgress,
+}
+
+/// 合并各分片的后台索引进度。关闭时不接管进度，消息原样转发给前端。
+#[derive(Default)]
+pub struct IndexProgress {
+    enabled: bool,
+    state: Mutex<State>,
+}
+
+impl IndexProgress {
+    /// 按 `[index_progress]` 配置创建。
+    pub fn new(config: &IndexProgressConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            state: Mutex::default(),
+        }
+    }
+
+    /// 是否接管后台索引进度。
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 消息是否是由代理接管的后台索引进度：创建进度的请求或者 `$/progress` 通知。
+    pub fn owns(&self, rpc: &Value) -> bool {
+        self.enabled
+            && matches!(
+                rpc.get("method").and_then(|m| m.as_str()),
+                Some(WorkDoneProgressCreate::METHOD | Progress::METHOD)
+            )
+            && rpc.pointer("/params/token").and_then(|t| t.as_str()) == Some(BACKGROUND_INDEX_TOKEN)
+    }
+
+    /// 记录分片的一条进度（`$/progress` 的 `value`）。
+    pub fn observe(&self, shard: usize, value: &Value) -> Step {
+        let mut state = self.state.lock().unwrap();
+        match value.get("kind").and_then(|k| k.as_str()) {
+            Some("begin") => {
+                state.shards.insert(shard, ShardIndex::default());
+            }
+            Some("report") => {
+                let Some(index) = state.shards.get_mut(&shard).filter(|index| !index.finished)
+                else {
+                    return Step::Nothing;
+                };
+                if let Some((indexed, total)) = value
+                    .get("message")
+                    .and_then(|m| m.as_str())
+                    .and_then(parse_counts)
+                {
+                    index.indexed = indexed;
+                    index.total = total;
+                }
+            }
+            Some("end") => match state.shards.get_mut(&shard) {
+                Some(index) => {
+                    index.finished = true;
+                    index.indexed = index.total;
+                }
+                None => return Step::Nothing,
+            },
+            _ => return Step::Nothing,
+        }
+
+        let State { shards, frontend } = &mut *state;
+        if shards.values().all(|index| index.finished) {
+            shards.clear();
+            return frontend.finish();
+        }
+        frontend.update(|| report(&summarize(shards)))
+    }
+
+    /// 前端的进度创建完成。
+    ///
+    /// # 参数
+    ///
+    /// * `token` - 创建的进度，前端不支持时为 `None`
+    ///
+    /// # 返回
+    ///
+    /// 需要发给前端的 `$/progress` 通知：当前的进度，等待期间索引已经结束时是结束的通知
+    pub fn created(&self, token: Option<String>) -> Vec<Value> {
+        let mut state = self.state.lock().unwrap();
+        let current = (!state.shards.is_empty()).then(|| report(&summarize(&state.shards)));
+        state.frontend.created(token, current)
+    }
+
+    /// 当前的进度，没有分片在索引时返回 `None`。
+    pub fn summary(&self) -> Option<Summary> {
+        let state = self.state.lock().unwrap();
+        (!state.shards.is_empty()).then(|| summarize(&state.shards))
+    }
+}
+
+/// 解析 clangd 的进度消息 `3/120`。
+fn parse_counts(message: &str) -> Option<(u64, u64)> {
+    let (indexed, total) = message.trim().split_once('/')?;
+    Some((indexed.trim().parse().ok()?, total.trim().parse().ok()?))
+}
+
+fn summarize(shards: &BTreeMap<usize, ShardIndex>) -> Summary {
+    let indexed = shards.values().map(|index| index.indexed).sum();
+    let total = shards.values().map(|index| index.total).sum();
+    Summary {
+        indexed,
+        total,
+        percentage: (indexed * 100).checked_div(total).unwrap_or(0) as u32,
+        shards: shards.values().filter(|index| !index.finished).count(),
+    }
+}
+
+fn report(summary: &Summary) -> Value {
+    json!({
+        "kind": "report",
+        "message": summary.message(),
+        "percentage": summary.percentage,
+    })
+}
@@ -0,0 +1,134 @@
+//! # 行内值模块
+//!
+//! clangd 不提供 `textDocument/inlineValue`，代理用 tree-sitter 找出调试器停下的函数中的参数和局部变量，
+//! 把停止位置之前对它们的引用作为变量查找返回，由调试适配器填入变量的值。
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::collections::HashSet;
+use tower_lsp::lsp_types::request::{InlineValueRequest, Request};
+use tower_lsp::lsp_types::{
+    InlineValue, InlineValueParams, InlineValueVariableLookup, Position, Range,
+};
+use tree_sitter::Node;
+
+use crate::dispatcher::Dispatcher;
+use crate::document_store::{offset_at, position_at};
+use crate::handlers::{self, HandlerCtx, LOCAL_PROVIDER_PRIORITY, Verdict};
+use crate::syntax;
+use crate::trace::Direction;
+
+/// 声明变量的节点，变量名在 `declarator` 字段中。
+const DECLARING_KINDS: &[&str] = &[
+    "parameter_declaration",
+    "optional_parameter_declaration",
+    "declaration",
+    "for_range_loop",
+    "condition_clause",
+];
+
+/// 请求的行内值：可见范围内、停止的行及之前对当前函数参数和局部变量的引用。
+pub fn inline_values(text: &str, params: &InlineValueParams) -> Vec<InlineValue> {
+    let stopped = params.context.stopped_location.end;
+    // 停止的行本身也显示
+    let limit = offset_at(text, Position::new(stopped.line + 1, 0));
+    let visible = offset_at(text, params.range.start)..offset_at(text, params.range.end);
+
+    let Some(tree) = syntax::parse(text) else {
+        return Vec::new();
+    };
+    let stopped = offset_at(text, stopped);
+    let Some(function) = enclosing_function(tree.root_node(), stopped) else {
+        return Vec::new();
+    };
+
+    let mut locals = HashSet::new();
+    let mut references = Vec::new();
+    walk(function, &mut |node| {
+        if node.start_byte() >= limit {
+            return;
+        }
+        if DECLARING_KINDS.contains(&node.kind()) {
+            let mut cursor = node.walk();
+            for declarator in node.children_by_field_name("declarator", &mut cursor) {
+                if let Some(name) = declared_name(declarator) {
+                    locals.insert(&text[name.byte_range()]);
+                }
+            }
+        }
+        if node.kind() == "identifier"
+            && node.end_byte() <= limit
+            && visible.contains(&node.start_byte())
+        {
+            references.push(node.byte_range());
+        }
+    });
+
+    references
+        .into_iter()
+        .filter(|range| locals.contains(&text[range.clone()]))
+        .map(|range| {
+            InlineValue::VariableLookup(InlineValueVariableLookup {
+                range: Range::new(position_at(text, range.start), position_at(text, range.end)),
+                variable_name: Some(text[range].to_string()),
+                case_sensitive_lookup: true,
+            })
+        })
+        .collect()
+}
+
+/// 包含 `offset` 的最内层函数定义。
+fn enclosing_function(root: Node<'_>, offset: usize) -> Option<Node<'_>> {
+    let mut node = root.descendant_for_byte_range(offset, offset)?;
+    loop {
+        if node.kind() == "function_definition" {
+            return Some(node);
+        }
+        node = node.parent()?;
+    }
+}
+
+/// 声明符中声明的名字，跳过指针、引用、数组和初始化；函数声明不算变量。
+fn declared_name(declarator: Node<'_>) -> Option<Node<'_>> {
+    match declarator.kind() {
+        "identifier" => Some(declarator),
+        "function_declarator" => None,
+        _ => match declarator.child_by_field_name("declarator") {
+            Some(inner) => declared_name(inner),
+            // 引用声明符没有 `declarator` 字段
+            None => declared_name(
+                declarator.named_child(declarator.named_child_count().checked_sub(1)?)?,
+            ),
+        },
+    }
+}
+
+/// 按在文档中的顺序访问节点及其所有子节点。
+fn walk<'tree>(node: Node<'tree>, visit: &mut impl FnMut(Node<'tree>)) {
+    visit(node);
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        walk(child, visit);
+    }
+}
+
+/// 注册行内值的处理器，clangd 不提供行内值，由代理应答。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        InlineValueRequest::METHOD,
+        LOCAL_PROVIDER_PRIORITY,
+        answer_inline_values,
+    );
+}
+
+/// 后端不提供行内值时，以停止的函数中参数和局部变量的引用应答。
+fn answer_inline_values(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        match ctx.dispatcher().local_inline_values(&rpc) {
+            Some(result) => Ok(Verdict::Respond(handlers::response(&rpc, result))),
+            None => Ok(Verdict::Continue(rpc)),
+        }
+    })
+}
@@ -0,0 +1,146 @@
+//! # 关键字补全模块
+//!
+//! 有些环境中 clangd 不提供关键字（例如关闭了 `--completion-style` 相关的选项，或者文件没有编译参数）。
+//! 开启 `[completion] keywords` 后，代理按光标前的内容提供三类补全项，与 clangd 的结果合并：
+//!
+//! - 行首的 `#` 之后：预处理指令
+//! - `[[` 之后：标准属性
+//! - 其他位置：C 或 C++ 的关键字（按文档的语言 id），至少输入一个字符之后才提供
+//!
+//! 由触发字符（`.`、`->`、`::`）触发的补全不提供关键字。
+
+use futures::future::BoxFuture;
+use serde_json::{Value, json};
+use tower_lsp::lsp_types::{CompletionItemKind, CompletionTriggerKind};
+
+use crate::completion_sources::{CompletionProvider, CompletionRequest};
+use crate::document_store;
+
+/// 关键字来源的名称。
+pub const SOURCE: &str = "keywords";
+
+/// 预处理指令。
+#[rustfmt::skip]
+pub const DIRECTIVES: &[&str] = &[
+    "define", "elif", "elifdef", "elifndef", "else", "embed", "endif", "error", "if", "ifdef",
+    "ifndef", "include", "include_next", "line", "pragma", "undef", "warning",
+];
+
+/// C++ 和 C23 的标准属性。
+pub const ATTRIBUTES: &[&str] = &[
+    "assume",
+    "carries_dependency",
+    "deprecated",
+    "fallthrough",
+    "likely",
+    "maybe_unused",
+    "no_unique_address",
+    "nodiscard",
+    "noreturn",
+    "unlikely",
+];
+
+/// C 的关键字（C23）。
+#[rustfmt::skip]
+pub const C_KEYWORDS: &[&str] = &[
+    "alignas", "alignof", "auto", "bool", "break", "case", "char", "const", "constexpr",
+    "continue", "default", "do", "double", "else", "enum", "extern", "false", "float", "for",
+    "goto", "if", "inline", "int", "long", "nullptr", "register", "restrict", "return", "short",
+    "signed", "sizeof", "static", "static_assert", "struct", "switch", "thread_local", "true",
+    "typedef", "typeof", "typeof_unqual", "union", "unsigned", "void", "volatile", "while",
+];
+
+/// C++ 的关键字（C++23）。
+#[rustfmt::skip]
+pub const CPP_KEYWORDS: &[&str] = &[
+    "alignas", "alignof", "asm", "auto", "bool", "break", "case", "catch", "char", "char8_t",
+    "char16_t", "char32_t", "class", "co_await", "co_return", "co_yield", "concept", "const",
+    "const_cast", "consteval", "constexpr", "constinit", "continue", "decltype", "default",
+    "delete", "do", "double", "dynamic_cast", "else", "enum", "explicit", "export", "extern",
+    "false", "final", "float", "for", "friend", "goto", "if", "import", "inline", "int", "long",
+    "module", "mutable", "namespace", "new", "noexcept", "nullptr", "operator", "override",
+    "private", "protected", "public", "register", "reinterpret_cast", "requires", "return",
+    "short", "signed", "sizeof", "static", "static_assert", "static_cast", "struct", "switch",
+    "template", "this", "thread_local", "throw", "true", "try", "typedef", "typeid", "typename",
+    "union", "unsigned", "using", "virtual", "void", "volatile", "wchar_t", "while",
+];
+
+/// 光标前的内容决定的补全种类。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    /// 行首的 `#` 之后
+    Directive,
+    /// 没有闭合的 `[[` 之内
+    Attribute,
+    /// 其他位置
+    Code,
+}
+
+/// 判断光标所在行中光标之前的内容（不含正在输入的单词）属于哪种补全。
+pub fn context(line_before_word: &str) -> Context {
+    if line_before_word.trim() == "#" {
+        return Context::Directive;
+    }
+    match line_before_word.rfind("[[") {
+        Some(open) if !line_before_word[open..].contains("]]") => Context::Attribute,
+        _ => Context::Code,
+    }
+}
+
+/// 按语言 id 选择关键字，C 和 Objective-C 使用 C 的关键字，其余使用 C++ 的关键字。
+pub fn keywords_for(language: &str) -> &'static [&'static str] {
+    match language {
+        "c" | "objective-c" => C_KEYWORDS,
+        _ => CPP_KEYWORDS,
+    }
+}
+
+/// 内置的关键字、属性和预处理指令补全。
+#[derive(Debug, Default)]
+pub struct Keywords;
+
+impl Keywords {
+    /// 光标处可用的补全项。
+    pub fn items(&self, request: &CompletionRequest) -> Vec<Value> {
+        let triggered = request.params.context.as_ref().is_some_and(|context| {
+            context.trigger_kind == CompletionTriggerKind::TRIGGER_CHARACTER
+        });
+        let Some(doc) = request.document.as_ref().filter(|_| !triggered) else {
+            return Vec::new();
+        };
+        let position = request.params.text_document_position.position;
+        let before = &doc.text[..document_store::offset_at(&doc.text, position)];
+        let typed = document_store::word_before(before);
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line = &before[line_start..before.len() - typed.len()];
+
+        let (candidates, detail) = match context(line) {
+            Context::Directive => (DIRECTIVES, "预处理指令"),
+            Context::Attribute => (ATTRIBUTES, "属性"),
+            Context::Code if typed.is_empty() => return Vec::new(),
+            Context::Code => (keywords_for(&doc.language_id), "关键字"),
+        };
+        candidates
+            .iter()
+            .filter(|candidate| candidate.starts_with(typed))
+            .map(|candidate| {
+                json!({
+                    "label": candidate,
+                    "kind": CompletionItemKind::KEYWORD,
+                    "detail": detail,
+                })
+            })
+            .collect()
+    }
+}
+
+impl CompletionProvider for Keywords {
+    fn name(&self) -> &str {
+        SOURCE
+    }
+
+    fn complete(&self, request: &CompletionRequest) -> BoxFuture<'static, Vec<Value>> {
+        let items = self.items(request);
+        Box::pin(async move { items })
+    }
+}
@@ -0,0 +1,169 @@
+//! # 文档通道模块
+//!
+//! 每条消息都在新任务中处理，同一个文档的 `didChange` 或诊断可能被调换顺序。
+//! 对顺序敏感的消息按文档进入先进先出的通道：读取循环按到达顺序领取 [`LaneTicket`]，
+//! 处理任务等前一条消息处理完再开始。针对文档的前端请求排在之前的同步通知之后，
+//! 后端不会收到针对它还没见过的版本的请求；请求之间以及其他消息照常并行处理。
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tower_lsp::lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    Notification, PublishDiagnostics, WillSaveTextDocument,
+};
+
+use crate::notebooks;
+use crate::trace::Direction;
+
+/// 来自前端、需要按文档保持顺序的消息。
+pub const ORDERED_FROM_FRONTEND: &[&str] = &[
+    DidOpenTextDocument::METHOD,
+    DidChangeTextDocument::METHOD,
+    WillSaveTextDocument::METHOD,
+    DidSaveTextDocument::METHOD,
+    DidCloseTextDocument::METHOD,
+    notebooks::DID_OPEN,
+    notebooks::DID_CHANGE,
+    notebooks::DID_SAVE,
+    notebooks::DID_CLOSE,
+];
+
+/// 来自后端、需要按文档保持顺序的消息。
+pub const ORDERED_FROM_BACKEND: &[&str] =
+    &[PublishDiagnostics::METHOD, "textDocument/clangd.fileStatus"];
+
+/// 通道的末尾：最后领取的凭证的序号，以及它处理完成的信号。
+type Tail = (u64, watch::Receiver<bool>);
+
+/// 按文档划分的先进先出通道。
+#[derive(Default)]
+pub struct DocumentLanes {
+    tails: Arc<Mutex<HashMap<String, Tail>>>,
+    next: AtomicU64,
+}
+
+/// 一条消息在通道中的位置。
+///
+/// [`wait`](Self::wait) 等到前一条顺序敏感的消息处理完成；顺序敏感的消息的凭证被丢弃时通知之后的消息。
+pub struct LaneTicket {
+    previous: Option<watch::Receiver<bool>>,
+    /// 顺序敏感的消息占用的通道，丢弃时通知之后的消息
+    _lane: Option<Lane>,
+}
+
+struct Lane {
+    key: String,
+    seq: u64,
+    done: watch::Sender<bool>,
+    tails: Arc<Mutex<HashMap<String, Tail>>>,
+}
+
+impl DocumentLanes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 为一条消息领取凭证，必须在读取循环中按消息到达的顺序调用。
+    ///
+    /// 针对文档的前端请求等待之前的同步通知，但不占用通道；其他顺序不敏感的消息得到一个不需要等待的凭证。
+    ///
+    /// # 参数
+    ///
+    /// * `direction` - 消息来源，前端和后端的通道互不影响
+    /// * `shard` - 来自后端时的分片下标
+    /// * `rpc` - 消息
+    pub fn enter(&self, direction: Direction, shard: Option<usize>, rpc: &Value) -> LaneTicket {
+        let Some(key) = lane_key(direction, shard, rpc) else {
+            let previous = request_lane_key(direction, rpc).and_then(|key| {
+                self.tails
+                    .lock()
+                    .unwrap()
+                    .get(&key)
+                    .map(|(_, tail)| tail.clone())
+            });
+            return LaneTicket {
+                previous,
+                _lane: None,
+            };
+        };
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        let (done, tail) = watch::channel(false);
+        let previous = self
+            .tails
+            .lock()
+            .unwrap()
+            .insert(key.clone(), (seq, tail))
+            .map(|(_, previous)| previous);
+        LaneTicket {
+            previous,
+            _lane: Some(Lane {
+                key,
+                seq,
+                done,
+                tails: Arc::clone(&self.tails),
+            }),
+        }
+    }
+
+    /// 当前有消息在处理或等待的通道数量。
+    pub fn active(&self) -> usize {
+        self.tails.lock().unwrap().len()
+    }
+}
+
+impl LaneTicket {
+    /// 等待同一通道中前面的消息处理完成，返回的凭证在处理完成后丢弃。
+    pub async fn wait(mut self) -> Self {
+        if let Some(mut previous) = self.previous.take() {
+            // 前一个凭证被丢弃时发送端关闭，结果无关紧要
+            let _ = previous.wait_for(|done| *done).await;
+        }
+        self
+    }
+}
+
+impl Drop for Lane {
+    fn drop(&mut self) {
+        let mut tails = self.tails.lock().unwrap();
+        if tails
+            .get(&self.key)
+            .is_some_and(|(seq, _)| *seq == self.seq)
+        {
+            tails.remove(&self.key);
+        }
+        drop(tails);
+        let _ = self.done.send(true);
+    }
+}
+
+/// 顺序敏感的消息所在通道的键：来源、分片和文档 URI。
+fn lane_key(direction: Direction, shard: Option<usize>, rpc: &Value) -> Option<String> {
+    let method = rpc.get("method")?.as_str()?;
+    let ordered = match direction {
+        Direction::Frontend => ORDERED_FROM_FRONTEND,
+        Direction::Backend => ORDERED_FROM_BACKEND,
+    };
+    if !ordered.contains(&method) {
+        return None;
+    }
+    let params = rpc.get("params")?;
+    let uri = params
+        .pointer("/textDocument/uri")
+        .or_else(|| params.pointer("/notebookDocument/uri"))
+        .or_else(|| params.get("uri"))?
+        .as_str()?;
+    Some(format!("{:?}/{}/{}", direction, shard.unwrap_or(0), uri))
+}
+
+/// 针对文档的前端请求需要等待的通道的键。
+fn request_lane_key(direction: Direction, rpc: &Value) -> Option<String> {
+    if direction != Direction::Frontend || rpc.get("id").is_none() {
+        return None;
+    }
+    rpc.get("method")?;
+    let uri = rpc.pointer("/params/textDocument/uri")?.as_str()?;
+    Some(format!("{:?}/0/{}", direction, uri))
+}
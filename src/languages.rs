@@ -0,0 +1,101 @@
+//! # 语言模块
+//!
+//! 按 `[languages]` 配置改正编辑器给出的语言 id，并记录每个打开的文档最终使用的语言，
+//! 多个分片时用来把指定语言的文档交给指定的分片。
+
+use dashmap::DashMap;
+use log::debug;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::Path;
+use tower_lsp::lsp_types::Url;
+
+use crate::config::LanguageConfig;
+
+/// 文档的语言映射。
+#[derive(Default)]
+pub struct Languages {
+    extensions: HashMap<String, String>,
+    ids: HashMap<String, String>,
+    shards: HashMap<String, String>,
+    /// 打开的文档使用的语言
+    opened: DashMap<Url, String>,
+}
+
+impl Languages {
+    pub fn new(config: &LanguageConfig) -> Self {
+        Self {
+            extensions: config
+                .extensions
+                .iter()
+                .map(|(extension, language)| {
+                    (
+                        extension.trim_start_matches('.').to_ascii_lowercase(),
+                        language.clone(),
+                    )
+                })
+                .collect(),
+            ids: config.ids.clone(),
+            shards: config.shards.clone(),
+            opened: DashMap::new(),
+        }
+    }
+
+    /// 按扩展名映射的语言。
+    pub fn language_for_path(&self, path: &str) -> Option<&str> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        self.extensions.get(&extension).map(String::as_str)
+    }
+
+    /// 改正 `didOpen` 通知中的语言 id，并记录文档的语言。
+    ///
+    /// 扩展名的映射优先，其次是语言 id 的映射，都没有时保留编辑器给出的语言。
+    pub fn apply(&self, rpc: &mut Value) {
+        let Some(document) = rpc.pointer_mut("/params/textDocument") else {
+            return;
+        };
+        let Some(uri) = document
+            .get("uri")
+            .and_then(|uri| uri.as_str())
+            .and_then(|uri| Url::parse(uri).ok())
+        else {
+            return;
+        };
+        let given = document
+            .get("languageId")
+            .and_then(|id| id.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let language = self
+            .language_for_path(uri.path())
+            .or_else(|| self.ids.get(&given).map(String::as_str))
+            .unwrap_or(&given)
+            .to_string();
+        if language != given {
+            debug!("{} 的语言从 {} 改为 {}", uri, given, language);
+            document["languageId"] = json!(language);
+        }
+        self.opened.insert(uri, language);
+    }
+
+    /// 文档关闭后不再记录它的语言。
+    pub fn forget(&self, uri: &Url) {
+        self.opened.remove(uri);
+    }
+
+    /// 文档的语言：打开时使用的语言，没有打开时按扩展名映射。
+    pub fn language(&self, uri: &Url) -> Option<String> {
+        self.opened
+            .get(uri)
+            .map(|language| language.clone())
+            .or_else(|| self.language_for_path(uri.path()).map(str::to_string))
+    }
+
+    /// 配置为负责文档所用语言的分片名称。
+    pub fn shard(&self, uri: &Url) -> Option<&str> {
+        if self.shards.is_empty() {
+            return None;
+        }
+        self.shards.get(&self.language(uri)?).map(String::as_str)
+    }
+}
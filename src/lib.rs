@@ -1,4 +1,101 @@
-pub mod lsp_backend;
+pub mod admin;
+pub mod backend_log;
+pub mod batch;
+pub mod bench;
+pub mod cache;
+pub mod capabilities;
+pub mod clangd_flags;
+pub mod cli;
+pub mod client;
+pub mod cmake;
+pub mod codec;
+pub mod colors;
+pub mod commands;
+pub mod compat;
+pub mod compile_flags;
+pub mod completion_sources;
+pub mod compression;
+pub mod config;
+pub mod container;
+pub mod content_modified;
+pub mod crash_report;
+pub mod diagnostic_sources;
+pub mod diagnostics;
+pub mod directory_config;
 pub mod dispatcher;
+pub mod doctor;
+pub mod document_highlight;
+pub mod document_observer;
+pub mod document_store;
+pub mod embedded;
+pub mod events;
+pub mod fallback;
+pub mod features;
+pub mod file_status;
+pub mod file_watcher;
+pub mod fixits;
+pub mod frontend;
+pub mod handlers;
+pub mod health;
+pub mod history;
+pub mod idle;
+pub mod include_check;
+pub mod include_policy;
+pub mod index;
+pub mod index_progress;
+pub mod inline_values;
+pub mod keywords;
+pub mod lanes;
+pub mod languages;
+pub mod lifecycle;
+pub mod liveness;
+pub mod lsp_backend;
+pub mod message;
+pub mod message_throttle;
+pub mod metrics;
+pub mod mock_lsp_server;
+pub mod modules;
+pub mod notebooks;
+pub mod platform;
+pub mod prefetch;
+pub mod profiles;
+pub mod query_driver;
+pub mod rate_limit;
+pub mod reload;
+pub mod remote;
+pub mod rename;
+pub mod replay;
+pub mod responses;
+pub mod sandbox;
+pub mod session;
+pub mod shadow;
+pub mod shard;
+pub mod shutdown;
+pub mod signature_help;
+pub mod size_limit;
+pub mod slow_requests;
+pub mod snippets;
+pub mod spellcheck;
+pub mod ssh;
+pub mod state;
+pub mod stats;
+pub mod supervisor;
+pub mod symbol_index;
+pub mod syntax;
+pub mod tasks;
+pub mod telemetry;
+pub mod tidy_policy;
+pub mod tls;
+pub mod todos;
+pub mod trace;
+pub mod trace_diff;
+pub mod transport;
+pub mod trust;
+pub mod validate;
+pub mod variables;
+pub mod virtual_documents;
+pub mod warmup;
+pub mod word_completion;
+pub mod workspace;
 
-pub use dispatcher::Dispatcher;
\ No newline at end of file
+pub use dispatcher::Dispatcher;
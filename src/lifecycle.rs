@@ -0,0 +1,171 @@
+//! # 请求生命周期模块
+//!
+//! 跟踪每个前端请求从代理收到到响应交给前端的过程：到达时间、排队深度、转发给哪个分片、后端何时应答
+//! 以及消息大小。请求应答后调度器把结束的请求依次交给订阅者（会话统计、请求历史、慢请求日志和遥测），
+//! 这些组件不需要各自维护正在进行的请求。
+
+use dashmap::DashMap;
+use serde_json::Value;
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+
+use crate::size_limit::serialized_len;
+
+/// 同时跟踪的请求数量超过这个值时，清理长时间没有响应的请求（例如被取消的请求）。
+const MAX_IN_FLIGHT: usize = 4096;
+
+/// 清理时保留的最长等待时间。
+const MAX_REQUEST_AGE: Duration = Duration::from_secs(300);
+
+/// 请求到达时的排队深度。
+///
+/// - `pending_backend_requests`: 已转发给后端、还没有响应的请求数量
+/// - `handlers_waiting`: 等待处理许可的消息数量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueDepth {
+    pub pending_backend_requests: usize,
+    pub handlers_waiting: usize,
+}
+
+/// 一个已经应答的前端请求。
+///
+/// - `received_at`/`responded_at`: 代理收到请求和把响应交给前端的时间
+/// - `duration`: 从收到请求到应答前端的时间
+/// - `forwarded`: 转发给的分片和转发的时间，代理自己应答时为 `None`
+/// - `request_bytes`/`response_bytes`: 请求和响应序列化后的字节数，不记录大小时为 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct FinishedRequest {
+    pub id: Value,
+    pub method: String,
+    pub uri: Option<String>,
+    pub received_at: SystemTime,
+    pub responded_at: SystemTime,
+    pub duration: Duration,
+    pub failed: bool,
+    pub queue: QueueDepth,
+    pub forwarded: Option<(usize, SystemTime)>,
+    pub backend_responded: Option<SystemTime>,
+    pub request_bytes: usize,
+    pub response_bytes: usize,
+}
+
+/// 前端请求结束的订阅者。
+///
+/// 方法在响应交给前端之后同步调用，耗时的工作应当交给后台任务。
+pub trait RequestObserver: Send + Sync {
+    /// 请求的响应交给了前端。
+    fn finished(&self, request: &FinishedRequest);
+}
+
+struct InFlight {
+    request: FinishedRequest,
+    at: Instant,
+}
+
+/// 正在进行的前端请求，键是请求 id。
+#[derive(Default)]
+pub struct RequestLifecycle {
+    measure_sizes: bool,
+    in_flight: DashMap<String, InFlight>,
+}
+
+impl RequestLifecycle {
+    /// 创建跟踪器，`measure_sizes` 为 `true` 时记录请求和响应序列化后的大小。
+    pub fn new(measure_sizes: bool) -> Self {
+        Self {
+            measure_sizes,
+            in_flight: DashMap::new(),
+        }
+    }
+
+    /// 代理收到前端的请求，没有 id 的消息不跟踪。
+    pub fn started(&self, rpc: &Value, method: &str, queue: QueueDepth) {
+        let Some(id) = rpc.get("id") else {
+            return;
+        };
+        if self.in_flight.len() >= MAX_IN_FLIGHT {
+            self.in_flight
+                .retain(|_, in_flight| in_flight.at.elapsed() < MAX_REQUEST_AGE);
+        }
+        let now = SystemTime::now();
+        let request = FinishedRequest {
+            id: id.clone(),
+            method: method.to_string(),
+            uri: rpc
+                .pointer("/params/textDocument/uri")
+                .and_then(|uri| uri.as_str())
+                .map(str::to_string),
+            received_at: now,
+            responded_at: now,
+            duration: Duration::ZERO,
+            failed: false,
+            queue,
+            forwarded: None,
+            backend_responded: None,
+            request_bytes: if self.measure_sizes {
+                serialized_len(rpc)
+            } else {
+                0
+            },
+            response_bytes: 0,
+        };
+        self.in_flight.insert(
+            id.to_string(),
+            InFlight {
+                request,
+                at: Instant::now(),
+            },
+        );
+    }
+
+    /// 请求被转发给 `shard` 分片的后端。
+    pub fn forwarded(&self, id: &Value, shard: usize) {
+        if let Some(mut in_flight) = self.in_flight.get_mut(&id.to_string()) {
+            in_flight.request.forwarded = Some((shard, SystemTime::now()));
+        }
+    }
+
+    /// 后端应答了请求。
+    pub fn backend_responded(&self, id: &Value) {
+        if let Some(mut in_flight) = self.in_flight.get_mut(&id.to_string()) {
+            in_flight.request.backend_responded = Some(SystemTime::now());
+        }
+    }
+
+    /// 请求的响应即将发给前端，记录响应的大小。
+    pub fn responding(&self, response: &Value) {
+        if !self.measure_sizes {
+            return;
+        }
+        let Some(id) = response.get("id") else {
+            return;
+        };
+        if let Some(mut in_flight) = self.in_flight.get_mut(&id.to_string()) {
+            in_flight.request.response_bytes = serialized_len(response);
+        }
+    }
+
+    /// 请求的响应交给了前端，`failed` 表示是否以错误应答。
+    ///
+    /// # 返回
+    ///
+    /// 结束的请求；没有跟踪这个 id（已经应答或者被清理）时返回 `None`
+    pub fn finished(&self, id: &Value, failed: bool) -> Option<FinishedRequest> {
+        let (_, in_flight) = self.in_flight.remove(&id.to_string())?;
+        let mut request = in_flight.request;
+        request.responded_at = SystemTime::now();
+        request.duration = in_flight.at.elapsed();
+        request.failed = failed;
+        Some(request)
+    }
+
+    /// 跟踪中的请求数。
+    pub fn len(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// 是否没有跟踪中的请求。
+    pub fn is_empty(&self) -> bool {
+        self.in_flight.is_empty()
+    }
+}
@@ -0,0 +1,110 @@
+//! # 前端存活检测模块
+//!
+//! 编辑器崩溃或被强制结束时，标准输入输出有时没有正常关闭（例如管道被编辑器的其他子进程继承），
+//! 代理读不到 EOF，带着 clangd 一直留在后台。这个模块定期检查前端是否还在：
+//! 标准输入输出上用零字节的探测检查标准输出的管道是否已经关闭，其他传输向前端发送 `$/codefuse/ping` 请求，
+//! 确认前端已经不在时返回，由调用方结束后端并退出。
+//!
+//! 此外按 LSP 的约定监视编辑器在 `initialize` 中给出的 `processId`，编辑器进程结束时同样返回，
+//! 避免编辑器崩溃后留下 clangd 进程。
+
+use log::{debug, warn};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::LivenessConfig;
+use crate::dispatcher::Dispatcher;
+use crate::platform;
+use crate::transport::Transport;
+
+/// 检查编辑器进程是否还在的间隔。
+const PARENT_CHECK_INTERVAL: Duration = Duration::from_secs(3);
+
+/// 检查前端是否还在时发送的请求。
+///
+/// 前端不需要认识这个请求：按 LSP 的约定，不认识的 `$/` 请求以 MethodNotFound 应答，任何应答都说明前端还在。
+pub const PING: &str = "$/codefuse/ping";
+
+/// 检查前端的方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// 查看标准输出的管道另一端是否已经关闭，不写入数据
+    Stdout,
+    /// 发送 [`PING`] 请求，超时没有应答时认为前端已经不在
+    Ping,
+}
+
+impl Probe {
+    /// 传输方式对应的检查方式。Windows 上无法查看标准输出的管道状态，标准输入输出也使用 ping。
+    pub fn for_transport(transport: &Transport) -> Self {
+        match transport {
+            Transport::Stdio if cfg!(unix) => Probe::Stdout,
+            _ => Probe::Ping,
+        }
+    }
+}
+
+/// 定期检查前端，确认前端已经不在时返回。`[liveness] interval_secs` 为 0 时永远不返回。
+///
+/// ping 在前端发送 `initialize` 之后才开始：LSP 不允许服务器在初始化之前向前端发送请求。
+pub async fn watch(dispatcher: Arc<Dispatcher>, config: LivenessConfig, probe: Probe) {
+    if config.interval_secs == 0 {
+        return std::future::pending().await;
+    }
+    let interval = Duration::from_secs(config.interval_secs);
+    let timeout = Duration::from_secs(config.timeout_secs);
+    if probe == Probe::Ping {
+        let mut initialize = dispatcher.subscribe_initialize();
+        if initialize.wait_for(Option::is_some).await.is_err() {
+            return std::future::pending().await;
+        }
+    }
+    loop {
+        tokio::time::sleep(interval).await;
+        let alive = match probe {
+            Probe::Stdout => !stdout_closed(),
+            Probe::Ping => dispatcher.ping_frontend(PING, timeout).await,
+        };
+        if !alive {
+            warn!("前端已经不在（{:?}）", probe);
+            return;
+        }
+        debug!("前端仍然在线");
+    }
+}
+
+/// 监视编辑器在 `initialize` 中给出的 `processId`，这个进程结束时返回。
+///
+/// 编辑器没有给出 `processId`（为 `null`）时永远不返回。
+pub async fn watch_parent(dispatcher: Arc<Dispatcher>) {
+    let mut initialize = dispatcher.subscribe_initialize();
+    let pid = match initialize.wait_for(Option::is_some).await {
+        Ok(params) => params
+            .as_ref()
+            .and_then(|params| params.get("processId"))
+            .and_then(|pid| pid.as_u64())
+            .and_then(|pid| u32::try_from(pid).ok()),
+        Err(_) => None,
+    };
+    let Some(pid) = pid else {
+        return std::future::pending().await;
+    };
+    debug!("监视编辑器进程 {}", pid);
+    loop {
+        tokio::time::sleep(PARENT_CHECK_INTERVAL).await;
+        if !platform::process_alive(pid) {
+            warn!("编辑器进程 {} 已经结束", pid);
+            return;
+        }
+    }
+}
+
+#[cfg(unix)]
+fn stdout_closed() -> bool {
+    platform::peer_closed(libc::STDOUT_FILENO)
+}
+
+#[cfg(not(unix))]
+fn stdout_closed() -> bool {
+    false
+}
@@ -1,19 +1,33 @@
-﻿//! # Lsp后端模块
+//! # Lsp后端模块
+//!
+//! 启动 clangd 进程。进程由 [`BackendProcess`] 持有，代理退出、任务被丢弃或 panic 时都会结束后端，
+//! 不会留下孤儿 clangd。
 
-use std::sync::atomic::AtomicU64;
-use tokio::io::BufReader;
-use tokio::process::{ChildStderr, ChildStdin, ChildStdout, Command};
+use anyhow::{Context, Result};
 use log::{debug, error, info, warn};
+use std::io;
+use std::process::ExitStatus;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::io::AsyncBufReadExt;
+use tokio::io::BufReader;
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+
+use crate::backend_log::{LogLevel, LogLine, LogParser};
+use crate::config::ResourceLimits;
+use crate::platform;
 
 /// Lsp后端结构体。
 ///
 /// - `stdin`: 用于向 lsp 发送数据的标准输入句柄
-/// - `stdout`: 用于从 lsp 接收数据的标准输出缓冲读取器
+/// - `stdout`: 用于从 lsp 接收数据的标准输出，由消息解码器负责缓冲
 /// - `id_counter`: 用于生成唯一的请求 ID 的原子计数器
+/// - `child`: 子进程句柄，用于等待进程退出或结束进程
 pub struct LspBackend {
+    pub child: BackendProcess,
     pub stdin: ChildStdin,
-    pub stdout: BufReader<ChildStdout>,
+    pub stdout: ChildStdout,
     pub stderr: BufReader<ChildStderr>,
     pub id_counter: AtomicU64,
 }
@@ -22,7 +36,7 @@ impl LspBackend {
     /// 启动新的 lsp 进程
     ///
     /// 这个方法执行以下操作：
-    /// 1. 使用 `Command::new(program)` 创建新的进程
+    /// 1. 按 `PATH`（Windows 上还有 `PATHEXT`）查找程序，使用给定的参数、环境变量和资源限制创建新的进程
     /// 2. 设置标准输入和输出为管道
     /// 3. 启动进程并获取输入输出句柄
     /// 4. 初始化 ID 计数器为 1
@@ -30,54 +44,181 @@ impl LspBackend {
     /// # 返回
     ///
     /// 返回初始化后的 `LspBackend` 实例
-    pub async fn spawn(program: &str) -> Self {
-        let mut child = Command::new(program)
+    ///
+    /// # 错误
+    ///
+    /// 如果进程无法启动（例如找不到程序），返回错误
+    pub async fn spawn(
+        program: &str,
+        args: &[String],
+        envs: &[(String, String)],
+        limits: &ResourceLimits,
+    ) -> Result<Self> {
+        let resolved = platform::resolve_program(program);
+        let mut command = Command::new(resolved.as_deref().unwrap_or(program.as_ref()));
+        command
+            .kill_on_drop(true)
+            .args(args)
+            .envs(envs.iter().cloned())
             .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        #[cfg(unix)]
+        platform::apply_limits(&mut command, limits);
+        let mut child = command
             .spawn()
-            .expect(format!("Failed to start {}", program).as_str());
+            .with_context(|| format!("无法启动 {}", program))?;
+
+        #[cfg(windows)]
+        if let Err(e) = platform::job::assign(&child, limits) {
+            warn!("无法把后端加入作业对象，代理异常退出时后端可能残留: {}", e);
+        }
 
         let stdin = child.stdin.take().unwrap();
-        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let stdout = child.stdout.take().unwrap();
         let stderr = BufReader::new(child.stderr.take().unwrap());
 
-        Self {
+        Ok(Self {
+            child: BackendProcess::new(child),
             stdin,
             stdout,
             stderr,
             id_counter: AtomicU64::new(1),
+        })
+    }
+}
+
+/// 正在运行的后端进程的 pid，panic 钩子据此结束后端。
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// `shutdown` 等待进程退出的时间。
+const REAP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 持有一个后端子进程。
+///
+/// 丢弃时结束进程（`kill_on_drop`），已退出的进程由 tokio 在后台回收；
+/// 需要确认进程已经回收时调用 [`shutdown`](Self::shutdown)。
+pub struct BackendProcess {
+    child: Child,
+    pid: Option<u32>,
+}
+
+impl BackendProcess {
+    fn new(child: Child) -> Self {
+        let pid = child.id();
+        if let Some(pid) = pid {
+            RUNNING.lock().unwrap_or_else(|e| e.into_inner()).push(pid);
+        }
+        Self { child, pid }
+    }
+
+    /// 进程 id，进程已经被回收时返回 `None`。
+    pub fn id(&self) -> Option<u32> {
+        self.child.id()
+    }
+
+    /// 等待进程退出并回收。
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        let status = self.child.wait().await;
+        self.forget();
+        status
+    }
+
+    /// 结束进程并等待回收，超时后放弃等待。
+    ///
+    /// # 错误
+    ///
+    /// 无法向进程发送信号或等待进程时返回错误
+    pub async fn shutdown(mut self) -> io::Result<()> {
+        self.child.start_kill()?;
+        match tokio::time::timeout(REAP_TIMEOUT, self.child.wait()).await {
+            Ok(status) => status.map(|_| ()),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "等待后端进程退出超时",
+            )),
+        }
+    }
+
+    fn forget(&mut self) {
+        if let Some(pid) = self.pid.take() {
+            RUNNING
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .retain(|&running| running != pid);
         }
     }
 }
 
-pub async fn pipe_lsp_backend_stderr(stderr: BufReader<ChildStderr>) {
-    let mut lines = stderr.lines();
+impl Drop for BackendProcess {
+    fn drop(&mut self) {
+        self.forget();
+        // 子进程句柄随后被丢弃，kill_on_drop 负责结束进程
+    }
+}
 
-    while let Ok(Some(line)) = lines.next_line().await {
-        // 示例：I[11:01:38.638] clangd version 21.1.0
-        let trimmed = line.trim();
-
-        if let Some((level, rest)) = parse_lsp_backend_log_line(trimmed) {
-            match level {
-                'I' => info!("{}", rest),
-                'W' => warn!("{}", rest),
-                'E' => error!("{}", rest),
-                'F' => error!("FATAL: {}", rest),
-                _ => debug!("{}", trimmed),
-            }
-        } else {
-            debug!("{}", trimmed); // 无法解析，降级为 debug
+/// 正在运行的后端进程的 pid。
+pub fn running_backends() -> Vec<u32> {
+    RUNNING.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// 向所有正在运行的后端发送 SIGTERM，不等待进程退出。
+///
+/// 用在无法再执行析构的场合，例如 panic 之后进程将被中止。
+pub fn terminate_running_backends() {
+    // panic 可能发生在持有锁的时候，拿不到锁就放弃
+    let Ok(pids) = RUNNING.try_lock() else {
+        return;
+    };
+    for &pid in pids.iter() {
+        #[cfg(unix)]
+        // SAFETY: 只是向一个 pid 发送信号，不涉及内存
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
         }
+        #[cfg(not(unix))]
+        let _ = pid;
     }
 }
 
-fn parse_lsp_backend_log_line(line: &str) -> Option<(char, &str)> {
-    if line.len() >= 15 {
-        let level = line.chars().next()?; // 第一个字符是日志等级
-        let rest = &line[15..]; // 跳过前缀
-        Some((level, rest.trim()))
-    } else {
-        None
+/// 安装 panic 钩子：在原有的钩子输出 panic 信息后结束所有后端。
+///
+/// 只在 `panic = "abort"` 时需要：进程会被直接中止，`BackendProcess` 的析构不会执行。
+/// 展开模式下 panic 只结束所在的任务，后端由各自的持有者负责。
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        previous(info);
+        if cfg!(panic = "abort") {
+            terminate_running_backends();
+        }
+    }));
+}
+
+/// 读取后端的标准错误输出，按日志级别写入代理的日志。
+///
+/// # 参数
+///
+/// * `stderr` - 后端进程的标准错误输出
+/// * `parser` - 后端日志格式的解析器
+/// * `inspect` - 对解析出的每一行调用，例如记录到后端日志或者从中识别 clangd 版本
+pub async fn pipe_lsp_backend_stderr(
+    stderr: BufReader<ChildStderr>,
+    parser: Arc<dyn LogParser>,
+    inspect: impl Fn(&LogLine) + Send + 'static,
+) {
+    let mut lines = stderr.lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = parser.parse(&line);
+        match line.level {
+            Some(LogLevel::Info) => info!("{}", line.message),
+            Some(LogLevel::Warning) => warn!("{}", line.message),
+            Some(LogLevel::Error) => error!("{}", line.message),
+            Some(LogLevel::Fatal) => error!("FATAL: {}", line.message),
+            // 无法解析的行降级为 debug
+            Some(LogLevel::Debug) | None => debug!("{}", line.message),
+        }
+        inspect(&line);
     }
 }
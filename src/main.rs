@@ -5,35 +5,100 @@
 //!
 //! ## 主要组件
 //!
-//! - `clangd_client`: 负责启动和管理 clangd 进程
+//! - `lsp_backend`: 负责启动和管理 clangd 进程
 //! - `dispatcher`: 负责消息的分发和处理逻辑
+//! - `symbol_index`: clangd 索引就绪前的 ctags 后备符号索引
+//! - `shard`: 按子树拆分的多个 clangd 实例之间的路由
+//! - `supervisor`: 看护后端进程，维护备用后端以便无缝重启
 //! - `main`: 主程序入口，设置异步任务和消息循环
 
-mod lsp_backend;
-mod dispatcher;
-mod handlers;
-mod tasks;
-
-use crate::lsp_backend::{LspBackend, pipe_lsp_backend_stderr};
-use crate::dispatcher::Dispatcher;
-use crate::handlers::setup_handlers;
-use crate::tasks::*;
-use anyhow::Result;
+use anyhow::{Result, bail};
 use chrono::Local;
-use log::{error, info};
+use futures::future::select_all;
+use log::{error, info, warn};
+use lsp_proxy::admin;
+use lsp_proxy::batch::BatchTracker;
+use lsp_proxy::bench;
+use lsp_proxy::cache;
+use lsp_proxy::clangd_flags;
+use lsp_proxy::cli::{CliArgs, Command};
+use lsp_proxy::cmake;
+use lsp_proxy::compression::FrameCompression;
+use lsp_proxy::config::Config;
+use lsp_proxy::crash_report;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::doctor;
+use lsp_proxy::frontend::{self, FrontendMode, TypedFrontend};
+use lsp_proxy::handlers::setup_handlers;
+use lsp_proxy::health;
+use lsp_proxy::index;
+use lsp_proxy::liveness::{self, Probe};
+use lsp_proxy::lsp_backend;
+use lsp_proxy::message::Message;
+use lsp_proxy::mock_lsp_server;
+use lsp_proxy::modules;
+use lsp_proxy::query_driver;
+use lsp_proxy::reload::{self, ConfigSource};
+use lsp_proxy::remote;
+use lsp_proxy::session::{self, SessionKeeper};
+use lsp_proxy::shadow::Shadow;
+use lsp_proxy::shard::Shard;
+use lsp_proxy::shutdown;
+use lsp_proxy::supervisor::{self, BackendSupervisor};
+use lsp_proxy::tasks::*;
+use lsp_proxy::telemetry;
+use lsp_proxy::trace_diff;
+use lsp_proxy::transport::{FrontendListener, Transport};
+use lsp_proxy::trust::{self, WorkspaceTrust};
 use std::io::Write;
 use std::sync::Arc;
-use tokio::io::BufReader;
-use tokio::sync::{Semaphore, mpsc};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// 退出时等待写完发给前端的消息的最长时间。
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 按命令行参数调整后端的启动方式，启动时和重新加载配置时都会调用。
+///
+/// # 参数
+///
+/// * `config` - 代理配置
+/// * `mock_backend` - 使用模拟后端时的命令和参数
+fn prepare_backend(config: &mut Config, mock_backend: Option<&(String, Vec<String>)>) {
+    // 模拟后端由代理自身以 mock-server 子命令启动，沿用后端的进程管理
+    if let Some((command, args)) = mock_backend {
+        config.backend.command = command.clone();
+        config.backend.args = args.clone();
+        return;
+    }
+
+    // clangd 的模块支持还是实验性的，需要显式开启
+    if config.modules.enabled
+        && !config
+            .backend
+            .args
+            .iter()
+            .any(|arg| arg == modules::BACKEND_FLAG)
+    {
+        config.backend.args.push(modules::BACKEND_FLAG.to_string());
+    }
+
+    clangd_flags::apply(&mut config.backend);
+}
 
 /// 主函数，程序的入口点。
 ///
 /// 这个函数设置了整个 LSP 代理服务器的架构：
-/// - 启动 clangd 进程
+/// - 读取命令行参数和配置文件
+/// - 启动 clangd 进程（配置了分片时每个分片一个）
 /// - 创建消息通道
 /// - 启动发送和接收数据的异步任务
 /// - 设置消息处理器
-/// - 等待任一任务完成
+/// - 等待任一任务完成，或者收到 SIGTERM / Ctrl-C
+///
+/// 返回时运行时丢弃所有任务，后端进程随之结束。
+/// 收到退出信号时先按 LSP 的方式结束后端，写完发给前端的消息后以 [`shutdown`] 模块约定的退出码退出。
 ///
 /// # 返回
 ///
@@ -57,71 +122,312 @@ async fn main() -> Result<()> {
                 record.args()
             )
         })
-        .filter_level(log::LevelFilter::Info)
+        .filter_level(log::LevelFilter::Trace)
         .write_style(env_logger::WriteStyle::Auto)
         .target(env_logger::Target::Stderr) // 写入 stderr，避免污染 stdout
         .init();
+    // 实际的级别由 [logging] level 决定，重新加载配置时可以修改
+    log::set_max_level(log::LevelFilter::Info);
 
-    info!("Starting LSP proxy server...");
+    let args = CliArgs::parse(std::env::args().skip(1))?;
+    let mut config = Config::discover_profile(args.config.as_deref(), args.profile.as_deref())?;
+    log::set_max_level(config.logging.level_filter());
+    // 工作区中的配置文件只有在工作区受信任时才能启动进程
+    let trust = Arc::new(WorkspaceTrust::discover(
+        Config::discover_path(args.config.as_deref()).as_deref(),
+        &std::env::current_dir()?,
+        args.trust_workspace,
+    ));
+    trust.apply(&mut config);
+    if let Some(message) = trust.message() {
+        warn!("{}", message);
+    }
 
-    let LspBackend {
-        stdin,
-        stdout,
-        stderr,
-        id_counter: _,
-    } = LspBackend::spawn("clangd").await;
+    match &args.command {
+        Some(Command::Cache(command)) => return cache::run(command, &config),
+        Some(Command::Bench(command)) => {
+            return bench::run(command, &config, args.config.as_deref()).await;
+        }
+        Some(Command::Doctor) => return doctor::run(&config, args.config.as_deref()),
+        Some(Command::Index(command)) => return index::run(command, &config).await,
+        Some(Command::Setup(command)) => return cmake::run(command, &config),
+        Some(Command::Trust(command)) => return trust::run(command),
+        Some(Command::Diff(command)) => {
+            if !trace_diff::run(command)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Admin { socket }) => {
+            let Some(socket) = socket.as_ref().or(config.admin.socket.as_ref()) else {
+                bail!("没有给出管理控制台的套接字，也没有配置 [admin] socket");
+            };
+            admin::attach(socket).await?;
+            // 标准输入在阻塞线程上读取，运行时退出时会一直等它读完，所以直接结束进程
+            std::process::exit(0);
+        }
+        Some(Command::Relay { address }) => {
+            return remote::relay(address, &config).await;
+        }
+        Some(Command::MockServer { fixture }) => {
+            return mock_lsp_server::run_stdio(fixture.as_deref()).await;
+        }
+        None => {}
+    }
 
-    tokio::spawn(pipe_lsp_backend_stderr(stderr));
+    let mock_backend = if args.mock_backend {
+        let mut mock_args = vec!["mock-server".to_string()];
+        if let Some(fixture) = &args.mock_fixture {
+            mock_args.push(fixture.to_string_lossy().into_owned());
+        }
+        let command = std::env::current_exe()?.to_string_lossy().into_owned();
+        Some((command, mock_args))
+    } else {
+        None
+    };
+    prepare_backend(&mut config, mock_backend.as_ref());
 
-    // 读取 VSCode 请求
-    let reader = BufReader::new(tokio::io::stdin());
-    let writer = tokio::io::stdout();
+    info!("Starting LSP proxy server...");
+    lsp_backend::install_panic_hook();
 
-    let (backend_tx, backend_rx) = mpsc::unbounded_channel::<String>();
-    let (frontend_tx, frontend_rx) = mpsc::unbounded_channel::<String>();
+    // 读取 VSCode 请求；保留会话时编辑器断开后还要在同一个地址上等待它重新连接
+    let grace = config.session.grace_secs.map(Duration::from_secs);
+    let mut keeper = None;
+    let (reader, writer) = match (&args.transport, grace) {
+        (Transport::Listen(address), Some(grace)) if args.frontend == FrontendMode::Raw => {
+            let listener = FrontendListener::bind(address, &config.transport).await?;
+            let connection = listener.accept().await?;
+            keeper = Some(SessionKeeper::new(
+                listener,
+                grace,
+                config.transport.clone(),
+            ));
+            connection
+        }
+        (_, grace) => {
+            if grace.is_some() {
+                warn!(
+                    "只有以 --listen 监听并使用默认前端时才能保留会话，忽略 [session] grace_secs"
+                );
+            }
+            args.transport.connect(&config.transport).await?
+        }
+    };
 
-    let send_backend_handle = tokio::spawn(send_data_backend(stdin, backend_rx));
-    let send_frontend_handle = tokio::spawn(send_data_frontend(writer, frontend_rx));
+    let (frontend_tx, frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let closing = CancellationToken::new();
 
-    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    // 默认后端和每个配置的分片各启动一个进程
+    let mut shard_specs = vec![("default".to_string(), None)];
+    for shard in &config.shards {
+        shard_specs.push((shard.display_name(), Some(shard.path.clone())));
+    }
+    let shard_args = supervisor::shard_args(&config);
 
-    let semaphore = Arc::new(Semaphore::new(15)); // 限制最多 10 个并发任务
+    // 每个分片由一个监管者负责启动后端，必要时切换到备用后端
+    let backend_env = config.cache.backend_env();
+    let mut shards = Vec::new();
+    let mut supervisors = Vec::new();
+    for (index, ((name, root), args)) in shard_specs.into_iter().zip(shard_args).enumerate() {
+        if let Some(root) = &root {
+            info!("分片 {} 负责 {}", name, root.display());
+        }
+        let (supervisor, sender) = BackendSupervisor::new(
+            index,
+            name.clone(),
+            &config.backend,
+            args,
+            backend_env.clone(),
+        )?;
+        supervisors.push(supervisor);
+        shards.push(Shard { name, root, sender });
+    }
 
-    let recv_backend_handle = tokio::spawn(receive_data_backend(
-        stdout,
-        Arc::clone(&dispatcher),
-        Arc::clone(&semaphore),
-    ));
-    let recv_frontend_handle = tokio::spawn(receive_data_frontend(
-        reader,
+    // 影子后端只用于比较，无法启动时不影响主后端
+    let shadow = Shadow::spawn(&config.shadow).unwrap_or_else(|e| {
+        error!("无法启动影子后端: {:?}", e);
+        None
+    });
+
+    let max_handlers = config.concurrency.max_handlers;
+    let health_port = config.health.port;
+    let admin_socket = config.admin.socket.clone();
+    let liveness_config = config.liveness.clone();
+    let query_driver_mode = config.backend.clangd.auto_query_driver;
+    let telemetry_config = config.telemetry.clone();
+    let transport_config = config.transport.clone();
+    // 配置文件修改或者切换配置档后重新加载，能立即生效的设置不需要重启
+    let config_source = Arc::new(
+        ConfigSource::new(
+            Config::discover_path(args.config.as_deref()),
+            args.profile.clone(),
+            config.clone(),
+            move |config| prepare_backend(config, mock_backend.as_ref()),
+        )
+        .with_trust(trust),
+    );
+    let mut dispatcher = Dispatcher::with_shards(shards, frontend_tx)
+        .with_config(config)
+        .with_config_source(Arc::clone(&config_source))
+        .with_shadow(shadow)
+        .with_validation(args.validate);
+    setup_handlers(&mut dispatcher);
+    let dispatcher = Arc::new(dispatcher);
+    crash_report::install_panic_hook(&dispatcher);
+
+    let limiter = Arc::new(HandlerLimiter::new(max_handlers, dispatcher.metrics()));
+    tokio::spawn(Arc::clone(&limiter).follow(dispatcher.subscribe_handler_limit()));
+    tokio::spawn(reload::watch(config_source, Arc::clone(&dispatcher)));
+    tokio::spawn(query_driver::watch(
         Arc::clone(&dispatcher),
-        Arc::clone(&semaphore),
+        query_driver_mode,
     ));
 
-    setup_handlers(Arc::clone(&dispatcher)).await;
+    if telemetry_config.endpoint.is_some() {
+        tokio::spawn(telemetry::export_loop(
+            dispatcher.telemetry(),
+            telemetry_config,
+        ));
+    }
 
-    tokio::select! {
-        result = send_backend_handle => {
-            if let Err(e) = result {
-                error!("后端发送任务失败: {:?}", e);
+    // 健康检查是可选的，端口被占用时只记录错误
+    if let Some(port) = health_port
+        && let Err(e) = health::serve(port, Arc::clone(&dispatcher)).await
+    {
+        error!("{:?}", e);
+    }
+
+    // 管理控制台同样是可选的
+    if let Some(path) = &admin_socket
+        && let Err(e) = admin::serve(path, Arc::clone(&dispatcher)).await
+    {
+        error!("{:?}", e);
+    }
+
+    // 保留会话时编辑器断开是正常的，由会话的宽限期决定何时退出
+    let watch_liveness = keeper.is_none();
+    // --listen 时编辑器在另一台机器上，initialize 中的 processId 没有意义
+    let watch_parent =
+        liveness_config.parent_process && !matches!(args.transport, Transport::Listen(_));
+    let probe = Probe::for_transport(&args.transport);
+    let liveness = {
+        let dispatcher = Arc::clone(&dispatcher);
+        async move {
+            tokio::select! {
+                () = liveness::watch(Arc::clone(&dispatcher), liveness_config, probe) => {}
+                () = liveness::watch_parent(dispatcher), if watch_parent => {}
             }
-        },
-        result = send_frontend_handle => {
+        }
+    };
+
+    let backend_handles: Vec<_> = supervisors
+        .into_iter()
+        .map(|supervisor| {
+            tokio::spawn(supervisor.run(Arc::clone(&dispatcher), Arc::clone(&limiter)))
+        })
+        .collect();
+    let (mut send_frontend_handle, mut recv_frontend_handle) = match (args.frontend, keeper) {
+        // 编辑器断开后保留会话，每个连接的编辑器各有自己的读写任务
+        (FrontendMode::Raw, Some(keeper)) => {
+            let send = tokio::spawn(session::forward_to_connection(
+                frontend_rx,
+                keeper.connection(),
+                closing.clone(),
+            ));
+            let recv = tokio::spawn(keeper.serve(
+                (reader, writer),
+                Arc::clone(&dispatcher),
+                Arc::clone(&limiter),
+                closing.clone(),
+            ));
+            (send, recv)
+        }
+        (FrontendMode::Raw, None) => {
+            let batches = Arc::new(BatchTracker::new());
+            // 只有 TCP 连接的对端可能是另一个代理，才需要协商压缩
+            let compression = args
+                .transport
+                .is_socket()
+                .then(|| FrameCompression::new(&transport_config, dispatcher.metrics()))
+                .flatten();
+            let send = tokio::spawn(send_data_frontend(
+                writer,
+                frontend_rx,
+                Arc::clone(&batches),
+                closing.clone(),
+                compression.clone(),
+            ));
+            let recv = tokio::spawn(receive_data_frontend(
+                reader,
+                Arc::clone(&dispatcher),
+                Arc::clone(&limiter),
+                batches,
+                compression,
+            ));
+            (send, recv)
+        }
+        // tower-lsp 负责读写前端的消息，调度器发给前端的消息经由它的客户端发出
+        (FrontendMode::Typed, _) => {
+            if args.transport.is_socket() && !transport_config.compression.is_empty() {
+                warn!("类型化前端不支持帧压缩，忽略 [transport] compression");
+            }
+            let (service, socket, relay) =
+                TypedFrontend::builder(Arc::clone(&dispatcher), frontend_rx).build();
+            let send = tokio::spawn(relay.run(closing.clone()));
+            let recv = tokio::spawn(frontend::serve(reader, writer, service, socket));
+            (send, recv)
+        }
+    };
+
+    let received = tokio::select! {
+        (result, _, _) = select_all(backend_handles) => {
             if let Err(e) = result {
-                error!("前端发送任务失败: {:?}", e);
+                error!("后端任务失败: {:?}", e);
             }
+            None
         },
-        result = recv_backend_handle => {
+        result = &mut send_frontend_handle => {
             if let Err(e) = result {
-                error!("后端接收任务失败: {:?}", e);
+                error!("前端发送任务失败: {:?}", e);
             }
+            None
         },
-        result = recv_frontend_handle => {
+        result = &mut recv_frontend_handle => {
             if let Err(e) = result {
                 error!("前端接收任务失败: {:?}", e);
             }
+            None
         }
-    }
+        received = shutdown::signal() => Some(received),
+        () = liveness, if watch_liveness => {
+            // 编辑器已经不在，没有人接收消息；标准输入可能一直读不到 EOF，所以直接结束进程
+            warn!("编辑器已经不在，结束后端并退出");
+            dispatcher.save_stats();
+            dispatcher.save_state();
+            dispatcher.shutdown_backends().await;
+            lsp_backend::terminate_running_backends();
+            std::process::exit(0);
+        }
+    };
+    dispatcher.save_stats();
+    dispatcher.save_state();
+    let Some(received) = received else {
+        return Ok(());
+    };
 
-    Ok(())
+    // 不再读取前端的消息，结束后端，再写完已经排队的响应和通知
+    recv_frontend_handle.abort();
+    dispatcher.shutdown_backends().await;
+    closing.cancel();
+    match tokio::time::timeout(FLUSH_TIMEOUT, send_frontend_handle).await {
+        Ok(Ok(Ok(()))) => {}
+        Ok(Ok(Err(e))) => error!("写出前端消息失败: {:?}", e),
+        Ok(Err(e)) => error!("前端发送任务失败: {:?}", e),
+        Err(_) => warn!("写出前端消息超时"),
+    }
+    info!("代理退出");
+    // 使用标准输入输出时，标准输入在阻塞线程上读取，运行时退出时会一直等它读完，所以直接结束进程；
+    // 没有响应 exit 的后端（包括备用后端）不会执行析构，这里单独结束
+    lsp_backend::terminate_running_backends();
+    std::process::exit(received.exit_code().into())
 }
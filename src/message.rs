@@ -0,0 +1,95 @@
+//! # 消息模块
+//!
+//! 前端和后端之间的通道传递的是解析过的 [`Message`]，转发途中的代码可以直接查看方法和 id，
+//! 只有写出任务才把消息格式化为带 `Content-Length` 消息头的文本。
+
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::dispatcher::Dispatcher;
+
+/// 一条 JSON-RPC 消息：解析出的 id 和方法，以及完整的消息体。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Request {
+        id: Value,
+        method: String,
+        body: Value,
+    },
+    Response {
+        id: Value,
+        body: Value,
+    },
+    Notification {
+        method: String,
+        body: Value,
+    },
+}
+
+impl Message {
+    /// 根据消息体判断消息的种类。没有 `method` 的消息都视为响应，缺少 `id` 时 id 为 `null`。
+    pub fn new(body: Value) -> Self {
+        let method = body
+            .get("method")
+            .and_then(|m| m.as_str())
+            .map(String::from);
+        let id = body.get("id").cloned();
+        match (method, id) {
+            (Some(method), Some(id)) => Self::Request { id, method, body },
+            (Some(method), None) => Self::Notification { method, body },
+            (None, id) => Self::Response {
+                id: id.unwrap_or(Value::Null),
+                body,
+            },
+        }
+    }
+
+    /// 请求和通知的方法。
+    pub fn method(&self) -> Option<&str> {
+        match self {
+            Self::Request { method, .. } | Self::Notification { method, .. } => Some(method),
+            Self::Response { .. } => None,
+        }
+    }
+
+    /// 请求和响应的 id。
+    pub fn id(&self) -> Option<&Value> {
+        match self {
+            Self::Request { id, .. } | Self::Response { id, .. } => Some(id),
+            Self::Notification { .. } => None,
+        }
+    }
+
+    /// 完整的消息体。
+    pub fn body(&self) -> &Value {
+        match self {
+            Self::Request { body, .. }
+            | Self::Response { body, .. }
+            | Self::Notification { body, .. } => body,
+        }
+    }
+
+    /// 取出消息体。
+    pub fn into_body(self) -> Value {
+        match self {
+            Self::Request { body, .. }
+            | Self::Response { body, .. }
+            | Self::Notification { body, .. } => body,
+        }
+    }
+
+    /// 格式化为带消息头的 LSP 消息，由写出任务调用。
+    ///
+    /// # 错误
+    ///
+    /// 如果消息体无法序列化，返回错误
+    pub fn to_lsp_string(&self) -> Result<String> {
+        Dispatcher::format_lsp_message(self.body())
+    }
+}
+
+impl From<Value> for Message {
+    fn from(body: Value) -> Self {
+        Self::new(body)
+    }
+}
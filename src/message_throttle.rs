@@ -0,0 +1,124 @@
+//! # 消息去重模块
+//!
+//! clangd 有时反复发送内容相同的 `window/showMessage` 警告，编辑器随之弹出一串相同的提示。
+//! 这个模块注册后端消息的处理器：同一种通知中类型和文字都相同的消息，在时间窗口内只转发前几条，
+//! 其余的丢弃，窗口结束后再次出现时照常转发。没有配置时间窗口时不做处理。
+
+use anyhow::Result;
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use log::debug;
+use serde_json::Value;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::time::Instant;
+use tower_lsp::lsp_types::notification::{LogMessage, Notification, ShowMessage};
+
+use crate::config::MessagesConfig;
+use crate::dispatcher::{DEFAULT_HANDLER_PRIORITY, Dispatcher};
+use crate::handlers::{HandlerCtx, Verdict};
+use crate::trace::Direction;
+
+/// 记录的不同消息超过这个数量时，清理窗口已经结束的记录。
+const MAX_TRACKED: usize = 1024;
+
+/// 一条消息在当前窗口内的情况。
+struct Seen {
+    since: Instant,
+    count: usize,
+}
+
+/// `window/showMessage` 和 `window/logMessage` 的去重和限速。
+#[derive(Default)]
+pub struct MessageThrottle {
+    config: RwLock<MessagesConfig>,
+    /// (方法, 类型, 文字) → 当前窗口的开始时间和出现次数
+    seen: DashMap<(String, i64, String), Seen>,
+}
+
+impl MessageThrottle {
+    /// 按 `[messages]` 配置创建。
+    pub fn new(config: &MessagesConfig) -> Self {
+        Self {
+            config: RwLock::new(config.clone()),
+            seen: DashMap::new(),
+        }
+    }
+
+    /// 替换配置，重新加载配置时调用。之前的记录被丢弃。
+    pub fn set_config(&self, config: &MessagesConfig) {
+        *self.config.write().unwrap() = config.clone();
+        self.seen.clear();
+    }
+
+    /// 后端发来的通知是否应该转发给前端。
+    ///
+    /// # 参数
+    ///
+    /// * `method` - `window/showMessage` 或 `window/logMessage`
+    /// * `params` - 通知的参数，按其中的 `type` 和 `message` 判断是否相同
+    pub fn allow(&self, method: &str, params: &Value) -> bool {
+        let config = self.config.read().unwrap();
+        let Some(window) = config.window_secs.map(Duration::from_secs) else {
+            return true;
+        };
+        let (Some(kind), Some(message)) = (
+            params.get("type").and_then(|t| t.as_i64()),
+            params.get("message").and_then(|m| m.as_str()),
+        ) else {
+            return true;
+        };
+
+        let now = Instant::now();
+        if self.seen.len() >= MAX_TRACKED {
+            self.seen
+                .retain(|_, seen| now.duration_since(seen.since) < window);
+        }
+        let mut seen = self
+            .seen
+            .entry((method.to_string(), kind, message.to_string()))
+            .or_insert(Seen {
+                since: now,
+                count: 0,
+            });
+        if now.duration_since(seen.since) >= window {
+            if seen.count > config.max_repeats {
+                debug!(
+                    "{} 中的消息在 {} 秒内重复了 {} 次: {}",
+                    method,
+                    window.as_secs(),
+                    seen.count,
+                    message
+                );
+            }
+            seen.since = now;
+            seen.count = 0;
+        }
+        seen.count += 1;
+        seen.count <= config.max_repeats
+    }
+}
+
+/// 注册 `window/showMessage` 和 `window/logMessage` 的去重处理器。
+pub fn register(dispatcher: &mut Dispatcher) {
+    for method in [ShowMessage::METHOD, LogMessage::METHOD] {
+        dispatcher.register_handler(
+            Direction::Backend,
+            method,
+            DEFAULT_HANDLER_PRIORITY,
+            throttle_message,
+        );
+    }
+}
+
+fn throttle_message(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let method = rpc.get("method").and_then(|m| m.as_str()).unwrap_or("");
+        let params = rpc.get("params").cloned().unwrap_or_default();
+        if ctx.message_throttle().allow(method, &params) {
+            Ok(Verdict::Continue(rpc))
+        } else {
+            Ok(Verdict::Drop)
+        }
+    })
+}
@@ -0,0 +1,183 @@
+//! # 指标模块
+//!
+//! 代理运行时的计数器，通过自定义请求 `codefuse/metrics` 查询。
+//! 记录消息处理任务的并发情况（正在运行的任务、等待许可的消息和等待的时间）、后端的异常响应、
+//! 没有转发的重复和过时诊断、超过速率限制的请求，以及套接字传输上帧压缩的效果。
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::dispatcher::{DEFAULT_HANDLER_PRIORITY, Dispatcher};
+use crate::handlers::{self, HandlerCtx, Verdict};
+use crate::trace::Direction;
+
+/// 查询指标的自定义请求，结果是 [`MetricsSnapshot`]。
+pub const METRICS: &str = "codefuse/metrics";
+
+/// 代理的运行指标。
+#[derive(Debug, Default)]
+pub struct Metrics {
+    handlers_running: AtomicUsize,
+    handlers_waiting: AtomicUsize,
+    handlers_completed: AtomicU64,
+    max_wait_micros: AtomicU64,
+    responses_duplicate: AtomicU64,
+    responses_unknown: AtomicU64,
+    diagnostics_duplicate: AtomicU64,
+    diagnostics_outdated: AtomicU64,
+    requests_rate_limited: AtomicU64,
+    frames_compressed: AtomicU64,
+    compression_input_bytes: AtomicU64,
+    compression_output_bytes: AtomicU64,
+    frames_decompressed: AtomicU64,
+    decompression_input_bytes: AtomicU64,
+    decompression_output_bytes: AtomicU64,
+}
+
+/// 某一时刻的指标。
+///
+/// - `handlers_running`: 正在运行的消息处理任务
+/// - `handlers_waiting`: 已经读出、正在等待处理许可的消息，也就是排队深度
+/// - `handlers_completed`: 已经完成的消息处理任务
+/// - `max_wait_ms`: 消息等待处理许可的最长时间
+/// - `responses_duplicate`: 后端对同一个请求的重复响应
+/// - `responses_unknown`: 后端发来的、没有对应请求的响应
+/// - `diagnostics_duplicate`: 与上次内容相同、没有转发的诊断
+/// - `diagnostics_outdated`: 针对文档旧版本、没有转发的诊断
+/// - `requests_rate_limited`: 超过速率限制、由代理应答的请求
+/// - `frames_compressed`: 压缩后发出的帧
+/// - `compression_input_bytes`/`compression_output_bytes`: 这些帧压缩前和压缩后的消息体长度
+/// - `frames_decompressed`: 收到的压缩帧
+/// - `decompression_input_bytes`/`decompression_output_bytes`: 这些帧解压前和解压后的消息体长度
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsSnapshot {
+    pub handlers_running: usize,
+    pub handlers_waiting: usize,
+    pub handlers_completed: u64,
+    pub max_wait_ms: f64,
+    pub responses_duplicate: u64,
+    pub responses_unknown: u64,
+    pub diagnostics_duplicate: u64,
+    pub diagnostics_outdated: u64,
+    pub requests_rate_limited: u64,
+    pub frames_compressed: u64,
+    pub compression_input_bytes: u64,
+    pub compression_output_bytes: u64,
+    pub frames_decompressed: u64,
+    pub decompression_input_bytes: u64,
+    pub decompression_output_bytes: u64,
+}
+
+impl Metrics {
+    /// 创建所有计数为零的指标。
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 一条消息开始等待处理许可。
+    pub fn handler_queued(&self) {
+        self.handlers_waiting.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 一条消息拿到了处理许可，`waited` 是等待的时间。
+    pub fn handler_started(&self, waited: Duration) {
+        self.handlers_waiting.fetch_sub(1, Ordering::Relaxed);
+        self.handlers_running.fetch_add(1, Ordering::Relaxed);
+        self.max_wait_micros
+            .fetch_max(waited.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// 一个消息处理任务结束。
+    pub fn handler_finished(&self) {
+        self.handlers_running.fetch_sub(1, Ordering::Relaxed);
+        self.handlers_completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 后端发来一个重复的响应。
+    pub fn duplicate_response(&self) {
+        self.responses_duplicate.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 后端发来一个没有对应请求的响应。
+    pub fn unknown_response(&self) {
+        self.responses_unknown.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 后端发来与上次内容相同的诊断。
+    pub fn duplicate_diagnostics(&self) {
+        self.diagnostics_duplicate.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 后端发来针对文档旧版本的诊断。
+    pub fn outdated_diagnostics(&self) {
+        self.diagnostics_outdated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 一个请求超过速率限制，由代理应答。
+    pub fn rate_limited(&self) {
+        self.requests_rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 发出一个压缩帧，消息体从 `input` 字节压缩到 `output` 字节。
+    pub fn frame_compressed(&self, input: usize, output: usize) {
+        self.frames_compressed.fetch_add(1, Ordering::Relaxed);
+        self.compression_input_bytes
+            .fetch_add(input as u64, Ordering::Relaxed);
+        self.compression_output_bytes
+            .fetch_add(output as u64, Ordering::Relaxed);
+    }
+
+    /// 收到一个压缩帧，消息体从 `input` 字节解压到 `output` 字节。
+    pub fn frame_decompressed(&self, input: usize, output: usize) {
+        self.frames_decompressed.fetch_add(1, Ordering::Relaxed);
+        self.decompression_input_bytes
+            .fetch_add(input as u64, Ordering::Relaxed);
+        self.decompression_output_bytes
+            .fetch_add(output as u64, Ordering::Relaxed);
+    }
+
+    /// 读取当前的计数，供 `codefuse/metrics` 和健康检查上报。
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            handlers_running: self.handlers_running.load(Ordering::Relaxed),
+            handlers_waiting: self.handlers_waiting.load(Ordering::Relaxed),
+            handlers_completed: self.handlers_completed.load(Ordering::Relaxed),
+            max_wait_ms: self.max_wait_micros.load(Ordering::Relaxed) as f64 / 1000.0,
+            responses_duplicate: self.responses_duplicate.load(Ordering::Relaxed),
+            responses_unknown: self.responses_unknown.load(Ordering::Relaxed),
+            diagnostics_duplicate: self.diagnostics_duplicate.load(Ordering::Relaxed),
+            diagnostics_outdated: self.diagnostics_outdated.load(Ordering::Relaxed),
+            requests_rate_limited: self.requests_rate_limited.load(Ordering::Relaxed),
+            frames_compressed: self.frames_compressed.load(Ordering::Relaxed),
+            compression_input_bytes: self.compression_input_bytes.load(Ordering::Relaxed),
+            compression_output_bytes: self.compression_output_bytes.load(Ordering::Relaxed),
+            frames_decompressed: self.frames_decompressed.load(Ordering::Relaxed),
+            decompression_input_bytes: self.decompression_input_bytes.load(Ordering::Relaxed),
+            decompression_output_bytes: self.decompression_output_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 注册 `codefuse/metrics` 的处理器，由代理直接应答。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        METRICS,
+        DEFAULT_HANDLER_PRIORITY,
+        answer_metrics,
+    );
+}
+
+/// 以当前的运行指标应答。
+fn answer_metrics(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let snapshot = ctx.metrics().snapshot();
+        let response = handlers::response(&rpc, Ok(json!(snapshot)));
+        Ok(Verdict::Respond(response))
+    })
+}
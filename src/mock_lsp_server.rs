@@ -0,0 +1,296 @@
+//! # 模拟后端模块
+//!
+//! 一个可编排的假 LSP 服务器，供集成测试和 `--backend mock` 使用，不需要真实的 clangd。
+//! 每个方法的响应、延迟和错误以及收到消息后发出的通知（诊断、进度等）都由脚本描述，脚本可以从 JSON 夹具文件加载。
+
+use anyhow::{Context, Result, bail};
+use futures::TryStreamExt;
+use log::debug;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio_util::codec::FramedRead;
+use tower_lsp::lsp_types::notification::{Exit, Notification};
+use tower_lsp::lsp_types::request::{Initialize, Request};
+
+use crate::batch;
+use crate::codec::LspCodec;
+use crate::dispatcher::Dispatcher;
+
+/// 模拟后端收到这条通知时以错误退出，用于测试后端崩溃后的切换和重新连接。
+pub const CRASH_METHOD: &str = "$/mock/crash";
+
+/// 通知参数中的占位符，替换为触发消息的 `textDocument.uri`。
+pub const URI_PLACEHOLDER: &str = "${uri}";
+
+/// 一个方法的预设响应。
+///
+/// - `result`: 成功时的结果，默认为 `null`
+/// - `error`: 设置后返回错误响应，忽略 `result`
+/// - `delay_ms`: 返回响应前等待的时间
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MockResponse {
+    pub result: Value,
+    pub error: Option<MockError>,
+    pub delay_ms: u64,
+}
+
+/// 注入的 JSON-RPC 错误。
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockError {
+    pub code: i64,
+    pub message: String,
+}
+
+/// 收到某个方法的消息后发出的通知，在响应（如果有）之后按顺序发出。
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockNotification {
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    /// 发出这条通知前等待的时间
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+/// 模拟后端的脚本。
+///
+/// 夹具文件示例：
+///
+/// ```json
+/// {
+///   "responses": {
+///     "textDocument/hover": {"result": {"contents": "int x"}, "delay_ms": 20},
+///     "textDocument/definition": {"error": {"code": -32603, "message": "boom"}}
+///   },
+///   "notifications": {
+///     "textDocument/didOpen": [
+///       {"method": "textDocument/publishDiagnostics", "params": {"uri": "${uri}", "diagnostics": []}}
+///     ]
+///   }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MockScript {
+    /// 方法 → 预设响应；没有预设的请求返回 `null`，`initialize` 返回默认的服务器能力
+    pub responses: HashMap<String, MockResponse>,
+    /// 触发的方法 → 随后发出的通知
+    pub notifications: HashMap<String, Vec<MockNotification>>,
+}
+
+impl MockScript {
+    /// 创建空脚本：`initialize` 返回默认的服务器能力，其他请求返回 `null`，不发出通知。
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 从 JSON 夹具文件加载脚本。
+    ///
+    /// # 错误
+    ///
+    /// 如果文件无法读取或格式不正确，返回错误
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("无法读取模拟脚本 {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("模拟脚本格式错误 {}", path.display()))
+    }
+
+    /// 为方法设置成功的响应。
+    pub fn respond(mut self, method: &str, result: Value) -> Self {
+        let response = self.responses.entry(method.to_string()).or_default();
+        response.result = result;
+        response.error = None;
+        self
+    }
+
+    /// 让方法返回错误。
+    pub fn fail(mut self, method: &str, code: i64, message: &str) -> Self {
+        self.responses.entry(method.to_string()).or_default().error = Some(MockError {
+            code,
+            message: message.to_string(),
+        });
+        self
+    }
+
+    /// 设置方法响应前的延迟。
+    pub fn delay(mut self, method: &str, delay: Duration) -> Self {
+        self.responses
+            .entry(method.to_string())
+            .or_default()
+            .delay_ms = delay.as_millis() as u64;
+        self
+    }
+
+    /// 收到 `trigger` 后发出一条通知。
+    pub fn emit(mut self, trigger: &str, method: &str, params: Value) -> Self {
+        self.notifications
+            .entry(trigger.to_string())
+            .or_default()
+            .push(MockNotification {
+                method: method.to_string(),
+                params,
+                delay_ms: 0,
+            });
+        self
+    }
+
+    /// 请求的响应消息。
+    fn response(&self, method: &str, id: &Value) -> (Value, Duration) {
+        let Some(response) = self.responses.get(method) else {
+            let result = if method == Initialize::METHOD {
+                default_initialize_result()
+            } else {
+                Value::Null
+            };
+            return (
+                json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                Duration::ZERO,
+            );
+        };
+        let message = match &response.error {
+            Some(error) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {"code": error.code, "message": error.message},
+            }),
+            None => json!({"jsonrpc": "2.0", "id": id, "result": response.result}),
+        };
+        (message, Duration::from_millis(response.delay_ms))
+    }
+}
+
+/// 没有预设时 `initialize` 返回的结果。
+fn default_initialize_result() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": 2,
+            "hoverProvider": true,
+            "completionProvider": {},
+            "definitionProvider": true,
+            "referencesProvider": true,
+            "documentSymbolProvider": true,
+            "workspaceSymbolProvider": true,
+        },
+        "serverInfo": {"name": "mock-lsp-server"},
+    })
+}
+
+/// 模拟的 LSP 服务器。
+pub struct MockLspServer {
+    script: Arc<MockScript>,
+}
+
+impl MockLspServer {
+    /// 创建按 `script` 应答的服务器。
+    pub fn new(script: MockScript) -> Self {
+        Self {
+            script: Arc::new(script),
+        }
+    }
+
+    /// 从 `reader` 读取消息，把响应和通知写入 `writer`，直到 EOF 或收到 `exit`。
+    ///
+    /// 每条消息在单独的任务中处理，延迟较长的响应不会阻塞后面的消息。
+    ///
+    /// # 错误
+    ///
+    /// 如果读取、解析或写入消息失败，或者收到 [`CRASH_METHOD`]，返回错误
+    pub async fn serve<R, W>(self, reader: R, mut writer: W) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+        let write_task = tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                writer.write_all(message.as_bytes()).await?;
+                writer.flush().await?;
+            }
+            Ok::<_, std::io::Error>(())
+        });
+
+        let mut reader = FramedRead::new(reader, LspCodec::default());
+        'read: while let Some(json_body) = reader.try_next().await? {
+            for rpc in batch::split_batch(json_body) {
+                let Some(method) = rpc.get("method").and_then(|m| m.as_str()) else {
+                    continue; // 客户端对模拟后端请求的响应
+                };
+                if method == Exit::METHOD {
+                    break 'read;
+                }
+                if method == CRASH_METHOD {
+                    bail!("模拟后端收到 {}，模拟崩溃", CRASH_METHOD);
+                }
+                debug!("模拟后端收到 {}", method);
+                self.handle(method.to_string(), rpc, tx.clone());
+            }
+        }
+
+        drop(tx);
+        write_task.await??;
+        Ok(())
+    }
+
+    /// 按脚本应答一条消息并发出通知。
+    fn handle(&self, method: String, rpc: Value, tx: UnboundedSender<String>) {
+        let script = Arc::clone(&self.script);
+        tokio::spawn(async move {
+            if let Some(id) = rpc.get("id") {
+                let (response, delay) = script.response(&method, id);
+                tokio::time::sleep(delay).await;
+                send(&tx, &response);
+            }
+
+            let uri = rpc.pointer("/params/textDocument/uri").cloned();
+            for notification in script.notifications.get(&method).into_iter().flatten() {
+                tokio::time::sleep(Duration::from_millis(notification.delay_ms)).await;
+                let mut params = notification.params.clone();
+                if let Some(uri) = &uri {
+                    substitute_uri(&mut params, uri);
+                }
+                send(
+                    &tx,
+                    &json!({"jsonrpc": "2.0", "method": notification.method, "params": params}),
+                );
+            }
+        });
+    }
+}
+
+fn send(tx: &UnboundedSender<String>, message: &Value) {
+    if let Ok(message) = Dispatcher::format_lsp_message(message) {
+        let _ = tx.send(message);
+    }
+}
+
+/// 把参数中的 `${uri}` 替换为触发消息的文档 URI。
+fn substitute_uri(value: &mut Value, uri: &Value) {
+    match value {
+        Value::String(s) if s == URI_PLACEHOLDER => *value = uri.clone(),
+        Value::Array(items) => items.iter_mut().for_each(|item| substitute_uri(item, uri)),
+        Value::Object(map) => map.values_mut().for_each(|item| substitute_uri(item, uri)),
+        _ => {}
+    }
+}
+
+/// 在标准输入输出上运行模拟后端（`lsp-proxy mock-server [<fixture>]`）。
+///
+/// # 错误
+///
+/// 如果夹具文件无法加载或者读写失败，返回错误
+pub async fn run_stdio(fixture: Option<&Path>) -> Result<()> {
+    let script = match fixture {
+        Some(path) => MockScript::load(path)?,
+        None => MockScript::new(),
+    };
+    MockLspServer::new(script)
+        .serve(tokio::io::stdin(), tokio::io::stdout())
+        .await
+}
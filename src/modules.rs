@@ -0,0 +1,168 @@
+//! # 模块模块
+//!
+//! C++20 模块的支持：识别模块接口单元，打开时把配置的编译参数交给 clangd，在编辑器中显示模块的构建进度，
+//! 并把与模块有关的诊断归入单独的 `modules` 来源。
+
+use dashmap::DashMap;
+use regex::Regex;
+use serde_json::{Value, json};
+use std::path::Path;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tower_lsp::lsp_types::Url;
+
+use crate::compile_flags::{self, FileCommand};
+use crate::config::ModuleConfig;
+
+/// 启用 clangd 模块支持的启动参数。
+pub const BACKEND_FLAG: &str = "--experimental-modules-support";
+
+/// 与模块有关的诊断的来源名称。
+pub const SOURCE: &str = "modules";
+
+/// 模块接口单元常用的扩展名。
+pub const INTERFACE_EXTENSIONS: &[&str] = &["cppm", "ccm", "cxxm", "c++m", "ixx", "mpp"];
+
+static EXPORT_MODULE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^\s*export\s+module\s+([\w.:]+)\s*;").unwrap());
+
+static MODULE_DIAGNOSTIC: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\bmodules?\b|\.pcm\b|precompiled file|module interface unit").unwrap()
+});
+
+/// 接口单元导出的模块名称，不是接口单元时返回 `None`。
+pub fn module_name(text: &str) -> Option<&str> {
+    EXPORT_MODULE
+        .captures(text)
+        .and_then(|captures| captures.get(1))
+        .map(|name| name.as_str())
+}
+
+/// 文件是否模块接口单元：扩展名是接口单元常用的扩展名，或者内容中有 `export module` 声明。
+pub fn is_interface_unit(path: &Path, text: &str) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            INTERFACE_EXTENSIONS
+                .iter()
+                .any(|candidate| candidate.eq_ignore_ascii_case(extension))
+        })
+        || module_name(text).is_some()
+}
+
+/// 诊断是否与模块有关，例如找不到模块、模块构建失败或者缺少 `export module` 声明。
+pub fn is_module_diagnostic(diagnostic: &Value) -> bool {
+    diagnostic
+        .get("message")
+        .and_then(|message| message.as_str())
+        .is_some_and(|message| MODULE_DIAGNOSTIC.is_match(message))
+}
+
+/// 构建中的接口单元：进度的 token，以及是否已经向编辑器报告开始。
+struct Build {
+    token: String,
+    begun: bool,
+}
+
+/// 模块接口单元的编译参数、构建进度和诊断分组。
+#[derive(Default)]
+pub struct Modules {
+    enabled: bool,
+    flags: Vec<String>,
+    compiler: String,
+    building: DashMap<Url, Build>,
+    next_token: AtomicU64,
+}
+
+impl Modules {
+    pub fn new(config: &ModuleConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            flags: config.flags.clone(),
+            compiler: config.compiler.clone(),
+            building: DashMap::new(),
+            next_token: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 为接口单元生成 `workspace/didChangeConfiguration` 通知，通过 `compilationDatabaseChanges` 注入编译命令。
+    ///
+    /// # 返回
+    ///
+    /// 交给后端的通知；没有配置编译参数或者文档不是本地文件时返回 `None`
+    pub fn compile_command(&self, uri: &Url) -> Option<Value> {
+        if self.flags.is_empty() {
+            return None;
+        }
+        let path = uri.to_file_path().ok()?;
+        let mut arguments = vec![self.compiler.clone()];
+        arguments.extend(self.flags.iter().cloned());
+        arguments.extend(["-x".to_string(), "c++-module".to_string()]);
+        arguments.push(path.display().to_string());
+        let command = FileCommand {
+            directory: path.parent()?.to_path_buf(),
+            arguments,
+        };
+        Some(compile_flags::database_change(&path, &command))
+    }
+
+    /// 接口单元开始构建，返回新的进度 token。
+    pub fn start_build(&self, uri: &Url) -> String {
+        let token = format!(
+            "codefuse/modules/{}",
+            self.next_token.fetch_add(1, Ordering::Relaxed)
+        );
+        self.building.insert(
+            uri.clone(),
+            Build {
+                token: token.clone(),
+                begun: false,
+            },
+        );
+        token
+    }
+
+    /// 编辑器创建进度之后，如果构建还没有结束就标记为已经开始。
+    ///
+    /// # 返回
+    ///
+    /// 是否需要向编辑器报告开始
+    pub fn begin_build(&self, uri: &Url, token: &str) -> bool {
+        match self.building.get_mut(uri) {
+            Some(mut build) if build.token == token => {
+                build.begun = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// 后端发布了接口单元的诊断，构建结束。
+    ///
+    /// # 返回
+    ///
+    /// 已经向编辑器报告开始、需要报告结束的进度 token
+    pub fn finish_build(&self, uri: &Url) -> Option<String> {
+        let (_, build) = self.building.remove(uri)?;
+        build.begun.then_some(build.token)
+    }
+
+    /// 从 `publishDiagnostics` 通知中取出与模块有关的诊断，标上 `modules` 来源。
+    pub fn take_diagnostics(&self, rpc: &mut Value) -> Vec<Value> {
+        let Some(Value::Array(diagnostics)) = rpc.pointer_mut("/params/diagnostics") else {
+            return Vec::new();
+        };
+        let (mut modules, others): (Vec<Value>, Vec<Value>) = std::mem::take(diagnostics)
+            .into_iter()
+            .partition(is_module_diagnostic);
+        *diagnostics = others;
+        for diagnostic in &mut modules {
+            diagnostic["source"] = json!(SOURCE);
+        }
+        modules
+    }
+}
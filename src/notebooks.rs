@@ -0,0 +1,305 @@
+//! # 笔记本模块
+//!
+//! clangd 不理解 LSP 3.17 的笔记本文档。代理把一个笔记本的代码单元按顺序拼成一个虚拟的 C++ 文档交给后端，
+//! 单元的增删、重排和编辑都变成虚拟文档的更新，位置的换算由 [`crate::virtual_documents`] 完成。
+
+use dashmap::DashMap;
+use log::warn;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use tower_lsp::lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    Notification,
+};
+use tower_lsp::lsp_types::{TextDocumentContentChangeEvent, Url};
+
+use crate::config::NotebookConfig;
+use crate::document_store::apply_content_change;
+use crate::virtual_documents::{Layout, Layouts, Slot};
+
+pub const DID_OPEN: &str = "notebookDocument/didOpen";
+pub const DID_CHANGE: &str = "notebookDocument/didChange";
+pub const DID_SAVE: &str = "notebookDocument/didSave";
+pub const DID_CLOSE: &str = "notebookDocument/didClose";
+
+/// 笔记本的同步通知。
+pub const SYNC_METHODS: &[&str] = &[DID_OPEN, DID_CHANGE, DID_SAVE, DID_CLOSE];
+
+/// 代码单元的 `NotebookCellKind`，其他单元（Markdown）不交给后端。
+const CODE_CELL: u64 = 2;
+
+/// 虚拟文档的语言。
+const LANGUAGE_ID: &str = "cpp";
+
+/// initialize 响应中声明的 `notebookDocumentSync`：同步单元语言是 `languages` 之一的笔记本。
+pub fn sync_options(languages: &[String]) -> Value {
+    let cells: Vec<Value> = languages
+        .iter()
+        .map(|language| json!({"language": language}))
+        .collect();
+    json!({"notebookSelector": [{"cells": cells}]})
+}
+
+/// 笔记本对应的虚拟文档的 URI：在笔记本的 URI 后加上 `.cpp`。
+pub fn virtual_uri(notebook: &Url) -> Option<Url> {
+    Url::parse(&format!("{}.cpp", notebook)).ok()
+}
+
+struct Cell {
+    uri: Url,
+    kind: u64,
+    text: String,
+}
+
+struct Notebook {
+    version: i64,
+    virtual_uri: Url,
+    cells: Vec<Cell>,
+}
+
+impl Notebook {
+    fn code_cells(&self) -> impl Iterator<Item = &Cell> {
+        self.cells.iter().filter(|cell| cell.kind == CODE_CELL)
+    }
+
+    /// 虚拟文档的内容：代码单元依次相接，每个单元以换行结束。
+    fn text(&self) -> String {
+        let mut text = String::new();
+        for cell in self.code_cells() {
+            text.push_str(&cell.text);
+            if !cell.text.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+        text
+    }
+
+    fn layout(&self) -> Layout {
+        let mut line = 0;
+        let slots = self
+            .code_cells()
+            .map(|cell| {
+                let lines =
+                    cell.text.matches('\n').count() as u32 + u32::from(!cell.text.ends_with('\n'));
+                let slot = Slot {
+                    uri: cell.uri.clone(),
+                    start: line,
+                    lines,
+                };
+                line += lines;
+                slot
+            })
+            .collect();
+        Layout { slots }
+    }
+}
+
+/// 打开的笔记本。
+pub struct Notebooks {
+    enabled: bool,
+    notebooks: DashMap<Url, Notebook>,
+    /// 单元的 URI 到所在笔记本的 URI
+    cells: DashMap<Url, Url>,
+}
+
+impl Default for Notebooks {
+    fn default() -> Self {
+        Self::new(&NotebookConfig::default())
+    }
+}
+
+impl Notebooks {
+    pub fn new(config: &NotebookConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            notebooks: DashMap::new(),
+            cells: DashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 代码单元所在的虚拟文档，以及单元在虚拟文档中的起始行；不是打开的笔记本中的代码单元时返回 `None`。
+    pub fn locate(&self, cell: &Url) -> Option<(Url, u32)> {
+        let owner = self.cells.get(cell)?;
+        let notebook = self.notebooks.get(owner.value())?;
+        let start = notebook.layout().slot(cell)?.start;
+        Some((notebook.virtual_uri.clone(), start))
+    }
+
+    /// 把打开的笔记本的布局加入 `layouts`。
+    pub fn add_layouts(&self, layouts: &mut Layouts) {
+        for notebook in self.notebooks.iter() {
+            layouts.insert(notebook.virtual_uri.to_string(), notebook.layout());
+        }
+    }
+
+    /// 把笔记本的同步通知换成虚拟文档的文本同步通知。
+    ///
+    /// # 返回
+    ///
+    /// 交给后端的 `textDocument/*` 通知；参数无法解析或者笔记本没有打开时返回 `None`
+    pub fn to_text_document(&self, method: &str, params: &Value) -> Option<Value> {
+        let uri = params
+            .pointer("/notebookDocument/uri")
+            .and_then(|uri| uri.as_str())
+            .and_then(|uri| Url::parse(uri).ok())?;
+        let (method, params) = match method {
+            DID_OPEN => (DidOpenTextDocument::METHOD, self.open(uri, params)?),
+            DID_CHANGE => (DidChangeTextDocument::METHOD, self.change(&uri, params)?),
+            DID_SAVE => {
+                let notebook = self.notebooks.get(&uri)?;
+                let params = json!({"textDocument": {"uri": notebook.virtual_uri}});
+                (DidSaveTextDocument::METHOD, params)
+            }
+            DID_CLOSE => {
+                let (_, notebook) = self.notebooks.remove(&uri)?;
+                self.cells.retain(|_, owner| owner != &uri);
+                let params = json!({"textDocument": {"uri": notebook.virtual_uri}});
+                (DidCloseTextDocument::METHOD, params)
+            }
+            _ => return None,
+        };
+        Some(json!({"jsonrpc": "2.0", "method": method, "params": params}))
+    }
+
+    fn open(&self, uri: Url, params: &Value) -> Option<Value> {
+        let mut texts = cell_texts(params.get("cellTextDocuments"));
+        let cells: Vec<Cell> = params
+            .pointer("/notebookDocument/cells")?
+            .as_array()?
+            .iter()
+            .filter_map(|cell| parse_cell(cell, &mut texts))
+            .collect();
+        for cell in &cells {
+            self.cells.insert(cell.uri.clone(), uri.clone());
+        }
+        let notebook = Notebook {
+            version: params
+                .pointer("/notebookDocument/version")
+                .and_then(Value::as_i64)
+                .unwrap_or(0),
+            virtual_uri: virtual_uri(&uri)?,
+            cells,
+        };
+        let params = json!({"textDocument": {
+            "uri": notebook.virtual_uri,
+            "languageId": LANGUAGE_ID,
+            "version": notebook.version,
+            "text": notebook.text(),
+        }});
+        self.notebooks.insert(uri, notebook);
+        Some(params)
+    }
+
+    fn change(&self, uri: &Url, params: &Value) -> Option<Value> {
+        let Some(mut notebook) = self.notebooks.get_mut(uri) else {
+            warn!("收到未打开笔记本的 didChange: {}", uri);
+            return None;
+        };
+        if let Some(version) = params
+            .pointer("/notebookDocument/version")
+            .and_then(Value::as_i64)
+        {
+            notebook.version = version;
+        }
+        let cells = params.pointer("/change/cells");
+
+        if let Some(structure) = cells.and_then(|cells| cells.get("structure")) {
+            let mut texts = cell_texts(structure.get("didOpen"));
+            if let Some(array) = structure.get("array") {
+                let len = notebook.cells.len();
+                let start =
+                    (array.get("start").and_then(Value::as_u64).unwrap_or(0) as usize).min(len);
+                let count = (array
+                    .get("deleteCount")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(0) as usize)
+                    .min(len - start);
+                // 移动的单元先被删除再插入，没有重新打开，沿用原来的内容
+                let removed: Vec<Cell> = notebook.cells.drain(start..start + count).collect();
+                for cell in removed {
+                    texts.entry(cell.uri).or_insert(cell.text);
+                }
+                let inserted: Vec<Cell> = array
+                    .get("cells")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|cell| parse_cell(cell, &mut texts))
+                    .collect();
+                for cell in &inserted {
+                    self.cells.insert(cell.uri.clone(), uri.clone());
+                }
+                notebook.cells.splice(start..start, inserted);
+            }
+            for closed in document_uris(structure.get("didClose")) {
+                if !notebook.cells.iter().any(|cell| cell.uri == closed) {
+                    self.cells.remove(&closed);
+                }
+            }
+        }
+
+        for data in array_items(cells.and_then(|cells| cells.get("data"))) {
+            if let Some(changed) = parse_cell(data, &mut HashMap::new())
+                && let Some(cell) = notebook.cells.iter_mut().find(|c| c.uri == changed.uri)
+            {
+                cell.kind = changed.kind;
+            }
+        }
+
+        for content in array_items(cells.and_then(|cells| cells.get("textContent"))) {
+            let Some(cell) = content
+                .pointer("/document/uri")
+                .and_then(|uri| uri.as_str())
+                .and_then(|uri| Url::parse(uri).ok())
+                .and_then(|uri| notebook.cells.iter_mut().find(|cell| cell.uri == uri))
+            else {
+                continue;
+            };
+            for change in array_items(content.get("changes")) {
+                match serde_json::from_value::<TextDocumentContentChangeEvent>(change.clone()) {
+                    Ok(change) => apply_content_change(&mut cell.text, &change),
+                    Err(e) => warn!("无法解析单元 {} 的修改: {}", cell.uri, e),
+                }
+            }
+        }
+
+        Some(json!({
+            "textDocument": {"uri": notebook.virtual_uri, "version": notebook.version},
+            "contentChanges": [{"text": notebook.text()}],
+        }))
+    }
+}
+
+fn array_items(value: Option<&Value>) -> impl Iterator<Item = &Value> {
+    value.and_then(Value::as_array).into_iter().flatten()
+}
+
+fn document_uris(value: Option<&Value>) -> impl Iterator<Item = Url> + '_ {
+    array_items(value).filter_map(|doc| Url::parse(doc.get("uri")?.as_str()?).ok())
+}
+
+/// `TextDocumentItem` 列表中各单元的内容。
+fn cell_texts(items: Option<&Value>) -> HashMap<Url, String> {
+    array_items(items)
+        .filter_map(|item| {
+            let uri = Url::parse(item.get("uri")?.as_str()?).ok()?;
+            Some((uri, item.get("text")?.as_str()?.to_string()))
+        })
+        .collect()
+}
+
+fn parse_cell(cell: &Value, texts: &mut HashMap<Url, String>) -> Option<Cell> {
+    let uri = Url::parse(cell.get("document")?.as_str()?).ok()?;
+    Some(Cell {
+        kind: cell
+            .get("kind")
+            .and_then(Value::as_u64)
+            .unwrap_or(CODE_CELL),
+        text: texts.remove(&uri).unwrap_or_default(),
+        uri,
+    })
+}
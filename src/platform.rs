@@ -0,0 +1,338 @@
+//! # 平台模块
+//!
+//! 启动后端时与操作系统相关的细节：按 `PATH` 查找后端程序（Windows 上补全 `PATHEXT` 中的扩展名），
+//! 后端进程的资源限制，暂停空闲的后端，以及 Windows 上让后端随代理一起结束的作业对象。
+
+use anyhow::{Result, bail};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use crate::config::ResourceLimits;
+
+/// 没有设置 `PATHEXT` 时 Windows 使用的可执行文件扩展名。
+pub const DEFAULT_PATHEXT: &str = ".COM;.EXE;.BAT;.CMD";
+
+/// 可以配置的 CPU 数量，与 Linux 的 `cpu_set_t` 的大小相同。
+pub const MAX_CPUS: usize = 1024;
+
+/// 检查 `[backend.limits]`：nice 值在 -20 到 19 之间，CPU 编号小于 [`MAX_CPUS`]。
+///
+/// # 错误
+///
+/// 如果 nice 值或 CPU 编号超出范围，返回错误
+pub fn validate_limits(limits: &ResourceLimits) -> Result<()> {
+    if let Some(nice) = limits.nice
+        && !(-20..=19).contains(&nice)
+    {
+        bail!("[backend.limits] nice 必须在 -20 到 19 之间: {}", nice);
+    }
+    if let Some(cpu) = limits.cpus.iter().find(|&&cpu| cpu >= MAX_CPUS) {
+        bail!(
+            "[backend.limits] cpus 中的 CPU 编号必须小于 {}: {}",
+            MAX_CPUS,
+            cpu
+        );
+    }
+    Ok(())
+}
+
+/// 查找后端程序。
+///
+/// Windows 上 `Command` 只会为 `PATH` 中查找的裸程序名补全 `.exe`，配置成 `bin/clangd`
+/// 这样的相对路径时找不到 `bin/clangd.exe`；这里对两种写法都按 `PATHEXT` 补全扩展名。
+///
+/// # 参数
+///
+/// * `program` - 配置的程序名或路径
+///
+/// # 返回
+///
+/// 找到时返回程序的路径，否则返回 `None`，由调用方按原样启动并报告错误
+pub fn resolve_program(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH");
+    let extensions = if cfg!(windows) {
+        std::env::var("PATHEXT").unwrap_or_else(|_| DEFAULT_PATHEXT.to_string())
+    } else {
+        String::new()
+    };
+    resolve_program_in(program, path.as_deref(), &extensions, |path| path.is_file())
+}
+
+/// [`resolve_program`] 的实现，搜索路径、扩展名和文件检查都由参数给出，不依赖当前平台。
+///
+/// # 参数
+///
+/// * `program` - 配置的程序名或路径
+/// * `path` - `PATH` 环境变量
+/// * `extensions` - 分号分隔的扩展名列表（`PATHEXT` 的格式），为空时不补全扩展名
+/// * `exists` - 判断候选路径是否是存在的文件
+pub fn resolve_program_in(
+    program: &str,
+    path: Option<&OsStr>,
+    extensions: &str,
+    exists: impl Fn(&Path) -> bool,
+) -> Option<PathBuf> {
+    let extensions: Vec<&str> = extensions
+        .split(';')
+        .map(str::trim)
+        .filter(|ext| !ext.is_empty())
+        .collect();
+    let candidates = |base: PathBuf| {
+        // 已经带有可执行扩展名的程序不再补全
+        let has_extension = base.extension().and_then(OsStr::to_str).is_some_and(|ext| {
+            extensions
+                .iter()
+                .any(|known| known.trim_start_matches('.').eq_ignore_ascii_case(ext))
+        });
+        let mut candidates = vec![base.clone()];
+        if !has_extension {
+            candidates.extend(extensions.iter().map(|ext| {
+                let mut name = base.clone().into_os_string();
+                name.push(ext.to_ascii_lowercase());
+                PathBuf::from(name)
+            }));
+        }
+        candidates
+    };
+
+    let program_path = Path::new(program);
+    if program_path.components().count() > 1 || program_path.is_absolute() {
+        return candidates(program_path.to_path_buf())
+            .into_iter()
+            .find(|candidate| exists(candidate));
+    }
+    std::env::split_paths(path?)
+        .flat_map(|dir| candidates(dir.join(program)))
+        .find(|candidate| exists(candidate))
+}
+
+/// 让启动的进程在 `exec` 之前设置优先级、CPU 亲和性和内存上限。
+///
+/// 某项设置失败时（例如普通用户不能使用负的 nice 值）在后端的标准错误中说明，后端照常启动。
+#[cfg(unix)]
+pub fn apply_limits(command: &mut tokio::process::Command, limits: &ResourceLimits) {
+    if limits.is_empty() {
+        return;
+    }
+    let nice = limits.nice;
+    let memory = limits
+        .memory_mb
+        .map(|mb| mb.saturating_mul(1024 * 1024) as libc::rlim_t);
+    // CPU 集合在 fork 之前准备好，子进程中只调用系统调用
+    #[cfg(target_os = "linux")]
+    let cpus = (!limits.cpus.is_empty()).then(|| {
+        // SAFETY: cpu_set_t 是普通的位图，全零是合法的空集合
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        for &cpu in &limits.cpus {
+            // SAFETY: 加载配置时已经检查 CPU 编号小于 cpu_set_t 能表示的数量
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+        set
+    });
+    #[cfg(not(target_os = "linux"))]
+    if !limits.cpus.is_empty() {
+        log::warn!("当前平台不支持设置后端的 CPU 亲和性，忽略 cpus 配置");
+    }
+
+    // SAFETY: 闭包在 fork 之后、exec 之前执行，只调用异步信号安全的系统调用，不分配内存
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(nice) = nice
+                && libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0
+            {
+                report_limit_failure(b"lsp-proxy: failed to set backend nice value\n");
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(set) = &cpus
+                && libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), set) != 0
+            {
+                report_limit_failure(b"lsp-proxy: failed to set backend CPU affinity\n");
+            }
+            if let Some(bytes) = memory {
+                let limit = libc::rlimit {
+                    rlim_cur: bytes,
+                    rlim_max: bytes,
+                };
+                if libc::setrlimit(libc::RLIMIT_DATA, &limit) != 0 {
+                    report_limit_failure(b"lsp-proxy: failed to set backend memory limit\n");
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// 在 `pre_exec` 中把设置失败的说明写到标准错误，代理把后端的标准错误记录到日志中。
+///
+/// 只调用 `write`，可以在 fork 之后使用。
+#[cfg(unix)]
+fn report_limit_failure(message: &[u8]) {
+    // SAFETY: write 是异步信号安全的，缓冲区在调用期间有效
+    unsafe { libc::write(libc::STDERR_FILENO, message.as_ptr().cast(), message.len()) };
+}
+
+/// 暂停（SIGSTOP）或继续（SIGCONT）进程。
+///
+/// # 错误
+///
+/// 如果无法向进程发送信号，返回错误
+#[cfg(unix)]
+pub fn set_process_stopped(pid: u32, stopped: bool) -> std::io::Result<()> {
+    let signal = if stopped {
+        libc::SIGSTOP
+    } else {
+        libc::SIGCONT
+    };
+    // SAFETY: kill 只向指定的进程发送信号
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// 文件描述符上的管道或套接字的另一端是否已经关闭。
+///
+/// 只用 `poll` 查看描述符的状态，不读写数据：写入端的读者都已关闭时管道报告 `POLLERR`，
+/// 对端关闭的套接字报告 `POLLHUP`。普通文件和终端总是返回 `false`。
+#[cfg(unix)]
+pub fn peer_closed(fd: std::os::fd::RawFd) -> bool {
+    let mut poll = libc::pollfd {
+        fd,
+        events: 0,
+        revents: 0,
+    };
+    // SAFETY: 只传入一个有效的 pollfd，超时为 0，不会阻塞
+    let ready = unsafe { libc::poll(&mut poll, 1, 0) };
+    ready > 0 && poll.revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0
+}
+
+/// 进程是否还在运行。
+///
+/// 没有权限向进程发送信号时同样认为它在运行。
+#[cfg(unix)]
+pub fn process_alive(pid: u32) -> bool {
+    // SAFETY: 信号 0 只检查进程是否存在，不发送信号
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+        return true;
+    }
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// 进程是否还在运行。
+///
+/// 没有权限打开进程时同样认为它在运行。
+#[cfg(windows)]
+pub fn process_alive(pid: u32) -> bool {
+    use windows_sys::Win32::Foundation::{
+        CloseHandle, ERROR_INVALID_PARAMETER, GetLastError, STILL_ACTIVE,
+    };
+    use windows_sys::Win32::System::Threading::{
+        GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    // SAFETY: 打开的句柄在返回之前关闭
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process.is_null() {
+            return GetLastError() != ERROR_INVALID_PARAMETER;
+        }
+        let mut code = 0;
+        let ok = GetExitCodeProcess(process, &mut code);
+        CloseHandle(process);
+        ok == 0 || code == STILL_ACTIVE as u32
+    }
+}
+
+/// Windows 作业对象：代理持有一个设置了 `JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE` 的作业，
+/// 所有后端都加入这个作业。代理以任何方式退出时句柄被系统关闭，作业中的进程随之结束。
+///
+/// 资源限制也通过这个作业设置：作业在第一个后端启动时创建，所有后端使用相同的限制。
+#[cfg(windows)]
+pub mod job {
+    use std::io;
+    use std::sync::OnceLock;
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_AFFINITY,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE, JOB_OBJECT_LIMIT_PRIORITY_CLASS,
+        JOB_OBJECT_LIMIT_PROCESS_MEMORY, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JobObjectExtendedLimitInformation, SetInformationJobObject,
+    };
+    use windows_sys::Win32::System::Threading::{BELOW_NORMAL_PRIORITY_CLASS, IDLE_PRIORITY_CLASS};
+
+    use crate::config::ResourceLimits;
+
+    struct Job(HANDLE);
+
+    // SAFETY: 作业句柄在进程的整个生命周期内有效，可以在线程之间共享
+    unsafe impl Send for Job {}
+    unsafe impl Sync for Job {}
+
+    static JOB: OnceLock<Option<Job>> = OnceLock::new();
+
+    fn create(limits: &ResourceLimits) -> Option<Job> {
+        // SAFETY: 参数都是有效的指针或空指针，失败时关闭已经创建的句柄
+        unsafe {
+            let handle = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+            if handle.is_null() {
+                return None;
+            }
+            let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+            info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            match limits.nice {
+                Some(nice) if nice >= 10 => {
+                    info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PRIORITY_CLASS;
+                    info.BasicLimitInformation.PriorityClass = IDLE_PRIORITY_CLASS;
+                }
+                Some(nice) if nice > 0 => {
+                    info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PRIORITY_CLASS;
+                    info.BasicLimitInformation.PriorityClass = BELOW_NORMAL_PRIORITY_CLASS;
+                }
+                _ => {}
+            }
+            if !limits.cpus.is_empty() {
+                info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_AFFINITY;
+                info.BasicLimitInformation.Affinity = limits
+                    .cpus
+                    .iter()
+                    .filter(|&&cpu| cpu < usize::BITS as usize)
+                    .fold(0, |mask, &cpu| mask | (1 << cpu));
+            }
+            if let Some(mb) = limits.memory_mb {
+                info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PROCESS_MEMORY;
+                info.ProcessMemoryLimit = (mb as usize).saturating_mul(1024 * 1024);
+            }
+            let ok = SetInformationJobObject(
+                handle,
+                JobObjectExtendedLimitInformation,
+                &info as *const _ as *const std::ffi::c_void,
+                std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            );
+            if ok == 0 {
+                CloseHandle(handle);
+                return None;
+            }
+            Some(Job(handle))
+        }
+    }
+
+    /// 把后端进程加入代理的作业。
+    ///
+    /// # 错误
+    ///
+    /// 无法创建作业或加入作业时返回错误
+    pub fn assign(child: &tokio::process::Child, limits: &ResourceLimits) -> io::Result<()> {
+        let Some(job) = JOB.get_or_init(|| create(limits)) else {
+            return Err(io::Error::other("无法创建作业对象"));
+        };
+        let Some(process) = child.raw_handle() else {
+            // 进程已经退出
+            return Ok(());
+        };
+        // SAFETY: 两个句柄都有效
+        if unsafe { AssignProcessToJobObject(job.0, process as HANDLE) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+//! # 预取模块
+//!
+//! 用户在某个位置请求悬停、跳转定义或文档高亮后，代理在空闲时为附近的标识符预先请求悬停和定义，
+//! 结果放入缓存。用户移动到这些标识符时直接由缓存应答，隐藏后端的延迟。
+
+use dashmap::DashMap;
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::Semaphore;
+use tower_lsp::lsp_types::request::{
+    DocumentHighlightRequest, GotoDefinition, HoverRequest, Request,
+};
+use tower_lsp::lsp_types::{
+    DidChangeTextDocumentParams, DidSaveTextDocumentParams, Position, Range,
+    TextDocumentPositionParams, Url,
+};
+
+use crate::config::PrefetchConfig;
+use crate::document_observer::DocumentObserver;
+use crate::document_store::DocumentStore;
+
+/// 结果会被预取和缓存的请求。
+pub const PREFETCH_METHODS: &[&str] = &[HoverRequest::METHOD, GotoDefinition::METHOD];
+
+/// 能说明光标位置、触发预取的请求。
+pub const TRIGGER_METHODS: &[&str] = &[
+    HoverRequest::METHOD,
+    GotoDefinition::METHOD,
+    DocumentHighlightRequest::METHOD,
+];
+
+/// 缓存的结果数量上限，超过时丢弃最久没有使用的结果。
+pub const MAX_CACHED_RESULTS: usize = 512;
+
+/// 不值得预取的 C/C++ 关键字。
+#[rustfmt::skip]
+const KEYWORDS: &[&str] = &[
+    "auto", "bool", "break", "case", "char", "class", "const", "constexpr", "continue",
+    "default", "delete", "do", "double", "else", "enum", "false", "float", "for", "if",
+    "inline", "int", "long", "namespace", "new", "nullptr", "private", "protected", "public",
+    "return", "short", "signed", "sizeof", "static", "struct", "switch", "template", "this",
+    "true", "typedef", "typename", "union", "unsigned", "using", "virtual", "void", "while",
+];
+
+/// 缓存键：请求方法和文档中标识符的范围，光标落在标识符中的任何位置都命中同一个结果。
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    method: String,
+    uri: Url,
+    line: u32,
+    start: u32,
+    end: u32,
+}
+
+impl CacheKey {
+    /// 为 `uri` 中 `identifier` 范围内的标识符创建 `method` 请求的缓存键。
+    pub fn new(method: &str, uri: Url, identifier: Range) -> Self {
+        Self {
+            method: method.to_string(),
+            uri,
+            line: identifier.start.line,
+            start: identifier.start.character,
+            end: identifier.end.character,
+        }
+    }
+}
+
+/// 缓存的结果和最近一次使用的时刻（逻辑时钟）。
+struct Cached {
+    result: Value,
+    used: AtomicU64,
+}
+
+/// 预取的结果缓存和调度状态。
+///
+/// 每次用户发起新的触发请求或者编辑文档都会开始新的一代，上一代还没完成的预取随之放弃，
+/// 文档编辑后整个缓存失效。
+pub struct Prefetcher {
+    config: RwLock<PrefetchConfig>,
+    cache: DashMap<CacheKey, Cached>,
+    /// 逻辑时钟，每次读写缓存加一
+    clock: AtomicU64,
+    generation: AtomicU64,
+    permits: RwLock<Arc<Semaphore>>,
+}
+
+impl Prefetcher {
+    /// 按 `[prefetch]` 配置创建，缓存为空。
+    pub fn new(config: PrefetchConfig) -> Self {
+        Self {
+            permits: RwLock::new(Arc::new(Semaphore::new(config.max_concurrent.max(1)))),
+            config: RwLock::new(config),
+            cache: DashMap::new(),
+            clock: AtomicU64::new(0),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// 替换预取的设置。正在进行的预取按原来的并发数量完成，关闭预取时清空缓存。
+    pub fn set_config(&self, config: PrefetchConfig) {
+        *self.permits.write().unwrap() = Arc::new(Semaphore::new(config.max_concurrent.max(1)));
+        if !config.enabled {
+            self.cache.clear();
+        }
+        *self.config.write().unwrap() = config;
+    }
+
+    /// 是否启用了预取。
+    pub fn is_enabled(&self) -> bool {
+        self.config.read().unwrap().enabled
+    }
+
+    /// 每次触发最多预取的标识符数量。
+    pub fn max_identifiers(&self) -> usize {
+        self.config.read().unwrap().max_identifiers
+    }
+
+    /// 限制预取并发数量的信号量。
+    pub fn permits(&self) -> Arc<Semaphore> {
+        Arc::clone(&self.permits.read().unwrap())
+    }
+
+    /// 查找请求在缓存中的结果，按光标所在的标识符查找。
+    ///
+    /// # 参数
+    ///
+    /// * `method` - 请求方法
+    /// * `params` - 请求参数
+    /// * `documents` - 打开的文档，光标不在打开的文档中的标识符上时不命中
+    pub fn cached(&self, method: &str, params: &Value, documents: &DocumentStore) -> Option<Value> {
+        let params: TextDocumentPositionParams = serde_json::from_value(params.clone()).ok()?;
+        let doc = documents.get(&params.text_document.uri)?;
+        let identifier = identifier_at(&doc.text, params.position)?;
+        let key = CacheKey::new(method, params.text_document.uri, identifier);
+        let cached = self.cache.get(&key)?;
+        cached.used.store(self.tick(), Ordering::Relaxed);
+        Some(cached.result.clone())
+    }
+
+    /// 缓存中是否已经有结果。
+    pub fn contains(&self, key: &CacheKey) -> bool {
+        self.cache.contains_key(key)
+    }
+
+    /// 开始新的一代，之前的预取不再需要。
+    ///
+    /// # 返回
+    ///
+    /// 返回新一代的编号
+    pub fn begin(&self) -> u64 {
+        self.generation.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// 指定的一代是否仍然是最新的。
+    pub fn is_current(&self, generation: u64) -> bool {
+        self.generation.load(Ordering::Relaxed) == generation
+    }
+
+    /// 在这一代仍然有效时缓存预取的结果，缓存已满时先丢弃最久没有使用的结果。
+    pub fn insert_if_current(&self, generation: u64, key: CacheKey, result: Value) {
+        if !self.is_current(generation) {
+            return;
+        }
+        if self.cache.len() >= MAX_CACHED_RESULTS && !self.cache.contains_key(&key) {
+            let oldest = self
+                .cache
+                .iter()
+                .min_by_key(|entry| entry.used.load(Ordering::Relaxed))
+                .map(|entry| entry.key().clone());
+            if let Some(oldest) = oldest {
+                self.cache.remove(&oldest);
+            }
+        }
+        let used = AtomicU64::new(self.tick());
+        self.cache.insert(key, Cached { result, used });
+    }
+
+    /// 缓存的结果数量。
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// 缓存是否为空。
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// 文档内容发生变化，清空缓存并放弃进行中的预取。
+    pub fn invalidate(&self) {
+        self.begin();
+        self.cache.clear();
+    }
+}
+
+/// 文档的内容或磁盘上的文件变化后，缓存的结果可能已经过时。
+impl DocumentObserver for Prefetcher {
+    fn did_change(&self, _params: &DidChangeTextDocumentParams) {
+        self.invalidate();
+    }
+
+    fn did_save(&self, _params: &DidSaveTextDocumentParams) {
+        self.invalidate();
+    }
+}
+
+/// 光标所在的标识符的范围（UTF-16），光标紧跟在标识符之后也算在标识符上。
+///
+/// 光标不在标识符上或者在数字上时返回 `None`。
+pub fn identifier_at(text: &str, position: Position) -> Option<Range> {
+    let line = text.lines().nth(position.line as usize)?;
+    let mut column = 0u32;
+    let mut start: Option<(u32, usize)> = None;
+    for (offset, c) in line
+        .char_indices()
+        .chain(std::iter::once((line.len(), ' ')))
+    {
+        let is_ident = c.is_alphanumeric() || c == '_';
+        match (start, is_ident) {
+            (None, true) => start = Some((column, offset)),
+            (Some((start_column, start_offset)), false) => {
+                if (start_column..=column).contains(&position.character) {
+                    let word = &line[start_offset..offset];
+                    return (!word.starts_with(|c: char| c.is_ascii_digit())).then(|| {
+                        Range::new(
+                            Position::new(position.line, start_column),
+                            Position::new(position.line, column),
+                        )
+                    });
+                }
+                start = None;
+            }
+            _ => {}
+        }
+        column += c.len_utf16() as u32;
+    }
+    None
+}
+
+/// 找出光标附近（当前行和上下各一行）的标识符起始位置，按距离从近到远排列。
+///
+/// 光标所在的标识符和关键字会被跳过；位置使用 UTF-16 编码。
+///
+/// # 参数
+///
+/// * `text` - 文档内容
+/// * `position` - 光标位置
+/// * `limit` - 最多返回的数量
+pub fn adjacent_identifiers(text: &str, position: Position, limit: usize) -> Vec<Position> {
+    let first = position.line.saturating_sub(1);
+    let mut candidates = Vec::new();
+
+    for (line_number, line) in text
+        .lines()
+        .enumerate()
+        .skip(first as usize)
+        .take((position.line - first + 2) as usize)
+    {
+        let line_number = line_number as u32;
+        let mut column = 0u32;
+        let mut start: Option<(u32, usize)> = None;
+        for (offset, c) in line
+            .char_indices()
+            .chain(std::iter::once((line.len(), ' ')))
+        {
+            let is_ident = c.is_alphanumeric() || c == '_';
+            match (start, is_ident) {
+                (None, true) => start = Some((column, offset)),
+                (Some((start_column, start_offset)), false) => {
+                    let word = &line[start_offset..offset];
+                    let under_cursor = line_number == position.line
+                        && (start_column..=column).contains(&position.character);
+                    let is_number = word.starts_with(|c: char| c.is_ascii_digit());
+                    if !under_cursor && !is_number && !KEYWORDS.contains(&word) {
+                        candidates.push(Position::new(line_number, start_column));
+                    }
+                    start = None;
+                }
+                _ => {}
+            }
+            column += c.len_utf16() as u32;
+        }
+    }
+
+    candidates.sort_by_key(|p| {
+        (
+            p.line.abs_diff(position.line),
+            p.character.abs_diff(position.character),
+        )
+    });
+    candidates.truncate(limit);
+    candidates
+}
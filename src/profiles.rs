@@ -0,0 +1,124 @@
+//! # 配置档模块
+//!
+//! 配置档是一组预设的设置（`[profile.<name>]`），以 `--profile <name>` 选择，或者在运行时通过
+//! `codefuse/setProfile` 切换。选中的配置档逐层覆盖配置文件中的同名设置；内置了 `low-latency`、`battery` 和 `ci`，
+//! 配置文件中的同名配置档在内置的基础上修改。
+
+use anyhow::{Result, bail};
+use futures::future::BoxFuture;
+use toml::{Table, Value};
+
+use crate::dispatcher::{DEFAULT_HANDLER_PRIORITY, Dispatcher};
+use crate::handlers::{self, HandlerCtx, Verdict};
+use crate::trace::Direction;
+
+/// 切换配置档的请求，参数是 `{"profile": "<name>"}`，`profile` 为 `null` 时回到不使用配置档的设置。
+pub const SET_PROFILE: &str = "codefuse/setProfile";
+
+/// 配置文件中保存配置档的表。
+pub const PROFILE_KEY: &str = "profile";
+
+/// 内置的配置档。
+pub const BUILTIN: &str = r#"
+# 更多并发和积极的预取，换取更低的响应延迟
+[low-latency]
+concurrency.max_handlers = 32
+prefetch.enabled = true
+prefetch.max_identifiers = 8
+prefetch.max_concurrent = 4
+
+# 限制并发、关闭预取，减少后台的 CPU 占用
+[battery]
+concurrency.max_handlers = 4
+prefetch.enabled = false
+
+# 非交互环境：关闭预取，只记录警告和错误
+[ci]
+concurrency.max_handlers = 64
+prefetch.enabled = false
+logging.level = "warn"
+"#;
+
+/// 把 `overlay` 合并进 `base`：两边都是表时逐项合并，否则 `overlay` 的值替换 `base` 的值（包括数组）。
+pub fn merge(base: &mut Table, overlay: &Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge(base, overlay),
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// 可以选择的配置档：内置的加上配置文件中声明的，按名称排序。
+pub fn names(document: &Table) -> Vec<String> {
+    let builtin: Table = toml::from_str(BUILTIN).expect("内置配置档是合法的 TOML");
+    let mut names: Vec<String> = builtin.keys().cloned().collect();
+    if let Some(Value::Table(profiles)) = document.get(PROFILE_KEY) {
+        names.extend(profiles.keys().cloned());
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// 从配置文档中取出配置档，把选中的配置档合并进文档。
+///
+/// # 参数
+///
+/// * `document` - 配置文件解析出的 TOML 文档
+/// * `profile` - 选中的配置档，`None` 时只删除配置档
+///
+/// # 错误
+///
+/// 配置档不存在或者不是表时返回错误
+pub fn apply(document: &mut Table, profile: Option<&str>) -> Result<()> {
+    let Some(name) = profile else {
+        document.remove(PROFILE_KEY);
+        return Ok(());
+    };
+    let available = names(document);
+    let declared = match document.remove(PROFILE_KEY) {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => bail!("[{}] 必须是表", PROFILE_KEY),
+        None => Table::new(),
+    };
+    let mut builtin: Table = toml::from_str(BUILTIN).expect("内置配置档是合法的 TOML");
+    let mut overlay = match builtin.remove(name) {
+        Some(Value::Table(overlay)) => overlay,
+        _ => Table::new(),
+    };
+    match declared.get(name) {
+        Some(Value::Table(declared)) => merge(&mut overlay, declared),
+        Some(_) => bail!("[{}.{}] 必须是表", PROFILE_KEY, name),
+        None if overlay.is_empty() => {
+            bail!(
+                "没有名为 {} 的配置档，可用的配置档：{}",
+                name,
+                available.join(", ")
+            )
+        }
+        None => {}
+    }
+    merge(document, &overlay);
+    Ok(())
+}
+
+/// 注册 `codefuse/setProfile` 的处理器，由代理直接应答。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        SET_PROFILE,
+        DEFAULT_HANDLER_PRIORITY,
+        set_profile,
+    );
+}
+
+/// 切换配置档，以新的配置档和按生效方式分组的修改应答。
+fn set_profile(rpc: serde_json::Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let result = ctx.dispatcher().set_profile(&rpc);
+        Ok(Verdict::Respond(handlers::response(&rpc, result)))
+    })
+}
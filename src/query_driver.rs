@@ -0,0 +1,190 @@
+//! # query-driver 检测模块
+//!
+//! 嵌入式和交叉编译项目使用的编译器（例如 `arm-none-eabi-gcc`）有自己的系统头文件目录，
+//! clangd 只有在 `--query-driver` 允许时才会运行编译器查询这些目录，否则找不到标准库头文件。
+//! 这个模块从工作区的 `compile_commands.json` 中找出使用的编译器，按 `[backend.clangd] auto_query_driver`
+//! 询问用户或者直接加入 `--query-driver`，然后重启后端。
+//!
+//! `--query-driver` 允许 clangd 运行编译数据库中的程序，不可信的工作区可能借此运行任意程序，
+//! 因此默认关闭，`ask` 在每次发现新的编译器时询问用户。
+
+use anyhow::{Context, Result};
+use globset::Glob;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tower_lsp::lsp_types::MessageType;
+use tower_lsp::lsp_types::request::{Request, ShowMessageRequest};
+
+use crate::config::QueryDriverMode;
+use crate::dispatcher::Dispatcher;
+use crate::include_check::{DATABASE_PATHS, split_command};
+use crate::platform;
+
+/// 询问用户时允许的选项。
+const ALLOW: &str = "允许";
+
+/// 编译命令前面的编译器包装程序，真正的编译器是下一个参数。
+const WRAPPERS: &[&str] = &["ccache", "sccache", "distcc", "icecc"];
+
+#[derive(Deserialize)]
+struct CompileCommand {
+    directory: PathBuf,
+    #[serde(default)]
+    arguments: Vec<String>,
+    command: Option<String>,
+}
+
+/// 程序名（去掉目录和 `.exe`），用于判断包装程序和 clang。
+fn program_name(program: &str) -> String {
+    let name = program.rsplit(['/', '\\']).next().unwrap_or(program);
+    let name = name.to_lowercase();
+    name.strip_suffix(".exe").unwrap_or(&name).to_string()
+}
+
+/// 编译数据库中使用的编译器的绝对路径，按路径排序、去重。
+///
+/// clang 自己的头文件目录 clangd 已经知道，不需要查询，不包括在结果中；找不到的编译器也被忽略。
+///
+/// # 错误
+///
+/// 如果文件无法读取或者格式不对，返回错误
+pub fn compilers(database: &Path) -> Result<Vec<String>> {
+    let text = std::fs::read_to_string(database)
+        .with_context(|| format!("无法读取 {}", database.display()))?;
+    let commands: Vec<CompileCommand> =
+        serde_json::from_str(&text).with_context(|| format!("无法解析 {}", database.display()))?;
+
+    let mut compilers = BTreeSet::new();
+    for command in commands {
+        let arguments = match &command.command {
+            Some(line) if command.arguments.is_empty() => split_command(line),
+            _ => command.arguments,
+        };
+        let mut arguments = arguments.iter();
+        let mut compiler = arguments.next();
+        while let Some(program) = compiler
+            && WRAPPERS.contains(&program_name(program).as_str())
+        {
+            compiler = arguments.next();
+        }
+        let Some(compiler) = compiler else {
+            continue;
+        };
+        if program_name(compiler).starts_with("clang") {
+            continue;
+        }
+        let path = if compiler.contains(['/', '\\']) {
+            Some(remove_dots(&command.directory.join(compiler))).filter(|path| path.is_file())
+        } else {
+            platform::resolve_program(compiler)
+        };
+        match path {
+            Some(path) => {
+                compilers.insert(path.to_string_lossy().into_owned());
+            }
+            None => debug!("找不到编译器 {}", compiler),
+        }
+    }
+    Ok(compilers.into_iter().collect())
+}
+
+/// 按字面去掉路径中的 `.` 和 `..`，与 clangd 匹配 `--query-driver` 之前对编译器路径的处理相同。
+fn remove_dots(path: &Path) -> PathBuf {
+    let mut clean = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                clean.pop();
+            }
+            component => clean.push(component),
+        }
+    }
+    clean
+}
+
+/// 工作区中所有编译数据库使用的编译器，去掉已经被 `allowed` 中的模式覆盖的编译器。
+///
+/// # 参数
+///
+/// * `roots` - 工作区根目录，在其中查找 `compile_commands.json`
+/// * `allowed` - 已经允许的 `--query-driver` 模式
+pub fn detect(roots: &[PathBuf], allowed: &[String]) -> Vec<String> {
+    let matchers: Vec<_> = allowed
+        .iter()
+        .filter_map(|pattern| Glob::new(pattern).ok())
+        .map(|glob| glob.compile_matcher())
+        .collect();
+    let mut found = BTreeSet::new();
+    for database in roots
+        .iter()
+        .flat_map(|root| DATABASE_PATHS.iter().map(move |path| root.join(path)))
+        .filter(|path| path.is_file())
+    {
+        match compilers(&database) {
+            Ok(compilers) => found.extend(compilers),
+            Err(e) => debug!("{:?}", e),
+        }
+    }
+    found
+        .into_iter()
+        .filter(|compiler| !matchers.iter().any(|matcher| matcher.is_match(compiler)))
+        .collect()
+}
+
+/// 前端初始化后检测工作区使用的编译器，按 `mode` 询问用户或者直接允许，然后重启后端。
+///
+/// # 参数
+///
+/// * `dispatcher` - 运行中的调度器
+/// * `mode` - `[backend.clangd] auto_query_driver`
+pub async fn watch(dispatcher: Arc<Dispatcher>, mode: QueryDriverMode) {
+    if mode == QueryDriverMode::Off {
+        return;
+    }
+    let mut initialize = dispatcher.subscribe_initialize();
+    if initialize.wait_for(Option::is_some).await.is_err() {
+        return;
+    }
+    let mut roots = dispatcher.workspace_roots();
+    if roots.is_empty() {
+        roots.extend(std::env::current_dir().ok());
+    }
+    let mut allowed = dispatcher.config().backend.clangd.query_driver.clone();
+    allowed.extend(dispatcher.query_drivers());
+    let drivers = detect(&roots, &allowed);
+    if drivers.is_empty() {
+        return;
+    }
+    info!("编译数据库中的编译器: {}", drivers.join("、"));
+
+    if mode == QueryDriverMode::Ask {
+        let params = json!({
+            "type": MessageType::WARNING,
+            "message": format!(
+                "编译数据库使用了以下编译器，允许 clangd 运行它们获取系统头文件目录吗？\n{}",
+                drivers.join("\n")
+            ),
+            "actions": [{"title": ALLOW}, {"title": "不允许"}],
+        });
+        match dispatcher
+            .request_frontend(ShowMessageRequest::METHOD, params)
+            .await
+        {
+            Ok(choice) if choice.get("title").and_then(|t| t.as_str()) == Some(ALLOW) => {}
+            Ok(_) => {
+                info!("用户没有允许 --query-driver");
+                return;
+            }
+            Err(e) => {
+                warn!("无法询问用户是否允许 --query-driver: {:?}", e);
+                return;
+            }
+        }
+    }
+    dispatcher.allow_query_drivers(&drivers);
+}
@@ -0,0 +1,234 @@
+//! # 请求速率限制模块
+//!
+//! 按方法（可选再按文档）维护令牌桶，保护后端不被行为异常或过于频繁的客户端拖垮，
+//! 例如每秒最多 5 次 `semanticTokens`。超过限制的请求不转发给后端，
+//! 文档没有变化时以上一次相同请求的结果应答，否则以方法的空结果应答。
+
+use anyhow::Result;
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use tokio::time::Instant;
+use tower_lsp::lsp_types::{DidCloseTextDocumentParams, Url};
+
+use crate::config::RateLimitRule;
+use crate::dispatcher::Dispatcher;
+use crate::document_observer::DocumentObserver;
+use crate::document_store::DocumentStore;
+use crate::handlers::{self, HandlerCtx, MethodPattern, Verdict, empty_result};
+use crate::trace::Direction;
+
+/// 等待响应的请求数量超过这个值时清空。后端退出时没有应答的请求不会再有响应，
+/// 清空丢失的只是之后可以代替超过限制的请求的结果。
+const MAX_PENDING_REQUESTS: usize = 4096;
+
+/// 令牌桶。
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    /// 上一次取令牌时桶已经空了
+    exhausted: bool,
+}
+
+/// 超过限制的请求的应答。
+///
+/// - `result`: 代替后端应答的结果
+/// - `newly_exhausted`: 令牌桶刚刚用完，之前的请求都没有超过限制
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    pub result: Value,
+    pub newly_exhausted: bool,
+}
+
+/// 一次请求的结果，文档版本和参数都相同时可以代替超过限制的请求。
+struct Cached {
+    version: i32,
+    params: String,
+    result: Value,
+}
+
+/// 按方法和文档的请求速率限制。没有配置规则时不限制。
+///
+/// 按文档的令牌桶和缓存的结果在文档关闭时丢弃。
+#[derive(Default)]
+pub struct RateLimits {
+    rules: RwLock<HashMap<String, RateLimitRule>>,
+    /// (方法, 文档) → 令牌桶，不按文档限制时文档为 `None`
+    buckets: DashMap<(String, Option<Url>), Bucket>,
+    /// 等待响应的请求：请求 id → (方法, 文档, 文档版本, 参数)
+    requests: DashMap<String, (String, Url, i32, String)>,
+    /// 方法和文档 → 最近一次请求的结果
+    results: DashMap<(String, Url), Cached>,
+}
+
+impl RateLimits {
+    /// 按 `[rate_limits]` 配置创建限制。
+    pub fn new(rules: &HashMap<String, RateLimitRule>) -> Self {
+        Self {
+            rules: RwLock::new(rules.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// 替换规则，重新加载配置时调用。已有的令牌桶和缓存的结果被丢弃。
+    pub fn set_rules(&self, rules: &HashMap<String, RateLimitRule>) {
+        *self.rules.write().unwrap() = rules.clone();
+        self.buckets.clear();
+        self.requests.clear();
+        self.results.clear();
+    }
+
+    /// 检查前端的请求是否超过限制。
+    ///
+    /// # 参数
+    ///
+    /// * `method` - 请求的方法
+    /// * `params` - 请求的参数，文档由 `textDocument.uri` 给出
+    /// * `documents` - 打开的文档
+    ///
+    /// # 返回
+    ///
+    /// 没有超过限制时返回 `None`，请求照常转发；否则返回代替后端应答的结果
+    pub fn reject(
+        &self,
+        method: &str,
+        params: Option<&Value>,
+        documents: &DocumentStore,
+    ) -> Option<Rejection> {
+        let rule = self.rules.read().unwrap().get(method).cloned()?;
+        let uri = document_uri(params);
+        let key = (
+            method.to_string(),
+            uri.clone().filter(|_| rule.per_document),
+        );
+        let newly_exhausted = match self.take(key, &rule) {
+            Ok(()) => return None,
+            Err(newly_exhausted) => newly_exhausted,
+        };
+
+        let cached = uri.and_then(|uri| {
+            let doc = documents.get(&uri)?;
+            let cached = self.results.get(&(method.to_string(), uri))?;
+            (cached.version == doc.version && cached.params == params_key(params))
+                .then(|| cached.result.clone())
+        });
+        Some(Rejection {
+            result: cached.unwrap_or_else(|| empty_result(method)),
+            newly_exhausted,
+        })
+    }
+
+    /// 记录转发给后端的请求，响应到达时缓存它的结果。没有限制的方法和未打开的文档被忽略。
+    pub fn request(
+        &self,
+        id: &Value,
+        method: &str,
+        params: Option<&Value>,
+        documents: &DocumentStore,
+    ) {
+        if !self.rules.read().unwrap().contains_key(method) {
+            return;
+        }
+        let Some(uri) = document_uri(params) else {
+            return;
+        };
+        if let Some(doc) = documents.get(&uri) {
+            if self.requests.len() >= MAX_PENDING_REQUESTS {
+                self.requests.clear();
+            }
+            self.requests.insert(
+                id.to_string(),
+                (method.to_string(), uri, doc.version, params_key(params)),
+            );
+        }
+    }
+
+    /// 后端的响应到达，成功的结果留作之后超过限制的相同请求的应答。
+    pub fn responded(&self, id: &Value, rpc: &Value) {
+        let Some((_, (method, uri, version, params))) = self.requests.remove(&id.to_string())
+        else {
+            return;
+        };
+        if let Some(result) = rpc.get("result") {
+            let cached = Cached {
+                version,
+                params,
+                result: result.clone(),
+            };
+            self.results.insert((method, uri), cached);
+        }
+    }
+
+    /// 从令牌桶中取出一个令牌。桶空时返回错误，其中的值表示桶是不是刚刚变空。
+    fn take(&self, key: (String, Option<Url>), rule: &RateLimitRule) -> Result<(), bool> {
+        let capacity = rule.capacity();
+        let now = Instant::now();
+        let mut bucket = self.buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            refilled: now,
+            exhausted: false,
+        });
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rule.per_second).min(capacity);
+        bucket.refilled = now;
+        if bucket.tokens < 1.0 {
+            let newly_exhausted = !bucket.exhausted;
+            bucket.exhausted = true;
+            return Err(newly_exhausted);
+        }
+        bucket.tokens -= 1.0;
+        bucket.exhausted = false;
+        Ok(())
+    }
+}
+
+impl DocumentObserver for RateLimits {
+    fn did_close(&self, params: &DidCloseTextDocumentParams) {
+        let uri = &params.text_document.uri;
+        self.buckets
+            .retain(|(_, document), _| document.as_ref() != Some(uri));
+        self.requests
+            .retain(|_, (_, document, _, _)| document != uri);
+        self.results.retain(|(_, document), _| document != uri);
+    }
+}
+
+fn document_uri(params: Option<&Value>) -> Option<Url> {
+    params
+        .and_then(|params| params.pointer("/textDocument/uri"))
+        .and_then(|uri| uri.as_str())
+        .and_then(|uri| Url::parse(uri).ok())
+}
+
+fn params_key(params: Option<&Value>) -> String {
+    params.map(Value::to_string).unwrap_or_default()
+}
+
+/// 速率限制处理器的优先级，高于其他所有处理器，看到的是兼容垫片改写之前的请求。
+pub const RATE_LIMIT_PRIORITY: i32 = 1000;
+
+/// 注册速率限制的处理器。规则可以在运行时重新加载，所以对所有方法注册，处理请求时按当前的规则判断。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_pattern(
+        Direction::Frontend,
+        MethodPattern::Prefix(String::new()),
+        RATE_LIMIT_PRIORITY,
+        limit_request,
+    );
+}
+
+/// 超过速率限制的请求不转发给后端，以上一次相同请求的结果或空结果应答。
+fn limit_request(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let (Some(_), Some(method)) = (rpc.get("id"), rpc.get("method").and_then(|m| m.as_str()))
+        else {
+            return Ok(Verdict::Continue(rpc));
+        };
+        match ctx.dispatcher().rate_limited(method, &rpc) {
+            Some(result) => Ok(Verdict::Respond(handlers::response(&rpc, Ok(result)))),
+            None => Ok(Verdict::Continue(rpc)),
+        }
+    })
+}
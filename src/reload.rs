@@ -0,0 +1,306 @@
+//! # 配置热加载模块
+//!
+//! 监视配置文件，修改后不重启代理即可生效：`[logging]`、`[diagnostics]`、`[[tidy]]`、`[prefetch]`、`[concurrency]`、`[rate_limits]` 和 `[messages]`
+//! 立即应用，`[backend]` 的修改通过一次受控的后端重启生效，其他部分需要重启代理。每次重新加载都会告诉编辑器结果。
+//! 切换配置档同样按这种方式应用。
+//! 工作区不受信任时，重新加载的配置同样去掉会启动进程的设置（见 [`crate::trust`]）。
+
+use anyhow::Result;
+use log::{info, warn};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tower_lsp::lsp_types::MessageType;
+
+use crate::config::{BackendConfig, Config};
+use crate::dispatcher::Dispatcher;
+use crate::trust::WorkspaceTrust;
+
+/// 检查配置文件是否修改的间隔。
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 重新加载的后端配置，监管者在下一次重启后端时使用。
+///
+/// - `config`: 新的 `[backend]`
+/// - `args`: 每个分片的完整启动参数，下标与分片相同
+#[derive(Debug, Clone)]
+pub struct BackendLaunch {
+    pub config: BackendConfig,
+    pub args: Vec<Vec<String>>,
+}
+
+/// 两份配置之间修改了的部分，按生效方式分组。
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    /// 立即生效的部分
+    pub live: Vec<&'static str>,
+    /// 重启后端之后生效的部分
+    pub restart_backend: Vec<&'static str>,
+    /// 需要重启代理才能生效的部分
+    pub restart_proxy: Vec<&'static str>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.live.is_empty() && self.restart_backend.is_empty() && self.restart_proxy.is_empty()
+    }
+
+    /// 告诉编辑器的说明。
+    pub fn message(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.live.is_empty() {
+            parts.push(format!("已应用 {}", self.live.join("、")));
+        }
+        if !self.restart_backend.is_empty() {
+            parts.push(format!(
+                "{} 已修改，正在重启后端",
+                self.restart_backend.join("、")
+            ));
+        }
+        if !self.restart_proxy.is_empty() {
+            parts.push(format!(
+                "{} 需要重启代理才能生效",
+                self.restart_proxy.join("、")
+            ));
+        }
+        format!("配置已重新加载：{}", parts.join("；"))
+    }
+}
+
+/// 比较两份配置，找出修改了的部分。
+pub fn diff(old: &Config, new: &Config) -> Changes {
+    // 配置的结构体只实现了 Debug，按 Debug 的输出比较
+    fn changed(old: &dyn Debug, new: &dyn Debug) -> bool {
+        format!("{:?}", old) != format!("{:?}", new)
+    }
+
+    let mut changes = Changes::default();
+    for (name, changed) in [
+        ("[logging]", changed(&old.logging, &new.logging)),
+        ("[diagnostics]", changed(&old.diagnostics, &new.diagnostics)),
+        ("[[tidy]]", changed(&old.tidy, &new.tidy)),
+        ("[prefetch]", changed(&old.prefetch, &new.prefetch)),
+        ("[concurrency]", changed(&old.concurrency, &new.concurrency)),
+        ("[rate_limits]", changed(&old.rate_limits, &new.rate_limits)),
+        ("[messages]", changed(&old.messages, &new.messages)),
+    ] {
+        if changed {
+            changes.live.push(name);
+        }
+    }
+    if changed(&old.backend, &new.backend) {
+        changes.restart_backend.push("[backend]");
+    }
+    for (name, changed) in [
+        ("[[shards]]", changed(&old.shards, &new.shards)),
+        ("[warmup]", changed(&old.warmup, &new.warmup)),
+        ("[cache]", changed(&old.cache, &new.cache)),
+        ("[includes]", changed(&old.includes, &new.includes)),
+        ("[protocol]", changed(&old.protocol, &new.protocol)),
+        ("[health]", changed(&old.health, &new.health)),
+        ("[telemetry]", changed(&old.telemetry, &new.telemetry)),
+        ("[transport]", changed(&old.transport, &new.transport)),
+        ("[remote]", changed(&old.remote, &new.remote)),
+        ("[session]", changed(&old.session, &new.session)),
+        ("[spellcheck]", changed(&old.spellcheck, &new.spellcheck)),
+        ("[todos]", changed(&old.todos, &new.todos)),
+        ("[colors]", changed(&old.colors, &new.colors)),
+        ("[notebooks]", changed(&old.notebooks, &new.notebooks)),
+        ("[[embedded]]", changed(&old.embedded, &new.embedded)),
+        ("[languages]", changed(&old.languages, &new.languages)),
+        ("[modules]", changed(&old.modules, &new.modules)),
+        ("[cmake]", changed(&old.cmake, &new.cmake)),
+        ("[flags]", changed(&old.flags, &new.flags)),
+        ("[history]", changed(&old.history, &new.history)),
+        ("[admin]", changed(&old.admin, &new.admin)),
+        ("[liveness]", changed(&old.liveness, &new.liveness)),
+        (
+            "[crash_reports]",
+            changed(&old.crash_reports, &new.crash_reports),
+        ),
+        (
+            "[index_progress]",
+            changed(&old.index_progress, &new.index_progress),
+        ),
+        ("[file_status]", changed(&old.file_status, &new.file_status)),
+        ("[shadow]", changed(&old.shadow, &new.shadow)),
+        ("[completion]", changed(&old.completion, &new.completion)),
+        ("[snippets]", changed(&old.snippets, &new.snippets)),
+        (
+            "[signature_help]",
+            changed(&old.signature_help, &new.signature_help),
+        ),
+        (
+            "[document_highlight]",
+            changed(&old.document_highlight, &new.document_highlight),
+        ),
+        ("[state]", changed(&old.state, &new.state)),
+    ] {
+        if changed {
+            changes.restart_proxy.push(name);
+        }
+    }
+    changes
+}
+
+/// 代理正在使用的配置：配置文件、选中的配置档和当前生效的配置。
+pub struct ConfigSource {
+    path: Option<PathBuf>,
+    profile: Mutex<Option<String>>,
+    current: Mutex<Config>,
+    /// 对新加载的配置做与启动时相同的调整，例如追加后端参数
+    prepare: Box<dyn Fn(&mut Config) + Send + Sync>,
+    /// 工作区不受信任时去掉配置中会启动进程的设置
+    trust: Arc<WorkspaceTrust>,
+}
+
+impl ConfigSource {
+    /// # 参数
+    ///
+    /// * `path` - 配置文件，没有配置文件时为 `None`
+    /// * `profile` - 启动时选择的配置档
+    /// * `config` - 启动时加载的配置
+    /// * `prepare` - 对新加载的配置做与启动时相同的调整
+    pub fn new(
+        path: Option<PathBuf>,
+        profile: Option<String>,
+        config: Config,
+        prepare: impl Fn(&mut Config) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            path,
+            profile: Mutex::new(profile),
+            current: Mutex::new(config),
+            prepare: Box::new(prepare),
+            trust: Arc::new(WorkspaceTrust::default()),
+        }
+    }
+
+    /// 按工作区的信任状态加载配置；启动时加载的配置应当已经应用过同一个信任状态。
+    pub fn with_trust(mut self, trust: Arc<WorkspaceTrust>) -> Self {
+        self.trust = trust;
+        self
+    }
+
+    /// 配置文件。
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// 当前的配置档。
+    pub fn profile(&self) -> Option<String> {
+        self.profile.lock().unwrap().clone()
+    }
+
+    /// 当前生效的配置。
+    pub fn current(&self) -> Config {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// 工作区的信任状态。
+    pub fn trust(&self) -> &WorkspaceTrust {
+        &self.trust
+    }
+
+    /// 改变工作区的信任状态（`workspace/trust`），按新的状态重新加载配置并交给调度器应用。
+    ///
+    /// # 错误
+    ///
+    /// 配置无法加载时返回错误，此时保留当前的配置
+    pub fn set_trusted(&self, dispatcher: &Dispatcher, trusted: bool) -> Result<Changes> {
+        if !self.trust.set_trusted(trusted) {
+            return Ok(Changes::default());
+        }
+        info!(
+            "工作区{}",
+            if trusted {
+                "受信任"
+            } else {
+                "不再受信任"
+            }
+        );
+        self.reload(dispatcher)
+    }
+
+    /// 按当前的配置档重新加载配置，交给调度器应用。
+    ///
+    /// # 错误
+    ///
+    /// 配置无法加载时返回错误，此时保留当前的配置
+    pub fn reload(&self, dispatcher: &Dispatcher) -> Result<Changes> {
+        self.set_profile(dispatcher, self.profile())
+    }
+
+    /// 切换配置档，重新加载配置并交给调度器应用。
+    ///
+    /// # 参数
+    ///
+    /// * `dispatcher` - 应用配置的调度器
+    /// * `profile` - 新的配置档，`None` 表示不使用配置档
+    ///
+    /// # 错误
+    ///
+    /// 配置无法加载或者配置档不存在时返回错误，此时保留当前的配置和配置档
+    pub fn set_profile(&self, dispatcher: &Dispatcher, profile: Option<String>) -> Result<Changes> {
+        let mut next = match &self.path {
+            Some(path) => Config::load_profile(path, profile.as_deref())?,
+            None => Config::parse_profile("", profile.as_deref())?,
+        };
+        self.trust.apply(&mut next);
+        if let Some(message) = self.trust.message() {
+            warn!("{}", message);
+        }
+        (self.prepare)(&mut next);
+        *self.profile.lock().unwrap() = profile;
+        let mut current = self.current.lock().unwrap();
+        let changes = diff(&current, &next);
+        if !changes.is_empty() {
+            info!("{}", changes.message());
+            dispatcher.apply_config(&next, &changes);
+        }
+        *current = next;
+        Ok(changes)
+    }
+}
+
+/// 文件的修改时间和大小，文件不存在时返回 `None`。
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// 每隔 [`POLL_INTERVAL`] 检查配置文件，修改后重新加载并交给调度器应用。没有配置文件时立即返回。
+///
+/// 新的配置无法加载时保留当前的配置，并在编辑器中显示错误。
+///
+/// # 参数
+///
+/// * `source` - 代理正在使用的配置
+/// * `dispatcher` - 应用配置的调度器
+pub async fn watch(source: Arc<ConfigSource>, dispatcher: Arc<Dispatcher>) {
+    let Some(path) = source.path().map(Path::to_path_buf) else {
+        return;
+    };
+    let mut last = stamp(&path);
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let current = stamp(&path);
+        if current.is_none() || current == last {
+            continue;
+        }
+        last = current;
+        if let Err(e) = source.reload(&dispatcher) {
+            warn!("无法重新加载配置 {}: {:?}", path.display(), e);
+            dispatcher.show_message(
+                MessageType::WARNING,
+                &format!(
+                    "配置文件 {} 有错误，继续使用原来的配置: {:#}",
+                    path.display(),
+                    e
+                ),
+            );
+        }
+    }
+}
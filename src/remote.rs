@@ -0,0 +1,367 @@
+//! # 远程模式模块
+//!
+//! 让笔记本上的编辑器使用构建服务器上的索引：本地的 `relay` 把编辑器的消息转发给与代码和 clangd 在一起的远程代理，
+//! 转发时在本地和远程的工作区路径之间替换 URI，并把编辑器保存的文件同步到远程机器。
+
+use anyhow::{Context, Result, bail};
+use futures::TryStreamExt;
+use futures::future::BoxFuture;
+use log::{debug, info, warn};
+use serde_json::{Map, Value, json};
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::process::Command;
+use tokio_util::codec::FramedRead;
+use tower_lsp::lsp_types::{FileChangeType, Url};
+
+use crate::codec::LspCodec;
+use crate::compression::FrameCompression;
+use crate::config::{Config, RemoteConfig};
+use crate::dispatcher::{DEFAULT_HANDLER_PRIORITY, Dispatcher};
+use crate::handlers::{HandlerCtx, Verdict};
+use crate::metrics::Metrics;
+use crate::tls::ConnectSecurity;
+use crate::trace::Direction;
+
+/// 本地 `relay` 发给远程代理的文件同步通知，参数是 `{uri, text}`，`text` 为 null 表示文件已被删除。
+pub const SYNC_FILE: &str = "codefuse/syncFile";
+
+/// 同步的文件大小上限，更大的文件（例如生成的数据文件）不同步，以免阻塞消息转发。
+pub const MAX_SYNC_FILE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// 本地与远程工作区之间的路径映射，URI 和文件路径都按前缀替换。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathMapping {
+    /// 本地的 URI 前缀和路径前缀
+    local: [String; 2],
+    /// 远程的 URI 前缀和路径前缀
+    remote: [String; 2],
+}
+
+impl PathMapping {
+    /// 创建映射，两个根目录都必须是绝对路径。
+    ///
+    /// # 错误
+    ///
+    /// 如果根目录不是绝对路径，返回错误
+    pub fn new(local_root: &Path, remote_root: &Path) -> Result<Self> {
+        Ok(Self {
+            local: prefixes(local_root)?,
+            remote: prefixes(remote_root)?,
+        })
+    }
+
+    /// 按 `[remote]` 配置创建映射，没有同时设置两个根目录时返回 `None`。
+    ///
+    /// # 错误
+    ///
+    /// 如果根目录不是绝对路径，返回错误
+    pub fn from_config(config: &RemoteConfig) -> Result<Option<Self>> {
+        match (&config.local_root, &config.remote_root) {
+            (Some(local), Some(remote)) => Ok(Some(Self::new(local, remote)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// 把发给远程代理的消息中的本地路径换成远程路径。
+    pub fn to_remote(&self, message: &mut Value) {
+        translate(message, &self.local, &self.remote);
+    }
+
+    /// 把远程代理发来的消息中的远程路径换成本地路径。
+    pub fn to_local(&self, message: &mut Value) {
+        translate(message, &self.remote, &self.local);
+    }
+}
+
+fn prefixes(root: &Path) -> Result<[String; 2]> {
+    let uri = Url::from_file_path(root)
+        .ok()
+        .with_context(|| format!("远程模式的根目录必须是绝对路径: {}", root.display()))?;
+    let path = root.to_string_lossy();
+    Ok([
+        uri.as_str().trim_end_matches('/').to_string(),
+        path.trim_end_matches('/').to_string(),
+    ])
+}
+
+/// 值是 URI 或文件路径的字段，只有这些字段中的前缀会被替换，文档文本、悬停内容等其他字符串保持原样。
+const PATH_FIELDS: &[&str] = &[
+    "uri",
+    "targetUri",
+    "rootUri",
+    "rootPath",
+    "oldUri",
+    "newUri",
+    "scopeUri",
+    "baseUri",
+    "target",
+    "compilationDatabasePath",
+];
+
+/// 以 URI 为键的对象字段，例如 `WorkspaceEdit.changes`。
+const URI_KEYED_FIELDS: &[&str] = &["changes"];
+
+/// 替换消息中 URI 和路径字段里以 `from` 中的前缀开头的值，以 URI 为键的对象的键也会被替换。
+fn translate(value: &mut Value, from: &[String; 2], to: &[String; 2]) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(|item| translate(item, from, to)),
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match value {
+                    Value::String(s) if PATH_FIELDS.contains(&key.as_str()) => {
+                        if let Some(translated) = translate_str(s, from, to) {
+                            *s = translated;
+                        }
+                    }
+                    Value::Object(entries) if URI_KEYED_FIELDS.contains(&key.as_str()) => {
+                        *entries = std::mem::take(entries)
+                            .into_iter()
+                            .map(|(uri, mut value)| {
+                                translate(&mut value, from, to);
+                                (translate_str(&uri, from, to).unwrap_or(uri), value)
+                            })
+                            .collect::<Map<_, _>>();
+                    }
+                    _ => translate(value, from, to),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn translate_str(s: &str, from: &[String; 2], to: &[String; 2]) -> Option<String> {
+    from.iter().zip(to).find_map(|(from, to)| {
+        let rest = s.strip_prefix(from.as_str())?;
+        // 前缀必须在路径分隔处结束，`/src/app` 不能匹配 `/src/application`
+        (rest.is_empty() || rest.starts_with('/')).then(|| format!("{}{}", to, rest))
+    })
+}
+
+/// 把 `initialize` 中的 `processId` 置为 null：这是本地编辑器的进程，远程代理监视它会立即退出。
+pub fn clear_process_id(message: &mut Value) {
+    if message.get("method").and_then(|m| m.as_str()) == Some("initialize")
+        && let Some(process_id) = message.pointer_mut("/params/processId")
+    {
+        *process_id = Value::Null;
+    }
+}
+
+/// 编辑器保存文件或报告文件变化时，读取本地文件生成 [`SYNC_FILE`] 通知，通知中是本地的 URI。
+/// 超过 [`MAX_SYNC_FILE_BYTES`] 的文件不同步。
+pub fn sync_notifications(message: &Value) -> Vec<Value> {
+    let method = message.get("method").and_then(|m| m.as_str());
+    let changes: Vec<(&str, bool)> = match method {
+        Some("textDocument/didSave") => message
+            .pointer("/params/textDocument/uri")
+            .and_then(|uri| uri.as_str())
+            .map(|uri| vec![(uri, false)])
+            .unwrap_or_default(),
+        Some("workspace/didChangeWatchedFiles") => message
+            .pointer("/params/changes")
+            .and_then(|changes| changes.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|change| {
+                let uri = change.get("uri")?.as_str()?;
+                let deleted = change.get("type") == Some(&json!(FileChangeType::DELETED));
+                Some((uri, deleted))
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let mut notifications = Vec::new();
+    for (uri, deleted) in changes {
+        let text = if deleted {
+            Value::Null
+        } else {
+            let Some(path) = Url::parse(uri).ok().and_then(|uri| uri.to_file_path().ok()) else {
+                continue;
+            };
+            match std::fs::metadata(&path) {
+                Ok(metadata) if metadata.len() > MAX_SYNC_FILE_BYTES => {
+                    warn!(
+                        "要同步的文件 {} 有 {} 字节，超过上限 {} 字节，不同步",
+                        path.display(),
+                        metadata.len(),
+                        MAX_SYNC_FILE_BYTES
+                    );
+                    continue;
+                }
+                _ => {}
+            }
+            match std::fs::read_to_string(&path) {
+                Ok(text) => Value::String(text),
+                Err(e) => {
+                    warn!("无法读取要同步的文件 {}: {}", path.display(), e);
+                    continue;
+                }
+            }
+        };
+        notifications.push(json!({
+            "jsonrpc": "2.0",
+            "method": SYNC_FILE,
+            "params": {"uri": uri, "text": text},
+        }));
+    }
+    notifications
+}
+
+/// 远程代理收到 [`SYNC_FILE`] 通知时写入或删除文件，只允许修改工作区中的文件。
+///
+/// # 返回
+///
+/// 返回被修改的文件
+///
+/// # 错误
+///
+/// 如果参数无效、文件不在工作区中，或者写入失败，返回错误
+pub fn apply_sync(params: &Value, roots: &[PathBuf]) -> Result<PathBuf> {
+    let path = params
+        .get("uri")
+        .and_then(|uri| uri.as_str())
+        .and_then(|uri| Url::parse(uri).ok())
+        .and_then(|uri| uri.to_file_path().ok())
+        .context("同步的文件没有有效的 URI")?;
+    if path.components().any(|c| c == Component::ParentDir)
+        || !roots.iter().any(|root| path.starts_with(root))
+    {
+        bail!("同步的文件不在工作区中: {}", path.display());
+    }
+    match params.get("text").and_then(|text| text.as_str()) {
+        Some(text) => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&path, text)
+        }
+        None => match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+    }
+    .with_context(|| format!("无法同步文件 {}", path.display()))?;
+    Ok(path)
+}
+
+/// 远程模式的本地一端：在标准输入输出与远程代理之间转发消息。
+///
+/// `address` 是 `host:port` 时连接以 `--listen` 运行的远程代理，按 `[transport]` 配置使用 TLS、发送令牌并协商压缩；
+/// 是 `ssh://<host>` 时通过 ssh 在远程机器上运行 `[remote] ssh_command`，使用它的标准输入输出。
+/// 任意一端关闭时返回，并在日志中记录压缩的效果。
+///
+/// # 错误
+///
+/// 如果路径映射无效、无法连接远程代理，或者读写失败，返回错误
+pub async fn relay(address: &str, config: &Config) -> Result<()> {
+    let mapping = PathMapping::from_config(&config.remote)?;
+    let metrics = Arc::new(Metrics::new());
+    let (remote_read, mut remote_write, compression, _ssh): (
+        Box<dyn AsyncRead + Send + Unpin>,
+        Box<dyn AsyncWrite + Send + Unpin>,
+        _,
+        _,
+    ) = match address.strip_prefix("ssh://") {
+        Some(host) => {
+            let mut child = Command::new("ssh")
+                .arg(host)
+                .arg(&config.remote.ssh_command)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .context("无法启动 ssh")?;
+            let stdin = child.stdin.take().context("无法获取 ssh 的标准输入")?;
+            let stdout = child.stdout.take().context("无法获取 ssh 的标准输出")?;
+            (Box::new(stdout), Box::new(stdin), None, Some(child))
+        }
+        None => {
+            let security = ConnectSecurity::from_config(&config.transport)?;
+            let (read, write) = security.connect(address).await?;
+            let compression = FrameCompression::new(&config.transport, Arc::clone(&metrics));
+            (read, write, compression, None)
+        }
+    };
+    info!("已连接远程代理 {}", address);
+
+    let upstream = async {
+        let mut editor = FramedRead::new(tokio::io::stdin(), LspCodec::default());
+        while let Some(mut message) = editor.try_next().await? {
+            clear_process_id(&mut message);
+            // 同步的文件先于触发同步的消息到达远程代理
+            let mut outgoing = Vec::new();
+            if config.remote.sync_files {
+                outgoing = sync_notifications(&message);
+            }
+            outgoing.push(message);
+            for mut message in outgoing {
+                if let Some(mapping) = &mapping {
+                    mapping.to_remote(&mut message);
+                }
+                let frame = match &compression {
+                    Some(compression) => compression.encode_frame(&message)?,
+                    None => Dispatcher::format_lsp_message(&message)?.into_bytes(),
+                };
+                remote_write.write_all(&frame).await?;
+            }
+            remote_write.flush().await?;
+        }
+        anyhow::Ok(())
+    };
+    let downstream = async {
+        let mut remote =
+            FramedRead::new(remote_read, LspCodec::with_compression(compression.clone()));
+        let mut stdout = tokio::io::stdout();
+        while let Some(mut message) = remote.try_next().await? {
+            if let Some(mapping) = &mapping {
+                mapping.to_local(&mut message);
+            }
+            stdout
+                .write_all(Dispatcher::format_lsp_message(&message)?.as_bytes())
+                .await?;
+            stdout.flush().await?;
+        }
+        anyhow::Ok(())
+    };
+    let result = tokio::select! {
+        result = upstream => result,
+        result = downstream => result,
+    };
+
+    let stats = metrics.snapshot();
+    info!(
+        "压缩: 发出 {} 帧 {} -> {} 字节，收到 {} 帧 {} -> {} 字节",
+        stats.frames_compressed,
+        stats.compression_input_bytes,
+        stats.compression_output_bytes,
+        stats.frames_decompressed,
+        stats.decompression_input_bytes,
+        stats.decompression_output_bytes
+    );
+    result
+}
+
+/// 注册 `codefuse/syncFile` 的处理器：本地的 relay 同步过来的文件写入工作区，不转发给后端。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        SYNC_FILE,
+        DEFAULT_HANDLER_PRIORITY,
+        sync_file,
+    );
+}
+
+fn sync_file(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let params = rpc.get("params").cloned().unwrap_or(json!(null));
+        match apply_sync(&params, &ctx.dispatcher().workspace_roots()) {
+            Ok(path) => debug!("已同步文件 {}", path.display()),
+            Err(e) => warn!("{:?}", e),
+        }
+        Ok(Verdict::Drop)
+    })
+}
@@ -0,0 +1,206 @@
+//! # 重命名预览模块
+//!
+//! 实现自定义请求 `codefuse/renamePreview`：代理向后端请求重命名，但不应用结果，
+//! 而是返回一份摘要（涉及的文件、每个文件的修改次数、潜在冲突），供编辑器扩展展示确认界面。
+
+use anyhow::{Result, anyhow};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use tower_lsp::lsp_types::{TextEdit, Url};
+
+use crate::dispatcher::{DEFAULT_HANDLER_PRIORITY, Dispatcher};
+use crate::document_store::DocumentStore;
+use crate::handlers::{self, HandlerCtx, Verdict};
+use crate::trace::Direction;
+
+/// 自定义请求的方法名，参数与 `textDocument/rename` 相同。
+pub const RENAME_PREVIEW: &str = "codefuse/renamePreview";
+
+/// 重命名涉及的一个文件。
+///
+/// - `version`: `documentChanges` 中声明的文档版本
+#[derive(Debug, Clone)]
+pub struct FileEdits {
+    pub uri: Url,
+    pub version: Option<i32>,
+    pub edits: Vec<TextEdit>,
+}
+
+/// 解析 `textDocument/rename` 返回的 `WorkspaceEdit`，按文件汇总编辑。
+///
+/// # 错误
+///
+/// 如果结果不是 `WorkspaceEdit` 或者包含无效的 URI，返回错误
+pub fn parse_rename_response(result: &Value) -> Result<Vec<FileEdits>> {
+    if result.is_null() {
+        return Ok(Vec::new());
+    }
+    let mut files: BTreeMap<Url, FileEdits> = BTreeMap::new();
+    let mut add = |uri: &str, version: Option<i32>, edits: &Value| -> Result<()> {
+        let uri = Url::parse(uri)?;
+        let edits: Vec<TextEdit> = serde_json::from_value(edits.clone())?;
+        let file = files.entry(uri.clone()).or_insert_with(|| FileEdits {
+            uri,
+            version,
+            edits: Vec::new(),
+        });
+        file.edits.extend(edits);
+        Ok(())
+    };
+
+    if let Some(changes) = result.get("changes").and_then(|c| c.as_object()) {
+        for (uri, edits) in changes {
+            add(uri, None, edits)?;
+        }
+    }
+    for change in result
+        .get("documentChanges")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+    {
+        // 只处理文本编辑，创建、重命名、删除文件的操作不会出现在 clangd 的重命名结果中
+        let Some(uri) = change.pointer("/textDocument/uri").and_then(|u| u.as_str()) else {
+            continue;
+        };
+        let version = change
+            .pointer("/textDocument/version")
+            .and_then(|v| v.as_i64())
+            .map(|v| v as i32);
+        add(uri, version, change.get("edits").unwrap_or(&Value::Null))?;
+    }
+
+    if files.is_empty()
+        && result.get("changes").is_none()
+        && result.get("documentChanges").is_none()
+    {
+        return Err(anyhow!("重命名结果不是 WorkspaceEdit"));
+    }
+    Ok(files.into_values().collect())
+}
+
+/// 预览中的一个文件。
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FilePreview {
+    pub uri: Url,
+    pub occurrences: usize,
+    /// 修改所在的行（从 0 开始），去重后升序
+    pub lines: Vec<u32>,
+}
+
+/// 重命名预览，作为 `codefuse/renamePreview` 的结果返回。
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenamePreview {
+    pub new_name: String,
+    pub files: Vec<FilePreview>,
+    pub total_occurrences: usize,
+    pub conflicts: Vec<String>,
+    pub summary: String,
+}
+
+/// 生成重命名预览。
+///
+/// 以下情况会被列为冲突：同一文件中的编辑相互重叠、编辑工作区以外的文件、
+/// 编辑所基于的版本与编辑器中打开的版本不一致。
+///
+/// # 参数
+///
+/// * `new_name` - 新名称
+/// * `files` - `parse_rename_response` 的结果
+/// * `roots` - 工作区根目录
+/// * `documents` - 编辑器中打开的文档
+pub fn build_preview(
+    new_name: &str,
+    files: &[FileEdits],
+    roots: &[PathBuf],
+    documents: &DocumentStore,
+) -> RenamePreview {
+    let mut conflicts = Vec::new();
+    let mut previews = Vec::new();
+
+    for file in files {
+        let mut ranges: Vec<_> = file
+            .edits
+            .iter()
+            .map(|e| {
+                (
+                    (e.range.start.line, e.range.start.character),
+                    (e.range.end.line, e.range.end.character),
+                )
+            })
+            .collect();
+        ranges.sort();
+        if ranges.windows(2).any(|pair| pair[1].0 < pair[0].1) {
+            conflicts.push(format!("{} 中的修改相互重叠", file.uri));
+        }
+
+        if let Ok(path) = file.uri.to_file_path()
+            && !roots.is_empty()
+            && !roots.iter().any(|root| path.starts_with(root))
+        {
+            conflicts.push(format!("{} 不在工作区内", file.uri));
+        }
+
+        if let (Some(version), Some(doc)) = (file.version, documents.get(&file.uri))
+            && doc.version != version
+        {
+            conflicts.push(format!(
+                "{} 已被修改（后端基于版本 {}，编辑器中为 {}）",
+                file.uri, version, doc.version
+            ));
+        }
+
+        let mut lines: Vec<u32> = file.edits.iter().map(|e| e.range.start.line).collect();
+        lines.sort_unstable();
+        lines.dedup();
+        previews.push(FilePreview {
+            uri: file.uri.clone(),
+            occurrences: file.edits.len(),
+            lines,
+        });
+    }
+
+    let total_occurrences = previews.iter().map(|f| f.occurrences).sum();
+    let summary = format!(
+        "重命名为 `{}`：{} 个文件，共 {} 处修改{}",
+        new_name,
+        previews.len(),
+        total_occurrences,
+        if conflicts.is_empty() {
+            String::new()
+        } else {
+            format!("，{} 个冲突", conflicts.len())
+        }
+    );
+
+    RenamePreview {
+        new_name: new_name.to_string(),
+        files: previews,
+        total_occurrences,
+        conflicts,
+        summary,
+    }
+}
+
+/// 注册 `codefuse/renamePreview` 的处理器，由代理直接应答。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        RENAME_PREVIEW,
+        DEFAULT_HANDLER_PRIORITY,
+        answer_preview,
+    );
+}
+
+/// 向后端请求重命名，以摘要应答而不应用。
+fn answer_preview(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let result = ctx.dispatcher().rename_preview(&rpc).await;
+        Ok(Verdict::Respond(handlers::response(&rpc, result)))
+    })
+}
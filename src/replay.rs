@@ -0,0 +1,148 @@
+//! # 请求重放模块
+//!
+//! 记录转发给后端、还没有得到响应的请求。后端崩溃后切换到新的后端时，只读请求在重放文档之后重新发出，
+//! 编辑器不会在恢复后看到一批失败的悬停和补全。每个请求只重放一次，再次遇到后端退出时以错误应答，
+//! 避免让后端崩溃的请求反复让新的后端崩溃。其他请求（例如重命名、执行命令）重复执行可能改变状态，
+//! 切换后端时直接以错误应答，编辑器不会一直等待旧后端的响应。
+
+use dashmap::DashMap;
+use serde_json::{Value, json};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tower_lsp::lsp_types::request::{self, Request};
+
+/// 可以重新发出的请求：只读取后端的状态，重复执行不会改变任何东西。
+pub const REPLAYABLE_METHODS: &[&str] = &[
+    request::HoverRequest::METHOD,
+    request::Completion::METHOD,
+    request::ResolveCompletionItem::METHOD,
+    request::SignatureHelpRequest::METHOD,
+    request::GotoDefinition::METHOD,
+    request::GotoDeclaration::METHOD,
+    request::GotoTypeDefinition::METHOD,
+    request::GotoImplementation::METHOD,
+    request::References::METHOD,
+    request::DocumentHighlightRequest::METHOD,
+    request::DocumentSymbolRequest::METHOD,
+    request::WorkspaceSymbolRequest::METHOD,
+    request::CodeActionRequest::METHOD,
+    request::CodeLensRequest::METHOD,
+    request::DocumentLinkRequest::METHOD,
+    request::FoldingRangeRequest::METHOD,
+    request::SelectionRangeRequest::METHOD,
+    request::SemanticTokensFullRequest::METHOD,
+    request::SemanticTokensRangeRequest::METHOD,
+    request::InlayHintRequest::METHOD,
+    request::Formatting::METHOD,
+    request::RangeFormatting::METHOD,
+    request::PrepareRenameRequest::METHOD,
+    "textDocument/switchSourceHeader",
+    "textDocument/ast",
+    "textDocument/symbolInfo",
+];
+
+/// 后端处理请求时退出、请求不能重放或者已经重放过一次时使用的错误码（RequestFailed）。
+const REQUEST_FAILED: i64 = -32803;
+
+struct Entry {
+    shard: usize,
+    /// 记录的顺序，重放时按原来的顺序发出
+    sequence: u64,
+    rpc: Value,
+    /// 请求是否可以重新发出，见 [`REPLAYABLE_METHODS`]
+    replayable: bool,
+    replayed: bool,
+}
+
+/// 一次切换后端时要处理的请求。
+#[derive(Debug, Default)]
+pub struct Replay {
+    /// 重新发给新后端的请求
+    pub requests: Vec<Value>,
+    /// 不能重放或者已经重放过一次、以错误应答的请求的响应
+    pub failed: Vec<Value>,
+}
+
+/// 等待后端响应的请求。
+#[derive(Default)]
+pub struct RequestJournal {
+    entries: DashMap<String, Entry>,
+    sequence: AtomicU64,
+}
+
+impl RequestJournal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录一个转发给分片的请求。
+    ///
+    /// # 参数
+    ///
+    /// * `shard` - 处理请求的分片
+    /// * `method` - 前端请求的方法
+    /// * `rpc` - 转发给后端的请求
+    pub fn record(&self, shard: usize, method: &str, rpc: &Value) {
+        let Some(id) = rpc.get("id") else {
+            return;
+        };
+        let entry = Entry {
+            shard,
+            sequence: self.sequence.fetch_add(1, Ordering::Relaxed),
+            rpc: rpc.clone(),
+            replayable: REPLAYABLE_METHODS.contains(&method),
+            replayed: false,
+        };
+        self.entries.insert(id.to_string(), entry);
+    }
+
+    /// 请求已经得到响应或者被前端取消，不再需要重放。
+    pub fn forget(&self, id: &Value) {
+        self.entries.remove(&id.to_string());
+    }
+
+    /// 记录的请求数量。
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// 分片切换到新的后端，取出要重放的请求。
+    ///
+    /// 第一次重放的请求继续留在记录中，直到得到响应；不能重放和已经重放过的请求从记录中移除并以错误应答。
+    ///
+    /// # 返回
+    ///
+    /// 返回按原来顺序排列的要重放的请求，以及发给前端的错误响应
+    pub fn replay(&self, shard: usize) -> Replay {
+        let mut requests = Vec::new();
+        let mut failed = Vec::new();
+        self.entries.retain(|_, entry| {
+            if entry.shard != shard {
+                return true;
+            }
+            let message = if !entry.replayable {
+                "后端已重启"
+            } else if entry.replayed {
+                "后端处理这个请求时退出"
+            } else {
+                entry.replayed = true;
+                requests.push((entry.sequence, entry.rpc.clone()));
+                return true;
+            };
+            failed.push(json!({
+                "jsonrpc": "2.0",
+                "id": entry.rpc["id"],
+                "error": {"code": REQUEST_FAILED, "message": message},
+            }));
+            false
+        });
+        requests.sort_by_key(|(sequence, _)| *sequence);
+        Replay {
+            requests: requests.into_iter().map(|(_, rpc)| rpc).collect(),
+            failed,
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! # 响应跟踪模块
+//!
+//! 记录转发给后端、等待响应的前端请求。后端返回的响应按 id 找回请求的方法；
+//! 找不到时区分重复的响应（同一个 id 刚刚应答过）和未知的响应，由调度器记录并按配置丢弃。
+
+use dashmap::DashMap;
+use serde_json::Value;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+
+/// 记住最近多少个已经应答的 id，用来识别重复的响应。
+const RECENT_RESPONSES: usize = 1024;
+
+/// 一个后端响应对应的请求。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// 等待中的请求，附带请求的方法
+    Pending(String),
+    /// 这个 id 的响应已经转发过
+    Duplicate,
+    /// 没有发出过这个 id 的请求
+    Unknown,
+}
+
+/// 等待后端响应的前端请求。
+#[derive(Default)]
+pub struct ResponseTracker {
+    pending: DashMap<String, String>,
+    answered: Mutex<RecentIds>,
+}
+
+#[derive(Default)]
+struct RecentIds {
+    order: VecDeque<String>,
+    ids: HashSet<String>,
+}
+
+impl ResponseTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录一个转发给后端的请求。
+    pub fn request(&self, id: &Value, method: &str) {
+        self.pending.insert(id_key(id), method.to_string());
+    }
+
+    /// 等待响应的请求数量。
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// 等待响应的请求的 id 和方法，按 id 排序。
+    pub fn pending_requests(&self) -> Vec<(String, String)> {
+        let mut requests: Vec<(String, String)> = self
+            .pending
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        requests.sort();
+        requests
+    }
+
+    /// 按 id 找回响应对应的请求，找到时把请求标记为已应答。
+    pub fn resolve(&self, id: &Value) -> Resolution {
+        let key = id_key(id);
+        if let Some((_, method)) = self.pending.remove(&key) {
+            self.answered.lock().unwrap().insert(key);
+            return Resolution::Pending(method);
+        }
+        if self.answered.lock().unwrap().ids.contains(&key) {
+            Resolution::Duplicate
+        } else {
+            Resolution::Unknown
+        }
+    }
+}
+
+impl RecentIds {
+    fn insert(&mut self, key: String) {
+        if self.order.len() == RECENT_RESPONSES
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.ids.remove(&oldest);
+        }
+        self.ids.insert(key.clone());
+        self.order.push_back(key);
+    }
+}
+
+/// 请求 id 的键。数字和字符串 id 分开：`1` 与 `"1"` 是不同的 id。
+fn id_key(id: &Value) -> String {
+    id.to_string()
+}
@@ -0,0 +1,212 @@
+//! # 沙箱后端模块
+//!
+//! 配置了 `[backend.sandbox]` 时，本地启动的后端在 bubblewrap 或 firejail 沙箱中运行，用于审阅不可信的代码：
+//! 后端只能读写工作区和 clangd 的缓存目录，系统目录、后端程序的安装目录和 clangd 的用户配置只读，
+//! `/tmp` 是沙箱私有的，默认不能访问网络。通过 ssh 或在容器中运行的后端不使用沙箱。
+//!
+//! firejail 的 `--whitelist` 只限制家目录（以及 `/tmp` 等少数目录）中的访问，家目录之外的工作区在 firejail 中
+//! 没有文件系统隔离，需要隔离时使用 bwrap。沙箱配置本身也属于会启动进程的设置，不受信任的工作区中的
+//! `[backend.sandbox]` 被忽略（见 [`crate::trust`]），需要时写在用户的配置文件中。
+
+use anyhow::{Context, Result, bail};
+use log::warn;
+use std::path::{Path, PathBuf};
+
+use crate::config::{SandboxConfig, SandboxTool};
+use crate::platform;
+
+/// 沙箱中只读挂载的系统目录，不存在的目录被跳过。
+pub const SYSTEM_DIRS: &[&str] = &[
+    "/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/etc", "/opt", "/nix",
+];
+
+/// 运行后端的沙箱。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandboxSpec {
+    pub tool: SandboxTool,
+    /// 工作区，后端的当前目录
+    pub workspace: PathBuf,
+    /// 可以读写的目录：工作区、clangd 的缓存目录和配置的目录
+    pub writable: Vec<PathBuf>,
+    /// 只读的目录：后端程序的安装目录、clangd 的用户配置和配置的目录
+    pub read_only: Vec<PathBuf>,
+    pub network: bool,
+    /// 传给沙箱程序的额外参数
+    pub extra_args: Vec<String>,
+}
+
+impl SandboxSpec {
+    /// 按配置创建，相对的工作区路径相对于代理的当前目录。
+    ///
+    /// 工作区必须明确配置：编辑器可能在家目录中启动代理，默认使用当前目录会让整个家目录可写。
+    ///
+    /// # 参数
+    ///
+    /// * `config` - 沙箱配置
+    /// * `program` - 后端程序
+    /// * `envs` - 启动后端时额外设置的环境变量，其中的 `XDG_CACHE_HOME` 是 clangd 的缓存目录
+    ///
+    /// # 错误
+    ///
+    /// 不是 Linux、没有配置工作区，或者工作区是根目录、家目录或家目录的上级目录时返回错误
+    pub fn new(config: &SandboxConfig, program: &str, envs: &[(String, String)]) -> Result<Self> {
+        if !cfg!(target_os = "linux") {
+            bail!("[backend.sandbox] 只支持 Linux");
+        }
+        let Some(workspace) = &config.workspace else {
+            bail!("[backend.sandbox] 需要设置 workspace，沙箱中的后端只能写入这个目录");
+        };
+        let current = std::env::current_dir().context("无法获取当前目录")?;
+        let workspace = current.join(workspace);
+        let resolved = workspace
+            .canonicalize()
+            .unwrap_or_else(|_| workspace.clone());
+        let home = std::env::var_os("HOME").map(PathBuf::from);
+        if resolved.parent().is_none() || home.is_some_and(|home| home.starts_with(&resolved)) {
+            bail!(
+                "[backend.sandbox] workspace 不能是根目录、家目录或家目录的上级目录: {}",
+                workspace.display()
+            );
+        }
+        let mut writable = vec![workspace.clone()];
+        if let Some(cache) = clangd_cache_dir(envs) {
+            // 绑定挂载的目录必须存在，第一次运行时 clangd 还没有创建它
+            let _ = std::fs::create_dir_all(&cache);
+            writable.push(cache);
+        }
+        writable.extend(config.writable.iter().cloned());
+        if config.tool == SandboxTool::Firejail
+            && let Some(home) = std::env::var_os("HOME").map(PathBuf::from)
+            && let Some(dir) = writable.iter().find(|dir| !dir.starts_with(&home))
+        {
+            // firejail 的 --whitelist 只隐藏家目录中的其他文件，家目录之外的文件系统仍然可见
+            warn!(
+                "{} 在家目录之外，firejail 不限制后端访问家目录之外的文件，需要隔离时请使用 bwrap",
+                dir.display()
+            );
+        }
+        let mut read_only: Vec<PathBuf> = install_dir(program).into_iter().collect();
+        read_only.extend(clangd_config_dir());
+        read_only.extend(config.read_only.iter().cloned());
+        Ok(Self {
+            tool: config.tool,
+            workspace,
+            writable,
+            read_only,
+            network: config.network,
+            extra_args: config.args.clone(),
+        })
+    }
+
+    /// 在沙箱中启动后端的参数，沙箱程序是 [`SandboxTool::program`]。
+    ///
+    /// # 参数
+    ///
+    /// * `program` - 后端程序
+    /// * `args` - 后端的参数
+    pub fn wrap_args(&self, program: &str, args: &[String]) -> Vec<String> {
+        let mut wrapped: Vec<String> = Vec::new();
+        let mut push = |parts: &[&str]| wrapped.extend(parts.iter().map(|part| part.to_string()));
+        match self.tool {
+            SandboxTool::Bwrap => {
+                for dir in SYSTEM_DIRS {
+                    push(&["--ro-bind-try", dir, dir]);
+                }
+                for dir in &self.read_only {
+                    let dir = dir.display().to_string();
+                    push(&["--ro-bind-try", &dir, &dir]);
+                }
+                // 可写的目录在只读目录之后挂载，位于只读目录之中时覆盖它们
+                for dir in &self.writable {
+                    let dir = dir.display().to_string();
+                    push(&["--bind-try", &dir, &dir]);
+                }
+                push(&["--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"]);
+                push(&["--unshare-all"]);
+                if self.network {
+                    push(&["--share-net"]);
+                }
+                let workspace = self.workspace.display().to_string();
+                push(&["--die-with-parent", "--new-session", "--chdir", &workspace]);
+            }
+            SandboxTool::Firejail => {
+                push(&["--quiet", "--noprofile", "--private-tmp"]);
+                push(&["--caps.drop=all", "--nonewprivs", "--nogroups"]);
+                if !self.network {
+                    push(&["--net=none"]);
+                }
+                for dir in &self.writable {
+                    push(&[&format!("--whitelist={}", dir.display())]);
+                }
+                for dir in &self.read_only {
+                    push(&[&format!("--whitelist={}", dir.display())]);
+                    push(&[&format!("--read-only={}", dir.display())]);
+                }
+            }
+        }
+        wrapped.extend(self.extra_args.iter().cloned());
+        if self.tool == SandboxTool::Bwrap {
+            wrapped.push("--".to_string());
+        }
+        wrapped.push(program.to_string());
+        wrapped.extend(args.iter().cloned());
+        wrapped
+    }
+}
+
+/// 检查沙箱配置：`args` 中的每一项都必须是选项。沙箱程序在第一个不是选项的参数处停止解析，
+/// 把它当作要运行的程序，`args = ["/bin/sh", ...]` 会在沙箱外的参数中插入另一个程序。
+///
+/// # 错误
+///
+/// `args` 中有不以 `-` 开头的值或者 `--` 时返回错误
+pub fn validate(config: &Option<SandboxConfig>) -> Result<()> {
+    let Some(config) = config else {
+        return Ok(());
+    };
+    for arg in &config.args {
+        if !arg.starts_with('-') || arg == "--" {
+            bail!(
+                "[backend.sandbox] args 中只能是选项（例如 --option=value），不能是 {:?}；\
+                 允许访问的目录请使用 writable 和 read_only",
+                arg
+            );
+        }
+    }
+    Ok(())
+}
+
+/// clangd 的缓存目录：启动参数中的 `XDG_CACHE_HOME`，否则是代理的 `XDG_CACHE_HOME` 或 `~/.cache`，再加上 `clangd`。
+fn clangd_cache_dir(envs: &[(String, String)]) -> Option<PathBuf> {
+    let configured = envs
+        .iter()
+        .find(|(key, _)| key == "XDG_CACHE_HOME")
+        .map(|(_, value)| PathBuf::from(value));
+    let base = match configured.or_else(|| std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from)) {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("clangd"))
+}
+
+/// clangd 的用户配置目录（`config.yaml` 所在的目录）。
+fn clangd_config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("clangd"))
+}
+
+/// 后端程序的安装目录：程序在 `bin` 目录中时是 `bin` 的上一级（clangd 从 `../lib/clang` 找到自己的头文件），
+/// 否则是程序所在的目录。符号链接按实际的文件计算。
+pub fn install_dir(program: &str) -> Option<PathBuf> {
+    let program = platform::resolve_program(program)?;
+    let program = program.canonicalize().unwrap_or(program);
+    let dir = program.parent()?;
+    let dir = match dir.file_name() {
+        Some(name) if name == "bin" => dir.parent().unwrap_or(dir),
+        _ => dir,
+    };
+    Some(Path::to_path_buf(dir))
+}
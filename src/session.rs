@@ -0,0 +1,410 @@
+//! # 会话保持模块
+//!
+//! 编辑器重新加载窗口时会断开与代理的连接。配置了 `[session] grace_secs` 并以 `--listen` 监听时，
+//! 代理在宽限期内保留后端和文档状态，等待编辑器重新连接：`initialize` 由代理以上次的结果应答，
+//! 内容未变的 `didOpen` 不再交给后端而是重放最近的诊断，后端动态注册的能力重新注册到新的编辑器。
+//!
+//! 重新连接的编辑器从 1 开始编号请求，每个连接的请求 id 加上连接编号再交给调度器，
+//! 发给前端的响应去掉编号，其他连接的请求的响应被丢弃。编辑器断开时，代理取消它还在等待的请求，
+//! 并以错误应答转发给它、还没有得到响应的请求。
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use serde_json::{Value, json};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tower_lsp::lsp_types::notification::{self, Notification};
+use tower_lsp::lsp_types::request::{self, Request};
+
+use crate::batch::BatchTracker;
+use crate::compression::FrameCompression;
+use crate::config::TransportConfig;
+use crate::dispatcher::Dispatcher;
+use crate::message::Message;
+use crate::tasks::{HandlerLimiter, receive_data_frontend, send_data_frontend};
+use crate::transport::{FrontendListener, FrontendReader, FrontendWriter};
+
+/// 调度器记录的会话状态，编辑器重新连接时用来恢复会话。
+#[derive(Default)]
+pub struct SessionState {
+    /// 发给前端的 `initialize` 结果
+    initialize_result: RwLock<Option<Value>>,
+    /// 后端动态注册、已经转发给前端的能力
+    registrations: Mutex<Vec<Value>>,
+    /// 编辑器断开后保留会话，前端的 `shutdown` 和 `exit` 不再交给后端
+    keep_alive: AtomicBool,
+    /// 当前连接的编辑器是重新连接的
+    resumed: AtomicBool,
+    /// 当前连接的编号，第一个连接为 0，它的请求 id 保持不变
+    connection: AtomicU64,
+    /// 发给当前连接、还没有得到响应的请求的 id
+    outstanding: Mutex<Vec<Value>>,
+}
+
+/// LSP 的 `RequestCancelled` 错误码。
+const REQUEST_CANCELLED: i64 = -32800;
+
+/// 重新连接的编辑器的请求 id 的前缀，后面是连接编号、冒号和原来的 id。
+const RECONNECT_ID_PREFIX: &str = "reconnect";
+
+/// 给连接的请求 id 加上连接编号。
+fn namespaced(connection: u64, id: &Value) -> Value {
+    json!(format!("{RECONNECT_ID_PREFIX}{connection}:{id}"))
+}
+
+impl SessionState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录转发给前端的消息中需要在重新连接时恢复的状态。
+    ///
+    /// # 参数
+    ///
+    /// * `method` - 消息的方法，响应是对应请求的方法
+    /// * `rpc` - 转发给前端的消息
+    pub fn record(&self, method: Option<&str>, rpc: &Value) {
+        match method {
+            Some(request::Initialize::METHOD) => {
+                if let Some(result) = rpc.get("result") {
+                    *self.initialize_result.write().unwrap() = Some(result.clone());
+                }
+            }
+            Some(request::RegisterCapability::METHOD) => {
+                let added = rpc
+                    .pointer("/params/registrations")
+                    .and_then(|r| r.as_array());
+                self.registrations
+                    .lock()
+                    .unwrap()
+                    .extend(added.into_iter().flatten().cloned());
+            }
+            Some(request::UnregisterCapability::METHOD) => {
+                // 协议中的字段名就是 unregisterations
+                let removed: Vec<_> = rpc
+                    .pointer("/params/unregisterations")
+                    .and_then(|r| r.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|r| r.get("id").cloned())
+                    .collect();
+                self.registrations
+                    .lock()
+                    .unwrap()
+                    .retain(|r| !r.get("id").is_some_and(|id| removed.contains(id)));
+            }
+            _ => {}
+        }
+    }
+
+    /// 编辑器断开后保留会话。
+    pub fn keep_alive(&self) {
+        self.keep_alive.store(true, Ordering::Relaxed);
+    }
+
+    /// 是否在编辑器断开后保留会话。
+    pub fn is_kept_alive(&self) -> bool {
+        self.keep_alive.load(Ordering::Relaxed)
+    }
+
+    /// 编辑器重新连接，之后它的 `initialize` 由代理应答，请求 id 加上新的连接编号。
+    pub fn resume(&self) {
+        self.resumed.store(true, Ordering::Relaxed);
+        self.connection.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 处理从当前连接读到的消息：重新连接的编辑器的请求 id 和取消的请求 id 加上连接编号，
+    /// 前端的响应说明对应的请求已经得到应答。
+    pub fn incoming(&self, rpc: &mut Value) {
+        let Some(method) = rpc.get("method").and_then(|m| m.as_str()) else {
+            if let Some(id) = rpc.get("id") {
+                self.outstanding
+                    .lock()
+                    .unwrap()
+                    .retain(|pending| pending != id);
+            }
+            return;
+        };
+        let connection = self.connection.load(Ordering::Relaxed);
+        if connection == 0 {
+            return;
+        }
+        let target = if method == notification::Cancel::METHOD {
+            rpc.pointer_mut("/params/id")
+        } else {
+            rpc.get_mut("id")
+        };
+        if let Some(id) = target {
+            *id = namespaced(connection, id);
+        }
+    }
+
+    /// 处理写给当前连接的消息：响应去掉连接编号，其他连接的请求的响应返回 `None`；
+    /// 记录发给前端的请求，断开时由 [`SessionState::detach`] 取出。
+    pub fn outgoing(&self, mut rpc: Value) -> Option<Value> {
+        let Some(id) = rpc.get("id").filter(|id| !id.is_null()) else {
+            return Some(rpc);
+        };
+        if rpc.get("method").is_some() {
+            self.outstanding.lock().unwrap().push(id.clone());
+            return Some(rpc);
+        }
+        let connection = self.connection.load(Ordering::Relaxed);
+        if connection == 0 {
+            return Some(rpc);
+        }
+        let prefix = format!("{RECONNECT_ID_PREFIX}{connection}:");
+        let original = id
+            .as_str()
+            .and_then(|id| id.strip_prefix(&prefix))
+            .and_then(|id| serde_json::from_str::<Value>(id).ok());
+        match original {
+            Some(original) => {
+                rpc["id"] = original;
+                Some(rpc)
+            }
+            None => {
+                debug!("丢弃断开的编辑器的请求 {} 的响应", id);
+                None
+            }
+        }
+    }
+
+    /// 当前连接断开，返回转发给它、还没有得到响应的请求的 id。
+    pub fn detach(&self) -> Vec<Value> {
+        std::mem::take(&mut *self.outstanding.lock().unwrap())
+    }
+
+    /// 当前连接的编辑器是否是重新连接的。
+    pub fn is_resumed(&self) -> bool {
+        self.resumed.load(Ordering::Relaxed)
+    }
+
+    /// 上次发给前端的 `initialize` 结果。
+    pub fn initialize_result(&self) -> Option<Value> {
+        self.initialize_result.read().unwrap().clone()
+    }
+
+    /// 需要重新注册到编辑器的能力。
+    pub fn registrations(&self) -> Vec<Value> {
+        self.registrations.lock().unwrap().clone()
+    }
+}
+
+/// 写入一个连接的通道和写出消息的任务。
+type Attached = (UnboundedSender<Message>, JoinHandle<Result<()>>);
+
+/// 当前连接的前端。
+#[derive(Default)]
+pub struct FrontendConnection {
+    current: Mutex<Option<Attached>>,
+}
+
+impl FrontendConnection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 把发给前端的消息交给当前连接，没有连接的前端时返回 `false`。
+    pub fn send(&self, message: Message) -> bool {
+        let current = self.current.lock().unwrap();
+        current
+            .as_ref()
+            .is_some_and(|(sender, _)| sender.send(message).is_ok())
+    }
+
+    fn attach(&self, sender: UnboundedSender<Message>, task: JoinHandle<Result<()>>) {
+        *self.current.lock().unwrap() = Some((sender, task));
+    }
+
+    /// 前端已经断开，写给它的消息不必再写完。
+    fn detach(&self) {
+        if let Some((_, task)) = self.current.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+
+    /// 不再有发给前端的消息，等待当前连接写完。
+    async fn finish(&self) -> Result<()> {
+        let current = self.current.lock().unwrap().take();
+        match current {
+            Some((sender, task)) => {
+                drop(sender);
+                task.await?
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// 把调度器发给前端的消息交给当前连接的前端，前端断开期间的消息被丢弃。
+///
+/// 与 [`send_data_frontend`] 一样，`closing` 取消后写完已经排队的消息再返回。
+///
+/// # 错误
+///
+/// 如果当前连接写出消息失败，返回错误
+pub async fn forward_to_connection(
+    mut rx: UnboundedReceiver<Message>,
+    connection: Arc<FrontendConnection>,
+    closing: CancellationToken,
+) -> Result<()> {
+    loop {
+        let message = tokio::select! {
+            message = rx.recv() => message,
+            _ = closing.cancelled(), if !rx.is_closed() => {
+                rx.close();
+                continue;
+            }
+        };
+        let Some(message) = message else {
+            break;
+        };
+        if !connection.send(message) {
+            debug!("前端已断开，丢弃发给前端的消息");
+        }
+    }
+    connection.finish().await
+}
+
+/// 以 `--listen` 监听并保留会话时的前端连接。
+pub struct SessionKeeper {
+    listener: FrontendListener,
+    grace: Duration,
+    transport: TransportConfig,
+    connection: Arc<FrontendConnection>,
+}
+
+impl SessionKeeper {
+    /// # 参数
+    ///
+    /// * `listener` - 编辑器重新连接的监听套接字
+    /// * `grace` - 编辑器断开后保留会话的时间
+    /// * `transport` - `[transport]` 配置，每个连接重新协商压缩
+    pub fn new(listener: FrontendListener, grace: Duration, transport: TransportConfig) -> Self {
+        Self {
+            listener,
+            grace,
+            transport,
+            connection: Arc::new(FrontendConnection::new()),
+        }
+    }
+
+    /// 当前连接的前端，交给 [`forward_to_connection`]。
+    pub fn connection(&self) -> Arc<FrontendConnection> {
+        Arc::clone(&self.connection)
+    }
+
+    /// 依次服务每个连接的编辑器，编辑器断开后在宽限期内等待它重新连接。
+    ///
+    /// 宽限期内没有编辑器连接时按 LSP 的方式结束后端并返回。
+    ///
+    /// # 参数
+    ///
+    /// * `first` - 第一个连接的编辑器
+    /// * `dispatcher` - 调度器实例
+    /// * `limiter` - 处理任务的并发上限
+    /// * `closing` - 代理退出时取消，连接写完排队的消息
+    ///
+    /// # 错误
+    ///
+    /// 如果监听的套接字出错，返回错误
+    pub async fn serve(
+        self,
+        first: (FrontendReader, FrontendWriter),
+        dispatcher: Arc<Dispatcher>,
+        limiter: Arc<HandlerLimiter>,
+        closing: CancellationToken,
+    ) -> Result<()> {
+        dispatcher.session().keep_alive();
+        let (mut reader, mut writer) = first;
+        loop {
+            let batches = Arc::new(BatchTracker::new());
+            let compression = FrameCompression::new(&self.transport, dispatcher.metrics());
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let receiver = restore_ids(receiver, Arc::clone(&dispatcher));
+            let send = tokio::spawn(send_data_frontend(
+                writer,
+                receiver,
+                Arc::clone(&batches),
+                closing.clone(),
+                compression.clone(),
+            ));
+            self.connection.attach(sender, send);
+            let received = receive_data_frontend(
+                reader,
+                Arc::clone(&dispatcher),
+                Arc::clone(&limiter),
+                batches,
+                compression,
+            )
+            .await;
+            self.connection.detach();
+            if let Err(e) = received {
+                warn!("前端连接出错: {:?}", e);
+            }
+            abandon_requests(&dispatcher).await;
+
+            info!("前端已断开，会话保留 {} 秒", self.grace.as_secs());
+            (reader, writer) = match tokio::time::timeout(self.grace, self.listener.accept()).await
+            {
+                Ok(accepted) => accepted?,
+                Err(_) => {
+                    info!("宽限期内前端没有重新连接，结束会话");
+                    dispatcher.shutdown_backends().await;
+                    return Ok(());
+                }
+            };
+            info!("前端重新连接，继续会话");
+            dispatcher.session().resume();
+        }
+    }
+}
+
+/// 在写给连接的消息中去掉请求 id 的连接编号，丢弃其他连接的请求的响应。
+fn restore_ids(
+    mut rx: UnboundedReceiver<Message>,
+    dispatcher: Arc<Dispatcher>,
+) -> UnboundedReceiver<Message> {
+    let (tx, restored) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let Some(rpc) = dispatcher.session().outgoing(message.into_body()) else {
+                continue;
+            };
+            if tx.send(Message::new(rpc)).is_err() {
+                break;
+            }
+        }
+    });
+    restored
+}
+
+/// 编辑器断开后不会再应答转发给它的请求，也不再需要它还在等待的响应：
+/// 以错误应答前者，让后端或代理中的等待结束；取消后者，它们的响应不会交给下一个连接。
+async fn abandon_requests(dispatcher: &Arc<Dispatcher>) {
+    for id in dispatcher.session().detach() {
+        let response = json!({"jsonrpc": "2.0", "id": id, "error": {
+            "code": REQUEST_CANCELLED,
+            "message": "编辑器已断开",
+        }});
+        if let Err(e) = dispatcher.handle_from_frontend(response).await {
+            warn!("应答发给断开的编辑器的请求失败: {:?}", e);
+        }
+    }
+    for (id, method) in dispatcher.pending_request_methods() {
+        let Ok(id) = serde_json::from_str::<Value>(&id) else {
+            continue;
+        };
+        debug!("编辑器已断开，取消请求 {} ({})", id, method);
+        let cancel = json!({"jsonrpc": "2.0", "method": notification::Cancel::METHOD,
+            "params": {"id": id}});
+        if let Err(e) = dispatcher.handle_from_frontend(cancel).await {
+            warn!("取消断开的编辑器的请求失败: {:?}", e);
+        }
+    }
+}
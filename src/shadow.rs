@@ -0,0 +1,358 @@
+//! # 影子后端模块
+//!
+//! 升级 clangd 之前，可以让新版本作为影子后端和正在使用的后端一起运行：文档同步通知全部转发给影子后端，
+//! 只读请求按 `[shadow] sample_rate` 抽样镜像过去。影子后端的响应不会交给前端，等两个后端都响应之后
+//! 在后台比较，不一致的请求连同两边的结果和耗时写入差异报告（每行一个 JSON），
+//! 镜像、一致和不一致的次数通过自定义请求 `codefuse/shadow` 查询。
+//!
+//! 只镜像可以重放的只读请求（[`REPLAYABLE_METHODS`]），影子后端不会修改任何东西；
+//! 它发来的请求由客户端应答 `null`，通知被忽略。
+
+use anyhow::{Result, bail};
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use log::{debug, info, warn};
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tower_lsp::lsp_types::notification::{self, Notification};
+use tower_lsp::lsp_types::request::{self, Request};
+
+use crate::client::LspClient;
+use crate::config::ShadowConfig;
+use crate::content_modified::CONTENT_MODIFIED;
+use crate::dispatcher::{DEFAULT_HANDLER_PRIORITY, Dispatcher};
+use crate::handlers::{self, HandlerCtx, Verdict};
+use crate::replay::REPLAYABLE_METHODS;
+use crate::trace::Direction;
+
+/// 查询镜像统计的自定义请求，没有参数；没有配置影子后端时结果为 `null`。
+pub const SHADOW: &str = "codefuse/shadow";
+
+/// 等待两个后端响应的最长时间，超时的请求不参与比较。
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// LSP 的 `RequestCancelled` 错误码。
+const REQUEST_CANCELLED: i64 = -32800;
+
+/// 检查 `[shadow]` 的设置。
+///
+/// # 错误
+///
+/// `sample_rate` 不在 0 到 1 之间，或者 `methods` 中有不能重放的请求时返回错误
+pub fn validate(config: &ShadowConfig) -> Result<()> {
+    if !(0.0..=1.0).contains(&config.sample_rate) {
+        bail!("shadow.sample_rate 必须在 0 到 1 之间");
+    }
+    if let Some(method) = config
+        .methods
+        .iter()
+        .find(|method| !REPLAYABLE_METHODS.contains(&method.as_str()))
+    {
+        bail!("shadow.methods 中的 {} 不是只读请求，不能镜像", method);
+    }
+    Ok(())
+}
+
+/// 一次比较的结果。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// 两个后端的结果相同
+    Matched,
+    /// 结果不同
+    Diverged,
+    /// 请求被取消或者文档已经被修改，结果不可比较
+    Skipped,
+}
+
+/// 比较两个后端对同一个请求的响应。
+///
+/// 结果中的 `data` 字段是后端给自己留的数据（例如补全项解析时需要的信息），不同版本之间本来就不同，
+/// 比较时去掉；错误响应只比较错误码。
+pub fn compare(primary: &Value, shadow: &Value) -> Comparison {
+    let (primary, shadow) = (outcome(primary), outcome(shadow));
+    let incomparable = |outcome: &Value| {
+        matches!(
+            outcome.get("error").and_then(|code| code.as_i64()),
+            Some(REQUEST_CANCELLED | CONTENT_MODIFIED)
+        )
+    };
+    if incomparable(&primary) || incomparable(&shadow) {
+        Comparison::Skipped
+    } else if primary == shadow {
+        Comparison::Matched
+    } else {
+        Comparison::Diverged
+    }
+}
+
+/// 比较用的响应：结果去掉 `data` 字段，错误只保留错误码。
+fn outcome(response: &Value) -> Value {
+    match response.get("error") {
+        Some(error) => json!({"error": error.get("code")}),
+        None => without_data(response.get("result").unwrap_or(&Value::Null)),
+    }
+}
+
+fn without_data(value: &Value) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(items.iter().map(without_data).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .filter(|(key, _)| key.as_str() != "data")
+                .map(|(key, field)| (key.clone(), without_data(field)))
+                .collect::<Map<String, Value>>(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// 影子后端的镜像统计。
+///
+/// - `command`: 影子后端的启动命令
+/// - `mirrored`: 镜像的请求数
+/// - `matched`/`diverged`: 结果一致和不一致的请求数
+/// - `skipped`: 被取消、文档已被修改或者主后端没有响应的请求数
+/// - `failed`: 影子后端没有响应或者无法发送的请求数
+/// - `report`: 差异报告的路径
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ShadowStats {
+    pub command: String,
+    pub mirrored: u64,
+    pub matched: u64,
+    pub diverged: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub report: PathBuf,
+}
+
+#[derive(Default)]
+struct Counters {
+    mirrored: AtomicU64,
+    matched: AtomicU64,
+    diverged: AtomicU64,
+    skipped: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// 运行中的影子后端。
+pub struct Shadow {
+    command: String,
+    client: LspClient,
+    methods: Vec<String>,
+    sample_rate: f64,
+    /// 抽样的累加器，每个请求加上 `sample_rate`，满 1 时镜像
+    sampled: Mutex<f64>,
+    report: PathBuf,
+    /// 已经镜像、等待主后端响应的请求，键是请求 id
+    primary: DashMap<String, oneshot::Sender<Value>>,
+    counters: Counters,
+}
+
+impl Shadow {
+    /// 按 `[shadow]` 配置启动影子后端，没有设置 `command` 时返回 `None`。
+    ///
+    /// # 错误
+    ///
+    /// 如果影子后端无法启动，返回错误
+    pub fn spawn(config: &ShadowConfig) -> Result<Option<Arc<Self>>> {
+        let Some(command) = &config.command else {
+            return Ok(None);
+        };
+        let client = LspClient::spawn(command, &config.args)?;
+        let methods = if config.methods.is_empty() {
+            REPLAYABLE_METHODS.iter().map(|m| m.to_string()).collect()
+        } else {
+            config.methods.clone()
+        };
+        let report = config
+            .report
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("codefuse-shadow.ndjson"));
+        info!(
+            "影子后端 {}，镜像 {}% 的只读请求，差异写入 {}",
+            command,
+            config.sample_rate * 100.0,
+            report.display()
+        );
+        Ok(Some(Arc::new(Self {
+            command: command.clone(),
+            client,
+            methods,
+            sample_rate: config.sample_rate,
+            sampled: Mutex::new(0.0),
+            report,
+            primary: DashMap::new(),
+            counters: Counters::default(),
+        })))
+    }
+
+    /// 把转发给主后端的消息镜像给影子后端。
+    ///
+    /// 通知（取消和 `exit` 除外）和 `initialize` 总是转发，影子后端才能和主后端看到同样的文档；
+    /// 配置的只读请求按比例抽样。
+    pub fn mirror(self: &Arc<Self>, rpc: &Value) {
+        let Some(method) = rpc.get("method").and_then(|m| m.as_str()) else {
+            return;
+        };
+        let params = rpc.get("params").cloned().unwrap_or(Value::Null);
+        let sent = match rpc.get("id") {
+            None if matches!(
+                method,
+                notification::Cancel::METHOD | notification::Exit::METHOD
+            ) =>
+            {
+                return;
+            }
+            None => self.client.notify(method, params),
+            // 影子后端的能力不影响前端，响应直接丢弃
+            Some(_) if method == request::Initialize::METHOD => {
+                self.client.send_request(method, params).map(drop)
+            }
+            Some(id) if self.methods.iter().any(|m| m == method) && self.sample() => {
+                self.compare_later(id, method, params);
+                return;
+            }
+            Some(_) => return,
+        };
+        if let Err(e) = sent {
+            debug!("无法把 {} 发给影子后端: {:?}", method, e);
+        }
+    }
+
+    /// 是否镜像这个请求。
+    fn sample(&self) -> bool {
+        let mut sampled = self.sampled.lock().unwrap();
+        *sampled += self.sample_rate;
+        if *sampled >= 1.0 {
+            *sampled -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 把请求发给影子后端，在后台等待两个后端的响应并比较。
+    fn compare_later(self: &Arc<Self>, id: &Value, method: &str, params: Value) {
+        let shadow = match self.client.send_request(method, params.clone()) {
+            Ok(shadow) => shadow,
+            Err(e) => {
+                debug!("无法把 {} 发给影子后端: {:?}", method, e);
+                self.counters.failed.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+        let (waiter, primary) = oneshot::channel();
+        let key = id.to_string();
+        self.primary.insert(key.clone(), waiter);
+        self.counters.mirrored.fetch_add(1, Ordering::Relaxed);
+
+        let this = Arc::clone(self);
+        let method = method.to_string();
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let timed = |response: oneshot::Receiver<Value>| async move {
+                let response = tokio::time::timeout(RESPONSE_TIMEOUT, response).await;
+                (response.ok().and_then(Result::ok), started.elapsed())
+            };
+            let ((primary, primary_time), (shadow, shadow_time)) =
+                tokio::join!(timed(primary), timed(shadow));
+            this.primary.remove(&key);
+            let (primary, shadow) = match (primary, shadow) {
+                (Some(primary), Some(shadow)) => (primary, shadow),
+                (_, None) => {
+                    this.counters.failed.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                (None, Some(_)) => {
+                    this.counters.skipped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            };
+            match compare(&primary, &shadow) {
+                Comparison::Matched => {
+                    this.counters.matched.fetch_add(1, Ordering::Relaxed);
+                }
+                Comparison::Skipped => {
+                    this.counters.skipped.fetch_add(1, Ordering::Relaxed);
+                }
+                Comparison::Diverged => {
+                    this.counters.diverged.fetch_add(1, Ordering::Relaxed);
+                    let body = |response: &Value| {
+                        response
+                            .get("error")
+                            .map(|error| json!({"error": error}))
+                            .unwrap_or_else(|| response.get("result").cloned().unwrap_or_default())
+                    };
+                    this.append(&json!({
+                        "timestamp": chrono::Utc::now().to_rfc3339(),
+                        "method": method,
+                        "params": params,
+                        "primary": body(&primary),
+                        "shadow": body(&shadow),
+                        "primaryMs": primary_time.as_millis() as u64,
+                        "shadowMs": shadow_time.as_millis() as u64,
+                    }));
+                }
+            }
+        });
+    }
+
+    /// 主后端对请求的响应到达。没有镜像的请求直接忽略。
+    pub fn primary_responded(&self, id: &Value, response: &Value) {
+        if let Some((_, waiter)) = self.primary.remove(&id.to_string()) {
+            let _ = waiter.send(response.clone());
+        }
+    }
+
+    /// 在差异报告中追加一行。
+    fn append(&self, entry: &Value) {
+        let written = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.report)
+            .and_then(|mut file| writeln!(file, "{}", entry));
+        if let Err(e) = written {
+            warn!("无法写入差异报告 {}: {}", self.report.display(), e);
+        }
+    }
+
+    /// 镜像统计。
+    pub fn stats(&self) -> ShadowStats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        ShadowStats {
+            command: self.command.clone(),
+            mirrored: load(&self.counters.mirrored),
+            matched: load(&self.counters.matched),
+            diverged: load(&self.counters.diverged),
+            skipped: load(&self.counters.skipped),
+            failed: load(&self.counters.failed),
+            report: self.report.clone(),
+        }
+    }
+}
+
+/// 注册 `codefuse/shadow` 的处理器，由代理直接应答。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        SHADOW,
+        DEFAULT_HANDLER_PRIORITY,
+        answer_stats,
+    );
+}
+
+/// 以影子后端的对比统计应答，没有影子后端时结果是 `null`。
+fn answer_stats(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let stats = ctx.dispatcher().shadow().map(|shadow| shadow.stats());
+        Ok(Verdict::Respond(handlers::response(&rpc, Ok(json!(stats)))))
+    })
+}
@@ -0,0 +1,227 @@
+//! # 分片模块
+//!
+//! 对于巨大的单体仓库，可以为配置的每个子树启动一个独立的 clangd（分片），
+//! 这个模块负责按文档 URI 前缀选择分片，合并需要发往所有分片的工作区级请求的结果和各分片对同一个文件的诊断，
+//! 以及把文件变更分给关心它们的分片。
+
+use dashmap::DashMap;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use tokio::sync::mpsc::UnboundedSender;
+use tower_lsp::lsp_types::request::{self, Request};
+use tower_lsp::lsp_types::{FileEvent, Url};
+
+use crate::message::Message;
+
+/// 一个后端分片。
+///
+/// - `name`: 日志中显示的名字
+/// - `root`: 分片负责的子树，`None` 表示默认分片，负责其余所有文档
+/// - `sender`: 向该分片的后端进程发送消息的通道
+#[derive(Clone)]
+pub struct Shard {
+    pub name: String,
+    pub root: Option<PathBuf>,
+    pub sender: UnboundedSender<Message>,
+}
+
+impl Shard {
+    /// 创建负责其余所有文档的默认分片。
+    pub fn default_shard(sender: UnboundedSender<Message>) -> Self {
+        Self {
+            name: "default".to_string(),
+            root: None,
+            sender,
+        }
+    }
+}
+
+/// 消息的路由结果。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    /// 只发送给指定的分片
+    Shard(usize),
+    /// 发送给所有分片并合并结果（工作区级查询）
+    FanOut,
+    /// 发送给所有分片，只把默认分片的结果返回给前端（生命周期消息）
+    Broadcast,
+}
+
+/// 选择包含给定文档的分片，没有匹配的子树时返回默认分片（下标 0）。
+///
+/// 子树可以嵌套，此时选择路径最长（最内层）的分片。
+pub fn shard_for_uri(shards: &[Shard], uri: &Url) -> usize {
+    let Ok(path) = uri.to_file_path() else {
+        return 0;
+    };
+    shards
+        .iter()
+        .enumerate()
+        .filter_map(|(i, shard)| Some((i, shard.root.as_ref()?)))
+        .filter(|(_, root)| path.starts_with(root))
+        .max_by_key(|(_, root)| root.components().count())
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+/// 决定一条来自前端的消息应该如何路由。
+pub fn route(shards: &[Shard], method: &str, rpc: &Value) -> Route {
+    if shards.len() <= 1 {
+        return Route::Shard(0);
+    }
+
+    match method {
+        request::WorkspaceSymbolRequest::METHOD | request::References::METHOD => {
+            return Route::FanOut;
+        }
+        request::Initialize::METHOD | request::Shutdown::METHOD => return Route::Broadcast,
+        _ => {}
+    }
+
+    if let Some(uri) = rpc
+        .pointer("/params/textDocument/uri")
+        .and_then(|u| u.as_str())
+        .and_then(|u| Url::parse(u).ok())
+    {
+        return Route::Shard(shard_for_uri(shards, &uri));
+    }
+
+    // 不针对具体文档的通知（initialized、exit、配置变化、取消请求等）需要所有分片都收到
+    if rpc.get("id").is_none() {
+        Route::Broadcast
+    } else {
+        Route::Shard(0)
+    }
+}
+
+/// 合并各分片对同一个工作区级请求的结果。
+///
+/// 结果按分片顺序拼接，按位置去重（同一个位置只保留第一个分片的结果）；不是数组的结果（例如 `null`）被忽略。
+pub fn merge_results(method: &str, results: Vec<Value>) -> Value {
+    let mut seen = HashSet::new();
+    let mut merged = Vec::new();
+    for result in results {
+        let Value::Array(items) = result else {
+            continue;
+        };
+        for item in items {
+            if seen.insert(dedup_key(method, &item)) {
+                merged.push(item);
+            }
+        }
+    }
+    Value::Array(merged)
+}
+
+/// 计算去重使用的键：符号使用名称加位置，诊断使用范围加消息，其余结果（`Location` 或 `LocationLink`）使用位置。
+///
+/// 不同分片对同一个位置附带的其他字段（例如 clangd 给引用加上的 `containerName`）可能不同，不参与比较。
+fn dedup_key(method: &str, item: &Value) -> String {
+    match method {
+        request::WorkspaceSymbolRequest::METHOD => format!(
+            "{}@{}",
+            item.get("name").and_then(|n| n.as_str()).unwrap_or(""),
+            item.get("location").map(location_key).unwrap_or_default()
+        ),
+        PUBLISH_DIAGNOSTICS => format!(
+            "{}@{}",
+            item.get("message").and_then(|m| m.as_str()).unwrap_or(""),
+            item.get("range").map(|r| r.to_string()).unwrap_or_default()
+        ),
+        _ => location_key(item),
+    }
+}
+
+/// 位置的文件和范围；`LocationLink` 使用目标文件和目标的选择范围。
+fn location_key(location: &Value) -> String {
+    let uri = location.get("uri").or_else(|| location.get("targetUri"));
+    let range = location
+        .get("range")
+        .or_else(|| location.get("targetSelectionRange"));
+    match (uri, range) {
+        (Some(uri), range) => format!(
+            "{}#{}",
+            uri,
+            range.map(|r| r.to_string()).unwrap_or_default()
+        ),
+        (None, _) => location.to_string(),
+    }
+}
+
+const PUBLISH_DIAGNOSTICS: &str = "textDocument/publishDiagnostics";
+
+/// 各分片对同一个文件发布的诊断。
+///
+/// 每个分片只知道自己的诊断，多个分片都发布了同一个文件的诊断时（例如共用的头文件），后发布的会覆盖先发布的。
+/// 这里记录每个分片最近发布的诊断，合并、去重之后再交给前端。
+#[derive(Default)]
+pub struct ShardDiagnostics {
+    published: DashMap<String, BTreeMap<usize, Vec<Value>>>,
+}
+
+impl ShardDiagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录分片发布的诊断。
+    ///
+    /// # 参数
+    ///
+    /// * `shard` - 发布诊断的分片
+    /// * `uri` - 诊断所属的文件
+    /// * `diagnostics` - 分片这次发布的全部诊断
+    ///
+    /// # 返回
+    ///
+    /// 所有分片的诊断按分片顺序合并、去重后的结果
+    pub fn merge(&self, shard: usize, uri: &str, diagnostics: Vec<Value>) -> Vec<Value> {
+        let mut published = self.published.entry(uri.to_string()).or_default();
+        if diagnostics.is_empty() {
+            published.remove(&shard);
+        } else {
+            published.insert(shard, diagnostics);
+        }
+        let merged = merge_results(
+            PUBLISH_DIAGNOSTICS,
+            published.values().cloned().map(Value::Array).collect(),
+        );
+        let empty = published.is_empty();
+        drop(published);
+        if empty {
+            self.published.remove(uri);
+        }
+        match merged {
+            Value::Array(merged) => merged,
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// 把文件变更分给关心它们的分片。
+///
+/// 子树中的文件只交给负责这个子树的分片；不属于任何子树的文件（例如仓库根目录的 `compile_commands.json`
+/// 和共用的头文件）每个分片都可能依赖，交给所有分片。没有变更的分片不出现在结果中。
+///
+/// # 返回
+///
+/// 按分片顺序排列的分片下标和交给它的变更
+pub fn split_file_changes(shards: &[Shard], changes: &[FileEvent]) -> Vec<(usize, Vec<FileEvent>)> {
+    let mut split: Vec<Vec<FileEvent>> = vec![Vec::new(); shards.len()];
+    for change in changes {
+        match shard_for_uri(shards, &change.uri) {
+            0 => {
+                for changes in &mut split {
+                    changes.push(change.clone());
+                }
+            }
+            shard => split[shard].push(change.clone()),
+        }
+    }
+    split
+        .into_iter()
+        .enumerate()
+        .filter(|(_, changes)| !changes.is_empty())
+        .collect()
+}
@@ -0,0 +1,88 @@
+//! # 退出模块
+//!
+//! 收到 SIGTERM / SIGINT（Windows 上是控制台的 Ctrl-C、Ctrl-Break 和关闭事件）时按 LSP 的方式退出：
+//! 向每个后端发送 `shutdown` 请求和 `exit` 通知，把还没写出的消息发给前端，然后以约定的退出码结束。
+//! 前端断开或后端退出时代理照常以 0 退出。
+
+use log::{error, info};
+
+/// 收到 SIGINT（Ctrl-C）后退出，按惯例是 128 + 2。
+pub const EXIT_INTERRUPTED: u8 = 130;
+/// 收到 SIGTERM 后退出，按惯例是 128 + 15。
+pub const EXIT_TERMINATED: u8 = 143;
+
+/// 请求代理退出的信号。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGINT，Windows 上的 Ctrl-C 和 Ctrl-Break
+    Interrupt,
+    /// SIGTERM，Windows 上的控制台关闭和系统关机
+    Terminate,
+}
+
+impl Signal {
+    /// 收到这个信号后代理的退出码。
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Signal::Interrupt => EXIT_INTERRUPTED,
+            Signal::Terminate => EXIT_TERMINATED,
+        }
+    }
+}
+
+/// 等待退出信号。
+///
+/// 无法监听某个信号时记录错误并忽略它，而不是立即返回。
+pub async fn signal() -> Signal {
+    let received = wait_for_signal().await;
+    info!("收到退出信号: {:?}", received);
+    received
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> Signal {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let listen = |kind: SignalKind| {
+        signal(kind)
+            .inspect_err(|e| error!("无法监听信号 {:?}: {}", kind, e))
+            .ok()
+    };
+    let mut terminate = listen(SignalKind::terminate());
+    let mut interrupt = listen(SignalKind::interrupt());
+    tokio::select! {
+        Some(()) = recv(&mut terminate) => Signal::Terminate,
+        Some(()) = recv(&mut interrupt) => Signal::Interrupt,
+        else => std::future::pending().await,
+    }
+}
+
+#[cfg(unix)]
+async fn recv(signal: &mut Option<tokio::signal::unix::Signal>) -> Option<()> {
+    match signal {
+        Some(signal) => signal.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(windows)]
+async fn wait_for_signal() -> Signal {
+    use tokio::signal::windows;
+
+    let (Ok(mut ctrl_c), Ok(mut ctrl_break), Ok(mut ctrl_close), Ok(mut ctrl_shutdown)) = (
+        windows::ctrl_c(),
+        windows::ctrl_break(),
+        windows::ctrl_close(),
+        windows::ctrl_shutdown(),
+    ) else {
+        error!("无法监听控制台事件");
+        return std::future::pending().await;
+    };
+    tokio::select! {
+        Some(()) = ctrl_c.recv() => Signal::Interrupt,
+        Some(()) = ctrl_break.recv() => Signal::Interrupt,
+        Some(()) = ctrl_close.recv() => Signal::Terminate,
+        Some(()) = ctrl_shutdown.recv() => Signal::Terminate,
+        else => std::future::pending().await,
+    }
+}
@@ -0,0 +1,345 @@
+//! # 签名帮助模块
+//!
+//! 后端没有应答 `textDocument/signatureHelp`（返回空结果、超过 `[signature_help] timeout_ms`，或者正在重启）时，
+//! 开启 `[signature_help] fallback` 后由代理合成一个简单的签名，输入参数时的提示不会突然消失。
+//!
+//! 代理从光标向前找到所在的调用和被调用的函数名，再按函数名查找声明：先查之前 hover 响应中的声明
+//! （clangd 的 hover 以代码块给出声明），再用 tree-sitter 在文档中查找函数声明。合成的签名只有标签和参数的位置，
+//! 重载时取找到的第一个声明。向前查找时不识别字符串和注释中的括号。
+
+use anyhow::Result;
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use serde_json::{Value, json};
+use std::ops::Range;
+use std::time::Duration;
+use tower_lsp::lsp_types::request::{Request, SignatureHelpRequest};
+use tree_sitter::Node;
+
+use crate::config::SignatureHelpConfig;
+use crate::dispatcher::Dispatcher;
+use crate::document_store::{self, DocumentStore};
+use crate::fallback::{Arrival, Cursor, FallbackRequests};
+use crate::handlers::{FALLBACK_PRIORITY, HandlerCtx, Verdict};
+use crate::keywords;
+use crate::syntax;
+use crate::trace::Direction;
+
+/// 最多记录的 hover 声明数，满了以后不再记录新的函数。
+const MAX_HOVERS: usize = 4096;
+
+/// 包含函数声明符的声明节点，合成的签名从这里开始，带上返回类型和修饰符。
+const DECLARATION_KINDS: &[&str] = &["declaration", "field_declaration", "function_definition"];
+
+/// hover 代码块中访问控制的前缀。
+const ACCESS_PREFIXES: &[&str] = &["public:", "protected:", "private:"];
+
+/// 后端没有应答时合成的签名帮助。
+pub struct SignatureFallback {
+    enabled: bool,
+    timeout: Duration,
+    /// 函数名 → 最近一次 hover 中的声明
+    hovers: DashMap<String, String>,
+    /// 等待后端应答的请求和光标位置
+    pending: FallbackRequests<Cursor>,
+}
+
+impl Default for SignatureFallback {
+    fn default() -> Self {
+        Self::new(&SignatureHelpConfig::default())
+    }
+}
+
+impl SignatureFallback {
+    pub fn new(config: &SignatureHelpConfig) -> Self {
+        Self {
+            enabled: config.fallback,
+            timeout: Duration::from_millis(config.timeout_ms),
+            hovers: DashMap::new(),
+            pending: FallbackRequests::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 等待后端的时间，超过后以合成的签名应答。
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// 前端的签名帮助请求交给后端之前调用。
+    pub fn request(&self, id: &Value, cursor: Cursor) {
+        self.pending.insert(id, cursor);
+    }
+
+    /// 记录 hover 响应中的函数声明。
+    pub fn learn_hover(&self, result: &Value) {
+        let Some(declaration) = hover_declaration(result) else {
+            return;
+        };
+        let Some(name) = declared_name(&declaration) else {
+            return;
+        };
+        if self.hovers.len() < MAX_HOVERS || self.hovers.contains_key(name) {
+            self.hovers.insert(name.to_string(), declaration);
+        }
+    }
+
+    /// 后端的签名帮助响应到达，结果为空时换成合成的签名。后端返回错误时原样转发。
+    ///
+    /// # 返回
+    ///
+    /// 后端超过等待时间、代理已经应答时返回 `false`，响应应当丢弃
+    pub fn respond(&self, rpc: &mut Value, documents: &DocumentStore) -> bool {
+        let Some(id) = rpc.get("id") else {
+            return true;
+        };
+        let cursor = match self.pending.arrive(id) {
+            Arrival::Waiting(cursor) => cursor,
+            Arrival::Late => return false,
+            Arrival::Untracked => return true,
+        };
+        if let Some(result) = rpc.get_mut("result")
+            && is_empty(result)
+        {
+            *result = self.synthesize(&cursor, documents);
+        }
+        true
+    }
+
+    /// 后端超过了等待时间，改由代理应答。
+    ///
+    /// # 返回
+    ///
+    /// 应答前端的签名帮助，找不到声明时为 `null`；后端已经响应时返回 `None`
+    pub fn expire(&self, id: &Value, shard: usize, documents: &DocumentStore) -> Option<Value> {
+        let cursor = self.pending.expire(id, shard)?;
+        Some(self.synthesize(&cursor, documents))
+    }
+
+    /// `shard` 分片的后端退出，不再等待它的响应。
+    pub fn backend_exited(&self, shard: usize) {
+        self.pending.backend_exited(shard);
+    }
+
+    /// 光标所在调用的签名，找不到调用或者声明时为 `null`。
+    fn synthesize(&self, cursor: &Cursor, documents: &DocumentStore) -> Value {
+        let Some(doc) = cursor.document(documents) else {
+            return Value::Null;
+        };
+        let before = &doc.text[..document_store::offset_at(&doc.text, cursor.position)];
+        let Some((name, active_parameter)) = call_at(before) else {
+            return Value::Null;
+        };
+        let declaration = match self.hovers.get(name) {
+            Some(declaration) => Some(declaration.clone()),
+            None => find_declaration(&doc.text, name),
+        };
+        declaration
+            .and_then(|declaration| signature(&declaration, active_parameter))
+            .unwrap_or(Value::Null)
+    }
+}
+
+/// 后端的结果中没有任何签名。
+fn is_empty(result: &Value) -> bool {
+    result.is_null()
+        || result
+            .get("signatures")
+            .and_then(|signatures| signatures.as_array())
+            .is_some_and(|signatures| signatures.is_empty())
+}
+
+/// 光标之前的内容所在的调用：被调用的函数名和光标处是第几个参数（从 0 开始）。
+///
+/// 括号前不是函数名（括号表达式、`if (` 等）时继续向外找；遇到语句或者代码块的边界时停止。
+pub fn call_at(before: &str) -> Option<(&str, u32)> {
+    let mut depth = 0usize;
+    let mut commas = 0;
+    for (i, c) in before.char_indices().rev() {
+        match c {
+            ')' | ']' | '}' => depth += 1,
+            '(' | '[' | '{' if depth > 0 => depth -= 1,
+            '(' => {
+                let name = document_store::word_before(before[..i].trim_end());
+                if !name.is_empty()
+                    && !name.starts_with(|c: char| c.is_ascii_digit())
+                    && !keywords::CPP_KEYWORDS.contains(&name)
+                {
+                    return Some((name, commas));
+                }
+                commas = 0;
+            }
+            '[' => commas = 0,
+            '{' | ';' => return None,
+            ',' if depth == 0 => commas += 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// hover 结果中最后一个代码块里的函数声明，去掉注释行和访问控制前缀，空白合并成一个空格。
+pub fn hover_declaration(result: &Value) -> Option<String> {
+    let code = match result.get("contents")? {
+        Value::Object(marked) if marked.contains_key("language") => {
+            marked.get("value")?.as_str()?.to_string()
+        }
+        Value::Object(markup) => last_code_block(markup.get("value")?.as_str()?)?,
+        Value::String(markdown) => last_code_block(markdown)?,
+        Value::Array(parts) => parts
+            .iter()
+            .rev()
+            .find_map(|part| part.get("value")?.as_str())?
+            .to_string(),
+        _ => return None,
+    };
+    let lines: Vec<_> = code
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with("//"))
+        .collect();
+    let mut declaration = collapse(&lines.join(" "));
+    for prefix in ACCESS_PREFIXES {
+        if let Some(rest) = declaration.strip_prefix(prefix) {
+            declaration = rest.trim_start().to_string();
+        }
+    }
+    declared_name(&declaration)?;
+    Some(declaration)
+}
+
+/// Markdown 中最后一个围栏代码块的内容。
+fn last_code_block(markdown: &str) -> Option<String> {
+    let mut block = None;
+    let mut current: Option<Vec<&str>> = None;
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            match current.take() {
+                Some(lines) => block = Some(lines.join("\n")),
+                None => current = Some(Vec::new()),
+            }
+        } else if let Some(lines) = &mut current {
+            lines.push(line);
+        }
+    }
+    block
+}
+
+/// 文档中名为 `name` 的第一个函数声明，从返回类型到参数列表之后的修饰符，空白合并成一个空格。
+pub fn find_declaration(text: &str, name: &str) -> Option<String> {
+    let tree = syntax::parse(text)?;
+    let declarator = find_declarator(tree.root_node(), text, name)?;
+    let mut start = declarator;
+    while let Some(parent) = start.parent() {
+        start = parent;
+        if DECLARATION_KINDS.contains(&parent.kind()) {
+            break;
+        }
+    }
+    if !DECLARATION_KINDS.contains(&start.kind()) {
+        start = declarator;
+    }
+    Some(collapse(&text[start.start_byte()..declarator.end_byte()]))
+}
+
+/// 按在文档中的顺序查找声明名字为 `name` 的函数声明符。
+fn find_declarator<'tree>(node: Node<'tree>, text: &str, name: &str) -> Option<Node<'tree>> {
+    if node.kind() == "function_declarator"
+        && let Some(declarator) = node.child_by_field_name("declarator")
+        && document_store::word_before(&text[declarator.byte_range()]) == name
+    {
+        return Some(node);
+    }
+    let mut cursor = node.walk();
+    let mut children = node.children(&mut cursor);
+    children.find_map(|child| find_declarator(child, text, name))
+}
+
+/// 声明中参数列表之前的函数名。
+fn declared_name(declaration: &str) -> Option<&str> {
+    let open = declaration.find('(')?;
+    let name = document_store::word_before(declaration[..open].trim_end());
+    (!name.is_empty()).then_some(name)
+}
+
+/// 由函数声明合成 LSP 的 `SignatureHelp`，参数的位置按 UTF-16 计算。
+pub fn signature(declaration: &str, active_parameter: u32) -> Option<Value> {
+    let open = declaration.find('(')?;
+    let parameters: Vec<_> = parameter_ranges(declaration, open)
+        .into_iter()
+        .map(|range| {
+            let start = declaration[..range.start].encode_utf16().count();
+            let end = start + declaration[range].encode_utf16().count();
+            json!({"label": [start, end]})
+        })
+        .collect();
+    Some(json!({
+        "signatures": [{"label": declaration, "parameters": parameters}],
+        "activeSignature": 0,
+        "activeParameter": active_parameter,
+    }))
+}
+
+/// 从 `open` 处的括号开始的参数列表中每个参数的字节范围，不含两边的空白；没有参数或者 `(void)` 时为空。
+fn parameter_ranges(declaration: &str, open: usize) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut depth = 0i32;
+    let mut start = open + 1;
+    for (i, c) in declaration[open..].char_indices() {
+        let i = open + i;
+        match c {
+            '(' | '<' | '[' | '{' => depth += 1,
+            ')' | '>' | ']' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    ranges.push(start..i);
+                    break;
+                }
+            }
+            ',' if depth == 1 => {
+                ranges.push(start..i);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let ranges: Vec<_> = ranges
+        .into_iter()
+        .map(|range| {
+            let parameter = &declaration[range.clone()];
+            let start = range.start + (parameter.len() - parameter.trim_start().len());
+            start..range.start + parameter.trim_end().len()
+        })
+        .filter(|range| !range.is_empty())
+        .collect();
+    match ranges.as_slice() {
+        [only] if &declaration[only.clone()] == "void" => Vec::new(),
+        _ => ranges,
+    }
+}
+
+/// 把连续的空白合并成一个空格。
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// 注册签名帮助的处理器，请求转发给后端时开始计时。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        SignatureHelpRequest::METHOD,
+        FALLBACK_PRIORITY,
+        start_fallback,
+    );
+}
+
+/// 记下请求的位置，后端超时没有应答时由代理合成签名。
+fn start_fallback(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        ctx.dispatcher().start_signature_help(&rpc);
+        Ok(Verdict::Continue(rpc))
+    })
+}
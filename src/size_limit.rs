@@ -0,0 +1,128 @@
+//! # 消息大小限制模块
+//!
+//! 发给前端的消息超过配置的上限时（例如生成文件的几十 MB 语义高亮），记录方法和文档，
+//! 响应换成错误或空结果，通知直接丢弃，避免编辑器被巨大的消息卡住。
+
+use dashmap::DashMap;
+use log::warn;
+use serde_json::{Value, json};
+use std::io;
+
+use crate::config::{OversizeReply, ProtocolConfig};
+use crate::handlers::empty_result;
+
+/// 发给前端的消息的大小上限。
+#[derive(Default)]
+pub struct SizeLimit {
+    max_bytes: Option<usize>,
+    reply: OversizeReply,
+    /// 等待响应的请求：请求 id → 请求针对的文档，超限时写入日志
+    uris: DashMap<String, String>,
+}
+
+impl SizeLimit {
+    /// 按 `[protocol]` 配置创建上限，没有设置 `max_message_mb` 时不限制。
+    pub fn new(config: &ProtocolConfig) -> Self {
+        Self {
+            max_bytes: config
+                .max_message_mb
+                .map(|mb| (mb as usize).saturating_mul(1024 * 1024)),
+            reply: config.oversize_reply,
+            uris: DashMap::new(),
+        }
+    }
+
+    /// 记录转发给后端的请求针对的文档，没有设置上限时不记录。
+    pub fn request(&self, id: &Value, params: Option<&Value>) {
+        if self.max_bytes.is_none() {
+            return;
+        }
+        if let Some(uri) = params
+            .and_then(|params| params.pointer("/textDocument/uri"))
+            .and_then(|uri| uri.as_str())
+        {
+            self.uris.insert(id.to_string(), uri.to_string());
+        }
+    }
+
+    /// 丢弃请求的记录，响应不经过 [`SizeLimit::enforce`] 交给前端时调用。
+    pub fn forget(&self, id: &Value) {
+        self.uris.remove(&id.to_string());
+    }
+
+    /// 检查发给前端的消息的大小。
+    ///
+    /// # 参数
+    ///
+    /// * `method` - 请求或通知的方法；响应对应的请求的方法
+    /// * `rpc` - 消息
+    ///
+    /// # 返回
+    ///
+    /// 没有超过上限时原样返回；超过上限的响应换成错误或空结果，通知返回 `None`；后端的请求不检查
+    pub fn enforce(&self, method: Option<&str>, rpc: Value) -> Option<Value> {
+        let id = rpc
+            .get("method")
+            .is_none()
+            .then(|| rpc.get("id").cloned())
+            .flatten();
+        let uri = match &id {
+            Some(id) => self.uris.remove(&id.to_string()).map(|(_, uri)| uri),
+            None => None,
+        };
+        // 后端的请求需要前端应答，不能丢弃
+        let is_request = rpc.get("method").is_some() && rpc.get("id").is_some();
+        let Some(max_bytes) = self.max_bytes.filter(|_| !is_request) else {
+            return Some(rpc);
+        };
+        let size = serialized_len(&rpc);
+        if size <= max_bytes {
+            return Some(rpc);
+        }
+
+        let method = method.unwrap_or("-");
+        let uri = uri
+            .as_deref()
+            .or_else(|| rpc.pointer("/params/uri").and_then(|uri| uri.as_str()))
+            .unwrap_or("-");
+        warn!(
+            "发给前端的消息超过大小上限: {} {} {} 字节（上限 {} 字节）",
+            method, uri, size, max_bytes
+        );
+        let id = id?;
+        Some(match self.reply {
+            OversizeReply::Error => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": -32603,
+                    "message": format!("响应大小 {} 字节超过上限 {} 字节", size, max_bytes),
+                },
+            }),
+            OversizeReply::Empty => {
+                json!({"jsonrpc": "2.0", "id": id, "result": empty_result(method)})
+            }
+        })
+    }
+}
+
+/// 消息序列化后的字节数，不分配序列化的结果。
+pub fn serialized_len(value: &Value) -> usize {
+    let mut counter = ByteCounter(0);
+    // 写入计数器不会失败，Value 的序列化也不会失败
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
+}
+
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
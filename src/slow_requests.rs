@@ -0,0 +1,82 @@
+//! # 慢请求日志模块
+//!
+//! 前端请求的响应交给前端时，往返时间超过阈值的请求写入日志，
+//! 包括方法、文档、耗时和请求到达时的排队深度，以便事后从日志回答“14:32 的补全为什么慢”。
+
+use log::warn;
+use std::fmt;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::lifecycle::{FinishedRequest, RequestObserver};
+
+/// 一个超过阈值的请求。
+///
+/// - `pending_backend_requests`: 请求到达时已转发给后端、还没有响应的请求数量
+/// - `handlers_waiting`: 请求到达时等待处理许可的消息数量
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowRequest {
+    pub method: String,
+    pub uri: Option<String>,
+    pub duration: Duration,
+    pub pending_backend_requests: usize,
+    pub handlers_waiting: usize,
+}
+
+/// 慢请求的阈值。没有设置阈值时不记录。
+#[derive(Default)]
+pub struct SlowRequests {
+    threshold: RwLock<Option<Duration>>,
+}
+
+impl SlowRequests {
+    /// 创建记录器，往返时间超过 `threshold` 的请求被视为慢请求。
+    pub fn new(threshold: Option<Duration>) -> Self {
+        Self {
+            threshold: RwLock::new(threshold),
+        }
+    }
+
+    /// 修改阈值，不设置时不再记录。
+    pub fn set_threshold(&self, threshold: Option<Duration>) {
+        *self.threshold.write().unwrap() = threshold;
+    }
+
+    /// 检查已经应答的请求。
+    ///
+    /// # 返回
+    ///
+    /// 往返时间超过阈值时返回慢请求的记录
+    pub fn check(&self, request: &FinishedRequest) -> Option<SlowRequest> {
+        let threshold = (*self.threshold.read().unwrap())?;
+        (request.duration > threshold).then(|| SlowRequest {
+            method: request.method.clone(),
+            uri: request.uri.clone(),
+            duration: request.duration,
+            pending_backend_requests: request.queue.pending_backend_requests,
+            handlers_waiting: request.queue.handlers_waiting,
+        })
+    }
+}
+
+impl RequestObserver for SlowRequests {
+    fn finished(&self, request: &FinishedRequest) {
+        if let Some(slow) = self.check(request) {
+            warn!("{}", slow);
+        }
+    }
+}
+
+impl fmt::Display for SlowRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "慢请求 {} {} 耗时 {} ms（到达时 {} 个请求等待后端响应，{} 条消息等待处理）",
+            self.method,
+            self.uri.as_deref().unwrap_or("-"),
+            self.duration.as_millis(),
+            self.pending_backend_requests,
+            self.handlers_waiting
+        )
+    }
+}
@@ -0,0 +1,300 @@
+//! # 代码片段模块
+//!
+//! 团队可以把共享的 C++ 样板代码放在项目中，由代理作为补全来源提供，不需要每个人在编辑器中各自配置。
+//! `[snippets] files` 列出代码片段文件：`.json` 和 `.code-snippets` 是 VSCode 的格式（允许注释），
+//! `.toml` 是代理的格式：
+//!
+//! ```toml
+//! [[snippets]]
+//! name = "include guard"
+//! prefix = "guard"
+//! body = "#ifndef ${1:NAME}\n#define $1\n\n$0\n\n#endif"
+//! languages = ["c", "cpp"]
+//! ```
+//!
+//! 输入的单词是代码片段前缀的开头时提供补全项，补全项的 `insertText` 是代码片段的语法。
+//! 由触发字符（`.`、`->` 等）触发的补全不提供代码片段。
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tower_lsp::lsp_types::{CompletionItemKind, CompletionTriggerKind, InsertTextFormat};
+
+use crate::completion_sources::{CompletionProvider, CompletionRequest};
+use crate::config::SnippetsConfig;
+use crate::document_store;
+
+/// 代码片段来源的名称。
+pub const SOURCE: &str = "snippets";
+
+/// 一个代码片段。
+///
+/// - `name`: 名称，显示为补全项的详情
+/// - `prefixes`: 触发的前缀，每个前缀是一个补全项
+/// - `body`: 代码片段的语法（`$1`、`${1:name}`、`$0`）
+/// - `description`: 说明，显示为补全项的文档
+/// - `languages`: 适用的语言 id，为空时适用于所有文档
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    pub name: String,
+    pub prefixes: Vec<String>,
+    pub body: String,
+    pub description: Option<String>,
+    pub languages: Vec<String>,
+}
+
+/// 一个或多个字符串，VSCode 的前缀、正文和说明都可以是字符串数组。
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            OneOrMany::One(text) => vec![text],
+            OneOrMany::Many(texts) => texts,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct VscodeSnippet {
+    prefix: OneOrMany,
+    body: OneOrMany,
+    description: Option<OneOrMany>,
+    /// 逗号分隔的语言 id
+    scope: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TomlSnippet {
+    name: Option<String>,
+    prefix: OneOrMany,
+    body: String,
+    description: Option<String>,
+    #[serde(default)]
+    languages: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TomlFile {
+    #[serde(default)]
+    snippets: Vec<TomlSnippet>,
+}
+
+/// 解析 VSCode 格式的代码片段文件：名称 → 代码片段，正文的数组按行拼接。
+///
+/// # 错误
+///
+/// 文件不是合法的 JSON（去掉注释后）时返回错误
+pub fn parse_vscode(text: &str) -> Result<Vec<Snippet>> {
+    let snippets: BTreeMap<String, VscodeSnippet> = serde_json::from_str(&strip_comments(text))?;
+    Ok(snippets
+        .into_iter()
+        .map(|(name, snippet)| Snippet {
+            name,
+            prefixes: snippet.prefix.into_vec(),
+            body: snippet.body.into_vec().join("\n"),
+            description: snippet
+                .description
+                .map(|description| description.into_vec().join("\n")),
+            languages: snippet
+                .scope
+                .map(|scope| {
+                    scope
+                        .split(',')
+                        .map(|language| language.trim().to_string())
+                        .filter(|language| !language.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// 解析代理格式的代码片段文件，没有名称的代码片段以第一个前缀为名称。
+///
+/// # 错误
+///
+/// 文件不是合法的 TOML 时返回错误
+pub fn parse_toml(text: &str) -> Result<Vec<Snippet>> {
+    let file: TomlFile = toml::from_str(text)?;
+    Ok(file
+        .snippets
+        .into_iter()
+        .map(|snippet| {
+            let prefixes = snippet.prefix.into_vec();
+            Snippet {
+                name: snippet
+                    .name
+                    .or_else(|| prefixes.first().cloned())
+                    .unwrap_or_default(),
+                prefixes,
+                body: snippet.body,
+                description: snippet.description,
+                languages: snippet.languages,
+            }
+        })
+        .collect())
+}
+
+/// 去掉 JSON 中字符串之外的 `//` 和 `/* */` 注释。
+fn strip_comments(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            stripped.push(c);
+            match c {
+                '\\' => stripped.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                stripped.push(c);
+            }
+            ('/', Some('/')) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        stripped.push(c);
+                        break;
+                    }
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            _ => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+/// 读取一个代码片段文件，按扩展名选择格式。
+///
+/// # 错误
+///
+/// 文件无法读取或者无法解析时返回错误
+pub fn load_file(path: &Path) -> Result<Vec<Snippet>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("无法读取代码片段文件 {}", path.display()))?;
+    let snippets = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => parse_toml(&text),
+        _ => parse_vscode(&text),
+    };
+    snippets.with_context(|| format!("无法解析代码片段文件 {}", path.display()))
+}
+
+/// 项目的代码片段，作为补全来源。
+#[derive(Debug, Default)]
+pub struct Snippets {
+    snippets: Vec<Snippet>,
+}
+
+impl Snippets {
+    /// 读取 `[snippets]` 配置的所有文件，无法读取的文件记录警告后跳过。
+    pub fn new(config: &SnippetsConfig) -> Self {
+        let mut snippets = Vec::new();
+        for path in &config.files {
+            match load_file(path) {
+                Ok(loaded) => snippets.extend(loaded),
+                Err(e) => warn!("{:?}", e),
+            }
+        }
+        if !snippets.is_empty() {
+            info!("加载了 {} 个代码片段", snippets.len());
+        }
+        Self { snippets }
+    }
+
+    /// 使用已经解析的代码片段。
+    pub fn from_snippets(snippets: Vec<Snippet>) -> Self {
+        Self { snippets }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snippets.is_empty()
+    }
+
+    /// 光标前正在输入的单词以及文档的语言可用的补全项。
+    pub fn items(&self, request: &CompletionRequest) -> Vec<Value> {
+        let triggered = request.params.context.as_ref().is_some_and(|context| {
+            context.trigger_kind == CompletionTriggerKind::TRIGGER_CHARACTER
+        });
+        if triggered {
+            return Vec::new();
+        }
+        let (typed, language) = match &request.document {
+            Some(doc) => {
+                let position = request.params.text_document_position.position;
+                let offset = document_store::offset_at(&doc.text, position);
+                (
+                    document_store::word_before(&doc.text[..offset]),
+                    Some(doc.language_id.as_str()),
+                )
+            }
+            None => ("", None),
+        };
+        let typed = typed.to_lowercase();
+        self.snippets
+            .iter()
+            .filter(|snippet| {
+                snippet.languages.is_empty()
+                    || language
+                        .is_none_or(|language| snippet.languages.iter().any(|l| l == language))
+            })
+            .flat_map(|snippet| {
+                snippet
+                    .prefixes
+                    .iter()
+                    .filter(|prefix| prefix.to_lowercase().starts_with(&typed))
+                    .map(move |prefix| item(snippet, prefix))
+            })
+            .collect()
+    }
+}
+
+fn item(snippet: &Snippet, prefix: &str) -> Value {
+    let mut item = json!({
+        "label": prefix,
+        "kind": CompletionItemKind::SNIPPET,
+        "detail": snippet.name,
+        "filterText": prefix,
+        "insertText": snippet.body,
+        "insertTextFormat": InsertTextFormat::SNIPPET,
+    });
+    if let Some(description) = &snippet.description {
+        item["documentation"] = json!(description);
+    }
+    item
+}
+
+impl CompletionProvider for Snippets {
+    fn name(&self) -> &str {
+        SOURCE
+    }
+
+    fn complete(&self, request: &CompletionRequest) -> BoxFuture<'static, Vec<Value>> {
+        let items = self.items(request);
+        Box::pin(async move { items })
+    }
+}
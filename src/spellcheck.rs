@@ -0,0 +1,329 @@
+//! # 拼写检查模块
+//!
+//! 可选的内置诊断来源：用 tree-sitter 找出文档中的注释和字符串字面量，检查其中的单词，
+//! 以提示级别的诊断显示拼错的单词，经由诊断来源模块与后端的诊断合并。
+//! 编辑器为这些诊断请求代码操作时，代理在后端的结果后面追加替换为建议拼写的快速修复。
+
+use anyhow::{Context, Result};
+use dashmap::DashMap;
+use log::warn;
+use serde_json::{Value, json};
+use std::collections::{BTreeSet, HashSet};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use tower_lsp::lsp_types::Url;
+
+use crate::config::SpellcheckConfig;
+use crate::document_store::position_at;
+use crate::syntax;
+
+/// 拼写诊断的来源名称。
+pub const SOURCE: &str = "spell";
+
+/// 没有配置单词列表时使用的文件。
+const DEFAULT_DICTIONARY: &str = "/usr/share/dict/words";
+
+/// 每个拼错的单词最多给出的建议。
+const MAX_SUGGESTIONS: usize = 3;
+
+/// 编辑距离为 2 的建议只为不长于这个长度的单词查找，候选太多时查找很慢。
+const MAX_DISTANCE_TWO_LENGTH: usize = 12;
+
+/// 诊断的严重级别：提示（DiagnosticSeverity::HINT）。
+const HINT: u8 = 4;
+
+/// 单词前面是这些字符时是路径、网址或标识符的一部分，不检查。
+const SKIP_AFTER: &[char] = &['.', '/', ':', '@', '#', '\\', '$', '%'];
+
+/// 单词列表和拼写建议。
+pub struct SpellChecker {
+    /// 小写的单词
+    words: HashSet<String>,
+    min_length: usize,
+}
+
+impl SpellChecker {
+    /// # 参数
+    ///
+    /// * `words` - 拼写正确的单词，不区分大小写
+    /// * `min_length` - 短于这个长度的单词不检查
+    pub fn new<'a>(words: impl IntoIterator<Item = &'a str>, min_length: usize) -> Self {
+        Self {
+            words: words
+                .into_iter()
+                .map(|word| word.trim().to_lowercase())
+                .filter(|word| !word.is_empty())
+                .collect(),
+            min_length,
+        }
+    }
+
+    /// 读取单词列表文件，每行一个单词。
+    ///
+    /// # 错误
+    ///
+    /// 如果文件无法读取，返回错误
+    pub fn load(dictionary: &Path, extra: &[String], min_length: usize) -> Result<Self> {
+        let text = std::fs::read_to_string(dictionary)
+            .with_context(|| format!("无法读取单词列表 {}", dictionary.display()))?;
+        let words = text.lines().chain(extra.iter().map(String::as_str));
+        Ok(Self::new(words, min_length))
+    }
+
+    /// 单词的拼写是否正确，所有格 `'s` 按原词检查。
+    pub fn is_correct(&self, word: &str) -> bool {
+        let word = word.to_lowercase();
+        self.words.contains(&word)
+            || word
+                .strip_suffix("'s")
+                .is_some_and(|stem| self.words.contains(stem))
+    }
+
+    /// 拼错的单词的建议拼写，先给出编辑距离为 1 的单词，没有时再找编辑距离为 2 的单词。
+    ///
+    /// 单词首字母大写时建议也大写首字母。
+    pub fn suggestions(&self, word: &str) -> Vec<String> {
+        let lower = word.to_lowercase();
+        let known = |candidates: Vec<String>| -> BTreeSet<String> {
+            candidates
+                .into_iter()
+                .filter(|candidate| self.words.contains(candidate))
+                .collect()
+        };
+        let nearby = edits(&lower);
+        let mut found = known(nearby.clone());
+        if found.is_empty() && lower.len() <= MAX_DISTANCE_TWO_LENGTH {
+            found = known(nearby.iter().flat_map(|edit| edits(edit)).collect());
+        }
+        let capitalized = word.starts_with(|c: char| c.is_ascii_uppercase());
+        found
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|suggestion| {
+                if capitalized {
+                    capitalize(&suggestion)
+                } else {
+                    suggestion
+                }
+            })
+            .collect()
+    }
+
+    /// 检查文档中注释和字符串字面量里的单词。
+    ///
+    /// # 返回
+    ///
+    /// 返回拼错的单词的诊断，`data.word` 是拼错的单词
+    pub fn check(&self, text: &str) -> Vec<Value> {
+        let mut diagnostics = Vec::new();
+        for range in text_ranges(text) {
+            for word in words(text, range) {
+                let spelled = &text[word.clone()];
+                if !self.should_check(spelled) || self.is_correct(spelled) {
+                    continue;
+                }
+                diagnostics.push(json!({
+                    "range": {
+                        "start": position_at(text, word.start),
+                        "end": position_at(text, word.end),
+                    },
+                    "severity": HINT,
+                    "source": SOURCE,
+                    "message": format!("单词 '{}' 可能拼写错误", spelled),
+                    "data": {"word": spelled},
+                }));
+            }
+        }
+        diagnostics
+    }
+
+    /// 只检查像普通单词的词：全是字母，除首字母外都是小写。标识符、缩写和数字不检查。
+    fn should_check(&self, word: &str) -> bool {
+        let mut letters = word.trim_end_matches("'s").chars().filter(|c| *c != '\'');
+        let Some(first) = letters.next() else {
+            return false;
+        };
+        word.chars().count() >= self.min_length
+            && first.is_ascii_alphabetic()
+            && letters.all(|c| c.is_ascii_lowercase())
+    }
+}
+
+/// 文档中注释和字符串字面量内容的字节范围。
+pub fn text_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut kinds = vec![syntax::COMMENT];
+    kinds.extend(syntax::STRING_CONTENTS);
+    syntax::node_ranges(text, &kinds)
+}
+
+/// 范围内的单词：字母、数字、下划线和夹在中间的撇号组成的词，跳过路径、网址和函数调用中的词。
+fn words(text: &str, range: Range<usize>) -> Vec<Range<usize>> {
+    let segment = &text[range.clone()];
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in segment.char_indices().chain([(segment.len(), ' ')]) {
+        let in_word = c.is_alphanumeric() || c == '_' || c == '\'';
+        match (start, in_word) {
+            (None, true) => start = Some(i),
+            (Some(begin), false) => {
+                start = None;
+                let raw = &segment[begin..i];
+                let word = raw.trim_matches('\'');
+                if word.is_empty() {
+                    continue;
+                }
+                let begin = begin + raw.len() - raw.trim_start_matches('\'').len();
+                let end = begin + word.len();
+                let before = segment[..begin].chars().next_back();
+                let mut after = segment[end..].chars();
+                let next = after.next();
+                let joined = matches!(next, Some('.' | ':' | '/'))
+                    && after.next().is_some_and(|c| !c.is_whitespace());
+                if before.is_some_and(|c| SKIP_AFTER.contains(&c)) || next == Some('(') || joined {
+                    continue;
+                }
+                words.push(range.start + begin..range.start + end);
+            }
+            _ => {}
+        }
+    }
+    words
+}
+
+/// 与单词编辑距离为 1 的所有字符串：删除、交换相邻、替换和插入一个字母。
+fn edits(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let join = |parts: &[&[char]]| parts.concat().into_iter().collect::<String>();
+    let mut edits = Vec::new();
+    for i in 0..=chars.len() {
+        let (head, tail) = chars.split_at(i);
+        if let [_, rest @ ..] = tail {
+            edits.push(join(&[head, rest]));
+        }
+        if let [first, second, rest @ ..] = tail {
+            edits.push(join(&[head, &[*second, *first], rest]));
+        }
+        for letter in 'a'..='z' {
+            if let [_, rest @ ..] = tail {
+                edits.push(join(&[head, &[letter], rest]));
+            }
+            edits.push(join(&[head, &[letter], tail]));
+        }
+    }
+    edits
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// 调度器使用的拼写检查：检查器和等待后端响应的代码操作请求的快速修复。
+#[derive(Default)]
+pub struct Spellcheck {
+    checker: Option<Arc<SpellChecker>>,
+    /// 代码操作请求 id → 追加到响应中的快速修复
+    actions: DashMap<String, Vec<Value>>,
+}
+
+impl Spellcheck {
+    /// 按 `[spellcheck]` 配置创建拼写检查，单词列表无法读取时记录警告并关闭。
+    pub fn new(config: &SpellcheckConfig) -> Self {
+        if !config.enabled {
+            return Self::default();
+        }
+        let dictionary = config
+            .dictionary
+            .as_deref()
+            .unwrap_or(Path::new(DEFAULT_DICTIONARY));
+        match SpellChecker::load(dictionary, &config.words, config.min_length) {
+            Ok(checker) => Self {
+                checker: Some(Arc::new(checker)),
+                actions: DashMap::new(),
+            },
+            Err(e) => {
+                warn!("关闭拼写检查: {:?}", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.checker.is_some()
+    }
+
+    /// 拼写检查器，没有启用时返回 `None`。
+    pub fn checker(&self) -> Option<Arc<SpellChecker>> {
+        self.checker.clone()
+    }
+
+    /// 记录一个转发给后端的 `textDocument/codeAction` 请求，为其中的拼写诊断准备快速修复。
+    pub fn request(&self, id: &Value, params: Option<&Value>) {
+        let Some(checker) = &self.checker else {
+            return;
+        };
+        let Some(uri) = params
+            .and_then(|params| params.pointer("/textDocument/uri"))
+            .and_then(|uri| uri.as_str())
+            .and_then(|uri| Url::parse(uri).ok())
+        else {
+            return;
+        };
+        let diagnostics = params
+            .and_then(|params| params.pointer("/context/diagnostics"))
+            .and_then(|d| d.as_array());
+        let actions: Vec<Value> = diagnostics
+            .into_iter()
+            .flatten()
+            .filter(|d| d.get("source").and_then(|s| s.as_str()) == Some(SOURCE))
+            .flat_map(|diagnostic| quick_fixes(checker, &uri, diagnostic))
+            .collect();
+        if !actions.is_empty() {
+            self.actions.insert(id.to_string(), actions);
+        }
+    }
+
+    /// 在后端的代码操作响应后面追加拼写的快速修复。
+    pub fn rewrite_code_action_response(&self, rpc: &mut Value) {
+        let Some((_, actions)) = rpc
+            .get("id")
+            .and_then(|id| self.actions.remove(&id.to_string()))
+        else {
+            return;
+        };
+        if rpc.get("error").is_some() {
+            return;
+        }
+        match rpc.get_mut("result") {
+            Some(Value::Array(result)) => result.extend(actions),
+            _ => rpc["result"] = Value::Array(actions),
+        }
+    }
+}
+
+/// 把拼错的单词替换为每个建议拼写的快速修复。
+fn quick_fixes(checker: &SpellChecker, uri: &Url, diagnostic: &Value) -> Vec<Value> {
+    let Some(word) = diagnostic.pointer("/data/word").and_then(|w| w.as_str()) else {
+        return Vec::new();
+    };
+    checker
+        .suggestions(word)
+        .into_iter()
+        .enumerate()
+        .map(|(i, suggestion)| {
+            json!({
+                "title": format!("改为 '{}'", suggestion),
+                "kind": "quickfix",
+                "diagnostics": [diagnostic],
+                "isPreferred": i == 0,
+                "edit": {"changes": {
+                    uri.as_str(): [{"range": diagnostic["range"], "newText": suggestion}],
+                }},
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,99 @@
+//! # SSH 后端模块
+//!
+//! `[backend] command` 是 `ssh://user@host//usr/bin/clangd` 时，代理通过 ssh 在远程机器上启动后端，
+//! ssh 进程的标准输入输出接入原有的消息管道。连接断开后由监管者重新连接并重放打开的文档。
+
+use crate::config::SshConfig;
+
+/// ssh 连接失败时的退出码。
+pub const SSH_CONNECTION_ERROR: i32 = 255;
+
+/// 通过 ssh 启动的后端。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshTarget {
+    /// ssh 的目标，例如 `user@host`
+    pub destination: String,
+    /// 远程机器上的后端程序，绝对路径或者按远程的 `PATH` 查找的程序名
+    pub program: String,
+}
+
+impl SshTarget {
+    /// 解析 `ssh://<destination>/<program>`，不是 ssh 地址时返回 `None`。
+    ///
+    /// `ssh://user@host//usr/bin/clangd` 中的程序是 `/usr/bin/clangd`，`ssh://host/clangd` 中的程序是 `clangd`。
+    pub fn parse(command: &str) -> Option<Self> {
+        let (destination, program) = command.strip_prefix("ssh://")?.split_once('/')?;
+        if destination.is_empty() || program.is_empty() {
+            return None;
+        }
+        Some(Self {
+            destination: destination.to_string(),
+            program: program.to_string(),
+        })
+    }
+
+    /// 启动后端的 ssh 参数。
+    ///
+    /// 远程命令由 ssh 交给远程的 shell 执行，程序、参数和环境变量都经过引用；
+    /// 环境变量通过 `env` 设置，因为 sshd 通常不接受客户端传来的环境变量。
+    ///
+    /// # 参数
+    ///
+    /// * `args` - 后端的参数
+    /// * `envs` - 启动后端时额外设置的环境变量
+    /// * `config` - `[backend.ssh]` 配置
+    pub fn ssh_args(
+        &self,
+        args: &[String],
+        envs: &[(String, String)],
+        config: &SshConfig,
+    ) -> Vec<String> {
+        // 网络中断时尽快发现，而不是等待 TCP 超时
+        let mut ssh_args: Vec<String> = [
+            "-T",
+            "-o",
+            "BatchMode=yes",
+            "-o",
+            "ServerAliveInterval=15",
+            "-o",
+            "ServerAliveCountMax=3",
+        ]
+        .map(String::from)
+        .to_vec();
+        if let Some(control_path) = &config.control_path {
+            ssh_args.extend([
+                "-o".to_string(),
+                "ControlMaster=auto".to_string(),
+                "-o".to_string(),
+                format!("ControlPath={}", control_path),
+                "-o".to_string(),
+                "ControlPersist=10m".to_string(),
+            ]);
+        }
+        ssh_args.extend(config.args.iter().cloned());
+        ssh_args.push(self.destination.clone());
+        ssh_args.push("--".to_string());
+
+        let mut remote = Vec::new();
+        if !envs.is_empty() {
+            remote.push("env".to_string());
+            remote.extend(
+                envs.iter()
+                    .map(|(key, value)| shell_quote(&format!("{}={}", key, value))),
+            );
+        }
+        remote.push(shell_quote(&self.program));
+        remote.extend(args.iter().map(|arg| shell_quote(arg)));
+        ssh_args.push(remote.join(" "));
+        ssh_args
+    }
+}
+
+/// 按 POSIX shell 的规则引用参数，只包含安全字符的参数原样返回。
+pub fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
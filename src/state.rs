@@ -0,0 +1,212 @@
+//! # 会话状态模块
+//!
+//! 开启 `[state] enabled` 后，代理把一些轻量的状态保存在第一个工作区根目录的 `.cache/codefuse/state.json` 中，
+//! 下一次会话开始时恢复，冷启动逐渐变快（最近编辑的文件由 [`crate::warmup`] 保存在同一个目录中）：
+//!
+//! - `backend`: 后端上次 `initialize` 响应中的能力和版本，本次响应到达之前作为后端的能力使用
+//! - `completionUsage`: 补全项被编辑器解析（通常是用户选中）的次数，用得最多的补全项预先选中
+//! - `index`: clangd 上次完成后台索引的时间和索引目录的大小；索引目录仍然存在时不再生成 ctags 索引，
+//!   `workspace/symbol` 直接交给从磁盘加载索引的 clangd
+//!
+//! 状态在后端初始化完成、后台索引完成和代理退出时写入，文件无法解析时当作没有保存的状态。
+
+use anyhow::Result;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cache;
+use crate::config::StateConfig;
+
+/// 状态文件相对于第一个工作区根目录的位置。
+pub const STATE_PATH: &str = ".cache/codefuse/state.json";
+
+/// 最多记录的补全项数，超过后不再记录新的补全项。
+const MAX_COMPLETION_LABELS: usize = 2048;
+
+/// 补全项至少被使用这么多次才会被预先选中。
+const MIN_PRESELECT_USES: u64 = 2;
+
+/// 持久化的状态。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SavedState {
+    pub backend: Option<Value>,
+    pub completion_usage: HashMap<String, u64>,
+    pub index: Option<IndexMetadata>,
+}
+
+/// clangd 上次完成后台索引时的索引目录。
+///
+/// - `completed_at_ms`: 完成的时间（Unix 毫秒）
+/// - `files`/`bytes`: 索引目录中的文件数量和总大小
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexMetadata {
+    pub completed_at_ms: u64,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// 当前会话的状态，写入时生成 [`SavedState`]。
+#[derive(Default)]
+pub struct ProxyState {
+    enabled: bool,
+    /// 第一个工作区根目录，加载之前为 `None`
+    root: Mutex<Option<PathBuf>>,
+    backend: RwLock<Option<Value>>,
+    completion_usage: DashMap<String, u64>,
+    index: RwLock<Option<IndexMetadata>>,
+}
+
+impl ProxyState {
+    pub fn new(config: &StateConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 从第一个工作区根目录加载上次会话的状态。
+    ///
+    /// # 返回
+    ///
+    /// 上次保存的状态；没有开启、没有工作区或者没有保存的状态时为 `None`
+    pub fn load(&self, roots: &[PathBuf]) -> Option<SavedState> {
+        if !self.enabled {
+            return None;
+        }
+        let root = roots.first()?;
+        *self.root.lock().unwrap() = Some(root.clone());
+        let saved: SavedState = std::fs::read_to_string(root.join(STATE_PATH))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())?;
+        *self.backend.write().unwrap() = saved.backend.clone();
+        for (label, uses) in &saved.completion_usage {
+            self.completion_usage.insert(label.clone(), *uses);
+        }
+        *self.index.write().unwrap() = saved.index.clone();
+        Some(saved)
+    }
+
+    /// 记录后端 `initialize` 响应的结果，只保留能力和版本。
+    pub fn record_backend(&self, result: &Value) {
+        let mut backend = serde_json::Map::new();
+        for field in ["capabilities", "serverInfo"] {
+            if let Some(value) = result.get(field) {
+                backend.insert(field.to_string(), value.clone());
+            }
+        }
+        *self.backend.write().unwrap() = Some(Value::Object(backend));
+    }
+
+    /// 记录编辑器解析了一个补全项。
+    pub fn record_completion_use(&self, label: &str) {
+        if let Some(mut uses) = self.completion_usage.get_mut(label) {
+            *uses += 1;
+        } else if self.completion_usage.len() < MAX_COMPLETION_LABELS {
+            self.completion_usage.insert(label.to_string(), 1);
+        }
+    }
+
+    /// 补全结果中没有预先选中的补全项时，选中用得最多的一个。
+    pub fn preselect_completion(&self, result: &mut Value) {
+        let items = match result {
+            Value::Array(items) => items,
+            Value::Object(list) => match list.get_mut("items") {
+                Some(Value::Array(items)) => items,
+                _ => return,
+            },
+            _ => return,
+        };
+        if items.iter().any(|item| item["preselect"] == true) {
+            return;
+        }
+        let uses = |item: &Value| {
+            item["label"]
+                .as_str()
+                .and_then(|label| self.completion_usage.get(label).map(|uses| *uses))
+                .unwrap_or(0)
+        };
+        // 次数相同时取靠前的补全项
+        let best = items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (uses(item), std::cmp::Reverse(i)))
+            .max();
+        if let Some((uses, std::cmp::Reverse(i))) = best
+            && uses >= MIN_PRESELECT_USES
+        {
+            items[i]["preselect"] = Value::Bool(true);
+        }
+    }
+
+    /// 记录 clangd 完成了后台索引，统计工作区中的索引目录。
+    pub fn record_index_completed(&self, roots: &[PathBuf]) {
+        let (files, bytes) = cache::index_dirs(roots, None)
+            .iter()
+            .filter_map(|dir| cache::measure(dir).ok())
+            .fold((0, 0), |(files, bytes), stats| {
+                (files + stats.files, bytes + stats.bytes)
+            });
+        let completed_at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+        *self.index.write().unwrap() = Some(IndexMetadata {
+            completed_at_ms,
+            files,
+            bytes,
+        });
+    }
+
+    /// 当前的状态。
+    pub fn snapshot(&self) -> SavedState {
+        SavedState {
+            backend: self.backend.read().unwrap().clone(),
+            completion_usage: self
+                .completion_usage
+                .iter()
+                .map(|entry| (entry.key().clone(), *entry.value()))
+                .collect(),
+            index: self.index.read().unwrap().clone(),
+        }
+    }
+
+    /// 把状态写入加载时的工作区根目录。
+    ///
+    /// # 返回
+    ///
+    /// 写入的文件路径；没有开启或者还没有加载时为 `None`
+    ///
+    /// # 错误
+    ///
+    /// 如果目录无法创建或文件无法写入，返回错误
+    pub fn save(&self) -> Result<Option<PathBuf>> {
+        let Some(root) = self.root.lock().unwrap().clone() else {
+            return Ok(None);
+        };
+        let path = root.join(STATE_PATH);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, serde_json::to_string_pretty(&self.snapshot())?)?;
+        Ok(Some(path))
+    }
+}
+
+/// 上次会话中 clangd 完成了后台索引，并且工作区中仍然有索引文件。
+pub fn index_reusable(saved: &SavedState, roots: &[PathBuf]) -> bool {
+    saved.index.is_some()
+        && cache::index_dirs(roots, None)
+            .iter()
+            .any(|dir| cache::measure(dir).is_ok_and(|stats| stats.files > 0))
+}
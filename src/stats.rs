@@ -0,0 +1,220 @@
+//! # 会话统计模块
+//!
+//! 统计整个会话的请求数量、错误、延迟分位数、预取缓存命中率和后端重启次数。
+//! 统计可以通过自定义请求 `codefuse/stats` 查询，代理退出时写入工作区的 `.cache/codefuse/session-stats.json`，
+//! 用于调整配置或附在问题报告中。
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::dispatcher::{DEFAULT_HANDLER_PRIORITY, Dispatcher};
+use crate::handlers::{self, HandlerCtx, Verdict};
+use crate::lifecycle::{FinishedRequest, RequestObserver};
+use crate::trace::Direction;
+
+/// 查询会话统计的自定义请求，结果是 [`StatsReport`]。
+pub const STATS: &str = "codefuse/stats";
+
+/// 退出时写入的统计文件，相对于第一个工作区根目录。
+pub const STATS_PATH: &str = ".cache/codefuse/session-stats.json";
+
+/// 每个方法保留的最近延迟样本数量，分位数按这些样本计算。
+const LATENCY_SAMPLES: usize = 1024;
+
+/// 整个会话的统计。
+pub struct SessionStats {
+    started: Instant,
+    started_at: SystemTime,
+    methods: Mutex<HashMap<String, MethodStats>>,
+    prefetch_hits: AtomicU64,
+    prefetch_misses: AtomicU64,
+    backend_restarts: AtomicU64,
+}
+
+#[derive(Default)]
+struct MethodStats {
+    count: u64,
+    errors: u64,
+    latencies: VecDeque<Duration>,
+}
+
+/// 会话统计的快照。
+///
+/// - `started_at_ms`: 会话开始的时间（Unix 毫秒）
+/// - `methods`: 按方法统计的请求
+/// - `errors`: 以错误应答的请求总数
+/// - `prefetch_hit_rate`: 预取缓存的命中率，没有可以命中的请求时为 `null`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsReport {
+    pub started_at_ms: u64,
+    pub duration_secs: u64,
+    pub methods: BTreeMap<String, MethodReport>,
+    pub errors: u64,
+    pub prefetch_hits: u64,
+    pub prefetch_misses: u64,
+    pub prefetch_hit_rate: Option<f64>,
+    pub backend_restarts: u64,
+}
+
+/// 一个方法的请求统计，延迟按最近的样本计算。
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MethodReport {
+    pub count: u64,
+    pub errors: u64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+            methods: Mutex::new(HashMap::new()),
+            prefetch_hits: AtomicU64::new(0),
+            prefetch_misses: AtomicU64::new(0),
+            backend_restarts: AtomicU64::new(0),
+        }
+    }
+}
+
+impl SessionStats {
+    /// 创建空的会话统计。
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录一个已经应答的请求。
+    ///
+    /// # 参数
+    ///
+    /// * `method` - 请求方法
+    /// * `latency` - 从收到请求到应答的时间
+    /// * `failed` - 是否以错误应答
+    pub fn record(&self, method: &str, latency: Duration, failed: bool) {
+        let mut methods = self.methods.lock().unwrap();
+        let stats = methods.entry(method.to_string()).or_default();
+        stats.count += 1;
+        if failed {
+            stats.errors += 1;
+        }
+        if stats.latencies.len() == LATENCY_SAMPLES {
+            stats.latencies.pop_front();
+        }
+        stats.latencies.push_back(latency);
+    }
+
+    /// 可以由预取缓存应答的请求命中或没有命中缓存。
+    pub fn prefetch(&self, hit: bool) {
+        let counter = if hit {
+            &self.prefetch_hits
+        } else {
+            &self.prefetch_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 某个分片切换到了新的后端进程。
+    pub fn backend_restarted(&self) {
+        self.backend_restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 汇总到目前为止的统计。
+    pub fn report(&self) -> StatsReport {
+        let methods: BTreeMap<String, MethodReport> = self
+            .methods
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(method, stats)| {
+                let mut latencies: Vec<Duration> = stats.latencies.iter().copied().collect();
+                latencies.sort_unstable();
+                let report = MethodReport {
+                    count: stats.count,
+                    errors: stats.errors,
+                    p50_ms: percentile_ms(&latencies, 0.50),
+                    p95_ms: percentile_ms(&latencies, 0.95),
+                };
+                (method.clone(), report)
+            })
+            .collect();
+        let prefetch_hits = self.prefetch_hits.load(Ordering::Relaxed);
+        let prefetch_misses = self.prefetch_misses.load(Ordering::Relaxed);
+        let lookups = prefetch_hits + prefetch_misses;
+        StatsReport {
+            started_at_ms: self
+                .started_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            duration_secs: self.started.elapsed().as_secs(),
+            errors: methods.values().map(|method| method.errors).sum(),
+            methods,
+            prefetch_hits,
+            prefetch_misses,
+            prefetch_hit_rate: (lookups > 0).then(|| prefetch_hits as f64 / lookups as f64),
+            backend_restarts: self.backend_restarts.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 把统计写入 `root` 下的 [`STATS_PATH`]。
+    ///
+    /// # 返回
+    ///
+    /// 返回写入的文件路径
+    ///
+    /// # 错误
+    ///
+    /// 如果目录无法创建或文件无法写入，返回错误
+    pub fn save(&self, root: &Path) -> Result<PathBuf> {
+        let path = root.join(STATS_PATH);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, serde_json::to_string_pretty(&self.report())?)?;
+        Ok(path)
+    }
+}
+
+impl RequestObserver for SessionStats {
+    fn finished(&self, request: &FinishedRequest) {
+        self.record(&request.method, request.duration, request.failed);
+    }
+}
+
+/// 已排序样本的分位数（最近秩法），没有样本时为 0。
+fn percentile_ms(sorted: &[Duration], quantile: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = ((quantile * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1].as_secs_f64() * 1000.0
+}
+
+/// 注册 `codefuse/stats` 的处理器，由代理直接应答。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        STATS,
+        DEFAULT_HANDLER_PRIORITY,
+        answer_stats,
+    );
+}
+
+/// 以本次会话的统计应答。
+fn answer_stats(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let report = ctx.dispatcher().stats().report();
+        let response = handlers::response(&rpc, Ok(json!(report)));
+        Ok(Verdict::Respond(response))
+    })
+}
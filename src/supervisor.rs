@@ -0,0 +1,923 @@
+//! # 后端监管模块
+//!
+//! 每个分片由一个监管者启动和看护后端进程。启用备用后端时，监管者额外维护一个已经完成初始化的 clangd，
+//! 主后端崩溃或内存占用过高时把流量切换到备用后端并重放打开的文档，编辑器不会看到语言功能的中断。
+//! 通过 ssh 启动的后端在连接断开后重新连接，同样重放打开的文档；在容器中运行的后端各自使用一个容器，
+//! 配置了沙箱的本地后端由沙箱程序启动。
+//! 配置了 `[backend.idle]` 时，长时间没有消息的后端被暂停或结束，下一条消息到达时唤醒。
+
+use anyhow::{Result, anyhow};
+use futures::TryStreamExt;
+use log::{debug, error, info, warn};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::process::ChildStdout;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio_util::codec::FramedRead;
+use tower_lsp::lsp_types::MessageType;
+use tower_lsp::lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Exit, Initialized,
+    Notification,
+};
+use tower_lsp::lsp_types::request::{Initialize, Request, Shutdown};
+
+use crate::backend_log::{self, LogParser};
+use crate::batch;
+use crate::codec::LspCodec;
+use crate::config::{BackendConfig, Config, IdleAction, ResourceLimits};
+use crate::container::{ContainerGuard, ContainerSpec};
+use crate::dispatcher::Dispatcher;
+use crate::events::{ProxyEvent, RestartReason};
+use crate::idle::{IDLE_CHECK_INTERVAL, IdleTracker, MAX_IDLE_MINS};
+use crate::lsp_backend::{BackendProcess, LspBackend, pipe_lsp_backend_stderr};
+use crate::message::Message;
+use crate::remote::PathMapping;
+use crate::sandbox::SandboxSpec;
+use crate::ssh::SshTarget;
+use crate::tasks::{HandlerLimiter, send_data_backend};
+use crate::trace::Direction;
+
+/// 检查主后端内存占用的间隔。
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 重新连接 ssh 后端时，第一次重试之前等待的时间，之后每次加倍。
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// 重新连接 ssh 后端时，两次重试之间最长的等待时间。
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// 重新连接的后端完成初始化的最长时间，超时的连接视为失败。
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 备用后端无法接替退出的主后端、或者空闲后端无法重新启动时，再次启动后端的次数。
+const RESTART_ATTEMPTS: u32 = 3;
+
+/// 启动一个后端进程所需的信息。
+#[derive(Clone)]
+struct ProcessSpec {
+    shard: usize,
+    name: String,
+    command: String,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    limits: ResourceLimits,
+    /// 运行后端的容器，`command` 是容器中的程序
+    container: Option<ContainerSpec>,
+    /// 代理与后端看到的路径不同时的映射
+    mapping: Option<PathMapping>,
+    /// 后端标准错误输出的解析器
+    log_parser: Arc<dyn LogParser>,
+}
+
+/// 每个分片的完整启动参数，下标与分片相同：默认分片使用 `[backend] args`，`[[shards]]` 中的分片再追加自己的 `args`。
+pub fn shard_args(config: &Config) -> Vec<Vec<String>> {
+    std::iter::once(&Vec::new())
+        .chain(config.shards.iter().map(|shard| &shard.args))
+        .map(|extra| {
+            let mut args = config.backend.args.clone();
+            args.extend(extra.iter().cloned());
+            args
+        })
+        .collect()
+}
+
+/// 一个正在运行的后端进程。
+///
+/// - `sender`: 直接写入该进程标准输入的通道
+/// - `promoted`: 是否是分片当前的主后端；备用后端的消息不会转发给前端
+/// - `child`: 子进程，丢弃时结束进程
+/// - `container`: 后端所在的容器，丢弃时删除
+struct RunningBackend {
+    sender: UnboundedSender<Message>,
+    promoted: Arc<AtomicBool>,
+    child: BackendProcess,
+    _container: Option<ContainerGuard>,
+}
+
+/// 切换后端时重放过的文档版本。
+///
+/// 调度器处理前端的消息时就更新了文档存储，同一文档的 didChange 可能还在分片通道中排队；
+/// 重放的 didOpen 已经包含这次修改，排队的 didChange 再转发给新的后端会让修改应用两次。
+/// 转发时丢弃版本不高于重放版本的 didOpen 和 didChange；didClose 之后文档可能以更小的版本重新打开，记录随之清除。
+#[derive(Debug, Default)]
+pub struct ReplayedDocuments {
+    versions: Mutex<HashMap<String, i64>>,
+}
+
+impl ReplayedDocuments {
+    /// 创建空的记录，所有消息都转发。
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录这次重放的文档（URI 和版本），之前的记录作废。
+    pub fn reset(&self, documents: impl IntoIterator<Item = (String, i64)>) {
+        let mut versions = self.versions.lock().unwrap();
+        versions.clear();
+        versions.extend(documents);
+    }
+
+    /// 分片通道中的消息是否还应当转发给重放过文档的后端。
+    pub fn should_forward(&self, message: &Message) -> bool {
+        let Some(method) = message.method() else {
+            return true;
+        };
+        let body = message.body();
+        let Some(uri) = body
+            .pointer("/params/textDocument/uri")
+            .and_then(Value::as_str)
+        else {
+            return true;
+        };
+        let mut versions = self.versions.lock().unwrap();
+        match method {
+            DidCloseTextDocument::METHOD => {
+                versions.remove(uri);
+                true
+            }
+            DidOpenTextDocument::METHOD | DidChangeTextDocument::METHOD => {
+                let version = body
+                    .pointer("/params/textDocument/version")
+                    .and_then(Value::as_i64);
+                match (versions.get(uri), version) {
+                    (Some(replayed), Some(version)) if version <= *replayed => false,
+                    (Some(_), _) => {
+                        versions.remove(uri);
+                        true
+                    }
+                    (None, _) => true,
+                }
+            }
+            _ => true,
+        }
+    }
+}
+
+/// 分片消息的转发目标。
+///
+/// - `sender`: 当前主后端的输入通道，切换后端时替换
+/// - `replayed`: 切换到这个后端时重放过的文档
+struct ActiveBackend {
+    sender: RwLock<UnboundedSender<Message>>,
+    replayed: ReplayedDocuments,
+}
+
+impl ProcessSpec {
+    /// 按后端配置确定启动方式：ssh 后端在本地运行 ssh，容器中的后端由容器引擎启动，沙箱中的后端由沙箱程序启动。
+    ///
+    /// # 返回
+    ///
+    /// 启动信息、异常退出后重新连接的次数，以及能否通过暂停进程让后端休眠
+    ///
+    /// # 错误
+    ///
+    /// 如果容器配置无效，或者当前平台不支持沙箱，返回错误
+    fn new(
+        shard: usize,
+        name: String,
+        config: &BackendConfig,
+        args: Vec<String>,
+        envs: Vec<(String, String)>,
+    ) -> Result<(Self, u32, bool)> {
+        let container = config
+            .container
+            .as_ref()
+            .map(ContainerSpec::new)
+            .transpose()?;
+        let mapping = match &container {
+            Some(container) => container.mapping()?,
+            None => None,
+        };
+        // ssh 后端在本地运行的是 ssh，远程的命令、参数和环境变量都放进 ssh 的参数
+        let ssh = SshTarget::parse(&config.command).filter(|_| container.is_none());
+        let sandbox = match &config.sandbox {
+            Some(sandbox) if ssh.is_none() && container.is_none() => {
+                Some(SandboxSpec::new(sandbox, &config.command, &envs)?)
+            }
+            Some(_) => {
+                warn!(
+                    "分片 {} 的后端通过 ssh 或在容器中运行，忽略 [backend.sandbox]",
+                    name
+                );
+                None
+            }
+            None => None,
+        };
+        // 暂停本地的 ssh、容器引擎客户端或沙箱程序不会暂停真正的后端
+        let suspendable = cfg!(unix) && ssh.is_none() && container.is_none() && sandbox.is_none();
+        let (command, args, envs, reconnect_attempts) = match ssh {
+            Some(target) => {
+                info!(
+                    "分片 {} 的后端通过 ssh 在 {} 上运行",
+                    name, target.destination
+                );
+                let args = target.ssh_args(&args, &envs, &config.ssh);
+                (
+                    "ssh".to_string(),
+                    args,
+                    Vec::new(),
+                    config.ssh.reconnect_attempts,
+                )
+            }
+            None => match &sandbox {
+                Some(sandbox) => {
+                    info!(
+                        "分片 {} 的后端在 {} 沙箱中运行",
+                        name,
+                        sandbox.tool.program()
+                    );
+                    let args = sandbox.wrap_args(&config.command, &args);
+                    (sandbox.tool.program().to_string(), args, envs, 0)
+                }
+                None => (config.command.clone(), args, envs, 0),
+            },
+        };
+        let spec = Self {
+            shard,
+            name,
+            command,
+            args,
+            envs,
+            limits: config.limits.clone(),
+            container,
+            mapping,
+            log_parser: backend_log::parser(config.log_format, &config.command),
+        };
+        Ok((spec, reconnect_attempts, suspendable))
+    }
+
+    /// 启动后端进程，并开始转发它的输入输出。
+    ///
+    /// # 错误
+    ///
+    /// 如果进程无法启动，返回错误
+    async fn spawn(
+        &self,
+        dispatcher: &Arc<Dispatcher>,
+        limiter: &Arc<HandlerLimiter>,
+        promoted: bool,
+    ) -> Result<RunningBackend> {
+        let mut guard = None;
+        let LspBackend {
+            child,
+            stdin,
+            stdout,
+            stderr,
+            id_counter: _,
+        } = match &self.container {
+            // 资源限制作用于容器，而不是本地的引擎客户端
+            Some(container) => {
+                let name = container.next_name(self.shard);
+                let args =
+                    container.run_args(&name, &self.command, &self.args, &self.envs, &self.limits);
+                guard = Some(ContainerGuard::new(&container.engine, name));
+                LspBackend::spawn(&container.engine, &args, &[], &ResourceLimits::default()).await?
+            }
+            None => LspBackend::spawn(&self.command, &self.args, &self.envs, &self.limits).await?,
+        };
+
+        // serverInfo 中没有版本的旧 clangd 由日志判断版本；主后端的输出记录到后端日志
+        let promoted = Arc::new(AtomicBool::new(promoted));
+        let logs_to = Arc::clone(dispatcher);
+        let shard = self.shard;
+        let logging_promoted = Arc::clone(&promoted);
+        let parser = Arc::clone(&self.log_parser);
+        tokio::spawn(pipe_lsp_backend_stderr(stderr, parser, move |line| {
+            logs_to
+                .backend_capabilities()
+                .record_log_line(&line.message);
+            if logging_promoted.load(Ordering::Relaxed) {
+                logs_to.backend_log().record(shard, line.clone());
+            }
+        }));
+
+        let (sender, mut rx) = mpsc::unbounded_channel::<Message>();
+        if let Some(mapping) = &self.mapping {
+            rx = map_to_backend(rx, mapping.clone());
+        }
+        tokio::spawn(send_data_backend(stdin, rx));
+
+        tokio::spawn(receive_from_process(
+            stdout,
+            self.shard,
+            sender.clone(),
+            Arc::clone(&promoted),
+            Arc::clone(dispatcher),
+            Arc::clone(limiter),
+            self.mapping.clone(),
+        ));
+
+        Ok(RunningBackend {
+            sender,
+            promoted,
+            child,
+            _container: guard,
+        })
+    }
+}
+
+/// 一个分片的后端监管者。
+///
+/// 分片的发送通道始终不变，监管者把其中的消息转发给当前的主后端进程，
+/// 因此切换后端对调度器是透明的。
+pub struct BackendSupervisor {
+    spec: ProcessSpec,
+    standby: bool,
+    max_memory_mb: Option<u64>,
+    /// 后端异常退出后重新连接的次数，只有通过 ssh 启动的后端会重新连接
+    reconnect_attempts: u32,
+    /// 空闲多久之后以什么方式休眠
+    idle: Option<(Duration, IdleAction)>,
+    /// 启动后端时额外设置的环境变量，重新加载后端配置时使用
+    envs: Vec<(String, String)>,
+    active: Arc<ActiveBackend>,
+    shard_rx: UnboundedReceiver<Message>,
+}
+
+impl BackendSupervisor {
+    /// 创建监管者，此时还没有启动任何进程。
+    ///
+    /// # 参数
+    ///
+    /// * `shard` - 分片下标
+    /// * `name` - 分片名称
+    /// * `config` - 后端配置
+    /// * `args` - 该分片的完整启动参数
+    /// * `envs` - 启动后端时额外设置的环境变量
+    ///
+    /// # 返回
+    ///
+    /// 返回监管者和分片的发送通道，发送通道用于构造 `Shard`
+    ///
+    /// # 错误
+    ///
+    /// 如果容器配置无效，返回错误
+    pub fn new(
+        shard: usize,
+        name: String,
+        config: &BackendConfig,
+        args: Vec<String>,
+        envs: Vec<(String, String)>,
+    ) -> Result<(Self, UnboundedSender<Message>)> {
+        let (shard_tx, shard_rx) = mpsc::unbounded_channel::<Message>();
+        // 进程启动之前的消息先由占位通道接收，启动后立即替换
+        let (placeholder, _) = mpsc::unbounded_channel::<Message>();
+        let (spec, reconnect_attempts, suspendable) =
+            ProcessSpec::new(shard, name, config, args, envs.clone())?;
+        let idle = config.idle.after_mins.map(|mins| {
+            let action = match config.idle.action {
+                IdleAction::Suspend if !suspendable => IdleAction::Shutdown,
+                action => action,
+            };
+            // 配置加载时已经检查过范围，这里再限制一次，避免直接构造的配置让间隔为 0 或者溢出
+            let mins = mins.clamp(1, MAX_IDLE_MINS);
+            (Duration::from_secs(mins * 60), action)
+        });
+        let supervisor = Self {
+            spec,
+            standby: config.standby,
+            max_memory_mb: config.max_memory_mb,
+            reconnect_attempts,
+            idle,
+            envs,
+            active: Arc::new(ActiveBackend {
+                sender: RwLock::new(placeholder),
+                replayed: ReplayedDocuments::new(),
+            }),
+            shard_rx,
+        };
+        Ok((supervisor, shard_tx))
+    }
+
+    /// 启动后端进程并持续看护。
+    ///
+    /// 主后端正常退出（`exit` 或者代理关闭）时这个函数返回，代理随之退出。异常退出时，启用了备用后端就切换到
+    /// 备用后端并在后台准备新的备用后端，ssh 后端（通常是连接断开）重新连接，否则同样返回。
+    ///
+    /// 新的后端无法启动时保留仍在运行的主后端；主后端已经退出或者休眠后结束时，再重试启动几次。
+    ///
+    /// # 错误
+    ///
+    /// 如果主后端无法启动，或者多次重新启动、重新连接都失败，返回错误
+    pub async fn run(
+        self,
+        dispatcher: Arc<Dispatcher>,
+        limiter: Arc<HandlerLimiter>,
+    ) -> Result<()> {
+        let Self {
+            mut spec,
+            standby: standby_enabled,
+            max_memory_mb,
+            reconnect_attempts,
+            idle,
+            envs,
+            active,
+            shard_rx,
+        } = self;
+
+        let mut primary = spec.spawn(&dispatcher, &limiter, true).await?;
+        *active.sender.write().unwrap() = primary.sender.clone();
+        dispatcher.health().set_alive(spec.shard, true);
+        let tracker = idle.map(|_| Arc::new(IdleTracker::new()));
+        tokio::spawn(forward_to_active(
+            shard_rx,
+            Arc::clone(&active),
+            tracker.clone(),
+            Arc::clone(&dispatcher),
+            spec.shard,
+        ));
+
+        let mut standby = standby_enabled.then(|| spawn_standby(&spec, &dispatcher, &limiter));
+        let mut memory_check = tokio::time::interval(MEMORY_CHECK_INTERVAL);
+        let mut restart = dispatcher.subscribe_restart();
+        let mut launch = dispatcher.subscribe_backend_launch();
+        let idle_after = idle.map_or(IDLE_CHECK_INTERVAL, |(after, _)| after);
+        let mut idle_check = tokio::time::interval(idle_after.min(IDLE_CHECK_INTERVAL));
+
+        loop {
+            tokio::select! {
+                status = primary.child.wait() => {
+                    warn!("分片 {} 的后端已退出: {:?}", spec.name, status);
+                    dispatcher.backend_exited(spec.shard);
+                    let crashed = !status.is_ok_and(|status| status.success());
+                    if crashed {
+                        dispatcher.report_crash(&format!("分片 {} 的后端异常退出", spec.name));
+                    }
+                    dispatcher.emit_event(ProxyEvent::BackendExited {
+                        shard: spec.name.clone(),
+                    });
+                    // 正常退出（编辑器发送 exit，或者代理关闭时结束后端）不切换到备用后端，也不重新连接
+                    if !crashed {
+                        if let Some(standby) = standby.take() {
+                            standby.abort();
+                        }
+                        return Ok(());
+                    }
+                    let Some(next) = standby.take() else {
+                        if reconnect_attempts == 0 {
+                            return Ok(());
+                        }
+                        primary =
+                            reconnect(&spec, reconnect_attempts, &active, &dispatcher, &limiter)
+                                .await?;
+                        dispatcher.health().set_alive(spec.shard, true);
+                        continue;
+                    };
+                    primary = match promote(&spec, next, &active, &dispatcher, RestartReason::Exited)
+                        .await
+                    {
+                        Ok(next) => next,
+                        Err(e) => {
+                            report_start_failure(&spec, &dispatcher, &e);
+                            reconnect(
+                                &spec,
+                                reconnect_attempts.max(RESTART_ATTEMPTS),
+                                &active,
+                                &dispatcher,
+                                &limiter,
+                            )
+                            .await?
+                        }
+                    };
+                    dispatcher.health().set_alive(spec.shard, true);
+                    standby = Some(spawn_standby(&spec, &dispatcher, &limiter));
+                }
+                _ = memory_check.tick(), if max_memory_mb.is_some() && standby.is_some() => {
+                    let limit = max_memory_mb.unwrap_or(u64::MAX);
+                    let Some(rss) = primary.child.id().and_then(resident_memory_mb) else {
+                        continue;
+                    };
+                    if rss <= limit {
+                        continue;
+                    }
+                    info!(
+                        "分片 {} 的后端占用 {} MB 内存，超过上限 {} MB，切换到备用后端",
+                        spec.name, rss, limit
+                    );
+                    let next = standby.take().expect("备用后端存在时才会检查内存");
+                    if let Err(e) = replace_primary(
+                        &mut primary,
+                        &spec,
+                        next,
+                        &active,
+                        &dispatcher,
+                        RestartReason::Memory,
+                    )
+                    .await
+                    {
+                        report_start_failure(&spec, &dispatcher, &e);
+                    }
+                    standby = Some(spawn_standby(&spec, &dispatcher, &limiter));
+                }
+                _ = idle_check.tick(), if tracker.is_some() => {
+                    let (Some(tracker), Some((after, action))) = (&tracker, idle) else {
+                        continue;
+                    };
+                    if !tracker.try_sleep(after, action) {
+                        continue;
+                    }
+                    // 备用后端在唤醒后重新准备
+                    if let Some(standby) = standby.take() {
+                        standby.abort();
+                    }
+                    primary = hibernate(
+                        primary, action, &spec, tracker, &active, &dispatcher, &limiter,
+                    )
+                    .await?;
+                    tracker.woke();
+                    standby = standby_enabled.then(|| spawn_standby(&spec, &dispatcher, &limiter));
+                }
+                changed = restart.changed() => {
+                    if changed.is_err() {
+                        return Ok(());
+                    }
+                    info!("重启分片 {} 的后端", spec.name);
+                    // 重新加载了后端配置时按新的配置启动，切换成功后才替换原来的配置
+                    let mut relaunched = None;
+                    if launch.has_changed().unwrap_or(false)
+                        && let Some(next) = launch.borrow_and_update().clone()
+                        && let Some(args) = next.args.get(spec.shard)
+                    {
+                        match ProcessSpec::new(
+                            spec.shard,
+                            spec.name.clone(),
+                            &next.config,
+                            args.clone(),
+                            envs.clone(),
+                        ) {
+                            Ok((next, _, _)) => relaunched = Some(next),
+                            Err(e) => warn!("无法应用新的后端配置，继续使用原来的配置: {:?}", e),
+                        }
+                    }
+                    // 没有备用后端时现场启动一个，初始化完成后再切换
+                    let next = match &relaunched {
+                        Some(next) => spawn_standby(next, &dispatcher, &limiter),
+                        None => standby
+                            .take()
+                            .unwrap_or_else(|| spawn_standby(&spec, &dispatcher, &limiter)),
+                    };
+                    let next_spec = relaunched.as_ref().unwrap_or(&spec);
+                    match replace_primary(
+                        &mut primary,
+                        next_spec,
+                        next,
+                        &active,
+                        &dispatcher,
+                        RestartReason::Requested,
+                    )
+                    .await
+                    {
+                        // 原来的备用后端按旧的配置启动，随之作废
+                        Ok(()) => {
+                            if let Some(next) = relaunched {
+                                spec = next;
+                            }
+                            if let Some(standby) = standby.take() {
+                                standby.abort();
+                            }
+                        }
+                        Err(e) => report_start_failure(&spec, &dispatcher, &e),
+                    }
+                    if standby.is_none() {
+                        standby =
+                            standby_enabled.then(|| spawn_standby(&spec, &dispatcher, &limiter));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 在后台启动并初始化一个备用后端。
+///
+/// 备用后端使用与前端相同的 initialize 参数，如果前端还没有发送 initialize，就等待它发送。
+fn spawn_standby(
+    spec: &ProcessSpec,
+    dispatcher: &Arc<Dispatcher>,
+    limiter: &Arc<HandlerLimiter>,
+) -> JoinHandle<Result<RunningBackend>> {
+    let spec = spec.clone();
+    let dispatcher = Arc::clone(dispatcher);
+    let limiter = Arc::clone(limiter);
+    tokio::spawn(async move {
+        let process = spec.spawn(&dispatcher, &limiter, false).await?;
+
+        let mut initialize = dispatcher.subscribe_initialize();
+        let params = initialize
+            .wait_for(|params| params.is_some())
+            .await?
+            .clone()
+            .unwrap_or(json!(null));
+        dispatcher
+            .request_via(&process.sender, Initialize::METHOD, params)
+            .await?;
+        let initialized = json!({
+            "jsonrpc": "2.0",
+            "method": Initialized::METHOD,
+            "params": {},
+        });
+        process.sender.send(Message::new(initialized))?;
+
+        info!("分片 {} 的备用后端已就绪", spec.name);
+        Ok(process)
+    })
+}
+
+/// 新的后端无法启动或者初始化失败时，记录错误并在编辑器中提示。
+fn report_start_failure(spec: &ProcessSpec, dispatcher: &Dispatcher, error: &anyhow::Error) {
+    warn!("分片 {} 的新后端无法启动: {:?}", spec.name, error);
+    dispatcher.show_message(
+        MessageType::WARNING,
+        &format!("分片 {} 的新后端无法启动: {:#}", spec.name, error),
+    );
+}
+
+/// 把备用后端提升为主后端：重放该分片的所有打开文档和还没有得到响应的只读请求，然后切换转发目标。
+///
+/// 重放期间持有写锁，保证前端的新消息不会早于 didOpen 到达新的后端；
+/// 重放的文档版本记录在 [`ActiveBackend::replayed`] 中，排队的同步通知不会再次应用已经重放的修改。
+/// 不能重放的请求和已经重放过一次的请求不再发出，切换后以错误应答。
+async fn promote(
+    spec: &ProcessSpec,
+    next: JoinHandle<Result<RunningBackend>>,
+    active: &Arc<ActiveBackend>,
+    dispatcher: &Arc<Dispatcher>,
+    reason: RestartReason,
+) -> Result<RunningBackend> {
+    let next = next
+        .await
+        .map_err(|e| anyhow!("备用后端启动任务失败: {}", e))??;
+
+    let replay = {
+        let mut sender = active.sender.write().unwrap();
+        let mut documents = Vec::new();
+        for doc in dispatcher.documents().documents() {
+            if dispatcher.shard_for_uri(&doc.uri) != spec.shard {
+                continue;
+            }
+            next.sender
+                .send(Message::new(doc.did_open_notification()))?;
+            documents.push((doc.uri.to_string(), i64::from(doc.version)));
+        }
+        let documents_replayed = documents.len();
+        let replay = dispatcher.journal().replay(spec.shard);
+        for request in &replay.requests {
+            next.sender.send(Message::new(request.clone()))?;
+        }
+        // 重放全部送达之后才切换，切换失败时原来的后端不受影响
+        active.replayed.reset(documents);
+        next.promoted.store(true, Ordering::Relaxed);
+        *sender = next.sender.clone();
+        dispatcher.stats().backend_restarted();
+        dispatcher.emit_event(ProxyEvent::BackendRestarted {
+            shard: spec.name.clone(),
+            reason,
+        });
+
+        info!(
+            "分片 {} 已切换到备用后端，重放了 {} 个打开的文档和 {} 个请求",
+            spec.name,
+            documents_replayed,
+            replay.requests.len()
+        );
+        replay
+    };
+    for response in replay.failed {
+        warn!(
+            "分片 {} 的请求 {} 没有重放: {}",
+            spec.name, response["id"], response["error"]["message"]
+        );
+        if let Err(e) = dispatcher.handle_from_shard(spec.shard, response).await {
+            warn!("无法应答重放失败的请求: {:?}", e);
+        }
+    }
+    Ok(next)
+}
+
+/// 重新启动分片的后端（断开的 ssh 后端，或者备用后端没能接替的后端），初始化后重放打开的文档。
+/// 每次失败后等待的时间加倍。
+///
+/// # 错误
+///
+/// 如果 `attempts` 次重试都失败，返回错误
+async fn reconnect(
+    spec: &ProcessSpec,
+    attempts: u32,
+    active: &Arc<ActiveBackend>,
+    dispatcher: &Arc<Dispatcher>,
+    limiter: &Arc<HandlerLimiter>,
+) -> Result<RunningBackend> {
+    let mut delay = RECONNECT_DELAY;
+    for attempt in 1..=attempts {
+        tokio::time::sleep(delay).await;
+        info!("重新连接分片 {} 的后端（第 {} 次）", spec.name, attempt);
+        let next = spawn_standby(spec, dispatcher, limiter);
+        // 连接失败时 initialize 永远等不到响应，超时后结束这次尝试，进程随任务一起被丢弃
+        let abort = next.abort_handle();
+        let promoted = promote(spec, next, active, dispatcher, RestartReason::Exited);
+        match tokio::time::timeout(RECONNECT_TIMEOUT, promoted).await {
+            Ok(Ok(backend)) => return Ok(backend),
+            Ok(Err(e)) => warn!("分片 {} 重新连接失败: {:?}", spec.name, e),
+            Err(_) => {
+                abort.abort();
+                warn!("分片 {} 重新连接超时", spec.name);
+            }
+        }
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+    }
+    Err(anyhow!(
+        "分片 {} 重新连接 {} 次后仍然失败",
+        spec.name,
+        attempts
+    ))
+}
+
+/// 用备用后端替换仍在运行的主后端，然后结束旧的进程。
+async fn replace_primary(
+    primary: &mut RunningBackend,
+    spec: &ProcessSpec,
+    next: JoinHandle<Result<RunningBackend>>,
+    active: &Arc<ActiveBackend>,
+    dispatcher: &Arc<Dispatcher>,
+    reason: RestartReason,
+) -> Result<()> {
+    let old = std::mem::replace(
+        primary,
+        promote(spec, next, active, dispatcher, reason).await?,
+    );
+    if let Err(e) = old.child.shutdown().await {
+        warn!("无法结束旧的后端进程: {}", e);
+    }
+    Ok(())
+}
+
+/// 让空闲的主后端休眠，等到有消息要发给这个分片时唤醒。
+///
+/// 暂停的进程继续运行；结束的进程由新启动的后端代替，初始化后重放打开的文档。
+///
+/// # 错误
+///
+/// 如果新的后端多次重新启动都失败，返回错误
+async fn hibernate(
+    primary: RunningBackend,
+    action: IdleAction,
+    spec: &ProcessSpec,
+    tracker: &IdleTracker,
+    active: &Arc<ActiveBackend>,
+    dispatcher: &Arc<Dispatcher>,
+    limiter: &Arc<HandlerLimiter>,
+) -> Result<RunningBackend> {
+    match action {
+        #[cfg(unix)]
+        IdleAction::Suspend => {
+            let pid = primary.child.id();
+            info!("分片 {} 的后端空闲，暂停进程", spec.name);
+            if let Some(pid) = pid
+                && let Err(e) = crate::platform::set_process_stopped(pid, true)
+            {
+                warn!("无法暂停分片 {} 的后端: {}", spec.name, e);
+            }
+            tracker.wait_for_wake().await;
+            info!("继续运行分片 {} 的后端", spec.name);
+            if let Some(pid) = pid
+                && let Err(e) = crate::platform::set_process_stopped(pid, false)
+            {
+                warn!("无法继续运行分片 {} 的后端: {}", spec.name, e);
+            }
+            Ok(primary)
+        }
+        _ => {
+            info!("分片 {} 的后端空闲，结束进程", spec.name);
+            if let Err(e) = primary.child.shutdown().await {
+                warn!("无法结束空闲的后端进程: {}", e);
+            }
+            tracker.wait_for_wake().await;
+            info!("重新启动分片 {} 的后端", spec.name);
+            let next = spawn_standby(spec, dispatcher, limiter);
+            match promote(spec, next, active, dispatcher, RestartReason::Idle).await {
+                Ok(next) => Ok(next),
+                Err(e) => {
+                    report_start_failure(spec, dispatcher, &e);
+                    reconnect(spec, RESTART_ATTEMPTS, active, dispatcher, limiter).await
+                }
+            }
+        }
+    }
+}
+
+/// 把分片通道中的消息转发给当前的主后端，后端休眠时先唤醒它。
+///
+/// 已经结束的空闲后端不会为了退出而重新启动：`shutdown` 请求由代理应答，`exit` 被丢弃。
+/// 切换后端时已经随重放送达的文档修改被丢弃，见 [`ReplayedDocuments`]。
+async fn forward_to_active(
+    mut shard_rx: UnboundedReceiver<Message>,
+    active: Arc<ActiveBackend>,
+    idle: Option<Arc<IdleTracker>>,
+    dispatcher: Arc<Dispatcher>,
+    shard: usize,
+) {
+    while let Some(message) = shard_rx.recv().await {
+        if let Some(idle) = &idle {
+            match idle.touch() {
+                None => {}
+                Some(IdleAction::Shutdown) if message.method() == Some(Exit::METHOD) => continue,
+                Some(IdleAction::Shutdown) if message.method() == Some(Shutdown::METHOD) => {
+                    let reply = json!({"jsonrpc": "2.0", "id": message.id(), "result": null});
+                    if let Err(e) = dispatcher.handle_from_shard(shard, reply).await {
+                        warn!("无法应答 shutdown 请求: {:?}", e);
+                    }
+                    continue;
+                }
+                Some(_) => idle.wake().await,
+            }
+        }
+        let sender = active.sender.read().unwrap();
+        if !active.replayed.should_forward(&message) {
+            debug!(
+                "文档修改已经随重放发给后端，丢弃排队的 {:?}",
+                message.method()
+            );
+            continue;
+        }
+        if sender.send(message).is_err() {
+            warn!("后端进程已退出，消息被丢弃");
+        }
+    }
+}
+
+/// 把发给后端的消息中代理看到的路径换成后端看到的路径，返回替换后的消息通道。
+fn map_to_backend(
+    mut rx: UnboundedReceiver<Message>,
+    mapping: PathMapping,
+) -> UnboundedReceiver<Message> {
+    let (tx, mapped) = mpsc::unbounded_channel::<Message>();
+    tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let mut body = message.into_body();
+            mapping.to_remote(&mut body);
+            if tx.send(Message::new(body)).is_err() {
+                break;
+            }
+        }
+    });
+    mapped
+}
+
+/// 读取一个后端进程的输出，根据它当前的角色交给调度器处理。
+async fn receive_from_process(
+    stdout: ChildStdout,
+    shard: usize,
+    sender: UnboundedSender<Message>,
+    promoted: Arc<AtomicBool>,
+    dispatcher: Arc<Dispatcher>,
+    limiter: Arc<HandlerLimiter>,
+    mapping: Option<PathMapping>,
+) -> Result<()> {
+    let mut reader = FramedRead::new(stdout, LspCodec::default());
+
+    while let Some(mut json_body) = reader.try_next().await? {
+        if let Some(mapping) = &mapping {
+            mapping.to_local(&mut json_body);
+        }
+        for json_body in batch::split_batch(json_body) {
+            let ticket = dispatcher
+                .lanes()
+                .enter(Direction::Backend, Some(shard), &json_body);
+            let dispatcher = dispatcher.clone();
+            let sender = sender.clone();
+            let promoted = promoted.load(Ordering::Relaxed);
+            limiter
+                .spawn(json_body, move |rpc| async move {
+                    let _ticket = ticket.wait().await;
+                    let result = if promoted {
+                        dispatcher.handle_from_shard(shard, rpc).await
+                    } else {
+                        dispatcher.handle_from_standby(&sender, rpc).await
+                    };
+                    if let Err(e) = result {
+                        error!("处理失败: {:?}", e);
+                    }
+                })
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// 读取进程的常驻内存（MB），目前只支持 Linux。
+#[cfg(target_os = "linux")]
+fn resident_memory_mb(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb / 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resident_memory_mb(_pid: u32) -> Option<u64> {
+    None
+}
@@ -0,0 +1,267 @@
+//! # 符号索引模块
+//!
+//! clangd 的后台索引在大型项目中需要很长时间才能完成，在此之前 `workspace/symbol` 几乎查不到结果。
+//! 这个模块由代理维护一个通过 ctags 快速生成的符号索引，精度较低但立即可用，
+//! 在 clangd 索引完成之前用来应答 `workspace/symbol`，完成之后再交还给 clangd。
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use log::{info, warn};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::process::Command;
+use tower_lsp::lsp_types::request::{Request, WorkspaceSymbolRequest};
+use tower_lsp::lsp_types::{Location, Position, Range, SymbolInformation, SymbolKind, Url};
+
+use crate::dispatcher::Dispatcher;
+use crate::handlers::{self, HandlerCtx, LOCAL_PROVIDER_PRIORITY, Verdict};
+use crate::trace::Direction;
+
+/// clangd 后台索引进度使用的 `$/progress` token。
+pub const BACKGROUND_INDEX_TOKEN: &str = "backgroundIndexProgress";
+
+/// 单次查询最多返回的符号数量，与 clangd 的默认值保持一致。
+pub const DEFAULT_QUERY_LIMIT: usize = 100;
+
+/// 基于 ctags 的后备符号索引。
+///
+/// - `symbols`: 当前索引中的全部符号
+/// - `backend_ready`: 后端索引是否已经完成，完成后不再使用后备索引
+/// - `ctags_program`: 用于生成索引的 ctags 可执行文件
+pub struct SymbolIndex {
+    symbols: RwLock<Vec<SymbolInformation>>,
+    backend_ready: AtomicBool,
+    ctags_program: String,
+}
+
+impl SymbolIndex {
+    /// 创建一个空索引。
+    ///
+    /// # 参数
+    ///
+    /// * `ctags_program` - ctags 可执行文件，需要支持 `--output-format=json`（universal-ctags）
+    pub fn new(ctags_program: &str) -> Self {
+        Self {
+            symbols: RwLock::new(Vec::new()),
+            backend_ready: AtomicBool::new(false),
+            ctags_program: ctags_program.to_string(),
+        }
+    }
+
+    /// 对给定的工作区根目录重新运行 ctags 并替换索引内容。
+    ///
+    /// # 返回
+    ///
+    /// 返回索引中的符号数量
+    ///
+    /// # 错误
+    ///
+    /// 如果 ctags 无法启动或者退出码非零，返回错误，原有索引保持不变
+    pub async fn rebuild(&self, roots: &[PathBuf]) -> Result<usize> {
+        let mut symbols = Vec::new();
+        for root in roots {
+            let output = Command::new(&self.ctags_program)
+                .args([
+                    "-R",
+                    "--output-format=json",
+                    "--fields=+nKZ",
+                    "--languages=C,C++",
+                    "-f",
+                    "-",
+                ])
+                .arg(root)
+                .output()
+                .await
+                .with_context(|| format!("无法启动 {}", self.ctags_program))?;
+
+            if !output.status.success() {
+                anyhow::bail!(
+                    "{} 退出码 {:?}: {}",
+                    self.ctags_program,
+                    output.status.code(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            symbols.extend(parse_ctags_output(&String::from_utf8_lossy(&output.stdout)));
+        }
+
+        let count = symbols.len();
+        *self.symbols.write().unwrap() = symbols;
+        info!("ctags 索引已生成，共 {} 个符号", count);
+        Ok(count)
+    }
+
+    /// 直接用 ctags 的 JSON 输出替换索引内容。
+    ///
+    /// # 返回
+    ///
+    /// 返回索引中的符号数量
+    pub fn load_ctags_output(&self, output: &str) -> usize {
+        let symbols = parse_ctags_output(output);
+        let count = symbols.len();
+        *self.symbols.write().unwrap() = symbols;
+        count
+    }
+
+    /// 标记后端索引已经完成，此后 `workspace/symbol` 交由后端处理。
+    pub fn mark_backend_ready(&self) {
+        if !self.backend_ready.swap(true, Ordering::Relaxed) {
+            info!("后端索引已就绪，workspace/symbol 不再使用 ctags 索引");
+        }
+    }
+
+    /// 后端索引是否已经完成。
+    pub fn is_backend_ready(&self) -> bool {
+        self.backend_ready.load(Ordering::Relaxed)
+    }
+
+    /// 是否应当由后备索引应答 `workspace/symbol`。
+    pub fn should_answer(&self) -> bool {
+        !self.is_backend_ready() && !self.symbols.read().unwrap().is_empty()
+    }
+
+    /// 按名称查询符号。
+    ///
+    /// 匹配规则与 clangd 类似：忽略大小写的子序列匹配，前缀匹配和更短的名字排在前面。
+    ///
+    /// # 参数
+    ///
+    /// * `query` - 查询字符串，为空时返回任意符号
+    /// * `limit` - 最多返回的数量
+    pub fn query(&self, query: &str, limit: usize) -> Vec<SymbolInformation> {
+        let query = query.to_lowercase();
+        let symbols = self.symbols.read().unwrap();
+
+        let mut matches: Vec<(bool, &SymbolInformation)> = symbols
+            .iter()
+            .filter_map(|symbol| {
+                let name = symbol.name.to_lowercase();
+                is_subsequence(&query, &name).then(|| (!name.starts_with(&query), symbol))
+            })
+            .collect();
+        matches.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.name.len().cmp(&b.1.name.len())));
+
+        matches
+            .into_iter()
+            .take(limit)
+            .map(|(_, symbol)| symbol.clone())
+            .collect()
+    }
+}
+
+/// 判断消息是否为 clangd 后台索引结束的 `$/progress` 通知。
+pub fn is_index_progress_end(rpc: &Value) -> bool {
+    rpc.get("method").and_then(|m| m.as_str()) == Some("$/progress")
+        && rpc.pointer("/params/token").and_then(|t| t.as_str()) == Some(BACKGROUND_INDEX_TOKEN)
+        && rpc.pointer("/params/value/kind").and_then(|k| k.as_str()) == Some("end")
+}
+
+fn parse_ctags_output(output: &str) -> Vec<SymbolInformation> {
+    output
+        .lines()
+        .filter_map(|line| match serde_json::from_str::<Value>(line) {
+            Ok(tag) => parse_ctags_tag(&tag),
+            Err(e) => {
+                warn!("无法解析 ctags 输出: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+#[allow(deprecated)] // SymbolInformation::deprecated 字段已废弃，但构造时必须提供
+fn parse_ctags_tag(tag: &Value) -> Option<SymbolInformation> {
+    if tag.get("_type").and_then(|t| t.as_str()) != Some("tag") {
+        return None;
+    }
+    let name = tag.get("name")?.as_str()?;
+    let path = tag.get("path")?.as_str()?;
+    let line = tag.get("line")?.as_u64()?.saturating_sub(1) as u32;
+    let kind = tag.get("kind").and_then(|k| k.as_str()).unwrap_or("");
+
+    // ctags 只给出行号，从搜索模式中推算符号所在的列。LSP 的列按 UTF-16 计数，
+    // 因此用行中符号之前的文本换算
+    let character = tag
+        .get("pattern")
+        .and_then(|p| p.as_str())
+        .map(|p| unescape_pattern(p.trim_start_matches("/^")))
+        .and_then(|line| {
+            line.find(name)
+                .map(|col| line[..col].encode_utf16().count() as u32)
+        })
+        .unwrap_or(0);
+
+    let uri = Url::from_file_path(Path::new(path)).ok()?;
+    let start = Position::new(line, character);
+    let end = Position::new(line, character + name.encode_utf16().count() as u32);
+
+    Some(SymbolInformation {
+        name: name.to_string(),
+        kind: symbol_kind(kind),
+        tags: None,
+        deprecated: None,
+        location: Location::new(uri, Range::new(start, end)),
+        container_name: tag
+            .get("scope")
+            .and_then(|s| s.as_str())
+            .map(|s| s.to_string()),
+    })
+}
+
+/// 去掉 ctags 搜索模式中 `\\` 和 `\/` 的转义。
+fn unescape_pattern(pattern: &str) -> String {
+    let mut line = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => line.extend(chars.next()),
+            c => line.push(c),
+        }
+    }
+    line
+}
+
+fn symbol_kind(ctags_kind: &str) -> SymbolKind {
+    match ctags_kind {
+        "function" | "prototype" => SymbolKind::FUNCTION,
+        "class" => SymbolKind::CLASS,
+        "struct" => SymbolKind::STRUCT,
+        "union" => SymbolKind::STRUCT,
+        "enum" => SymbolKind::ENUM,
+        "enumerator" => SymbolKind::ENUM_MEMBER,
+        "member" => SymbolKind::FIELD,
+        "namespace" => SymbolKind::NAMESPACE,
+        "macro" => SymbolKind::CONSTANT,
+        // 与 clangd 一样，类型别名显示为类
+        "typedef" | "alias" => SymbolKind::CLASS,
+        "variable" | "externvar" => SymbolKind::VARIABLE,
+        _ => SymbolKind::NULL,
+    }
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut chars = haystack.chars();
+    needle.chars().all(|c| chars.any(|h| h == c))
+}
+
+/// 注册工作区符号的处理器，后端索引就绪之前由 ctags 索引应答。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        WorkspaceSymbolRequest::METHOD,
+        LOCAL_PROVIDER_PRIORITY,
+        answer_symbols,
+    );
+}
+
+/// 以 ctags 索引中匹配查询的符号应答 `workspace/symbol`。
+fn answer_symbols(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        match ctx.dispatcher().local_workspace_symbols(&rpc) {
+            Some(result) => Ok(Verdict::Respond(handlers::response(&rpc, Ok(result)))),
+            None => Ok(Verdict::Continue(rpc)),
+        }
+    })
+}
@@ -0,0 +1,47 @@
+//! # 语法模块
+//!
+//! 用 tree-sitter 解析 C/C++ 文档，找出注释、字符串字面量等节点。只用于代理自己的轻量检查，
+//! 不需要完整的语义，解析出错的部分照常跳过。
+
+use log::warn;
+use std::ops::Range;
+
+/// 注释节点。
+pub const COMMENT: &str = "comment";
+
+/// 字符串字面量的内容，不含引号和转义序列。
+pub const STRING_CONTENTS: &[&str] = &["string_content", "raw_string_content"];
+
+/// 按 C++ 语法解析文档，语法无法加载时返回 `None`。
+pub fn parse(text: &str) -> Option<tree_sitter::Tree> {
+    let mut parser = tree_sitter::Parser::new();
+    if let Err(e) = parser.set_language(&tree_sitter_cpp::LANGUAGE.into()) {
+        warn!("无法加载 C++ 语法: {}", e);
+        return None;
+    }
+    parser.parse(text, None)
+}
+
+/// 文档中指定类型的节点的字节范围，按在文档中的顺序排列。节点内部不再查找。
+pub fn node_ranges(text: &str, kinds: &[&str]) -> Vec<Range<usize>> {
+    let Some(tree) = parse(text) else {
+        return Vec::new();
+    };
+
+    let mut ranges = Vec::new();
+    let mut cursor = tree.walk();
+    'walk: loop {
+        let node = cursor.node();
+        if kinds.contains(&node.kind()) {
+            ranges.push(node.byte_range());
+        } else if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+    ranges
+}
@@ -1,12 +1,106 @@
-use anyhow::{Context, Result};
-use log::{error, info, trace};
+use anyhow::Result;
+use futures::TryStreamExt;
+use log::{error, trace};
 use serde_json::Value;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Stdin, Stdout};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::process::{ChildStdin, ChildStdout};
-use tokio::sync::{Semaphore, mpsc};
+use tokio::sync::{Semaphore, mpsc, watch};
+use tokio::time;
+use tokio_util::codec::FramedRead;
+use tokio_util::sync::CancellationToken;
 
+use crate::batch::{self, BatchTracker, Outgoing};
+use crate::codec::LspCodec;
+use crate::compression::FrameCompression;
 use crate::dispatcher::Dispatcher;
+use crate::message::Message;
+use crate::metrics::Metrics;
+use crate::trace::Direction;
+
+/// 消息处理任务的并发上限。
+///
+/// 读取循环在启动处理任务之前获取许可，许可随任务移动，任务结束时才释放；
+/// 许可用完时读取循环暂停，不再读取新消息。响应不占用许可：处理任务可能正在等待后端或前端的响应，
+/// 让响应排队等许可会使这些任务永远等不到结果。
+pub struct HandlerLimiter {
+    semaphore: Arc<Semaphore>,
+    limit: AtomicUsize,
+    metrics: Arc<Metrics>,
+}
+
+impl HandlerLimiter {
+    /// 创建最多同时运行 `limit` 个处理任务的限制器，排队和运行情况记录到 `metrics`。
+    pub fn new(limit: usize, metrics: Arc<Metrics>) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit.max(1))),
+            limit: AtomicUsize::new(limit.max(1)),
+            metrics,
+        }
+    }
+
+    /// 当前的并发上限。
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// 修改并发上限。降低上限时，多出的许可在正在运行的任务结束后收回。
+    pub fn set_limit(&self, limit: usize) {
+        let limit = limit.max(1);
+        let previous = self.limit.swap(limit, Ordering::Relaxed);
+        if limit > previous {
+            self.semaphore.add_permits(limit - previous);
+        } else if limit < previous {
+            let semaphore = Arc::clone(&self.semaphore);
+            let excess = u32::try_from(previous - limit).unwrap_or(u32::MAX);
+            tokio::spawn(async move {
+                if let Ok(permits) = semaphore.acquire_many_owned(excess).await {
+                    permits.forget();
+                }
+            });
+        }
+    }
+
+    /// 按 `limits` 中的值调整并发上限，直到发送端被丢弃。
+    pub async fn follow(self: Arc<Self>, mut limits: watch::Receiver<usize>) {
+        while limits.changed().await.is_ok() {
+            let limit = *limits.borrow_and_update();
+            self.set_limit(limit);
+        }
+    }
+
+    /// 等待许可，然后在新任务中运行 `handle(rpc)`，任务结束时归还许可。
+    ///
+    /// # 错误
+    ///
+    /// 如果信号量已关闭，返回错误
+    pub async fn spawn<F, Fut>(&self, rpc: Value, handle: F) -> Result<()>
+    where
+        F: FnOnce(Value) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        if rpc.get("method").is_none() {
+            tokio::spawn(handle(rpc));
+            return Ok(());
+        }
+
+        self.metrics.handler_queued();
+        let start = Instant::now();
+        let permit = Arc::clone(&self.semaphore).acquire_owned().await?;
+        self.metrics.handler_started(start.elapsed());
+
+        let metrics = Arc::clone(&self.metrics);
+        let task = handle(rpc);
+        tokio::spawn(async move {
+            task.await;
+            metrics.handler_finished();
+            drop(permit);
+        });
+        Ok(())
+    }
+}
 
 /// 向后端（clangd）发送数据的异步任务。
 ///
@@ -27,9 +121,10 @@ use crate::dispatcher::Dispatcher;
 /// 如果写入或刷新失败，将返回错误
 pub async fn send_data_backend(
     mut stdin: ChildStdin,
-    mut rx: mpsc::UnboundedReceiver<String>,
+    mut rx: mpsc::UnboundedReceiver<Message>,
 ) -> Result<()> {
     while let Some(message) = rx.recv().await {
+        let message = message.to_lsp_string()?;
         // 发送数据到外部程序
         stdin.write_all(message.as_bytes()).await?;
         stdin.flush().await?;
@@ -45,8 +140,10 @@ pub async fn send_data_backend(
 ///
 /// # 参数
 ///
-/// * `stdout` - clangd 进程的标准输出缓冲读取器
+/// * `stdout` - clangd 进程的标准输出
+/// * `shard` - 该后端对应的分片下标
 /// * `dispatcher` - 调度器实例，用于处理接收到的消息
+/// * `limiter` - 处理任务的并发上限
 ///
 /// # 返回
 ///
@@ -56,59 +153,31 @@ pub async fn send_data_backend(
 ///
 /// 如果读取、解析或处理消息失败，将返回错误
 pub async fn receive_data_backend(
-    stdout: BufReader<ChildStdout>,
+    stdout: ChildStdout,
+    shard: usize,
     dispatcher: Arc<Dispatcher>,
-    semaphore: Arc<Semaphore>,
+    limiter: Arc<HandlerLimiter>,
 ) -> Result<()> {
-    let mut reader = stdout;
+    let mut reader = FramedRead::new(stdout, LspCodec::default());
 
-    loop {
-        // 1. 读取 header
-        let mut content_length = None;
-        loop {
-            let mut line = String::new();
-            reader.read_line(&mut line).await?;
-            let line = line.trim();
-
-            if line.is_empty() {
-                break; // header 结束
-            }
-
-            if let Some(cl) = line.strip_prefix("Content-Length:") {
-                content_length = Some(
-                    cl.trim()
-                        .parse::<usize>()
-                        .context("Content-Length 解析失败")?,
-                );
-            }
+    while let Some(json_body) = reader.try_next().await? {
+        for json_body in batch::split_batch(json_body) {
+            // 并发处理，许可用完时等待；同一文档的诊断按到达顺序处理
+            let ticket = dispatcher
+                .lanes()
+                .enter(Direction::Backend, Some(shard), &json_body);
+            let dispatcher = dispatcher.clone();
+            limiter
+                .spawn(json_body, move |rpc| async move {
+                    let _ticket = ticket.wait().await;
+                    if let Err(e) = dispatcher.handle_from_shard(shard, rpc).await {
+                        error!("处理失败: {:?}", e);
+                    }
+                })
+                .await?;
         }
-
-        let content_length = match content_length {
-            Some(len) => len,
-            None => continue, // 没有 Content-Length，跳过
-        };
-
-        // 2. 读取 body
-        let mut body_buf = vec![0u8; content_length];
-        reader.read_exact(&mut body_buf).await?;
-        // let body_str = String::from_utf8(body_buf).context("UTF-8 解码失败")?;
-
-        // 3. 解析 JSON
-        // let json_body: Value = serde_json::from_str(&body_str).context("JSON 解析失败")?;
-        let json_body: Value = serde_json::from_slice(&body_buf).context("JSON 解析失败")?;
-
-        // 限制并发：获取许可
-        let permit = semaphore.clone().acquire_owned().await?;
-        let _ = permit;
-
-        // 5. 并发处理
-        let dispatcher = dispatcher.clone();
-        tokio::spawn(async move {
-            if let Err(e) = dispatcher.handle_from_backend(json_body).await {
-                error!("处理失败: {:?}", e);
-            }
-        });
     }
+    Ok(())
 }
 
 /// 向前端（VSCode）发送数据的异步任务。
@@ -118,8 +187,11 @@ pub async fn receive_data_backend(
 ///
 /// # 参数
 ///
-/// * `stdout` - 标准输出句柄
+/// * `stdout` - 标准输出句柄，基准测试中是内存管道
 /// * `rx` - 从调度器接收消息的通道接收器
+/// * `batches` - 前端发来的批量请求，其中请求的响应合并为一个数组发送
+/// * `closing` - 取消后不再接受新消息，写完通道中已有的消息后返回
+/// * `compression` - 套接字传输上的帧压缩，`None` 时不压缩
 ///
 /// # 返回
 ///
@@ -128,19 +200,64 @@ pub async fn receive_data_backend(
 /// # 错误
 ///
 /// 如果写入或刷新失败，将返回错误
-pub async fn send_data_frontend(
-    mut stdout: Stdout,
-    mut rx: mpsc::UnboundedReceiver<String>,
-) -> Result<()> {
-    while let Some(message) = rx.recv().await {
-        // 发送数据到vscode
-        stdout.write_all(message.as_bytes()).await?;
-        stdout.flush().await?;
-        trace!("已发送: {}", message);
+pub async fn send_data_frontend<W>(
+    mut stdout: W,
+    mut rx: mpsc::UnboundedReceiver<Message>,
+    batches: Arc<BatchTracker>,
+    closing: CancellationToken,
+    compression: Option<Arc<FrameCompression>>,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        let deadline = batches.next_deadline().map(time::Instant::from_std);
+        let message = tokio::select! {
+            message = rx.recv() => message,
+            _ = closing.cancelled(), if !rx.is_closed() => {
+                rx.close();
+                continue;
+            }
+            // 批量请求超时：没有响应的请求以错误应答，发送整个响应数组
+            () = time::sleep_until(deadline.unwrap_or_else(time::Instant::now)), if deadline.is_some() => {
+                for responses in batches.expire(Instant::now()) {
+                    write_frame(&mut stdout, &compression, &responses).await?;
+                }
+                continue;
+            }
+        };
+        let Some(message) = message else {
+            break;
+        };
+        let message = match batches.outgoing(message) {
+            Outgoing::Send(message) => message.into_body(),
+            Outgoing::Flush(responses) => responses,
+            Outgoing::Hold => continue,
+        };
+        write_frame(&mut stdout, &compression, &message).await?;
     }
     Ok(())
 }
 
+async fn write_frame<W>(
+    stdout: &mut W,
+    compression: &Option<Arc<FrameCompression>>,
+    message: &Value,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let frame = match compression {
+        Some(compression) => compression.encode_frame(message)?,
+        None => Dispatcher::format_lsp_message(message)?.into_bytes(),
+    };
+    // 发送数据到vscode
+    stdout.write_all(&frame).await?;
+    stdout.flush().await?;
+    trace!("已发送: {}", message);
+    Ok(())
+}
+
 /// 从前端（VSCode）接收数据的异步任务。
 ///
 /// 这个函数读取标准输入，按照 LSP 协议解析消息头和消息体，
@@ -148,8 +265,11 @@ pub async fn send_data_frontend(
 ///
 /// # 参数
 ///
-/// * `stdin` - 标准输入缓冲读取器
+/// * `stdin` - 标准输入，基准测试中是内存管道
 /// * `dispatcher` - 调度器实例，用于处理接收到的消息
+/// * `limiter` - 处理任务的并发上限
+/// * `batches` - 登记收到的批量请求，以便合并它们的响应
+/// * `compression` - 套接字传输上的帧压缩，`None` 时不处理压缩的消息头
 ///
 /// # 返回
 ///
@@ -158,57 +278,45 @@ pub async fn send_data_frontend(
 /// # 错误
 ///
 /// 如果读取、解析或处理消息失败，将返回错误
-pub async fn receive_data_frontend(
-    stdin: BufReader<Stdin>,
+pub async fn receive_data_frontend<R>(
+    stdin: R,
     dispatcher: Arc<Dispatcher>,
-    semaphore: Arc<Semaphore>,
-) -> Result<()> {
-    let mut reader = stdin;
+    limiter: Arc<HandlerLimiter>,
+    batches: Arc<BatchTracker>,
+    compression: Option<Arc<FrameCompression>>,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut reader = FramedRead::new(stdin, LspCodec::with_compression(compression));
 
-    loop {
-        // 1. 读取 header
-        let mut content_length = None;
-        loop {
-            let mut line = String::new();
-            reader.read_line(&mut line).await?;
-            let line = line.trim();
-
-            if line.is_empty() {
-                break;
+    while let Some(json_body) = reader.try_next().await? {
+        if let Value::Array(items) = &json_body {
+            if items.is_empty() {
+                dispatcher.send_to_frontend(&batch::empty_batch_error())?;
+                continue;
             }
-
-            if let Some(cl) = line.strip_prefix("Content-Length:") {
-                content_length = Some(
-                    cl.trim()
-                        .parse::<usize>()
-                        .context("Content-Length 解析失败")?,
-                );
+            if let Some(errors) = batches.register(items) {
+                dispatcher.send_to_frontend(&errors)?;
             }
         }
 
-        let content_length = match content_length {
-            Some(len) => len,
-            None => continue,
-        };
-
-        // 2. 读取 body
-        let mut body_buf = vec![0u8; content_length];
-        reader.read_exact(&mut body_buf).await?;
-        // let body_str = String::from_utf8(body_buf).context("UTF-8 解码失败")?;
-
-        // 3. 解析 JSON
-        // let json_body: Value = serde_json::from_str(&body_str).context("JSON 解析失败")?;
-        let json_body: Value = serde_json::from_slice(&body_buf).context("JSON 解析失败")?;
-
-        let permit = semaphore.clone().acquire_owned().await?;
-        let _ = permit;
-
-        // 5. 并发处理
-        let dispatcher = dispatcher.clone();
-        tokio::spawn(async move {
-            if let Err(e) = dispatcher.handle_from_frontend(json_body).await {
-                error!("前端消息处理失败: {:?}", e);
-            }
-        });
+        for mut json_body in batch::split_batch(json_body) {
+            dispatcher.session().incoming(&mut json_body);
+            // 并发处理，许可用完时等待；同一文档的同步通知按到达顺序处理
+            let ticket = dispatcher
+                .lanes()
+                .enter(Direction::Frontend, None, &json_body);
+            let dispatcher = dispatcher.clone();
+            limiter
+                .spawn(json_body, move |rpc| async move {
+                    let _ticket = ticket.wait().await;
+                    if let Err(e) = dispatcher.handle_from_frontend(rpc).await {
+                        error!("前端消息处理失败: {:?}", e);
+                    }
+                })
+                .await?;
+        }
     }
+    Ok(())
 }
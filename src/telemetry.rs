@@ -0,0 +1,296 @@
+//! # 遥测模块
+//!
+//! 为每个前端请求记录跨度：根跨度从代理收到请求到把响应交给前端，子跨度是请求在后端停留的时间，
+//! 两者之差就是代理自身的开销。配置了 OTLP 端点时定期以 OTLP/HTTP JSON 格式导出，
+//! 便于在多人共享的远程开发环境中分析延迟花在了哪里。
+
+use anyhow::{Context, Result, anyhow, bail};
+use log::{debug, warn};
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::config::TelemetryConfig;
+use crate::lifecycle::{FinishedRequest, RequestObserver};
+
+/// 等待导出的跨度数量上限，导出跟不上时丢弃最早的跨度。
+const MAX_BUFFERED_SPANS: usize = 4096;
+
+/// 一次导出最多等待的时间，收集器没有响应时放弃这一批跨度。
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 跨度的类型，取值与 OTLP 的 `SpanKind` 相同。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanKind {
+    /// 代理处理前端的请求
+    Server = 2,
+    /// 代理等待后端的响应
+    Client = 3,
+}
+
+/// 一个已经结束的跨度。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub parent_span_id: Option<[u8; 8]>,
+    pub name: String,
+    pub kind: SpanKind,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(&'static str, Value)>,
+}
+
+/// 请求跨度的记录器。没有开启时不记录任何跨度。
+#[derive(Default)]
+pub struct Telemetry {
+    enabled: bool,
+    finished: Mutex<VecDeque<Span>>,
+    ids: IdGenerator,
+}
+
+impl Telemetry {
+    /// 创建记录器，`enabled` 为 `false` 时不记录任何跨度。
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Self::default()
+        }
+    }
+
+    /// 取出所有已经结束、还没有导出的跨度。
+    pub fn drain(&self) -> Vec<Span> {
+        self.finished.lock().unwrap().drain(..).collect()
+    }
+}
+
+impl RequestObserver for Telemetry {
+    fn finished(&self, request: &FinishedRequest) {
+        if !self.enabled {
+            return;
+        }
+        let trace_id = self.ids.trace_id();
+        let span_id = self.ids.span_id();
+        let mut spans = vec![Span {
+            trace_id,
+            span_id,
+            parent_span_id: None,
+            name: request.method.clone(),
+            kind: SpanKind::Server,
+            start: request.received_at,
+            end: request.responded_at,
+            attributes: vec![
+                ("rpc.system", json!("jsonrpc")),
+                ("rpc.method", json!(request.method)),
+                ("rpc.jsonrpc.request_id", json!(request.id.to_string())),
+            ],
+        }];
+        if let Some((shard, forwarded)) = request.forwarded {
+            spans.push(Span {
+                trace_id,
+                span_id: self.ids.span_id(),
+                parent_span_id: Some(span_id),
+                name: format!("backend {}", request.method),
+                kind: SpanKind::Client,
+                start: forwarded,
+                end: request.backend_responded.unwrap_or(request.responded_at),
+                attributes: vec![
+                    ("rpc.method", json!(request.method)),
+                    ("codefuse.shard", json!(shard)),
+                ],
+            });
+        }
+
+        let mut finished = self.finished.lock().unwrap();
+        for span in spans {
+            if finished.len() == MAX_BUFFERED_SPANS {
+                finished.pop_front();
+            }
+            finished.push_back(span);
+        }
+    }
+}
+
+/// 跟踪和跨度 id 的生成器。
+///
+/// 每个生成器使用随机的种子，id 只需要在一段时间内不重复，不需要密码学强度。
+struct IdGenerator {
+    seed: RandomState,
+    counter: AtomicU64,
+}
+
+impl Default for IdGenerator {
+    fn default() -> Self {
+        Self {
+            seed: RandomState::new(),
+            counter: AtomicU64::new(0),
+        }
+    }
+}
+
+impl IdGenerator {
+    fn next(&self) -> u64 {
+        let count = self.counter.fetch_add(1, Ordering::Relaxed);
+        // 全零的 id 在 OTLP 中无效
+        self.seed.hash_one(count).max(1)
+    }
+
+    fn trace_id(&self) -> [u8; 16] {
+        let mut id = [0u8; 16];
+        id[..8].copy_from_slice(&self.next().to_be_bytes());
+        id[8..].copy_from_slice(&self.next().to_be_bytes());
+        id
+    }
+
+    fn span_id(&self) -> [u8; 8] {
+        self.next().to_be_bytes()
+    }
+}
+
+/// 把跨度编码为 OTLP/HTTP 的 JSON 请求体（`ExportTraceServiceRequest`）。
+pub fn otlp_json(service_name: &str, spans: &[Span]) -> Value {
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|span| {
+            let mut encoded = json!({
+                "traceId": hex(&span.trace_id),
+                "spanId": hex(&span.span_id),
+                "name": span.name,
+                "kind": span.kind as u8,
+                // 64 位整数在 OTLP JSON 中编码为字符串
+                "startTimeUnixNano": unix_nanos(span.start).to_string(),
+                "endTimeUnixNano": unix_nanos(span.end).to_string(),
+                "attributes": span
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| json!({"key": key, "value": attribute_value(value)}))
+                    .collect::<Vec<_>>(),
+            });
+            if let Some(parent) = &span.parent_span_id {
+                encoded["parentSpanId"] = json!(hex(parent));
+            }
+            encoded
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{"key": "service.name", "value": {"stringValue": service_name}}]
+            },
+            "scopeSpans": [{
+                "scope": {"name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION")},
+                "spans": spans,
+            }]
+        }]
+    })
+}
+
+/// 检查 `[telemetry]` 配置：导出间隔必须大于 0。
+///
+/// # 错误
+///
+/// 如果 `export_interval_ms` 为 0，返回错误
+pub fn validate(config: &TelemetryConfig) -> Result<()> {
+    if config.export_interval_ms == 0 {
+        bail!("[telemetry] export_interval_ms 必须大于 0");
+    }
+    Ok(())
+}
+
+/// 定期把结束的跨度导出到配置的 OTLP 端点，导出失败或超时时丢弃这一批跨度。
+///
+/// # 参数
+///
+/// * `telemetry` - 跨度记录器
+/// * `config` - 遥测配置，`endpoint` 为空时立即返回
+pub async fn export_loop(telemetry: std::sync::Arc<Telemetry>, config: TelemetryConfig) {
+    let Some(endpoint) = config.endpoint else {
+        return;
+    };
+    let mut interval = tokio::time::interval(Duration::from_millis(config.export_interval_ms));
+    let mut failing = false;
+    loop {
+        interval.tick().await;
+        let spans = telemetry.drain();
+        if spans.is_empty() {
+            continue;
+        }
+        let body = otlp_json(&config.service_name, &spans);
+        let exported = tokio::time::timeout(EXPORT_TIMEOUT, post_json(&endpoint, &body))
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("{} 秒内没有完成", EXPORT_TIMEOUT.as_secs())));
+        match exported {
+            Ok(()) => failing = false,
+            // 收集器不可用时每次都会失败，只在第一次失败时警告
+            Err(e) if !failing => {
+                warn!("导出 {} 个跨度失败: {:?}", spans.len(), e);
+                failing = true;
+            }
+            Err(e) => debug!("导出 {} 个跨度失败: {:?}", spans.len(), e),
+        }
+    }
+}
+
+/// 向 `http://host:port[/path]` 发送 JSON。没有路径时使用 OTLP 的默认路径 `/v1/traces`。
+async fn post_json(endpoint: &str, body: &Value) -> Result<()> {
+    let Some(rest) = endpoint.strip_prefix("http://") else {
+        bail!("只支持 http:// 端点: {}", endpoint);
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(index) if index + 1 < rest.len() => rest.split_at(index),
+        Some(index) => (&rest[..index], "/v1/traces"),
+        None => (rest, "/v1/traces"),
+    };
+    let body = serde_json::to_vec(body)?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        authority,
+        body.len()
+    );
+
+    let mut stream = TcpStream::connect(authority)
+        .await
+        .with_context(|| format!("无法连接 {}", authority))?;
+    stream.write_all(request.as_bytes()).await?;
+    stream.write_all(&body).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let status_line = response.split(|b| *b == b'\n').next().unwrap_or_default();
+    let status = std::str::from_utf8(status_line)?
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default();
+    if !status.starts_with('2') {
+        bail!("收集器返回 {}", String::from_utf8_lossy(status_line).trim());
+    }
+    Ok(())
+}
+
+fn attribute_value(value: &Value) -> Value {
+    match value {
+        Value::Number(number) if number.is_i64() || number.is_u64() => {
+            json!({"intValue": number.to_string()})
+        }
+        Value::Number(number) => json!({"doubleValue": number}),
+        Value::Bool(value) => json!({"boolValue": value}),
+        Value::String(value) => json!({"stringValue": value}),
+        other => json!({"stringValue": other.to_string()}),
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,231 @@
+//! # clang-tidy 策略模块
+//!
+//! 配置可以为不同目录声明 clang-tidy 检查集合，语法与 `.clang-tidy` 的 `Checks` 相同（例如 `-*,bugprone-*`）。
+//! 适用于所有文件的规则合并进 `initialize` 的 `initializationOptions` 和 `workspace/didChangeConfiguration` 的 `settings`
+//! （`clangTidy.checks`），由后端直接运行这些检查；按目录的规则和不接受这个设置的后端仍然依靠代理在转发诊断之前
+//! 过滤掉策略不允许的检查，为整个团队提供统一的策略。子目录的 `.codefuse.toml` 中的规则与配置中的规则一起生效。
+
+use globset::{Glob, GlobMatcher};
+use log::warn;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tower_lsp::lsp_types::Url;
+use tower_lsp::lsp_types::notification::{DidChangeConfiguration, Notification};
+
+use crate::config::TidyRule;
+use crate::directory_config::DirectoryConfigs;
+
+/// clangd 报告 clang-tidy 诊断时使用的 `source`。
+const TIDY_SOURCE: &str = "clang-tidy";
+
+/// 后端设置中 clang-tidy 检查集合所在的位置。
+const CHECKS_SETTING: [&str; 2] = ["clangTidy", "checks"];
+
+/// 检查集合中的一项：`bugprone-*` 启用匹配的检查，`-bugprone-*` 禁用。
+struct CheckPattern {
+    enabled: bool,
+    matcher: GlobMatcher,
+}
+
+/// 一个目录的检查集合。
+struct Rule {
+    root: Option<PathBuf>,
+    patterns: Vec<CheckPattern>,
+}
+
+/// 按目录组织的 clang-tidy 策略。
+#[derive(Default)]
+pub struct TidyPolicy {
+    /// 配置中的规则，与子目录的规则合并时使用
+    config: Vec<TidyRule>,
+    rules: Vec<Rule>,
+    directories: Option<Arc<DirectoryConfigs>>,
+}
+
+impl TidyPolicy {
+    /// 根据配置创建策略，无法解析的检查模式会被忽略并记录警告。
+    pub fn new(config: &[TidyRule]) -> Self {
+        let mut rules: Vec<Rule> = config
+            .iter()
+            .map(|rule| Rule {
+                root: rule.path.clone(),
+                patterns: parse_checks(&rule.checks),
+            })
+            .collect();
+        // 外层目录先应用，内层目录的设置覆盖外层
+        rules.sort_by_key(|rule| {
+            rule.root
+                .as_ref()
+                .map_or(0, |root| root.components().count())
+        });
+        Self {
+            config: config.to_vec(),
+            rules,
+            directories: None,
+        }
+    }
+
+    /// 同时应用子目录 `.codefuse.toml` 中的规则。
+    pub fn with_directories(mut self, directories: Arc<DirectoryConfigs>) -> Self {
+        self.directories = Some(directories);
+        self
+    }
+
+    /// 是否没有配置任何策略。
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.directories.is_none()
+    }
+
+    /// 适用于所有文件的检查集合，按配置中的顺序合并；没有这样的规则时返回 `None`。
+    pub fn checks(&self) -> Option<String> {
+        let checks: Vec<&str> = self
+            .config
+            .iter()
+            .filter(|rule| rule.path.is_none())
+            .map(|rule| rule.checks.trim())
+            .filter(|checks| !checks.is_empty())
+            .collect();
+        (!checks.is_empty()).then(|| checks.join(","))
+    }
+
+    /// 把检查集合合并进 `initialize` 参数的 `initializationOptions`。
+    pub fn on_initialize(&self, params: &mut Value) {
+        if let Some(checks) = self.checks() {
+            merge_checks(params, "initializationOptions", &checks);
+        }
+    }
+
+    /// 把检查集合合并进编辑器发来的 `workspace/didChangeConfiguration` 通知的 `settings`。
+    pub fn on_configuration_changed(&self, rpc: &mut Value) {
+        if let (Some(checks), Some(params)) = (self.checks(), rpc.get_mut("params")) {
+            merge_checks(params, "settings", &checks);
+        }
+    }
+
+    /// 重新加载配置后交给后端的 `workspace/didChangeConfiguration` 通知；没有适用于所有文件的规则时返回 `None`。
+    pub fn configuration_notification(&self) -> Option<Value> {
+        let checks = self.checks()?;
+        Some(json!({
+            "jsonrpc": "2.0",
+            "method": DidChangeConfiguration::METHOD,
+            "params": {"settings": {CHECKS_SETTING[0]: {CHECKS_SETTING[1]: checks}}},
+        }))
+    }
+
+    /// 检查在指定文件中是否启用。
+    ///
+    /// 与 clang-tidy 一样，最后一个匹配的模式决定结果；没有任何模式匹配时保留检查。
+    ///
+    /// # 参数
+    ///
+    /// * `path` - 文件路径
+    /// * `check` - 检查名，例如 `bugprone-use-after-move`
+    pub fn is_enabled(&self, path: &Path, check: &str) -> bool {
+        let mut enabled = true;
+        for rule in &self.rules {
+            if rule
+                .root
+                .as_ref()
+                .is_some_and(|root| !path.starts_with(root))
+            {
+                continue;
+            }
+            for pattern in &rule.patterns {
+                if pattern.matcher.is_match(check) {
+                    enabled = pattern.enabled;
+                }
+            }
+        }
+        enabled
+    }
+
+    /// 从 `textDocument/publishDiagnostics` 通知中删除策略不允许的 clang-tidy 诊断。
+    ///
+    /// # 参数
+    ///
+    /// * `rpc` - 后端发来的通知
+    pub fn filter_diagnostics(&self, rpc: &mut Value) {
+        if self.is_empty() {
+            return;
+        }
+        let Some(path) = rpc
+            .pointer("/params/uri")
+            .and_then(|uri| uri.as_str())
+            .and_then(|uri| Url::parse(uri).ok())
+            .and_then(|uri| uri.to_file_path().ok())
+        else {
+            return;
+        };
+        let Some(diagnostics) = rpc
+            .pointer_mut("/params/diagnostics")
+            .and_then(|d| d.as_array_mut())
+        else {
+            return;
+        };
+        let layered = self.directories.as_ref().and_then(|directories| {
+            let rules = directories.tidy_rules(&path);
+            (!rules.is_empty()).then(|| TidyPolicy::new(&[self.config.as_slice(), &rules].concat()))
+        });
+        let policy = layered.as_ref().unwrap_or(self);
+
+        diagnostics.retain(|diagnostic| {
+            if diagnostic.get("source").and_then(|s| s.as_str()) != Some(TIDY_SOURCE) {
+                return true;
+            }
+            match diagnostic.get("code").and_then(|c| c.as_str()) {
+                Some(check) => policy.is_enabled(&path, check),
+                None => true,
+            }
+        });
+    }
+}
+
+/// 把策略的检查集合追加在 `params[field]` 中已有的检查之后，与 clang-tidy 一样后面的模式优先。
+fn merge_checks(params: &mut Value, field: &str, checks: &str) {
+    if !params.is_object() {
+        return;
+    }
+    let options = &mut params[field];
+    if !options.is_object() {
+        *options = json!({});
+    }
+    let section = &mut options[CHECKS_SETTING[0]];
+    if !section.is_object() {
+        *section = json!({});
+    }
+    let merged = match section
+        .get(CHECKS_SETTING[1])
+        .and_then(|existing| existing.as_str())
+        .map(str::trim)
+    {
+        Some(existing) if !existing.is_empty() => format!("{},{}", existing, checks),
+        _ => checks.to_string(),
+    };
+    section[CHECKS_SETTING[1]] = json!(merged);
+}
+
+/// 解析 `.clang-tidy` 风格的检查字符串。
+fn parse_checks(checks: &str) -> Vec<CheckPattern> {
+    checks
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .filter_map(|item| {
+            let (enabled, glob) = match item.strip_prefix('-') {
+                Some(glob) => (false, glob),
+                None => (true, item),
+            };
+            match Glob::new(glob) {
+                Ok(glob) => Some(CheckPattern {
+                    enabled,
+                    matcher: glob.compile_matcher(),
+                }),
+                Err(e) => {
+                    warn!("忽略无效的 clang-tidy 检查模式 {}: {}", item, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
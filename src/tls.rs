@@ -0,0 +1,275 @@
+//! # 连接认证模块
+//!
+//! 在共享的开发服务器上以 `--listen` 监听 TCP 端口时，同一台机器上的任何人都能连接代理。配置了证书时连接使用 TLS，
+//! 配置了共享令牌时对端在 LSP 消息之前先发送 `Authorization: Bearer <token>` 和一个空行，令牌不对的连接被直接关闭。
+
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::config::TransportConfig;
+use crate::transport::{FrontendReader, FrontendWriter, connect_socket};
+
+/// 等待 TLS 握手和令牌的时间，超时的连接被关闭。
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 认证消息头的最大长度。
+const MAX_AUTH_HEADER: usize = 1024;
+
+/// `--listen` 接受连接时的 TLS 和令牌检查。
+#[derive(Clone)]
+pub struct ListenSecurity {
+    tls: Option<TlsAcceptor>,
+    token: Option<String>,
+}
+
+impl ListenSecurity {
+    /// 按 `[transport]` 配置创建，读取证书、私钥和令牌。
+    ///
+    /// # 错误
+    ///
+    /// 如果证书、私钥或令牌无法读取，或者只配置了证书和私钥中的一个，返回错误
+    pub fn from_config(config: &TransportConfig) -> Result<Self> {
+        let tls = match (&config.tls_cert, &config.tls_key) {
+            (Some(cert), Some(key)) => {
+                let certs = load_certs(cert)?;
+                let key = PrivateKeyDer::from_pem_file(key)
+                    .with_context(|| format!("无法读取私钥 {}", key.display()))?;
+                let config =
+                    ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                        .with_safe_default_protocol_versions()?
+                        .with_no_client_auth()
+                        .with_single_cert(certs, key)
+                        .context("证书与私钥不匹配")?;
+                Some(TlsAcceptor::from(Arc::new(config)))
+            }
+            (None, None) => None,
+            _ => bail!("[transport] tls_cert 和 tls_key 需要同时设置"),
+        };
+        Ok(Self {
+            tls,
+            token: load_token(config)?,
+        })
+    }
+
+    /// 是否要求对端发送令牌。
+    pub fn requires_token(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// 接受第一个通过认证的连接，认证失败的连接被关闭后继续等待。
+    ///
+    /// 每个连接在各自的任务中认证，不发送令牌的连接不会挡住后来的连接；
+    /// 返回时还在认证的连接被关闭。
+    ///
+    /// # 错误
+    ///
+    /// 如果监听的套接字出错，返回错误
+    pub async fn accept(&self, listener: &TcpListener) -> Result<(FrontendReader, FrontendWriter)> {
+        let mut pending = JoinSet::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, peer) = accepted?;
+                    stream.set_nodelay(true)?;
+                    let security = self.clone();
+                    pending.spawn(async move { (peer, security.authenticate(stream).await) });
+                }
+                Some(authenticated) = pending.join_next(), if !pending.is_empty() => {
+                    let Ok((peer, authenticated)) = authenticated else {
+                        continue;
+                    };
+                    match authenticated {
+                        Ok(connection) => {
+                            info!("前端已连接: {}", peer);
+                            return Ok(connection);
+                        }
+                        Err(e) => warn!("拒绝来自 {} 的连接: {:#}", peer, e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// 在一个连接上完成 TLS 握手并检查令牌。
+    ///
+    /// # 错误
+    ///
+    /// 如果握手失败、超时，或者令牌不对，返回错误
+    pub async fn authenticate(
+        &self,
+        stream: TcpStream,
+    ) -> Result<(FrontendReader, FrontendWriter)> {
+        match &self.tls {
+            Some(acceptor) => {
+                let stream = tokio::time::timeout(AUTH_TIMEOUT, acceptor.accept(stream))
+                    .await
+                    .context("TLS 握手超时")?
+                    .context("TLS 握手失败")?;
+                self.check_token(stream).await
+            }
+            None => self.check_token(stream).await,
+        }
+    }
+
+    async fn check_token<S>(&self, mut stream: S) -> Result<(FrontendReader, FrontendWriter)>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        if let Some(token) = &self.token {
+            let header = tokio::time::timeout(AUTH_TIMEOUT, read_auth_header(&mut stream))
+                .await
+                .context("等待令牌超时")??;
+            let received = header
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.trim()
+                        .eq_ignore_ascii_case("authorization")
+                        .then(|| value.trim().strip_prefix("Bearer "))?
+                })
+                .unwrap_or_default();
+            if !constant_time_eq(received.trim().as_bytes(), token.as_bytes()) {
+                bail!("令牌无效");
+            }
+        }
+        let (reader, writer) = tokio::io::split(stream);
+        Ok((Box::new(reader), Box::new(writer)))
+    }
+}
+
+/// `relay` 连接远程代理时的 TLS 和令牌。
+pub struct ConnectSecurity {
+    tls: Option<TlsConnector>,
+    server_name: Option<String>,
+    token: Option<String>,
+}
+
+impl ConnectSecurity {
+    /// 按 `[transport]` 配置创建，读取信任的证书和令牌。
+    ///
+    /// # 错误
+    ///
+    /// 如果证书或令牌无法读取，返回错误
+    pub fn from_config(config: &TransportConfig) -> Result<Self> {
+        let tls = match &config.tls_ca {
+            Some(ca) => {
+                let mut roots = RootCertStore::empty();
+                for cert in load_certs(ca)? {
+                    roots.add(cert).context("无效的证书")?;
+                }
+                let config =
+                    ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                        .with_safe_default_protocol_versions()?
+                        .with_root_certificates(roots)
+                        .with_no_client_auth();
+                Some(TlsConnector::from(Arc::new(config)))
+            }
+            None => None,
+        };
+        Ok(Self {
+            tls,
+            server_name: config.tls_server_name.clone(),
+            token: load_token(config)?,
+        })
+    }
+
+    /// 连接 TCP 地址，完成 TLS 握手并发送令牌。
+    ///
+    /// # 错误
+    ///
+    /// 如果无法连接、握手失败，或者无法发送令牌，返回错误
+    pub async fn connect(&self, address: &str) -> Result<(FrontendReader, FrontendWriter)> {
+        let stream = connect_socket(address).await?;
+        match &self.tls {
+            Some(connector) => {
+                let name = match &self.server_name {
+                    Some(name) => name.clone(),
+                    None => host(address).to_string(),
+                };
+                let server_name = ServerName::try_from(name.clone())
+                    .with_context(|| format!("无效的证书名称: {}", name))?;
+                let stream =
+                    tokio::time::timeout(AUTH_TIMEOUT, connector.connect(server_name, stream))
+                        .await
+                        .context("TLS 握手超时")?
+                        .with_context(|| format!("与 {} 的 TLS 握手失败", address))?;
+                self.send_token(stream).await
+            }
+            None => self.send_token(stream).await,
+        }
+    }
+
+    async fn send_token<S>(&self, mut stream: S) -> Result<(FrontendReader, FrontendWriter)>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        if let Some(token) = &self.token {
+            stream
+                .write_all(format!("Authorization: Bearer {}\r\n\r\n", token).as_bytes())
+                .await?;
+            stream.flush().await?;
+        }
+        let (reader, writer) = tokio::io::split(stream);
+        Ok((Box::new(reader), Box::new(writer)))
+    }
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("无法读取证书 {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("证书文件中没有证书: {}", path.display());
+    }
+    Ok(certs)
+}
+
+fn load_token(config: &TransportConfig) -> Result<Option<String>> {
+    let Some(path) = &config.token_file else {
+        return Ok(None);
+    };
+    let token = std::fs::read_to_string(path)
+        .with_context(|| format!("无法读取令牌文件 {}", path.display()))?;
+    let token = token.trim();
+    if token.is_empty() {
+        bail!("令牌文件是空的: {}", path.display());
+    }
+    Ok(Some(token.to_string()))
+}
+
+/// 读取到空行为止的认证消息头，不多读后面的 LSP 消息。
+async fn read_auth_header<S: AsyncRead + Unpin>(stream: &mut S) -> Result<String> {
+    let mut header = Vec::new();
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() >= MAX_AUTH_HEADER {
+            bail!("认证消息头过长");
+        }
+        header.push(stream.read_u8().await.context("连接在认证前关闭")?);
+    }
+    Ok(String::from_utf8_lossy(&header).into_owned())
+}
+
+/// 地址中的主机名，只有端口时是 127.0.0.1。
+fn host(address: &str) -> &str {
+    if address.parse::<u16>().is_ok() {
+        return "127.0.0.1";
+    }
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// 比较令牌的时间不取决于第一个不同的字节的位置。
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
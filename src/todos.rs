@@ -0,0 +1,250 @@
+//! # TODO 标记模块
+//!
+//! 在注释中查找 `[todos] patterns` 配置的 TODO/FIXME/HACK 等标记。标记在打开的文档中显示为代码透镜，
+//! 自定义请求 `codefuse/todos` 返回打开的文档（配置了 `workspace` 时还有工作区中的文件）里所有标记的汇总。
+
+use anyhow::Result;
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use log::warn;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use tower_lsp::lsp_types::request::{CodeLensRequest, Request};
+use tower_lsp::lsp_types::{Range, Url};
+
+use crate::config::TodoConfig;
+use crate::dispatcher::{DEFAULT_HANDLER_PRIORITY, Dispatcher};
+use crate::document_store::{Document, DocumentStore, position_at};
+use crate::handlers::{self, HandlerCtx, LOCAL_PROVIDER_PRIORITY, Verdict};
+use crate::syntax;
+use crate::trace::Direction;
+use crate::workspace;
+
+/// 查询 TODO 标记的自定义请求，结果是 [`TodoReport`]。
+pub const TODOS: &str = "codefuse/todos";
+
+/// 扫描工作区时最多读取的文件数。
+const MAX_WORKSPACE_FILES: usize = 20_000;
+
+/// 一个标记。
+///
+/// - `marker`: 匹配到的标记，例如 `TODO`
+/// - `text`: 标记之后到行尾的说明
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TodoItem {
+    pub uri: Url,
+    pub range: Range,
+    pub marker: String,
+    pub text: String,
+}
+
+/// `codefuse/todos` 的结果。
+///
+/// - `items`: 按文档和位置排列的标记
+/// - `counts`: 每种标记的数量
+/// - `truncated`: 工作区的文件超过上限，没有全部扫描
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TodoReport {
+    pub items: Vec<TodoItem>,
+    pub counts: BTreeMap<String, usize>,
+    pub truncated: bool,
+}
+
+/// 按配置的模式查找标记。
+#[derive(Default)]
+pub struct TodoScanner {
+    patterns: Vec<Regex>,
+    code_lens: bool,
+    workspace: bool,
+    /// 代码透镜请求 id → 追加到后端响应中的代码透镜
+    lenses: DashMap<String, Vec<Value>>,
+}
+
+impl TodoScanner {
+    /// 按 `[todos]` 配置创建，无效的正则表达式记录警告后忽略。
+    pub fn new(config: &TodoConfig) -> Self {
+        let patterns = config
+            .patterns
+            .iter()
+            .filter_map(|pattern| match Regex::new(pattern) {
+                Ok(regex) => Some(regex),
+                Err(e) => {
+                    warn!("忽略无效的 TODO 模式 {}: {}", pattern, e);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            patterns,
+            code_lens: config.code_lens,
+            workspace: config.workspace,
+            lenses: DashMap::new(),
+        }
+    }
+
+    /// 是否为标记显示代码透镜。
+    pub fn is_code_lens_enabled(&self) -> bool {
+        self.code_lens && !self.patterns.is_empty()
+    }
+
+    /// 查找文档注释中的标记，按位置排列。
+    pub fn scan(&self, uri: &Url, text: &str) -> Vec<TodoItem> {
+        if self.patterns.is_empty() {
+            return Vec::new();
+        }
+        let mut found = Vec::new();
+        for comment in syntax::node_ranges(text, &[syntax::COMMENT]) {
+            let body = &text[comment.clone()];
+            for pattern in &self.patterns {
+                for marker in pattern.find_iter(body) {
+                    let rest = &body[marker.end()..];
+                    let line = rest.split('\n').next().unwrap_or_default();
+                    let description = line
+                        .trim_end()
+                        .trim_end_matches("*/")
+                        .trim_start_matches([':', ' ', '\t'])
+                        .trim_end();
+                    let start = comment.start + marker.start();
+                    let end = comment.start + marker.end();
+                    found.push((start, end, marker.as_str(), description));
+                }
+            }
+        }
+        found.sort_by_key(|(start, ..)| *start);
+        found
+            .into_iter()
+            .map(|(start, end, marker, description)| TodoItem {
+                uri: uri.clone(),
+                range: Range::new(position_at(text, start), position_at(text, end)),
+                marker: marker.to_string(),
+                text: description.to_string(),
+            })
+            .collect()
+    }
+
+    /// 文档中标记的代码透镜，标题是标记和说明。
+    pub fn code_lenses(&self, uri: &Url, text: &str) -> Vec<Value> {
+        self.scan(uri, text)
+            .into_iter()
+            .map(|item| {
+                let title = if item.text.is_empty() {
+                    item.marker
+                } else {
+                    format!("{}: {}", item.marker, item.text)
+                };
+                json!({"range": item.range, "command": {"title": title, "command": ""}})
+            })
+            .collect()
+    }
+
+    /// 记录一个转发给后端的 `textDocument/codeLens` 请求，准备追加到响应中的代码透镜。
+    pub fn request(&self, id: &Value, params: Option<&Value>, documents: &DocumentStore) {
+        if !self.is_code_lens_enabled() {
+            return;
+        }
+        let Some(doc) = params
+            .and_then(|params| params.pointer("/textDocument/uri"))
+            .and_then(|uri| uri.as_str())
+            .and_then(|uri| Url::parse(uri).ok())
+            .and_then(|uri| documents.get(&uri))
+        else {
+            return;
+        };
+        let lenses = self.code_lenses(&doc.uri, &doc.text);
+        if !lenses.is_empty() {
+            self.lenses.insert(id.to_string(), lenses);
+        }
+    }
+
+    /// 在后端的代码透镜响应后面追加标记的代码透镜。
+    pub fn rewrite_code_lens_response(&self, rpc: &mut Value) {
+        let Some((_, lenses)) = rpc
+            .get("id")
+            .and_then(|id| self.lenses.remove(&id.to_string()))
+        else {
+            return;
+        };
+        if rpc.get("error").is_some() {
+            return;
+        }
+        match rpc.get_mut("result") {
+            Some(Value::Array(result)) => result.extend(lenses),
+            _ => rpc["result"] = Value::Array(lenses),
+        }
+    }
+
+    /// 汇总打开的文档中的标记；配置了 `workspace` 时还扫描工作区中没有打开的 C/C++ 文件。
+    ///
+    /// # 参数
+    ///
+    /// * `documents` - 打开的文档，使用编辑器中的内容
+    /// * `roots` - 工作区根目录
+    pub fn report(&self, documents: &[Document], roots: &[PathBuf]) -> TodoReport {
+        let mut report = TodoReport::default();
+        for doc in documents {
+            report.items.extend(self.scan(&doc.uri, &doc.text));
+        }
+        if self.workspace {
+            let open: HashSet<PathBuf> = documents
+                .iter()
+                .filter_map(|doc| doc.uri.to_file_path().ok())
+                .collect();
+            let (files, truncated) = workspace::source_files(roots, MAX_WORKSPACE_FILES);
+            report.truncated = truncated;
+            for path in files.into_iter().filter(|path| !open.contains(path)) {
+                let (Ok(text), Ok(uri)) =
+                    (std::fs::read_to_string(&path), Url::from_file_path(&path))
+                else {
+                    continue;
+                };
+                report.items.extend(self.scan(&uri, &text));
+            }
+        }
+        report
+            .items
+            .sort_by(|a, b| (a.uri.as_str(), a.range.start).cmp(&(b.uri.as_str(), b.range.start)));
+        for item in &report.items {
+            *report.counts.entry(item.marker.clone()).or_default() += 1;
+        }
+        report
+    }
+}
+
+/// 注册 `codefuse/todos` 的处理器，以及后端不提供代码透镜时 TODO 标记的代码透镜。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        TODOS,
+        DEFAULT_HANDLER_PRIORITY,
+        answer_todos,
+    );
+    dispatcher.register_handler(
+        Direction::Frontend,
+        CodeLensRequest::METHOD,
+        LOCAL_PROVIDER_PRIORITY,
+        answer_code_lenses,
+    );
+}
+
+/// 以打开的文档和工作区中的 TODO 标记汇总应答。
+fn answer_todos(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let result = ctx.dispatcher().todo_report().await;
+        Ok(Verdict::Respond(handlers::response(&rpc, result)))
+    })
+}
+
+/// 后端不提供代码透镜时，以 TODO 标记的代码透镜应答。
+fn answer_code_lenses(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        match ctx.dispatcher().local_code_lenses(&rpc) {
+            Some(lenses) => Ok(Verdict::Respond(handlers::response(&rpc, Ok(lenses)))),
+            None => Ok(Verdict::Continue(rpc)),
+        }
+    })
+}
@@ -0,0 +1,109 @@
+//! # 消息追踪模块
+//!
+//! 代理在内存中保留最近收到的消息（来自前端和各个后端分片），可以通过 `codefuse.dumpTrace` 命令导出为
+//! NDJSON 文件，每行一条消息，便于排查问题或比较两次会话。
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 默认保留的消息数量。
+pub const DEFAULT_TRACE_CAPACITY: usize = 2000;
+
+/// 消息的来源。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Frontend,
+    Backend,
+}
+
+/// 追踪中的一条消息。
+///
+/// - `timestamp_ms`: 收到消息的时间（Unix 毫秒）
+/// - `direction`: 消息来源
+/// - `shard`: 来自后端时的分片下标
+/// - `message`: 完整的 JSON-RPC 消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceEntry {
+    pub timestamp_ms: u64,
+    pub direction: Direction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shard: Option<usize>,
+    pub message: Value,
+}
+
+/// 最近消息的环形缓冲区。
+pub struct MessageTrace {
+    entries: Mutex<VecDeque<TraceEntry>>,
+    capacity: usize,
+}
+
+impl Default for MessageTrace {
+    fn default() -> Self {
+        Self::new(DEFAULT_TRACE_CAPACITY)
+    }
+}
+
+impl MessageTrace {
+    /// 创建最多保留 `capacity` 条消息的追踪。
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::with_capacity(
+                capacity.min(DEFAULT_TRACE_CAPACITY),
+            )),
+            capacity,
+        }
+    }
+
+    /// 记录一条消息，超过容量时丢弃最旧的消息。
+    pub fn record(&self, direction: Direction, shard: Option<usize>, message: &Value) {
+        if self.capacity == 0 {
+            return;
+        }
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(TraceEntry {
+            timestamp_ms,
+            direction,
+            shard,
+            message: message.clone(),
+        });
+    }
+
+    /// 当前保留的所有消息，最旧的在前。
+    pub fn entries(&self) -> Vec<TraceEntry> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// 把保留的消息写入 NDJSON 文件。
+    ///
+    /// # 返回
+    ///
+    /// 返回写入的消息数量
+    ///
+    /// # 错误
+    ///
+    /// 如果文件无法写入，返回错误
+    pub fn dump(&self, path: &Path) -> Result<usize> {
+        let entries = self.entries();
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        for entry in &entries {
+            serde_json::to_writer(&mut file, entry)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()?;
+        Ok(entries.len())
+    }
+}
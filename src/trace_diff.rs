@@ -0,0 +1,392 @@
+//! # 追踪比较模块
+//!
+//! `lsp-proxy diff <trace-a> <trace-b>` 比较两个由 `codefuse.dumpTrace` 导出的会话，例如同一组操作在两个 clangd 版本
+//! 或者开关代理功能前后的结果。两次会话中的请求 id 不同，请求按方法和参数对齐，同样的请求按出现的顺序配对；
+//! 响应比较之前去掉每次都会变化的字段（[`VOLATILE_FIELDS`]），对象不考虑键的顺序，
+//! 只是顺序不同的数组视为相同。输出每个不同的请求中改变了的字段，以及只在一边出现的请求。
+
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::trace::{Direction, TraceEntry};
+
+/// 比较时去掉的字段：消息的 id、后端给自己留的数据、结果的版本标识和进度令牌。
+pub const VOLATILE_FIELDS: &[&str] = &[
+    "id",
+    "jsonrpc",
+    "data",
+    "resultId",
+    "workDoneToken",
+    "partialResultToken",
+];
+
+/// 输出中的值超过这个长度时截断。
+const MAX_VALUE_CHARS: usize = 80;
+
+/// `diff` 子命令的参数。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffCommand {
+    pub a: PathBuf,
+    pub b: PathBuf,
+    /// 只比较这些方法，为空时比较所有请求
+    pub methods: Vec<String>,
+}
+
+/// 追踪中的一个前端请求和后端对它的响应。
+///
+/// - `params`/`outcome`: 去掉了易变字段的参数和响应（`result` 或 `error`）；追踪中没有响应时 `outcome` 为 `None`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exchange {
+    pub method: String,
+    pub params: Value,
+    pub outcome: Option<Value>,
+}
+
+impl Exchange {
+    /// 对齐两次会话的请求用的键。
+    fn key(&self) -> String {
+        format!("{} {}", self.method, self.params)
+    }
+
+    /// 输出中表示这个请求的文字：方法，以及文档和位置（行列从 1 开始）。
+    pub fn label(&self) -> String {
+        let mut label = self.method.clone();
+        if let Some(uri) = self
+            .params
+            .pointer("/textDocument/uri")
+            .and_then(|u| u.as_str())
+        {
+            label.push(' ');
+            label.push_str(uri);
+        }
+        let position = self
+            .params
+            .get("position")
+            .or_else(|| self.params.pointer("/range/start"));
+        if let Some(position) = position
+            && let (Some(line), Some(character)) = (
+                position.get("line").and_then(|l| l.as_u64()),
+                position.get("character").and_then(|c| c.as_u64()),
+            )
+        {
+            label.push_str(&format!(":{}:{}", line + 1, character + 1));
+        }
+        if let Some(query) = self.params.get("query").and_then(|q| q.as_str()) {
+            label.push_str(&format!(" {:?}", query));
+        }
+        label
+    }
+}
+
+/// 一个改变了的字段，路径形如 `result.contents.value` 或 `result[2].label`；只在一边存在时另一边为 `None`。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub path: String,
+    pub a: Option<Value>,
+    pub b: Option<Value>,
+}
+
+/// 两次会话中响应不同的请求。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangedResponse {
+    pub exchange: Exchange,
+    pub differences: Vec<Difference>,
+}
+
+/// 比较的结果。
+///
+/// - `same`: 响应相同的请求数
+/// - `changed`: 响应不同的请求，按第一次会话中的顺序
+/// - `only_a`/`only_b`: 只在一次会话中出现的请求
+#[derive(Debug, Default, PartialEq)]
+pub struct DiffReport {
+    pub same: usize,
+    pub changed: Vec<ChangedResponse>,
+    pub only_a: Vec<Exchange>,
+    pub only_b: Vec<Exchange>,
+}
+
+impl DiffReport {
+    /// 两次会话的响应完全一致。
+    pub fn is_identical(&self) -> bool {
+        self.changed.is_empty() && self.only_a.is_empty() && self.only_b.is_empty()
+    }
+}
+
+/// 去掉 [`VOLATILE_FIELDS`] 中的字段。
+pub fn normalize(value: &Value) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(items.iter().map(normalize).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .filter(|(key, _)| !VOLATILE_FIELDS.contains(&key.as_str()))
+                .map(|(key, field)| (key.clone(), normalize(field)))
+                .collect::<Map<String, Value>>(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// 从追踪中取出前端的请求和后端的响应，按请求的顺序。后端发给前端的请求和代理自己发出的请求不包括在内。
+pub fn exchanges(entries: &[TraceEntry]) -> Vec<Exchange> {
+    let mut responses: HashMap<String, &Value> = HashMap::new();
+    for entry in entries {
+        let message = &entry.message;
+        if entry.direction == Direction::Backend
+            && message.get("method").is_none()
+            && let Some(id) = message.get("id")
+        {
+            responses.entry(id.to_string()).or_insert(message);
+        }
+    }
+    entries
+        .iter()
+        .filter(|entry| entry.direction == Direction::Frontend)
+        .filter_map(|entry| {
+            let message = &entry.message;
+            let method = message.get("method")?.as_str()?;
+            let id = message.get("id")?;
+            let outcome = responses.get(&id.to_string()).map(|response| {
+                let mut outcome = Map::new();
+                for field in ["result", "error"] {
+                    if let Some(value) = response.get(field) {
+                        outcome.insert(field.to_string(), normalize(value));
+                    }
+                }
+                Value::Object(outcome)
+            });
+            Some(Exchange {
+                method: method.to_string(),
+                params: normalize(message.get("params").unwrap_or(&Value::Null)),
+                outcome,
+            })
+        })
+        .collect()
+}
+
+/// 读取 NDJSON 格式的追踪文件，空行被忽略。
+///
+/// # 错误
+///
+/// 如果文件无法读取，或者某一行不是追踪中的消息，返回错误
+pub fn load(path: &Path) -> Result<Vec<TraceEntry>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("无法读取追踪 {}", path.display()))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("{} 第 {} 行不是追踪中的消息", path.display(), index + 1))
+        })
+        .collect()
+}
+
+/// 比较两个 JSON 值，返回改变了的字段。
+///
+/// # 参数
+///
+/// * `path` - 这两个值的路径，作为结果中路径的前缀
+pub fn diff_values(path: &str, a: &Value, b: &Value) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    collect(path, a, b, &mut differences);
+    differences
+}
+
+fn collect(path: &str, a: &Value, b: &Value, differences: &mut Vec<Difference>) {
+    if a == b {
+        return;
+    }
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            for (key, value) in a {
+                let child = format!("{}.{}", path, key);
+                match b.get(key) {
+                    Some(other) => collect(&child, value, other, differences),
+                    None => differences.push(Difference {
+                        path: child,
+                        a: Some(value.clone()),
+                        b: None,
+                    }),
+                }
+            }
+            for (key, value) in b.iter().filter(|(key, _)| !a.contains_key(*key)) {
+                differences.push(Difference {
+                    path: format!("{}.{}", path, key),
+                    a: None,
+                    b: Some(value.clone()),
+                });
+            }
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            if same_elements(a, b) {
+                return;
+            }
+            for index in 0..a.len().max(b.len()) {
+                let child = format!("{}[{}]", path, index);
+                match (a.get(index), b.get(index)) {
+                    (Some(a), Some(b)) => collect(&child, a, b, differences),
+                    (a, b) => differences.push(Difference {
+                        path: child,
+                        a: a.cloned(),
+                        b: b.cloned(),
+                    }),
+                }
+            }
+        }
+        (a, b) => differences.push(Difference {
+            path: path.to_string(),
+            a: Some(a.clone()),
+            b: Some(b.clone()),
+        }),
+    }
+}
+
+/// 两个数组只是元素的顺序不同。
+fn same_elements(a: &[Value], b: &[Value]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut remaining: Vec<&Value> = b.iter().collect();
+    a.iter().all(
+        |item| match remaining.iter().position(|other| *other == item) {
+            Some(index) => {
+                remaining.swap_remove(index);
+                true
+            }
+            None => false,
+        },
+    )
+}
+
+/// 对齐两次会话的请求并比较响应。
+///
+/// # 参数
+///
+/// * `a`/`b` - 两次会话的请求和响应
+/// * `methods` - 只比较这些方法，为空时比较所有请求
+pub fn diff_traces(a: &[Exchange], b: &[Exchange], methods: &[String]) -> DiffReport {
+    let selected = |exchange: &&Exchange| methods.is_empty() || methods.contains(&exchange.method);
+    let mut unmatched: HashMap<String, VecDeque<usize>> = HashMap::new();
+    let b: Vec<&Exchange> = b.iter().filter(selected).collect();
+    for (index, exchange) in b.iter().enumerate() {
+        unmatched
+            .entry(exchange.key())
+            .or_default()
+            .push_back(index);
+    }
+    let mut paired = vec![false; b.len()];
+    let mut report = DiffReport::default();
+    for exchange in a.iter().filter(selected) {
+        let Some(index) = unmatched
+            .get_mut(&exchange.key())
+            .and_then(|indices| indices.pop_front())
+        else {
+            report.only_a.push(exchange.clone());
+            continue;
+        };
+        paired[index] = true;
+        let null = Value::Null;
+        let differences = diff_values(
+            "",
+            exchange.outcome.as_ref().unwrap_or(&null),
+            b[index].outcome.as_ref().unwrap_or(&null),
+        );
+        if differences.is_empty() {
+            report.same += 1;
+        } else {
+            report.changed.push(ChangedResponse {
+                exchange: exchange.clone(),
+                differences: differences
+                    .into_iter()
+                    .map(|difference| Difference {
+                        path: difference.path.trim_start_matches('.').to_string(),
+                        ..difference
+                    })
+                    .collect(),
+            });
+        }
+    }
+    report.only_b = b
+        .into_iter()
+        .zip(paired)
+        .filter(|(_, paired)| !paired)
+        .map(|(exchange, _)| exchange.clone())
+        .collect();
+    report
+}
+
+/// 输出中的值：紧凑的 JSON，过长时截断；不存在时是 `(无)`。
+fn show(value: &Option<Value>) -> String {
+    let Some(value) = value else {
+        return "(无)".to_string();
+    };
+    let text = value.to_string();
+    if text.chars().count() <= MAX_VALUE_CHARS {
+        return text;
+    }
+    let truncated: String = text.chars().take(MAX_VALUE_CHARS).collect();
+    format!("{}…", truncated)
+}
+
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for changed in &self.changed {
+            writeln!(f, "{}", changed.exchange.label())?;
+            for difference in &changed.differences {
+                let path = if difference.path.is_empty() {
+                    "(响应)"
+                } else {
+                    &difference.path
+                };
+                writeln!(
+                    f,
+                    "  {}: {} → {}",
+                    path,
+                    show(&difference.a),
+                    show(&difference.b)
+                )?;
+            }
+        }
+        for (title, exchanges) in [("只在 A 中", &self.only_a), ("只在 B 中", &self.only_b)] {
+            if exchanges.is_empty() {
+                continue;
+            }
+            writeln!(f, "{}:", title)?;
+            for exchange in exchanges {
+                writeln!(f, "  {}", exchange.label())?;
+            }
+        }
+        writeln!(
+            f,
+            "相同 {}，不同 {}，只在 A 中 {}，只在 B 中 {}",
+            self.same,
+            self.changed.len(),
+            self.only_a.len(),
+            self.only_b.len()
+        )
+    }
+}
+
+/// 执行 `diff` 子命令，把比较结果输出到标准输出。
+///
+/// # 返回
+///
+/// 两次会话的响应是否完全一致
+///
+/// # 错误
+///
+/// 如果追踪文件无法读取或者格式不对，返回错误
+pub fn run(command: &DiffCommand) -> Result<bool> {
+    let a = exchanges(&load(&command.a)?);
+    let b = exchanges(&load(&command.b)?);
+    let report = diff_traces(&a, &b, &command.methods);
+    println!("A: {}", command.a.display());
+    println!("B: {}", command.b.display());
+    print!("{}", report);
+    Ok(report.is_identical())
+}
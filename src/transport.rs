@@ -0,0 +1,155 @@
+//! # 传输模块
+//!
+//! 代理与前端之间的连接。默认使用标准输入输出；`--pipe <name>` 时连接编辑器创建的管道，
+//! Unix 上是 Unix 域套接字，Windows 上是 `\\.\pipe\...` 命名管道，对应 VSCode 的 `TransportKind.pipe`。
+//! `--socket` 和 `--listen` 使用 TCP 连接，远程模式下本地的 `relay` 通过它连接远程代理；
+//! `--listen` 按 `[transport]` 配置要求 TLS 和共享令牌。
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::TransportConfig;
+use crate::tls::ListenSecurity;
+
+/// 读取前端消息的一端。
+pub type FrontendReader = Box<dyn AsyncRead + Send + Unpin>;
+/// 写出前端消息的一端。
+pub type FrontendWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// 与前端之间的传输方式。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// 标准输入输出
+    #[default]
+    Stdio,
+    /// 编辑器创建的管道，代理作为客户端连接
+    Pipe(String),
+    /// 连接这个 TCP 地址，只有端口时连接 127.0.0.1，对应 VSCode 的 `TransportKind.socket`
+    Socket(String),
+    /// 在这个 TCP 地址上监听并接受一个通过认证的连接，供远程的 `relay` 连接
+    Listen(String),
+}
+
+impl Transport {
+    /// 是否是 TCP 连接，只有 TCP 连接上启用帧压缩。
+    pub fn is_socket(&self) -> bool {
+        matches!(self, Transport::Socket(_) | Transport::Listen(_))
+    }
+
+    /// 打开与前端的连接。
+    ///
+    /// # 参数
+    ///
+    /// * `config` - `[transport]` 配置，监听时使用其中的证书和令牌
+    ///
+    /// # 错误
+    ///
+    /// 如果无法连接管道或 TCP 地址、无法监听，或者证书和令牌无法读取，返回错误
+    pub async fn connect(
+        &self,
+        config: &TransportConfig,
+    ) -> Result<(FrontendReader, FrontendWriter)> {
+        match self {
+            Transport::Stdio => Ok((Box::new(tokio::io::stdin()), Box::new(tokio::io::stdout()))),
+            Transport::Pipe(name) => {
+                let stream = connect_pipe(name)
+                    .await
+                    .with_context(|| format!("无法连接管道 {}", name))?;
+                let (reader, writer) = tokio::io::split(stream);
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+            Transport::Socket(address) => {
+                let stream = connect_socket(address).await?;
+                let (reader, writer) = stream.into_split();
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+            Transport::Listen(address) => {
+                FrontendListener::bind(address, config)
+                    .await?
+                    .accept()
+                    .await
+            }
+        }
+    }
+}
+
+/// `--listen` 监听的套接字，保留会话时编辑器重新连接到这里。
+pub struct FrontendListener {
+    listener: TcpListener,
+    security: ListenSecurity,
+}
+
+impl FrontendListener {
+    /// 在地址上监听，按 `[transport]` 配置要求 TLS 和令牌。
+    ///
+    /// # 错误
+    ///
+    /// 如果无法监听，或者证书和令牌无法读取，返回错误
+    pub async fn bind(address: &str, config: &TransportConfig) -> Result<Self> {
+        let security = ListenSecurity::from_config(config)?;
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("无法监听 {}", address))?;
+        if !security.requires_token() {
+            warn!(
+                "没有配置 [transport] token_file，能访问 {} 的任何人都能连接",
+                address
+            );
+        }
+        Ok(Self { listener, security })
+    }
+
+    /// 监听的地址。
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// 等待下一个通过认证的前端连接。
+    ///
+    /// # 错误
+    ///
+    /// 如果监听的套接字出错，返回错误
+    pub async fn accept(&self) -> Result<(FrontendReader, FrontendWriter)> {
+        info!("等待前端连接 {}", self.listener.local_addr()?);
+        self.security.accept(&self.listener).await
+    }
+}
+
+/// 连接 TCP 地址，只有端口时连接 127.0.0.1。
+pub async fn connect_socket(address: &str) -> Result<TcpStream> {
+    let address = match address.parse::<u16>() {
+        Ok(port) => format!("127.0.0.1:{}", port),
+        Err(_) => address.to_string(),
+    };
+    let stream = TcpStream::connect(&address)
+        .await
+        .with_context(|| format!("无法连接 {}", address))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+#[cfg(unix)]
+async fn connect_pipe(name: &str) -> io::Result<tokio::net::UnixStream> {
+    tokio::net::UnixStream::connect(name).await
+}
+
+#[cfg(windows)]
+async fn connect_pipe(name: &str) -> io::Result<tokio::net::windows::named_pipe::NamedPipeClient> {
+    use std::time::Duration;
+    use tokio::net::windows::named_pipe::ClientOptions;
+    use windows_sys::Win32::Foundation::ERROR_PIPE_BUSY;
+
+    loop {
+        match ClientOptions::new().open(name) {
+            Ok(client) => return Ok(client),
+            // 服务端的所有实例都在使用中，稍后重试
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => {}
+            Err(e) => return Err(e),
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
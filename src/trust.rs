@@ -0,0 +1,386 @@
+//! # 工作区信任模块
+//!
+//! 工作区中的 `.codefuse.toml` 随项目一起分发，打开一个克隆下来的仓库就可能让代理运行其中指定的程序。
+//! 配置文件位于工作区（代理的当前目录）之中时，只有工作区受信任后才使用其中会启动进程的设置
+//! （后端和影子后端的命令和参数、ssh、容器和沙箱、`--query-driver`、cmake、bazel 和编译器等，见 [`restrict`]）；
+//! 不受信任时这些设置恢复默认值，代理在日志和编辑器中说明忽略了哪些设置。
+//!
+//! 工作区通过以下任一方式受信任：
+//!
+//! - 命令行 `--trust-workspace`，只对这一次会话有效
+//! - `lsp-proxy trust [<dir>]` 记录的决定，保存在 `$XDG_CONFIG_HOME/codefuse/trusted.json`，`--revoke` 撤销
+//! - 编辑器发送 `workspace/trust` 通知（参数 `{"trusted": true}`），代理按新的信任状态重新应用配置，
+//!   后端的设置通过一次受控的后端重启生效；`{"trusted": false}` 同样立即收回信任
+//!
+//! 不在工作区中的配置文件（例如 `--config ~/.config/codefuse.toml`）由用户自己指定，总是受信任。
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::{Config, QueryDriverMode};
+use crate::dispatcher::{DEFAULT_HANDLER_PRIORITY, Dispatcher};
+use crate::handlers::{self, HandlerCtx, Verdict};
+use crate::trace::Direction;
+
+/// 编辑器告诉代理工作区是否受信任的通知，参数是 `{"trusted": <bool>}`；作为请求发送时响应信任状态和被忽略的设置。
+pub const TRUST: &str = "workspace/trust";
+
+/// `trust` 子命令的参数。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustCommand {
+    /// 工作区根目录，默认是当前目录
+    pub root: Option<PathBuf>,
+    /// 撤销信任
+    pub revoke: bool,
+}
+
+/// 代理自己的配置目录：`$XDG_CONFIG_HOME/codefuse`，没有设置时使用 `~/.config/codefuse`。
+pub fn proxy_config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("codefuse"))
+}
+
+/// 用户信任的工作区列表。
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TrustedWorkspaces {
+    pub workspaces: Vec<PathBuf>,
+}
+
+impl TrustedWorkspaces {
+    /// 默认的列表文件，在 [`proxy_config_dir`] 中。
+    pub fn default_path() -> Option<PathBuf> {
+        Some(proxy_config_dir()?.join("trusted.json"))
+    }
+
+    /// 加载列表，文件不存在或无法解析时返回空列表。
+    pub fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    /// 工作区是否受信任。
+    pub fn contains(&self, root: &Path) -> bool {
+        self.workspaces.iter().any(|workspace| workspace == root)
+    }
+
+    /// 记录或者撤销对工作区的信任，写入 `path`。
+    ///
+    /// # 返回
+    ///
+    /// 列表是否改变
+    ///
+    /// # 错误
+    ///
+    /// 如果列表无法写入，返回错误
+    pub fn set(&mut self, path: &Path, root: &Path, trusted: bool) -> Result<bool> {
+        if self.contains(root) == trusted {
+            return Ok(false);
+        }
+        if trusted {
+            self.workspaces.push(root.to_path_buf());
+        } else {
+            self.workspaces.retain(|workspace| workspace != root);
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(true)
+    }
+}
+
+/// 本次会话的工作区信任状态。
+#[derive(Debug)]
+pub struct WorkspaceTrust {
+    workspace: PathBuf,
+    /// 工作区中的配置文件，只有它需要信任；没有时总是受信任
+    config: Option<PathBuf>,
+    trusted: AtomicBool,
+    /// 最近一次应用配置时因为不受信任而忽略的设置
+    withheld: Mutex<Vec<&'static str>>,
+}
+
+impl Default for WorkspaceTrust {
+    fn default() -> Self {
+        Self::new(PathBuf::new(), None, true)
+    }
+}
+
+impl WorkspaceTrust {
+    /// # 参数
+    ///
+    /// * `workspace` - 工作区根目录
+    /// * `config` - 工作区中的配置文件
+    /// * `trusted` - 工作区是否受信任
+    pub fn new(workspace: PathBuf, config: Option<PathBuf>, trusted: bool) -> Self {
+        Self {
+            workspace,
+            config,
+            trusted: AtomicBool::new(trusted),
+            withheld: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// 按配置文件的位置、命令行参数和记录的决定判断工作区是否受信任。
+    ///
+    /// # 参数
+    ///
+    /// * `config` - 使用的配置文件，见 [`Config::discover_path`]
+    /// * `workspace` - 工作区根目录，即代理的当前目录
+    /// * `flag` - 命令行是否给出了 `--trust-workspace`
+    pub fn discover(config: Option<&Path>, workspace: &Path, flag: bool) -> Self {
+        let workspace = workspace
+            .canonicalize()
+            .unwrap_or_else(|_| workspace.to_path_buf());
+        let config = config
+            .and_then(|path| path.canonicalize().ok())
+            .filter(|path| path.starts_with(&workspace));
+        let trusted = config.is_none()
+            || flag
+            || TrustedWorkspaces::default_path()
+                .is_some_and(|path| TrustedWorkspaces::load(&path).contains(&workspace));
+        Self::new(workspace, config, trusted)
+    }
+
+    pub fn is_trusted(&self) -> bool {
+        self.trusted.load(Ordering::Relaxed)
+    }
+
+    /// 改变信任状态（编辑器的 `workspace/trust`），之后重新应用配置才会生效。
+    ///
+    /// # 返回
+    ///
+    /// 状态是否改变
+    pub fn set_trusted(&self, trusted: bool) -> bool {
+        self.trusted.swap(trusted, Ordering::Relaxed) != trusted
+    }
+
+    /// 不受信任时从工作区的配置中去掉会启动进程的设置，记录被忽略的设置。
+    pub fn apply(&self, config: &mut Config) -> Vec<&'static str> {
+        let withheld = if self.config.is_some() && !self.is_trusted() {
+            restrict(config)
+        } else {
+            Vec::new()
+        };
+        *self.withheld.lock().unwrap() = withheld.clone();
+        withheld
+    }
+
+    /// 最近一次应用配置时忽略的设置。
+    pub fn withheld(&self) -> Vec<&'static str> {
+        self.withheld.lock().unwrap().clone()
+    }
+
+    /// 告诉用户忽略了哪些设置以及如何信任工作区，没有忽略任何设置时为 `None`。
+    pub fn message(&self) -> Option<String> {
+        let withheld = self.withheld();
+        if withheld.is_empty() {
+            return None;
+        }
+        let config = self.config.as_deref().unwrap_or(&self.workspace);
+        Some(format!(
+            "工作区 {} 不受信任，没有使用 {} 中会启动进程的设置：{}。\
+             信任这个工作区请运行 lsp-proxy trust，或者以 --trust-workspace 启动代理",
+            self.workspace.display(),
+            config.display(),
+            withheld.join("、")
+        ))
+    }
+}
+
+/// 与默认值不同时恢复默认值，并记录设置的名称。
+fn reset<T: Debug>(
+    withheld: &mut Vec<&'static str>,
+    name: &'static str,
+    value: &mut T,
+    default: T,
+) {
+    // 配置的结构体只实现了 Debug，按 Debug 的输出比较
+    if format!("{:?}", value) != format!("{:?}", default) {
+        *value = default;
+        withheld.push(name);
+    }
+}
+
+/// 把配置中会启动进程或者让后端运行程序的设置恢复为默认值。
+///
+/// # 返回
+///
+/// 被恢复的设置，按配置文件中的顺序
+pub fn restrict(config: &mut Config) -> Vec<&'static str> {
+    let defaults = Config::default();
+    let mut withheld = Vec::new();
+    let backend = &mut config.backend;
+    reset(
+        &mut withheld,
+        "[backend] command",
+        &mut backend.command,
+        defaults.backend.command,
+    );
+    reset(
+        &mut withheld,
+        "[backend] args",
+        &mut backend.args,
+        defaults.backend.args,
+    );
+    reset(
+        &mut withheld,
+        "[backend.ssh] args",
+        &mut backend.ssh.args,
+        defaults.backend.ssh.args,
+    );
+    reset(
+        &mut withheld,
+        "[backend.container]",
+        &mut backend.container,
+        defaults.backend.container,
+    );
+    // 沙箱的参数插在后端程序之前，目录和网络设置能让沙箱失去作用
+    reset(
+        &mut withheld,
+        "[backend.sandbox]",
+        &mut backend.sandbox,
+        defaults.backend.sandbox,
+    );
+    reset(
+        &mut withheld,
+        "[backend.clangd] query_driver",
+        &mut backend.clangd.query_driver,
+        defaults.backend.clangd.query_driver,
+    );
+    // 自动加入 --query-driver 降为询问用户
+    if backend.clangd.auto_query_driver == QueryDriverMode::On {
+        backend.clangd.auto_query_driver = QueryDriverMode::Ask;
+        withheld.push("[backend.clangd] auto_query_driver");
+    }
+    if config.shards.iter().any(|shard| !shard.args.is_empty()) {
+        config
+            .shards
+            .iter_mut()
+            .for_each(|shard| shard.args.clear());
+        withheld.push("[[shards]] args");
+    }
+    let shadow = &mut config.shadow;
+    reset(
+        &mut withheld,
+        "[shadow] command",
+        &mut shadow.command,
+        defaults.shadow.command,
+    );
+    reset(
+        &mut withheld,
+        "[shadow] args",
+        &mut shadow.args,
+        defaults.shadow.args,
+    );
+    reset(
+        &mut withheld,
+        "[modules] compiler",
+        &mut config.modules.compiler,
+        defaults.modules.compiler,
+    );
+    reset(
+        &mut withheld,
+        "[cmake] command",
+        &mut config.cmake.command,
+        defaults.cmake.command,
+    );
+    reset(
+        &mut withheld,
+        "[cmake] args",
+        &mut config.cmake.args,
+        defaults.cmake.args,
+    );
+    let flags = &mut config.flags;
+    reset(
+        &mut withheld,
+        "[flags] compiler",
+        &mut flags.compiler,
+        defaults.flags.compiler,
+    );
+    reset(
+        &mut withheld,
+        "[flags] bazel",
+        &mut flags.bazel,
+        defaults.flags.bazel,
+    );
+    reset(
+        &mut withheld,
+        "[flags] bazel_command",
+        &mut flags.bazel_command,
+        defaults.flags.bazel_command,
+    );
+    reset(
+        &mut withheld,
+        "[remote] ssh_command",
+        &mut config.remote.ssh_command,
+        defaults.remote.ssh_command,
+    );
+    withheld
+}
+
+/// 执行 `trust` 子命令：记录或者撤销对工作区的信任。
+///
+/// # 错误
+///
+/// 工作区无法访问、找不到配置目录或者列表无法写入时返回错误
+pub fn run(command: &TrustCommand) -> Result<()> {
+    let root = match &command.root {
+        Some(root) => root.clone(),
+        None => std::env::current_dir()?,
+    };
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("无法访问工作区 {}", root.display()))?;
+    let path = TrustedWorkspaces::default_path().context("没有设置 HOME，无法保存信任的工作区")?;
+    let trusted = !command.revoke;
+    let changed = TrustedWorkspaces::load(&path).set(&path, &root, trusted)?;
+    let state = if trusted { "受信任" } else { "不受信任" };
+    if changed {
+        println!(
+            "工作区 {} 已设为{}（{}）",
+            root.display(),
+            state,
+            path.display()
+        );
+    } else {
+        println!("工作区 {} 已经是{}的", root.display(), state);
+    }
+    Ok(())
+}
+
+/// 注册 `workspace/trust` 的处理器，工作区的信任由代理处理，不转发给后端。
+pub fn register(dispatcher: &mut Dispatcher) {
+    dispatcher.register_handler(
+        Direction::Frontend,
+        TRUST,
+        DEFAULT_HANDLER_PRIORITY,
+        set_trust,
+    );
+}
+
+/// 改变工作区的信任状态。请求以新的状态应答，通知失败时只记录日志。
+fn set_trust(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, Result<Verdict>> {
+    Box::pin(async move {
+        let result = ctx.dispatcher().set_workspace_trust(&rpc);
+        if rpc.get("id").is_some() {
+            return Ok(Verdict::Respond(handlers::response(&rpc, result)));
+        }
+        if let Err(e) = result {
+            warn!("无法改变工作区的信任状态: {:?}", e);
+        }
+        Ok(Verdict::Drop)
+    })
+}
@@ -0,0 +1,240 @@
+//! # 消息校验模块
+//!
+//! `--validate` 模式下，代理按声明的方法用 lsp_types 反序列化经过的消息，报告无法解析的字段，
+//! 以及解析后再序列化时丢失或改变的字段。适合开发新的处理器和发现后端的非标准行为。
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt::Write;
+use tower_lsp::lsp_types::notification::{self, Notification};
+use tower_lsp::lsp_types::request::{self, Request};
+
+/// 校验模式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidateMode {
+    /// `--validate`: 只记录违规
+    Report,
+    /// `--validate=strict`: 无法解析的请求和响应还会变成协议错误
+    Strict,
+}
+
+/// 校验消息的哪一部分。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    Params,
+    Result,
+}
+
+/// 一条不符合 lsp_types 定义的消息。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub method: String,
+    pub part: Part,
+    /// 反序列化失败的原因；为 `None` 时消息可以解析，但有字段在解析中丢失或改变
+    pub error: Option<String>,
+    /// 差异行：`-` 为收到的值，`+` 为 lsp_types 解析后的值
+    pub diff: Vec<String>,
+}
+
+impl Violation {
+    /// 消息是否无法解析。
+    pub fn is_error(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// 生成差异形式的报告。
+    pub fn report(&self) -> String {
+        let part = match self.part {
+            Part::Params => "参数",
+            Part::Result => "结果",
+        };
+        let mut report = match &self.error {
+            Some(error) => format!("{} 的{}无法按 lsp_types 解析: {}", self.method, part, error),
+            None => format!("{} 的{}有字段不符合 lsp_types 定义", self.method, part),
+        };
+        if !self.diff.is_empty() {
+            report.push_str("\n--- 收到的消息\n+++ lsp_types 解析结果");
+            for line in &self.diff {
+                let _ = write!(report, "\n{}", line);
+            }
+        }
+        report
+    }
+}
+
+/// 校验消息的参数或结果。
+///
+/// # 参数
+///
+/// * `method` - 消息声明的方法；响应使用对应请求的方法
+/// * `part` - 校验参数还是结果
+/// * `value` - 参数或结果，缺失时传入 `null`
+///
+/// # 返回
+///
+/// 消息符合定义或者方法不在 lsp_types 中时返回 `None`
+pub fn validate(method: &str, part: Part, value: &Value) -> Option<Violation> {
+    let roundtrip = roundtrip_for(method, part, value)?;
+    let violation = |error, diff| Violation {
+        method: method.to_string(),
+        part,
+        error,
+        diff,
+    };
+    match roundtrip {
+        Err(e) => Some(violation(Some(e.to_string()), Vec::new())),
+        Ok(parsed) => {
+            let mut diff = Vec::new();
+            diff_values("", value, &parsed, &mut diff);
+            (!diff.is_empty()).then(|| violation(None, diff))
+        }
+    }
+}
+
+/// 校验一条完整的消息：请求和通知校验参数，响应校验结果，错误响应不校验。
+///
+/// # 参数
+///
+/// * `method` - 消息的方法；响应使用对应请求的方法
+/// * `rpc` - JSON-RPC 消息
+pub fn validate_message(method: &str, rpc: &Value) -> Option<Violation> {
+    if rpc.get("method").is_some() {
+        validate(
+            method,
+            Part::Params,
+            rpc.get("params").unwrap_or(&Value::Null),
+        )
+    } else if let Some(result) = rpc.get("result") {
+        validate(method, Part::Result, result)
+    } else {
+        None
+    }
+}
+
+/// 反序列化后再序列化，得到 lsp_types 眼中的消息。
+fn roundtrip<T: DeserializeOwned + Serialize>(value: &Value) -> serde_json::Result<Value> {
+    serde_json::to_value(serde_json::from_value::<T>(value.clone())?)
+}
+
+/// 生成按方法查找 lsp_types 类型并做往返转换的函数。
+macro_rules! schema_table {
+    (requests: [$($request:ty),* $(,)?], notifications: [$($notification:ty),* $(,)?] $(,)?) => {
+        fn roundtrip_for(
+            method: &str,
+            part: Part,
+            value: &Value,
+        ) -> Option<serde_json::Result<Value>> {
+            $(
+                if method == <$request as Request>::METHOD {
+                    return Some(match part {
+                        Part::Params => roundtrip::<<$request as Request>::Params>(value),
+                        Part::Result => roundtrip::<<$request as Request>::Result>(value),
+                    });
+                }
+            )*
+            $(
+                if method == <$notification as Notification>::METHOD && part == Part::Params {
+                    return Some(roundtrip::<<$notification as Notification>::Params>(value));
+                }
+            )*
+            None
+        }
+    };
+}
+
+schema_table! {
+    requests: [
+        request::Initialize,
+        request::Shutdown,
+        request::Completion,
+        request::ResolveCompletionItem,
+        request::HoverRequest,
+        request::SignatureHelpRequest,
+        request::GotoDeclaration,
+        request::GotoDefinition,
+        request::GotoTypeDefinition,
+        request::GotoImplementation,
+        request::References,
+        request::DocumentHighlightRequest,
+        request::DocumentSymbolRequest,
+        request::WorkspaceSymbolRequest,
+        request::CodeActionRequest,
+        request::CodeLensRequest,
+        request::DocumentLinkRequest,
+        request::Formatting,
+        request::RangeFormatting,
+        request::OnTypeFormatting,
+        request::Rename,
+        request::PrepareRenameRequest,
+        request::ExecuteCommand,
+        request::FoldingRangeRequest,
+        request::SelectionRangeRequest,
+        request::SemanticTokensFullRequest,
+        request::SemanticTokensFullDeltaRequest,
+        request::SemanticTokensRangeRequest,
+        request::InlayHintRequest,
+        request::CallHierarchyPrepare,
+        request::CallHierarchyIncomingCalls,
+        request::CallHierarchyOutgoingCalls,
+        request::TypeHierarchyPrepare,
+        request::WorkDoneProgressCreate,
+        request::RegisterCapability,
+        request::UnregisterCapability,
+        request::ApplyWorkspaceEdit,
+        request::WorkspaceConfiguration,
+        request::WorkspaceFoldersRequest,
+        request::ShowMessageRequest,
+    ],
+    notifications: [
+        notification::Initialized,
+        notification::Exit,
+        notification::Cancel,
+        notification::SetTrace,
+        notification::Progress,
+        notification::DidOpenTextDocument,
+        notification::DidChangeTextDocument,
+        notification::DidSaveTextDocument,
+        notification::DidCloseTextDocument,
+        notification::DidChangeConfiguration,
+        notification::DidChangeWatchedFiles,
+        notification::DidChangeWorkspaceFolders,
+        notification::PublishDiagnostics,
+        notification::LogMessage,
+        notification::ShowMessage,
+    ],
+}
+
+/// 比较收到的值和解析后的值，`null` 与缺失的字段视为相同。
+fn diff_values(path: &str, received: &Value, parsed: &Value, out: &mut Vec<String>) {
+    match (received, parsed) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = format!("{}/{}", path, key);
+                match (a.get(key), b.get(key)) {
+                    (Some(a), Some(b)) => diff_values(&path, a, b, out),
+                    (Some(Value::Null), None) | (None, Some(Value::Null)) => {}
+                    (Some(a), None) => out.push(format!("- {}: {}", path, a)),
+                    (None, Some(b)) => out.push(format!("+ {}: {}", path, b)),
+                    (None, None) => {}
+                }
+            }
+        }
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+            for (index, (a, b)) in a.iter().zip(b).enumerate() {
+                diff_values(&format!("{}/{}", path, index), a, b, out);
+            }
+        }
+        // 1 和 1.0 对 lsp_types 没有区别
+        (Value::Number(a), Value::Number(b)) if a.as_f64() == b.as_f64() => {}
+        (a, b) if a != b => {
+            let path = if path.is_empty() { "/" } else { path };
+            out.push(format!("- {}: {}", path, a));
+            out.push(format!("+ {}: {}", path, b));
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,93 @@
+//! # 配置变量模块
+//!
+//! 配置中的路径和命令可以使用 `${env:VAR}`、`${workspaceFolder}` 和开头的 `~`，加载配置时展开，
+//! 同一份配置可以在不同的机器和 CI 上使用。无法展开的变量在加载时报错，而不是在启动后端时才失败。
+
+use anyhow::{Result, anyhow, bail};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// 展开变量时使用的值。
+///
+/// - `workspace`: `${workspaceFolder}` 的值，即启动代理的目录（编辑器在工作区中启动代理）
+/// - `home`: `~` 代表的用户主目录
+/// - `env`: 环境变量
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    pub workspace: PathBuf,
+    pub home: Option<PathBuf>,
+    pub env: HashMap<String, String>,
+}
+
+impl Variables {
+    /// 使用当前目录、用户主目录和当前进程的环境变量。
+    ///
+    /// # 错误
+    ///
+    /// 无法取得当前目录时返回错误
+    pub fn from_env() -> Result<Self> {
+        let home = std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .map(PathBuf::from);
+        Ok(Self {
+            workspace: std::env::current_dir()?,
+            home,
+            env: std::env::vars().collect(),
+        })
+    }
+
+    /// 展开字符串中的变量。
+    ///
+    /// `~` 只在开头（`~` 或 `~/...`）展开；`$` 后面不是 `{` 时保持原样。
+    ///
+    /// # 错误
+    ///
+    /// 环境变量没有设置、变量未知、`${` 没有闭合或者无法确定用户主目录时返回错误
+    pub fn expand(&self, value: &str) -> Result<String> {
+        let mut expanded = String::with_capacity(value.len());
+        let mut rest = value;
+        if rest == "~" || rest.starts_with("~/") || rest.starts_with("~\\") {
+            let home = self
+                .home
+                .as_ref()
+                .ok_or_else(|| anyhow!("无法确定用户主目录，不能展开 ~"))?;
+            expanded.push_str(&home.display().to_string());
+            rest = &rest[1..];
+        }
+        while let Some(start) = rest.find("${") {
+            expanded.push_str(&rest[..start]);
+            let Some(end) = rest[start..].find('}') else {
+                bail!("{} 中的 ${{ 没有对应的 }}", value);
+            };
+            let name = &rest[start + 2..start + end];
+            match name.strip_prefix("env:") {
+                Some(var) => match self.env.get(var) {
+                    Some(var) => expanded.push_str(var),
+                    None => bail!("环境变量 {} 没有设置", var),
+                },
+                None if name == "workspaceFolder" => {
+                    expanded.push_str(&self.workspace.display().to_string())
+                }
+                None => bail!(
+                    "未知的变量 ${{{}}}，支持 ${{env:NAME}} 和 ${{workspaceFolder}}",
+                    name
+                ),
+            }
+            rest = &rest[start + end + 1..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+
+    /// 展开路径中的变量，不是 UTF-8 的路径保持原样。
+    ///
+    /// # 错误
+    ///
+    /// 与 [`Variables::expand`] 相同
+    pub fn expand_path(&self, path: &Path) -> Result<PathBuf> {
+        match path.to_str() {
+            Some(text) => self.expand(text).map(PathBuf::from),
+            None => Ok(path.to_path_buf()),
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! # 虚拟文档模块
+//!
+//! 笔记本的代码单元、宿主文件中嵌入的 C/C++ 片段等不能直接交给 clangd 的内容被拼成虚拟文档交给后端。
+//! 这个模块负责两个方向的位置换算：针对原文档的请求换成虚拟文档中的位置，响应和诊断再换回原文档。
+
+use dashmap::DashMap;
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use tower_lsp::lsp_types::Url;
+use tower_lsp::lsp_types::notification::{Notification, PublishDiagnostics};
+
+const SEMANTIC_TOKENS_DELTA: &str = "textDocument/semanticTokens/full/delta";
+const SEMANTIC_TOKENS_FULL: &str = "textDocument/semanticTokens/full";
+const SEMANTIC_TOKENS_RANGE: &str = "textDocument/semanticTokens/range";
+
+/// 原文档在虚拟文档中占据的行：虚拟文档的第 `start` 行是原文档的第 0 行。
+#[derive(Debug, Clone)]
+pub struct Slot {
+    pub uri: Url,
+    pub start: u32,
+    pub lines: u32,
+}
+
+impl Slot {
+    fn contains(&self, line: u64) -> bool {
+        (u64::from(self.start)..u64::from(self.start + self.lines)).contains(&line)
+    }
+}
+
+/// 一个虚拟文档由哪些原文档拼成，按在虚拟文档中的位置排列。
+#[derive(Debug, Clone)]
+pub struct Layout {
+    pub slots: Vec<Slot>,
+}
+
+impl Layout {
+    pub fn slot(&self, uri: &Url) -> Option<&Slot> {
+        self.slots.iter().find(|slot| &slot.uri == uri)
+    }
+
+    /// 虚拟文档中的一行所在的原文档，不属于任何原文档的行算作最后一个原文档。
+    fn at_line(&self, line: u64) -> Option<&Slot> {
+        self.slots
+            .iter()
+            .find(|slot| slot.contains(line))
+            .or_else(|| self.slots.last())
+    }
+}
+
+/// 所有虚拟文档的布局，以虚拟文档的 URI 为键。
+pub type Layouts = HashMap<String, Layout>;
+
+/// 换成虚拟文档位置的请求。
+struct PendingRequest {
+    virtual_uri: String,
+    source: Url,
+    method: String,
+}
+
+/// 换成虚拟文档位置、等待响应的请求。
+#[derive(Default)]
+pub struct VirtualDocuments {
+    requests: DashMap<String, PendingRequest>,
+}
+
+impl VirtualDocuments {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 把针对原文档的请求换成针对虚拟文档的请求：替换文档 URI，参数中的位置加上原文档在虚拟文档中的起始行。
+    ///
+    /// 语义标记的增量请求改成完整请求，增量结果中的下标无法换回原文档。
+    ///
+    /// # 参数
+    ///
+    /// * `rpc` - 前端的请求，`params.textDocument.uri` 是原文档
+    /// * `virtual_uri` - 原文档所在的虚拟文档
+    /// * `start` - 原文档在虚拟文档中的起始行
+    pub fn to_virtual(&self, rpc: &mut Value, virtual_uri: &Url, start: u32) {
+        let (Some(id), Some(method), Some(source)) = (
+            rpc.get("id").map(|id| id.to_string()),
+            rpc.get("method")
+                .and_then(|m| m.as_str())
+                .map(str::to_string),
+            rpc.pointer("/params/textDocument/uri")
+                .and_then(|uri| uri.as_str())
+                .and_then(|uri| Url::parse(uri).ok()),
+        ) else {
+            return;
+        };
+
+        rpc["params"]["textDocument"]["uri"] = json!(virtual_uri);
+        shift_positions(&mut rpc["params"], i64::from(start));
+        if method == SEMANTIC_TOKENS_DELTA {
+            rpc["method"] = json!(SEMANTIC_TOKENS_FULL);
+            if let Some(params) = rpc["params"].as_object_mut() {
+                params.remove("previousResultId");
+            }
+        }
+        self.requests.insert(
+            id,
+            PendingRequest {
+                virtual_uri: virtual_uri.to_string(),
+                source,
+                method,
+            },
+        );
+    }
+
+    /// 是否有换成虚拟文档位置、还没有收到响应的请求。
+    pub fn has_pending(&self) -> bool {
+        !self.requests.is_empty()
+    }
+
+    /// 把发给前端的消息中虚拟文档的 URI 和位置换回原文档，虚拟文档的诊断按原文档拆成多条通知。
+    pub fn to_sources(&self, mut rpc: Value, layouts: &Layouts) -> Vec<Value> {
+        if rpc.get("method").is_none() {
+            if let Some(id) = rpc.get("id")
+                && let Some((_, request)) = self.requests.remove(&id.to_string())
+                && let Some(slot) = layouts
+                    .get(&request.virtual_uri)
+                    .and_then(|layout| layout.slot(&request.source))
+                && let Some(result) = rpc.get_mut("result")
+            {
+                restore_result(result, &request.method, layouts, slot);
+            }
+            return vec![rpc];
+        }
+
+        if rpc["method"] == PublishDiagnostics::METHOD
+            && let Some(layout) = rpc
+                .pointer("/params/uri")
+                .and_then(|uri| uri.as_str())
+                .and_then(|uri| layouts.get(uri))
+        {
+            return split_diagnostics(&rpc, layout, layouts);
+        }
+
+        if !layouts.is_empty()
+            && let Some(params) = rpc.get_mut("params")
+        {
+            restore(params, layouts, None);
+        }
+        vec![rpc]
+    }
+}
+
+fn is_position(map: &Map<String, Value>) -> bool {
+    map.len() == 2
+        && map.get("line").is_some_and(Value::is_u64)
+        && map.get("character").is_some_and(Value::is_u64)
+}
+
+/// 把 `value` 中所有位置的行号加上 `delta`，`FoldingRange` 的 `startLine`/`endLine` 也一样。
+pub fn shift_positions(value: &mut Value, delta: i64) {
+    let shift = |line: &mut Value| {
+        if let Some(n) = line.as_u64() {
+            *line = json!((n as i64 + delta).max(0));
+        }
+    };
+    match value {
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| shift_positions(item, delta)),
+        Value::Object(map) if is_position(map) => shift(&mut map["line"]),
+        Value::Object(map) => {
+            for key in ["startLine", "endLine"] {
+                if let Some(line) = map.get_mut(key) {
+                    shift(line);
+                }
+            }
+            map.values_mut()
+                .for_each(|value| shift_positions(value, delta));
+        }
+        _ => {}
+    }
+}
+
+/// 值中第一个位置的行号，用来判断没有 URI 的结果属于哪个原文档。
+fn first_line(value: &Value) -> Option<u64> {
+    match value {
+        Value::Object(map) if is_position(map) => map["line"].as_u64(),
+        Value::Object(map) => map
+            .get("startLine")
+            .and_then(Value::as_u64)
+            .or_else(|| map.values().find_map(first_line)),
+        Value::Array(items) => items.iter().find_map(first_line),
+        _ => None,
+    }
+}
+
+/// 换回请求的结果。没有 URI 的结果属于请求的原文档，其他原文档中的结果被去掉。
+fn restore_result(result: &mut Value, method: &str, layouts: &Layouts, slot: &Slot) {
+    if matches!(
+        method,
+        SEMANTIC_TOKENS_FULL | SEMANTIC_TOKENS_DELTA | SEMANTIC_TOKENS_RANGE
+    ) {
+        return restore_semantic_tokens(result, slot);
+    }
+    if let Some(items) = result.as_array_mut() {
+        items.retain(|item| {
+            ["uri", "targetUri", "location"]
+                .iter()
+                .any(|key| item.get(*key).is_some())
+                || first_line(item).is_none_or(|line| slot.contains(line))
+        });
+    }
+    restore(result, layouts, Some(slot));
+}
+
+/// 换回消息中的 URI 和位置。
+///
+/// 带有虚拟文档 URI 的对象按位置换成所在的原文档；`WorkspaceEdit` 的修改按原文档拆开；
+/// 其他位置属于请求的原文档 `requested`，没有请求的原文档时不变。
+fn restore(value: &mut Value, layouts: &Layouts, requested: Option<&Slot>) {
+    match value {
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| restore(item, layouts, requested)),
+        Value::Object(map) if is_position(map) => {
+            if let Some(slot) = requested {
+                shift_positions(value, -i64::from(slot.start));
+            }
+        }
+        Value::Object(map) => {
+            if let Some((key, uri)) = ["uri", "targetUri"]
+                .into_iter()
+                .find_map(|key| Some((key, map.get(key)?.as_str()?)))
+            {
+                if layouts.contains_key(uri) {
+                    return restore_location(map, key, layouts, requested);
+                }
+                // 其他文档中的位置不变，只有跳转的起点在请求的原文档中
+                if let Some(slot) = requested
+                    && let Some(origin) = map.get_mut("originSelectionRange")
+                {
+                    shift_positions(origin, -i64::from(slot.start));
+                }
+                return;
+            }
+            if map.contains_key("changes") || map.contains_key("documentChanges") {
+                return restore_workspace_edit(map, layouts);
+            }
+            if let Some(slot) = requested {
+                for key in ["startLine", "endLine"] {
+                    if let Some(line) = map.get_mut(key)
+                        && let Some(n) = line.as_u64()
+                    {
+                        *line = json!(n.saturating_sub(u64::from(slot.start)));
+                    }
+                }
+            }
+            map.values_mut()
+                .for_each(|value| restore(value, layouts, requested));
+        }
+        _ => {}
+    }
+}
+
+/// 换回 `Location`/`LocationLink` 之类带有虚拟文档 URI 的对象。
+fn restore_location(
+    map: &mut Map<String, Value>,
+    key: &str,
+    layouts: &Layouts,
+    requested: Option<&Slot>,
+) {
+    let layout = &layouts[map[key].as_str().unwrap_or_default()];
+    let line = ["range", "targetSelectionRange", "targetRange"]
+        .iter()
+        .find_map(|range| map.get(*range)?.pointer("/start/line")?.as_u64())
+        .unwrap_or(0);
+    let Some(slot) = layout.at_line(line) else {
+        return;
+    };
+    map.insert(key.to_string(), json!(slot.uri));
+    for (name, value) in map.iter_mut() {
+        // 跳转的起点在请求的原文档中
+        let start = match name.as_str() {
+            "originSelectionRange" => requested.map(|slot| slot.start),
+            _ => Some(slot.start),
+        };
+        if let Some(start) = start {
+            shift_positions(value, -i64::from(start));
+        }
+    }
+}
+
+/// 把虚拟文档的修改按所在的原文档拆开。
+fn split_edits(edits: &[Value], layout: &Layout) -> Vec<(Url, Vec<Value>)> {
+    let mut split: Vec<(Url, Vec<Value>)> = Vec::new();
+    for edit in edits {
+        let line = edit.pointer("/range/start/line").and_then(Value::as_u64);
+        let Some(slot) = line.and_then(|line| layout.at_line(line)) else {
+            continue;
+        };
+        let mut edit = edit.clone();
+        shift_positions(&mut edit, -i64::from(slot.start));
+        match split.iter_mut().find(|(uri, _)| uri == &slot.uri) {
+            Some((_, edits)) => edits.push(edit),
+            None => split.push((slot.uri.clone(), vec![edit])),
+        }
+    }
+    split
+}
+
+fn restore_workspace_edit(map: &mut Map<String, Value>, layouts: &Layouts) {
+    if let Some(Value::Object(changes)) = map.get_mut("changes") {
+        for (virtual_uri, layout) in layouts {
+            let Some(Value::Array(edits)) = changes.remove(virtual_uri) else {
+                continue;
+            };
+            for (cell, edits) in split_edits(&edits, layout) {
+                changes.insert(cell.to_string(), json!(edits));
+            }
+        }
+    }
+    if let Some(Value::Array(document_changes)) = map.get_mut("documentChanges") {
+        *document_changes = std::mem::take(document_changes)
+            .into_iter()
+            .flat_map(|change| {
+                let layout = change
+                    .pointer("/textDocument/uri")
+                    .and_then(|uri| uri.as_str())
+                    .and_then(|uri| layouts.get(uri));
+                let (Some(layout), Some(edits)) =
+                    (layout, change.get("edits").and_then(Value::as_array))
+                else {
+                    return vec![change];
+                };
+                // 原文档在虚拟文档之外没有单独的版本
+                split_edits(edits, layout)
+                    .into_iter()
+                    .map(|(cell, edits)| {
+                        json!({"textDocument": {"uri": cell, "version": null}, "edits": edits})
+                    })
+                    .collect()
+            })
+            .collect();
+    }
+}
+
+/// 把虚拟文档的诊断按原文档拆开，每个原文档一条通知，没有诊断的原文档以空列表清除之前的诊断。
+fn split_diagnostics(rpc: &Value, layout: &Layout, layouts: &Layouts) -> Vec<Value> {
+    let diagnostics = rpc
+        .pointer("/params/diagnostics")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    layout
+        .slots
+        .iter()
+        .map(|slot| {
+            let mut own: Vec<Value> = diagnostics
+                .iter()
+                .filter(|diagnostic| {
+                    diagnostic
+                        .pointer("/range/start/line")
+                        .and_then(Value::as_u64)
+                        .and_then(|line| layout.at_line(line))
+                        .is_some_and(|owner| owner.uri == slot.uri)
+                })
+                .cloned()
+                .collect();
+            own.iter_mut()
+                .for_each(|diagnostic| restore(diagnostic, layouts, Some(slot)));
+            json!({
+                "jsonrpc": "2.0",
+                "method": PublishDiagnostics::METHOD,
+                "params": {"uri": slot.uri, "diagnostics": own},
+            })
+        })
+        .collect()
+}
+
+/// 从整个虚拟文档的语义标记中取出请求的原文档的部分，重新按相对位置编码。
+fn restore_semantic_tokens(result: &mut Value, slot: &Slot) {
+    let Some(data) = result.get("data").and_then(Value::as_array) else {
+        return;
+    };
+    let numbers: Vec<u64> = data.iter().filter_map(Value::as_u64).collect();
+    let mut tokens = Vec::new();
+    let (mut line, mut start) = (0, 0);
+    let (mut last_line, mut last_start) = (0, 0);
+    for token in numbers.chunks_exact(5) {
+        if token[0] > 0 {
+            line += token[0];
+            start = token[1];
+        } else {
+            start += token[1];
+        }
+        if !slot.contains(line) {
+            continue;
+        }
+        let cell_line = line - u64::from(slot.start);
+        let delta_line = cell_line - last_line;
+        let delta_start = if delta_line == 0 {
+            start - last_start
+        } else {
+            start
+        };
+        tokens.extend([delta_line, delta_start, token[2], token[3], token[4]]);
+        last_line = cell_line;
+        last_start = start;
+    }
+    result["data"] = json!(tokens);
+}
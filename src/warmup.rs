@@ -0,0 +1,196 @@
+//! # 预热模块
+//!
+//! 代理在工作区的 `.cache/codefuse/recent_files.json` 中记录最近编辑的文件。
+//! 会话开始时为这些文件发送合成的 `didOpen`，让 clangd 提前构建 preamble，用户打开它们时第一次悬停和补全就很快。
+
+use anyhow::Result;
+use dashmap::DashSet;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tower_lsp::lsp_types::Url;
+use tower_lsp::lsp_types::notification::{DidCloseTextDocument, DidOpenTextDocument, Notification};
+
+use crate::config::WarmupConfig;
+use crate::document_store::DocumentStore;
+
+/// 最近文件列表相对于工作区根目录的位置。
+pub const RECENT_FILES_PATH: &str = ".cache/codefuse/recent_files.json";
+
+/// 持久化的最近编辑文件列表，最近的排在最前面。
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RecentFiles {
+    files: Vec<PathBuf>,
+    #[serde(skip)]
+    capacity: usize,
+}
+
+impl RecentFiles {
+    /// 创建空列表。
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            files: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// 从文件加载列表，文件不存在或无法解析时返回空列表。
+    pub fn load(path: &Path, capacity: usize) -> Self {
+        let mut recent = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str::<Self>(&text).ok())
+            .unwrap_or_default();
+        recent.capacity = capacity;
+        recent.files.truncate(capacity);
+        recent
+    }
+
+    /// 把文件移到列表最前面。
+    ///
+    /// # 返回
+    ///
+    /// 列表发生变化时返回 `true`
+    pub fn touch(&mut self, file: PathBuf) -> bool {
+        if self.files.first() == Some(&file) {
+            return false;
+        }
+        self.files.retain(|f| f != &file);
+        self.files.insert(0, file);
+        self.files.truncate(self.capacity);
+        true
+    }
+
+    /// 最近编辑的文件，最近的排在最前面。
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// 把列表写入文件，必要时创建目录。
+    ///
+    /// # 错误
+    ///
+    /// 如果目录无法创建或文件无法写入，返回错误
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// 会话开始时的 preamble 预热。
+///
+/// 被预热但用户还没有打开的文件称为“预热文档”：它们的诊断不会转发给前端，
+/// 用户打开它们时代理先关闭预热文档，再转发用户的 `didOpen`。
+pub struct Warmup {
+    config: WarmupConfig,
+    state: Mutex<Option<(PathBuf, RecentFiles)>>,
+    warm: DashSet<Url>,
+}
+
+impl Warmup {
+    /// 按 `[warmup]` 配置创建，工作区在 `load` 时确定。
+    pub fn new(config: WarmupConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(None),
+            warm: DashSet::new(),
+        }
+    }
+
+    /// 从第一个工作区根目录加载最近文件列表。
+    pub fn load(&self, roots: &[PathBuf]) {
+        if !self.config.enabled {
+            return;
+        }
+        let Some(root) = roots.first() else {
+            return;
+        };
+        let path = root.join(RECENT_FILES_PATH);
+        let recent = RecentFiles::load(&path, self.config.max_files);
+        *self.state.lock().unwrap() = Some((path, recent));
+    }
+
+    /// 记录用户打开或编辑了文档。
+    ///
+    /// # 返回
+    ///
+    /// 如果文档当前是预热文档，返回关闭它的 `didClose` 通知，调用方需要在转发用户消息之前发送
+    pub fn record(&self, uri: &Url) -> Option<Value> {
+        let mut guard = self.state.lock().unwrap();
+        if let Some((path, recent)) = guard.as_mut()
+            && let Ok(file) = uri.to_file_path()
+            && recent.touch(file)
+            && let Err(e) = recent.save(path)
+        {
+            warn!("无法保存最近文件列表: {:?}", e);
+        }
+        drop(guard);
+
+        self.warm.remove(uri).map(|_| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": DidCloseTextDocument::METHOD,
+                "params": {"textDocument": {"uri": uri}},
+            })
+        })
+    }
+
+    /// 为最近文件生成预热用的 `didOpen` 通知，跳过用户已经打开的和已经不存在的文件。
+    pub fn warm_up(&self, documents: &DocumentStore) -> Vec<(Url, Value)> {
+        let files = match self.state.lock().unwrap().as_ref() {
+            Some((_, recent)) => recent.files().to_vec(),
+            None => return Vec::new(),
+        };
+
+        let mut notifications = Vec::new();
+        for file in files {
+            let Ok(uri) = Url::from_file_path(&file) else {
+                continue;
+            };
+            if documents.get(&uri).is_some() || self.warm.contains(&uri) {
+                continue;
+            }
+            let Ok(text) = std::fs::read_to_string(&file) else {
+                debug!("跳过无法读取的最近文件 {}", file.display());
+                continue;
+            };
+            notifications.push((
+                uri.clone(),
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": DidOpenTextDocument::METHOD,
+                    "params": {
+                        "textDocument": {
+                            "uri": uri,
+                            "languageId": language_id(&file),
+                            "version": 0,
+                            "text": text,
+                        }
+                    }
+                }),
+            ));
+            self.warm.insert(uri);
+        }
+        notifications
+    }
+
+    /// 文档是否是用户还没有打开的预热文档。
+    pub fn is_warm(&self, uri: &Url) -> bool {
+        self.warm.contains(uri)
+    }
+}
+
+/// 根据扩展名推断 clangd 使用的语言 id。
+pub fn language_id(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("c") => "c",
+        Some("m") => "objective-c",
+        Some("mm") => "objective-cpp",
+        Some("cu") => "cuda-cpp",
+        _ => "cpp",
+    }
+}
@@ -0,0 +1,125 @@
+//! # 单词补全模块
+//!
+//! clangd 正在重启或者后台索引还没有完成时，后端的补全不可用或者很不完整。开启 `[completion] words` 后，
+//! 代理从打开的文档中收集标识符作为补全来源，只在后端没有就绪时提供，输入时的提示不会完全消失。
+//! 请求所在文档的单词排在前面；每个文档的单词按版本缓存，文档变化后下一次补全时重新收集。
+
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use serde_json::{Value, json};
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+use tower_lsp::lsp_types::{CompletionItemKind, Url};
+
+use crate::completion_sources::{CompletionProvider, CompletionRequest};
+use crate::document_store::{self, Document, DocumentStore};
+
+/// 单词来源的名称。
+pub const SOURCE: &str = "words";
+
+/// 短于这个长度的单词不提供。
+const MIN_LENGTH: usize = 3;
+
+/// 一次补全最多提供的单词数。
+const MAX_ITEMS: usize = 100;
+
+/// 文档 → (收集时的版本, 单词)
+type WordCache = DashMap<Url, (i32, Arc<BTreeSet<String>>)>;
+
+/// 打开的文档中的单词，作为补全来源。
+pub struct WordCompletion {
+    documents: Arc<DocumentStore>,
+    words: Arc<WordCache>,
+}
+
+impl WordCompletion {
+    /// 从 `documents` 中打开的文档收集单词。
+    pub fn new(documents: Arc<DocumentStore>) -> Self {
+        Self {
+            documents,
+            words: Arc::new(DashMap::new()),
+        }
+    }
+}
+
+/// 文档中的单词，版本没有变化时使用缓存。
+fn words_of(cache: &WordCache, doc: &Document) -> Arc<BTreeSet<String>> {
+    if let Some(cached) = cache.get(&doc.uri)
+        && cached.0 == doc.version
+    {
+        return Arc::clone(&cached.1);
+    }
+    let words: Arc<BTreeSet<String>> = Arc::new(
+        document_store::identifiers(&doc.text)
+            .filter(|word| word.chars().count() >= MIN_LENGTH)
+            .map(str::to_string)
+            .collect(),
+    );
+    cache.insert(doc.uri.clone(), (doc.version, Arc::clone(&words)));
+    words
+}
+
+/// 光标前正在输入的单词。
+fn typed_word(request: &CompletionRequest) -> String {
+    let Some(doc) = &request.document else {
+        return String::new();
+    };
+    let position = request.params.text_document_position.position;
+    let offset = document_store::offset_at(&doc.text, position);
+    document_store::word_before(&doc.text[..offset]).to_string()
+}
+
+/// 以 `typed` 开头（忽略大小写）的单词，请求所在的文档优先。
+fn complete_words(
+    cache: &WordCache,
+    documents: &DocumentStore,
+    current: Option<&Url>,
+    typed: &str,
+) -> Vec<Value> {
+    let mut documents = documents.documents();
+    // 关闭的文档不再保留单词
+    cache.retain(|uri, _| documents.iter().any(|doc| &doc.uri == uri));
+    documents.sort_by_key(|doc| Some(&doc.uri) != current);
+
+    let prefix = typed.to_lowercase();
+    let mut seen = HashSet::new();
+    let mut items = Vec::new();
+    for doc in &documents {
+        for word in words_of(cache, doc).iter() {
+            if items.len() >= MAX_ITEMS {
+                return items;
+            }
+            if word != typed
+                && word.to_lowercase().starts_with(&prefix)
+                && seen.insert(word.clone())
+            {
+                items.push(json!({"label": word, "kind": CompletionItemKind::TEXT}));
+            }
+        }
+    }
+    items
+}
+
+/// 后端就绪时不提供单词，补全完全由后端负责。
+impl CompletionProvider for WordCompletion {
+    fn name(&self) -> &str {
+        SOURCE
+    }
+
+    fn complete(&self, request: &CompletionRequest) -> BoxFuture<'static, Vec<Value>> {
+        if request.backend_ready {
+            return Box::pin(async { Vec::new() });
+        }
+        let typed = typed_word(request);
+        let current = request.document.as_ref().map(|doc| doc.uri.clone());
+        let documents = Arc::clone(&self.documents);
+        let cache = Arc::clone(&self.words);
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                complete_words(&cache, &documents, current.as_ref(), &typed)
+            })
+            .await
+            .unwrap_or_default()
+        })
+    }
+}
@@ -0,0 +1,182 @@
+//! # 工作区模块
+//!
+//! 这个模块跟踪编辑器打开的工作区文件夹（multi-root），
+//! 包括 initialize 时给出的初始文件夹以及之后 `workspace/didChangeWorkspaceFolders` 带来的变化。
+
+use log::info;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use tower_lsp::lsp_types::{Url, WorkspaceFolder, WorkspaceFoldersChangeEvent};
+
+/// 工作区文件夹集合。
+pub struct WorkspaceFolders {
+    folders: RwLock<Vec<WorkspaceFolder>>,
+}
+
+impl Default for WorkspaceFolders {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WorkspaceFolders {
+    /// 创建空的工作区文件夹集合。
+    pub fn new() -> Self {
+        Self {
+            folders: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// 根据 initialize 请求参数设置初始的工作区文件夹。
+    ///
+    /// 客户端提供的 `workspaceFolders` 保留各自的名字；没有提供时，使用 `rootUri` 或 `rootPath`
+    /// 作为唯一的文件夹，以目录名命名。
+    pub fn set_from_initialize(&self, params: &Value) {
+        let mut folders: Vec<WorkspaceFolder> = params
+            .get("workspaceFolders")
+            .and_then(|folders| folders.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|folder| serde_json::from_value::<WorkspaceFolder>(folder.clone()).ok())
+            .filter(|folder| folder.uri.to_file_path().is_ok())
+            .collect();
+        if folders.is_empty() {
+            folders = initialize_roots(params)
+                .into_iter()
+                .filter_map(|path| {
+                    let uri = Url::from_directory_path(&path).ok()?;
+                    Some(WorkspaceFolder {
+                        uri,
+                        name: folder_name(&path),
+                    })
+                })
+                .collect();
+        }
+        *self.folders.write().unwrap() = folders;
+    }
+
+    /// 应用 `workspace/didChangeWorkspaceFolders` 通知中的变化。
+    pub fn apply_change(&self, event: WorkspaceFoldersChangeEvent) {
+        let mut folders = self.folders.write().unwrap();
+        for removed in &event.removed {
+            folders.retain(|f| !same_folder(&f.uri, &removed.uri));
+            info!("移除工作区文件夹: {}", removed.uri);
+        }
+        for added in event.added {
+            if !folders.iter().any(|f| same_folder(&f.uri, &added.uri)) {
+                info!("添加工作区文件夹: {}", added.uri);
+                folders.push(added);
+            }
+        }
+    }
+
+    /// 当前的全部工作区文件夹。
+    pub fn folders(&self) -> Vec<WorkspaceFolder> {
+        self.folders.read().unwrap().clone()
+    }
+
+    /// 当前全部工作区文件夹对应的本地路径。
+    pub fn roots(&self) -> Vec<PathBuf> {
+        self.folders
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|f| f.uri.to_file_path().ok())
+            .collect()
+    }
+}
+
+/// 从 initialize 请求参数中提取工作区根目录。
+///
+/// 优先使用 `workspaceFolders`，其次是 `rootUri`，最后是已经废弃的 `rootPath`。
+pub fn initialize_roots(params: &Value) -> Vec<PathBuf> {
+    let folders: Vec<PathBuf> = params
+        .get("workspaceFolders")
+        .and_then(|f| f.as_array())
+        .map(|folders| {
+            folders
+                .iter()
+                .filter_map(|f| f.get("uri").and_then(|u| u.as_str()))
+                .filter_map(uri_to_path)
+                .collect()
+        })
+        .unwrap_or_default();
+    if !folders.is_empty() {
+        return folders;
+    }
+
+    if let Some(path) = params
+        .get("rootUri")
+        .and_then(|u| u.as_str())
+        .and_then(uri_to_path)
+    {
+        return vec![path];
+    }
+
+    params
+        .get("rootPath")
+        .and_then(|p| p.as_str())
+        .map(|p| vec![PathBuf::from(p)])
+        .unwrap_or_default()
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    Url::parse(uri).ok()?.to_file_path().ok()
+}
+
+fn folder_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// 比较两个文件夹 URI，忽略末尾的 `/`。
+fn same_folder(a: &Url, b: &Url) -> bool {
+    a.as_str().trim_end_matches('/') == b.as_str().trim_end_matches('/')
+}
+
+/// 工作区中的 C/C++ 源文件和头文件的扩展名。
+const SOURCE_EXTENSIONS: &[&str] = &[
+    "c", "cc", "cpp", "cxx", "c++", "cu", "h", "hh", "hpp", "hxx", "inl", "ipp", "m", "mm",
+];
+
+/// 工作区中的 C/C++ 文件，跳过隐藏目录和符号链接。
+///
+/// # 返回
+///
+/// 返回找到的文件，以及是否因为超过 `limit` 而没有找全
+pub fn source_files(roots: &[PathBuf], limit: usize) -> (Vec<PathBuf>, bool) {
+    let mut files = Vec::new();
+    let mut pending: Vec<PathBuf> = roots.to_vec();
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file() && is_source(&path) {
+                if files.len() == limit {
+                    return (files, true);
+                }
+                files.push(path);
+            }
+        }
+    }
+    (files, false)
+}
+
+/// 文件是否是 C/C++ 源文件或头文件。
+pub fn is_source(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SOURCE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
@@ -0,0 +1,97 @@
+use lsp_proxy::admin::{self, PROMPT, Reply};
+use lsp_proxy::cli::{CliArgs, Command};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::handlers::setup_handlers;
+use lsp_proxy::message::Message;
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+fn output(reply: Reply) -> String {
+    match reply {
+        Reply::Output(output) => output,
+        Reply::Quit => panic!("unexpected quit"),
+    }
+}
+
+#[test]
+fn test_parse_admin_attach() {
+    let args = CliArgs::parse(["admin", "attach", "/tmp/a.sock"].map(String::from)).unwrap();
+    assert_eq!(
+        args.command,
+        Some(Command::Admin {
+            socket: Some(PathBuf::from("/tmp/a.sock"))
+        })
+    );
+    let args = CliArgs::parse(["admin", "attach"].map(String::from)).unwrap();
+    assert_eq!(args.command, Some(Command::Admin { socket: None }));
+    assert!(CliArgs::parse(["admin"].map(String::from)).is_err());
+}
+
+#[tokio::test]
+async fn test_commands() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let mut dispatcher = Dispatcher::new(backend_tx, frontend_tx);
+    setup_handlers(&mut dispatcher);
+    let dispatcher = Arc::new(dispatcher);
+
+    let open = json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": 3, "text": "int a;"}
+    }});
+    dispatcher.handle_from_frontend(open).await.unwrap();
+    let hover = json!({"jsonrpc": "2.0", "id": 7, "method": "textDocument/hover", "params": {
+        "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 4}
+    }});
+    dispatcher.handle_from_frontend(hover).await.unwrap();
+    while backend_rx.try_recv().is_ok() {}
+
+    let status = output(admin::execute(&dispatcher, "status"));
+    assert!(status.contains("后端 default: 未运行"));
+    assert!(status.contains("等待后端响应的请求: 1"));
+    assert!(status.contains("打开的文档: 1"));
+
+    let pending = output(admin::execute(&dispatcher, "pending"));
+    assert_eq!(pending.trim(), "7  textDocument/hover");
+
+    let handlers = output(admin::execute(&dispatcher, "handlers"));
+    assert!(handlers.contains("window/showMessage"));
+
+    let dump = output(admin::execute(&dispatcher, "dump doc file:///a.cpp"));
+    assert_eq!(dump, "file:///a.cpp (cpp, 版本 3)\nint a;");
+    let missing = output(admin::execute(&dispatcher, "dump doc file:///b.cpp"));
+    assert!(missing.starts_with("文档没有打开"));
+
+    let restart = dispatcher.subscribe_restart();
+    output(admin::execute(&dispatcher, "restart-backend"));
+    assert!(restart.has_changed().unwrap());
+
+    assert!(output(admin::execute(&dispatcher, "frobnicate")).starts_with("未知命令"));
+    assert_eq!(admin::execute(&dispatcher, "quit"), Reply::Quit);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_socket_session() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("admin.sock");
+    // 留下的旧套接字文件不影响监听
+    std::fs::write(&path, "").unwrap();
+    let _server = admin::serve(&path, Arc::clone(&dispatcher)).await.unwrap();
+
+    let mut stream = UnixStream::connect(&path).await.unwrap();
+    stream.write_all(b"pending\nquit\n").await.unwrap();
+    let mut transcript = String::new();
+    stream.read_to_string(&mut transcript).await.unwrap();
+    assert_eq!(
+        transcript,
+        format!("{}没有等待响应的请求\n{}", PROMPT, PROMPT)
+    );
+}
@@ -0,0 +1,185 @@
+use lsp_proxy::backend_log::{
+    BackendLog, LogIssue, LogLevel, LogLine, TAIL_LINES, classify, detect_format, parser,
+};
+use lsp_proxy::config::LogFormat;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::shard::Shard;
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+#[test]
+fn test_parse_clangd_line() {
+    let line = LogLine::parse("I[11:01:38.638] clangd version 21.1.0");
+    assert_eq!(line.level, Some(LogLevel::Info));
+    assert_eq!(line.message, "clangd version 21.1.0");
+    assert_eq!(line.issue, None);
+
+    let line =
+        LogLine::parse("E[11:01:39.001] Failed to find compilation database for /src/main.cpp");
+    assert_eq!(line.level, Some(LogLevel::Error));
+    assert_eq!(line.issue, Some(LogIssue::CompilationDatabaseMissing));
+
+    // 不是 clangd 格式的行原样保留
+    let line = LogLine::parse("Stack dump:");
+    assert_eq!(line.level, None);
+    assert_eq!(line.message, "Stack dump:");
+    assert_eq!(line.issue, Some(LogIssue::Crash));
+}
+
+#[test]
+fn test_detect_format() {
+    assert_eq!(detect_format("clangd"), LogFormat::Clangd);
+    assert_eq!(detect_format("/opt/llvm/bin/clangd-18"), LogFormat::Clangd);
+    assert_eq!(
+        detect_format("C:\\tools\\rust-analyzer.exe"),
+        LogFormat::RustAnalyzer
+    );
+    assert_eq!(
+        detect_format("ssh://dev@build//usr/bin/pyright-langserver"),
+        LogFormat::Pyright
+    );
+    // 无法判断的程序按 clangd 处理
+    assert_eq!(detect_format("./wrapper.sh"), LogFormat::Clangd);
+}
+
+#[test]
+fn test_parse_rust_analyzer_line() {
+    let parser = parser(LogFormat::Auto, "rust-analyzer");
+    let line = parser.parse("[ERROR rust_analyzer::main_loop] FetchWorkspaceError: no Cargo.toml");
+    assert_eq!(line.level, Some(LogLevel::Error));
+    assert_eq!(line.message, "FetchWorkspaceError: no Cargo.toml");
+
+    let line = parser
+        .parse("2024-05-01T10:00:00.000Z  WARN rust_analyzer::reload: proc-macro server missing");
+    assert_eq!(line.level, Some(LogLevel::Warning));
+    assert_eq!(line.message, "proc-macro server missing");
+
+    let line = parser.parse("thread 'Worker' panicked at crates/hir/src/lib.rs:10:5:");
+    assert_eq!(line.level, Some(LogLevel::Fatal));
+    assert_eq!(line.issue, Some(LogIssue::Crash));
+
+    // clangd 的格式在 rust-analyzer 中没有意义
+    let line = parser.parse("I[11:01:38.638] clangd version 21.1.0");
+    assert_eq!(line.level, None);
+}
+
+#[test]
+fn test_parse_pyright_line() {
+    let parser = parser(LogFormat::Pyright, "node");
+    let line = parser.parse("[Error - 10:00:00 AM] Import \"numpy\" could not be resolved");
+    assert_eq!(line.level, Some(LogLevel::Error));
+    assert_eq!(line.message, "Import \"numpy\" could not be resolved");
+
+    let line = parser.parse("[Info  - 10:00:01 AM] Found 12 source files");
+    assert_eq!(line.level, Some(LogLevel::Info));
+
+    let line = parser.parse("(node:4242) Warning: Accessing non-existent property");
+    assert_eq!(line.level, Some(LogLevel::Warning));
+    assert_eq!(line.message, "Accessing non-existent property");
+
+    let line = parser.parse("TypeError: Cannot read properties of undefined");
+    assert_eq!(line.level, Some(LogLevel::Error));
+    assert_eq!(
+        line.message,
+        "TypeError: Cannot read properties of undefined"
+    );
+
+    let line = parser.parse("FATAL ERROR: Reached heap limit Allocation failed");
+    assert_eq!(line.level, Some(LogLevel::Fatal));
+    assert_eq!(line.issue, Some(LogIssue::Crash));
+
+    let line = parser.parse("Searching for source files");
+    assert_eq!(line.level, None);
+}
+
+#[test]
+fn test_parse_plain_line() {
+    let line = parser(LogFormat::Plain, "clangd").parse("  E[11:01:39.001] not parsed  ");
+    assert_eq!(line.level, None);
+    assert_eq!(line.message, "E[11:01:39.001] not parsed");
+    assert_eq!(line.issue, None);
+}
+
+#[test]
+fn test_classify() {
+    assert_eq!(
+        classify("Preamble for /a.cpp cannot be reused. Attempting to rebuild it."),
+        Some(LogIssue::PreambleRebuild)
+    );
+    assert_eq!(
+        classify("Could not build a preamble for file /a.cpp version 3"),
+        Some(LogIssue::PreambleFailed)
+    );
+    assert_eq!(
+        classify("Built preamble of size 1024 for file /a.cpp"),
+        None
+    );
+}
+
+#[test]
+fn test_tail_is_bounded_and_counts_issues() {
+    let log = BackendLog::default();
+    for line in 0..TAIL_LINES + 5 {
+        log.record(0, LogLine::parse(&line.to_string()));
+    }
+    log.record(
+        0,
+        LogLine::parse("E[10:00:00.000] Failed to find compilation database for /a.cpp"),
+    );
+    let snapshot = log.snapshot(0, "default", None);
+    assert_eq!(snapshot.lines.len(), TAIL_LINES);
+    assert_eq!(snapshot.lines[0].message, "6");
+    assert_eq!(snapshot.issues[&LogIssue::CompilationDatabaseMissing], 1);
+
+    let snapshot = log.snapshot(0, "default", Some(2));
+    assert_eq!(snapshot.lines.len(), 2);
+    assert_eq!(snapshot.lines[0].message, (TAIL_LINES + 4).to_string());
+    assert!(log.snapshot(1, "other", None).lines.is_empty());
+}
+
+#[tokio::test]
+async fn test_backend_log_request() {
+    let (default_tx, _default_rx) = mpsc::unbounded_channel::<Message>();
+    let (services_tx, _services_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let shards = vec![
+        Shard {
+            name: "default".to_string(),
+            root: None,
+            sender: default_tx,
+        },
+        Shard {
+            name: "services".to_string(),
+            root: Some(PathBuf::from("/repo/services")),
+            sender: services_tx,
+        },
+    ];
+    let dispatcher = Arc::new(Dispatcher::with_shards(shards, frontend_tx));
+    dispatcher.backend_log().record(
+        1,
+        LogLine::parse("W[10:00:00.000] PLEASE submit a bug report"),
+    );
+
+    let request = json!({"jsonrpc": "2.0", "id": 1, "method": "codefuse/backendLog", "params": {
+        "shard": "services"
+    }});
+    dispatcher.handle_from_frontend(request).await.unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(
+        response["result"],
+        json!([{
+            "shard": "services",
+            "lines": [{"level": "warning", "message": "PLEASE submit a bug report", "issue": "crash"}],
+            "issues": {"crash": 1},
+        }])
+    );
+
+    let request = json!({"jsonrpc": "2.0", "id": 2, "method": "codefuse/backendLog"});
+    dispatcher.handle_from_frontend(request).await.unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["result"].as_array().unwrap().len(), 2);
+    assert_eq!(response["result"][0]["lines"], json!([]));
+}
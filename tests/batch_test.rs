@@ -0,0 +1,134 @@
+use lsp_proxy::batch::{BatchTracker, Outgoing, split_batch};
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::time::Duration;
+
+fn response(id: u64) -> Message {
+    Message::new(json!({"jsonrpc": "2.0", "id": id, "result": id}))
+}
+
+#[test]
+fn test_batch_responses_are_combined() {
+    let batches = BatchTracker::new();
+    let items = vec![
+        json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": {}}),
+        json!({"jsonrpc": "2.0", "method": "textDocument/didSave", "params": {}}),
+        json!({"jsonrpc": "2.0", "id": 2, "method": "textDocument/definition", "params": {}}),
+    ];
+    batches.register(&items);
+    assert_eq!(split_batch(Value::Array(items)).len(), 3);
+
+    // 与批量请求无关的消息不受影响
+    let notification =
+        Message::new(json!({"jsonrpc": "2.0", "method": "window/logMessage", "params": {}}));
+    assert_eq!(
+        batches.outgoing(notification.clone()),
+        Outgoing::Send(notification)
+    );
+
+    assert_eq!(batches.outgoing(response(2)), Outgoing::Hold);
+    let Outgoing::Flush(combined) = batches.outgoing(response(1)) else {
+        panic!("批量请求完成后应该发送合并的响应");
+    };
+    let mut ids: Vec<u64> = combined
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["id"].as_u64().unwrap())
+        .collect();
+    ids.sort();
+    assert_eq!(ids, vec![1, 2]);
+
+    // 批量请求完成后，同一个 id 的响应原样发送
+    assert_eq!(batches.outgoing(response(1)), Outgoing::Send(response(1)));
+}
+
+#[test]
+fn test_notification_only_batch_is_not_tracked() {
+    let batches = BatchTracker::new();
+    batches.register(&[json!({"jsonrpc": "2.0", "method": "initialized", "params": {}})]);
+    assert_eq!(batches.outgoing(response(1)), Outgoing::Send(response(1)));
+}
+
+#[test]
+fn test_invalid_elements_get_their_own_errors() {
+    let batches = BatchTracker::new();
+    // 只有无效元素：立即应答每个元素的错误
+    let errors = batches.register(&[json!(1), json!("x")]).unwrap();
+    let errors = errors.as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert!(errors.iter().all(|e| e["error"]["code"] == -32600));
+
+    // 与请求混在一起：错误放在批量响应中，无效元素不会交给调度器
+    let items = vec![
+        json!(1),
+        json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": {}}),
+    ];
+    assert_eq!(batches.register(&items), None);
+    assert_eq!(split_batch(Value::Array(items)).len(), 1);
+    let Outgoing::Flush(combined) = batches.outgoing(response(1)) else {
+        panic!("批量请求完成后应该发送合并的响应");
+    };
+    let combined = combined.as_array().unwrap();
+    assert_eq!(combined.len(), 2);
+    assert_eq!(combined[0]["error"]["code"], -32600);
+    assert_eq!(combined[1]["id"], 1);
+}
+
+#[test]
+fn test_expired_batch_answers_missing_responses() {
+    let batches = BatchTracker::with_timeout(Duration::from_millis(10));
+    assert_eq!(batches.next_deadline(), None);
+    batches.register(&[
+        json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": {}}),
+        json!({"jsonrpc": "2.0", "id": 2, "method": "textDocument/definition", "params": {}}),
+    ]);
+    assert_eq!(batches.outgoing(response(1)), Outgoing::Hold);
+    let deadline = batches.next_deadline().unwrap();
+    assert!(
+        batches
+            .expire(deadline - Duration::from_millis(1))
+            .is_empty()
+    );
+
+    let expired = batches.expire(deadline);
+    assert_eq!(expired.len(), 1);
+    let responses = expired[0].as_array().unwrap();
+    assert_eq!(responses[0]["result"], 1);
+    assert_eq!(responses[1]["id"], 2);
+    assert_eq!(responses[1]["error"]["code"], -32803);
+    assert_eq!(batches.next_deadline(), None);
+}
+
+#[tokio::test]
+async fn test_writer_flushes_expired_batch() {
+    use futures::TryStreamExt;
+    use lsp_proxy::codec::LspCodec;
+    use lsp_proxy::tasks::send_data_frontend;
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+    use tokio_util::codec::FramedRead;
+    use tokio_util::sync::CancellationToken;
+
+    let batches = Arc::new(BatchTracker::with_timeout(Duration::from_millis(50)));
+    batches.register(&[json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/hover"})]);
+    let (writer, reader) = tokio::io::duplex(4096);
+    let (_tx, rx) = mpsc::unbounded_channel::<Message>();
+    tokio::spawn(send_data_frontend(
+        writer,
+        rx,
+        batches,
+        CancellationToken::new(),
+        None,
+    ));
+
+    // 后端一直没有响应，超时后写出错误
+    let mut reader = FramedRead::new(reader, LspCodec::default());
+    let responses = tokio::time::timeout(Duration::from_secs(5), reader.try_next())
+        .await
+        .expect("超时的批量请求没有发送")
+        .unwrap()
+        .unwrap();
+    assert_eq!(responses[0]["id"], 1);
+    assert_eq!(responses[0]["error"]["code"], -32803);
+}
@@ -0,0 +1,79 @@
+use lsp_proxy::bench::{BenchCommand, MAX_RATE, Scenario, parse_mix, percentile};
+use lsp_proxy::cli::{CliArgs, Command};
+use std::time::Duration;
+
+#[test]
+fn test_parse_bench_command() {
+    let args = CliArgs::parse(
+        [
+            "--backend",
+            "mock",
+            "bench",
+            "--rate",
+            "50",
+            "--mix",
+            "hover=1,definition=3",
+        ]
+        .map(String::from),
+    )
+    .unwrap();
+    let Some(Command::Bench(command)) = args.command else {
+        panic!("应该解析为 bench 子命令");
+    };
+    assert_eq!(
+        command,
+        BenchCommand {
+            scenario: "completion-storm".to_string(),
+            rate: Some(50),
+            duration_secs: None,
+            mix: Some("hover=1,definition=3".to_string()),
+            mock: true,
+        }
+    );
+
+    let scenario = Scenario::from_command(&command).unwrap();
+    assert_eq!(scenario.rate, 50);
+    assert_eq!(scenario.duration, Duration::from_secs(10));
+    let methods: Vec<&str> = (0..4).map(|i| scenario.method_at(i)).collect();
+    assert_eq!(
+        methods,
+        [
+            "textDocument/hover",
+            "textDocument/definition",
+            "textDocument/definition",
+            "textDocument/definition"
+        ]
+    );
+
+    assert!(parse_mix("hover").is_err());
+    assert!(parse_mix("hover=0").is_err());
+    assert!(Scenario::builtin("unknown").is_none());
+
+    // 速率必须在 1 到 MAX_RATE 之间
+    for (rate, valid) in [
+        (0, false),
+        (MAX_RATE, true),
+        (MAX_RATE + 1, false),
+        (u32::MAX, false),
+    ] {
+        let command = BenchCommand {
+            rate: Some(rate),
+            ..command.clone()
+        };
+        assert_eq!(
+            Scenario::from_command(&command).is_ok(),
+            valid,
+            "rate {}",
+            rate
+        );
+    }
+}
+
+#[test]
+fn test_percentile() {
+    let latencies: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+    assert_eq!(percentile(&latencies, 50.0), Duration::from_millis(50));
+    assert_eq!(percentile(&latencies, 99.0), Duration::from_millis(99));
+    assert_eq!(percentile(&latencies, 100.0), Duration::from_millis(100));
+    assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+}
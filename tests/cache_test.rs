@@ -0,0 +1,75 @@
+use lsp_proxy::cache::{self, format_size};
+use lsp_proxy::cli::{CacheCommand, CliArgs, Command};
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn test_parse_cache_commands() {
+    let args = CliArgs::parse(["cache", "size"].map(String::from)).unwrap();
+    assert_eq!(
+        args.command,
+        Some(Command::Cache(CacheCommand::Size { workspaces: vec![] }))
+    );
+
+    let args = CliArgs::parse(
+        [
+            "--config",
+            "x.toml",
+            "cache",
+            "prune",
+            "--older-than",
+            "7",
+            "/repo",
+        ]
+        .map(String::from),
+    )
+    .unwrap();
+    assert_eq!(args.config, Some(PathBuf::from("x.toml")));
+    assert_eq!(
+        args.command,
+        Some(Command::Cache(CacheCommand::Prune {
+            workspaces: vec![PathBuf::from("/repo")],
+            older_than_days: 7,
+        }))
+    );
+
+    assert!(CliArgs::parse(["cache", "size", "--older-than", "7"].map(String::from)).is_err());
+}
+
+#[test]
+fn test_measure_and_prune_index() {
+    let root = tempfile::tempdir().unwrap();
+    let index = root.path().join(".cache/clangd/index");
+    std::fs::create_dir_all(&index).unwrap();
+    std::fs::write(index.join("fresh.cpp.1234.idx"), vec![0u8; 100]).unwrap();
+    std::fs::write(index.join("stale.cpp.5678.idx"), vec![0u8; 50]).unwrap();
+    File::options()
+        .write(true)
+        .open(index.join("stale.cpp.5678.idx"))
+        .unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(90 * 24 * 60 * 60))
+        .unwrap();
+
+    let dirs = cache::index_dirs(&[root.path().to_path_buf()], None);
+    assert_eq!(dirs, vec![index.clone()]);
+    // 同一个工作区出现多次，或者缓存目录就是工作区的 .cache 时，每个目录只统计一次
+    let other = tempfile::tempdir().unwrap();
+    let workspaces = [
+        root.path().to_path_buf(),
+        other.path().to_path_buf(),
+        root.path().to_path_buf(),
+    ];
+    let dirs = cache::index_dirs(&workspaces, Some(&root.path().join(".cache")));
+    assert_eq!(dirs, vec![index.clone()]);
+
+    let stats = cache::measure(&index).unwrap();
+    assert_eq!((stats.files, stats.bytes), (2, 150));
+
+    let removed = cache::prune(&index, Duration::from_secs(30 * 24 * 60 * 60)).unwrap();
+    assert_eq!(removed, (1, 50));
+    assert!(index.join("fresh.cpp.1234.idx").exists());
+
+    assert_eq!(format_size(150), "150 B");
+    assert_eq!(format_size(3 * 1024 * 1024), "3.0 MiB");
+}
@@ -0,0 +1,72 @@
+use lsp_proxy::capabilities::{BackendCapabilities, parse_clangd_version};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+#[test]
+fn test_supports_reads_standard_and_extension_capabilities() {
+    let capabilities = BackendCapabilities::new();
+    assert_eq!(capabilities.supports("textDocument/hover"), None);
+
+    capabilities.record(&json!({
+        "capabilities": {
+            "hoverProvider": true,
+            "documentLinkProvider": false,
+            "inlayHintProvider": {"resolveProvider": false},
+            "astProvider": true,
+        },
+        "serverInfo": {"name": "clangd", "version": "clangd version 17.0.6 (https://github.com/llvm/llvm-project) linux x86_64"},
+    }));
+    assert!(capabilities.is_known());
+    assert_eq!(capabilities.supports("textDocument/hover"), Some(true));
+    assert_eq!(
+        capabilities.supports("textDocument/documentLink"),
+        Some(false)
+    );
+    assert_eq!(capabilities.supports("textDocument/inlayHint"), Some(true));
+    assert_eq!(
+        capabilities.supports("textDocument/definition"),
+        Some(false)
+    );
+    assert_eq!(capabilities.supports("textDocument/ast"), Some(true));
+    assert_eq!(capabilities.supports("$/unknown"), None);
+    assert_eq!(capabilities.clangd_version(), Some((17, 0, 6)));
+}
+
+#[test]
+fn test_parse_clangd_version() {
+    assert_eq!(
+        parse_clangd_version("clangd version 12.0.0-3ubuntu1"),
+        Some((12, 0, 0))
+    );
+    assert_eq!(parse_clangd_version("clangd version 18"), Some((18, 0, 0)));
+    assert_eq!(parse_clangd_version("trunk"), None);
+}
+
+#[tokio::test]
+async fn test_dispatcher_records_initialize_result() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+
+    let initialize =
+        json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"capabilities": {}}});
+    dispatcher.handle_from_frontend(initialize).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    let response = json!({"jsonrpc": "2.0", "id": 1, "result": {
+        "capabilities": {"hoverProvider": true},
+        "serverInfo": {"name": "clangd", "version": "clangd version 15.0.7"},
+    }});
+    dispatcher.handle_from_backend(response).await.unwrap();
+    frontend_rx.recv().await.unwrap();
+
+    let capabilities = dispatcher.backend_capabilities();
+    assert_eq!(capabilities.supports("textDocument/hover"), Some(true));
+    assert_eq!(
+        capabilities.supports("textDocument/documentLink"),
+        Some(false)
+    );
+    assert_eq!(capabilities.clangd_version(), Some((15, 0, 7)));
+}
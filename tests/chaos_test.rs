@@ -0,0 +1,86 @@
+mod common;
+
+use common::Harness;
+use common::chaos::ChaosConfig;
+use futures::future::join_all;
+use lsp_proxy::mock_lsp_server::MockScript;
+use serde_json::json;
+use std::time::Duration;
+use tokio::time::Instant;
+
+fn hover_params(line: u32) -> serde_json::Value {
+    json!({
+        "textDocument": {"uri": "file:///work/main.cpp"},
+        "position": {"line": line, "character": 0}
+    })
+}
+
+fn script() -> MockScript {
+    MockScript::new().respond("textDocument/hover", json!({"contents": "int x"}))
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_dropped_responses_time_out() {
+    let dropping = ChaosConfig {
+        drop: 1.0,
+        ..Default::default()
+    };
+    let harness = Harness::start_with_chaos(script(), ChaosConfig::default(), dropping).await;
+
+    let start = Instant::now();
+    let error = harness
+        .dispatcher()
+        .request_backend(0, "textDocument/hover", hover_params(0))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("超时"), "{}", error);
+    assert_eq!(start.elapsed(), Duration::from_secs(30));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_recovers_after_corrupted_and_truncated_messages() {
+    let faults = ChaosConfig {
+        delay: 0.3,
+        reorder: 0.2,
+        truncate: 0.15,
+        corrupt: 0.15,
+        ..Default::default()
+    };
+    let from_backend = ChaosConfig { seed: 42, ..faults };
+    let harness = Harness::start_with_chaos(script(), faults, from_backend).await;
+
+    let dispatcher = harness.dispatcher();
+    let results = join_all(
+        (0..40).map(|line| dispatcher.request_backend(0, "textDocument/hover", hover_params(line))),
+    )
+    .await;
+    let succeeded = results.iter().filter(|r| r.is_ok()).count();
+    assert!(
+        succeeded > 0 && succeeded < results.len(),
+        "{} 个成功",
+        succeeded
+    );
+    for error in results.iter().filter_map(|r| r.as_ref().err()) {
+        assert!(error.to_string().contains("超时"), "{}", error);
+    }
+
+    // 故障停止后，被截断的消息最多再影响一条消息，之后的请求恢复正常
+    harness.set_chaos(false);
+    let mut recovered = false;
+    for line in 0..3 {
+        if dispatcher
+            .request_backend(0, "textDocument/hover", hover_params(line))
+            .await
+            .is_ok()
+        {
+            recovered = true;
+            break;
+        }
+    }
+    assert!(recovered);
+    let result = dispatcher
+        .request_backend(0, "textDocument/hover", hover_params(0))
+        .await
+        .unwrap();
+    assert_eq!(result["contents"], "int x");
+}
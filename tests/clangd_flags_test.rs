@@ -0,0 +1,100 @@
+use lsp_proxy::clangd_flags::{launch_flags, validate};
+use lsp_proxy::config::{ClangdFlagsConfig, Config, HeaderInsertion, PchStorage};
+
+fn flags() -> ClangdFlagsConfig {
+    ClangdFlagsConfig {
+        background_index: Some(true),
+        jobs: Some(4),
+        pch_storage: Some(PchStorage::Memory),
+        query_driver: vec![
+            "/usr/bin/g++*".to_string(),
+            "**/arm-none-eabi-*".to_string(),
+        ],
+        header_insertion: Some(HeaderInsertion::Never),
+        ..ClangdFlagsConfig::default()
+    }
+}
+
+#[test]
+fn test_parse_clangd_flags() {
+    let config = Config::parse(
+        r#"
+[backend.clangd]
+background_index = false
+jobs = 8
+pch_storage = "disk"
+query_driver = ["/opt/gcc/bin/*"]
+header_insertion = "iwyu"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.backend.clangd.background_index, Some(false));
+    assert_eq!(config.backend.clangd.jobs, Some(8));
+    assert_eq!(config.backend.clangd.pch_storage, Some(PchStorage::Disk));
+    assert_eq!(config.backend.clangd.query_driver, vec!["/opt/gcc/bin/*"]);
+    assert_eq!(
+        config.backend.clangd.header_insertion,
+        Some(HeaderInsertion::Iwyu)
+    );
+
+    // 没有设置时不生成任何参数
+    let config = Config::parse("").unwrap();
+    assert_eq!(config.backend.clangd, ClangdFlagsConfig::default());
+    assert!(launch_flags(&config.backend.clangd, None).args.is_empty());
+}
+
+#[test]
+fn test_invalid_clangd_flags() {
+    assert!(Config::parse("[backend.clangd]\njobs = 0").is_err());
+    assert!(Config::parse("[backend.clangd]\nquery_driver = [\"\"]").is_err());
+    assert!(Config::parse("[backend.clangd]\npch_storage = \"ram\"").is_err());
+
+    // 同一个参数不能同时出现在 args 中
+    let error = Config::parse(
+        r#"
+[backend]
+args = ["-j4"]
+
+[backend.clangd]
+jobs = 2
+"#,
+    )
+    .unwrap_err();
+    assert!(format!("{:#}", error).contains("-j4"));
+    assert!(validate(&flags(), &["--background-index".to_string()]).is_err());
+    assert!(validate(&flags(), &["--log=verbose".to_string()]).is_ok());
+    // 名字相同前缀的参数不算重复
+    assert!(validate(&flags(), &["--background-index-priority=low".to_string()]).is_ok());
+}
+
+#[test]
+fn test_launch_flags() {
+    let flags = launch_flags(&flags(), Some((18, 1, 3)));
+    assert_eq!(
+        flags.args,
+        vec![
+            "--background-index=true",
+            "-j=4",
+            "--pch-storage=memory",
+            "--query-driver=/usr/bin/g++*,**/arm-none-eabi-*",
+            "--header-insertion=never",
+        ]
+    );
+    assert!(flags.omitted.is_empty());
+}
+
+#[test]
+fn test_launch_flags_gated_by_version() {
+    // clangd 8 还不支持 --query-driver 和 --header-insertion
+    let launch = launch_flags(&flags(), Some((8, 0, 1)));
+    assert_eq!(
+        launch.args,
+        vec!["--background-index=true", "-j=4", "--pch-storage=memory"]
+    );
+    assert_eq!(launch.omitted, vec!["--query-driver", "--header-insertion"]);
+
+    // 版本未知时不省略任何参数
+    let launch = launch_flags(&flags(), None);
+    assert_eq!(launch.args.len(), 5);
+    assert!(launch.omitted.is_empty());
+}
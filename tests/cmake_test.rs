@@ -0,0 +1,145 @@
+use lsp_proxy::cli::{CliArgs, Command};
+use lsp_proxy::cmake;
+use lsp_proxy::config::CmakeConfig;
+use std::path::{Path, PathBuf};
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn test_parse_setup_command() {
+    let parsed = CliArgs::parse(args(&["setup", "--build-dir", "out/debug", "proj"])).unwrap();
+    let Some(Command::Setup(command)) = parsed.command else {
+        panic!("应该解析为 setup 子命令");
+    };
+    assert_eq!(command.build_dir, Some(PathBuf::from("out/debug")));
+    assert_eq!(command.root, Some(PathBuf::from("proj")));
+    assert!(CliArgs::parse(args(&["setup", "--generator"])).is_err());
+
+    let config = CmakeConfig {
+        args: vec!["-GNinja".to_string()],
+        ..CmakeConfig::default()
+    };
+    assert_eq!(
+        cmake::configure_args(&config, Path::new("/repo")),
+        [
+            "-S",
+            "/repo",
+            "-B",
+            "/repo/build",
+            "-DCMAKE_EXPORT_COMPILE_COMMANDS=ON",
+            "-GNinja"
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_generate_links_database_into_root() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("proj");
+    std::fs::create_dir(&root).unwrap();
+    // 假的 cmake 只在 -B 指定的目录中写出编译数据库
+    let fake = dir.path().join("cmake");
+    std::fs::write(
+        &fake,
+        "#!/bin/sh\nmkdir -p \"$4\" && echo '[]' > \"$4/compile_commands.json\"\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&fake, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let config = CmakeConfig {
+        command: fake.display().to_string(),
+        build_dir: PathBuf::from("out"),
+        ..CmakeConfig::default()
+    };
+
+    assert!(cmake::generate(&config, &root).is_err());
+    std::fs::write(root.join("CMakeLists.txt"), "project(demo)\n").unwrap();
+    let database = cmake::generate(&config, &root).unwrap();
+    assert_eq!(database, root.join("out/compile_commands.json"));
+    let link = root.join(cmake::DATABASE);
+    assert_eq!(std::fs::read_link(&link).unwrap(), database);
+
+    // 重新生成时替换链接，但不覆盖用户自己的文件
+    cmake::generate(&config, &root).unwrap();
+    std::fs::remove_file(&link).unwrap();
+    std::fs::write(&link, "[{}]").unwrap();
+    cmake::generate(&config, &root).unwrap();
+    assert_eq!(std::fs::read_to_string(&link).unwrap(), "[{}]");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_generate_command_reports_progress_and_restarts() {
+    use lsp_proxy::config::Config;
+    use lsp_proxy::dispatcher::Dispatcher;
+    use lsp_proxy::message::Message;
+    use serde_json::json;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("proj");
+    std::fs::create_dir(&root).unwrap();
+    std::fs::write(root.join("CMakeLists.txt"), "project(demo)\n").unwrap();
+    let fake = dir.path().join("cmake");
+    std::fs::write(
+        &fake,
+        "#!/bin/sh\nmkdir -p \"$4\" && echo '[]' > \"$4/compile_commands.json\"\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&fake, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let config = Config {
+        cmake: CmakeConfig {
+            command: fake.display().to_string(),
+            ..CmakeConfig::default()
+        },
+        ..Config::default()
+    };
+
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    let restart = dispatcher.subscribe_restart();
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": 1, "method": "initialize",
+            "params": {"capabilities": {"window": {"workDoneProgress": true}}}}))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+
+    let command = Arc::clone(&dispatcher);
+    let arguments = json!([root]);
+    let execute = tokio::spawn(async move {
+        command
+            .handle_from_frontend(
+                json!({"jsonrpc": "2.0", "id": 2, "method": "workspace/executeCommand",
+                "params": {"command": "codefuse.generateCompileCommands", "arguments": arguments}}),
+            )
+            .await
+            .unwrap();
+    });
+    let create = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(create["method"], "window/workDoneProgress/create");
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": create["id"], "result": null}))
+        .await
+        .unwrap();
+    execute.await.unwrap();
+
+    let begin = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(begin["params"]["value"]["kind"], "begin");
+    let end = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(end["params"]["value"]["message"], "完成");
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["id"], 2);
+    assert_eq!(
+        response["result"]["path"],
+        json!(root.join("build/compile_commands.json"))
+    );
+    assert!(restart.has_changed().unwrap());
+}
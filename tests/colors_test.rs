@@ -0,0 +1,98 @@
+use lsp_proxy::colors::{self, Style};
+use lsp_proxy::config::Config;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::{Color, Position, Range};
+
+fn color(red: f32, green: f32, blue: f32, alpha: f32) -> Color {
+    Color {
+        red,
+        green,
+        blue,
+        alpha,
+    }
+}
+
+#[test]
+fn test_find_colors_in_source() {
+    let text = "const char *bg = \"#ff0000\";\n\
+                lv_color_t fg = lv_color_hex(0x00FF00);\n\
+                uint32_t argb = 0x800000ff;\n\
+                auto css = \"rgba(255, 255, 255, 0.5) rgb(300, 0, 0)\";\n\
+                int mask = 0xffff; // #include\n";
+    let found = colors::document_colors(text);
+    assert_eq!(found.len(), 4);
+    assert_eq!(found[0].color, color(1.0, 0.0, 0.0, 1.0));
+    assert_eq!(
+        found[0].range,
+        Range::new(Position::new(0, 18), Position::new(0, 25))
+    );
+    assert_eq!(found[1].color, color(0.0, 1.0, 0.0, 1.0));
+    // 八位的整数透明度在最前
+    assert_eq!(found[2].color, color(0.0, 0.0, 1.0, 128.0 / 255.0));
+    assert_eq!(found[3].color, color(1.0, 1.0, 1.0, 0.5));
+}
+
+#[test]
+fn test_presentations_keep_original_style() {
+    let text = "lv_color_hex(0x00FF00);\n";
+    let range = Range::new(Position::new(0, 13), Position::new(0, 21));
+    let labels: Vec<String> = colors::presentations(text, &color(1.0, 0.0, 0.0, 1.0), range)
+        .into_iter()
+        .map(|presentation| presentation.label)
+        .collect();
+    assert_eq!(labels, ["0xFF0000", "#ff0000", "rgb(255, 0, 0)"]);
+
+    let translucent = color(0.0, 0.0, 1.0, 0.5);
+    assert_eq!(Style::Hash.format(&translucent), "#0000ff80");
+    assert_eq!(
+        Style::Integer { upper: false }.format(&translucent),
+        "0x800000ff"
+    );
+    assert_eq!(Style::Function.format(&translucent), "rgba(0, 0, 255, 0.5)");
+}
+
+#[tokio::test]
+async fn test_dispatcher_answers_colors_without_backend_support() {
+    let mut config = Config::default();
+    config.colors.fallback = true;
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": {"uri": "file:///ui.c", "languageId": "c", "version": 1,
+                                 "text": "int c = 0x0000ff;\n"}
+            }}),
+        )
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": 1,
+            "method": "textDocument/documentColor",
+            "params": {"textDocument": {"uri": "file:///ui.c"}}}))
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    let information = response["result"][0].clone();
+    assert_eq!(information["color"]["blue"], 1.0);
+
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": 2,
+            "method": "textDocument/colorPresentation",
+            "params": {"textDocument": {"uri": "file:///ui.c"},
+                       "color": {"red": 1.0, "green": 1.0, "blue": 0.0, "alpha": 1.0},
+                       "range": information["range"]}}))
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["result"][0]["label"], "0xffff00");
+    assert_eq!(response["result"][0]["textEdit"]["newText"], "0xffff00");
+    assert!(backend_rx.try_recv().is_err());
+}
@@ -0,0 +1,75 @@
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use lsp_proxy::commands;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use tower_lsp::lsp_types::{ExecuteCommandOptions, ServerCapabilities};
+
+fn parse_message(message: &Message) -> Value {
+    message.body().clone()
+}
+
+fn execute(id: u64, command: &str, arguments: Value) -> Value {
+    json!({
+        "jsonrpc": "2.0", "id": id, "method": "workspace/executeCommand",
+        "params": {"command": command, "arguments": arguments}
+    })
+}
+
+#[test]
+fn test_advertise_merges_backend_commands() {
+    let mut capabilities = ServerCapabilities {
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: vec!["clangd.applyFix".to_string()],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    commands::advertise(&mut capabilities);
+    let advertised = capabilities.execute_command_provider.unwrap().commands;
+    assert_eq!(advertised[0], "clangd.applyFix");
+    for command in commands::PROXY_COMMANDS {
+        assert!(advertised.iter().any(|c| c == command));
+    }
+}
+
+#[tokio::test]
+async fn test_proxy_commands_are_handled_locally() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let restart = dispatcher.subscribe_restart();
+
+    dispatcher
+        .handle_from_frontend(execute(1, commands::RESTART_BACKEND, json!([])))
+        .await
+        .unwrap();
+    assert_eq!(parse_message(&frontend_rx.recv().await.unwrap())["id"], 1);
+    assert!(restart.has_changed().unwrap());
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("trace.ndjson");
+    dispatcher
+        .handle_from_frontend(execute(2, commands::DUMP_TRACE, json!([path])))
+        .await
+        .unwrap();
+    let response = parse_message(&frontend_rx.recv().await.unwrap());
+    assert_eq!(response["result"]["messages"], 2);
+    let trace = std::fs::read_to_string(&path).unwrap();
+    let first: Value = serde_json::from_str(trace.lines().next().unwrap()).unwrap();
+    assert_eq!(first["direction"], "frontend");
+    assert_eq!(
+        first["message"]["params"]["command"],
+        commands::RESTART_BACKEND
+    );
+
+    // clangd 自己的命令照常转发
+    dispatcher
+        .handle_from_frontend(execute(3, "clangd.applyFix", json!([])))
+        .await
+        .unwrap();
+    assert_eq!(parse_message(&backend_rx.recv().await.unwrap())["id"], 3);
+    assert!(backend_rx.try_recv().is_err());
+}
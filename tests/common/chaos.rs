@@ -0,0 +1,119 @@
+//! 故障注入：在消息通道中按概率延迟、丢弃、调换、截断或损坏消息，用来验证代理的恢复路径。
+//!
+//! 随机数由固定的种子生成，同样的配置每次注入同样的故障。
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+/// 每种故障发生的概率（0.0 到 1.0）。
+#[derive(Debug, Clone, Copy)]
+pub struct ChaosConfig {
+    pub seed: u64,
+    pub delay: f64,
+    pub max_delay: Duration,
+    pub drop: f64,
+    /// 把消息放到下一条消息之后发送
+    pub reorder: f64,
+    /// 截掉消息体的后半部分，但保留原来的 `Content-Length`
+    pub truncate: f64,
+    /// 破坏消息体，使它不再是合法的 JSON
+    pub corrupt: f64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0x5eed,
+            delay: 0.0,
+            max_delay: Duration::from_millis(100),
+            drop: 0.0,
+            reorder: 0.0,
+            truncate: 0.0,
+            corrupt: 0.0,
+        }
+    }
+}
+
+/// xorshift64*，测试只需要可重复，不需要高质量的随机数。
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    /// 返回 [0, 1) 之间的数。
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        self.next() < probability
+    }
+}
+
+/// 在 `downstream` 前面插入故障注入，返回新的发送端。`enabled` 为假时消息原样通过。
+pub fn interpose(
+    config: ChaosConfig,
+    enabled: Arc<AtomicBool>,
+    downstream: UnboundedSender<String>,
+) -> UnboundedSender<String> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    tokio::spawn(async move {
+        let mut rng = Rng::new(config.seed);
+        let mut held: Option<String> = None;
+        while let Some(mut message) = rx.recv().await {
+            if enabled.load(Ordering::Relaxed) {
+                if rng.chance(config.drop) {
+                    continue;
+                }
+                if rng.chance(config.delay) {
+                    tokio::time::sleep(config.max_delay.mul_f64(rng.next())).await;
+                }
+                if rng.chance(config.truncate) {
+                    message = truncate(&message);
+                } else if rng.chance(config.corrupt) {
+                    message = corrupt(&message);
+                }
+                if held.is_none() && rng.chance(config.reorder) {
+                    held = Some(message);
+                    continue;
+                }
+            }
+            if downstream.send(message).is_err() {
+                return;
+            }
+            if let Some(message) = held.take()
+                && downstream.send(message).is_err()
+            {
+                return;
+            }
+        }
+    });
+    tx
+}
+
+fn split(message: &str) -> (&str, &str) {
+    let index = message.find("\r\n\r\n").map_or(0, |i| i + 4);
+    message.split_at(index)
+}
+
+fn truncate(message: &str) -> String {
+    let (header, body) = split(message);
+    let mut end = body.len() / 2;
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", header, &body[..end])
+}
+
+fn corrupt(message: &str) -> String {
+    let (header, body) = split(message);
+    // 长度不变，消息边界保持完整
+    format!("{}#{}", header, body.get(1..).unwrap_or(""))
+}
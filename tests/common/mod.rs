@@ -0,0 +1,218 @@
+//! 进程内的端到端测试工具：调度器的前端是测试代码，后端是模拟后端，不需要 clangd。
+//!
+//! 测试在 `start_paused` 的运行时中执行，模拟后端的延迟由 tokio 的虚拟时钟推进，结果是确定的。
+
+#![allow(dead_code)]
+
+pub mod chaos;
+
+use futures::TryStreamExt;
+use lsp_proxy::codec::LspCodec;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::handlers::setup_handlers;
+use lsp_proxy::message::Message;
+use lsp_proxy::mock_lsp_server::{MockLspServer, MockScript};
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio_util::codec::FramedRead;
+
+use chaos::ChaosConfig;
+
+/// 等待消息的最长时间（虚拟时钟）。
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 夹具目录。
+pub fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// 与 `tests/fixtures/golden/<name>.json` 比较；设置 `UPDATE_GOLDEN=1` 时改为写入。
+pub fn assert_golden(name: &str, actual: &Value) {
+    let path = fixture("golden").join(format!("{}.json", name));
+    let actual_text = serde_json::to_string_pretty(actual).unwrap() + "\n";
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, actual_text).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "无法读取 {}（用 UPDATE_GOLDEN=1 生成）: {}",
+            path.display(),
+            e
+        )
+    });
+    let expected: Value = serde_json::from_str(&expected).unwrap();
+    assert_eq!(
+        &expected,
+        actual,
+        "与 {} 不一致，实际结果:\n{}",
+        path.display(),
+        actual_text
+    );
+}
+
+/// 连接到模拟后端的调度器，测试代码扮演前端。
+pub struct Harness {
+    dispatcher: Arc<Dispatcher>,
+    frontend_rx: mpsc::UnboundedReceiver<Message>,
+    /// 收到但还没被取走的消息
+    inbox: VecDeque<Value>,
+    next_id: u64,
+    chaos: Arc<AtomicBool>,
+}
+
+impl Harness {
+    /// 启动模拟后端并连接调度器，处理器与代理运行时相同。
+    pub async fn start(script: MockScript) -> Self {
+        Self::start_with_chaos(script, ChaosConfig::default(), ChaosConfig::default()).await
+    }
+
+    /// 启动模拟后端，在调度器与后端之间的两个方向上注入故障。
+    ///
+    /// `to_backend` 作用于发往后端的消息，`from_backend` 作用于后端发给代理的消息，
+    /// 后者经过代理读取后端输出的同一个解析函数。
+    pub async fn start_with_chaos(
+        script: MockScript,
+        to_backend: ChaosConfig,
+        from_backend: ChaosConfig,
+    ) -> Self {
+        let enabled = Arc::new(AtomicBool::new(true));
+        let (backend_tx, backend_rx) = mpsc::unbounded_channel::<Message>();
+        let (frontend_tx, frontend_rx) = mpsc::unbounded_channel::<Message>();
+        let mut dispatcher = Dispatcher::new(backend_tx, frontend_tx);
+        setup_handlers(&mut dispatcher);
+        let dispatcher = Arc::new(dispatcher);
+
+        // 调度器 → 故障注入 → 模拟后端
+        let (mock_side, server_side) = tokio::io::duplex(1 << 20);
+        let (server_read, server_write) = tokio::io::split(server_side);
+        tokio::spawn(MockLspServer::new(script).serve(server_read, server_write));
+        let (mock_read, mock_write) = tokio::io::split(mock_side);
+        let to_mock = chaos::interpose(to_backend, Arc::clone(&enabled), pump(mock_write));
+        forward(backend_rx, to_mock);
+
+        // 模拟后端 → 故障注入 → 代理的消息解析
+        let (proxy_write, proxy_read) = tokio::io::duplex(1 << 20);
+        let to_proxy = chaos::interpose(from_backend, Arc::clone(&enabled), pump(proxy_write));
+        tokio::spawn(async move {
+            let mut reader = FramedRead::new(mock_read, LspCodec::default());
+            while let Ok(Some(rpc)) = reader.try_next().await {
+                let message = Dispatcher::format_lsp_message(&rpc).unwrap();
+                if to_proxy.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        let backend_dispatcher = Arc::clone(&dispatcher);
+        tokio::spawn(async move {
+            let mut reader = FramedRead::new(proxy_read, LspCodec::default());
+            while let Ok(Some(rpc)) = reader.try_next().await {
+                backend_dispatcher.handle_from_shard(0, rpc).await.unwrap();
+            }
+        });
+
+        Self {
+            dispatcher,
+            frontend_rx,
+            inbox: VecDeque::new(),
+            next_id: 1,
+            chaos: enabled,
+        }
+    }
+
+    /// 调度器，用于直接调用代理的内部接口。
+    pub fn dispatcher(&self) -> &Arc<Dispatcher> {
+        &self.dispatcher
+    }
+
+    /// 打开或关闭故障注入。
+    pub fn set_chaos(&self, enabled: bool) {
+        self.chaos.store(enabled, Ordering::Relaxed);
+    }
+
+    /// 完成 `initialize` 握手，返回 `initialize` 的结果。
+    pub async fn initialize(&mut self) -> Value {
+        let result = self
+            .request(
+                "initialize",
+                json!({"processId": null, "rootUri": null, "capabilities": {}}),
+            )
+            .await;
+        self.notify("initialized", json!({})).await;
+        result["result"].clone()
+    }
+
+    /// 发送一个请求并等待对应的响应，期间收到的其他消息留给后续读取。
+    pub async fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}))
+            .await;
+        self.receive(|rpc| rpc.get("method").is_none() && rpc["id"] == id)
+            .await
+    }
+
+    /// 发送一个通知。
+    pub async fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({"jsonrpc": "2.0", "method": method, "params": params}))
+            .await;
+    }
+
+    /// 等待发往前端的指定方法的通知。
+    pub async fn notification(&mut self, method: &str) -> Value {
+        self.receive(|rpc| rpc["method"] == method).await
+    }
+
+    async fn send(&self, rpc: Value) {
+        self.dispatcher.handle_from_frontend(rpc).await.unwrap();
+    }
+
+    async fn receive(&mut self, matches: impl Fn(&Value) -> bool) -> Value {
+        if let Some(index) = self.inbox.iter().position(&matches) {
+            return self.inbox.remove(index).unwrap();
+        }
+        loop {
+            let message = tokio::time::timeout(RECEIVE_TIMEOUT, self.frontend_rx.recv())
+                .await
+                .expect("等待消息超时")
+                .expect("调度器已关闭");
+            let rpc = message.into_body();
+            if matches(&rpc) {
+                return rpc;
+            }
+            self.inbox.push_back(rpc);
+        }
+    }
+}
+
+/// 把通道中的消息写入 `writer`。
+fn pump<W: AsyncWrite + Unpin + Send + 'static>(mut writer: W) -> mpsc::UnboundedSender<String> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            if writer.write_all(message.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+    tx
+}
+
+/// 把调度器发出的消息编码后转发到另一个通道。
+fn forward(mut rx: mpsc::UnboundedReceiver<Message>, tx: mpsc::UnboundedSender<String>) {
+    tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            if tx.send(message.to_lsp_string().unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+}
@@ -0,0 +1,122 @@
+use lsp_proxy::capabilities::BackendCapabilities;
+use lsp_proxy::compat::{
+    LEGACY_INLAY_HINTS, POSITION_ENCODING, active_shims, fill_position_encoding, is_active,
+    legacy_inlay_hints_to_standard,
+};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::handlers::setup_handlers;
+use lsp_proxy::message::Message;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+#[test]
+fn test_active_shims_follow_version() {
+    assert!(active_shims(None).is_empty());
+    assert!(active_shims(Some((17, 0, 6))).is_empty());
+
+    let names = |version| {
+        active_shims(Some(version))
+            .iter()
+            .map(|shim| shim.name)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        names((14, 0, 6)),
+        vec![LEGACY_INLAY_HINTS, POSITION_ENCODING]
+    );
+    assert_eq!(names((13, 0, 1)), vec![POSITION_ENCODING]);
+    assert!(!is_active(LEGACY_INLAY_HINTS, Some((15, 0, 0))));
+}
+
+#[test]
+fn test_legacy_inlay_hints_to_standard() {
+    let legacy = json!([
+        {"kind": "parameter", "label": "x:", "range": {
+            "start": {"line": 1, "character": 4}, "end": {"line": 1, "character": 5}
+        }},
+        {"kind": "type", "label": ": int", "range": {
+            "start": {"line": 2, "character": 5}, "end": {"line": 2, "character": 6}
+        }},
+        {"kind": "designator", "label": ".a=", "position": {"line": 3, "character": 1},
+         "range": {"start": {"line": 3, "character": 1}, "end": {"line": 3, "character": 2}}},
+        {"kind": 1, "label": "standard", "position": {"line": 4, "character": 0}},
+    ]);
+    assert_eq!(
+        legacy_inlay_hints_to_standard(&legacy),
+        json!([
+            {"kind": 2, "label": "x:", "position": {"line": 1, "character": 4}},
+            {"kind": 1, "label": ": int", "position": {"line": 2, "character": 6}},
+            {"label": ".a=", "position": {"line": 3, "character": 1}},
+            {"kind": 1, "label": "standard", "position": {"line": 4, "character": 0}},
+        ])
+    );
+    assert_eq!(legacy_inlay_hints_to_standard(&json!(null)), json!(null));
+}
+
+#[test]
+fn test_fill_position_encoding_keeps_existing_field() {
+    let mut result = json!({"capabilities": {}, "offsetEncoding": "utf-8"});
+    assert!(fill_position_encoding(&mut result));
+    assert_eq!(result["capabilities"]["positionEncoding"], "utf-8");
+
+    let mut result =
+        json!({"capabilities": {"positionEncoding": "utf-16"}, "offsetEncoding": "utf-8"});
+    assert!(!fill_position_encoding(&mut result));
+    assert_eq!(result["capabilities"]["positionEncoding"], "utf-16");
+}
+
+#[test]
+fn test_clangd_version_falls_back_to_backend_log() {
+    let capabilities = BackendCapabilities::new();
+    capabilities.record_log_line("I[11:01:38.638] Ubuntu clangd version 14.0.0-1ubuntu1");
+    capabilities.record(&json!({"capabilities": {}, "serverInfo": {"name": "clangd"}}));
+    assert_eq!(capabilities.clangd_version(), Some((14, 0, 0)));
+
+    capabilities.record(&json!({"serverInfo": {"version": "clangd version 16.0.2"}}));
+    assert_eq!(capabilities.clangd_version(), Some((16, 0, 2)));
+}
+
+#[tokio::test]
+async fn test_inlay_hints_are_translated_for_clangd_14() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let mut dispatcher = Dispatcher::new(backend_tx, frontend_tx);
+    setup_handlers(&mut dispatcher);
+    let dispatcher = Arc::new(dispatcher);
+
+    let initialize =
+        json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"capabilities": {}}});
+    dispatcher.handle_from_frontend(initialize).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    let response = json!({"jsonrpc": "2.0", "id": 1, "result": {
+        "capabilities": {"clangdInlayHintsProvider": true},
+        "offsetEncoding": "utf-8",
+        "serverInfo": {"name": "clangd", "version": "clangd version 14.0.6"},
+    }});
+    dispatcher.handle_from_backend(response).await.unwrap();
+    let initialized = frontend_rx.recv().await.unwrap().into_body();
+    let capabilities = &initialized["result"]["capabilities"];
+    assert_eq!(capabilities["inlayHintProvider"], json!(true));
+    assert_eq!(capabilities["positionEncoding"], json!("utf-8"));
+
+    let inlay_hint = json!({"jsonrpc": "2.0", "id": 2, "method": "textDocument/inlayHint", "params": {
+        "textDocument": {"uri": "file:///a.cpp"},
+        "range": {"start": {"line": 0, "character": 0}, "end": {"line": 9, "character": 0}},
+    }});
+    dispatcher.handle_from_frontend(inlay_hint).await.unwrap();
+    let forwarded = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(forwarded["method"], "clangd/inlayHints");
+
+    let response = json!({"jsonrpc": "2.0", "id": 2, "result": [
+        {"kind": "parameter", "label": "x:", "range": {
+            "start": {"line": 1, "character": 4}, "end": {"line": 1, "character": 5}
+        }},
+    ]});
+    dispatcher.handle_from_backend(response).await.unwrap();
+    let hints = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(
+        hints["result"],
+        json!([{"kind": 2, "label": "x:", "position": {"line": 1, "character": 4}}])
+    );
+}
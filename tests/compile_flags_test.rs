@@ -0,0 +1,109 @@
+use lsp_proxy::compile_flags::{self, FileCommand};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::Url;
+
+#[test]
+fn test_parse_aquery_output() {
+    let output = json!({"actions": [
+        {"mnemonic": "CppCompile", "arguments": [
+            "external/toolchain/bin/clang", "-Iexternal/abseil", "-c", "server/main.cc", "-o", "bazel-out/main.o",
+        ]},
+        {"mnemonic": "CppLink", "arguments": ["external/toolchain/bin/clang", "-o", "server"]},
+    ]})
+    .to_string();
+    let commands = compile_flags::parse_aquery(
+        &output,
+        Path::new("/repo"),
+        Path::new("/cache/execroot/repo"),
+    )
+    .unwrap();
+    assert_eq!(commands.len(), 1);
+    let command = &commands[Path::new("/repo/server/main.cc")];
+    assert_eq!(command.directory, PathBuf::from("/cache/execroot/repo"));
+    assert_eq!(command.arguments[1], "-Iexternal/abseil");
+    assert!(compile_flags::parse_aquery("not json", Path::new("/repo"), Path::new("/")).is_err());
+
+    let change = compile_flags::database_change(Path::new("/repo/server/main.cc"), command);
+    let entry = &change["params"]["settings"]["compilationDatabaseChanges"]["/repo/server/main.cc"];
+    assert_eq!(entry["workingDirectory"], "/cache/execroot/repo");
+    assert_eq!(entry["compilationCommand"][3], "server/main.cc");
+}
+
+#[test]
+fn test_compile_flags_template() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("lib/net")).unwrap();
+    std::fs::write(
+        root.join("compile_flags.txt"),
+        "-std=c++17\n-I${root}/include\n-I${dir}\n\n-DSOURCE=\"${file}\"\n",
+    )
+    .unwrap();
+    let file = root.join("lib/net/socket.cpp");
+    let command = compile_flags::from_template(&file, "clang++").unwrap();
+    assert_eq!(
+        command,
+        FileCommand {
+            directory: root.to_path_buf(),
+            arguments: vec![
+                "clang++".to_string(),
+                "-std=c++17".to_string(),
+                format!("-I{}/include", root.display()),
+                format!("-I{}", root.join("lib/net").display()),
+                format!("-DSOURCE=\"{}\"", file.display()),
+                file.display().to_string(),
+            ],
+        }
+    );
+
+    // 没有模板变量的 compile_flags.txt 由 clangd 自己读取
+    std::fs::write(root.join("lib/compile_flags.txt"), "-std=c++20\n").unwrap();
+    assert!(compile_flags::from_template(&file, "clang++").is_none());
+}
+
+#[tokio::test]
+async fn test_dispatcher_injects_flags_without_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    std::fs::write(root.join("compile_flags.txt"), "-I${root}/include\n").unwrap();
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": 1, "method": "initialize",
+            "params": {"rootUri": Url::from_directory_path(&root).unwrap(), "capabilities": {}}}))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+
+    let open = |name: &str| {
+        json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {"textDocument": {
+            "uri": Url::from_file_path(root.join(name)).unwrap(), "languageId": "cpp", "version": 1, "text": ""}}})
+    };
+    dispatcher
+        .handle_from_frontend(open("a.cpp"))
+        .await
+        .unwrap();
+    let configuration = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(configuration["method"], "workspace/didChangeConfiguration");
+    assert_eq!(
+        backend_rx.recv().await.unwrap().into_body()["method"],
+        "textDocument/didOpen"
+    );
+
+    // 生成了编译数据库之后不再注入
+    std::fs::write(root.join("compile_commands.json"), "[]").unwrap();
+    dispatcher
+        .handle_from_frontend(open("b.cpp"))
+        .await
+        .unwrap();
+    assert_eq!(
+        backend_rx.recv().await.unwrap().into_body()["method"],
+        "textDocument/didOpen"
+    );
+}
@@ -0,0 +1,161 @@
+use futures::future::BoxFuture;
+use lsp_proxy::completion_sources::{
+    self, CompletionProvider, CompletionRequest, CompletionSources,
+};
+use lsp_proxy::config::{CompletionConfig, Config};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// 固定返回一组补全项的来源，`delay` 之后才返回。
+struct Fixed {
+    name: &'static str,
+    labels: Vec<&'static str>,
+    delay: Duration,
+}
+
+impl CompletionProvider for Fixed {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn complete(&self, _request: &CompletionRequest) -> BoxFuture<'static, Vec<Value>> {
+        let items: Vec<Value> = self
+            .labels
+            .iter()
+            .map(|label| json!({"label": label}))
+            .collect();
+        let delay = self.delay;
+        Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            items
+        })
+    }
+}
+
+fn fixed(name: &'static str, labels: &[&'static str], delay_ms: u64) -> Arc<Fixed> {
+    Arc::new(Fixed {
+        name,
+        labels: labels.to_vec(),
+        delay: Duration::from_millis(delay_ms),
+    })
+}
+
+fn completion(id: i64) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": "textDocument/completion", "params": {
+        "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 0},
+    }})
+}
+
+fn labels(result: &Value) -> Vec<&str> {
+    result["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_merge_appends_sources_in_time() {
+    let mut sources = CompletionSources::default();
+    sources.register(fixed("words", &["foo", "bar"], 0));
+    sources.register(fixed("slow", &["baz"], 1000));
+    let request = completion(1);
+    sources.request(&request["id"], &request["params"], None, true);
+
+    let mut response = json!({"jsonrpc": "2.0", "id": 1, "result": [
+        {"label": "foo", "data": {"clangd": 1}},
+    ]});
+    assert!(sources.merge(&mut response).await);
+    let result = &response["result"];
+    // 标签相同的补全项只保留后端的，超时的来源不等待
+    assert_eq!(labels(result), vec!["foo", "bar"]);
+    assert_eq!(result["isIncomplete"], true);
+    assert_eq!(result["items"][0]["data"], json!({"clangd": 1}));
+
+    let bar = &result["items"][1];
+    assert_eq!(completion_sources::source_of(bar), Some("words"));
+    assert_eq!(bar["labelDetails"]["description"], "words");
+    assert_eq!(completion_sources::source_of(&result["items"][0]), None);
+}
+
+#[tokio::test]
+async fn test_budgets_and_errors() {
+    let config = CompletionConfig {
+        budgets: HashMap::from([("slow".to_string(), 2000)]),
+        annotate: false,
+        words: false,
+        keywords: false,
+    };
+    let mut sources = CompletionSources::new(&config);
+    assert_eq!(sources.budget("slow"), Duration::from_secs(2));
+    assert_eq!(sources.budget("words"), Duration::from_millis(100));
+    assert_eq!(sources.backend_budget(), None);
+    sources.register(fixed("slow", &["baz"], 10));
+
+    let request = completion(1);
+    sources.request(&request["id"], &request["params"], None, true);
+    let mut response = json!({"jsonrpc": "2.0", "id": 1, "result": {
+        "isIncomplete": false, "items": [],
+    }});
+    assert!(sources.merge(&mut response).await);
+    assert_eq!(labels(&response["result"]), vec!["baz"]);
+    assert_eq!(response["result"]["isIncomplete"], false);
+    assert!(response["result"]["items"][0].get("labelDetails").is_none());
+
+    // 后端返回错误时原样转发
+    sources.request(&request["id"], &request["params"], None, true);
+    let mut error = json!({"jsonrpc": "2.0", "id": 1, "error": {"code": -32800, "message": ""}});
+    let original = error.clone();
+    assert!(sources.merge(&mut error).await);
+    assert_eq!(error, original);
+}
+
+#[tokio::test]
+async fn test_backend_over_budget() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse("[completion.budgets]\nclangd = 50").unwrap();
+    let mut dispatcher = Dispatcher::new(backend_tx, frontend_tx).with_config(config);
+    dispatcher.register_completion_provider(fixed("words", &["foo"], 0));
+    let dispatcher = Arc::new(dispatcher);
+    dispatcher.health().set_alive(0, true);
+
+    dispatcher
+        .handle_from_frontend(completion(7))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+
+    // 后端没有按时响应，代理先应答并取消后端的请求
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["id"], 7);
+    assert_eq!(labels(&response["result"]), vec!["foo"]);
+    assert_eq!(response["result"]["isIncomplete"], true);
+    let cancel = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(cancel["method"], "$/cancelRequest");
+    assert_eq!(cancel["params"]["id"], 7);
+
+    // 迟到的响应丢弃
+    dispatcher
+        .handle_from_backend(json!({"jsonrpc": "2.0", "id": 7, "result": [{"label": "late"}]}))
+        .await
+        .unwrap();
+    assert!(frontend_rx.try_recv().is_err());
+
+    // 代理内来源的补全项由代理解析
+    let item = response["result"]["items"][0].clone();
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": 8,
+            "method": "completionItem/resolve", "params": item}))
+        .await
+        .unwrap();
+    let resolved = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(resolved["result"], item);
+    assert!(backend_rx.try_recv().is_err());
+}
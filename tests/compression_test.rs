@@ -0,0 +1,84 @@
+use bytes::BytesMut;
+use lsp_proxy::codec::LspCodec;
+use lsp_proxy::compression::FrameCompression;
+use lsp_proxy::config::{FrameEncoding, TransportConfig};
+use lsp_proxy::metrics::Metrics;
+use serde_json::json;
+use std::sync::Arc;
+use tokio_util::codec::Decoder;
+
+fn compression(encodings: &[FrameEncoding]) -> (Arc<FrameCompression>, Arc<Metrics>) {
+    let metrics = Arc::new(Metrics::new());
+    let config = TransportConfig {
+        compression: encodings.to_vec(),
+        compression_min_bytes: 64,
+        ..Default::default()
+    };
+    let compression = FrameCompression::new(&config, Arc::clone(&metrics)).unwrap();
+    (compression, metrics)
+}
+
+fn semantic_tokens() -> serde_json::Value {
+    json!({"jsonrpc": "2.0", "id": 1, "result": {"data": vec![0; 4096]}})
+}
+
+#[test]
+fn test_peers_negotiate_and_compress() {
+    let (relay, relay_metrics) = compression(&[FrameEncoding::Gzip]);
+    let (proxy, proxy_metrics) = compression(&[FrameEncoding::Zstd, FrameEncoding::Gzip]);
+    let mut relay_codec = LspCodec::with_compression(Some(Arc::clone(&relay)));
+    let mut proxy_codec = LspCodec::with_compression(Some(Arc::clone(&proxy)));
+
+    // 第一条消息列出支持的算法，此时还不知道对端支持什么，不压缩
+    let initialize = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}});
+    let frame = relay.encode_frame(&initialize).unwrap();
+    assert!(String::from_utf8_lossy(&frame).contains("Accept-Encoding: gzip\r\n"));
+    let mut buffer = BytesMut::from(&frame[..]);
+    assert_eq!(proxy_codec.decode(&mut buffer).unwrap(), Some(initialize));
+    assert_eq!(proxy.encoding(), Some(FrameEncoding::Gzip));
+
+    let frame = proxy.encode_frame(&semantic_tokens()).unwrap();
+    assert!(String::from_utf8_lossy(&frame).contains("Content-Encoding: gzip\r\n"));
+    let mut buffer = BytesMut::from(&frame[..]);
+    assert_eq!(
+        relay_codec.decode(&mut buffer).unwrap(),
+        Some(semantic_tokens())
+    );
+    assert_eq!(relay.encoding(), Some(FrameEncoding::Gzip));
+
+    let sent = proxy_metrics.snapshot();
+    assert_eq!(sent.frames_compressed, 1);
+    assert!(sent.compression_output_bytes < sent.compression_input_bytes);
+    let received = relay_metrics.snapshot();
+    assert_eq!(received.frames_decompressed, 1);
+    assert_eq!(
+        received.decompression_output_bytes,
+        sent.compression_input_bytes
+    );
+}
+
+#[test]
+fn test_editor_peer_is_never_compressed() {
+    let (proxy, metrics) = compression(&[FrameEncoding::Zstd]);
+    let first = proxy.encode_frame(&semantic_tokens()).unwrap();
+    let second = proxy.encode_frame(&semantic_tokens()).unwrap();
+    assert!(!String::from_utf8_lossy(&second).contains("Encoding"));
+
+    // 不处理压缩的解码器忽略 Accept-Encoding
+    let mut buffer = BytesMut::from(&[first, second].concat()[..]);
+    let mut codec = LspCodec::default();
+    assert_eq!(codec.decode(&mut buffer).unwrap(), Some(semantic_tokens()));
+    assert_eq!(codec.decode(&mut buffer).unwrap(), Some(semantic_tokens()));
+    assert_eq!(metrics.snapshot().frames_compressed, 0);
+}
+
+#[test]
+fn test_zstd_round_trip() {
+    let data = vec![b'x'; 100_000];
+    let compressed = lsp_proxy::compression::compress(FrameEncoding::Zstd, &data).unwrap();
+    assert!(compressed.len() < 1000);
+    assert_eq!(
+        lsp_proxy::compression::decompress(FrameEncoding::Zstd, &compressed).unwrap(),
+        data
+    );
+}
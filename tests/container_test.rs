@@ -0,0 +1,99 @@
+use lsp_proxy::config::{ContainerConfig, ResourceLimits};
+use lsp_proxy::container::{ContainerGuard, ContainerSpec};
+use serde_json::json;
+use std::path::PathBuf;
+
+fn spec(mount: Option<&str>) -> ContainerSpec {
+    ContainerSpec::new(&ContainerConfig {
+        image: "ghcr.io/acme/clangd:17".to_string(),
+        workspace: Some(PathBuf::from("/home/me/app")),
+        mount: mount.map(PathBuf::from),
+        args: vec!["--network=none".to_string()],
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+#[test]
+fn test_run_args_mount_workspace() {
+    let spec = spec(Some("/src"));
+    let limits = ResourceLimits {
+        cpus: vec![0, 1],
+        memory_mb: Some(4096),
+        ..Default::default()
+    };
+    let args = spec.run_args(
+        "codefuse-1-0-1",
+        "clangd",
+        &["--background-index".to_string()],
+        &[("XDG_CACHE_HOME".to_string(), "/src/.cache".to_string())],
+        &limits,
+    );
+    assert_eq!(
+        args,
+        [
+            "run",
+            "--rm",
+            "-i",
+            "--name",
+            "codefuse-1-0-1",
+            "-v",
+            "/home/me/app:/src",
+            "-w",
+            "/src",
+            "-e",
+            "XDG_CACHE_HOME=/src/.cache",
+            "--cpuset-cpus=0,1",
+            "--memory=4096m",
+            "--network=none",
+            "ghcr.io/acme/clangd:17",
+            "clangd",
+            "--background-index",
+        ]
+    );
+    assert_ne!(spec.next_name(0), spec.next_name(0));
+}
+
+#[test]
+fn test_paths_are_mapped_only_when_mount_differs() {
+    assert_eq!(spec(None).mapping().unwrap(), None);
+
+    let mapping = spec(Some("/src")).mapping().unwrap().unwrap();
+    let mut message = json!({"uri": "file:///home/me/app/main.cpp"});
+    mapping.to_remote(&mut message);
+    assert_eq!(message["uri"], "file:///src/main.cpp");
+
+    assert!(ContainerSpec::new(&ContainerConfig::default()).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_guard_removes_container() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("engine.log");
+    let engine = dir.path().join("engine");
+    std::fs::write(
+        &engine,
+        format!("#!/bin/sh\necho \"$@\" > {}\n", log.display()),
+    )
+    .unwrap();
+    std::fs::set_permissions(&engine, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    drop(ContainerGuard::new(
+        &engine.to_string_lossy(),
+        "codefuse-1-0-1".to_string(),
+    ));
+    for _ in 0..100 {
+        if let Ok(line) = std::fs::read_to_string(&log)
+            && !line.is_empty()
+        {
+            assert_eq!(line.trim(), "rm -f codefuse-1-0-1");
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    panic!("容器没有被删除");
+}
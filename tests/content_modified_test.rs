@@ -0,0 +1,73 @@
+use lsp_proxy::config::Config;
+use lsp_proxy::content_modified::CONTENT_MODIFIED;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+fn request(id: i32, method: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": method, "params": {
+        "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 4}
+    }})
+}
+
+fn did_change(version: i32) -> Value {
+    json!({"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+        "textDocument": {"uri": "file:///a.cpp", "version": version},
+        "contentChanges": [{"text": "int y;"}]
+    }})
+}
+
+#[tokio::test]
+async fn test_stale_response_becomes_content_modified() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config =
+        Config::parse("[protocol]\ncontent_modified = [\"textDocument/documentHighlight\"]\n")
+            .unwrap();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+
+    let did_open = json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": 1, "text": "int x;"}
+    }});
+    dispatcher.handle_from_frontend(did_open).await.unwrap();
+    backend_rx.recv().await.unwrap();
+
+    // 请求之后文档被修改：配置的方法的响应换成 ContentModified，其他方法照常转发
+    for (id, method) in [
+        (1, "textDocument/documentHighlight"),
+        (2, "textDocument/hover"),
+    ] {
+        dispatcher
+            .handle_from_frontend(request(id, method))
+            .await
+            .unwrap();
+        backend_rx.recv().await.unwrap();
+    }
+    dispatcher
+        .handle_from_frontend(did_change(2))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+    for id in [1, 2] {
+        let response = json!({"jsonrpc": "2.0", "id": id, "result": []});
+        dispatcher.handle_from_backend(response).await.unwrap();
+    }
+    let highlight = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(highlight["id"], 1);
+    assert_eq!(highlight["error"]["code"], CONTENT_MODIFIED);
+    let hover = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(hover["result"], json!([]));
+
+    // 文档没有变化时响应照常转发
+    dispatcher
+        .handle_from_frontend(request(3, "textDocument/documentHighlight"))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+    let response = json!({"jsonrpc": "2.0", "id": 3, "result": []});
+    dispatcher.handle_from_backend(response).await.unwrap();
+    let highlight = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(highlight["result"], json!([]));
+}
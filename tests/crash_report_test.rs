@@ -0,0 +1,104 @@
+use flate2::read::DeflateDecoder;
+use lsp_proxy::backend_log::LogLine;
+use lsp_proxy::config::Config;
+use lsp_proxy::crash_report::redact;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io::Read;
+use tokio::sync::mpsc;
+
+/// 按本地文件头读出 zip 中的所有文件。
+fn unzip(data: &[u8]) -> HashMap<String, String> {
+    let u16_at = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]) as usize;
+    let u32_at = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize;
+    let mut files = HashMap::new();
+    let mut at = 0;
+    while u32_at(at) == 0x04034b50 {
+        let compressed = u32_at(at + 18);
+        let name_len = u16_at(at + 26);
+        let extra_len = u16_at(at + 28);
+        let name = String::from_utf8(data[at + 30..at + 30 + name_len].to_vec()).unwrap();
+        let start = at + 30 + name_len + extra_len;
+        let mut content = String::new();
+        DeflateDecoder::new(&data[start..start + compressed])
+            .read_to_string(&mut content)
+            .unwrap();
+        files.insert(name, content);
+        at = start + compressed;
+    }
+    files
+}
+
+#[test]
+fn test_redact_document_contents() {
+    let open = json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": 1, "text": "int secret;"}
+    }});
+    let redacted = redact(&open);
+    assert_eq!(redacted["params"]["textDocument"]["uri"], "file:///a.cpp");
+    assert_eq!(
+        redacted["params"]["textDocument"]["text"],
+        "<已隐去 11 字节>"
+    );
+
+    let hover = json!({"jsonrpc": "2.0", "id": 1, "result": {
+        "contents": {"kind": "markdown", "value": "int secret"},
+        "range": {"start": {"line": 0, "character": 4}, "end": {"line": 0, "character": 10}}
+    }});
+    let redacted = redact(&hover);
+    assert_eq!(redacted["result"]["contents"]["kind"], "<已隐去 8 字节>");
+    assert_eq!(redacted["result"]["contents"]["value"], "<已隐去 10 字节>");
+    assert_eq!(redacted["result"]["range"], hover["result"]["range"]);
+}
+
+#[tokio::test]
+async fn test_report_crash_writes_bundle_and_notifies() {
+    let dir = tempfile::tempdir().unwrap();
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse(&format!(
+        "[crash_reports]\ndir = {:?}\n",
+        dir.path().to_str().unwrap()
+    ))
+    .unwrap();
+    let dispatcher = Dispatcher::new(backend_tx, frontend_tx).with_config(config);
+
+    let open = json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": 1, "text": "int secret;"}
+    }});
+    let dispatcher = std::sync::Arc::new(dispatcher);
+    dispatcher.handle_from_frontend(open).await.unwrap();
+    dispatcher
+        .backend_log()
+        .record(0, LogLine::parse("E[10:00:00.000] Failed to build AST"));
+    while frontend_rx.try_recv().is_ok() {}
+
+    dispatcher.report_crash("分片 default 的后端异常退出");
+    let message = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(message["method"], "window/showMessage");
+    let text = message["params"]["message"].as_str().unwrap();
+    assert!(text.starts_with("分片 default 的后端异常退出"));
+
+    let bundles: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(bundles.len(), 1);
+    assert!(text.contains(bundles[0].to_str().unwrap()));
+    let files = unzip(&std::fs::read(&bundles[0]).unwrap());
+
+    let report: Value = serde_json::from_str(&files["report.json"]).unwrap();
+    assert_eq!(report["reason"], "分片 default 的后端异常退出");
+    assert_eq!(report["proxyVersion"], env!("CARGO_PKG_VERSION"));
+    assert!(files["config.txt"].contains("crash_reports"));
+    assert!(files["messages.ndjson"].contains("textDocument/didOpen"));
+    assert!(!files["messages.ndjson"].contains("secret"));
+    assert!(files["stderr-default.log"].contains("Failed to build AST"));
+
+    // 一分钟内不再生成报告
+    dispatcher.report_crash("again");
+    assert!(frontend_rx.try_recv().is_err());
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
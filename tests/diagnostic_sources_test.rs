@@ -0,0 +1,119 @@
+use lsp_proxy::config::DiagnosticsConfig;
+use lsp_proxy::diagnostic_sources::{BACKEND_SOURCE, DiagnosticSources};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::Url;
+
+fn diagnostic(message: &str, source: Option<&str>) -> Value {
+    let mut diagnostic = json!({
+        "range": {"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 1}},
+        "message": message,
+    });
+    if let Some(source) = source {
+        diagnostic["source"] = json!(source);
+    }
+    diagnostic
+}
+
+fn publish(diagnostics: Vec<Value>) -> Value {
+    json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": {
+        "uri": "file:///a.cpp", "diagnostics": diagnostics
+    }})
+}
+
+fn messages(rpc: &Value) -> Vec<(String, String)> {
+    rpc["params"]["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| {
+            (
+                d["source"].as_str().unwrap().to_string(),
+                d["message"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_sources_are_merged_in_stable_order() {
+    let sources = DiagnosticSources::default();
+    let mut review = publish(vec![diagnostic("possible leak", None)]);
+    sources.merge("review", &mut review);
+
+    let mut backend = publish(vec![diagnostic("expected ';'", Some("clang"))]);
+    sources.merge(BACKEND_SOURCE, &mut backend);
+    assert_eq!(
+        messages(&backend),
+        [
+            ("clang".to_string(), "expected ';'".to_string()),
+            ("review".to_string(), "possible leak".to_string()),
+        ]
+    );
+
+    // 一个来源清空诊断不影响其他来源
+    let mut backend = publish(Vec::new());
+    sources.merge(BACKEND_SOURCE, &mut backend);
+    assert_eq!(
+        messages(&backend),
+        [("review".to_string(), "possible leak".to_string())]
+    );
+}
+
+#[test]
+fn test_disabled_sources_are_hidden() {
+    let config = DiagnosticsConfig {
+        sources: [
+            ("review".to_string(), false),
+            ("clang-tidy".to_string(), false),
+        ]
+        .into(),
+    };
+    let sources = DiagnosticSources::new(&config);
+    let mut review = publish(vec![diagnostic("possible leak", None)]);
+    sources.merge("review", &mut review);
+    assert!(messages(&review).is_empty());
+
+    let mut backend = publish(vec![
+        diagnostic("expected ';'", Some("clang")),
+        diagnostic("use auto", Some("clang-tidy")),
+    ]);
+    sources.merge(BACKEND_SOURCE, &mut backend);
+    assert_eq!(
+        messages(&backend),
+        [("clang".to_string(), "expected ';'".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn test_dispatcher_publishes_merged_diagnostics() {
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let uri = Url::parse("file:///a.cpp").unwrap();
+
+    dispatcher
+        .handle_from_shard(0, publish(vec![diagnostic("expected ';'", None)]))
+        .await
+        .unwrap();
+    let forwarded = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(
+        messages(&forwarded),
+        [("clangd".to_string(), "expected ';'".to_string())]
+    );
+
+    dispatcher
+        .publish_diagnostics("review", &uri, vec![diagnostic("possible leak", None)])
+        .unwrap();
+    let forwarded = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(messages(&forwarded).len(), 2);
+
+    // 内容没有变化的发布不再发给前端
+    dispatcher
+        .publish_diagnostics("review", &uri, vec![diagnostic("possible leak", None)])
+        .unwrap();
+    assert!(frontend_rx.try_recv().is_err());
+}
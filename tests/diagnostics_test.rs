@@ -0,0 +1,84 @@
+use lsp_proxy::diagnostics::{self, DiagnosticsStore};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::Url;
+
+fn publish(version: Option<i32>, messages: &[&str]) -> Value {
+    let diagnostics: Vec<_> = messages
+        .iter()
+        .map(|message| {
+            json!({
+                "range": {"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 1}},
+                "message": message,
+            })
+        })
+        .collect();
+    let mut params = json!({"uri": "file:///a.cpp", "diagnostics": diagnostics});
+    if let Some(version) = version {
+        params["version"] = json!(version);
+    }
+    json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": params})
+}
+
+#[test]
+fn test_store_reports_unchanged_diagnostics() {
+    let store = DiagnosticsStore::new();
+    let uri = Url::parse("file:///a.cpp").unwrap();
+    assert!(store.update(&publish(None, &["unused variable"])));
+    assert!(!store.update(&publish(None, &["unused variable"])));
+    assert_eq!(store.uris().len(), 1);
+
+    // 清空诊断也只转发一次，清空后的文档不算有诊断
+    assert!(store.update(&publish(None, &[])));
+    assert!(!store.update(&publish(None, &[])));
+    assert!(store.uris().is_empty());
+    assert!(store.get(&uri).is_empty());
+}
+
+#[tokio::test]
+async fn test_dispatcher_drops_outdated_and_duplicate_diagnostics() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+            "textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": 1, "text": "int a"}
+        }}))
+        .await
+        .unwrap();
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+                "textDocument": {"uri": "file:///a.cpp", "version": 2},
+                "contentChanges": [{"text": "int a;"}]
+            }}),
+        )
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+    backend_rx.recv().await.unwrap();
+
+    assert!(diagnostics::is_outdated(
+        &publish(Some(1), &["expected ';'"]),
+        dispatcher.documents()
+    ));
+    for rpc in [
+        publish(Some(1), &["expected ';'"]),
+        publish(Some(2), &[]),
+        publish(Some(2), &[]),
+    ] {
+        dispatcher.handle_from_shard(0, rpc).await.unwrap();
+    }
+
+    // 只有针对当前版本的第一条诊断转发给前端
+    let forwarded = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(forwarded["params"]["version"], 2);
+    assert!(frontend_rx.try_recv().is_err());
+    let metrics = dispatcher.metrics().snapshot();
+    assert_eq!(metrics.diagnostics_outdated, 1);
+    assert_eq!(metrics.diagnostics_duplicate, 1);
+}
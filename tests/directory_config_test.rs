@@ -0,0 +1,149 @@
+use lsp_proxy::config::{Config, DiagnosticsConfig, TidyRule};
+use lsp_proxy::diagnostic_sources::DiagnosticSources;
+use lsp_proxy::directory_config::{DirectoryConfigs, DirectoryLayer};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::tidy_policy::TidyPolicy;
+use serde_json::json;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::Url;
+
+#[test]
+fn test_nested_layers_override_outer_settings() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("third_party/zlib/src")).unwrap();
+    // 根目录的配置文件是工作区配置，不作为一层
+    std::fs::write(
+        root.join(".codefuse.toml"),
+        "[diagnostics.sources]\ntodos = false\n",
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("third_party/.codefuse.toml"),
+        "[diagnostics.sources]\nspellcheck = false\nclang-tidy = false\n\n[[tidy]]\nchecks = \"-*\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("third_party/zlib/.codefuse.toml"),
+        "[diagnostics.sources]\nclang-tidy = true\n\n[[tidy]]\npath = \"src\"\nchecks = \"bugprone-*\"\n",
+    )
+    .unwrap();
+
+    let configs = DirectoryConfigs::default();
+    let file = root.join("third_party/zlib/src/inflate.c");
+    assert!(configs.layers(&file).is_empty());
+    configs.set_roots(vec![root.to_path_buf()]);
+    assert_eq!(configs.layers(&file).len(), 2);
+    assert!(configs.layers(&root.join("main.cpp")).is_empty());
+
+    let sources = configs.sources(&file, &[("review".to_string(), false)].into());
+    assert!(!sources["review"]);
+    assert!(!sources["spellcheck"]);
+    assert!(sources["clang-tidy"]);
+    assert!(!sources.contains_key("todos"));
+
+    let rules = configs.tidy_rules(&file);
+    assert_eq!(
+        rules[0].path.as_deref(),
+        Some(root.join("third_party").as_path())
+    );
+    assert_eq!(
+        rules[1].path.as_deref(),
+        Some(root.join("third_party/zlib/src").as_path())
+    );
+
+    // 修改之后重新读取，无效的文件被忽略
+    std::fs::write(root.join("third_party/zlib/.codefuse.toml"), "[[tidy]]\n").unwrap();
+    assert_eq!(configs.layers(&file).len(), 1);
+    assert!(DirectoryLayer::parse("tidy = 1", root).is_err());
+}
+
+#[test]
+fn test_policies_apply_directory_layers() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("legacy")).unwrap();
+    std::fs::write(
+        root.join("legacy/.codefuse.toml"),
+        "[diagnostics.sources]\nspellcheck = false\n\n[[tidy]]\nchecks = \"-modernize-*\"\n",
+    )
+    .unwrap();
+    let configs = Arc::new(DirectoryConfigs::default());
+    configs.set_roots(vec![root.to_path_buf()]);
+
+    let policy = TidyPolicy::new(&[TidyRule {
+        path: None,
+        checks: "-*,modernize-*".to_string(),
+    }])
+    .with_directories(Arc::clone(&configs));
+    let publish = |path: &Path| {
+        json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": {
+            "uri": Url::from_file_path(path).unwrap(),
+            "diagnostics": [{"source": "clang-tidy", "message": "x", "code": "modernize-use-nullptr"}]}})
+    };
+    let mut modern = publish(&root.join("a.cpp"));
+    policy.filter_diagnostics(&mut modern);
+    assert_eq!(modern["params"]["diagnostics"].as_array().unwrap().len(), 1);
+    let mut legacy = publish(&root.join("legacy/b.cpp"));
+    policy.filter_diagnostics(&mut legacy);
+    assert!(
+        legacy["params"]["diagnostics"]
+            .as_array()
+            .unwrap()
+            .is_empty()
+    );
+
+    let sources = DiagnosticSources::new(&DiagnosticsConfig::default()).with_directories(configs);
+    let mut spelling = json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": {
+        "uri": Url::from_file_path(root.join("legacy/b.cpp")).unwrap(),
+        "diagnostics": [{"message": "misspelled word"}]}});
+    sources.merge("spellcheck", &mut spelling);
+    assert!(
+        spelling["params"]["diagnostics"]
+            .as_array()
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_dispatcher_uses_workspace_roots() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    std::fs::create_dir_all(root.join("generated")).unwrap();
+    std::fs::write(
+        root.join("generated/.codefuse.toml"),
+        "[diagnostics.sources]\nclangd = false\n",
+    )
+    .unwrap();
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher =
+        Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(Config::default()));
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": 1, "method": "initialize",
+            "params": {"rootUri": Url::from_directory_path(&root).unwrap(), "capabilities": {}}}))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+
+    dispatcher
+        .handle_from_shard(
+            0,
+            json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics",
+            "params": {"uri": Url::from_file_path(root.join("generated/parser.cpp")).unwrap(),
+                "diagnostics": [{"message": "unused variable"}]}}),
+        )
+        .await
+        .unwrap();
+    let forwarded = frontend_rx.recv().await.unwrap().into_body();
+    assert!(
+        forwarded["params"]["diagnostics"]
+            .as_array()
+            .unwrap()
+            .is_empty()
+    );
+}
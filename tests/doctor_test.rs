@@ -0,0 +1,35 @@
+use lsp_proxy::cli::{CliArgs, Command};
+use lsp_proxy::doctor::DoctorReport;
+use std::path::PathBuf;
+
+#[test]
+fn test_parse_doctor_command() {
+    let args = CliArgs::parse(["--config", "x.toml", "doctor"].map(String::from)).unwrap();
+    assert_eq!(args.command, Some(Command::Doctor));
+    assert_eq!(args.config, Some(PathBuf::from("x.toml")));
+}
+
+#[test]
+fn test_report_lists_active_shims() {
+    let report = DoctorReport {
+        config: None,
+        command: "clangd".to_string(),
+        program: Some(PathBuf::from("/usr/bin/clangd")),
+        version: Some("Ubuntu clangd version 14.0.0-1ubuntu1".to_string()),
+        clangd_version: Some((14, 0, 0)),
+    };
+    let text = report.to_string();
+    assert!(text.contains("clangd 版本: 14.0.0\n"));
+    assert!(text.contains("  legacy-inlay-hints: "));
+    assert!(text.contains("  position-encoding: "));
+
+    let report = DoctorReport {
+        program: None,
+        version: None,
+        clangd_version: None,
+        ..report
+    };
+    let text = report.to_string();
+    assert!(text.contains("后端程序: 找不到 clangd\n"));
+    assert!(text.contains("兼容垫片: 无\n"));
+}
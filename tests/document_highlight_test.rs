@@ -0,0 +1,91 @@
+use lsp_proxy::config::Config;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::document_highlight;
+use lsp_proxy::message::Message;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::Position;
+
+const SOURCE: &str = "int count = 0;\n// count_all 和 count\nvoid f() { ++count; ë(count); }";
+
+#[test]
+fn test_word_at() {
+    assert_eq!(
+        document_highlight::word_at(SOURCE, Position::new(0, 6)),
+        Some("count")
+    );
+    // 光标紧挨在标识符之后
+    assert_eq!(
+        document_highlight::word_at(SOURCE, Position::new(0, 9)),
+        Some("count")
+    );
+    assert_eq!(
+        document_highlight::word_at(SOURCE, Position::new(0, 12)),
+        None
+    );
+    assert_eq!(
+        document_highlight::word_at(SOURCE, Position::new(0, 13)),
+        None
+    );
+}
+
+#[test]
+fn test_highlights_whole_identifiers() {
+    let highlights = document_highlight::highlights(SOURCE, Position::new(0, 5));
+    let ranges: Vec<_> = highlights
+        .iter()
+        .map(|highlight| {
+            assert_eq!(highlight["kind"], 1);
+            let range = &highlight["range"];
+            (
+                range["start"]["line"].clone(),
+                range["start"]["character"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        ranges,
+        vec![
+            (json!(0), json!(4)),
+            (json!(1), json!(15)),
+            (json!(2), json!(13)),
+            (json!(2), json!(22)),
+        ]
+    );
+    assert_eq!(highlights[3]["range"]["end"]["character"], 27);
+}
+
+#[tokio::test]
+async fn test_answers_when_backend_times_out() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse("[document_highlight]\nfallback = true\ntimeout_ms = 10").unwrap();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    dispatcher.health().set_alive(0, true);
+    let did_open = json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": 1, "text": SOURCE},
+    }});
+    dispatcher.handle_from_frontend(did_open).await.unwrap();
+    backend_rx.recv().await.unwrap();
+
+    let highlight = json!({"jsonrpc": "2.0", "id": 7, "method": "textDocument/documentHighlight",
+        "params": {"textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 2, "character": 14}}});
+    dispatcher.handle_from_frontend(highlight).await.unwrap();
+    backend_rx.recv().await.unwrap();
+
+    // 超时后代理应答并取消后端的请求
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["id"], 7);
+    assert_eq!(response["result"].as_array().unwrap().len(), 4);
+    let cancel = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(cancel["method"], "$/cancelRequest");
+    assert_eq!(cancel["params"]["id"], 7);
+
+    // 后端迟到的响应丢弃
+    dispatcher
+        .handle_from_backend(json!({"jsonrpc": "2.0", "id": 7, "result": []}))
+        .await
+        .unwrap();
+    assert!(frontend_rx.try_recv().is_err());
+}
@@ -0,0 +1,95 @@
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::document_observer::{DocumentEvent, DocumentObserver};
+use lsp_proxy::message::Message;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::{
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DidSaveTextDocumentParams,
+};
+
+#[derive(Default)]
+struct Recorder {
+    events: Mutex<Vec<String>>,
+}
+
+impl Recorder {
+    fn push(&self, event: String) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+impl DocumentObserver for Recorder {
+    fn did_open(&self, params: &DidOpenTextDocumentParams) {
+        self.push(format!("open {}", params.text_document.uri));
+    }
+
+    fn did_change(&self, params: &DidChangeTextDocumentParams) {
+        self.push(format!("change {}", params.text_document.version));
+    }
+
+    fn did_save(&self, params: &DidSaveTextDocumentParams) {
+        self.push(format!("save {}", params.text_document.uri));
+    }
+
+    fn did_close(&self, params: &DidCloseTextDocumentParams) {
+        self.push(format!("close {}", params.text_document.uri));
+    }
+}
+
+#[tokio::test]
+async fn test_observers_see_document_lifecycle() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let recorder = Arc::new(Recorder::default());
+    let mut dispatcher = Dispatcher::new(backend_tx, frontend_tx);
+    dispatcher.register_document_observer(recorder.clone());
+    let dispatcher = Arc::new(dispatcher);
+
+    let uri = "file:///a.cpp";
+    let notifications = [
+        json!({"method": "textDocument/didOpen", "params": {
+            "textDocument": {"uri": uri, "languageId": "cpp", "version": 1, "text": "int a;"}
+        }}),
+        json!({"method": "textDocument/didChange", "params": {
+            "textDocument": {"uri": uri, "version": 2},
+            "contentChanges": [{"text": "int b;"}]
+        }}),
+        json!({"method": "textDocument/didSave", "params": {"textDocument": {"uri": uri}}}),
+        json!({"method": "textDocument/didClose", "params": {"textDocument": {"uri": uri}}}),
+    ];
+    for mut notification in notifications {
+        notification["jsonrpc"] = json!("2.0");
+        dispatcher.handle_from_frontend(notification).await.unwrap();
+        // 观察者不截获通知，通知照常转发给后端
+        backend_rx.recv().await.unwrap();
+    }
+
+    assert_eq!(
+        *recorder.events.lock().unwrap(),
+        [
+            "open file:///a.cpp",
+            "change 2",
+            "save file:///a.cpp",
+            "close file:///a.cpp"
+        ]
+    );
+    assert!(dispatcher.documents().is_empty());
+}
+
+#[test]
+fn test_parse_ignores_other_methods_and_reports_bad_params() {
+    assert!(DocumentEvent::parse("textDocument/hover", &json!({})).is_none());
+    assert!(matches!(
+        DocumentEvent::parse("textDocument/didClose", &json!({"textDocument": {}})),
+        Some(Err(_))
+    ));
+    let event = DocumentEvent::parse(
+        "textDocument/didSave",
+        &json!({"textDocument": {"uri": "file:///a.cpp"}}),
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(event.uri().as_str(), "file:///a.cpp");
+}
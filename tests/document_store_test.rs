@@ -0,0 +1,41 @@
+use lsp_proxy::document_store::DocumentStore;
+use serde_json::json;
+use tower_lsp::lsp_types::Url;
+
+#[test]
+fn test_incremental_changes_use_utf16_positions() {
+    let store = DocumentStore::new();
+    let uri = "file:///repo/a.cpp";
+    store.apply(
+        "textDocument/didOpen",
+        &json!({"textDocument": {"uri": uri, "languageId": "cpp", "version": 1, "text": "// 注释\nint a;\n"}}),
+    );
+
+    // “注释”在 UTF-16 中各占一个单位，插入点位于第 0 行第 5 列之后
+    store.apply(
+        "textDocument/didChange",
+        &json!({
+            "textDocument": {"uri": uri, "version": 2},
+            "contentChanges": [
+                {"range": {"start": {"line": 0, "character": 5}, "end": {"line": 0, "character": 5}}, "text": "!"},
+                {"range": {"start": {"line": 1, "character": 4}, "end": {"line": 1, "character": 5}}, "text": "b"}
+            ]
+        }),
+    );
+
+    let doc = store.get(&Url::parse(uri).unwrap()).unwrap();
+    assert_eq!(doc.version, 2);
+    assert_eq!(doc.text, "// 注释!\nint b;\n");
+
+    let reopen = doc.did_open_notification();
+    assert_eq!(
+        reopen["params"]["textDocument"]["text"],
+        "// 注释!\nint b;\n"
+    );
+
+    store.apply(
+        "textDocument/didClose",
+        &json!({"textDocument": {"uri": uri}}),
+    );
+    assert!(store.is_empty());
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::{Harness, assert_golden, fixture};
+use lsp_proxy::mock_lsp_server::MockScript;
+use serde_json::json;
+use std::time::Duration;
+use tokio::time::Instant;
+
+async fn open_document(harness: &mut Harness) {
+    harness
+        .notify(
+            "textDocument/didOpen",
+            json!({"textDocument": {
+                "uri": "file:///work/main.cpp", "languageId": "cpp", "version": 1,
+                "text": "int x = 0;\n"
+            }}),
+        )
+        .await;
+}
+
+fn position() -> serde_json::Value {
+    json!({
+        "textDocument": {"uri": "file:///work/main.cpp"},
+        "position": {"line": 0, "character": 4}
+    })
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_initialize_and_hover() {
+    let mut harness = Harness::start(MockScript::load(&fixture("hover.json")).unwrap()).await;
+    assert_golden("initialize", &harness.initialize().await);
+    open_document(&mut harness).await;
+
+    let start = Instant::now();
+    let hover = harness.request("textDocument/hover", position()).await;
+    assert_eq!(start.elapsed(), Duration::from_millis(20));
+    assert_golden("hover", &hover);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_diagnostics_and_errors() {
+    let mut harness = Harness::start(MockScript::load(&fixture("hover.json")).unwrap()).await;
+    harness.initialize().await;
+    open_document(&mut harness).await;
+
+    assert_golden(
+        "diagnostics",
+        &harness
+            .notification("textDocument/publishDiagnostics")
+            .await,
+    );
+    let definition = harness.request("textDocument/definition", position()).await;
+    assert_eq!(definition["error"]["message"], "no definition");
+}
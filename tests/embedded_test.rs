@@ -0,0 +1,159 @@
+use lsp_proxy::config::{Config, EmbeddedRule};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::embedded::EmbeddedDocuments;
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+const SKETCH: &str = "file:///work/blink.ino";
+const VIRTUAL: &str = "file:///work/blink.ino.cpp";
+
+fn range(start: (u32, u32), end: (u32, u32)) -> Value {
+    json!({"start": {"line": start.0, "character": start.1},
+           "end": {"line": end.0, "character": end.1}})
+}
+
+fn arduino() -> EmbeddedRule {
+    EmbeddedRule {
+        extensions: vec!["ino".to_string()],
+        prelude: "#include <Arduino.h>".to_string(),
+        ..EmbeddedRule::default()
+    }
+}
+
+fn did_open(uri: &str, text: &str) -> Value {
+    json!({"textDocument": {"uri": uri, "languageId": "text", "version": 1, "text": text}})
+}
+
+#[test]
+fn test_regions_outside_markers_are_blanked() {
+    let embedded = EmbeddedDocuments::new(&[
+        EmbeddedRule {
+            extensions: vec!["md".to_string()],
+            start: Some(r"^```c\n".to_string()),
+            end: Some(r"^```$".to_string()),
+            ..EmbeddedRule::default()
+        },
+        EmbeddedRule {
+            extensions: vec!["bad".to_string()],
+            start: Some("(".to_string()),
+            ..EmbeddedRule::default()
+        },
+    ]);
+    assert!(!embedded.is_host_sync("textDocument/didOpen", &did_open("file:///a.bad", "")));
+    assert!(embedded.is_host_sync("textDocument/didOpen", &did_open("file:///a.MD", "")));
+
+    let open = embedded
+        .to_virtual_document(
+            "textDocument/didOpen",
+            &did_open("file:///a.md", "# 例子\n```c\nint x;\n```\n"),
+        )
+        .unwrap();
+    assert_eq!(open["params"]["textDocument"]["uri"], "file:///a.md.cpp");
+    assert_eq!(open["params"]["textDocument"]["languageId"], "cpp");
+    // “例子”各占一个 UTF-16 单元，替换后列位置不变
+    assert_eq!(
+        open["params"]["textDocument"]["text"],
+        "    \n    \nint x;\n   \n"
+    );
+}
+
+#[test]
+fn test_sketch_gets_prelude_and_incremental_changes() {
+    let embedded = EmbeddedDocuments::new(&[arduino()]);
+    let open = embedded
+        .to_virtual_document(
+            "textDocument/didOpen",
+            &did_open(SKETCH, "void setup() {}\n"),
+        )
+        .unwrap();
+    assert_eq!(
+        open["params"]["textDocument"]["text"],
+        "#include <Arduino.h>\nvoid setup() {}\n"
+    );
+    let host = SKETCH.parse().unwrap();
+    assert_eq!(embedded.locate(&host), Some((VIRTUAL.parse().unwrap(), 1)));
+
+    let change = embedded
+        .to_virtual_document(
+            "textDocument/didChange",
+            &json!({"textDocument": {"uri": SKETCH, "version": 2},
+                    "contentChanges": [{"range": range((0, 14), (0, 14)), "text": " pinMode(13, OUTPUT); "}]}),
+        )
+        .unwrap();
+    assert_eq!(change["params"]["textDocument"]["uri"], VIRTUAL);
+    assert_eq!(
+        change["params"]["contentChanges"][0]["text"],
+        "#include <Arduino.h>\nvoid setup() { pinMode(13, OUTPUT); }\n"
+    );
+
+    let close = embedded
+        .to_virtual_document(
+            "textDocument/didClose",
+            &json!({"textDocument": {"uri": SKETCH}}),
+        )
+        .unwrap();
+    assert_eq!(close["params"]["textDocument"]["uri"], VIRTUAL);
+    assert!(embedded.locate(&host).is_none());
+}
+
+#[tokio::test]
+async fn test_dispatcher_maps_sketch_positions() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config {
+        embedded: vec![arduino()],
+        ..Config::default()
+    };
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": did_open(SKETCH, "void setup() {}\nvoid loop() { setup(); }\n")}))
+        .await
+        .unwrap();
+    let open = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(open["params"]["textDocument"]["uri"], VIRTUAL);
+
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/definition",
+            "params": {"textDocument": {"uri": SKETCH}, "position": {"line": 1, "character": 15}}}),
+        )
+        .await
+        .unwrap();
+    let request = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(request["params"]["textDocument"]["uri"], VIRTUAL);
+    assert_eq!(request["params"]["position"]["line"], 2);
+    dispatcher
+        .handle_from_shard(
+            0,
+            json!({"jsonrpc": "2.0", "id": 1, "result": [{"uri": VIRTUAL, "range": range((1, 5), (1, 10))}]}),
+        )
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["result"][0]["uri"], SKETCH);
+    assert_eq!(response["result"][0]["range"], range((0, 5), (0, 10)));
+
+    // 前导代码中的诊断放到宿主文件的第一行
+    dispatcher
+        .handle_from_shard(
+            0,
+            json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": {
+                "uri": VIRTUAL,
+                "diagnostics": [
+                    {"range": range((0, 10), (0, 19)), "message": "'Arduino.h' file not found"},
+                    {"range": range((2, 14), (2, 19)), "message": "unused result"},
+                ],
+            }}),
+        )
+        .await
+        .unwrap();
+    let published = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(published["params"]["uri"], SKETCH);
+    let diagnostics = published["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 0);
+    assert_eq!(diagnostics[1]["range"], range((1, 14), (1, 19)));
+}
@@ -0,0 +1,73 @@
+use lsp_proxy::config::Config;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::events::{ProxyEvent, RestartReason};
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+fn tokens(id: i32) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": "textDocument/semanticTokens/full", "params": {
+        "textDocument": {"uri": "file:///a.cpp"}
+    }})
+}
+
+#[test]
+fn test_event_notification() {
+    let event = ProxyEvent::BackendRestarted {
+        shard: "default".to_string(),
+        reason: RestartReason::Memory,
+    };
+    let notification = event.notification();
+    assert_eq!(notification["method"], "telemetry/event");
+    assert_eq!(notification["params"]["source"], "codefuse");
+    assert_eq!(notification["params"]["event"], "backendRestarted");
+    assert_eq!(
+        notification["params"]["properties"],
+        json!({"shard": "default", "reason": "memory"})
+    );
+    assert!(notification["params"]["timestamp"].is_string());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_budget_exhausted_is_reported_once() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse(
+        r#"
+        [telemetry]
+        events = true
+        [rate_limits."textDocument/semanticTokens/full"]
+        per_second = 1
+        "#,
+    )
+    .unwrap();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+
+    dispatcher.handle_from_frontend(tokens(1)).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    for id in [2, 3] {
+        dispatcher.handle_from_frontend(tokens(id)).await.unwrap();
+    }
+
+    let event = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(event["params"]["event"], "budgetExhausted");
+    assert_eq!(
+        event["params"]["properties"]["method"],
+        "textDocument/semanticTokens/full"
+    );
+    let mut ids = Vec::new();
+    while let Ok(message) = frontend_rx.try_recv() {
+        ids.push(message.into_body()["id"].clone());
+    }
+    assert_eq!(ids, vec![json!(2), json!(3)]);
+}
+
+#[tokio::test]
+async fn test_events_are_opt_in() {
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Dispatcher::new(backend_tx, frontend_tx);
+    dispatcher.emit_event(ProxyEvent::CacheCleared { cache: "prefetch" });
+    assert!(frontend_rx.try_recv().is_err());
+}
@@ -0,0 +1,63 @@
+use lsp_proxy::document_store::DocumentStore;
+use lsp_proxy::fallback::{Arrival, Cursor, FallbackRequests};
+use serde_json::json;
+use tower_lsp::lsp_types::{Position, Url};
+
+#[test]
+fn test_requests_are_answered_once() {
+    let requests = FallbackRequests::default();
+    requests.insert(&json!(1), "a");
+    requests.insert(&json!(2), "b");
+
+    // 后端先应答
+    assert_eq!(requests.arrive(&json!(1)), Arrival::Waiting("a"));
+    assert_eq!(requests.expire(&json!(1), 0), None);
+
+    // 代理先应答，后端迟到的响应丢弃，之后不再跟踪
+    assert_eq!(requests.expire(&json!(2), 0), Some("b"));
+    assert_eq!(requests.expire(&json!(2), 0), None);
+    assert_eq!(requests.arrive(&json!(2)), Arrival::Late);
+    assert_eq!(requests.arrive(&json!(2)), Arrival::Untracked);
+    assert!(requests.is_empty());
+}
+
+#[test]
+fn test_backend_exit_prunes_answered_requests() {
+    let requests = FallbackRequests::default();
+    for id in 1..=3 {
+        requests.insert(&json!(id), ());
+    }
+    requests.expire(&json!(1), 0);
+    requests.expire(&json!(2), 1);
+
+    // 等待中的请求会重放给新的后端，仍然跟踪
+    requests.backend_exited(0);
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests.arrive(&json!(1)), Arrival::Untracked);
+    assert_eq!(requests.arrive(&json!(2)), Arrival::Late);
+    assert_eq!(requests.arrive(&json!(3)), Arrival::Waiting(()));
+}
+
+#[test]
+fn test_cursor_ignores_changed_document() {
+    let documents = DocumentStore::new();
+    let uri = Url::parse("file:///src/a.cpp").unwrap();
+    documents.apply(
+        "textDocument/didOpen",
+        &json!({"textDocument": {"uri": uri, "languageId": "cpp", "version": 1, "text": "int x;"}}),
+    );
+    let cursor = Cursor::new(&documents, uri.clone(), Position::new(0, 4));
+    assert_eq!(cursor.document(&documents).unwrap().text, "int x;");
+
+    documents.apply(
+        "textDocument/didChange",
+        &json!({"textDocument": {"uri": uri, "version": 2},
+            "contentChanges": [{"text": "long y;"}]}),
+    );
+    assert!(cursor.document(&documents).is_none());
+
+    let closed = Url::parse("file:///src/b.cpp").unwrap();
+    let cursor = Cursor::new(&documents, closed, Position::new(0, 0));
+    assert_eq!(cursor.version, None);
+    assert!(cursor.document(&documents).is_none());
+}
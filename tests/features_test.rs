@@ -0,0 +1,89 @@
+use lsp_proxy::config::Config;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::features::{Feature, FeatureSwitches};
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+fn did_change_configuration(settings: Value) -> Value {
+    json!({"jsonrpc": "2.0", "method": "workspace/didChangeConfiguration",
+        "params": {"settings": settings}})
+}
+
+#[test]
+fn test_apply_settings() {
+    let switches = FeatureSwitches::new();
+    assert!(Feature::ALL.iter().all(|f| switches.is_enabled(*f)));
+
+    let changed = switches.apply(&json!({"codefuse": {"prefetch": false, "spellcheck": true}}));
+    assert_eq!(changed, vec![(Feature::Prefetch, false)]);
+    assert!(!switches.is_enabled(Feature::Prefetch));
+
+    // 展开的键与嵌套的配置节合并
+    let changed = switches.apply(&json!({
+        "codefuse": {"prefetch": false},
+        "codefuse.fallbacks": false,
+    }));
+    assert_eq!(changed, vec![(Feature::Fallbacks, false)]);
+    assert_eq!(
+        switches.disabled(),
+        vec![Feature::Prefetch, Feature::Fallbacks]
+    );
+
+    // 其他扩展的设置不改变开关
+    assert!(
+        switches
+            .apply(&json!({"clangd": {"fallbackFlags": []}}))
+            .is_empty()
+    );
+    assert!(switches.apply(&Value::Null).is_empty());
+    assert_eq!(switches.disabled().len(), 2);
+
+    // 删除的键恢复配置文件的设置
+    let changed = switches.apply(&json!({"codefuse": {}}));
+    assert_eq!(
+        changed,
+        vec![(Feature::Prefetch, true), (Feature::Fallbacks, true)]
+    );
+}
+
+#[tokio::test]
+async fn test_editor_settings_turn_off_fallbacks() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse("[document_highlight]\nfallback = true").unwrap();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    let highlight = |id: u64| {
+        json!({"jsonrpc": "2.0", "id": id, "method": "textDocument/documentHighlight",
+            "params": {"textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 0}}})
+    };
+
+    // 后端没有运行时代理立即应答
+    dispatcher.handle_from_frontend(highlight(1)).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["id"], 1);
+    let cancel = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(cancel["method"], "$/cancelRequest");
+
+    // 设置本身仍然交给后端
+    dispatcher
+        .handle_from_frontend(did_change_configuration(
+            json!({"codefuse": {"fallbacks": false}}),
+        ))
+        .await
+        .unwrap();
+    let forwarded = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(forwarded["method"], "workspace/didChangeConfiguration");
+    assert_eq!(
+        forwarded["params"]["settings"]["codefuse"]["fallbacks"],
+        false
+    );
+
+    dispatcher.handle_from_frontend(highlight(2)).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    // 关闭兜底后代理不再应答，请求等待后端
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    assert!(frontend_rx.try_recv().is_err());
+}
@@ -0,0 +1,161 @@
+use lsp_proxy::config::{Config, FileStatusConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::document_observer::DocumentObserver;
+use lsp_proxy::file_status::{BusyFile, FileStatus};
+use lsp_proxy::index_progress::Step;
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::{DidCloseTextDocumentParams, TextDocumentIdentifier, Url};
+
+fn with_progress() -> FileStatus {
+    FileStatus::new(&FileStatusConfig {
+        enabled: true,
+        progress: true,
+    })
+}
+
+fn status(uri: &str, state: &str) -> Value {
+    json!({"uri": uri, "state": state})
+}
+
+fn value(progress: &Value) -> &Value {
+    &progress["params"]["value"]
+}
+
+#[test]
+fn test_requests_file_status_in_initialize() {
+    let file_status = with_progress();
+    let mut params = json!({"processId": null, "capabilities": {}});
+    file_status.on_initialize(&mut params);
+    assert_eq!(params["initializationOptions"]["clangdFileStatus"], true);
+    // 前端没有请求，通知不转发给前端
+    assert!(!file_status.forwards());
+
+    let mut params =
+        json!({"initializationOptions": {"clangdFileStatus": true, "fallbackFlags": []}});
+    file_status.on_initialize(&mut params);
+    assert_eq!(params["initializationOptions"]["fallbackFlags"], json!([]));
+    assert!(file_status.forwards());
+
+    // 关闭时不改写参数，通知原样转发
+    let disabled = FileStatus::default();
+    let mut params = json!({"capabilities": {}});
+    disabled.on_initialize(&mut params);
+    assert!(params.get("initializationOptions").is_none());
+    assert!(disabled.forwards());
+}
+
+#[test]
+fn test_status_summary() {
+    let file_status = FileStatus::new(&FileStatusConfig {
+        enabled: true,
+        progress: false,
+    });
+    assert_eq!(
+        file_status.observe(&status("file:///a.cpp", "building preamble")),
+        Step::Nothing
+    );
+    file_status.observe(&status("file:///b.cpp", "idle"));
+    file_status.observe(&status("file:///c.cpp", "idle"));
+
+    let summary = file_status.status();
+    assert_eq!(
+        summary.busy,
+        vec![BusyFile {
+            uri: "file:///a.cpp".to_string(),
+            state: "building preamble".to_string(),
+        }]
+    );
+    assert_eq!(summary.states["idle"], 2);
+    assert_eq!(summary.states["building preamble"], 1);
+
+    file_status.did_close(&DidCloseTextDocumentParams {
+        text_document: TextDocumentIdentifier {
+            uri: Url::parse("file:///a.cpp").unwrap(),
+        },
+    });
+    let summary = file_status.status();
+    assert!(summary.busy.is_empty());
+    assert!(!summary.states.contains_key("building preamble"));
+}
+
+#[test]
+fn test_progress_follows_busy_files() {
+    let file_status = with_progress();
+    assert_eq!(
+        file_status.observe(&status("file:///src/main.cpp", "parsing includes")),
+        Step::Create
+    );
+    assert_eq!(
+        file_status.observe(&status("file:///src/main.cpp", "building preamble")),
+        Step::Nothing
+    );
+    let sent = file_status.created(Some("t".to_string()));
+    assert_eq!(sent.len(), 1);
+    assert_eq!(value(&sent[0])["message"], "main.cpp：building preamble…");
+
+    let Step::Send(sent) = file_status.observe(&status("file:///src/util.cpp", "building AST"))
+    else {
+        panic!("应该报告新的状态");
+    };
+    assert_eq!(
+        value(&sent)["message"],
+        "util.cpp：building AST…（另有 1 个文件）"
+    );
+
+    file_status.observe(&status("file:///src/main.cpp", "idle"));
+    let Step::Send(sent) = file_status.observe(&status("file:///src/util.cpp", "idle")) else {
+        panic!("所有文件空闲时应该结束进度");
+    };
+    assert_eq!(value(&sent)["kind"], "end");
+
+    // 关闭最后一个正在处理的文件时结束进度
+    assert_eq!(
+        file_status.observe(&status("file:///src/main.cpp", "building AST")),
+        Step::Create
+    );
+    file_status.created(Some("u".to_string()));
+    file_status.did_close(&DidCloseTextDocumentParams {
+        text_document: TextDocumentIdentifier {
+            uri: Url::parse("file:///src/main.cpp").unwrap(),
+        },
+    });
+    let Step::Send(sent) = file_status.refresh() else {
+        panic!("应该结束进度");
+    };
+    assert_eq!(value(&sent)["kind"], "end");
+}
+
+#[tokio::test]
+async fn test_dispatcher_swallows_file_status() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse("[file_status]\nenabled = true").unwrap();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    let initialize = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+        "processId": null, "rootUri": null, "capabilities": {},
+    }});
+    dispatcher.handle_from_frontend(initialize).await.unwrap();
+    let forwarded = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(
+        forwarded["params"]["initializationOptions"]["clangdFileStatus"],
+        true
+    );
+
+    let notification = json!({"jsonrpc": "2.0", "method": "textDocument/clangd.fileStatus",
+        "params": {"uri": "file:///a.cpp", "state": "parsing includes"}});
+    dispatcher.handle_from_shard(0, notification).await.unwrap();
+    assert!(frontend_rx.try_recv().is_err());
+
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": 2, "method": "codefuse/status"}))
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["id"], 2);
+    assert_eq!(response["result"]["busy"][0]["state"], "parsing includes");
+    assert_eq!(response["result"]["states"]["parsing includes"], 1);
+    assert_eq!(response["result"]["index"], Value::Null);
+}
@@ -0,0 +1,166 @@
+use lsp_proxy::file_watcher::{FileWatcher, glob_pattern};
+use lsp_proxy::message::Message;
+use notify::event::{CreateKind, DataChange, EventKind, ModifyKind, RemoveKind};
+use serde_json::{Value, json};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tower_lsp::lsp_types::{FileChangeType, FileEvent, Url};
+
+/// 由代理接管文件监视的组件，监视 `root`。
+fn watcher(root: &Path) -> (Arc<FileWatcher>, UnboundedReceiver<Message>) {
+    let (backend_tx, backend_rx) = mpsc::unbounded_channel::<Message>();
+    let watcher = Arc::new(FileWatcher::new(backend_tx));
+    let mut params = json!({"capabilities": {}});
+    assert!(watcher.enable_for_client(&mut params, vec![root.to_path_buf()]));
+    assert_eq!(
+        params["capabilities"]["workspace"]["didChangeWatchedFiles"]["dynamicRegistration"],
+        true
+    );
+    (watcher, backend_rx)
+}
+
+fn watched_files(id: &str, watchers: Value) -> Value {
+    json!({"id": id, "method": "workspace/didChangeWatchedFiles",
+        "registerOptions": {"watchers": watchers}})
+}
+
+fn register(id: i64, registrations: Vec<Value>) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": "client/registerCapability",
+        "params": {"registrations": registrations}})
+}
+
+fn event(kind: EventKind, path: &Path) -> notify::Event {
+    notify::Event::new(kind).add_path(path.to_path_buf())
+}
+
+#[test]
+fn test_glob_pattern() {
+    assert_eq!(
+        glob_pattern(&json!("compile_commands.json")).as_deref(),
+        Some("**/compile_commands.json")
+    );
+    assert_eq!(glob_pattern(&json!("**/*.h")).as_deref(), Some("**/*.h"));
+    assert_eq!(
+        glob_pattern(&json!("/repo/.clangd")).as_deref(),
+        Some("/repo/.clangd")
+    );
+
+    // RelativePattern 的 baseUri 可以是 URI，也可以是工作区文件夹
+    let relative = json!({"baseUri": "file:///repo", "pattern": "build/*.json"});
+    assert_eq!(
+        glob_pattern(&relative).as_deref(),
+        Some("/repo/build/*.json")
+    );
+    let folder = json!({"baseUri": {"uri": "file:///repo", "name": "repo"}, "pattern": "*.h"});
+    assert_eq!(glob_pattern(&folder).as_deref(), Some("/repo/*.h"));
+    assert_eq!(
+        glob_pattern(&json!({"baseUri": "https://example.com/", "pattern": "*.h"})),
+        None
+    );
+    assert_eq!(glob_pattern(&json!(3)), None);
+}
+
+#[tokio::test]
+async fn test_changes_are_filtered_by_kind_and_pattern() {
+    let root = tempfile::tempdir().unwrap();
+    let (watcher, mut backend_rx) = watcher(root.path());
+    // 只关心创建和删除（WatchKind: Create = 1, Delete = 4）
+    let registration = watched_files(
+        "w",
+        json!([{"globPattern": "**/compile_commands.json", "kind": 5}]),
+    );
+    assert_eq!(
+        watcher
+            .handle_register_capability(register(1, vec![registration]))
+            .unwrap(),
+        None
+    );
+    assert_eq!(backend_rx.recv().await.unwrap().into_body()["id"], 1);
+
+    let database = root.path().join("build/compile_commands.json");
+    let uri = Url::from_file_path(&database).unwrap();
+    assert_eq!(
+        watcher.changes_for(&event(EventKind::Create(CreateKind::File), &database)),
+        vec![FileEvent::new(uri.clone(), FileChangeType::CREATED)]
+    );
+    assert_eq!(
+        watcher.changes_for(&event(EventKind::Remove(RemoveKind::File), &database)),
+        vec![FileEvent::new(uri, FileChangeType::DELETED)]
+    );
+    let modified = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+    assert!(watcher.changes_for(&event(modified, &database)).is_empty());
+    // 不匹配注册的模式
+    let source = root.path().join("main.cpp");
+    assert!(
+        watcher
+            .changes_for(&event(EventKind::Create(CreateKind::File), &source))
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_other_registrations_pass_through() {
+    let root = tempfile::tempdir().unwrap();
+    let (watcher, mut backend_rx) = watcher(root.path());
+    let formatting = json!({"id": "f", "method": "textDocument/formatting"});
+    let request = register(
+        1,
+        vec![
+            watched_files("w", json!([{"globPattern": "**/*.h"}])),
+            formatting.clone(),
+        ],
+    );
+
+    // 文件监视的注册由代理处理，其余注册仍然转发给客户端，由客户端应答
+    let forwarded = watcher
+        .handle_register_capability(request)
+        .unwrap()
+        .unwrap();
+    assert_eq!(forwarded["params"]["registrations"], json!([formatting]));
+    assert!(backend_rx.try_recv().is_err());
+
+    // 不是注册请求时原样返回
+    let other = register(2, vec![formatting.clone()]);
+    assert_eq!(
+        watcher.handle_register_capability(other.clone()).unwrap(),
+        Some(other)
+    );
+
+    let unregister = |id: i64, ids: &[&str]| {
+        let unregisterations: Vec<Value> = ids
+            .iter()
+            .map(|id| json!({"id": id, "method": "workspace/didChangeWatchedFiles"}))
+            .collect();
+        json!({"jsonrpc": "2.0", "id": id, "method": "client/unregisterCapability",
+            "params": {"unregisterations": unregisterations}})
+    };
+    // 代理不认识的注销转发给客户端
+    let forwarded = watcher
+        .handle_unregister_capability(unregister(3, &["w", "f"]))
+        .unwrap()
+        .unwrap();
+    assert_eq!(forwarded["params"]["unregisterations"][0]["id"], "f");
+    assert_eq!(
+        forwarded["params"]["unregisterations"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+    let unknown = unregister(4, &["f"]);
+    assert_eq!(
+        watcher
+            .handle_unregister_capability(unknown.clone())
+            .unwrap(),
+        Some(unknown)
+    );
+
+    // 注销之后不再生成变更
+    let header = root.path().join("a.h");
+    assert!(
+        watcher
+            .changes_for(&event(EventKind::Create(CreateKind::File), &header))
+            .is_empty()
+    );
+}
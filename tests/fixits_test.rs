@@ -0,0 +1,104 @@
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use lsp_proxy::commands;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::fixits::{compose_workspace_edit, workspace_edit_edits};
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+
+fn parse_message(message: &Message) -> Value {
+    message.body().clone()
+}
+
+fn edit(line: u32, start: u32, end: u32, text: &str) -> Value {
+    json!({
+        "range": {"start": {"line": line, "character": start}, "end": {"line": line, "character": end}},
+        "newText": text
+    })
+}
+
+#[test]
+fn test_compose_skips_overlapping_fixes() {
+    let uri = "file:///repo/a.cpp";
+    let include = edit(0, 0, 0, "#include <vector>\n");
+    let fixes = vec![
+        workspace_edit_edits(
+            &json!({"changes": {uri: [edit(3, 4, 8, "size_t"), include.clone()]}}),
+        ),
+        // 与第一个修复重叠，整组跳过
+        workspace_edit_edits(&json!({"changes": {uri: [edit(3, 6, 10, "x")]}})),
+        // 相同的 #include 只保留一次
+        workspace_edit_edits(&json!({"documentChanges": [
+            {"textDocument": {"uri": uri, "version": 1}, "edits": [include, edit(5, 0, 1, "")]}
+        ]})),
+    ];
+    let (workspace_edit, applied) = compose_workspace_edit(fixes);
+    assert_eq!(applied, 2);
+    let edits = workspace_edit["changes"][uri].as_array().unwrap();
+    let texts: Vec<&str> = edits
+        .iter()
+        .map(|e| e["newText"].as_str().unwrap())
+        .collect();
+    assert_eq!(texts, vec!["#include <vector>\n", "size_t", ""]);
+}
+
+#[tokio::test]
+async fn test_apply_all_fixits_command() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let uri = "file:///repo/a.cpp";
+
+    let diagnostic = json!({
+        "range": {"start": {"line": 1, "character": 2}, "end": {"line": 1, "character": 3}},
+        "message": "expected ';'"
+    });
+    dispatcher
+        .handle_from_backend(json!({
+            "jsonrpc": "2.0", "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": [diagnostic]}
+        }))
+        .await
+        .unwrap();
+    frontend_rx.recv().await.unwrap();
+
+    let command = Arc::clone(&dispatcher);
+    let execute = tokio::spawn(async move {
+        command
+            .handle_from_frontend(json!({
+                "jsonrpc": "2.0", "id": 9, "method": "workspace/executeCommand",
+                "params": {"command": commands::APPLY_ALL_FIXITS, "arguments": []}
+            }))
+            .await
+            .unwrap();
+    });
+
+    // 后端为诊断返回修复
+    let request = parse_message(&backend_rx.recv().await.unwrap());
+    assert_eq!(request["method"], "textDocument/codeAction");
+    assert_eq!(request["params"]["context"]["only"], json!(["quickfix"]));
+    dispatcher
+        .handle_from_backend(json!({"jsonrpc": "2.0", "id": request["id"], "result": [
+            {"title": "insert ';'", "kind": "quickfix", "isPreferred": true,
+             "edit": {"changes": {uri: [edit(1, 3, 3, ";")]}}}
+        ]}))
+        .await
+        .unwrap();
+
+    // 前端收到 applyEdit 请求并应答
+    let apply = parse_message(&frontend_rx.recv().await.unwrap());
+    assert_eq!(apply["method"], "workspace/applyEdit");
+    assert_eq!(apply["params"]["edit"]["changes"][uri][0]["newText"], ";");
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "id": apply["id"], "result": {"applied": true}}),
+        )
+        .await
+        .unwrap();
+
+    execute.await.unwrap();
+    let response = parse_message(&frontend_rx.recv().await.unwrap());
+    assert_eq!(response["id"], 9);
+    assert_eq!(response["result"], json!({"applied": true, "fixes": 1}));
+}
@@ -0,0 +1,75 @@
+use bytes::BytesMut;
+use futures::TryStreamExt;
+use lsp_proxy::codec::{LspCodec, MAX_MESSAGE_SIZE};
+use serde_json::json;
+use tokio_util::codec::{Decoder, FramedRead};
+
+#[tokio::test]
+async fn test_oversized_content_length_is_rejected() {
+    let input = format!("Content-Length: {}\r\n\r\n{{}}", MAX_MESSAGE_SIZE + 1);
+    let mut reader = FramedRead::new(input.as_bytes(), LspCodec::default());
+    let error = reader.try_next().await.unwrap_err();
+    assert!(error.to_string().contains("消息过大"));
+}
+
+#[tokio::test]
+async fn test_garbage_is_skipped_until_next_message() {
+    // 被截断的消息吞掉了下一条消息的开头，再后面的消息仍然可以读出
+    let frame = |body: &str| format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let lost = r#"{"jsonrpc":"2.0","method":"lost","params":{}}"#;
+    let body = r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#;
+    let input = format!(
+        "Content-Length: 40\r\n\r\n{{\"jsonrpc\":\"2.0\"{}{}",
+        frame(lost),
+        frame(body)
+    );
+    let mut reader = FramedRead::new(input.as_bytes(), LspCodec::default());
+    let message = reader.try_next().await.unwrap().unwrap();
+    assert_eq!(
+        message,
+        json!({"jsonrpc": "2.0", "method": "initialized", "params": {}})
+    );
+    assert!(reader.try_next().await.unwrap().is_none());
+}
+
+#[test]
+fn test_message_split_across_reads() {
+    // 消息头和消息体可能在任意位置被分成多次读取
+    let body = r#"{"jsonrpc":"2.0","id":1,"result":null}"#;
+    let input = format!("Content-Length: {}\r\n\r\n{}", body.len(), body).repeat(2);
+    let mut codec = LspCodec::default();
+    let mut buffer = BytesMut::new();
+    let mut messages = Vec::new();
+    for byte in input.bytes() {
+        buffer.extend_from_slice(&[byte]);
+        if let Some(message) = codec.decode(&mut buffer).unwrap() {
+            messages.push(message);
+        }
+    }
+    assert_eq!(messages.len(), 2);
+    assert_eq!(
+        messages[1],
+        json!({"jsonrpc": "2.0", "id": 1, "result": null})
+    );
+    assert!(buffer.is_empty());
+}
+
+#[tokio::test]
+async fn test_line_ending_and_header_case_variants() {
+    // Windows 文本模式会把 \n 转换成 \r\n，已经是 \r\n 的行尾变成 \r\r\n
+    let body = r#"{"jsonrpc":"2.0","id":1,"result":null}"#;
+    let input = [
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body),
+        format!("Content-Length: {}\n\n{}", body.len(), body),
+        format!("content-length: {}\r\r\n\r\r\n{}", body.len(), body),
+        format!(
+            "Content-Type: application/vscode-jsonrpc; charset=utf-8\r\nCONTENT-LENGTH: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+    ]
+    .concat();
+    let reader = FramedRead::new(input.as_bytes(), LspCodec::default());
+    let messages: Vec<_> = reader.try_collect().await.unwrap();
+    assert_eq!(messages.len(), 4);
+}
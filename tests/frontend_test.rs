@@ -0,0 +1,101 @@
+use lsp_proxy::cli::CliArgs;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::frontend::{FrontendMode, TypedFrontend};
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tower_lsp::LanguageServer;
+use tower_lsp::jsonrpc::ErrorCode;
+use tower_lsp::lsp_types::{
+    HoverContents, HoverParams, InitializeParams, MarkedString, Position, TextDocumentIdentifier,
+    TextDocumentPositionParams, Url,
+};
+
+fn hover_params() -> HoverParams {
+    HoverParams {
+        text_document_position_params: TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: Url::parse("file:///a.cpp").unwrap(),
+            },
+            position: Position::new(0, 4),
+        },
+        work_done_progress_params: Default::default(),
+    }
+}
+
+/// 等待转发给后端的请求，以 `result` 应答。
+async fn answer(
+    dispatcher: &Arc<Dispatcher>,
+    backend_rx: &mut mpsc::UnboundedReceiver<Message>,
+    result: Value,
+) -> Value {
+    let request = backend_rx.recv().await.unwrap().into_body();
+    let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": result});
+    dispatcher.handle_from_backend(response).await.unwrap();
+    request
+}
+
+#[test]
+fn test_parse_frontend_mode() {
+    let args = CliArgs::parse(["--frontend", "typed"].map(String::from)).unwrap();
+    assert_eq!(args.frontend, FrontendMode::Typed);
+    assert_eq!(CliArgs::parse([]).unwrap().frontend, FrontendMode::Raw);
+    assert!(CliArgs::parse(["--frontend", "json"].map(String::from)).is_err());
+}
+
+#[tokio::test]
+async fn test_typed_requests_are_forwarded_to_backend() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let (service, _socket, relay) = TypedFrontend::builder(Arc::clone(&dispatcher), frontend_rx)
+        .with_capabilities(|capabilities| capabilities.hover_provider = None)
+        .build();
+    tokio::spawn(relay.run(CancellationToken::new()));
+    let frontend = service.inner();
+
+    let (initialized, request) = tokio::join!(
+        frontend.initialize(InitializeParams::default()),
+        answer(
+            &dispatcher,
+            &mut backend_rx,
+            json!({"capabilities": {"hoverProvider": true, "definitionProvider": true}}),
+        )
+    );
+    assert_eq!(request["method"], "initialize");
+    let capabilities = initialized.unwrap().capabilities;
+    assert_eq!(capabilities.hover_provider, None);
+    assert!(capabilities.definition_provider.is_some());
+
+    let (hover, request) = tokio::join!(
+        frontend.hover(hover_params()),
+        answer(&dispatcher, &mut backend_rx, json!({"contents": "int x"})),
+    );
+    assert_eq!(request["method"], "textDocument/hover");
+    assert_eq!(
+        request["params"]["position"],
+        json!({"line": 0, "character": 4})
+    );
+    assert_eq!(
+        hover.unwrap().unwrap().contents,
+        HoverContents::Scalar(MarkedString::String("int x".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn test_malformed_backend_result_is_internal_error() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let (service, _socket, relay) =
+        TypedFrontend::builder(Arc::clone(&dispatcher), frontend_rx).build();
+    tokio::spawn(relay.run(CancellationToken::new()));
+
+    let (hover, _) = tokio::join!(
+        service.inner().hover(hover_params()),
+        answer(&dispatcher, &mut backend_rx, json!({"contents": 42})),
+    );
+    assert_eq!(hover.unwrap_err().code, ErrorCode::InternalError);
+}
@@ -0,0 +1,248 @@
+use futures::future::BoxFuture;
+use lsp_proxy::config::Config;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::handlers::{HandlerCtx, MethodPattern, Verdict, empty_result, setup_handlers};
+use lsp_proxy::message::Message;
+use lsp_proxy::trace::Direction;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::Url;
+use tower_lsp::lsp_types::request::HoverRequest;
+
+/// 用打开的文档和配置代替后端应答悬停请求。
+fn answer_hover(rpc: Value, ctx: HandlerCtx<'_>) -> BoxFuture<'_, anyhow::Result<Verdict>> {
+    Box::pin(async move {
+        let uri = Url::parse(rpc["params"]["textDocument"]["uri"].as_str().unwrap())?;
+        let text = ctx.documents().get(&uri).map(|doc| doc.text.clone());
+        Ok(Verdict::Respond(
+            json!({"jsonrpc": "2.0", "id": rpc["id"], "result": {
+                "text": text,
+                "slowRequestMs": ctx.config().logging.slow_request_ms,
+                "shard": ctx.shard,
+                "tags": rpc["params"]["tags"],
+            }}),
+        ))
+    })
+}
+
+/// 在参数中追加标记后交给下一个处理器。
+fn tag(name: &'static str) -> impl Fn(Value) -> Verdict {
+    move |mut rpc: Value| {
+        let tags = rpc["params"]
+            .as_object_mut()
+            .unwrap()
+            .entry("tags")
+            .or_insert(json!([]));
+        tags.as_array_mut().unwrap().push(json!(name));
+        Verdict::Continue(rpc)
+    }
+}
+
+fn tag_first(rpc: Value, _ctx: HandlerCtx<'_>) -> BoxFuture<'_, anyhow::Result<Verdict>> {
+    Box::pin(async move { Ok(tag("first")(rpc)) })
+}
+
+fn tag_second(rpc: Value, _ctx: HandlerCtx<'_>) -> BoxFuture<'_, anyhow::Result<Verdict>> {
+    Box::pin(async move { Ok(tag("second")(rpc)) })
+}
+
+fn drop_message(_rpc: Value, _ctx: HandlerCtx<'_>) -> BoxFuture<'_, anyhow::Result<Verdict>> {
+    Box::pin(async move { Ok(Verdict::Drop) })
+}
+
+fn hover(id: i32) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": "textDocument/hover", "params": {
+        "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 4}
+    }})
+}
+
+#[tokio::test]
+async fn test_frontend_handler_sees_shared_services() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse("[logging]\nslow_request_ms = 250\n").unwrap();
+    let mut dispatcher = Dispatcher::new(backend_tx, frontend_tx).with_config(config);
+    dispatcher.register_req_from_frontend::<HoverRequest>(answer_hover);
+    let dispatcher = Arc::new(dispatcher);
+
+    let did_open = json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": 1, "text": "int x;"}
+    }});
+    dispatcher.handle_from_frontend(did_open).await.unwrap();
+    backend_rx.recv().await.unwrap();
+
+    dispatcher.handle_from_frontend(hover(1)).await.unwrap();
+
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(
+        response["result"],
+        json!({"text": "int x;", "slowRequestMs": 250, "shard": 0, "tags": null})
+    );
+    // 处理器应答之后请求不再转发给后端
+    assert!(backend_rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_handlers_run_in_priority_order_until_verdict() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let mut dispatcher = Dispatcher::new(backend_tx, frontend_tx);
+    // 注册顺序与执行顺序无关，优先级相同时才按注册顺序
+    dispatcher.register_req_from_frontend::<HoverRequest>(answer_hover);
+    dispatcher.register_handler(Direction::Frontend, "textDocument/hover", 10, tag_second);
+    dispatcher.register_handler(Direction::Frontend, "textDocument/hover", 20, tag_first);
+    dispatcher.register_handler(Direction::Frontend, "textDocument/hover", -10, drop_message);
+    dispatcher.register_handler(Direction::Frontend, "textDocument/definition", 0, tag_first);
+    dispatcher.register_handler(
+        Direction::Frontend,
+        "textDocument/definition",
+        0,
+        drop_message,
+    );
+    dispatcher.register_handler(
+        Direction::Frontend,
+        "textDocument/definition",
+        0,
+        tag_second,
+    );
+    let dispatcher = Arc::new(dispatcher);
+
+    dispatcher.handle_from_frontend(hover(1)).await.unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["result"]["tags"], json!(["first", "second"]));
+
+    let mut definition = hover(2);
+    definition["method"] = json!("textDocument/definition");
+    dispatcher.handle_from_frontend(definition).await.unwrap();
+    assert!(frontend_rx.try_recv().is_err());
+    assert!(backend_rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_continue_forwards_modified_message() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let mut dispatcher = Dispatcher::new(backend_tx, frontend_tx);
+    dispatcher.register_req_from_frontend::<HoverRequest>(tag_first);
+    dispatcher.register_req_from_frontend::<HoverRequest>(tag_second);
+    let dispatcher = Arc::new(dispatcher);
+
+    dispatcher.handle_from_frontend(hover(1)).await.unwrap();
+    let forwarded = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(forwarded["params"]["tags"], json!(["first", "second"]));
+}
+
+#[test]
+fn test_method_patterns() {
+    let prefix = MethodPattern::parse("textDocument/*").unwrap();
+    assert!(prefix.matches("textDocument/hover"));
+    assert!(!prefix.matches("workspace/symbol"));
+
+    let regex = MethodPattern::parse("re:^textDocument/(hover|definition)$").unwrap();
+    assert!(regex.matches("textDocument/definition"));
+    assert!(!regex.matches("textDocument/typeDefinition"));
+
+    let exact = MethodPattern::parse("textDocument/hover").unwrap();
+    assert!(exact.matches("textDocument/hover"));
+    assert!(!exact.matches("textDocument/hover2"));
+    assert!(MethodPattern::parse("re:(").is_err());
+}
+
+#[tokio::test]
+async fn test_pattern_handlers_join_exact_handlers_by_priority() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let mut dispatcher = Dispatcher::new(backend_tx, frontend_tx);
+    dispatcher.register_req_from_frontend::<HoverRequest>(tag_second);
+    let prefix = MethodPattern::parse("textDocument/*").unwrap();
+    dispatcher.register_pattern(Direction::Frontend, prefix, 10, tag_first);
+    let other = MethodPattern::parse("re:^workspace/").unwrap();
+    dispatcher.register_pattern(Direction::Frontend, other, 20, drop_message);
+    let dispatcher = Arc::new(dispatcher);
+
+    dispatcher.handle_from_frontend(hover(1)).await.unwrap();
+    let forwarded = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(forwarded["params"]["tags"], json!(["first", "second"]));
+
+    // 只匹配模式的方法也会经过模式处理器
+    let mut definition = hover(2);
+    definition["method"] = json!("textDocument/definition");
+    dispatcher.handle_from_frontend(definition).await.unwrap();
+    let forwarded = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(forwarded["params"]["tags"], json!(["first"]));
+}
+
+#[tokio::test]
+async fn test_stub_methods_are_answered_locally() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse(
+        "[protocol]\nstub_methods = [\"textDocument/documentLink\", \"textDocument/hover\", \"$/custom\"]\n",
+    )
+    .unwrap();
+    let mut dispatcher = Dispatcher::new(backend_tx, frontend_tx).with_config(config);
+    setup_handlers(&mut dispatcher);
+    let dispatcher = Arc::new(dispatcher);
+
+    let mut link = hover(1);
+    link["method"] = json!("textDocument/documentLink");
+    dispatcher.handle_from_frontend(link).await.unwrap();
+    dispatcher.handle_from_frontend(hover(2)).await.unwrap();
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "method": "$/custom", "params": {}}))
+        .await
+        .unwrap();
+
+    let link = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(link, json!({"jsonrpc": "2.0", "id": 1, "result": []}));
+    let hover = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(hover["result"], json!(null));
+    assert!(backend_rx.try_recv().is_err());
+    assert_eq!(
+        empty_result("textDocument/semanticTokens/full"),
+        json!({"data": []})
+    );
+}
+
+fn answer_metrics(rpc: Value, _ctx: HandlerCtx<'_>) -> BoxFuture<'_, anyhow::Result<Verdict>> {
+    Box::pin(async move {
+        Ok(Verdict::Respond(
+            json!({"jsonrpc": "2.0", "id": rpc["id"], "result": "custom"}),
+        ))
+    })
+}
+
+#[tokio::test]
+async fn test_builtin_methods_are_registered_handlers() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let mut dispatcher = Dispatcher::new(backend_tx, frontend_tx);
+    let patterns: Vec<String> = dispatcher
+        .handler_patterns(Direction::Frontend)
+        .into_iter()
+        .map(|(pattern, _)| pattern)
+        .collect();
+    for method in [
+        "*",
+        "codefuse/metrics",
+        "textDocument/codeLens",
+        "workspace/symbol",
+    ] {
+        assert!(patterns.iter().any(|p| p == method), "{} 没有注册", method);
+    }
+    // 代理自己的方法与其他处理器一样按优先级执行，可以被优先级更高的处理器接管
+    dispatcher.register_handler(Direction::Frontend, "codefuse/metrics", 10, answer_metrics);
+    let dispatcher = Arc::new(dispatcher);
+
+    let metrics = json!({"jsonrpc": "2.0", "id": 1, "method": "codefuse/metrics"});
+    dispatcher.handle_from_frontend(metrics).await.unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["result"], json!("custom"));
+
+    let stats = json!({"jsonrpc": "2.0", "id": 2, "method": "codefuse/stats"});
+    dispatcher.handle_from_frontend(stats).await.unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert!(response["result"].is_object());
+    assert!(backend_rx.try_recv().is_err());
+}
@@ -0,0 +1,78 @@
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::health;
+use lsp_proxy::message::Message;
+
+async fn get(address: std::net::SocketAddr, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.lines().next().unwrap().to_string();
+    (status, body.to_string())
+}
+
+#[tokio::test]
+async fn test_healthz_reports_backends_and_queues() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let (address, _server) = health::serve(0, Arc::clone(&dispatcher)).await.unwrap();
+
+    // 后端启动之前状态是 degraded
+    let (status, body) = get(address, "/healthz").await;
+    assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+    let report: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(report["backends"][0]["alive"], json!(false));
+    assert_eq!(report["backends"][0]["lastResponseAgeMs"], json!(null));
+
+    dispatcher.health().set_alive(0, true);
+    let hover = json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": {
+        "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 0}
+    }});
+    dispatcher.handle_from_frontend(hover).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    let (status, body) = get(address, "/healthz").await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let report: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(report["status"], json!("ok"));
+    assert_eq!(report["queues"]["pendingBackendRequests"], json!(1));
+
+    dispatcher
+        .handle_from_backend(json!({"jsonrpc": "2.0", "id": 1, "result": null}))
+        .await
+        .unwrap();
+    let (_, body) = get(address, "/healthz").await;
+    let report: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(report["queues"]["pendingBackendRequests"], json!(0));
+    assert!(report["backends"][0]["lastResponseAgeMs"].is_u64());
+
+    let (status, _) = get(address, "/metrics").await;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_idle_connection_is_closed() {
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let (address, _server) = health::serve(0, Arc::clone(&dispatcher)).await.unwrap();
+
+    // 连接后不发送请求，超时后代理关闭连接，不再占用任务
+    let mut idle = TcpStream::connect(address).await.unwrap();
+    let mut response = Vec::new();
+    let closed =
+        tokio::time::timeout(health::READ_TIMEOUT * 2, idle.read_to_end(&mut response)).await;
+    assert_eq!(closed.unwrap().unwrap(), 0);
+
+    // 其他连接照常应答
+    let (status, _) = get(address, "/healthz").await;
+    assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+}
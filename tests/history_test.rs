@@ -0,0 +1,87 @@
+use lsp_proxy::config::{Config, HistoryConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::history::RequestHistory;
+use lsp_proxy::lifecycle::{QueueDepth, RequestLifecycle, RequestObserver};
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+fn hover(id: i32) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": "textDocument/hover", "params": {
+        "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 0}
+    }})
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_ring_buffer_keeps_most_recent() {
+    let history = RequestHistory::new(&HistoryConfig { capacity: 2 });
+    let lifecycle = RequestLifecycle::new(history.is_enabled());
+    for id in 1..=3 {
+        lifecycle.started(&hover(id), "textDocument/hover", QueueDepth::default());
+    }
+    tokio::time::advance(Duration::from_millis(40)).await;
+    for id in 1..=3 {
+        lifecycle.responding(&json!({"jsonrpc": "2.0", "id": id, "result": null}));
+        let request = lifecycle.finished(&json!(id), id == 3).unwrap();
+        history.finished(&request);
+    }
+
+    let recent = history.recent(None, None);
+    assert_eq!(recent.len(), 2);
+    assert_eq!(recent[0].id, json!(3));
+    assert_eq!(recent[0].status, "error");
+    assert_eq!(recent[1].id, json!(2));
+    assert_eq!(recent[1].status, "ok");
+    assert_eq!(recent[1].uri.as_deref(), Some("file:///a.cpp"));
+    assert_eq!(recent[1].duration_ms, 40.0);
+    assert!(recent[1].request_bytes > 0);
+    assert_eq!(recent[1].response_bytes, 38);
+    assert_eq!(history.recent(Some(1), None).len(), 1);
+    assert!(
+        history
+            .recent(None, Some("textDocument/definition"))
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_disabled_with_zero_capacity() {
+    let history = RequestHistory::new(&HistoryConfig { capacity: 0 });
+    assert!(!history.is_enabled());
+    let lifecycle = RequestLifecycle::new(history.is_enabled());
+    lifecycle.started(&hover(1), "textDocument/hover", QueueDepth::default());
+    lifecycle.responding(&json!({"jsonrpc": "2.0", "id": 1, "result": null}));
+    let request = lifecycle.finished(&json!(1), false).unwrap();
+    // 不记录历史时不计算消息大小
+    assert_eq!(request.request_bytes, 0);
+    assert_eq!(request.response_bytes, 0);
+    history.finished(&request);
+    assert!(history.recent(None, None).is_empty());
+}
+
+#[tokio::test]
+async fn test_history_request() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher =
+        Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(Config::default()));
+
+    dispatcher.handle_from_frontend(hover(1)).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    let response = json!({"jsonrpc": "2.0", "id": 1, "error": {"code": -32603, "message": "x"}});
+    dispatcher.handle_from_backend(response).await.unwrap();
+    frontend_rx.recv().await.unwrap();
+
+    let query = json!({"jsonrpc": "2.0", "id": "h", "method": "codefuse/history", "params": {
+        "method": "textDocument/hover"
+    }});
+    dispatcher.handle_from_frontend(query).await.unwrap();
+    let result = frontend_rx.recv().await.unwrap().into_body()["result"].clone();
+    assert_eq!(result.as_array().unwrap().len(), 1);
+    assert_eq!(result[0]["id"], 1);
+    assert_eq!(result[0]["method"], "textDocument/hover");
+    assert_eq!(result[0]["status"], "error");
+    assert!(result[0]["responseBytes"].as_u64().unwrap() > 0);
+}
@@ -0,0 +1,47 @@
+use lsp_proxy::config::{Config, IdleAction};
+use lsp_proxy::idle::IdleTracker;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn test_idle_config() {
+    let config = Config::parse("[backend.idle]\nafter_mins = 15\naction = \"shutdown\"\n").unwrap();
+    assert_eq!(config.backend.idle.after_mins, Some(15));
+    assert_eq!(config.backend.idle.action, IdleAction::Shutdown);
+    assert_eq!(Config::default().backend.idle.after_mins, None);
+}
+
+#[test]
+fn test_idle_minutes_are_validated() {
+    assert!(Config::parse("[backend.idle]\nafter_mins = 0\n").is_err());
+    assert!(Config::parse(&format!("[backend.idle]\nafter_mins = {}\n", u64::MAX / 2)).is_err());
+    assert!(Config::parse("[backend.idle]\nafter_mins = 1\n").is_ok());
+}
+
+#[test]
+fn test_backend_sleeps_only_when_idle() {
+    let tracker = IdleTracker::new();
+    assert!(!tracker.try_sleep(Duration::from_secs(60), IdleAction::Suspend));
+    assert_eq!(tracker.touch(), None);
+
+    assert!(tracker.try_sleep(Duration::ZERO, IdleAction::Suspend));
+    assert!(!tracker.try_sleep(Duration::ZERO, IdleAction::Suspend));
+    assert_eq!(tracker.touch(), Some(IdleAction::Suspend));
+}
+
+#[tokio::test]
+async fn test_message_waits_until_backend_wakes() {
+    let tracker = Arc::new(IdleTracker::new());
+    assert!(tracker.try_sleep(Duration::ZERO, IdleAction::Shutdown));
+
+    let supervisor = Arc::clone(&tracker);
+    let woken = tokio::spawn(async move {
+        supervisor.wait_for_wake().await;
+        supervisor.woke();
+    });
+    tokio::time::timeout(Duration::from_secs(5), tracker.wake())
+        .await
+        .unwrap();
+    woken.await.unwrap();
+    assert_eq!(tracker.touch(), None);
+}
@@ -0,0 +1,130 @@
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::include_check::{CompileDatabase, IncludeCheck};
+use lsp_proxy::message::Message;
+use serde_json::json;
+use std::fs;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::Url;
+
+#[test]
+fn test_database_reads_include_dirs_from_commands() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(
+        root.join("compile_commands.json"),
+        json!([
+            {"directory": root, "file": "a.cpp",
+             "command": "c++ -Iinclude -I \"third party\" -isystem/opt/sdk -c a.cpp"},
+            {"directory": root, "file": "b.cpp",
+             "arguments": ["c++", "-iquote", "gen", "-c", "b.cpp"]}
+        ])
+        .to_string(),
+    )
+    .unwrap();
+
+    let database = CompileDatabase::load(&root.join("compile_commands.json")).unwrap();
+    assert_eq!(
+        database.include_dirs(&root.join("a.cpp")),
+        [
+            root.join("include"),
+            root.join("third party"),
+            "/opt/sdk".into()
+        ]
+    );
+    assert_eq!(
+        database.include_dirs(&root.join("b.cpp")),
+        [root.join("gen")]
+    );
+    // 头文件不在数据库中，使用所有文件的包含目录
+    assert_eq!(database.include_dirs(&root.join("a.h")).len(), 4);
+}
+
+#[test]
+fn test_missing_headers_and_cycles_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir(root.join("include")).unwrap();
+    fs::write(
+        root.join("compile_commands.json"),
+        json!([{"directory": root, "file": "a.cpp", "arguments": ["c++", "-Iinclude", "a.cpp"]}])
+            .to_string(),
+    )
+    .unwrap();
+    fs::write(root.join("include/util.h"), "#pragma once\n").unwrap();
+    fs::write(root.join("x.h"), "#include \"y.h\"\n").unwrap();
+    fs::write(root.join("y.h"), "#include \"x.h\"\n").unwrap();
+
+    let check = IncludeCheck::new(true);
+    let roots = [root.to_path_buf()];
+    let text = "#include <vector>\n#include \"util.h\"\n#include \"missing.h\"\n";
+    let diagnostics = check.check(&root.join("a.cpp"), text, &roots);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["message"], "找不到头文件 \"missing.h\"");
+    assert_eq!(diagnostics[0]["source"], "includes");
+    assert_eq!(
+        diagnostics[0]["range"],
+        json!({"start": {"line": 2, "character": 10}, "end": {"line": 2, "character": 19}})
+    );
+
+    let diagnostics = check.check(&root.join("x.h"), "#include \"y.h\"\n", &roots);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["message"], "包含循环: x.h -> y.h -> x.h");
+}
+
+#[tokio::test]
+async fn test_dispatcher_answers_include_links() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join("include/net")).unwrap();
+    fs::write(
+        root.join("compile_commands.json"),
+        json!([{"directory": root, "file": "a.cpp", "arguments": ["c++", "-Iinclude", "a.cpp"]}])
+            .to_string(),
+    )
+    .unwrap();
+    fs::write(root.join("local.h"), "").unwrap();
+    fs::write(root.join("include/net/socket.h"), "").unwrap();
+
+    let text = "#include \"local.h\"\n#include <net/socket.h>\n#include <vector>\n";
+    let links = IncludeCheck::default().links(&root.join("a.cpp"), text, &[root.to_path_buf()]);
+    assert_eq!(links.len(), 2);
+    assert_eq!(
+        links[1]["target"],
+        Url::from_file_path(root.join("include/net/socket.h"))
+            .unwrap()
+            .as_str()
+    );
+    assert_eq!(
+        links[1]["range"],
+        json!({"start": {"line": 1, "character": 10}, "end": {"line": 1, "character": 22}})
+    );
+
+    // 后端的能力未知或者不提供文档链接时，由代理应答
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let uri = Url::from_file_path(root.join("a.cpp")).unwrap();
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "method": "initialize", "id": 0,
+            "params": {"capabilities": {}, "rootUri": Url::from_file_path(root).unwrap()}}))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": {"textDocument": {"uri": uri, "languageId": "cpp", "version": 1, "text": text}}}))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/documentLink",
+            "params": {"textDocument": {"uri": uri}}}),
+        )
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["result"], json!(links));
+    assert!(backend_rx.try_recv().is_err());
+}
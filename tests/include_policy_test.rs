@@ -0,0 +1,84 @@
+use lsp_proxy::config::{IncludeConfig, IncludeStyle};
+use lsp_proxy::include_policy::{IncludePolicy, IncludeRewrite, parse_iwyu_mappings};
+use serde_json::json;
+
+const MAPPINGS: &str = r#"
+# libstdc++ 私有头文件
+[
+  { include: ["<bits/stl_vector.h>", "private", "<vector>", "public"] },
+  { symbol: ["std::size_t", "private", "<cstddef>", "public"] },
+  { include: ["\"internal/detail.h\"", "private", "\"api/widget.h\"", "public"] },
+]
+"#;
+
+fn policy() -> IncludePolicy {
+    let dir = tempfile::tempdir().unwrap();
+    let mapping_file = dir.path().join("project.imp");
+    std::fs::write(&mapping_file, MAPPINGS).unwrap();
+    IncludePolicy::new(&IncludeConfig {
+        style: Some(IncludeStyle::Angle),
+        blocked: vec!["third_party/*".to_string()],
+        mapping_file: Some(mapping_file),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn test_parse_iwyu_mappings() {
+    let mappings = parse_iwyu_mappings(MAPPINGS);
+    assert_eq!(mappings.len(), 2);
+    assert_eq!(mappings["bits/stl_vector.h"], "<vector>");
+    assert_eq!(mappings["internal/detail.h"], "\"api/widget.h\"");
+}
+
+#[test]
+fn test_rewrite_include_text() {
+    let policy = policy();
+    assert_eq!(
+        policy.rewrite_text("#include \"bits/stl_vector.h\"\n"),
+        IncludeRewrite::Replace("#include <vector>\n".to_string())
+    );
+    assert_eq!(
+        policy.rewrite_text("#include \"internal/detail.h\"\n"),
+        IncludeRewrite::Replace("#include <api/widget.h>\n".to_string())
+    );
+    assert_eq!(
+        policy.rewrite_text("#include \"third_party/zlib.h\"\n"),
+        IncludeRewrite::Drop
+    );
+    assert_eq!(
+        policy.rewrite_text("#include <map>\n"),
+        IncludeRewrite::Keep
+    );
+    assert_eq!(policy.rewrite_text("std::vector"), IncludeRewrite::Keep);
+}
+
+#[test]
+fn test_rewrite_completion_and_code_actions() {
+    let policy = policy();
+    let edit = |text: &str| json!({"range": {"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 0}}, "newText": text});
+
+    let mut completion = json!({"jsonrpc": "2.0", "id": 1, "result": {"isIncomplete": false, "items": [
+        {"label": "deflate", "additionalTextEdits": [edit("#include \"third_party/zlib.h\"\n")]},
+        {"label": "Widget", "additionalTextEdits": [edit("#include \"internal/detail.h\"\n")]}
+    ]}});
+    policy.rewrite_completion_response(&mut completion);
+    let items = &completion["result"]["items"];
+    assert_eq!(items[0]["additionalTextEdits"], json!([]));
+    assert_eq!(
+        items[1]["additionalTextEdits"][0]["newText"],
+        "#include <api/widget.h>\n"
+    );
+
+    let mut actions = json!({"jsonrpc": "2.0", "id": 2, "result": [
+        {"title": "Include \"third_party/zlib.h\"", "edit": {"changes": {"file:///a.cpp": [edit("#include \"third_party/zlib.h\"\n")]}}},
+        {"title": "Include <vector>", "edit": {"changes": {"file:///a.cpp": [edit("#include \"bits/stl_vector.h\"\n")]}}}
+    ]});
+    policy.rewrite_code_action_response(&mut actions);
+    let result = actions["result"].as_array().unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(
+        result[0]["edit"]["changes"]["file:///a.cpp"][0]["newText"],
+        "#include <vector>\n"
+    );
+}
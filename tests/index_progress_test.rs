@@ -0,0 +1,184 @@
+use lsp_proxy::config::{Config, IndexProgressConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::index_progress::{IndexProgress, Step, Summary};
+use lsp_proxy::message::Message;
+use lsp_proxy::shard::Shard;
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+fn enabled() -> IndexProgress {
+    IndexProgress::new(&IndexProgressConfig { enabled: true })
+}
+
+fn value(progress: &Value) -> &Value {
+    &progress["params"]["value"]
+}
+
+#[test]
+fn test_owns_background_index_progress() {
+    let progress = json!({"jsonrpc": "2.0", "method": "$/progress", "params": {
+        "token": "backgroundIndexProgress", "value": {"kind": "end"}
+    }});
+    let create = json!({"jsonrpc": "2.0", "id": 0, "method": "window/workDoneProgress/create",
+        "params": {"token": "backgroundIndexProgress"}});
+    let other = json!({"jsonrpc": "2.0", "method": "$/progress", "params": {
+        "token": "other", "value": {"kind": "end"}
+    }});
+    assert!(enabled().owns(&progress));
+    assert!(enabled().owns(&create));
+    assert!(!enabled().owns(&other));
+    assert!(!IndexProgress::default().owns(&progress));
+}
+
+#[test]
+fn test_merge_shards() {
+    let progress = enabled();
+    assert_eq!(
+        progress.observe(0, &json!({"kind": "begin", "title": "indexing"})),
+        Step::Create
+    );
+    // 创建期间的进度只记录
+    assert_eq!(
+        progress.observe(0, &json!({"kind": "report", "message": "10/40"})),
+        Step::Nothing
+    );
+    let sent = progress.created(Some("t".to_string()));
+    assert_eq!(sent.len(), 1);
+    assert_eq!(value(&sent[0])["message"], "10/40 个文件");
+    assert_eq!(value(&sent[0])["percentage"], 25);
+
+    progress.observe(1, &json!({"kind": "begin", "title": "indexing"}));
+    let Step::Send(sent) = progress.observe(1, &json!({"kind": "report", "message": "30/60"}))
+    else {
+        panic!("应该报告合并后的进度");
+    };
+    assert_eq!(value(&sent)["message"], "40/100 个文件（2 个分片）");
+    assert_eq!(value(&sent)["percentage"], 40);
+    assert_eq!(
+        progress.summary(),
+        Some(Summary {
+            indexed: 40,
+            total: 100,
+            percentage: 40,
+            shards: 2
+        })
+    );
+
+    // 一个分片完成时按它的全部文件计算
+    let Step::Send(sent) = progress.observe(0, &json!({"kind": "end"})) else {
+        panic!("应该报告合并后的进度");
+    };
+    assert_eq!(value(&sent)["message"], "70/100 个文件");
+    // 完成之后乱序到达的进度被忽略
+    assert_eq!(
+        progress.observe(0, &json!({"kind": "report", "message": "11/40"})),
+        Step::Nothing
+    );
+
+    let Step::Send(sent) = progress.observe(1, &json!({"kind": "end"})) else {
+        panic!("所有分片完成时应该结束进度");
+    };
+    assert_eq!(value(&sent)["kind"], "end");
+    assert_eq!(progress.summary(), None);
+
+    // 下一轮索引重新创建进度
+    assert_eq!(progress.observe(1, &json!({"kind": "begin"})), Step::Create);
+}
+
+#[test]
+fn test_ends_while_creating() {
+    let progress = enabled();
+    assert_eq!(progress.observe(0, &json!({"kind": "begin"})), Step::Create);
+    assert_eq!(progress.observe(0, &json!({"kind": "end"})), Step::Nothing);
+    let sent = progress.created(Some("t".to_string()));
+    assert_eq!(sent.len(), 1);
+    assert_eq!(value(&sent[0])["kind"], "end");
+}
+
+#[test]
+fn test_frontend_without_progress() {
+    let progress = enabled();
+    assert_eq!(progress.observe(0, &json!({"kind": "begin"})), Step::Create);
+    assert!(progress.created(None).is_empty());
+    assert_eq!(
+        progress.observe(0, &json!({"kind": "report", "message": "1/2"})),
+        Step::Nothing
+    );
+    assert_eq!(progress.observe(0, &json!({"kind": "end"})), Step::Nothing);
+    assert_eq!(progress.observe(0, &json!({"kind": "begin"})), Step::Create);
+}
+
+#[tokio::test]
+async fn test_dispatcher_merges_index_progress() {
+    let (default_tx, mut default_rx) = mpsc::unbounded_channel::<Message>();
+    let (services_tx, _services_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let shards = vec![
+        Shard {
+            name: "default".to_string(),
+            root: None,
+            sender: default_tx,
+        },
+        Shard {
+            name: "services".to_string(),
+            root: Some(PathBuf::from("/repo/services")),
+            sender: services_tx,
+        },
+    ];
+    let config = Config::parse("[index_progress]\nenabled = true").unwrap();
+    let dispatcher = Arc::new(Dispatcher::with_shards(shards, frontend_tx).with_config(config));
+    let initialize = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+        "processId": null, "rootUri": null,
+        "capabilities": {"window": {"workDoneProgress": true}},
+    }});
+    dispatcher.handle_from_frontend(initialize).await.unwrap();
+    while default_rx.try_recv().is_ok() {}
+
+    // 后端创建进度的请求由代理应答
+    let create = json!({"jsonrpc": "2.0", "id": 7, "method": "window/workDoneProgress/create",
+        "params": {"token": "backgroundIndexProgress"}});
+    dispatcher.handle_from_shard(0, create).await.unwrap();
+    let reply = default_rx.recv().await.unwrap().into_body();
+    assert_eq!(reply["id"], 7);
+    assert_eq!(reply["result"], Value::Null);
+
+    let begin = json!({"jsonrpc": "2.0", "method": "$/progress", "params": {
+        "token": "backgroundIndexProgress", "value": {"kind": "begin", "title": "indexing"}
+    }});
+    let handling = {
+        let dispatcher = Arc::clone(&dispatcher);
+        tokio::spawn(async move { dispatcher.handle_from_shard(0, begin).await })
+    };
+    let create = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(create["method"], "window/workDoneProgress/create");
+    let token = create["params"]["token"].clone();
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": create["id"], "result": null}))
+        .await
+        .unwrap();
+    handling.await.unwrap().unwrap();
+
+    let begin = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(begin["params"]["token"], token);
+    assert_eq!(value(&begin)["kind"], "begin");
+    assert_eq!(value(&begin)["title"], "后台索引");
+    let report = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(value(&report)["kind"], "report");
+
+    let report = json!({"jsonrpc": "2.0", "method": "$/progress", "params": {
+        "token": "backgroundIndexProgress", "value": {"kind": "report", "message": "5/20", "percentage": 25}
+    }});
+    dispatcher.handle_from_shard(1, report).await.unwrap();
+    // 分片 1 没有报告开始，进度被忽略
+    assert!(frontend_rx.try_recv().is_err());
+
+    let end = json!({"jsonrpc": "2.0", "method": "$/progress", "params": {
+        "token": "backgroundIndexProgress", "value": {"kind": "end"}
+    }});
+    dispatcher.handle_from_shard(0, end).await.unwrap();
+    let end = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(end["params"]["token"], token);
+    assert_eq!(value(&end)["kind"], "end");
+}
@@ -0,0 +1,136 @@
+use lsp_proxy::cli::{CliArgs, Command};
+use lsp_proxy::client::LspClient;
+use lsp_proxy::index::{self, IndexFormat};
+use serde_json::{Value, json};
+use std::fs;
+use tower_lsp::lsp_types::{Position, Range, SymbolKind, Url};
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn range(line: u32, start: u32, end: u32) -> Value {
+    json!({"start": {"line": line, "character": start}, "end": {"line": line, "character": end}})
+}
+
+#[test]
+fn test_parse_index_command() {
+    let parsed = CliArgs::parse(args(&[
+        "index", "--format", "lsif", "--output", "x.lsif", "src",
+    ]))
+    .unwrap();
+    let Some(Command::Index(command)) = parsed.command else {
+        panic!("应该解析为 index 子命令");
+    };
+    assert_eq!(command.format, IndexFormat::Lsif);
+    assert_eq!(command.output.as_deref(), Some("x.lsif".as_ref()));
+    assert_eq!(command.root.as_deref(), Some("src".as_ref()));
+
+    let parsed = CliArgs::parse(args(&["--backend", "mock", "index"])).unwrap();
+    let Some(Command::Index(command)) = parsed.command else {
+        panic!("应该解析为 index 子命令");
+    };
+    assert_eq!(command.format, IndexFormat::Scip);
+    assert!(command.mock);
+    assert!(CliArgs::parse(args(&["index", "--format", "json"])).is_err());
+
+    assert_eq!(index::descriptor("draw", SymbolKind::METHOD), "draw().");
+    assert_eq!(
+        index::descriptor("operator==", SymbolKind::OPERATOR),
+        "`operator==`()."
+    );
+}
+
+#[tokio::test]
+async fn test_collect_and_export_with_mock_backend() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    let source = root.join("a.cpp");
+    fs::write(
+        &source,
+        "namespace ns { int add(int a, int b); }\n\
+         int ns::add(int a, int b) { return a + b; }\n\
+         int main() { return ns::add(1, 2); }\n",
+    )
+    .unwrap();
+    let uri = Url::from_file_path(&source).unwrap();
+    let fixture = root.join("fixture.json");
+    fs::write(
+        &fixture,
+        json!({"responses": {
+            "textDocument/documentSymbol": {"result": [
+                {"name": "ns", "kind": 3, "range": range(0, 0, 39), "selectionRange": range(0, 10, 12),
+                 "children": [{"name": "add", "kind": 12, "range": range(0, 15, 37),
+                               "selectionRange": range(0, 19, 22)}]},
+                {"name": "main", "kind": 12, "range": range(2, 0, 36), "selectionRange": range(2, 4, 8)},
+            ]},
+            "textDocument/references": {"result": [{"uri": uri, "range": range(2, 24, 27)}]},
+        }})
+        .to_string(),
+    )
+    .unwrap();
+
+    let client = LspClient::spawn(
+        env!("CARGO_BIN_EXE_lsp-proxy"),
+        &args(&["mock-server", fixture.to_str().unwrap()]),
+    )
+    .unwrap();
+    client
+        .request("initialize", json!({"capabilities": {}}))
+        .await
+        .unwrap();
+    let dump = index::collect(&client, &root, std::slice::from_ref(&source))
+        .await
+        .unwrap();
+    client.shutdown().await;
+
+    let document = &dump.documents["a.cpp"];
+    assert_eq!(document.language_id, "cpp");
+    let symbols: Vec<&str> = document.symbols.iter().map(|s| s.symbol.as_str()).collect();
+    assert_eq!(
+        symbols,
+        [
+            "codefuse . . . ns/",
+            "codefuse . . . ns/add().",
+            "codefuse . . . main()."
+        ]
+    );
+    let add = document
+        .occurrences
+        .iter()
+        .find(|o| o.symbol == "codefuse . . . ns/add()." && o.definition)
+        .unwrap();
+    assert_eq!(
+        add.range,
+        Range::new(Position::new(0, 19), Position::new(0, 22))
+    );
+    assert!(
+        document
+            .occurrences
+            .iter()
+            .any(|o| o.range.start == Position::new(2, 24) && !o.definition)
+    );
+
+    // LSIF：每行一个元素，符号名作为 moniker
+    let lsif = index::to_lsif(&dump);
+    let elements: Vec<Value> = lsif
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(elements[0]["label"], "metaData");
+    assert!(elements.iter().any(|e| e["label"] == "moniker"
+        && e["identifier"] == "codefuse . . . main()."
+        && e["scheme"] == "codefuse"));
+    assert!(
+        elements
+            .iter()
+            .any(|e| e["label"] == "document" && e["uri"] == uri.as_str())
+    );
+
+    // SCIP：Index.metadata 是第一个字段，文档路径和符号名原样出现在编码中
+    let scip = index::to_scip(&dump);
+    assert_eq!(scip[0], 0x0a);
+    let contains = |needle: &[u8]| scip.windows(needle.len()).any(|w| w == needle);
+    assert!(contains(b"a.cpp"));
+    assert!(contains(b"codefuse . . . ns/add()."));
+}
@@ -0,0 +1,98 @@
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::inline_values;
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::{InlineValue, InlineValueParams};
+
+const SOURCE: &str = "\
+int helper(int unused) { return unused; }
+
+int sum(const int *values, int &count) {
+    int total = 0, i;
+    for (i = 0; i < count; ++i) {
+        total += values[i];
+    }
+    int later = total;
+    return later;
+}
+";
+
+fn params(stopped_line: u32) -> InlineValueParams {
+    serde_json::from_value(json!({
+        "textDocument": {"uri": "file:///sum.cpp"},
+        "range": {"start": {"line": 0, "character": 0}, "end": {"line": 10, "character": 0}},
+        "context": {"frameId": 1, "stoppedLocation": {
+            "start": {"line": stopped_line, "character": 8},
+            "end": {"line": stopped_line, "character": 8},
+        }},
+    }))
+    .unwrap()
+}
+
+fn lookups(values: &[InlineValue]) -> Vec<(u32, String)> {
+    values
+        .iter()
+        .map(|value| match value {
+            InlineValue::VariableLookup(lookup) => (
+                lookup.range.start.line,
+                lookup.variable_name.clone().unwrap(),
+            ),
+            other => panic!("意外的行内值 {:?}", other),
+        })
+        .collect()
+}
+
+#[test]
+fn test_inline_values_cover_locals_up_to_stopped_line() {
+    let values = inline_values::inline_values(SOURCE, &params(5));
+    let found = lookups(&values);
+    // 其他函数的参数和停止行之后的变量不显示
+    assert!(found.iter().all(|(line, _)| (2..=5).contains(line)));
+    assert!(
+        !found
+            .iter()
+            .any(|(_, name)| name == "unused" || name == "later")
+    );
+    for name in ["values", "count", "total", "i"] {
+        assert!(found.iter().any(|(_, found)| found == name), "{}", name);
+    }
+    assert!(found.contains(&(5, "total".to_string())));
+
+    // 停在函数之外没有行内值
+    assert!(inline_values::inline_values(SOURCE, &params(1)).is_empty());
+}
+
+#[tokio::test]
+async fn test_dispatcher_answers_inline_values() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": {"uri": "file:///sum.cpp", "languageId": "cpp", "version": 1,
+                                 "text": SOURCE}
+            }}),
+        )
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": 1,
+            "method": "textDocument/inlineValue", "params": params(3)}))
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    let names: Vec<&Value> = response["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|value| &value["variableName"])
+        .collect();
+    assert_eq!(names, ["values", "count", "total", "i"]);
+    assert_eq!(response["result"][0]["caseSensitiveLookup"], true);
+    assert!(backend_rx.try_recv().is_err());
+}
@@ -0,0 +1,110 @@
+use lsp_proxy::completion_sources::CompletionRequest;
+use lsp_proxy::config::Config;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::document_store::Document;
+use lsp_proxy::keywords::{self, Context, Keywords};
+use lsp_proxy::message::Message;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::Url;
+
+/// 光标在 `text` 末尾的补全请求。
+fn request(text: &str, language: &str, context: Option<Value>) -> CompletionRequest {
+    let line = text.split('\n').count() - 1;
+    let character = text.split('\n').next_back().unwrap().len();
+    let mut params = json!({
+        "textDocument": {"uri": "file:///a.cpp"},
+        "position": {"line": line, "character": character},
+    });
+    if let Some(context) = context {
+        params["context"] = context;
+    }
+    CompletionRequest {
+        params: serde_json::from_value(params).unwrap(),
+        document: Some(Document {
+            uri: Url::parse("file:///a.cpp").unwrap(),
+            language_id: language.to_string(),
+            version: 1,
+            text: text.to_string(),
+        }),
+        backend_ready: true,
+    }
+}
+
+fn labels(items: &[Value]) -> Vec<&str> {
+    items
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_context() {
+    assert_eq!(keywords::context("#"), Context::Directive);
+    assert_eq!(keywords::context("  #  "), Context::Directive);
+    assert_eq!(keywords::context("[[ "), Context::Attribute);
+    assert_eq!(keywords::context("[[nodiscard, "), Context::Attribute);
+    assert_eq!(keywords::context("[[nodiscard]] "), Context::Code);
+    assert_eq!(keywords::context("x = a # "), Context::Code);
+}
+
+#[test]
+fn test_items_by_context() {
+    let keywords = Keywords;
+    assert_eq!(
+        labels(&keywords.items(&request("int x;\n#inc", "cpp", None))),
+        vec!["include", "include_next"]
+    );
+    assert_eq!(
+        labels(&keywords.items(&request("[[no", "cpp", None))),
+        vec!["no_unique_address", "nodiscard", "noreturn"]
+    );
+    let items = keywords.items(&request("  const", "cpp", None));
+    assert_eq!(
+        labels(&items),
+        vec!["const", "const_cast", "consteval", "constexpr", "constinit"]
+    );
+    assert_eq!(items[0]["kind"], 14);
+    // C 没有 C++ 的关键字
+    assert_eq!(
+        labels(&keywords.items(&request("const", "c", None))),
+        vec!["const", "constexpr"]
+    );
+
+    // 没有输入时不提供关键字，触发字符触发的补全不提供任何补全项
+    assert!(keywords.items(&request("  ", "cpp", None)).is_empty());
+    let context = json!({"triggerKind": 2, "triggerCharacter": "#"});
+    assert!(
+        keywords
+            .items(&request("#", "cpp", Some(context)))
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_keywords_merge_with_backend() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse("[completion]\nkeywords = true").unwrap();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    dispatcher.health().set_alive(0, true);
+    let did_open = json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": 1, "text": "#end"},
+    }});
+    dispatcher.handle_from_frontend(did_open).await.unwrap();
+    backend_rx.recv().await.unwrap();
+
+    let completion = json!({"jsonrpc": "2.0", "id": 2, "method": "textDocument/completion",
+        "params": {"textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 4}}});
+    dispatcher.handle_from_frontend(completion).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    dispatcher
+        .handle_from_backend(json!({"jsonrpc": "2.0", "id": 2, "result": []}))
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    let items = response["result"]["items"].as_array().unwrap();
+    assert_eq!(labels(items), vec!["endif"]);
+    assert_eq!(items[0]["labelDetails"]["description"], "keywords");
+}
@@ -0,0 +1,113 @@
+use lsp_proxy::lanes::DocumentLanes;
+use lsp_proxy::trace::Direction;
+use serde_json::{Value, json};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn did_change(uri: &str, version: i32) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {"textDocument": {"uri": uri, "version": version}, "contentChanges": []}
+    })
+}
+
+fn hover(uri: &str, id: i32) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "textDocument/hover",
+        "params": {"textDocument": {"uri": uri}, "position": {"line": 0, "character": 0}}
+    })
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_same_document_keeps_arrival_order() {
+    let lanes = DocumentLanes::new();
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    // 先到的消息处理得更慢，仍然先完成
+    let mut tasks = Vec::new();
+    for (version, uri, delay) in [
+        (1, "file:///a.cpp", 30),
+        (2, "file:///a.cpp", 10),
+        (3, "file:///b.cpp", 0),
+        (4, "file:///a.cpp", 0),
+    ] {
+        let ticket = lanes.enter(Direction::Frontend, None, &did_change(uri, version));
+        let order = Arc::clone(&order);
+        tasks.push(tokio::spawn(async move {
+            let _ticket = ticket.wait().await;
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            order.lock().unwrap().push(version);
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    // 另一个文档的消息不需要等待
+    assert_eq!(*order.lock().unwrap(), vec![3, 1, 2, 4]);
+    assert_eq!(lanes.active(), 0);
+}
+
+#[tokio::test]
+async fn test_unordered_messages_do_not_wait() {
+    let lanes = DocumentLanes::new();
+    let symbol = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "workspace/symbol",
+        "params": {"query": "x"}
+    });
+    let _held = lanes.enter(Direction::Frontend, None, &did_change("file:///a.cpp", 1));
+    lanes.enter(Direction::Frontend, None, &symbol).wait().await;
+    // 其他文档的请求不等待
+    lanes
+        .enter(Direction::Frontend, None, &hover("file:///b.cpp", 2))
+        .wait()
+        .await;
+    // 后端的诊断使用独立的通道
+    let diagnostics = json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": "file:///a.cpp", "diagnostics": []}
+    });
+    lanes
+        .enter(Direction::Backend, Some(0), &diagnostics)
+        .wait()
+        .await;
+    assert_eq!(lanes.active(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_requests_wait_for_earlier_edits() {
+    let lanes = DocumentLanes::new();
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    // 请求排在之前的 didChange 之后，请求之间不互相等待，之后的 didChange 也不等待请求
+    let mut tasks = Vec::new();
+    for (name, message, delay) in [
+        ("change1", did_change("file:///a.cpp", 1), 30),
+        ("hover1", hover("file:///a.cpp", 1), 20),
+        ("hover2", hover("file:///a.cpp", 2), 0),
+        ("change2", did_change("file:///a.cpp", 2), 5),
+    ] {
+        let ticket = lanes.enter(Direction::Frontend, None, &message);
+        let order = Arc::clone(&order);
+        tasks.push(tokio::spawn(async move {
+            let _ticket = ticket.wait().await;
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            order.lock().unwrap().push(name);
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    assert_eq!(
+        *order.lock().unwrap(),
+        vec!["change1", "hover2", "change2", "hover1"]
+    );
+    assert_eq!(lanes.active(), 0);
+}
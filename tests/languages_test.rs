@@ -0,0 +1,116 @@
+use lsp_proxy::config::{Config, LanguageConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::languages::Languages;
+use lsp_proxy::message::Message;
+use lsp_proxy::shard::Shard;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+fn map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn did_open(uri: &str, language: &str) -> Value {
+    json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {"textDocument": {
+        "uri": uri, "languageId": language, "version": 1, "text": "int x;\n"}}})
+}
+
+#[test]
+fn test_extensions_take_precedence_over_language_ids() {
+    let languages = Languages::new(&LanguageConfig {
+        extensions: map(&[(".INL", "cpp"), ("x", "c")]),
+        ids: map(&[("cuda", "cuda-cpp")]),
+        shards: map(&[("c", "legacy")]),
+    });
+
+    let mut inl = did_open("file:///src/vec.inl", "plaintext");
+    languages.apply(&mut inl);
+    assert_eq!(inl["params"]["textDocument"]["languageId"], "cpp");
+
+    let mut kernel = did_open("file:///src/kernel.cuh", "cuda");
+    languages.apply(&mut kernel);
+    assert_eq!(kernel["params"]["textDocument"]["languageId"], "cuda-cpp");
+
+    let mut header = did_open("file:///src/a.h", "c");
+    languages.apply(&mut header);
+    assert_eq!(header["params"]["textDocument"]["languageId"], "c");
+
+    // 打开的文档按记录的语言，没有打开的按扩展名
+    let header = "file:///src/a.h".parse().unwrap();
+    assert_eq!(languages.shard(&header), Some("legacy"));
+    languages.forget(&header);
+    assert_eq!(languages.shard(&header), None);
+    assert_eq!(
+        languages.shard(&"file:///src/grammar.x".parse().unwrap()),
+        Some("legacy")
+    );
+}
+
+#[tokio::test]
+async fn test_language_overrides_shard_routing() {
+    let (default_tx, mut default_rx) = mpsc::unbounded_channel::<Message>();
+    let (legacy_tx, mut legacy_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config {
+        languages: LanguageConfig {
+            extensions: map(&[("x", "c")]),
+            shards: map(&[("c", "legacy")]),
+            ..LanguageConfig::default()
+        },
+        ..Config::default()
+    };
+    let dispatcher = Arc::new(
+        Dispatcher::with_shards(
+            vec![
+                Shard::default_shard(default_tx),
+                Shard {
+                    name: "legacy".into(),
+                    root: Some(PathBuf::from("/repo/legacy")),
+                    sender: legacy_tx,
+                },
+            ],
+            frontend_tx,
+        )
+        .with_config(config),
+    );
+
+    // 不在 legacy 子树中，但按语言交给 legacy 分片
+    dispatcher
+        .handle_from_frontend(did_open("file:///repo/app/parse.x", "plaintext"))
+        .await
+        .unwrap();
+    let open = legacy_rx.recv().await.unwrap().into_body();
+    assert_eq!(open["params"]["textDocument"]["languageId"], "c");
+    assert_eq!(
+        dispatcher
+            .documents()
+            .get(&"file:///repo/app/parse.x".parse().unwrap())
+            .unwrap()
+            .language_id,
+        "c"
+    );
+
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "method": "textDocument/didClose",
+            "params": {"textDocument": {"uri": "file:///repo/app/parse.x"}}}))
+        .await
+        .unwrap();
+    let close = legacy_rx.recv().await.unwrap().into_body();
+    assert_eq!(close["method"], "textDocument/didClose");
+
+    dispatcher
+        .handle_from_frontend(did_open("file:///repo/app/main.cpp", "cpp"))
+        .await
+        .unwrap();
+    let open = default_rx.recv().await.unwrap().into_body();
+    assert_eq!(
+        open["params"]["textDocument"]["uri"],
+        "file:///repo/app/main.cpp"
+    );
+}
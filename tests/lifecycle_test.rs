@@ -0,0 +1,53 @@
+use lsp_proxy::lifecycle::{QueueDepth, RequestLifecycle};
+use serde_json::{Value, json};
+use std::time::Duration;
+
+fn hover(id: i32) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": "textDocument/hover", "params": {
+        "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 0}
+    }})
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_request_is_finished_once() {
+    let lifecycle = RequestLifecycle::new(false);
+    let queue = QueueDepth {
+        pending_backend_requests: 2,
+        handlers_waiting: 0,
+    };
+    lifecycle.started(&hover(1), "textDocument/hover", queue);
+    // 通知没有 id，不跟踪
+    lifecycle.started(
+        &json!({"jsonrpc": "2.0", "method": "initialized"}),
+        "initialized",
+        QueueDepth::default(),
+    );
+    assert_eq!(lifecycle.len(), 1);
+
+    lifecycle.forwarded(&json!(1), 3);
+    tokio::time::advance(Duration::from_millis(25)).await;
+    lifecycle.backend_responded(&json!(1));
+    let request = lifecycle.finished(&json!(1), true).unwrap();
+    assert_eq!(request.id, json!(1));
+    assert_eq!(request.method, "textDocument/hover");
+    assert_eq!(request.uri.as_deref(), Some("file:///a.cpp"));
+    assert_eq!(request.duration, Duration::from_millis(25));
+    assert!(request.failed);
+    assert_eq!(request.queue, queue);
+    assert_eq!(request.forwarded.map(|(shard, _)| shard), Some(3));
+    assert!(request.backend_responded.is_some());
+
+    // 同一个响应不会结束两次
+    assert_eq!(lifecycle.finished(&json!(1), false), None);
+    assert!(lifecycle.is_empty());
+}
+
+#[tokio::test]
+async fn test_proxy_answered_request_is_not_forwarded() {
+    let lifecycle = RequestLifecycle::new(false);
+    lifecycle.started(&hover(1), "textDocument/hover", QueueDepth::default());
+    let request = lifecycle.finished(&json!(1), false).unwrap();
+    assert_eq!(request.forwarded, None);
+    assert_eq!(request.backend_responded, None);
+    assert!(request.received_at <= request.responded_at);
+}
@@ -0,0 +1,148 @@
+use lsp_proxy::config::LivenessConfig;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::liveness::{self, PING, Probe};
+use lsp_proxy::message::Message;
+use lsp_proxy::transport::Transport;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+#[cfg(unix)]
+#[test]
+fn test_peer_closed() {
+    use lsp_proxy::platform::peer_closed;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    let (ours, theirs) = UnixStream::pair().unwrap();
+    assert!(!peer_closed(ours.as_raw_fd()));
+    drop(theirs);
+    assert!(peer_closed(ours.as_raw_fd()));
+
+    let file = tempfile::tempfile().unwrap();
+    assert!(!peer_closed(file.as_raw_fd()));
+}
+
+#[test]
+fn test_probe_for_transport() {
+    assert_eq!(
+        Probe::for_transport(&Transport::Socket("9000".to_string())),
+        Probe::Ping
+    );
+    if cfg!(unix) {
+        assert_eq!(Probe::for_transport(&Transport::Stdio), Probe::Stdout);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_watch_returns_when_ping_is_not_answered() {
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let config = LivenessConfig {
+        interval_secs: 5,
+        timeout_secs: 5,
+        ..LivenessConfig::default()
+    };
+    let watch = tokio::spawn(liveness::watch(
+        Arc::clone(&dispatcher),
+        config,
+        Probe::Ping,
+    ));
+
+    // 初始化之前不发送 ping
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    assert!(frontend_rx.try_recv().is_err());
+    let initialize = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+        "processId": null, "rootUri": null, "capabilities": {}
+    }});
+    dispatcher.handle_from_frontend(initialize).await.unwrap();
+
+    // 不认识 ping 的编辑器以错误应答，同样说明它还在
+    let ping = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(ping["method"], PING);
+    let error = json!({"jsonrpc": "2.0", "id": ping["id"], "error": {
+        "code": -32601, "message": "Unhandled method $/codefuse/ping"
+    }});
+    dispatcher.handle_from_frontend(error).await.unwrap();
+    assert!(!watch.is_finished());
+
+    let ping = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(ping["method"], PING);
+    tokio::time::timeout(Duration::from_secs(10), watch)
+        .await
+        .expect("没有应答 ping 时应该认为前端已经不在")
+        .unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_watch_disabled() {
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let config = LivenessConfig {
+        interval_secs: 0,
+        timeout_secs: 5,
+        ..LivenessConfig::default()
+    };
+    let watch = liveness::watch(dispatcher, config, Probe::Ping);
+    assert!(
+        tokio::time::timeout(Duration::from_secs(3600), watch)
+            .await
+            .is_err()
+    );
+}
+
+fn exited_pid() -> u32 {
+    let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+        .arg("--list")
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let pid = child.id();
+    child.wait().unwrap();
+    pid
+}
+
+#[test]
+fn test_process_alive() {
+    use lsp_proxy::platform::process_alive;
+
+    assert!(process_alive(std::process::id()));
+    assert!(!process_alive(exited_pid()));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_watch_parent() {
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let watch = tokio::spawn(liveness::watch_parent(Arc::clone(&dispatcher)));
+
+    let initialize = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+        "processId": exited_pid(), "rootUri": null, "capabilities": {}
+    }});
+    dispatcher.handle_from_frontend(initialize).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(10), watch)
+        .await
+        .expect("编辑器进程结束后应该返回")
+        .unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_watch_parent_without_process_id() {
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let initialize = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+        "processId": null, "rootUri": null, "capabilities": {}
+    }});
+    dispatcher.handle_from_frontend(initialize).await.unwrap();
+    let watch = liveness::watch_parent(dispatcher);
+    assert!(
+        tokio::time::timeout(Duration::from_secs(3600), watch)
+            .await
+            .is_err()
+    );
+}
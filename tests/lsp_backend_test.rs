@@ -0,0 +1,109 @@
+use lsp_proxy::config::ResourceLimits;
+use lsp_proxy::lsp_backend::{LspBackend, running_backends};
+use std::time::Duration;
+
+/// 进程已经退出（僵尸进程或已被回收）。
+#[cfg(target_os = "linux")]
+fn exited(pid: u32) -> bool {
+    match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        // 第三个字段是进程状态
+        Ok(stat) => stat
+            .rsplit(')')
+            .next()
+            .and_then(|rest| rest.split_whitespace().next())
+            .is_some_and(|state| state == "Z" || state == "X"),
+        Err(_) => true,
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_dropped_backend_is_killed() {
+    let backend = LspBackend::spawn(
+        "sleep",
+        &["30".to_string()],
+        &[],
+        &ResourceLimits::default(),
+    )
+    .await
+    .unwrap();
+    let pid = backend.child.id().unwrap();
+    assert!(running_backends().contains(&pid));
+
+    drop(backend);
+    assert!(!running_backends().contains(&pid));
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !exited(pid) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("丢弃后后端进程应当结束");
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_shutdown_reaps_backend() {
+    let backend = LspBackend::spawn(
+        "sleep",
+        &["30".to_string()],
+        &[],
+        &ResourceLimits::default(),
+    )
+    .await
+    .unwrap();
+    let pid = backend.child.id().unwrap();
+
+    backend.child.shutdown().await.unwrap();
+    assert!(!running_backends().contains(&pid));
+    // 已被回收，不会留下僵尸进程
+    assert!(!std::path::Path::new(&format!("/proc/{}", pid)).exists());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_resource_limits_are_applied() {
+    let limits = ResourceLimits {
+        nice: Some(5),
+        cpus: vec![0],
+        memory_mb: Some(512),
+    };
+    let backend = LspBackend::spawn("sleep", &["30".to_string()], &[], &limits)
+        .await
+        .unwrap();
+    let pid = backend.child.id().unwrap();
+
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+    // comm 之后的第 17 个字段是 nice 值
+    let nice: i32 = stat
+        .rsplit(')')
+        .next()
+        .and_then(|rest| rest.split_whitespace().nth(16))
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(nice >= 5);
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).unwrap();
+    assert!(status.contains("Cpus_allowed_list:\t0\n"));
+    let proc_limits = std::fs::read_to_string(format!("/proc/{}/limits", pid)).unwrap();
+    let data = proc_limits
+        .lines()
+        .find(|line| line.starts_with("Max data size"))
+        .unwrap();
+    assert!(data.contains(&(512u64 * 1024 * 1024).to_string()));
+
+    backend.child.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_missing_program_is_an_error() {
+    let result = LspBackend::spawn(
+        "codefuse-no-such-backend",
+        &[],
+        &[],
+        &ResourceLimits::default(),
+    )
+    .await;
+    let error = result.err().expect("找不到程序时应当返回错误");
+    assert!(format!("{:#}", error).contains("codefuse-no-such-backend"));
+}
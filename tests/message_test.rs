@@ -0,0 +1,31 @@
+use lsp_proxy::message::Message;
+use serde_json::json;
+
+#[test]
+fn test_message_kind_follows_body() {
+    let request = Message::new(json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/hover"}));
+    assert!(matches!(request, Message::Request { .. }));
+    assert_eq!(request.method(), Some("textDocument/hover"));
+    assert_eq!(request.id(), Some(&json!(1)));
+
+    let notification = Message::new(json!({"jsonrpc": "2.0", "method": "initialized"}));
+    assert!(matches!(notification, Message::Notification { .. }));
+    assert_eq!(notification.id(), None);
+
+    let response = Message::new(json!({"jsonrpc": "2.0", "id": "a", "result": null}));
+    assert!(matches!(response, Message::Response { .. }));
+    assert_eq!(response.method(), None);
+    assert_eq!(response.id(), Some(&json!("a")));
+}
+
+#[test]
+fn test_message_is_encoded_with_content_length() {
+    let body = json!({"jsonrpc": "2.0", "method": "exit"});
+    let text = Message::new(body.clone()).to_lsp_string().unwrap();
+    let (header, content) = text.split_once("\r\n\r\n").unwrap();
+    assert_eq!(header, format!("Content-Length: {}", content.len()));
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(content).unwrap(),
+        body
+    );
+}
@@ -0,0 +1,64 @@
+use lsp_proxy::config::{Config, MessagesConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::handlers::setup_handlers;
+use lsp_proxy::message::Message;
+use lsp_proxy::message_throttle::MessageThrottle;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+fn show_message(kind: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "method": "window/showMessage", "params": {
+        "type": kind, "message": message
+    }})
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_repeats_within_window_are_suppressed() {
+    let throttle = MessageThrottle::new(&MessagesConfig {
+        window_secs: Some(10),
+        max_repeats: 2,
+    });
+    let params = json!({"type": 2, "message": "Couldn't build compilation database"});
+    assert!(throttle.allow("window/showMessage", &params));
+    assert!(throttle.allow("window/showMessage", &params));
+    assert!(!throttle.allow("window/showMessage", &params));
+    // 方法、类型或者文字不同的消息分别计算
+    assert!(throttle.allow("window/logMessage", &params));
+    let error = json!({"type": 1, "message": "Couldn't build compilation database"});
+    assert!(throttle.allow("window/showMessage", &error));
+
+    tokio::time::advance(Duration::from_secs(10)).await;
+    assert!(throttle.allow("window/showMessage", &params));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_disabled_without_window() {
+    let throttle = MessageThrottle::new(&MessagesConfig::default());
+    let params = json!({"type": 2, "message": "again"});
+    for _ in 0..3 {
+        assert!(throttle.allow("window/showMessage", &params));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_duplicate_show_message_is_not_forwarded() {
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse("[messages]\nwindow_secs = 30\n").unwrap();
+    let mut dispatcher = Dispatcher::new(backend_tx, frontend_tx).with_config(config);
+    setup_handlers(&mut dispatcher);
+    let dispatcher = Arc::new(dispatcher);
+
+    for message in ["index failed", "index failed", "other"] {
+        dispatcher
+            .handle_from_backend(show_message(2, message))
+            .await
+            .unwrap();
+    }
+    let forwarded: Vec<Value> = std::iter::from_fn(|| frontend_rx.try_recv().ok())
+        .map(|message| message.into_body()["params"]["message"].clone())
+        .collect();
+    assert_eq!(forwarded, vec![json!("index failed"), json!("other")]);
+}
@@ -0,0 +1,62 @@
+use lsp_proxy::metrics::Metrics;
+use lsp_proxy::tasks::HandlerLimiter;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+#[tokio::test]
+async fn test_permit_is_held_until_handler_finishes() {
+    let metrics = Arc::new(Metrics::new());
+    let limiter = Arc::new(HandlerLimiter::new(1, Arc::clone(&metrics)));
+    let request = json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/hover"});
+
+    let (release, released) = oneshot::channel::<()>();
+    limiter
+        .spawn(request.clone(), |_| async move {
+            let _ = released.await;
+        })
+        .await
+        .unwrap();
+    assert_eq!(metrics.snapshot().handlers_running, 1);
+
+    // 第一个任务没有结束，第二条消息等待许可
+    let (done, finished) = oneshot::channel();
+    let waiting = tokio::spawn({
+        let limiter = Arc::clone(&limiter);
+        async move {
+            limiter
+                .spawn(request, |_| async move {
+                    let _ = done.send(());
+                })
+                .await
+                .unwrap();
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(metrics.snapshot().handlers_waiting, 1);
+
+    // 响应不占用许可
+    let (responded, response_handled) = oneshot::channel();
+    limiter
+        .spawn(
+            json!({"jsonrpc": "2.0", "id": 7, "result": null}),
+            |_| async move {
+                let _ = responded.send(());
+            },
+        )
+        .await
+        .unwrap();
+    response_handled.await.unwrap();
+
+    release.send(()).unwrap();
+    finished.await.unwrap();
+    waiting.await.unwrap();
+    // 任务结束后才记录完成
+    while metrics.snapshot().handlers_completed < 2 {
+        tokio::task::yield_now().await;
+    }
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.handlers_waiting, 0);
+    assert_eq!(snapshot.handlers_completed, 2);
+}
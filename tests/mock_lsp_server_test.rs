@@ -0,0 +1,98 @@
+use futures::TryStreamExt;
+use lsp_proxy::cli::{CliArgs, Command};
+use lsp_proxy::codec::LspCodec;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::mock_lsp_server::{MockLspServer, MockScript};
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::FramedRead;
+
+async fn send<W: AsyncWrite + Unpin>(writer: &mut W, message: Value) {
+    let message = Dispatcher::format_lsp_message(&message).unwrap();
+    writer.write_all(message.as_bytes()).await.unwrap();
+}
+
+#[test]
+fn test_mock_backend_flags() {
+    let args =
+        CliArgs::parse(["--backend", "mock", "--mock-fixture", "hover.json"].map(String::from))
+            .unwrap();
+    assert!(args.mock_backend);
+    assert_eq!(args.mock_fixture, Some(PathBuf::from("hover.json")));
+
+    // 分片追加的 clangd 参数被忽略
+    let args = CliArgs::parse(["mock-server", "hover.json", "-j=8"].map(String::from)).unwrap();
+    assert_eq!(
+        args.command,
+        Some(Command::MockServer {
+            fixture: Some(PathBuf::from("hover.json"))
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_scripted_responses_errors_and_notifications() {
+    let script: MockScript = serde_json::from_value(json!({
+        "responses": {
+            "textDocument/hover": {"result": {"contents": "int x"}, "delay_ms": 50},
+            "textDocument/definition": {"error": {"code": -32603, "message": "boom"}}
+        },
+        "notifications": {
+            "textDocument/didOpen": [{
+                "method": "textDocument/publishDiagnostics",
+                "params": {"uri": "${uri}", "diagnostics": []}
+            }]
+        }
+    }))
+    .unwrap();
+
+    let (mut client, server) = tokio::io::duplex(64 * 1024);
+    let (server_read, server_write) = tokio::io::split(server);
+    let handle = tokio::spawn(MockLspServer::new(script).serve(server_read, server_write));
+
+    let position =
+        json!({"textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 0}});
+    send(
+        &mut client,
+        json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": position}),
+    )
+    .await;
+    send(
+        &mut client,
+        json!({"jsonrpc": "2.0", "id": 2, "method": "textDocument/definition", "params": position}),
+    )
+    .await;
+    send(
+        &mut client,
+        json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+            "textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": 1, "text": ""}
+        }}),
+    )
+    .await;
+
+    let (read_half, mut write_half) = tokio::io::split(client);
+    let mut reader = FramedRead::new(read_half, LspCodec::default());
+    let mut messages = Vec::new();
+    for _ in 0..3 {
+        let message = tokio::time::timeout(Duration::from_secs(5), reader.try_next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        messages.push(message);
+    }
+
+    // 延迟的悬停响应最后到达，另外两条消息的顺序不确定
+    assert_eq!(messages[2]["id"], 1);
+    assert_eq!(messages[2]["result"]["contents"], "int x");
+    let error = messages.iter().find(|m| m["id"] == 2).unwrap();
+    assert_eq!(error["error"]["message"], "boom");
+    let diagnostics = messages.iter().find(|m| m.get("method").is_some()).unwrap();
+    assert_eq!(diagnostics["method"], "textDocument/publishDiagnostics");
+    assert_eq!(diagnostics["params"]["uri"], "file:///a.cpp");
+
+    send(&mut write_half, json!({"jsonrpc": "2.0", "method": "exit"})).await;
+    handle.await.unwrap().unwrap();
+}
@@ -0,0 +1,124 @@
+use lsp_proxy::config::{Config, ModuleConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::modules::{self, Modules};
+use serde_json::{Value, json};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+const INTERFACE: &str = "file:///work/math.cppm";
+
+fn diagnostic(message: &str) -> Value {
+    json!({"range": {"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 1}},
+           "severity": 1, "source": "clang", "message": message})
+}
+
+fn enabled() -> ModuleConfig {
+    ModuleConfig {
+        enabled: true,
+        ..ModuleConfig::default()
+    }
+}
+
+#[test]
+fn test_detect_interface_units_and_module_diagnostics() {
+    assert!(modules::is_interface_unit(Path::new("/work/math.cppm"), ""));
+    assert!(modules::is_interface_unit(
+        Path::new("/work/math.cpp"),
+        "module;\n#include <cmath>\nexport module math.core;\n"
+    ));
+    assert!(!modules::is_interface_unit(
+        Path::new("/work/main.cpp"),
+        "import math;\n"
+    ));
+    assert_eq!(
+        modules::module_name("export module math:ops;"),
+        Some("math:ops")
+    );
+
+    let modules = Modules::new(&enabled());
+    let command = modules
+        .compile_command(&INTERFACE.parse().unwrap())
+        .unwrap();
+    assert_eq!(command["method"], "workspace/didChangeConfiguration");
+    assert_eq!(
+        command["params"]["settings"]["compilationDatabaseChanges"]["/work/math.cppm"]["compilationCommand"],
+        json!([
+            "clang++",
+            "-std=c++20",
+            "-x",
+            "c++-module",
+            "/work/math.cppm"
+        ])
+    );
+
+    let mut rpc = json!({"params": {"uri": INTERFACE, "diagnostics": [
+        diagnostic("module 'std' not found"),
+        diagnostic("use of undeclared identifier 'x'"),
+    ]}});
+    let taken = modules.take_diagnostics(&mut rpc);
+    assert_eq!(taken.len(), 1);
+    assert_eq!(taken[0]["source"], modules::SOURCE);
+    assert_eq!(rpc["params"]["diagnostics"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_dispatcher_reports_module_build() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config {
+        modules: enabled(),
+        ..Config::default()
+    };
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": 1, "method": "initialize",
+            "params": {"capabilities": {"window": {"workDoneProgress": true}}}}))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": {"textDocument": {"uri": INTERFACE, "languageId": "cpp", "version": 1,
+                "text": "export module math;\nexport int add(int a, int b);\n"}}}))
+        .await
+        .unwrap();
+    // 编译参数先于 didOpen 交给后端
+    let configuration = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(configuration["method"], "workspace/didChangeConfiguration");
+    let open = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(open["method"], "textDocument/didOpen");
+
+    let create = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(create["method"], "window/workDoneProgress/create");
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": create["id"], "result": null}))
+        .await
+        .unwrap();
+    let begin = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(begin["params"]["token"], create["params"]["token"]);
+    assert_eq!(begin["params"]["value"]["title"], "构建模块 math");
+
+    dispatcher
+        .handle_from_shard(
+            0,
+            json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": {
+                "uri": INTERFACE, "version": 1,
+                "diagnostics": [diagnostic("unknown type name 'intt'"), diagnostic("could not build module 'std'")],
+            }}),
+        )
+        .await
+        .unwrap();
+    let end = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(end["params"]["value"]["kind"], "end");
+    let published = frontend_rx.recv().await.unwrap().into_body();
+    let sources: Vec<&str> = published["params"]["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["source"].as_str().unwrap())
+        .collect();
+    assert_eq!(sources, ["clang", "modules"]);
+}
@@ -0,0 +1,237 @@
+use lsp_proxy::config::NotebookConfig;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::notebooks::{self, Notebooks};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+const NOTEBOOK: &str = "file:///work/demo.ipynb";
+const VIRTUAL: &str = "file:///work/demo.ipynb.cpp";
+
+fn cell(n: u32) -> String {
+    format!("vscode-notebook-cell:/work/demo.ipynb#C{}", n)
+}
+
+fn range(start: (u32, u32), end: (u32, u32)) -> Value {
+    json!({"start": {"line": start.0, "character": start.1},
+           "end": {"line": end.0, "character": end.1}})
+}
+
+fn did_open() -> Value {
+    json!({
+        "notebookDocument": {"uri": NOTEBOOK, "notebookType": "jupyter-notebook", "version": 1,
+            "cells": [
+                {"kind": 2, "document": cell(1)},
+                {"kind": 1, "document": cell(2)},
+                {"kind": 2, "document": cell(3)},
+            ]},
+        "cellTextDocuments": [
+            {"uri": cell(1), "languageId": "cpp", "version": 1, "text": "#include <vector>\nint x = 1;"},
+            {"uri": cell(2), "languageId": "markdown", "version": 1, "text": "# 说明"},
+            {"uri": cell(3), "languageId": "cpp", "version": 1, "text": "x + y\n"},
+        ],
+    })
+}
+
+#[test]
+fn test_cells_become_one_virtual_document() {
+    let notebooks = Notebooks::new(&NotebookConfig::default());
+    let open = notebooks
+        .to_text_document(notebooks::DID_OPEN, &did_open())
+        .unwrap();
+    assert_eq!(open["method"], "textDocument/didOpen");
+    assert_eq!(open["params"]["textDocument"]["uri"], VIRTUAL);
+    assert_eq!(
+        open["params"]["textDocument"]["text"],
+        "#include <vector>\nint x = 1;\nx + y\n"
+    );
+
+    // 插入一个新单元，把第三个单元移到最前面，并编辑第一个单元
+    let change = notebooks
+        .to_text_document(
+            notebooks::DID_CHANGE,
+            &json!({
+                "notebookDocument": {"uri": NOTEBOOK, "version": 2},
+                "change": {"cells": {
+                    "structure": {
+                        "array": {"start": 2, "deleteCount": 1, "cells": [
+                            {"kind": 2, "document": cell(4)},
+                        ]},
+                        "didOpen": [{"uri": cell(4), "languageId": "cpp", "version": 1, "text": "int y;"}],
+                    },
+                    "textContent": [{
+                        "document": {"uri": cell(1), "version": 2},
+                        "changes": [{"range": range((1, 8), (1, 9)), "text": "2"}],
+                    }],
+                }},
+            }),
+        )
+        .unwrap();
+    assert_eq!(change["params"]["textDocument"]["version"], 2);
+    assert_eq!(
+        change["params"]["contentChanges"][0]["text"],
+        "#include <vector>\nint x = 2;\nint y;\n"
+    );
+    let moved = notebooks
+        .to_text_document(
+            notebooks::DID_CHANGE,
+            &json!({
+                "notebookDocument": {"uri": NOTEBOOK, "version": 3},
+                "change": {"cells": {"structure": {
+                    "array": {"start": 2, "deleteCount": 1},
+                }}},
+            }),
+        )
+        .unwrap();
+    assert_eq!(
+        moved["params"]["contentChanges"][0]["text"],
+        "#include <vector>\nint x = 2;\n"
+    );
+
+    let close = notebooks
+        .to_text_document(
+            notebooks::DID_CLOSE,
+            &json!({"notebookDocument": {"uri": NOTEBOOK}, "cellTextDocuments": []}),
+        )
+        .unwrap();
+    assert_eq!(close["method"], "textDocument/didClose");
+    assert!(
+        notebooks
+            .to_text_document(
+                notebooks::DID_SAVE,
+                &json!({"notebookDocument": {"uri": NOTEBOOK}})
+            )
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_dispatcher_translates_cell_positions() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "method": "notebookDocument/didOpen", "params": did_open()}),
+        )
+        .await
+        .unwrap();
+    let open = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(open["method"], "textDocument/didOpen");
+    assert_eq!(dispatcher.documents().len(), 1);
+
+    // 第三个单元从虚拟文档的第 2 行开始
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/definition",
+            "params": {"textDocument": {"uri": cell(3)}, "position": {"line": 0, "character": 0}}}),
+        )
+        .await
+        .unwrap();
+    let request = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(request["params"]["textDocument"]["uri"], VIRTUAL);
+    assert_eq!(request["params"]["position"]["line"], 2);
+
+    dispatcher
+        .handle_from_shard(
+            0,
+            json!({"jsonrpc": "2.0", "id": 1, "result": [
+                {"uri": VIRTUAL, "range": range((1, 4), (1, 5))},
+                {"uri": "file:///usr/include/vector", "range": range((1, 0), (1, 1))},
+            ]}),
+        )
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["result"][0]["uri"], cell(1));
+    assert_eq!(response["result"][0]["range"], range((1, 4), (1, 5)));
+    assert_eq!(response["result"][1]["range"], range((1, 0), (1, 1)));
+
+    // 诊断按单元拆开，没有诊断的单元收到空列表
+    dispatcher
+        .handle_from_shard(
+            0,
+            json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": {
+                "uri": VIRTUAL, "version": 1,
+                "diagnostics": [{"range": range((2, 4), (2, 5)), "message": "use of undeclared identifier 'y'"}],
+            }}),
+        )
+        .await
+        .unwrap();
+    let mut published = Vec::new();
+    for _ in 0..2 {
+        published.push(frontend_rx.recv().await.unwrap().into_body());
+    }
+    published.sort_by_key(|rpc| rpc["params"]["uri"].as_str().unwrap().to_string());
+    assert_eq!(published[0]["params"]["uri"], cell(1));
+    assert_eq!(published[0]["params"]["diagnostics"], json!([]));
+    assert_eq!(published[1]["params"]["uri"], cell(3));
+    assert_eq!(
+        published[1]["params"]["diagnostics"][0]["range"],
+        range((0, 4), (0, 5))
+    );
+}
+
+#[tokio::test]
+async fn test_workspace_edits_and_symbols_are_split_by_cell() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "method": "notebookDocument/didOpen", "params": did_open()}),
+        )
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "id": 2, "method": "textDocument/rename",
+            "params": {"textDocument": {"uri": cell(1)}, "position": {"line": 1, "character": 4},
+                       "newName": "z"}}),
+        )
+        .await
+        .unwrap();
+    let request = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(request["params"]["position"]["line"], 1);
+    dispatcher
+        .handle_from_shard(
+            0,
+            json!({"jsonrpc": "2.0", "id": 2, "result": {"changes": {VIRTUAL: [
+                {"range": range((1, 4), (1, 5)), "newText": "z"},
+                {"range": range((2, 0), (2, 1)), "newText": "z"},
+            ]}}}),
+        )
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    let changes = &response["result"]["changes"];
+    assert!(changes.get(VIRTUAL).is_none());
+    assert_eq!(changes[cell(1)][0]["range"], range((1, 4), (1, 5)));
+    assert_eq!(changes[cell(3)][0]["range"], range((0, 0), (0, 1)));
+
+    // 文档符号只保留请求的单元中的符号
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": 3,
+            "method": "textDocument/documentSymbol", "params": {"textDocument": {"uri": cell(3)}}}))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+    dispatcher
+        .handle_from_shard(
+            0,
+            json!({"jsonrpc": "2.0", "id": 3, "result": [
+                {"name": "x", "kind": 13, "range": range((1, 0), (1, 10)), "selectionRange": range((1, 4), (1, 5))},
+                {"name": "y", "kind": 13, "range": range((2, 0), (2, 5)), "selectionRange": range((2, 4), (2, 5))},
+            ]}),
+        )
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    let symbols = response["result"].as_array().unwrap();
+    assert_eq!(symbols.len(), 1);
+    assert_eq!(symbols[0]["name"], "y");
+    assert_eq!(symbols[0]["selectionRange"], range((0, 4), (0, 5)));
+}
@@ -0,0 +1,111 @@
+use lsp_proxy::config::{Config, ResourceLimits};
+use lsp_proxy::platform::{DEFAULT_PATHEXT, MAX_CPUS, resolve_program_in, validate_limits};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// 用给定的文件集合代替文件系统，测试不依赖当前平台。
+fn resolve(program: &str, dirs: &[&str], extensions: &str, files: &[&str]) -> Option<PathBuf> {
+    let files: HashSet<PathBuf> = files.iter().map(PathBuf::from).collect();
+    let path = std::env::join_paths(dirs).unwrap();
+    resolve_program_in(
+        program,
+        Some(path.as_os_str()),
+        extensions,
+        |candidate: &Path| files.contains(candidate),
+    )
+}
+
+#[test]
+fn test_program_is_found_with_pathext() {
+    let clangd = Path::new("tools").join("clangd.exe");
+    let found = resolve(
+        "clangd",
+        &["bin", "tools"],
+        DEFAULT_PATHEXT,
+        &[clangd.to_str().unwrap()],
+    );
+    assert_eq!(found, Some(clangd.clone()));
+    // 已经带扩展名时不再补全
+    let found = resolve(
+        "clangd.exe",
+        &["bin", "tools"],
+        DEFAULT_PATHEXT,
+        &[clangd.to_str().unwrap()],
+    );
+    assert_eq!(found, Some(clangd));
+    // 没有扩展名列表时（Unix）只找同名文件
+    assert_eq!(
+        resolve("clangd", &["tools"], "", &["tools/clangd.exe"]),
+        None
+    );
+}
+
+#[test]
+fn test_relative_path_is_not_searched_in_path() {
+    let program = Path::new("llvm").join("bin").join("clangd");
+    let exe = Path::new("llvm").join("bin").join("clangd.exe");
+    let found = resolve(
+        program.to_str().unwrap(),
+        &["elsewhere"],
+        ".EXE;.CMD",
+        &[exe.to_str().unwrap(), "elsewhere/clangd.exe"],
+    );
+    assert_eq!(found, Some(exe));
+    assert_eq!(resolve("clangd", &["bin", "tools"], ".EXE", &[]), None);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_process_can_be_stopped_and_continued() {
+    use lsp_proxy::platform::set_process_stopped;
+
+    let mut child = std::process::Command::new("sleep")
+        .arg("30")
+        .spawn()
+        .unwrap();
+    let state = |pid: u32| {
+        // 等待内核完成状态切换
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+        stat.rsplit(") ").next().unwrap().chars().next().unwrap()
+    };
+    set_process_stopped(child.id(), true).unwrap();
+    assert_eq!(state(child.id()), 'T');
+    set_process_stopped(child.id(), false).unwrap();
+    assert_eq!(state(child.id()), 'S');
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn test_limits_are_validated() {
+    let limits = |nice: Option<i32>, cpus: Vec<usize>| ResourceLimits {
+        nice,
+        cpus,
+        memory_mb: None,
+    };
+    assert!(validate_limits(&limits(Some(-20), vec![0, MAX_CPUS - 1])).is_ok());
+    assert!(validate_limits(&limits(Some(20), Vec::new())).is_err());
+    assert!(validate_limits(&limits(Some(-21), Vec::new())).is_err());
+    assert!(validate_limits(&limits(None, vec![MAX_CPUS])).is_err());
+    assert!(Config::parse("[backend.limits]\ncpus = [4096]\n").is_err());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_failed_limit_does_not_prevent_start() {
+    use lsp_proxy::platform::apply_limits;
+
+    // 普通用户不能使用负的 nice 值，以 root 运行时设置成功；两种情况下进程都照常启动
+    let mut command = tokio::process::Command::new("true");
+    apply_limits(
+        &mut command,
+        &ResourceLimits {
+            nice: Some(-20),
+            cpus: Vec::new(),
+            memory_mb: None,
+        },
+    );
+    let status = command.status().await.unwrap();
+    assert!(status.success());
+}
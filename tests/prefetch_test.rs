@@ -0,0 +1,154 @@
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use lsp_proxy::config::{Config, PrefetchConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::document_store::DocumentStore;
+use lsp_proxy::message::Message;
+use lsp_proxy::prefetch::{
+    CacheKey, MAX_CACHED_RESULTS, Prefetcher, adjacent_identifiers, identifier_at,
+};
+use serde_json::{Value, json};
+use tower_lsp::lsp_types::{Position, Range, Url};
+
+fn parse_message(message: &Message) -> Value {
+    message.body().clone()
+}
+
+#[test]
+fn test_adjacent_identifiers() {
+    let text = "int main() {\n  return add(lhs, rhs);\n}\n";
+    let positions = adjacent_identifiers(text, Position::new(1, 10), 3);
+    // 跳过光标下的 add 和关键字 return/int，按距离排序
+    assert_eq!(
+        positions,
+        vec![
+            Position::new(1, 13),
+            Position::new(1, 18),
+            Position::new(0, 4)
+        ]
+    );
+}
+
+#[test]
+fn test_identifier_at() {
+    let text = "int main() {\n  return add(lhs, 42);\n}\n";
+    let add = Range::new(Position::new(1, 9), Position::new(1, 12));
+    for character in 9..=12 {
+        assert_eq!(identifier_at(text, Position::new(1, character)), Some(add));
+    }
+    assert_eq!(identifier_at(text, Position::new(1, 1)), None);
+    assert_eq!(identifier_at(text, Position::new(1, 18)), None);
+    assert_eq!(identifier_at(text, Position::new(5, 0)), None);
+    // 位置使用 UTF-16 编码
+    let wide = "/*é*/ x";
+    assert_eq!(
+        identifier_at(wide, Position::new(0, 6)),
+        Some(Range::new(Position::new(0, 6), Position::new(0, 7)))
+    );
+}
+
+#[test]
+fn test_cache_evicts_least_recently_used() {
+    let prefetcher = Prefetcher::new(PrefetchConfig {
+        enabled: true,
+        ..PrefetchConfig::default()
+    });
+    // 每行一个四个字符的标识符
+    let documents = DocumentStore::new();
+    let uri = Url::parse("file:///repo/a.cpp").unwrap();
+    let text: Vec<String> = (0..=MAX_CACHED_RESULTS)
+        .map(|i| format!("v{:03}", i))
+        .collect();
+    documents.apply(
+        "textDocument/didOpen",
+        &json!({"textDocument": {
+            "uri": uri, "languageId": "cpp", "version": 1, "text": text.join("\n")
+        }}),
+    );
+    let key = |line: u32| {
+        let identifier = Range::new(Position::new(line, 0), Position::new(line, 4));
+        CacheKey::new("textDocument/hover", uri.clone(), identifier)
+    };
+    let cached = |line: u32| {
+        let params = json!({"textDocument": {"uri": uri}, "position": Position::new(line, 2)});
+        prefetcher.cached("textDocument/hover", &params, &documents)
+    };
+
+    let generation = prefetcher.begin();
+    let last = MAX_CACHED_RESULTS as u32;
+    for line in 0..last {
+        prefetcher.insert_if_current(generation, key(line), json!(line));
+    }
+    // 第 0 行的结果刚刚使用过，缓存满时丢弃的是第 1 行
+    assert_eq!(cached(0), Some(json!(0)));
+    prefetcher.insert_if_current(generation, key(last), json!(last));
+
+    assert_eq!(prefetcher.len(), MAX_CACHED_RESULTS);
+    assert_eq!(cached(1), None);
+    assert_eq!(cached(0), Some(json!(0)));
+    assert_eq!(cached(last), Some(json!(last)));
+}
+
+#[tokio::test]
+async fn test_hover_is_answered_from_prefetch_cache() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config {
+        prefetch: PrefetchConfig {
+            enabled: true,
+            max_identifiers: 1,
+            max_concurrent: 1,
+        },
+        ..Config::default()
+    };
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+
+    let uri = "file:///repo/a.cpp";
+    dispatcher
+        .handle_from_frontend(json!({
+            "jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": {"textDocument": {"uri": uri, "languageId": "cpp", "version": 1, "text": "foo(bar);\n"}}
+        }))
+        .await
+        .unwrap();
+    dispatcher
+        .handle_from_frontend(json!({
+            "jsonrpc": "2.0", "id": 1, "method": "textDocument/hover",
+            "params": {"textDocument": {"uri": uri}, "position": {"line": 0, "character": 1}}
+        }))
+        .await
+        .unwrap();
+
+    // 后端依次收到 didOpen、用户的悬停请求，以及空闲后对 bar 的预取
+    backend_rx.recv().await.unwrap();
+    backend_rx.recv().await.unwrap();
+    for _ in 0..2 {
+        let request = parse_message(&backend_rx.recv().await.unwrap());
+        assert_eq!(
+            request["params"]["position"],
+            json!({"line": 0, "character": 4})
+        );
+        let result = if request["method"] == "textDocument/hover" {
+            json!({"contents": "int bar"})
+        } else {
+            json!([])
+        };
+        dispatcher
+            .handle_from_backend(json!({"jsonrpc": "2.0", "id": request["id"], "result": result}))
+            .await
+            .unwrap();
+    }
+
+    // 光标在 bar 中间，同样命中预取的结果
+    dispatcher
+        .handle_from_frontend(json!({
+            "jsonrpc": "2.0", "id": 2, "method": "textDocument/hover",
+            "params": {"textDocument": {"uri": uri}, "position": {"line": 0, "character": 6}}
+        }))
+        .await
+        .unwrap();
+    let response = parse_message(&frontend_rx.recv().await.unwrap());
+    assert_eq!(response["id"], 2);
+    assert_eq!(response["result"]["contents"], "int bar");
+}
@@ -0,0 +1,78 @@
+use lsp_proxy::cli::CliArgs;
+use lsp_proxy::config::Config;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::reload::ConfigSource;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+#[test]
+fn test_declared_profile_extends_builtin() {
+    let text = "[concurrency]\nmax_handlers = 16\n\n\
+                [profile.battery]\nprefetch.max_concurrent = 1\n\n\
+                [profile.review]\nlogging.level = \"debug\"\n";
+
+    let plain = Config::parse(text).unwrap();
+    assert_eq!(plain.concurrency.max_handlers, 16);
+
+    let battery = Config::parse_profile(text, Some("battery")).unwrap();
+    assert_eq!(battery.concurrency.max_handlers, 4);
+    assert!(!battery.prefetch.enabled);
+    assert_eq!(battery.prefetch.max_concurrent, 1);
+
+    let ci = Config::parse_profile("", Some("ci")).unwrap();
+    assert_eq!(ci.logging.level_filter(), log::LevelFilter::Warn);
+
+    let err = Config::parse_profile(text, Some("turbo")).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("可用的配置档：battery, ci, low-latency, review"),
+        "{:#}",
+        err
+    );
+}
+
+#[test]
+fn test_cli_selects_profile() {
+    let args = CliArgs::parse(["--profile", "low-latency"].map(String::from)).unwrap();
+    assert_eq!(args.profile.as_deref(), Some("low-latency"));
+    assert!(CliArgs::parse(["--profile"].map(String::from)).is_err());
+}
+
+#[tokio::test]
+async fn test_set_profile_applies_live_settings() {
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::default();
+    let source = Arc::new(ConfigSource::new(None, None, config.clone(), |_| {}));
+    let dispatcher = Arc::new(
+        Dispatcher::new(backend_tx, frontend_tx)
+            .with_config(config)
+            .with_config_source(source.clone()),
+    );
+    let limit = dispatcher.subscribe_handler_limit();
+
+    let request = json!({"jsonrpc": "2.0", "id": 1, "method": "codefuse/setProfile",
+        "params": {"profile": "low-latency"}});
+    dispatcher.handle_from_frontend(request).await.unwrap();
+    let message = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(message["method"], "window/showMessage");
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["result"]["profile"], "low-latency");
+    assert_eq!(
+        response["result"]["live"],
+        json!(["[prefetch]", "[concurrency]"])
+    );
+    assert_eq!(*limit.borrow(), 32);
+    assert!(dispatcher.prefetcher().is_enabled());
+    assert_eq!(source.profile().as_deref(), Some("low-latency"));
+
+    // 配置档不存在时返回错误，保留原来的配置档
+    let request = json!({"jsonrpc": "2.0", "id": 2, "method": "codefuse/setProfile",
+        "params": {"profile": "turbo"}});
+    dispatcher.handle_from_frontend(request).await.unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["id"], 2);
+    assert!(response["error"]["message"].is_string());
+    assert_eq!(source.profile().as_deref(), Some("low-latency"));
+}
@@ -0,0 +1,180 @@
+use lsp_proxy::clangd_flags::add_query_drivers;
+use lsp_proxy::config::{Config, QueryDriverMode};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::query_driver::{self, compilers, detect};
+use serde_json::json;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::Url;
+
+/// 在 `root` 下创建一个假的编译器，返回它的绝对路径。
+fn fake_compiler(root: &Path, relative: &str) -> String {
+    let path = root.join(relative);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, "").unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn write_database(root: &Path, compiler: &str) {
+    let database = json!([
+        {
+            "directory": root,
+            "file": "main.c",
+            "arguments": ["ccache", compiler, "-c", "main.c"],
+        },
+        {
+            "directory": root.join("build"),
+            "file": "../startup.c",
+            "command": "../toolchain/bin/arm-none-eabi-gcc -c ../startup.c",
+        },
+        {"directory": root, "file": "host.cpp", "command": "clang++ -c host.cpp"},
+        {"directory": root, "file": "gone.c", "command": "/nonexistent/bin/gcc -c gone.c"},
+    ]);
+    std::fs::write(
+        root.join("compile_commands.json"),
+        serde_json::to_string(&database).unwrap(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_compilers_from_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let gcc = fake_compiler(dir.path(), "toolchain/bin/arm-none-eabi-gcc");
+    std::fs::create_dir_all(dir.path().join("build")).unwrap();
+    let xtensa = fake_compiler(dir.path(), "xtensa/bin/xtensa-esp32-elf-g++");
+    write_database(dir.path(), &xtensa);
+
+    // 包装程序被跳过，clang 和找不到的编译器不包括在内
+    let found = compilers(&dir.path().join("compile_commands.json")).unwrap();
+    assert_eq!(found, vec![gcc, xtensa]);
+}
+
+#[test]
+fn test_detect_skips_allowed_compilers() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("build")).unwrap();
+    fake_compiler(dir.path(), "toolchain/bin/arm-none-eabi-gcc");
+    let xtensa = fake_compiler(dir.path(), "xtensa/bin/xtensa-esp32-elf-g++");
+    write_database(dir.path(), &xtensa);
+    let roots = vec![dir.path().to_path_buf()];
+
+    assert_eq!(detect(&roots, &[]).len(), 2);
+    assert_eq!(
+        detect(&roots, &["**/arm-none-eabi-*".to_string()]),
+        vec![xtensa]
+    );
+    assert!(detect(&[dir.path().join("missing")], &[]).is_empty());
+}
+
+#[test]
+fn test_add_query_drivers() {
+    let mut args = vec!["--log=error".to_string()];
+    add_query_drivers(&mut args, &[]);
+    assert_eq!(args, vec!["--log=error"]);
+    add_query_drivers(&mut args, &["/opt/a/gcc".to_string()]);
+    assert_eq!(args, vec!["--log=error", "--query-driver=/opt/a/gcc"]);
+    add_query_drivers(
+        &mut args,
+        &["/opt/b/gcc".to_string(), "/opt/c/gcc".to_string()],
+    );
+    assert_eq!(
+        args,
+        vec![
+            "--log=error",
+            "--query-driver=/opt/a/gcc,/opt/b/gcc,/opt/c/gcc"
+        ]
+    );
+}
+
+#[test]
+fn test_parse_auto_query_driver() {
+    let config = Config::parse("[backend.clangd]\nauto_query_driver = \"ask\"").unwrap();
+    assert_eq!(
+        config.backend.clangd.auto_query_driver,
+        QueryDriverMode::Ask
+    );
+    assert_eq!(
+        Config::parse("").unwrap().backend.clangd.auto_query_driver,
+        QueryDriverMode::Off
+    );
+}
+
+#[tokio::test]
+async fn test_watch_asks_before_allowing() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("build")).unwrap();
+    fake_compiler(dir.path(), "toolchain/bin/arm-none-eabi-gcc");
+    let xtensa = fake_compiler(dir.path(), "xtensa/bin/xtensa-esp32-elf-g++");
+    write_database(dir.path(), &xtensa);
+
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let mut launch = dispatcher.subscribe_backend_launch();
+    let watch = tokio::spawn(query_driver::watch(
+        Arc::clone(&dispatcher),
+        QueryDriverMode::Ask,
+    ));
+
+    let root = Url::from_directory_path(dir.path()).unwrap();
+    let initialize = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+        "processId": null,
+        "rootUri": root,
+        "capabilities": {},
+        "workspaceFolders": [{"uri": root, "name": "firmware"}],
+    }});
+    dispatcher.handle_from_frontend(initialize).await.unwrap();
+
+    let prompt = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(prompt["method"], "window/showMessageRequest");
+    assert!(
+        prompt["params"]["message"]
+            .as_str()
+            .unwrap()
+            .contains(&xtensa)
+    );
+    let answer = json!({"jsonrpc": "2.0", "id": prompt["id"], "result": {"title": "允许"}});
+    dispatcher.handle_from_frontend(answer).await.unwrap();
+    watch.await.unwrap();
+
+    assert_eq!(dispatcher.query_drivers().len(), 2);
+    launch.changed().await.unwrap();
+    let args = launch.borrow().clone().unwrap().args;
+    assert!(
+        args[0]
+            .iter()
+            .any(|arg| arg.starts_with("--query-driver=") && arg.contains(&xtensa))
+    );
+}
+
+#[tokio::test]
+async fn test_watch_declined() {
+    let dir = tempfile::tempdir().unwrap();
+    let xtensa = fake_compiler(dir.path(), "xtensa/bin/xtensa-esp32-elf-g++");
+    write_database(dir.path(), &xtensa);
+
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let watch = tokio::spawn(query_driver::watch(
+        Arc::clone(&dispatcher),
+        QueryDriverMode::Ask,
+    ));
+    let root = Url::from_directory_path(dir.path()).unwrap();
+    let initialize = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+        "processId": null,
+        "rootUri": root,
+        "capabilities": {},
+        "workspaceFolders": [{"uri": root, "name": "firmware"}],
+    }});
+    dispatcher.handle_from_frontend(initialize).await.unwrap();
+
+    let prompt = frontend_rx.recv().await.unwrap().into_body();
+    let answer = json!({"jsonrpc": "2.0", "id": prompt["id"], "result": null});
+    dispatcher.handle_from_frontend(answer).await.unwrap();
+    watch.await.unwrap();
+    assert!(dispatcher.query_drivers().is_empty());
+}
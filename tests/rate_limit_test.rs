@@ -0,0 +1,183 @@
+use lsp_proxy::config::{Config, RateLimitRule};
+use lsp_proxy::document_observer::DocumentObserver;
+use lsp_proxy::document_store::DocumentStore;
+use lsp_proxy::rate_limit::{RateLimits, Rejection};
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const TOKENS: &str = "textDocument/semanticTokens/full";
+
+fn limits(rule: RateLimitRule) -> RateLimits {
+    RateLimits::new(&HashMap::from([(TOKENS.to_string(), rule)]))
+}
+
+fn documents() -> DocumentStore {
+    let documents = DocumentStore::new();
+    for uri in ["file:///a.cpp", "file:///b.cpp"] {
+        documents.apply(
+            "textDocument/didOpen",
+            &json!({"textDocument": {"uri": uri, "languageId": "cpp", "version": 1, "text": "int x;"}}),
+        );
+    }
+    documents
+}
+
+fn params(uri: &str) -> serde_json::Value {
+    json!({"textDocument": {"uri": uri}})
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_bucket_refills_over_time() {
+    let limits = limits(RateLimitRule {
+        per_second: 2.0,
+        ..Default::default()
+    });
+    let documents = documents();
+    let a = params("file:///a.cpp");
+
+    assert_eq!(limits.reject(TOKENS, Some(&a), &documents), None);
+    assert_eq!(limits.reject(TOKENS, Some(&a), &documents), None);
+    // 桶空了，没有缓存的结果时以空结果应答；只有第一次超过限制时报告令牌用完
+    assert_eq!(
+        limits.reject(TOKENS, Some(&a), &documents),
+        Some(Rejection {
+            result: json!({"data": []}),
+            newly_exhausted: true,
+        })
+    );
+    assert_eq!(
+        limits
+            .reject(TOKENS, Some(&a), &documents)
+            .map(|rejection| rejection.newly_exhausted),
+        Some(false)
+    );
+    // 其他文档有自己的令牌桶，其他方法不受限制
+    assert_eq!(
+        limits.reject(TOKENS, Some(&params("file:///b.cpp")), &documents),
+        None
+    );
+    assert_eq!(
+        limits.reject("textDocument/hover", Some(&a), &documents),
+        None
+    );
+
+    tokio::time::advance(Duration::from_millis(500)).await;
+    assert_eq!(limits.reject(TOKENS, Some(&a), &documents), None);
+    assert!(limits.reject(TOKENS, Some(&a), &documents).is_some());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_rejected_request_reuses_result_for_same_version() {
+    let limits = limits(RateLimitRule {
+        per_second: 1.0,
+        ..Default::default()
+    });
+    let documents = documents();
+    let a = params("file:///a.cpp");
+    let tokens = json!({"data": [0, 0, 3, 1, 0]});
+
+    assert_eq!(limits.reject(TOKENS, Some(&a), &documents), None);
+    limits.request(&json!(1), TOKENS, Some(&a), &documents);
+    limits.responded(
+        &json!(1),
+        &json!({"jsonrpc": "2.0", "id": 1, "result": tokens}),
+    );
+    assert_eq!(
+        limits
+            .reject(TOKENS, Some(&a), &documents)
+            .map(|rejection| rejection.result),
+        Some(tokens)
+    );
+
+    // 文档修改后缓存的结果不再可用
+    documents.apply(
+        "textDocument/didChange",
+        &json!({
+            "textDocument": {"uri": "file:///a.cpp", "version": 2},
+            "contentChanges": [{"text": "int y;"}]
+        }),
+    );
+    assert_eq!(
+        limits
+            .reject(TOKENS, Some(&a), &documents)
+            .map(|rejection| rejection.result),
+        Some(json!({"data": []}))
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_closed_document_drops_bucket_and_result() {
+    let limits = limits(RateLimitRule {
+        per_second: 1.0,
+        ..Default::default()
+    });
+    let documents = documents();
+    let a = params("file:///a.cpp");
+    assert_eq!(limits.reject(TOKENS, Some(&a), &documents), None);
+    limits.request(&json!(1), TOKENS, Some(&a), &documents);
+    limits.responded(
+        &json!(1),
+        &json!({"jsonrpc": "2.0", "id": 1, "result": {"data": [1]}}),
+    );
+    limits.request(&json!(2), TOKENS, Some(&a), &documents);
+
+    let close = serde_json::from_value(json!({"textDocument": {"uri": "file:///a.cpp"}})).unwrap();
+    limits.did_close(&close);
+    // 关闭前发出的请求的响应不再缓存
+    limits.responded(
+        &json!(2),
+        &json!({"jsonrpc": "2.0", "id": 2, "result": {"data": [2]}}),
+    );
+
+    // 重新打开后版本号可能相同，令牌桶重新开始，之前的结果不会用来应答
+    documents.apply(
+        "textDocument/didOpen",
+        &json!({"textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": 1, "text": "long y;"}}),
+    );
+    assert_eq!(limits.reject(TOKENS, Some(&a), &documents), None);
+    assert_eq!(
+        limits
+            .reject(TOKENS, Some(&a), &documents)
+            .map(|rejection| rejection.result),
+        Some(json!({"data": []}))
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_shared_bucket_and_burst() {
+    let limits = limits(RateLimitRule {
+        per_second: 0.5,
+        burst: Some(3),
+        per_document: false,
+    });
+    let documents = documents();
+    for uri in ["file:///a.cpp", "file:///b.cpp", "file:///a.cpp"] {
+        assert_eq!(limits.reject(TOKENS, Some(&params(uri)), &documents), None);
+    }
+    assert!(
+        limits
+            .reject(TOKENS, Some(&params("file:///b.cpp")), &documents)
+            .is_some()
+    );
+    limits.set_rules(&HashMap::new());
+    assert_eq!(
+        limits.reject(TOKENS, Some(&params("file:///b.cpp")), &documents),
+        None
+    );
+}
+
+#[test]
+fn test_rate_limits_config() {
+    let config = Config::parse(
+        r#"
+        [rate_limits."textDocument/semanticTokens/full"]
+        per_second = 5
+        "#,
+    )
+    .unwrap();
+    let rule = &config.rate_limits[TOKENS];
+    assert_eq!(rule.per_second, 5.0);
+    assert_eq!(rule.capacity(), 5.0);
+    assert!(rule.per_document);
+}
@@ -0,0 +1,108 @@
+use lsp_proxy::config::{Config, ShardConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::reload::{self, Changes};
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+fn config(text: &str) -> Config {
+    Config::parse(text).unwrap()
+}
+
+#[test]
+fn test_diff_groups_changes_by_effect() {
+    let old = config("[logging]\nslow_request_ms = 500\n");
+    assert!(reload::diff(&old, &old.clone()).is_empty());
+
+    let new = config(
+        "[logging]\nlevel = \"debug\"\n\n[diagnostics.sources]\nspellcheck = false\n\n\
+         [backend]\nargs = [\"-j=4\"]\n\n[warmup]\nenabled = true\n",
+    );
+    let changes = reload::diff(&old, &new);
+    assert_eq!(
+        changes,
+        Changes {
+            live: vec!["[logging]", "[diagnostics]"],
+            restart_backend: vec!["[backend]"],
+            restart_proxy: vec!["[warmup]"],
+        }
+    );
+    assert_eq!(
+        changes.message(),
+        "配置已重新加载：已应用 [logging]、[diagnostics]；[backend] 已修改，正在重启后端；\
+         [warmup] 需要重启代理才能生效"
+    );
+    assert_eq!(new.logging.level_filter(), log::LevelFilter::Debug);
+}
+
+#[tokio::test]
+async fn test_apply_config_updates_diagnostic_filters() {
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let old = Config::default();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(old.clone()));
+    let restart = dispatcher.subscribe_restart();
+    let publish = json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics",
+    "params": {"uri": "file:///a.cpp", "diagnostics": [
+        {"message": "expected ';'", "source": "clang"},
+        {"message": "use auto", "source": "clang-tidy", "code": "modernize-use-auto"},
+    ]}});
+
+    let new =
+        config("[diagnostics.sources]\nclang = false\n\n[[tidy]]\nchecks = \"-modernize-*\"\n");
+    let changes = reload::diff(&old, &new);
+    dispatcher.apply_config(&new, &changes);
+    let message = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(message["method"], "window/showMessage");
+    assert_eq!(message["params"]["type"], 3);
+    assert!(!restart.has_changed().unwrap());
+
+    dispatcher.handle_from_shard(0, publish).await.unwrap();
+    let forwarded = frontend_rx.recv().await.unwrap().into_body();
+    assert!(
+        forwarded["params"]["diagnostics"]
+            .as_array()
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_backend_change_restarts_with_new_arguments() {
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let mut old = Config::default();
+    old.shards.push(ShardConfig {
+        name: Some("services".to_string()),
+        path: "/repo/services".into(),
+        args: vec!["-j=8".to_string()],
+    });
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(old.clone()));
+    let restart = dispatcher.subscribe_restart();
+    let launch = dispatcher.subscribe_backend_launch();
+
+    let mut new = old.clone();
+    new.backend.args = vec!["--background-index".to_string()];
+    new.spellcheck.enabled = true;
+    dispatcher.apply_config(&new, &reload::diff(&old, &new));
+
+    assert!(restart.has_changed().unwrap());
+    let next = launch.borrow().clone().unwrap();
+    assert_eq!(
+        next.args,
+        [
+            vec!["--background-index".to_string()],
+            vec!["--background-index".to_string(), "-j=8".to_string()],
+        ]
+    );
+    // 需要重启代理的修改以警告显示
+    let message = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(message["params"]["type"], 2);
+    assert!(
+        message["params"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("[spellcheck] 需要重启代理")
+    );
+}
@@ -0,0 +1,110 @@
+use lsp_proxy::remote::{self, MAX_SYNC_FILE_BYTES, PathMapping, SYNC_FILE};
+use serde_json::json;
+use std::path::Path;
+use tower_lsp::lsp_types::Url;
+
+#[test]
+fn test_mapping_translates_uris_paths_and_keys() {
+    let mapping = PathMapping::new(Path::new("/home/me/app"), Path::new("/build/app")).unwrap();
+    let mut message = json!({
+        "rootPath": "/home/me/app",
+        "rootUri": "file:///home/me/app/",
+        "edit": {"changes": {"file:///home/me/app/main.cpp": []}},
+        "other": "file:///home/me/application/main.cpp",
+        "locations": [{"uri": "file:///home/me/app/a.h", "targetUri": "file:///home/me/app/b.h"}],
+        // 文档文本和悬停内容中的路径不是 URI 字段，保持原样
+        "text": "/home/me/app/main.cpp",
+        "contents": {"value": "file:///home/me/app/a.h"},
+    });
+    mapping.to_remote(&mut message);
+    assert_eq!(
+        message,
+        json!({
+            "rootPath": "/build/app",
+            "rootUri": "file:///build/app/",
+            "edit": {"changes": {"file:///build/app/main.cpp": []}},
+            "other": "file:///home/me/application/main.cpp",
+            "locations": [{"uri": "file:///build/app/a.h", "targetUri": "file:///build/app/b.h"}],
+            "text": "/home/me/app/main.cpp",
+            "contents": {"value": "file:///home/me/app/a.h"},
+        })
+    );
+
+    mapping.to_local(&mut message);
+    assert_eq!(message["rootUri"], "file:///home/me/app/");
+    assert!(PathMapping::new(Path::new("relative"), Path::new("/build")).is_err());
+}
+
+#[test]
+fn test_relay_clears_process_id() {
+    let mut initialize = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize",
+        "params": {"processId": 4242, "rootUri": null, "capabilities": {}}});
+    remote::clear_process_id(&mut initialize);
+    assert_eq!(initialize["params"]["processId"], json!(null));
+
+    // 其他消息中的同名字段不变
+    let mut other =
+        json!({"jsonrpc": "2.0", "method": "custom/notify", "params": {"processId": 7}});
+    remote::clear_process_id(&mut other);
+    assert_eq!(other["params"]["processId"], 7);
+}
+
+#[test]
+fn test_saved_and_deleted_files_are_synced() {
+    let dir = tempfile::tempdir().unwrap();
+    let saved = dir.path().join("main.cpp");
+    std::fs::write(&saved, "int main() {}").unwrap();
+    let saved_uri = Url::from_file_path(&saved).unwrap().to_string();
+
+    let did_save = json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didSave",
+        "params": {"textDocument": {"uri": saved_uri}}
+    });
+    let notifications = remote::sync_notifications(&did_save);
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0]["method"], SYNC_FILE);
+    assert_eq!(notifications[0]["params"]["text"], "int main() {}");
+
+    let watched = json!({
+        "jsonrpc": "2.0",
+        "method": "workspace/didChangeWatchedFiles",
+        "params": {"changes": [{"uri": "file:///gone.h", "type": 3}]}
+    });
+    let notifications = remote::sync_notifications(&watched);
+    assert_eq!(
+        notifications[0]["params"],
+        json!({"uri": "file:///gone.h", "text": null})
+    );
+}
+
+#[test]
+fn test_large_files_are_not_synced() {
+    let dir = tempfile::tempdir().unwrap();
+    let large = dir.path().join("data.bin");
+    let file = std::fs::File::create(&large).unwrap();
+    file.set_len(MAX_SYNC_FILE_BYTES + 1).unwrap();
+
+    let did_save = json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didSave",
+        "params": {"textDocument": {"uri": Url::from_file_path(&large).unwrap()}}
+    });
+    assert!(remote::sync_notifications(&did_save).is_empty());
+}
+
+#[test]
+fn test_sync_only_writes_inside_workspace() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("workspace");
+    let roots = vec![root.clone()];
+    let uri = Url::from_file_path(root.join("src/new.h")).unwrap();
+
+    let written = remote::apply_sync(&json!({"uri": uri, "text": "#pragma once"}), &roots).unwrap();
+    assert_eq!(std::fs::read_to_string(&written).unwrap(), "#pragma once");
+    remote::apply_sync(&json!({"uri": uri, "text": null}), &roots).unwrap();
+    assert!(!written.exists());
+
+    let outside = Url::from_file_path(dir.path().join("outside.h")).unwrap();
+    assert!(remote::apply_sync(&json!({"uri": outside, "text": "x"}), &roots).is_err());
+}
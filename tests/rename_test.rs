@@ -0,0 +1,44 @@
+use lsp_proxy::document_store::DocumentStore;
+use lsp_proxy::rename::{build_preview, parse_rename_response};
+use serde_json::json;
+use std::path::PathBuf;
+
+fn edit(line: u32, start: u32, end: u32) -> serde_json::Value {
+    json!({
+        "range": {"start": {"line": line, "character": start}, "end": {"line": line, "character": end}},
+        "newText": "renamed"
+    })
+}
+
+#[test]
+fn test_rename_preview_summary_and_conflicts() {
+    let result = json!({
+        "documentChanges": [
+            {"textDocument": {"uri": "file:///repo/a.cpp", "version": 3}, "edits": [edit(1, 4, 7), edit(9, 0, 3), edit(9, 10, 13)]},
+            {"textDocument": {"uri": "file:///usr/include/lib.h", "version": null}, "edits": [edit(2, 0, 3)]}
+        ]
+    });
+    let files = parse_rename_response(&result).unwrap();
+    assert_eq!(files.len(), 2);
+
+    let documents = DocumentStore::new();
+    documents.apply(
+        "textDocument/didOpen",
+        &json!({"textDocument": {"uri": "file:///repo/a.cpp", "languageId": "cpp", "version": 4, "text": ""}}),
+    );
+
+    let preview = build_preview("renamed", &files, &[PathBuf::from("/repo")], &documents);
+    assert_eq!(preview.total_occurrences, 4);
+    let a = preview
+        .files
+        .iter()
+        .find(|f| f.uri.path() == "/repo/a.cpp")
+        .unwrap();
+    assert_eq!((a.occurrences, a.lines.clone()), (3, vec![1, 9]));
+    // 版本不一致、工作区外的文件
+    assert_eq!(preview.conflicts.len(), 2, "{:?}", preview.conflicts);
+    assert!(preview.summary.contains("2 个文件，共 4 处修改"));
+
+    assert!(parse_rename_response(&json!(null)).unwrap().is_empty());
+    assert!(parse_rename_response(&json!({"foo": 1})).is_err());
+}
@@ -0,0 +1,98 @@
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::replay::RequestJournal;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+fn hover(id: i64) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": "textDocument/hover", "params": {
+        "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 0}
+    }})
+}
+
+#[test]
+fn test_journal_answers_requests_that_cannot_be_replayed() {
+    let journal = RequestJournal::new();
+    journal.record(0, "textDocument/hover", &hover(1));
+    journal.record(
+        0,
+        "textDocument/rename",
+        &json!({"jsonrpc": "2.0", "id": 2, "method": "textDocument/rename", "params": {}}),
+    );
+    journal.record(
+        0,
+        "workspace/executeCommand",
+        &json!({"jsonrpc": "2.0", "id": 3, "method": "workspace/executeCommand", "params": {}}),
+    );
+    journal.record(
+        1,
+        "textDocument/rename",
+        &json!({"jsonrpc": "2.0", "id": 4, "method": "textDocument/rename", "params": {}}),
+    );
+    assert_eq!(journal.len(), 4);
+
+    // 切换后端时只读请求重新发出，其他请求以错误应答，不会一直等待
+    let replay = journal.replay(0);
+    let ids: Vec<_> = replay.requests.iter().map(|r| r["id"].clone()).collect();
+    assert_eq!(ids, [json!(1)]);
+    let mut failed: Vec<_> = replay.failed.iter().map(|r| r["id"].clone()).collect();
+    failed.sort_by_key(|id| id.as_i64());
+    assert_eq!(failed, [json!(2), json!(3)]);
+    assert_eq!(replay.failed[0]["error"]["code"], json!(-32803));
+    assert_eq!(replay.failed[0]["error"]["message"], json!("后端已重启"));
+    // 其他分片的请求不受影响
+    assert_eq!(journal.len(), 2);
+
+    journal.forget(&json!(1));
+    journal.forget(&json!(4));
+    assert!(journal.is_empty());
+}
+
+#[test]
+fn test_journal_replays_once_in_order() {
+    let journal = RequestJournal::new();
+    journal.record(0, "textDocument/hover", &hover(2));
+    journal.record(0, "textDocument/hover", &hover(1));
+    journal.record(1, "textDocument/hover", &hover(3));
+
+    let replay = journal.replay(0);
+    let ids: Vec<_> = replay.requests.iter().map(|r| r["id"].clone()).collect();
+    assert_eq!(ids, [json!(2), json!(1)]);
+    assert!(replay.failed.is_empty());
+
+    // 重放后后端又退出了，这些请求以错误应答，不再重放
+    let replay = journal.replay(0);
+    assert!(replay.requests.is_empty());
+    assert_eq!(replay.failed.len(), 2);
+    assert_eq!(replay.failed[0]["error"]["code"], json!(-32803));
+    assert_eq!(journal.len(), 1);
+}
+
+#[tokio::test]
+async fn test_dispatcher_forgets_answered_and_cancelled_requests() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+
+    dispatcher.handle_from_frontend(hover(1)).await.unwrap();
+    dispatcher.handle_from_frontend(hover(2)).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    backend_rx.recv().await.unwrap();
+    assert_eq!(dispatcher.journal().len(), 2);
+
+    dispatcher
+        .handle_from_shard(0, json!({"jsonrpc": "2.0", "id": 1, "result": null}))
+        .await
+        .unwrap();
+    frontend_rx.recv().await.unwrap();
+    assert_eq!(dispatcher.journal().len(), 1);
+
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "method": "$/cancelRequest", "params": {"id": 2}}),
+        )
+        .await
+        .unwrap();
+    assert!(dispatcher.journal().is_empty());
+}
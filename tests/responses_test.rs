@@ -0,0 +1,58 @@
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use lsp_proxy::config::Config;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::responses::{Resolution, ResponseTracker};
+use serde_json::json;
+
+#[test]
+fn test_duplicate_and_unknown_responses_are_told_apart() {
+    let tracker = ResponseTracker::new();
+    tracker.request(&json!(1), "textDocument/hover");
+    tracker.request(&json!("a"), "textDocument/definition");
+
+    assert_eq!(
+        tracker.resolve(&json!(1)),
+        Resolution::Pending("textDocument/hover".to_string())
+    );
+    assert_eq!(tracker.resolve(&json!(1)), Resolution::Duplicate);
+    // 数字 id 与字符串 id 不同
+    assert_eq!(tracker.resolve(&json!("1")), Resolution::Unknown);
+    assert_eq!(
+        tracker.resolve(&json!("a")),
+        Resolution::Pending("textDocument/definition".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_unexpected_responses_are_counted_and_dropped() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse("[protocol]\ndrop_unexpected_responses = true\n").unwrap();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+
+    let hover = json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": {
+        "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 0}
+    }});
+    dispatcher.handle_from_frontend(hover).await.unwrap();
+    backend_rx.recv().await.unwrap();
+
+    let response = json!({"jsonrpc": "2.0", "id": 1, "result": null});
+    dispatcher
+        .handle_from_backend(response.clone())
+        .await
+        .unwrap();
+    dispatcher.handle_from_backend(response).await.unwrap();
+    dispatcher
+        .handle_from_backend(json!({"jsonrpc": "2.0", "id": 99, "result": null}))
+        .await
+        .unwrap();
+
+    assert_eq!(frontend_rx.recv().await.unwrap().id(), Some(&json!(1)));
+    assert!(frontend_rx.try_recv().is_err());
+    let metrics = dispatcher.metrics().snapshot();
+    assert_eq!(metrics.responses_duplicate, 1);
+    assert_eq!(metrics.responses_unknown, 1);
+}
@@ -0,0 +1,164 @@
+use lsp_proxy::config::{Config, SandboxConfig, SandboxTool};
+use lsp_proxy::sandbox::{self, SYSTEM_DIRS, SandboxSpec};
+use std::path::PathBuf;
+
+fn spec(tool: SandboxTool, network: bool) -> SandboxSpec {
+    SandboxSpec {
+        tool,
+        workspace: PathBuf::from("/home/me/app"),
+        writable: vec![
+            PathBuf::from("/home/me/app"),
+            PathBuf::from("/home/me/.cache/clangd"),
+        ],
+        read_only: vec![PathBuf::from("/opt/llvm")],
+        network,
+        extra_args: vec!["--verbose".to_string()],
+    }
+}
+
+#[test]
+fn test_bwrap_args() {
+    let args =
+        spec(SandboxTool::Bwrap, false).wrap_args("clangd", &["--background-index".to_string()]);
+    let system = SYSTEM_DIRS.len() * 3;
+    assert_eq!(&args[..3], ["--ro-bind-try", "/usr", "/usr"]);
+    assert_eq!(
+        &args[system..],
+        [
+            "--ro-bind-try",
+            "/opt/llvm",
+            "/opt/llvm",
+            "--bind-try",
+            "/home/me/app",
+            "/home/me/app",
+            "--bind-try",
+            "/home/me/.cache/clangd",
+            "/home/me/.cache/clangd",
+            "--dev",
+            "/dev",
+            "--proc",
+            "/proc",
+            "--tmpfs",
+            "/tmp",
+            "--unshare-all",
+            "--die-with-parent",
+            "--new-session",
+            "--chdir",
+            "/home/me/app",
+            "--verbose",
+            "--",
+            "clangd",
+            "--background-index",
+        ]
+    );
+
+    // 允许网络时重新共享网络命名空间
+    let args = spec(SandboxTool::Bwrap, true).wrap_args("clangd", &[]);
+    let unshare = args.iter().position(|arg| arg == "--unshare-all").unwrap();
+    assert_eq!(args[unshare + 1], "--share-net");
+}
+
+#[test]
+fn test_firejail_args() {
+    let args = spec(SandboxTool::Firejail, false).wrap_args("clangd", &[]);
+    assert_eq!(
+        args,
+        [
+            "--quiet",
+            "--noprofile",
+            "--private-tmp",
+            "--caps.drop=all",
+            "--nonewprivs",
+            "--nogroups",
+            "--net=none",
+            "--whitelist=/home/me/app",
+            "--whitelist=/home/me/.cache/clangd",
+            "--whitelist=/opt/llvm",
+            "--read-only=/opt/llvm",
+            "--verbose",
+            "clangd",
+        ]
+    );
+    let args = spec(SandboxTool::Firejail, true).wrap_args("clangd", &[]);
+    assert!(!args.iter().any(|arg| arg == "--net=none"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_spec_allows_workspace_and_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = dir.path().join("cache");
+    let config = SandboxConfig {
+        workspace: Some(dir.path().join("app")),
+        writable: vec![PathBuf::from("/srv/build")],
+        read_only: vec![PathBuf::from("/srv/third_party")],
+        ..Default::default()
+    };
+    let envs = [("XDG_CACHE_HOME".to_string(), cache.display().to_string())];
+    let spec = SandboxSpec::new(&config, "clangd", &envs).unwrap();
+    assert_eq!(spec.tool, SandboxTool::Bwrap);
+    assert_eq!(spec.workspace, dir.path().join("app"));
+    assert_eq!(
+        spec.writable,
+        [
+            dir.path().join("app"),
+            cache.join("clangd"),
+            PathBuf::from("/srv/build"),
+        ]
+    );
+    // 缓存目录在第一次启动之前创建，才能挂载进沙箱
+    assert!(cache.join("clangd").is_dir());
+    assert_eq!(spec.read_only.last().unwrap(), "/srv/third_party");
+    assert!(!spec.network);
+}
+
+#[test]
+fn test_parse_sandbox_config() {
+    let config = Config::parse(
+        "[backend.sandbox]\ntool = \"firejail\"\nnetwork = true\nread_only = [\"/srv/sdk\"]\n",
+    )
+    .unwrap();
+    let sandbox = config.backend.sandbox.unwrap();
+    assert_eq!(sandbox.tool, SandboxTool::Firejail);
+    assert_eq!(sandbox.tool.program(), "firejail");
+    assert!(sandbox.network);
+    assert_eq!(sandbox.read_only, [PathBuf::from("/srv/sdk")]);
+    assert!(Config::default().backend.sandbox.is_none());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_spec_requires_workspace() {
+    // 没有配置工作区时不使用代理的当前目录
+    let error = SandboxSpec::new(&SandboxConfig::default(), "clangd", &[]).unwrap_err();
+    assert!(error.to_string().contains("workspace"));
+
+    for workspace in ["/", "~"] {
+        let workspace = match workspace {
+            "~" => PathBuf::from(std::env::var_os("HOME").unwrap()),
+            root => PathBuf::from(root),
+        };
+        let config = SandboxConfig {
+            workspace: Some(workspace),
+            ..Default::default()
+        };
+        assert!(SandboxSpec::new(&config, "clangd", &[]).is_err());
+    }
+}
+
+#[test]
+fn test_rejects_positional_args() {
+    for args in [
+        r#"["/bin/sh", "-c", "id"]"#,
+        r#"["--"]"#,
+        r#"["--bind", "/", "/"]"#,
+    ] {
+        let text = format!("[backend.sandbox]\nworkspace = \"/src\"\nargs = {}\n", args);
+        assert!(Config::parse(&text).is_err(), "{}", args);
+    }
+    let config = SandboxConfig {
+        args: vec!["--hostname=sandbox".to_string()],
+        ..Default::default()
+    };
+    assert!(sandbox::validate(&Some(config)).is_ok());
+}
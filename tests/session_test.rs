@@ -0,0 +1,285 @@
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::session::SessionState;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+fn did_open(version: i32, text: &str) -> Value {
+    json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": version, "text": text}
+    }})
+}
+
+#[test]
+fn test_session_records_registrations() {
+    let session = SessionState::new();
+    let register = |id: &str| {
+        json!({"jsonrpc": "2.0", "id": 1, "method": "client/registerCapability", "params": {
+            "registrations": [{"id": id, "method": "workspace/didChangeWatchedFiles"}]
+        }})
+    };
+    session.record(Some("client/registerCapability"), &register("a"));
+    session.record(Some("client/registerCapability"), &register("b"));
+    session.record(
+        Some("client/unregisterCapability"),
+        &json!({"jsonrpc": "2.0", "id": 2, "method": "client/unregisterCapability", "params": {
+            "unregisterations": [{"id": "a", "method": "workspace/didChangeWatchedFiles"}]
+        }}),
+    );
+    let ids: Vec<_> = session
+        .registrations()
+        .iter()
+        .map(|r| r["id"].clone())
+        .collect();
+    assert_eq!(ids, [json!("b")]);
+
+    session.record(
+        Some("initialize"),
+        &json!({"jsonrpc": "2.0", "id": 1, "result": {"capabilities": {"hoverProvider": true}}}),
+    );
+    assert_eq!(
+        session.initialize_result(),
+        Some(json!({"capabilities": {"hoverProvider": true}}))
+    );
+}
+
+#[tokio::test]
+async fn test_reconnected_editor_resumes_session() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    dispatcher.session().keep_alive();
+
+    let initialize =
+        json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"capabilities": {}}});
+    dispatcher
+        .handle_from_frontend(initialize.clone())
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+    let result = json!({"capabilities": {"hoverProvider": true}});
+    dispatcher
+        .handle_from_shard(0, json!({"jsonrpc": "2.0", "id": 1, "result": result}))
+        .await
+        .unwrap();
+    frontend_rx.recv().await.unwrap();
+    dispatcher
+        .handle_from_frontend(did_open(1, "int x;"))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+    let diagnostic = json!({"message": "unused variable 'x'"});
+    dispatcher
+        .handle_from_shard(
+            0,
+            json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": {
+                "uri": "file:///a.cpp", "version": 1, "diagnostics": [diagnostic]
+            }}),
+        )
+        .await
+        .unwrap();
+    frontend_rx.recv().await.unwrap();
+
+    // 编辑器关闭时的 shutdown 和 exit 不交给后端
+    let shutdown = json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"});
+    dispatcher.handle_from_frontend(shutdown).await.unwrap();
+    let exit = json!({"jsonrpc": "2.0", "method": "exit"});
+    dispatcher.handle_from_frontend(exit).await.unwrap();
+    assert_eq!(
+        frontend_rx.recv().await.unwrap().into_body(),
+        json!({"jsonrpc": "2.0", "id": 2, "result": null})
+    );
+
+    // 重新连接的编辑器：initialize 以上次的结果应答，内容未变的文档重放诊断
+    dispatcher.session().resume();
+    dispatcher.handle_from_frontend(initialize).await.unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["result"], result);
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}))
+        .await
+        .unwrap();
+    dispatcher
+        .handle_from_frontend(did_open(1, "int x;"))
+        .await
+        .unwrap();
+    let replayed = frontend_rx.recv().await.unwrap().into_body();
+    // 没有 source 的诊断标上了后端的来源
+    assert_eq!(
+        replayed["params"]["diagnostics"],
+        json!([{"message": "unused variable 'x'", "source": "clangd"}])
+    );
+    assert!(backend_rx.try_recv().is_err());
+
+    // 内容变化的文档照常交给后端
+    dispatcher
+        .handle_from_frontend(did_open(1, "int y;"))
+        .await
+        .unwrap();
+    let forwarded = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(forwarded["params"]["textDocument"]["text"], "int y;");
+}
+
+#[tokio::test]
+async fn test_session_ends_after_grace_period() {
+    use lsp_proxy::config::TransportConfig;
+    use lsp_proxy::metrics::Metrics;
+    use lsp_proxy::session::{SessionKeeper, forward_to_connection};
+    use lsp_proxy::tasks::HandlerLimiter;
+    use lsp_proxy::transport::{FrontendListener, Transport};
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio_util::sync::CancellationToken;
+
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let limiter = Arc::new(HandlerLimiter::new(8, Arc::new(Metrics::new())));
+    let config = TransportConfig::default();
+    let listener = FrontendListener::bind("127.0.0.1:0", &config)
+        .await
+        .unwrap();
+    let editor = Transport::Socket(listener.local_addr().unwrap().to_string());
+
+    let (accepted, connected) = tokio::join!(listener.accept(), editor.connect(&config));
+    let keeper = SessionKeeper::new(listener, Duration::from_millis(200), config.clone());
+    let closing = CancellationToken::new();
+    tokio::spawn(forward_to_connection(
+        frontend_rx,
+        keeper.connection(),
+        closing.clone(),
+    ));
+    let serve =
+        tokio::spawn(keeper.serve(accepted.unwrap(), Arc::clone(&dispatcher), limiter, closing));
+
+    // 编辑器断开后重新连接，两个连接发来的消息都交给同一个后端
+    let frame = |text: &str| {
+        Dispatcher::format_lsp_message(&did_open(1, text))
+            .unwrap()
+            .into_bytes()
+    };
+    let (_, mut writer) = connected.unwrap();
+    writer.write_all(&frame("int x;")).await.unwrap();
+    writer.shutdown().await.unwrap();
+    drop(writer);
+    assert_eq!(
+        backend_rx.recv().await.unwrap().method(),
+        Some("textDocument/didOpen")
+    );
+
+    let (_, mut writer) = editor.connect(&config).await.unwrap();
+    writer.write_all(&frame("int y;")).await.unwrap();
+    let forwarded = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(forwarded["params"]["textDocument"]["text"], "int y;");
+    assert!(dispatcher.session().is_resumed());
+    drop(writer);
+
+    // 宽限期内没有重新连接：按 LSP 的方式结束后端
+    let shutdown = backend_rx.recv().await.unwrap().into_body();
+    assert_eq!(shutdown["method"], "shutdown");
+    dispatcher
+        .handle_from_shard(
+            0,
+            json!({"jsonrpc": "2.0", "id": shutdown["id"], "result": null}),
+        )
+        .await
+        .unwrap();
+    assert_eq!(backend_rx.recv().await.unwrap().method(), Some("exit"));
+    serve.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_reconnected_editor_request_ids_do_not_collide() {
+    use futures::TryStreamExt;
+    use lsp_proxy::codec::LspCodec;
+    use lsp_proxy::config::TransportConfig;
+    use lsp_proxy::metrics::Metrics;
+    use lsp_proxy::session::{SessionKeeper, forward_to_connection};
+    use lsp_proxy::tasks::HandlerLimiter;
+    use lsp_proxy::transport::{FrontendListener, Transport};
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio_util::codec::FramedRead;
+    use tokio_util::sync::CancellationToken;
+
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+    let limiter = Arc::new(HandlerLimiter::new(8, Arc::new(Metrics::new())));
+    let config = TransportConfig::default();
+    let listener = FrontendListener::bind("127.0.0.1:0", &config)
+        .await
+        .unwrap();
+    let editor = Transport::Socket(listener.local_addr().unwrap().to_string());
+
+    let (accepted, connected) = tokio::join!(listener.accept(), editor.connect(&config));
+    let keeper = SessionKeeper::new(listener, Duration::from_secs(10), config.clone());
+    let closing = CancellationToken::new();
+    tokio::spawn(forward_to_connection(
+        frontend_rx,
+        keeper.connection(),
+        closing.clone(),
+    ));
+    tokio::spawn(keeper.serve(accepted.unwrap(), Arc::clone(&dispatcher), limiter, closing));
+
+    let frame = |message: &Value| {
+        Dispatcher::format_lsp_message(message)
+            .unwrap()
+            .into_bytes()
+    };
+    let hover = json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": {
+        "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 4}}});
+
+    // 第一个编辑器发出请求 1，后端向它发出请求 7
+    let (reader, mut writer) = connected.unwrap();
+    let mut reader = FramedRead::new(reader, LspCodec::with_compression(None));
+    writer
+        .write_all(&frame(&did_open(1, "int x;")))
+        .await
+        .unwrap();
+    writer.write_all(&frame(&hover)).await.unwrap();
+    assert_eq!(
+        backend_rx.recv().await.unwrap().method(),
+        Some("textDocument/didOpen")
+    );
+    assert_eq!(backend_rx.recv().await.unwrap().into_body()["id"], 1);
+    let create = json!({"jsonrpc": "2.0", "id": 7, "method": "window/workDoneProgress/create",
+        "params": {"token": "index"}});
+    dispatcher.handle_from_shard(0, create).await.unwrap();
+    assert_eq!(reader.try_next().await.unwrap().unwrap()["id"], 7);
+
+    // 编辑器断开：请求 7 以错误应答，请求 1 被取消
+    writer.shutdown().await.unwrap();
+    drop((reader, writer));
+    let mut abandoned = Vec::new();
+    for _ in 0..2 {
+        abandoned.push(backend_rx.recv().await.unwrap().into_body());
+    }
+    assert!(
+        abandoned
+            .iter()
+            .any(|m| m["id"] == 7 && m["error"]["code"] == -32800)
+    );
+    assert!(
+        abandoned
+            .iter()
+            .any(|m| m["method"] == "$/cancelRequest" && m["params"]["id"] == 1)
+    );
+
+    // 重新加载的编辑器再次从 1 开始编号请求
+    let (reader, mut writer) = editor.connect(&config).await.unwrap();
+    let mut reader = FramedRead::new(reader, LspCodec::with_compression(None));
+    writer.write_all(&frame(&hover)).await.unwrap();
+    let forwarded = backend_rx.recv().await.unwrap().into_body();
+    assert_ne!(forwarded["id"], 1);
+
+    // 旧请求 1 迟到的响应不会交给新的编辑器
+    let late = json!({"jsonrpc": "2.0", "id": 1, "result": "old"});
+    dispatcher.handle_from_shard(0, late).await.unwrap();
+    let answer = json!({"jsonrpc": "2.0", "id": forwarded["id"], "result": "new"});
+    dispatcher.handle_from_shard(0, answer).await.unwrap();
+    let response = reader.try_next().await.unwrap().unwrap();
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["result"], "new");
+}
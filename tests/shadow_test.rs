@@ -0,0 +1,108 @@
+use lsp_proxy::config::{Config, ShadowConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::shadow::{self, Comparison, Shadow};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+#[test]
+fn test_compare_ignores_backend_data() {
+    let primary = json!({"id": 1, "result": [{"label": "foo", "data": {"id": 1}}]});
+    let shadow = json!({"id": 1, "result": [{"label": "foo", "data": {"id": 7}}]});
+    assert_eq!(shadow::compare(&primary, &shadow), Comparison::Matched);
+
+    let shadow = json!({"id": 1, "result": [{"label": "bar"}]});
+    assert_eq!(shadow::compare(&primary, &shadow), Comparison::Diverged);
+
+    // 错误只比较错误码
+    let primary = json!({"id": 1, "error": {"code": -32603, "message": "a"}});
+    let shadow = json!({"id": 1, "error": {"code": -32603, "message": "b"}});
+    assert_eq!(shadow::compare(&primary, &shadow), Comparison::Matched);
+
+    // 被取消的请求不可比较
+    let cancelled = json!({"id": 1, "error": {"code": -32800, "message": "cancelled"}});
+    assert_eq!(
+        shadow::compare(&cancelled, &json!({"id": 1, "result": null})),
+        Comparison::Skipped
+    );
+}
+
+#[test]
+fn test_validate() {
+    assert!(shadow::validate(&ShadowConfig::default()).is_ok());
+    let config = Config::parse(
+        "[shadow]\ncommand = \"clangd-21\"\nsample_rate = 0.5\nmethods = [\"textDocument/hover\"]",
+    )
+    .unwrap();
+    assert_eq!(config.shadow.sample_rate, 0.5);
+    assert!(Config::parse("[shadow]\nsample_rate = 1.5").is_err());
+    // 会修改文件的请求不能镜像
+    assert!(Config::parse("[shadow]\nmethods = [\"textDocument/rename\"]").is_err());
+}
+
+#[test]
+fn test_not_configured() {
+    assert!(Shadow::spawn(&ShadowConfig::default()).unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_mirrors_requests_to_shadow() {
+    let report = std::env::temp_dir().join(format!(
+        "codefuse-shadow-test-{}.ndjson",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&report);
+    let config = ShadowConfig {
+        command: Some(env!("CARGO_BIN_EXE_lsp-proxy").to_string()),
+        args: vec!["mock-server".to_string()],
+        sample_rate: 1.0,
+        methods: Vec::new(),
+        report: Some(report.clone()),
+    };
+    let shadow = Shadow::spawn(&config).unwrap();
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_shadow(shadow));
+
+    let initialize = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+        "processId": null, "rootUri": null, "capabilities": {},
+    }});
+    dispatcher.handle_from_frontend(initialize).await.unwrap();
+    backend_rx.recv().await.unwrap();
+
+    // 模拟后端对悬停返回 null，主后端返回内容时两边不一致
+    for (id, result) in [(2, json!({"contents": "int x"})), (3, Value::Null)] {
+        let hover = json!({"jsonrpc": "2.0", "id": id, "method": "textDocument/hover", "params": {
+            "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 0},
+        }});
+        dispatcher.handle_from_frontend(hover).await.unwrap();
+        backend_rx.recv().await.unwrap();
+        dispatcher
+            .handle_from_shard(0, json!({"jsonrpc": "2.0", "id": id, "result": result}))
+            .await
+            .unwrap();
+        frontend_rx.recv().await.unwrap();
+    }
+
+    let shadow = dispatcher.shadow().unwrap();
+    let mut stats = shadow.stats();
+    for _ in 0..100 {
+        if stats.matched + stats.diverged == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        stats = shadow.stats();
+    }
+    assert_eq!(stats.mirrored, 2);
+    assert_eq!(stats.matched, 1);
+    assert_eq!(stats.diverged, 1);
+
+    let text = std::fs::read_to_string(&report).unwrap();
+    let entry: Value = serde_json::from_str(text.lines().next().unwrap()).unwrap();
+    assert_eq!(entry["method"], "textDocument/hover");
+    assert_eq!(entry["primary"]["contents"], "int x");
+    assert_eq!(entry["shadow"], Value::Null);
+    let _ = std::fs::remove_file(&report);
+}
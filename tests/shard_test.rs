@@ -0,0 +1,284 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::shard::{self, Route, Shard};
+use serde_json::{Value, json};
+use tower_lsp::lsp_types::{FileChangeType, FileEvent, Url};
+
+fn parse_message(message: &Message) -> Value {
+    message.body().clone()
+}
+
+fn symbol(name: &str, uri: &str) -> Value {
+    json!({
+        "name": name,
+        "kind": 12,
+        "location": {
+            "uri": uri,
+            "range": {"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 1}}
+        }
+    })
+}
+
+#[test]
+fn test_route_by_uri() {
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let shards = vec![
+        Shard::default_shard(tx.clone()),
+        Shard {
+            name: "services".into(),
+            root: Some(PathBuf::from("/repo/services")),
+            sender: tx,
+        },
+    ];
+
+    let hover = json!({
+        "jsonrpc": "2.0", "id": 1, "method": "textDocument/hover",
+        "params": {"textDocument": {"uri": "file:///repo/services/a.cpp"}}
+    });
+    assert_eq!(
+        shard::route(&shards, "textDocument/hover", &hover),
+        Route::Shard(1)
+    );
+
+    let did_open = json!({
+        "jsonrpc": "2.0", "method": "textDocument/didOpen",
+        "params": {"textDocument": {"uri": "file:///repo/lib/b.cpp"}}
+    });
+    assert_eq!(
+        shard::route(&shards, "textDocument/didOpen", &did_open),
+        Route::Shard(0)
+    );
+
+    let initialized = json!({"jsonrpc": "2.0", "method": "initialized", "params": {}});
+    assert_eq!(
+        shard::route(&shards, "initialized", &initialized),
+        Route::Broadcast
+    );
+
+    let symbols =
+        json!({"jsonrpc": "2.0", "id": 2, "method": "workspace/symbol", "params": {"query": "x"}});
+    assert_eq!(
+        shard::route(&shards, "workspace/symbol", &symbols),
+        Route::FanOut
+    );
+}
+
+#[tokio::test]
+async fn test_workspace_symbol_fan_out_merges_shards() {
+    let (default_tx, mut default_rx) = mpsc::unbounded_channel::<Message>();
+    let (services_tx, mut services_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+
+    let dispatcher = Arc::new(Dispatcher::with_shards(
+        vec![
+            Shard::default_shard(default_tx),
+            Shard {
+                name: "services".into(),
+                root: Some(PathBuf::from("/repo/services")),
+                sender: services_tx,
+            },
+        ],
+        frontend_tx,
+    ));
+
+    // 两个假的后端各自返回一组符号，其中一个符号重复
+    let backend = Arc::clone(&dispatcher);
+    tokio::spawn(async move {
+        let request = parse_message(&default_rx.recv().await.unwrap());
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "result": [symbol("Widget", "file:///repo/lib/w.h"), symbol("Shared", "file:///repo/s.h")]
+        });
+        backend.handle_from_shard(0, response).await.unwrap();
+    });
+    let backend = Arc::clone(&dispatcher);
+    tokio::spawn(async move {
+        let request = parse_message(&services_rx.recv().await.unwrap());
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "result": [symbol("Service", "file:///repo/services/s.h"), symbol("Shared", "file:///repo/s.h")]
+        });
+        backend.handle_from_shard(1, response).await.unwrap();
+    });
+
+    dispatcher
+        .handle_from_frontend(json!({
+            "jsonrpc": "2.0", "id": 7, "method": "workspace/symbol", "params": {"query": ""}
+        }))
+        .await
+        .unwrap();
+
+    let response = parse_message(&frontend_rx.recv().await.unwrap());
+    assert_eq!(response["id"], 7);
+    let names: Vec<&str> = response["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["Widget", "Shared", "Service"]);
+}
+
+fn location(uri: &str, line: u32) -> Value {
+    json!({
+        "uri": uri,
+        "range": {"start": {"line": line, "character": 0}, "end": {"line": line, "character": 3}}
+    })
+}
+
+#[test]
+fn test_merge_references_by_location() {
+    let mut contained = location("file:///repo/s.h", 3);
+    contained["containerName"] = json!("Shared");
+    let merged = shard::merge_results(
+        "textDocument/references",
+        vec![
+            json!([
+                location("file:///repo/s.h", 3),
+                location("file:///repo/a.cpp", 1)
+            ]),
+            json!([contained, location("file:///repo/services/b.cpp", 2)]),
+            Value::Null,
+        ],
+    );
+    let uris: Vec<&str> = merged
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|l| l["uri"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        uris,
+        vec![
+            "file:///repo/s.h",
+            "file:///repo/a.cpp",
+            "file:///repo/services/b.cpp"
+        ]
+    );
+}
+
+#[test]
+fn test_merge_shard_diagnostics() {
+    let diagnostic = |message: &str| json!({"range": location("file:///repo/s.h", 0)["range"], "message": message});
+    let diagnostics = shard::ShardDiagnostics::new();
+    let merged = diagnostics.merge(
+        0,
+        "file:///repo/s.h",
+        vec![diagnostic("a"), diagnostic("b")],
+    );
+    assert_eq!(merged.len(), 2);
+    // 另一个分片的诊断合并进来，相同的诊断只保留一份
+    let merged = diagnostics.merge(
+        1,
+        "file:///repo/s.h",
+        vec![diagnostic("b"), diagnostic("c")],
+    );
+    let messages: Vec<&str> = merged
+        .iter()
+        .map(|d| d["message"].as_str().unwrap())
+        .collect();
+    assert_eq!(messages, vec!["a", "b", "c"]);
+    // 分片清空自己的诊断之后只剩其他分片的
+    let merged = diagnostics.merge(0, "file:///repo/s.h", Vec::new());
+    assert_eq!(merged.len(), 2);
+    assert!(
+        diagnostics
+            .merge(1, "file:///repo/s.h", Vec::new())
+            .is_empty()
+    );
+}
+
+fn two_shards() -> (
+    Vec<Shard>,
+    mpsc::UnboundedReceiver<Message>,
+    mpsc::UnboundedReceiver<Message>,
+) {
+    let (default_tx, default_rx) = mpsc::unbounded_channel();
+    let (services_tx, services_rx) = mpsc::unbounded_channel();
+    let shards = vec![
+        Shard::default_shard(default_tx),
+        Shard {
+            name: "services".into(),
+            root: Some(PathBuf::from("/repo/services")),
+            sender: services_tx,
+        },
+    ];
+    (shards, default_rx, services_rx)
+}
+
+#[test]
+fn test_split_file_changes() {
+    let (shards, _default_rx, _services_rx) = two_shards();
+    let change = |uri: &str| FileEvent {
+        uri: Url::parse(uri).unwrap(),
+        typ: FileChangeType::CHANGED,
+    };
+    let database = change("file:///repo/compile_commands.json");
+    let service = change("file:///repo/services/a.h");
+    let split = shard::split_file_changes(&shards, &[database.clone(), service.clone()]);
+    assert_eq!(
+        split,
+        vec![
+            (0, vec![database.clone()]),
+            (1, vec![database, service.clone()])
+        ]
+    );
+    // 只有子树中的变更时，默认分片不收到通知
+    assert_eq!(
+        shard::split_file_changes(&shards, std::slice::from_ref(&service)),
+        vec![(1, vec![service])]
+    );
+}
+
+#[tokio::test]
+async fn test_watched_files_go_to_relevant_shards() {
+    let (shards, mut default_rx, mut services_rx) = two_shards();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::with_shards(shards, frontend_tx));
+
+    dispatcher
+        .handle_from_frontend(json!({
+            "jsonrpc": "2.0", "method": "workspace/didChangeWatchedFiles",
+            "params": {"changes": [{"uri": "file:///repo/services/a.h", "type": 2}]}
+        }))
+        .await
+        .unwrap();
+    let notification = parse_message(&services_rx.recv().await.unwrap());
+    assert_eq!(
+        notification["params"]["changes"][0]["uri"],
+        "file:///repo/services/a.h"
+    );
+    assert!(default_rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_diagnostics_from_shards_are_merged() {
+    let (shards, _default_rx, _services_rx) = two_shards();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::with_shards(shards, frontend_tx));
+
+    for (shard, message) in [(0, "from default"), (1, "from services")] {
+        let publish = json!({
+            "jsonrpc": "2.0", "method": "textDocument/publishDiagnostics",
+            "params": {"uri": "file:///repo/shared.h", "diagnostics": [
+                {"range": location("file:///repo/shared.h", 0)["range"], "message": message}
+            ]}
+        });
+        dispatcher.handle_from_shard(shard, publish).await.unwrap();
+    }
+    frontend_rx.recv().await.unwrap();
+    let published = parse_message(&frontend_rx.recv().await.unwrap());
+    let messages: Vec<&str> = published["params"]["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["message"].as_str().unwrap())
+        .collect();
+    assert_eq!(messages, vec!["from default", "from services"]);
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use lsp_proxy::batch::BatchTracker;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::tasks::send_data_frontend;
+use serde_json::json;
+
+#[tokio::test]
+async fn test_shutdown_then_exit_is_sent_to_backend() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx));
+
+    let shutdown = tokio::spawn({
+        let dispatcher = Arc::clone(&dispatcher);
+        async move { dispatcher.shutdown_backends().await }
+    });
+
+    let request = backend_rx.recv().await.unwrap();
+    assert_eq!(request.method(), Some("shutdown"));
+    dispatcher
+        .handle_from_backend(json!({"jsonrpc": "2.0", "id": request.id().unwrap(), "result": null}))
+        .await
+        .unwrap();
+    shutdown.await.unwrap();
+
+    assert_eq!(backend_rx.recv().await.unwrap().method(), Some("exit"));
+}
+
+#[tokio::test]
+async fn test_queued_frontend_messages_are_flushed_on_close() {
+    let (writer, mut reader) = tokio::io::duplex(1 << 16);
+    let (frontend_tx, frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let closing = CancellationToken::new();
+
+    for i in 0..3 {
+        let notification = json!({"jsonrpc": "2.0", "method": "window/logMessage", "params": {"type": 3, "message": i.to_string()}});
+        frontend_tx.send(Message::new(notification)).unwrap();
+    }
+    closing.cancel();
+    // 发送端仍然存在，写出任务在写完排队的消息后返回
+    send_data_frontend(
+        writer,
+        frontend_rx,
+        Arc::new(BatchTracker::new()),
+        closing,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(frontend_tx.send(Message::new(json!({}))).is_err());
+
+    let mut output = String::new();
+    reader.read_to_string(&mut output).await.unwrap();
+    assert_eq!(output.matches("Content-Length").count(), 3);
+}
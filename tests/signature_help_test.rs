@@ -0,0 +1,169 @@
+use lsp_proxy::config::Config;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::signature_help;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+const SOURCE: &str = "int clamp(int value, int lo = 0,\n          int hi = max(1, 2));\n\
+                      void run() {\n  clamp(x, (a + b), ";
+
+const HOVER: &str = "### function `draw`\n\n---\n→ `void`\n\nParameters:\n- `int x`\n\n---\n\
+                     ```cpp\n// In namespace gfx\npublic: void draw(int x,\n  std::map<int, int> colors) const\n```";
+
+fn dispatcher(
+    config: &str,
+) -> (
+    Arc<Dispatcher>,
+    mpsc::UnboundedReceiver<Message>,
+    mpsc::UnboundedReceiver<Message>,
+) {
+    let (backend_tx, backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse(config).unwrap();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    (dispatcher, backend_rx, frontend_rx)
+}
+
+fn did_open(text: &str) -> Value {
+    json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": 1, "text": text},
+    }})
+}
+
+fn signature_help(id: u64, line: u32, character: u32) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": "textDocument/signatureHelp", "params": {
+        "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": line, "character": character},
+    }})
+}
+
+#[test]
+fn test_call_at() {
+    assert_eq!(signature_help::call_at("foo("), Some(("foo", 0)));
+    assert_eq!(
+        signature_help::call_at("x = ns::foo(a, bar(1, 2), "),
+        Some(("foo", 2))
+    );
+    assert_eq!(signature_help::call_at("foo(a, (b, c"), Some(("foo", 1)));
+    assert_eq!(
+        signature_help::call_at("if (foo({1, 2}, "),
+        Some(("foo", 1))
+    );
+    assert_eq!(signature_help::call_at("if (x"), None);
+    assert_eq!(signature_help::call_at("foo(a);\nbar"), None);
+}
+
+#[test]
+fn test_declarations() {
+    let declaration = signature_help::hover_declaration(
+        &json!({"contents": {"kind": "markdown", "value": HOVER}}),
+    )
+    .unwrap();
+    assert_eq!(
+        declaration,
+        "void draw(int x, std::map<int, int> colors) const"
+    );
+    assert_eq!(
+        signature_help::hover_declaration(
+            &json!({"contents": {"kind": "markdown", "value": "`int` x"}})
+        ),
+        None
+    );
+
+    assert_eq!(
+        signature_help::find_declaration(SOURCE, "clamp").unwrap(),
+        "int clamp(int value, int lo = 0, int hi = max(1, 2))"
+    );
+    assert_eq!(signature_help::find_declaration(SOURCE, "missing"), None);
+
+    let help = signature_help::signature(&declaration, 1).unwrap();
+    assert_eq!(help["activeParameter"], 1);
+    assert_eq!(
+        help["signatures"][0]["parameters"],
+        json!([{"label": [10, 15]}, {"label": [17, 42]}])
+    );
+    let help = signature_help::signature("void f(void)", 0).unwrap();
+    assert_eq!(help["signatures"][0]["parameters"], json!([]));
+}
+
+#[tokio::test]
+async fn test_synthesizes_when_backend_has_no_signature() {
+    let (dispatcher, mut backend_rx, mut frontend_rx) =
+        dispatcher("[signature_help]\nfallback = true\ntimeout_ms = 60000");
+    dispatcher.health().set_alive(0, true);
+    dispatcher
+        .handle_from_frontend(did_open(SOURCE))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+
+    dispatcher
+        .handle_from_frontend(signature_help(1, 3, 20))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+    dispatcher
+        .handle_from_backend(json!({"jsonrpc": "2.0", "id": 1, "result": null}))
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    let signature = &response["result"]["signatures"][0];
+    assert_eq!(
+        signature["label"],
+        "int clamp(int value, int lo = 0, int hi = max(1, 2))"
+    );
+    assert_eq!(response["result"]["activeParameter"], 2);
+
+    // 后端的签名原样转发
+    dispatcher
+        .handle_from_frontend(signature_help(2, 3, 20))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+    let backend = json!({"signatures": [{"label": "int clamp(int)"}]});
+    dispatcher
+        .handle_from_backend(json!({"jsonrpc": "2.0", "id": 2, "result": backend}))
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["result"], backend);
+}
+
+#[tokio::test]
+async fn test_uses_hover_while_backend_is_down() {
+    let (dispatcher, mut backend_rx, mut frontend_rx) =
+        dispatcher("[signature_help]\nfallback = true");
+    dispatcher.health().set_alive(0, true);
+    dispatcher
+        .handle_from_frontend(did_open("draw(1, "))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+
+    let hover = json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": {
+        "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 1},
+    }});
+    dispatcher.handle_from_frontend(hover).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    let result = json!({"contents": {"kind": "markdown", "value": HOVER}});
+    dispatcher
+        .handle_from_backend(json!({"jsonrpc": "2.0", "id": 1, "result": result}))
+        .await
+        .unwrap();
+    frontend_rx.recv().await.unwrap();
+
+    // 后端没有运行，代理不等待后端
+    dispatcher.health().set_alive(0, false);
+    dispatcher
+        .handle_from_frontend(signature_help(2, 0, 8))
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["id"], 2);
+    assert_eq!(
+        response["result"]["signatures"][0]["label"],
+        "void draw(int x, std::map<int, int> colors) const"
+    );
+    assert_eq!(response["result"]["activeParameter"], 1);
+}
@@ -0,0 +1,61 @@
+use lsp_proxy::config::{OversizeReply, ProtocolConfig};
+use lsp_proxy::size_limit::SizeLimit;
+use serde_json::json;
+
+fn limit(reply: OversizeReply) -> SizeLimit {
+    SizeLimit::new(&ProtocolConfig {
+        max_message_mb: Some(1),
+        oversize_reply: reply,
+        ..Default::default()
+    })
+}
+
+fn tokens(id: i32, len: usize) -> serde_json::Value {
+    json!({"jsonrpc": "2.0", "id": id, "result": {"data": vec![1; len]}})
+}
+
+#[test]
+fn test_small_messages_pass_through() {
+    let limit = limit(OversizeReply::Error);
+    let response = tokens(1, 10);
+    assert_eq!(
+        limit.enforce(Some("textDocument/semanticTokens/full"), response.clone()),
+        Some(response)
+    );
+    let unlimited = SizeLimit::new(&ProtocolConfig::default());
+    let response = tokens(2, 1024 * 1024);
+    assert_eq!(unlimited.enforce(None, response.clone()), Some(response));
+}
+
+#[test]
+fn test_oversize_response_is_replaced() {
+    let method = "textDocument/semanticTokens/full";
+    let params = json!({"textDocument": {"uri": "file:///gen.cpp"}});
+
+    let error = limit(OversizeReply::Error);
+    error.request(&json!(1), Some(&params));
+    let reply = error.enforce(Some(method), tokens(1, 1024 * 1024)).unwrap();
+    assert_eq!(reply["id"], 1);
+    assert_eq!(reply["error"]["code"], -32603);
+
+    let empty = limit(OversizeReply::Empty);
+    let reply = empty.enforce(Some(method), tokens(2, 1024 * 1024)).unwrap();
+    assert_eq!(
+        reply,
+        json!({"jsonrpc": "2.0", "id": 2, "result": {"data": []}})
+    );
+}
+
+#[test]
+fn test_oversize_notification_is_dropped() {
+    let limit = limit(OversizeReply::Empty);
+    let diagnostics = json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": "file:///gen.cpp", "diagnostics": vec!["x"; 1024 * 1024]}
+    });
+    assert_eq!(
+        limit.enforce(Some("textDocument/publishDiagnostics"), diagnostics),
+        None
+    );
+}
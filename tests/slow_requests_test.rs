@@ -0,0 +1,48 @@
+use lsp_proxy::lifecycle::{QueueDepth, RequestLifecycle};
+use lsp_proxy::slow_requests::SlowRequests;
+use serde_json::json;
+use std::time::Duration;
+
+#[tokio::test(start_paused = true)]
+async fn test_only_requests_over_threshold_are_reported() {
+    let slow = SlowRequests::new(Some(Duration::from_millis(500)));
+    let lifecycle = RequestLifecycle::new(false);
+    let completion = |id: i32| {
+        json!({"jsonrpc": "2.0", "id": id, "method": "textDocument/completion", "params": {
+            "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 0}
+        }})
+    };
+    let queue = QueueDepth {
+        pending_backend_requests: 3,
+        handlers_waiting: 1,
+    };
+    lifecycle.started(&completion(1), "textDocument/completion", queue);
+    lifecycle.started(&completion(2), "textDocument/completion", queue);
+
+    tokio::time::advance(Duration::from_millis(100)).await;
+    let fast = lifecycle.finished(&json!(1), false).unwrap();
+    assert_eq!(slow.check(&fast), None);
+
+    tokio::time::advance(Duration::from_millis(600)).await;
+    let request = lifecycle.finished(&json!(2), false).unwrap();
+    let report = slow.check(&request).unwrap();
+    assert_eq!(report.method, "textDocument/completion");
+    assert_eq!(report.uri.as_deref(), Some("file:///a.cpp"));
+    assert_eq!(report.duration, Duration::from_millis(700));
+    assert_eq!(report.pending_backend_requests, 3);
+    assert!(report.to_string().contains("耗时 700 ms"));
+
+    // 阈值关闭后不再报告
+    slow.set_threshold(None);
+    assert_eq!(slow.check(&request), None);
+}
+
+#[tokio::test]
+async fn test_disabled_without_threshold() {
+    let slow = SlowRequests::new(None);
+    let lifecycle = RequestLifecycle::new(false);
+    let request = json!({"jsonrpc": "2.0", "id": 1, "method": "shutdown"});
+    lifecycle.started(&request, "shutdown", QueueDepth::default());
+    let request = lifecycle.finished(&json!(1), false).unwrap();
+    assert_eq!(slow.check(&request), None);
+}
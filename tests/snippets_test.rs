@@ -0,0 +1,142 @@
+use lsp_proxy::completion_sources::CompletionRequest;
+use lsp_proxy::config::{Config, SnippetsConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::document_store::Document;
+use lsp_proxy::message::Message;
+use lsp_proxy::snippets::{self, Snippet, Snippets};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::Url;
+
+const VSCODE: &str = r##"{
+    // 头文件保护
+    "Include guard": {
+        "prefix": ["guard", "ifndef"],
+        "body": ["#ifndef ${1:NAME}", "#define $1", "$0", "#endif // http://example.com"],
+        "description": "头文件保护",
+        "scope": "c, cpp"
+    },
+    /* 所有语言 */
+    "Todo": {"prefix": "todo", "body": "// TODO: $0"}
+}"##;
+
+const TOML: &str = r##"
+[[snippets]]
+prefix = "fori"
+body = "for (int ${1:i} = 0; $1 < ${2:n}; ++$1) {\n\t$0\n}"
+description = "计数循环"
+languages = ["cpp"]
+"##;
+
+fn request(text: &str, language: &str, context: Option<Value>) -> CompletionRequest {
+    let line = text.lines().count().saturating_sub(1);
+    let character = text.lines().last().unwrap_or("").len();
+    let mut params = json!({
+        "textDocument": {"uri": "file:///a.cpp"},
+        "position": {"line": line, "character": character},
+    });
+    if let Some(context) = context {
+        params["context"] = context;
+    }
+    CompletionRequest {
+        params: serde_json::from_value(params).unwrap(),
+        document: Some(Document {
+            uri: Url::parse("file:///a.cpp").unwrap(),
+            language_id: language.to_string(),
+            version: 1,
+            text: text.to_string(),
+        }),
+        backend_ready: true,
+    }
+}
+
+fn labels(items: &[Value]) -> Vec<&str> {
+    items
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_parse_formats() {
+    let parsed = snippets::parse_vscode(VSCODE).unwrap();
+    assert_eq!(parsed.len(), 2);
+    assert_eq!(
+        parsed[0],
+        Snippet {
+            name: "Include guard".to_string(),
+            prefixes: vec!["guard".to_string(), "ifndef".to_string()],
+            body: "#ifndef ${1:NAME}\n#define $1\n$0\n#endif // http://example.com".to_string(),
+            description: Some("头文件保护".to_string()),
+            languages: vec!["c".to_string(), "cpp".to_string()],
+        }
+    );
+    assert!(parsed[1].languages.is_empty());
+
+    let parsed = snippets::parse_toml(TOML).unwrap();
+    assert_eq!(parsed[0].name, "fori");
+    assert_eq!(parsed[0].languages, vec!["cpp"]);
+    assert!(snippets::parse_vscode("{\"x\": {}}").is_err());
+}
+
+#[test]
+fn test_items_follow_typed_word_and_language() {
+    let mut all = snippets::parse_vscode(VSCODE).unwrap();
+    all.extend(snippets::parse_toml(TOML).unwrap());
+    let snippets = Snippets::from_snippets(all);
+
+    let items = snippets.items(&request("int main() {\n  fo", "cpp", None));
+    assert_eq!(labels(&items), vec!["fori"]);
+    assert_eq!(items[0]["insertTextFormat"], 2);
+    assert_eq!(items[0]["kind"], 15);
+    assert_eq!(items[0]["documentation"], "计数循环");
+
+    // C 文档中没有只适用于 C++ 的代码片段
+    let items = snippets.items(&request("", "c", None));
+    assert_eq!(labels(&items), vec!["guard", "ifndef", "todo"]);
+
+    // 触发字符触发的补全不提供代码片段
+    let context = json!({"triggerKind": 2, "triggerCharacter": "."});
+    assert!(
+        snippets
+            .items(&request("s.", "cpp", Some(context)))
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_snippets_from_config_join_completion() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("team.code-snippets"), VSCODE).unwrap();
+    std::fs::write(dir.path().join("loops.toml"), TOML).unwrap();
+    let config_path = dir.path().join(".codefuse.toml");
+    std::fs::write(
+        &config_path,
+        "[snippets]\nfiles = [\"team.code-snippets\", \"loops.toml\", \"missing.json\"]",
+    )
+    .unwrap();
+    let config = Config::load(&config_path).unwrap();
+    assert!(!Snippets::new(&config.snippets).is_empty());
+    assert!(Snippets::new(&SnippetsConfig::default()).is_empty());
+
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    dispatcher.health().set_alive(0, true);
+    let completion = json!({"jsonrpc": "2.0", "id": 3, "method": "textDocument/completion",
+        "params": {"textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 0}}});
+    dispatcher.handle_from_frontend(completion).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    dispatcher
+        .handle_from_backend(json!({"jsonrpc": "2.0", "id": 3, "result": [{"label": "main"}]}))
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    let items = response["result"]["items"].as_array().unwrap();
+    assert_eq!(
+        labels(items),
+        vec!["main", "guard", "ifndef", "todo", "fori"]
+    );
+    assert_eq!(items[1]["labelDetails"]["description"], "snippets");
+}
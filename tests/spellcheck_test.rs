@@ -0,0 +1,94 @@
+use lsp_proxy::config::Config;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::spellcheck::{self, SpellChecker};
+use serde_json::json;
+use std::io::Write;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+const WORDS: &[&str] = &[
+    "the", "value", "returns", "buffer", "hello", "world", "help",
+];
+
+#[test]
+fn test_only_comments_and_strings_are_checked() {
+    let checker = SpellChecker::new(WORDS.iter().copied(), 4);
+    let text = "// Retruns the valeu\nint bufer = 0;\nconst char *s = \"helo wrold\\n\";\n";
+    let ranges = spellcheck::text_ranges(text);
+    assert_eq!(ranges.len(), 2);
+
+    let diagnostics = checker.check(text);
+    let words: Vec<_> = diagnostics
+        .iter()
+        .map(|d| d["data"]["word"].as_str().unwrap())
+        .collect();
+    assert_eq!(words, ["Retruns", "valeu", "helo", "wrold"]);
+    assert_eq!(diagnostics[0]["source"], "spell");
+    assert_eq!(diagnostics[0]["severity"], 4);
+    assert_eq!(
+        diagnostics[3]["range"],
+        json!({"start": {"line": 2, "character": 22}, "end": {"line": 2, "character": 27}})
+    );
+}
+
+#[test]
+fn test_identifiers_and_paths_are_skipped() {
+    let checker = SpellChecker::new(WORDS.iter().copied(), 4);
+    let text = "// see fooBar, MAX_BUFER, http://exmaple.org, frobnicate() and teh\n";
+    assert!(checker.check(text).is_empty());
+    assert_eq!(checker.suggestions("Retruns"), ["Returns"]);
+    assert_eq!(checker.suggestions("helo"), ["hello", "help"]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_dispatcher_publishes_spelling_fixes() {
+    let mut dictionary = tempfile::NamedTempFile::new().unwrap();
+    writeln!(dictionary, "{}", WORDS.join("\n")).unwrap();
+    let mut config = Config::default();
+    config.spellcheck.enabled = true;
+    config.spellcheck.dictionary = Some(dictionary.path().to_path_buf());
+
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": 1,
+                                 "text": "// the valeu\n"}
+            }}),
+        )
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+
+    let published = frontend_rx.recv().await.unwrap().into_body();
+    let diagnostic = published["params"]["diagnostics"][0].clone();
+    assert_eq!(diagnostic["data"]["word"], "valeu");
+
+    // 代码操作请求中的拼写诊断得到替换单词的快速修复，追加在后端的结果后面
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "id": 5, "method": "textDocument/codeAction",
+            "params": {
+                "textDocument": {"uri": "file:///a.cpp"},
+                "range": diagnostic["range"],
+                "context": {"diagnostics": [diagnostic]}
+            }}),
+        )
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+    dispatcher
+        .handle_from_shard(0, json!({"jsonrpc": "2.0", "id": 5, "result": []}))
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    let action = &response["result"][0];
+    assert_eq!(action["kind"], "quickfix");
+    assert_eq!(
+        action["edit"]["changes"]["file:///a.cpp"][0]["newText"],
+        "value"
+    );
+}
@@ -0,0 +1,54 @@
+use lsp_proxy::config::SshConfig;
+use lsp_proxy::ssh::{SshTarget, shell_quote};
+
+#[test]
+fn test_parse_backend_address() {
+    assert_eq!(
+        SshTarget::parse("ssh://dev@build01//usr/bin/clangd"),
+        Some(SshTarget {
+            destination: "dev@build01".to_string(),
+            program: "/usr/bin/clangd".to_string(),
+        })
+    );
+    assert_eq!(
+        SshTarget::parse("ssh://build01/clangd").unwrap().program,
+        "clangd"
+    );
+    assert_eq!(SshTarget::parse("clangd"), None);
+    assert_eq!(SshTarget::parse("ssh://build01"), None);
+}
+
+#[test]
+fn test_ssh_args_quote_remote_command() {
+    let target = SshTarget::parse("ssh://dev@build01//opt/llvm/bin/clangd").unwrap();
+    let config = SshConfig {
+        control_path: Some("~/.ssh/cm-%r@%h:%p".to_string()),
+        args: vec!["-p".to_string(), "2222".to_string()],
+        ..Default::default()
+    };
+    let args = target.ssh_args(
+        &[
+            "--background-index".to_string(),
+            "--query-driver=/opt/gcc/bin/*".to_string(),
+        ],
+        &[("XDG_CACHE_HOME".to_string(), "/tmp/cache dir".to_string())],
+        &config,
+    );
+
+    assert!(args.contains(&"ControlPath=~/.ssh/cm-%r@%h:%p".to_string()));
+    let destination = args.iter().position(|arg| arg == "dev@build01").unwrap();
+    assert_eq!(args[destination - 2..destination], ["-p", "2222"]);
+    assert_eq!(args[destination + 1], "--");
+    assert_eq!(
+        args.last().unwrap(),
+        "env 'XDG_CACHE_HOME=/tmp/cache dir' /opt/llvm/bin/clangd --background-index \
+         '--query-driver=/opt/gcc/bin/*'"
+    );
+}
+
+#[test]
+fn test_shell_quote() {
+    assert_eq!(shell_quote("-j=8"), "-j=8");
+    assert_eq!(shell_quote(""), "''");
+    assert_eq!(shell_quote("it's"), r"'it'\''s'");
+}
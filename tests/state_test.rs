@@ -0,0 +1,130 @@
+use lsp_proxy::config::{Config, StateConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::state::{self, ProxyState, STATE_PATH};
+use serde_json::{Value, json};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::Url;
+
+fn initialize(root: &Path) -> Value {
+    json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+        "rootUri": Url::from_file_path(root).unwrap(), "capabilities": {},
+    }})
+}
+
+#[test]
+fn test_save_and_restore() {
+    let dir = tempfile::tempdir().unwrap();
+    let roots = vec![dir.path().to_path_buf()];
+    let index = dir.path().join(".cache/clangd/index");
+    std::fs::create_dir_all(&index).unwrap();
+    std::fs::write(index.join("a.cpp.idx"), "index").unwrap();
+
+    let config = StateConfig { enabled: true };
+    let state = ProxyState::new(&config);
+    assert_eq!(state.load(&roots), None);
+    state.record_backend(&json!({"capabilities": {"hoverProvider": true}, "extra": 1}));
+    state.record_completion_use("push_back");
+    state.record_completion_use("push_back");
+    state.record_index_completed(&roots);
+    assert_eq!(state.save().unwrap(), Some(dir.path().join(STATE_PATH)));
+
+    let saved = ProxyState::new(&config).load(&roots).unwrap();
+    assert_eq!(saved, state.snapshot());
+    assert_eq!(
+        saved.backend,
+        Some(json!({"capabilities": {"hoverProvider": true}}))
+    );
+    assert_eq!(saved.completion_usage["push_back"], 2);
+    let metadata = saved.index.clone().unwrap();
+    assert_eq!((metadata.files, metadata.bytes), (1, 5));
+    assert!(state::index_reusable(&saved, &roots));
+
+    // 索引目录被删除后重新生成 ctags 索引
+    std::fs::remove_dir_all(&index).unwrap();
+    assert!(!state::index_reusable(&saved, &roots));
+
+    // 没有开启时不读写
+    let disabled = ProxyState::new(&StateConfig::default());
+    assert_eq!(disabled.load(&roots), None);
+    assert_eq!(disabled.save().unwrap(), None);
+}
+
+#[test]
+fn test_preselect_most_used_completion() {
+    let state = ProxyState::new(&StateConfig { enabled: true });
+    for label in ["size", "push_back", "push_back", "size", "empty"] {
+        state.record_completion_use(label);
+    }
+    let mut result = json!({"isIncomplete": false, "items": [
+        {"label": "empty"}, {"label": "push_back"}, {"label": "size"},
+    ]});
+    state.preselect_completion(&mut result);
+    assert_eq!(result["items"][1]["preselect"], true);
+    assert!(result["items"][2].get("preselect").is_none());
+
+    // 后端已经预先选中时不改变，只用过一次的补全项不选中
+    let mut result = json!([{"label": "size"}, {"label": "at", "preselect": true}]);
+    state.preselect_completion(&mut result);
+    assert!(result[0].get("preselect").is_none());
+    let mut result = json!([{"label": "empty"}]);
+    state.preselect_completion(&mut result);
+    assert!(result[0].get("preselect").is_none());
+}
+
+#[tokio::test]
+async fn test_next_session_restores_state() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config::parse("[state]\nenabled = true").unwrap();
+
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config.clone()));
+    dispatcher
+        .handle_from_frontend(initialize(dir.path()))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+    let response = json!({"jsonrpc": "2.0", "id": 1, "result": {
+        "capabilities": {"hoverProvider": true},
+        "serverInfo": {"name": "clangd", "version": "clangd version 17.0.1"},
+    }});
+    dispatcher.handle_from_backend(response).await.unwrap();
+    frontend_rx.recv().await.unwrap();
+    // 后端初始化完成时写入状态
+    assert!(dir.path().join(STATE_PATH).exists());
+    for id in [2, 3] {
+        let resolve = json!({"jsonrpc": "2.0", "id": id, "method": "completionItem/resolve",
+            "params": {"label": "reserve"}});
+        dispatcher.handle_from_frontend(resolve).await.unwrap();
+        backend_rx.recv().await.unwrap();
+    }
+    dispatcher.save_state();
+
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    dispatcher
+        .handle_from_frontend(initialize(dir.path()))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+    // 后端响应之前使用上次的能力
+    let capabilities = dispatcher.backend_capabilities();
+    assert_eq!(capabilities.supports("textDocument/hover"), Some(true));
+    assert_eq!(capabilities.clangd_version(), Some((17, 0, 1)));
+
+    let completion = json!({"jsonrpc": "2.0", "id": 4, "method": "textDocument/completion",
+        "params": {"textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 0}}});
+    dispatcher.handle_from_frontend(completion).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    let items = json!([{"label": "resize"}, {"label": "reserve"}]);
+    dispatcher
+        .handle_from_backend(json!({"jsonrpc": "2.0", "id": 4, "result": items}))
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["result"][1]["preselect"], true);
+}
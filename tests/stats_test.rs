@@ -0,0 +1,52 @@
+use lsp_proxy::lifecycle::{QueueDepth, RequestLifecycle, RequestObserver};
+use lsp_proxy::stats::{STATS_PATH, SessionStats};
+use serde_json::json;
+use std::time::Duration;
+
+#[test]
+fn test_report_counts_errors_and_percentiles() {
+    let stats = SessionStats::new();
+    for ms in 1..=100 {
+        stats.record("textDocument/hover", Duration::from_millis(ms), ms == 100);
+    }
+    let lifecycle = RequestLifecycle::new(false);
+    let definition = json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/definition"});
+    lifecycle.started(
+        &definition,
+        "textDocument/definition",
+        QueueDepth::default(),
+    );
+    let request = lifecycle.finished(&json!(1), true).unwrap();
+    stats.finished(&request);
+    stats.prefetch(true);
+    stats.prefetch(true);
+    stats.prefetch(true);
+    stats.prefetch(false);
+    stats.backend_restarted();
+
+    let report = stats.report();
+    let hover = &report.methods["textDocument/hover"];
+    assert_eq!(hover.count, 100);
+    assert_eq!(hover.errors, 1);
+    assert_eq!(hover.p50_ms, 50.0);
+    assert_eq!(hover.p95_ms, 95.0);
+    assert_eq!(report.methods["textDocument/definition"].count, 1);
+    assert_eq!(report.methods.len(), 2);
+    assert_eq!(report.errors, 2);
+    assert_eq!(report.prefetch_hit_rate, Some(0.75));
+    assert_eq!(report.backend_restarts, 1);
+}
+
+#[test]
+fn test_save_writes_report_under_workspace() {
+    let root = tempfile::tempdir().unwrap();
+    let stats = SessionStats::new();
+    stats.record("textDocument/completion", Duration::from_millis(20), false);
+
+    let path = stats.save(root.path()).unwrap();
+    assert_eq!(path, root.path().join(STATS_PATH));
+    let saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(saved["methods"]["textDocument/completion"]["count"], 1);
+    assert_eq!(saved["prefetchHitRate"], json!(null));
+}
@@ -0,0 +1,279 @@
+use lsp_proxy::config::{BackendConfig, Config, IdleConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::mock_lsp_server::CRASH_METHOD;
+use lsp_proxy::reload::Changes;
+use lsp_proxy::supervisor::{BackendSupervisor, ReplayedDocuments};
+use lsp_proxy::tasks::HandlerLimiter;
+use serde_json::{Value, json};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+
+const URI: &str = "file:///src/a.cpp";
+
+/// 打开文档时发出通知的模拟后端脚本，收到通知说明后端（重新）打开了文档。
+/// 不使用诊断和日志，代理会丢弃重复的诊断和日志。
+fn write_fixture(dir: &Path) -> String {
+    let path = dir.join("backend.json");
+    let script = json!({"notifications": {"textDocument/didOpen": [
+        {"method": "mock/opened", "params": {"uri": "${uri}"}}
+    ]}});
+    std::fs::write(&path, script.to_string()).unwrap();
+    path.display().to_string()
+}
+
+struct Session {
+    dispatcher: Arc<Dispatcher>,
+    shard_tx: UnboundedSender<Message>,
+    frontend_rx: UnboundedReceiver<Message>,
+    run: JoinHandle<anyhow::Result<()>>,
+}
+
+impl Session {
+    fn start(config: &BackendConfig, args: Vec<String>) -> Self {
+        let (supervisor, shard_tx) =
+            BackendSupervisor::new(0, "default".to_string(), config, args, Vec::new()).unwrap();
+        let (frontend_tx, frontend_rx) = mpsc::unbounded_channel::<Message>();
+        let dispatcher = Arc::new(Dispatcher::new(shard_tx.clone(), frontend_tx));
+        let limiter = Arc::new(HandlerLimiter::new(16, dispatcher.metrics()));
+        let run = tokio::spawn(supervisor.run(Arc::clone(&dispatcher), limiter));
+        Self {
+            dispatcher,
+            shard_tx,
+            frontend_rx,
+            run,
+        }
+    }
+
+    async fn send(&self, message: Value) {
+        self.dispatcher.handle_from_frontend(message).await.unwrap();
+    }
+
+    /// 等待满足条件的消息，跳过其他消息。
+    async fn expect(&mut self, matches: impl Fn(&Value) -> bool) -> Value {
+        let wait = async {
+            loop {
+                let message = self.frontend_rx.recv().await.unwrap().into_body();
+                if matches(&message) {
+                    return message;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(20), wait)
+            .await
+            .expect("没有等到预期的消息")
+    }
+
+    async fn request(&mut self, id: i64, method: &str, params: Value) -> Value {
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}))
+            .await;
+        self.expect(|message| message["id"] == id && message.get("method").is_none())
+            .await
+    }
+
+    async fn initialize_and_open(&mut self) {
+        self.request(1, "initialize", json!({"capabilities": {}}))
+            .await;
+        self.send(json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}))
+            .await;
+        self.send(
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+            "textDocument": {"uri": URI, "languageId": "cpp", "version": 1, "text": "int x;"}}}),
+        )
+        .await;
+        self.expect_opened().await;
+    }
+
+    async fn expect_opened(&mut self) {
+        self.expect(|message| {
+            message["method"] == "mock/opened" && message["params"]["uri"] == URI
+        })
+        .await;
+    }
+
+    /// 让当前的后端以错误退出。
+    fn crash(&self) {
+        let crash = json!({"jsonrpc": "2.0", "method": CRASH_METHOD});
+        self.shard_tx.send(Message::new(crash)).unwrap();
+    }
+
+    async fn hover(&mut self, id: i64) -> Value {
+        let params = json!({"textDocument": {"uri": URI}, "position": {"line": 0, "character": 4}});
+        self.request(id, "textDocument/hover", params).await
+    }
+}
+
+fn mock_config(standby: bool) -> BackendConfig {
+    BackendConfig {
+        command: env!("CARGO_BIN_EXE_lsp-proxy").to_string(),
+        standby,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_standby_promoted_after_crash() {
+    let dir = tempfile::tempdir().unwrap();
+    let args = vec!["mock-server".to_string(), write_fixture(dir.path())];
+    let mut session = Session::start(&mock_config(true), args);
+    session.initialize_and_open().await;
+
+    session.crash();
+    // 备用后端接替后重放打开的文档，之后的请求由它应答
+    session.expect_opened().await;
+    assert_eq!(session.hover(2).await["result"], Value::Null);
+    assert_eq!(session.dispatcher.stats().report().backend_restarts, 1);
+    assert!(!session.run.is_finished());
+}
+
+#[tokio::test]
+async fn test_clean_exit_does_not_promote_standby() {
+    let dir = tempfile::tempdir().unwrap();
+    let args = vec!["mock-server".to_string(), write_fixture(dir.path())];
+    let mut session = Session::start(&mock_config(true), args);
+    session.initialize_and_open().await;
+
+    session.request(2, "shutdown", Value::Null).await;
+    session
+        .send(json!({"jsonrpc": "2.0", "method": "exit"}))
+        .await;
+    // 正常退出时监管者直接返回，不会再启动后端
+    let result = tokio::time::timeout(Duration::from_secs(20), session.run)
+        .await
+        .expect("后端退出后监管者没有返回");
+    result.unwrap().unwrap();
+    assert_eq!(session.dispatcher.stats().report().backend_restarts, 0);
+}
+
+#[tokio::test]
+async fn test_crash_without_standby_ends_supervisor() {
+    let dir = tempfile::tempdir().unwrap();
+    let args = vec!["mock-server".to_string(), write_fixture(dir.path())];
+    let mut session = Session::start(&mock_config(false), args);
+    session.initialize_and_open().await;
+
+    session.crash();
+    let result = tokio::time::timeout(Duration::from_secs(20), session.run)
+        .await
+        .expect("后端退出后监管者没有返回");
+    result.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_failed_relaunch_keeps_primary() {
+    let dir = tempfile::tempdir().unwrap();
+    let args = vec!["mock-server".to_string(), write_fixture(dir.path())];
+    let mut session = Session::start(&mock_config(false), args);
+    session.initialize_and_open().await;
+
+    // 重新加载的配置中的后端无法启动：继续使用原来的后端，并在编辑器中提示
+    let config = Config {
+        backend: BackendConfig {
+            command: "codefuse-no-such-backend".to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let changes = Changes {
+        restart_backend: vec!["[backend]"],
+        ..Default::default()
+    };
+    session.dispatcher.apply_config(&config, &changes);
+    let warning = session
+        .expect(|message| {
+            message["method"] == "window/showMessage"
+                && message["params"]["message"]
+                    .as_str()
+                    .is_some_and(|text| text.contains("codefuse-no-such-backend"))
+        })
+        .await;
+    assert_eq!(warning["params"]["type"], 2);
+    assert_eq!(session.hover(2).await["result"], Value::Null);
+    assert_eq!(session.dispatcher.stats().report().backend_restarts, 0);
+    assert!(!session.run.is_finished());
+}
+
+#[tokio::test]
+async fn test_zero_idle_minutes_do_not_panic() {
+    let dir = tempfile::tempdir().unwrap();
+    let args = vec!["mock-server".to_string(), write_fixture(dir.path())];
+    // 直接构造的配置不经过加载时的检查
+    let config = BackendConfig {
+        idle: IdleConfig {
+            after_mins: Some(0),
+            ..Default::default()
+        },
+        ..mock_config(false)
+    };
+    let mut session = Session::start(&config, args);
+    session.initialize_and_open().await;
+    assert_eq!(session.hover(2).await["result"], Value::Null);
+    assert!(!session.run.is_finished());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_ssh_backend_reconnects_and_replays() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // 用运行模拟后端的脚本代替 ssh
+    let dir = tempfile::tempdir().unwrap();
+    let fixture = write_fixture(dir.path());
+    let ssh = dir.path().join("ssh");
+    let script = format!(
+        "#!/bin/sh\nexec '{}' mock-server '{}'\n",
+        env!("CARGO_BIN_EXE_lsp-proxy"),
+        fixture
+    );
+    std::fs::write(&ssh, script).unwrap();
+    std::fs::set_permissions(&ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let mut paths = vec![dir.path().to_path_buf()];
+    paths.extend(std::env::split_paths(&path));
+    // 这个测试文件中的其他测试以绝对路径启动后端，不受 PATH 影响
+    unsafe { std::env::set_var("PATH", std::env::join_paths(paths).unwrap()) };
+
+    let config = BackendConfig {
+        command: "ssh://devbox/usr/bin/clangd".to_string(),
+        ..Default::default()
+    };
+    let mut session = Session::start(&config, Vec::new());
+    session.initialize_and_open().await;
+
+    session.crash();
+    // 重新连接后重放打开的文档
+    session.expect_opened().await;
+    assert_eq!(session.hover(2).await["result"], Value::Null);
+    assert!(!session.run.is_finished());
+}
+
+#[test]
+fn test_replayed_documents_drop_queued_changes() {
+    let change = |uri: &str, version: i64| {
+        Message::new(
+            json!({"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+            "textDocument": {"uri": uri, "version": version}, "contentChanges": []}}),
+        )
+    };
+    let replayed = ReplayedDocuments::new();
+    assert!(replayed.should_forward(&change(URI, 1)));
+
+    // 重放的 didOpen 已经包含版本 3 的修改
+    replayed.reset([(URI.to_string(), 3)]);
+    assert!(!replayed.should_forward(&change(URI, 2)));
+    assert!(!replayed.should_forward(&change(URI, 3)));
+    assert!(replayed.should_forward(&change(URI, 4)));
+    assert!(replayed.should_forward(&change("file:///src/b.cpp", 1)));
+
+    // 关闭之后文档以更小的版本重新打开
+    replayed.reset([(URI.to_string(), 5)]);
+    let close = json!({"jsonrpc": "2.0", "method": "textDocument/didClose", "params": {
+        "textDocument": {"uri": URI}}});
+    assert!(replayed.should_forward(&Message::new(close)));
+    let open = json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": {"uri": URI, "languageId": "cpp", "version": 1, "text": ""}}});
+    assert!(replayed.should_forward(&Message::new(open)));
+}
@@ -0,0 +1,91 @@
+use lsp_proxy::symbol_index::{self, SymbolIndex};
+use lsp_proxy::workspace;
+use serde_json::json;
+use std::path::PathBuf;
+use tower_lsp::lsp_types::SymbolKind;
+
+const CTAGS_OUTPUT: &str = r#"{"_type": "tag", "name": "main", "path": "/work/main.cpp", "pattern": "/^int main() {$/", "line": 3, "kind": "function"}
+{"_type": "tag", "name": "Widget", "path": "/work/widget.h", "pattern": "/^class Widget {$/", "line": 5, "kind": "class"}
+{"_type": "tag", "name": "widget_count", "path": "/work/widget.h", "pattern": "/^  int widget_count;$/", "line": 7, "kind": "member", "scope": "Widget"}
+{"_type": "tag", "name": "größe", "path": "/work/widget.h", "pattern": "/^  int /* \\/ 尺寸 */ größe;$/", "line": 8, "kind": "member", "scope": "Widget"}
+{"_type": "ptag", "name": "TAG_PROGRAM_NAME", "path": "Universal Ctags"}
+"#;
+
+#[test]
+fn test_ctags_output_query() {
+    let index = SymbolIndex::new("ctags");
+    assert!(!index.should_answer());
+    assert_eq!(index.load_ctags_output(CTAGS_OUTPUT), 4);
+    assert!(index.should_answer());
+
+    let results = index.query("widget", 10);
+    let names: Vec<&str> = results.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, vec!["Widget", "widget_count"]);
+    assert_eq!(results[0].kind, SymbolKind::CLASS);
+    assert_eq!(results[1].container_name.as_deref(), Some("Widget"));
+    assert_eq!(results[1].location.range.start.line, 6);
+    assert_eq!(results[1].location.range.start.character, 6);
+
+    // 列按 UTF-16 计数，搜索模式中的转义不占位置
+    let results = index.query("größe", 10);
+    assert_eq!(results[0].location.range.start.character, 17);
+    assert_eq!(results[0].location.range.end.character, 22);
+
+    // 子序列匹配
+    assert_eq!(index.query("wdcnt", 10).len(), 1);
+
+    index.mark_backend_ready();
+    assert!(!index.should_answer());
+}
+
+#[test]
+fn test_typedef_is_reported_as_class() {
+    let index = SymbolIndex::new("ctags");
+    let output = r#"{"_type": "tag", "name": "WidgetId", "path": "/work/widget.h", "pattern": "/^typedef int WidgetId;$/", "line": 2, "kind": "typedef"}
+{"_type": "tag", "name": "WidgetMap", "path": "/work/widget.h", "pattern": "/^using WidgetMap = std::map<int, Widget>;$/", "line": 3, "kind": "alias"}
+"#;
+    assert_eq!(index.load_ctags_output(output), 2);
+
+    let results = index.query("widget", 10);
+    let kinds: Vec<(&str, SymbolKind)> =
+        results.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            ("WidgetId", SymbolKind::CLASS),
+            ("WidgetMap", SymbolKind::CLASS)
+        ]
+    );
+    assert_eq!(results[0].location.range.start.character, 12);
+}
+
+#[test]
+fn test_index_progress_and_roots() {
+    let end = json!({
+        "jsonrpc": "2.0",
+        "method": "$/progress",
+        "params": {"token": "backgroundIndexProgress", "value": {"kind": "end"}}
+    });
+    let report = json!({
+        "jsonrpc": "2.0",
+        "method": "$/progress",
+        "params": {"token": "backgroundIndexProgress", "value": {"kind": "report", "percentage": 50}}
+    });
+    assert!(symbol_index::is_index_progress_end(&end));
+    assert!(!symbol_index::is_index_progress_end(&report));
+
+    let params = json!({"rootUri": "file:///work", "capabilities": {}});
+    assert_eq!(
+        workspace::initialize_roots(&params),
+        vec![PathBuf::from("/work")]
+    );
+
+    let params = json!({
+        "rootUri": "file:///work",
+        "workspaceFolders": [{"uri": "file:///a", "name": "a"}, {"uri": "file:///b", "name": "b"}]
+    });
+    assert_eq!(
+        workspace::initialize_roots(&params),
+        vec![PathBuf::from("/a"), PathBuf::from("/b")]
+    );
+}
@@ -0,0 +1,123 @@
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+use lsp_proxy::config::{Config, TelemetryConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::telemetry::{self, SpanKind};
+
+#[tokio::test]
+async fn test_request_produces_server_and_backend_spans() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse("[telemetry]\nendpoint = \"http://127.0.0.1:4318\"\n").unwrap();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+
+    let hover = json!({"jsonrpc": "2.0", "id": 7, "method": "textDocument/hover", "params": {
+        "textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 0, "character": 0}
+    }});
+    dispatcher.handle_from_frontend(hover).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    dispatcher
+        .handle_from_backend(json!({"jsonrpc": "2.0", "id": 7, "result": null}))
+        .await
+        .unwrap();
+    frontend_rx.recv().await.unwrap();
+
+    let spans = dispatcher.telemetry().drain();
+    assert_eq!(spans.len(), 2);
+    let (server, backend) = (&spans[0], &spans[1]);
+    assert_eq!(server.kind, SpanKind::Server);
+    assert_eq!(server.name, "textDocument/hover");
+    assert_eq!(backend.kind, SpanKind::Client);
+    assert_eq!(backend.trace_id, server.trace_id);
+    assert_eq!(backend.parent_span_id, Some(server.span_id));
+    assert!(server.start <= backend.start && backend.end <= server.end);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_spans_are_posted_as_otlp_json() {
+    let collector = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", collector.local_addr().unwrap());
+
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse(&format!("[telemetry]\nendpoint = \"{}\"\n", endpoint)).unwrap();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    // 代理自己应答的请求只有根跨度
+    let metrics = json!({"jsonrpc": "2.0", "id": "m", "method": "codefuse/metrics"});
+    dispatcher.handle_from_frontend(metrics).await.unwrap();
+
+    tokio::spawn(telemetry::export_loop(
+        dispatcher.telemetry(),
+        TelemetryConfig {
+            endpoint: Some(endpoint),
+            ..TelemetryConfig::default()
+        },
+    ));
+
+    let (mut stream, _) = collector.accept().await.unwrap();
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let body = loop {
+        let read = stream.read(&mut buffer).await.unwrap();
+        request.extend_from_slice(&buffer[..read]);
+        let text = String::from_utf8_lossy(&request).into_owned();
+        if let Some((_, body)) = text.split_once("\r\n\r\n")
+            && let Ok(body) = serde_json::from_str::<Value>(body)
+        {
+            assert!(text.starts_with("POST /v1/traces HTTP/1.1"));
+            break body;
+        }
+    };
+    stream
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+        .await
+        .unwrap();
+
+    let resource = &body["resourceSpans"][0];
+    assert_eq!(
+        resource["resource"]["attributes"][0]["value"]["stringValue"],
+        json!("codefuse-proxy")
+    );
+    let span = &resource["scopeSpans"][0]["spans"][0];
+    assert_eq!(span["name"], json!("codefuse/metrics"));
+    assert_eq!(span["kind"], json!(2));
+    assert_eq!(span["traceId"].as_str().unwrap().len(), 32);
+    assert!(span.get("parentSpanId").is_none());
+}
+
+#[test]
+fn test_zero_export_interval_is_rejected() {
+    assert!(Config::parse("[telemetry]\nexport_interval_ms = 0\n").is_err());
+    assert!(Config::parse("[telemetry]\nexport_interval_ms = 1\n").is_ok());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_stalled_collector_does_not_block_export() {
+    let collector = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", collector.local_addr().unwrap());
+
+    let (backend_tx, _backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, _frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse(&format!("[telemetry]\nendpoint = \"{}\"\n", endpoint)).unwrap();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    let metrics = |id: &str| json!({"jsonrpc": "2.0", "id": id, "method": "codefuse/metrics"});
+    dispatcher.handle_from_frontend(metrics("a")).await.unwrap();
+
+    tokio::spawn(telemetry::export_loop(
+        dispatcher.telemetry(),
+        TelemetryConfig {
+            endpoint: Some(endpoint),
+            ..TelemetryConfig::default()
+        },
+    ));
+
+    // 收集器接受连接后一直不应答，导出超时后下一批跨度照常导出
+    let (_stalled, _) = collector.accept().await.unwrap();
+    dispatcher.handle_from_frontend(metrics("b")).await.unwrap();
+    collector.accept().await.unwrap();
+}
@@ -0,0 +1,99 @@
+use lsp_proxy::config::TidyRule;
+use lsp_proxy::tidy_policy::TidyPolicy;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+fn rule(path: Option<&str>, checks: &str) -> TidyRule {
+    TidyRule {
+        path: path.map(PathBuf::from),
+        checks: checks.to_string(),
+    }
+}
+
+#[test]
+fn test_nested_rules_last_match_wins() {
+    let policy = TidyPolicy::new(&[
+        rule(Some("/repo/legacy"), "-modernize-*"),
+        rule(
+            None,
+            "-*,bugprone-*,modernize-*,-bugprone-easily-swappable-parameters",
+        ),
+    ]);
+
+    let src = Path::new("/repo/src/a.cpp");
+    assert!(policy.is_enabled(src, "bugprone-use-after-move"));
+    assert!(policy.is_enabled(src, "modernize-use-nullptr"));
+    assert!(!policy.is_enabled(src, "bugprone-easily-swappable-parameters"));
+    assert!(!policy.is_enabled(src, "readability-braces-around-statements"));
+
+    // 内层目录的规则覆盖外层
+    assert!(!policy.is_enabled(Path::new("/repo/legacy/b.cpp"), "modernize-use-nullptr"));
+}
+
+#[test]
+fn test_filter_publish_diagnostics() {
+    let policy = TidyPolicy::new(&[rule(None, "-readability-*")]);
+    let mut rpc = json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {
+            "uri": "file:///repo/a.cpp",
+            "diagnostics": [
+                {"source": "clang", "message": "unused variable", "code": "-Wunused-variable"},
+                {"source": "clang-tidy", "message": "x", "code": "readability-braces-around-statements"},
+                {"source": "clang-tidy", "message": "y", "code": "bugprone-use-after-move"}
+            ]
+        }
+    });
+    policy.filter_diagnostics(&mut rpc);
+    let codes: Vec<&str> = rpc["params"]["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["code"].as_str().unwrap())
+        .collect();
+    assert_eq!(codes, vec!["-Wunused-variable", "bugprone-use-after-move"]);
+}
+
+#[test]
+fn test_checks_are_merged_into_backend_settings() {
+    let policy = TidyPolicy::new(&[
+        rule(None, "-*,bugprone-*"),
+        rule(Some("/repo/legacy"), "-bugprone-*"),
+        rule(None, "performance-*"),
+    ]);
+    assert_eq!(
+        policy.checks().as_deref(),
+        Some("-*,bugprone-*,performance-*")
+    );
+
+    // 编辑器自己的设置在前，策略在后优先
+    let mut params = json!({"initializationOptions": {"clangTidy": {"checks": "readability-*"}}});
+    policy.on_initialize(&mut params);
+    assert_eq!(
+        params["initializationOptions"]["clangTidy"]["checks"],
+        "readability-*,-*,bugprone-*,performance-*"
+    );
+
+    let mut rpc = json!({
+        "jsonrpc": "2.0",
+        "method": "workspace/didChangeConfiguration",
+        "params": {"settings": null}
+    });
+    policy.on_configuration_changed(&mut rpc);
+    assert_eq!(
+        rpc["params"]["settings"]["clangTidy"]["checks"],
+        "-*,bugprone-*,performance-*"
+    );
+    assert_eq!(
+        policy.configuration_notification().unwrap()["params"],
+        rpc["params"]
+    );
+
+    // 只有按目录的规则时不修改后端设置
+    let policy = TidyPolicy::new(&[rule(Some("/repo/legacy"), "-*")]);
+    let mut params = json!({"processId": 1});
+    policy.on_initialize(&mut params);
+    assert_eq!(params, json!({"processId": 1}));
+    assert!(policy.configuration_notification().is_none());
+}
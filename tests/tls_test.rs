@@ -0,0 +1,103 @@
+use lsp_proxy::config::TransportConfig;
+use lsp_proxy::tls::{ConnectSecurity, ListenSecurity};
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn with_token(dir: &Path, token: &str) -> TransportConfig {
+    let path = dir.join("token");
+    std::fs::write(&path, format!("{}\n", token)).unwrap();
+    TransportConfig {
+        token_file: Some(path),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_wrong_token_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let server = ListenSecurity::from_config(&with_token(dir.path(), "secret")).unwrap();
+    let accepted = tokio::spawn(async move { server.accept(&listener).await.map(|_| ()) });
+
+    // 令牌不对的连接被关闭，代理继续等待
+    let intruder = ConnectSecurity::from_config(&with_token(dir.path(), "guess")).unwrap();
+    let (mut reader, _writer) = intruder.connect(&address).await.unwrap();
+    let mut buffer = Vec::new();
+    assert_eq!(reader.read_to_end(&mut buffer).await.unwrap(), 0);
+    assert!(!accepted.is_finished());
+
+    let client = ConnectSecurity::from_config(&with_token(dir.path(), "secret")).unwrap();
+    let _connection = client.connect(&address).await.unwrap();
+    accepted.await.unwrap().unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_silent_connection_does_not_block_accept() {
+    let dir = tempfile::tempdir().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let server = ListenSecurity::from_config(&with_token(dir.path(), "secret")).unwrap();
+    let accepted = tokio::spawn(async move { server.accept(&listener).await.map(|_| ()) });
+
+    // 第一个连接一直不发送令牌，后来的连接不必等它超时
+    let started = tokio::time::Instant::now();
+    let _silent = tokio::net::TcpStream::connect(&address).await.unwrap();
+    let client = ConnectSecurity::from_config(&with_token(dir.path(), "secret")).unwrap();
+    let _connection = client.connect(&address).await.unwrap();
+    accepted.await.unwrap().unwrap();
+    assert!(started.elapsed() < lsp_proxy::tls::AUTH_TIMEOUT);
+}
+
+#[tokio::test]
+async fn test_tls_connection_with_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_path = dir.path().join("cert.pem");
+    let key_path = dir.path().join("key.pem");
+    std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+    std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+
+    let server = ListenSecurity::from_config(&TransportConfig {
+        tls_cert: Some(cert_path.clone()),
+        tls_key: Some(key_path),
+        ..with_token(dir.path(), "secret")
+    })
+    .unwrap();
+    let client = ConnectSecurity::from_config(&TransportConfig {
+        tls_ca: Some(cert_path),
+        tls_server_name: Some("localhost".to_string()),
+        ..with_token(dir.path(), "secret")
+    })
+    .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let (accepted, connected) = tokio::join!(server.accept(&listener), client.connect(&address));
+    let (mut server_reader, mut server_writer) = accepted.unwrap();
+    let (mut client_reader, mut client_writer) = connected.unwrap();
+
+    client_writer.write_all(b"ping").await.unwrap();
+    client_writer.flush().await.unwrap();
+    let mut buffer = [0u8; 4];
+    server_reader.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(&buffer, b"ping");
+
+    server_writer.write_all(b"pong").await.unwrap();
+    server_writer.flush().await.unwrap();
+    client_reader.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(&buffer, b"pong");
+}
+
+#[test]
+fn test_incomplete_configuration_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = TransportConfig {
+        tls_cert: Some(dir.path().join("cert.pem")),
+        ..Default::default()
+    };
+    assert!(ListenSecurity::from_config(&config).is_err());
+
+    assert!(ListenSecurity::from_config(&with_token(dir.path(), "  ")).is_err());
+}
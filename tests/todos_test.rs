@@ -0,0 +1,111 @@
+use lsp_proxy::config::{Config, TodoConfig};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::document_store::Document;
+use lsp_proxy::message::Message;
+use lsp_proxy::todos::TodoScanner;
+use serde_json::json;
+use std::fs;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::{Position, Url};
+
+#[test]
+fn test_scan_finds_markers_only_in_comments() {
+    let scanner = TodoScanner::new(&TodoConfig::default());
+    let uri = Url::parse("file:///a.cpp").unwrap();
+    let text = "// TODO: handle errors\nconst char *s = \"TODO not a comment\";\n\
+                int x; /* FIXME overflow */\n";
+    let items = scanner.scan(&uri, text);
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].marker, "TODO");
+    assert_eq!(items[0].text, "handle errors");
+    assert_eq!(items[0].range.start, Position::new(0, 3));
+    assert_eq!(items[1].marker, "FIXME");
+    assert_eq!(items[1].text, "overflow");
+    assert_eq!(items[1].range.start, Position::new(2, 10));
+
+    // 自定义的模式，无效的正则表达式被忽略
+    let scanner = TodoScanner::new(&TodoConfig {
+        patterns: vec![r"\bXXX\b".to_string(), "(".to_string()],
+        ..Default::default()
+    });
+    assert!(scanner.scan(&uri, text).is_empty());
+    assert_eq!(scanner.scan(&uri, "// XXX later\n")[0].text, "later");
+}
+
+#[test]
+fn test_report_includes_unopened_workspace_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir(root.join(".git")).unwrap();
+    fs::write(root.join(".git/ignored.h"), "// TODO hidden\n").unwrap();
+    fs::write(root.join("b.h"), "// HACK on disk\n").unwrap();
+    fs::write(root.join("a.cpp"), "// TODO on disk\n").unwrap();
+    fs::write(root.join("notes.txt"), "// TODO not source\n").unwrap();
+    let open = Document {
+        uri: Url::from_file_path(root.join("a.cpp")).unwrap(),
+        language_id: "cpp".to_string(),
+        version: 2,
+        text: "// TODO edited\n// FIXME edited\n".to_string(),
+    };
+
+    let scanner = TodoScanner::new(&TodoConfig {
+        workspace: true,
+        ..Default::default()
+    });
+    let report = scanner.report(std::slice::from_ref(&open), &[root.to_path_buf()]);
+    let texts: Vec<&str> = report.items.iter().map(|item| item.text.as_str()).collect();
+    // 打开的文档使用编辑器中的内容
+    assert_eq!(texts, ["edited", "edited", "on disk"]);
+    assert_eq!(report.counts["TODO"], 1);
+    assert_eq!(report.counts["HACK"], 1);
+    assert!(!report.truncated);
+
+    // 不扫描工作区时只有打开的文档
+    let scanner = TodoScanner::new(&TodoConfig::default());
+    assert_eq!(
+        scanner.report(&[open], &[root.to_path_buf()]).items.len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_dispatcher_answers_code_lens_and_todos() {
+    let mut config = Config::default();
+    config.todos.code_lens = true;
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": {"uri": "file:///a.cpp", "languageId": "cpp", "version": 1,
+                                 "text": "int x; // TODO: rename\n"}
+            }}),
+        )
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+
+    // 后端不提供代码透镜，由代理直接应答
+    dispatcher
+        .handle_from_frontend(
+            json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/codeLens",
+            "params": {"textDocument": {"uri": "file:///a.cpp"}}}),
+        )
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["result"][0]["command"]["title"], "TODO: rename");
+    assert_eq!(response["result"][0]["range"]["start"]["character"], 10);
+
+    dispatcher
+        .handle_from_frontend(json!({"jsonrpc": "2.0", "id": 2, "method": "codefuse/todos"}))
+        .await
+        .unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["id"], 2);
+    assert_eq!(response["result"]["items"][0]["uri"], "file:///a.cpp");
+    assert_eq!(response["result"]["counts"], json!({"TODO": 1}));
+    assert!(backend_rx.try_recv().is_err());
+}
@@ -0,0 +1,185 @@
+use lsp_proxy::cli::{CliArgs, Command};
+use lsp_proxy::trace_diff::{self, DiffCommand, Difference};
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+
+fn entry(timestamp_ms: u64, direction: &str, message: Value) -> String {
+    json!({"timestamp_ms": timestamp_ms, "direction": direction, "message": message}).to_string()
+}
+
+fn hover(id: u64, line: u64) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": "textDocument/hover", "params": {
+        "textDocument": {"uri": "file:///src/main.cpp"},
+        "position": {"line": line, "character": 4}}})
+}
+
+fn write_trace(dir: &Path, name: &str, lines: &[String]) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+    path
+}
+
+#[test]
+fn test_diff_traces() {
+    let dir = tempfile::tempdir().unwrap();
+    let completion = |id: u64, labels: &[&str]| {
+        let items: Vec<Value> = labels
+            .iter()
+            .map(|label| json!({"label": label, "data": {"id": id}}))
+            .collect();
+        json!({"jsonrpc": "2.0", "id": id, "result": {"isIncomplete": false, "items": items}})
+    };
+    let complete = |id: u64| {
+        json!({"jsonrpc": "2.0", "id": id, "method": "textDocument/completion", "params": {
+            "textDocument": {"uri": "file:///src/main.cpp"},
+            "position": {"line": 9, "character": 2}, "workDoneToken": id}})
+    };
+    let a = write_trace(
+        dir.path(),
+        "a.ndjson",
+        &[
+            entry(100, "frontend", hover(1, 4)),
+            entry(
+                110,
+                "backend",
+                json!({"jsonrpc": "2.0", "id": 1,
+                "result": {"contents": {"kind": "markdown", "value": "int x"}}}),
+            ),
+            entry(120, "frontend", complete(2)),
+            entry(130, "backend", completion(2, &["push_back", "pop_back"])),
+            entry(140, "frontend", hover(3, 20)),
+            entry(
+                150,
+                "backend",
+                json!({"jsonrpc": "2.0", "id": 3, "result": null}),
+            ),
+        ],
+    );
+    let b = write_trace(
+        dir.path(),
+        "b.ndjson",
+        &[
+            entry(900, "frontend", complete(7)),
+            // 后端发给前端的通知不是响应
+            entry(
+                905,
+                "backend",
+                json!({"jsonrpc": "2.0", "method": "$/progress",
+                "params": {"token": 7}}),
+            ),
+            entry(910, "backend", completion(7, &["pop_back", "push_back"])),
+            entry(920, "frontend", hover(8, 4)),
+            entry(
+                930,
+                "backend",
+                json!({"jsonrpc": "2.0", "id": 8,
+                "result": {"contents": {"kind": "markdown", "value": "const int x"}}}),
+            ),
+            entry(940, "frontend", hover(9, 30)),
+            entry(
+                950,
+                "backend",
+                json!({"jsonrpc": "2.0", "id": 9, "result": null}),
+            ),
+        ],
+    );
+
+    let a = trace_diff::exchanges(&trace_diff::load(&a).unwrap());
+    let b = trace_diff::exchanges(&trace_diff::load(&b).unwrap());
+    assert_eq!(a.len(), 3);
+    let report = trace_diff::diff_traces(&a, &b, &[]);
+    // id、时间戳、data 和进度令牌不同，补全项的顺序不同，响应仍然相同
+    assert_eq!(report.same, 1);
+    assert_eq!(report.changed.len(), 1);
+    assert_eq!(
+        report.changed[0].differences,
+        [Difference {
+            path: "result.contents.value".to_string(),
+            a: Some(json!("int x")),
+            b: Some(json!("const int x")),
+        }]
+    );
+    assert_eq!(
+        report.changed[0].exchange.label(),
+        "textDocument/hover file:///src/main.cpp:5:5"
+    );
+    assert_eq!(
+        report.only_a[0].label(),
+        "textDocument/hover file:///src/main.cpp:21:5"
+    );
+    assert_eq!(
+        report.only_b[0].label(),
+        "textDocument/hover file:///src/main.cpp:31:5"
+    );
+    assert!(!report.is_identical());
+
+    let text = report.to_string();
+    assert!(text.contains("  result.contents.value: \"int x\" → \"const int x\"\n"));
+    assert!(text.ends_with("相同 1，不同 1，只在 A 中 1，只在 B 中 1\n"));
+
+    // 只比较补全请求
+    let methods = ["textDocument/completion".to_string()];
+    assert!(trace_diff::diff_traces(&a, &b, &methods).is_identical());
+}
+
+#[test]
+fn test_diff_values() {
+    let a = json!({"items": [{"label": "a"}, {"label": "b"}], "kind": 1});
+    let b = json!({"items": [{"label": "a"}], "detail": "x", "kind": 1});
+    assert_eq!(
+        trace_diff::diff_values("result", &a, &b),
+        [
+            Difference {
+                path: "result.items[1]".to_string(),
+                a: Some(json!({"label": "b"})),
+                b: None,
+            },
+            Difference {
+                path: "result.detail".to_string(),
+                a: None,
+                b: Some(json!("x")),
+            },
+        ]
+    );
+    assert_eq!(
+        trace_diff::normalize(&json!({"id": 1, "result": [{"data": 2, "label": "a"}]})),
+        json!({"result": [{"label": "a"}]})
+    );
+}
+
+#[test]
+fn test_load_reports_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_trace(
+        dir.path(),
+        "bad.ndjson",
+        &[entry(1, "frontend", hover(1, 0)), "{".to_string()],
+    );
+    let error = trace_diff::load(&path).unwrap_err();
+    assert!(error.to_string().contains("第 2 行"));
+}
+
+#[test]
+fn test_cli_diff() {
+    let args = CliArgs::parse(
+        [
+            "diff",
+            "--method",
+            "textDocument/hover",
+            "a.ndjson",
+            "b.ndjson",
+        ]
+        .map(String::from),
+    )
+    .unwrap();
+    assert_eq!(
+        args.command,
+        Some(Command::Diff(DiffCommand {
+            a: PathBuf::from("a.ndjson"),
+            b: PathBuf::from("b.ndjson"),
+            methods: vec!["textDocument/hover".to_string()],
+        }))
+    );
+    assert!(CliArgs::parse(["diff", "a.ndjson"].map(String::from)).is_err());
+    assert!(CliArgs::parse(["diff", "a", "b", "c"].map(String::from)).is_err());
+}
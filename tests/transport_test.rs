@@ -0,0 +1,66 @@
+use lsp_proxy::cli::{CliArgs, Command};
+use lsp_proxy::config::TransportConfig;
+use lsp_proxy::transport::Transport;
+
+#[test]
+fn test_pipe_argument_forms() {
+    let parse = |args: &[&str]| {
+        CliArgs::parse(args.iter().map(|arg| arg.to_string()))
+            .unwrap()
+            .transport
+    };
+    assert_eq!(parse(&[]), Transport::Stdio);
+    assert_eq!(parse(&["--stdio"]), Transport::Stdio);
+    assert_eq!(
+        parse(&["--pipe=/tmp/lsp.sock"]),
+        Transport::Pipe("/tmp/lsp.sock".to_string())
+    );
+    assert_eq!(
+        parse(&["--pipe", r"\\.\pipe\vscode-lsp"]),
+        Transport::Pipe(r"\\.\pipe\vscode-lsp".to_string())
+    );
+}
+
+#[test]
+fn test_socket_argument_forms() {
+    let parse = |args: &[&str]| CliArgs::parse(args.iter().map(|arg| arg.to_string())).unwrap();
+    assert_eq!(
+        parse(&["--socket=5007"]).transport,
+        Transport::Socket("5007".to_string())
+    );
+    let listen = parse(&["--listen", "0.0.0.0:7000"]).transport;
+    assert_eq!(listen, Transport::Listen("0.0.0.0:7000".to_string()));
+    assert!(listen.is_socket());
+    assert!(!Transport::Stdio.is_socket());
+    assert_eq!(
+        parse(&["relay", "devbox:7000"]).command,
+        Some(Command::Relay {
+            address: "devbox:7000".to_string()
+        })
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_pipe_transport_connects_to_editor_socket() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lsp.sock");
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+    let transport = Transport::Pipe(path.to_string_lossy().into_owned());
+    let config = TransportConfig::default();
+    let (connected, accepted) = tokio::join!(transport.connect(&config), listener.accept());
+    let (mut reader, mut writer) = connected.unwrap();
+    let (mut editor, _) = accepted.unwrap();
+
+    editor.write_all(b"ping").await.unwrap();
+    let mut buffer = [0u8; 4];
+    reader.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(&buffer, b"ping");
+
+    writer.write_all(b"pong").await.unwrap();
+    editor.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(&buffer, b"pong");
+}
@@ -0,0 +1,189 @@
+use lsp_proxy::cli::{CliArgs, Command};
+use lsp_proxy::config::{Config, QueryDriverMode};
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::message::Message;
+use lsp_proxy::reload::ConfigSource;
+use lsp_proxy::trust::{self, TrustCommand, TrustedWorkspaces, WorkspaceTrust};
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+const PROJECT_CONFIG: &str = r#"
+[backend]
+command = "./tools/clangd-wrapper.sh"
+args = ["--background-index"]
+
+[backend.clangd]
+auto_query_driver = "on"
+
+[[shards]]
+path = "services"
+args = ["--query-driver=/**"]
+
+[prefetch]
+enabled = true
+
+[flags]
+bazel = true
+"#;
+
+#[test]
+fn test_restrict_withholds_process_settings() {
+    let mut config = Config::parse(PROJECT_CONFIG).unwrap();
+    let withheld = trust::restrict(&mut config);
+    assert_eq!(
+        withheld,
+        vec![
+            "[backend] command",
+            "[backend] args",
+            "[backend.clangd] auto_query_driver",
+            "[[shards]] args",
+            "[flags] bazel",
+        ]
+    );
+    assert_eq!(config.backend.command, "clangd");
+    assert!(config.backend.args.is_empty());
+    assert_eq!(
+        config.backend.clangd.auto_query_driver,
+        QueryDriverMode::Ask
+    );
+    assert!(config.shards[0].args.is_empty());
+    assert!(!config.flags.bazel);
+    // 不启动进程的设置保留
+    assert!(config.prefetch.enabled);
+    assert_eq!(config.shards.len(), 1);
+
+    assert!(trust::restrict(&mut Config::default()).is_empty());
+}
+
+#[test]
+fn test_restrict_withholds_sandbox() {
+    // 不受信任的配置不能放宽沙箱，也不能借沙箱的参数运行其他程序
+    let mut config = Config::parse(
+        "[backend.sandbox]\nworkspace = \"/src/app\"\nwritable = [\"/\"]\nnetwork = true\n",
+    )
+    .unwrap();
+    assert_eq!(trust::restrict(&mut config), vec!["[backend.sandbox]"]);
+    assert!(config.backend.sandbox.is_none());
+}
+
+#[test]
+fn test_workspace_trust() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".codefuse.toml");
+    std::fs::write(&path, PROJECT_CONFIG).unwrap();
+
+    let trust = WorkspaceTrust::discover(Some(&path), dir.path(), false);
+    assert!(!trust.is_trusted());
+    let mut config = Config::load(&path).unwrap();
+    assert_eq!(trust.apply(&mut config).len(), 5);
+    let message = trust.message().unwrap();
+    assert!(message.contains("[backend] command、[backend] args"));
+    assert!(message.contains("lsp-proxy trust"));
+
+    // 编辑器信任工作区后重新应用配置
+    assert!(trust.set_trusted(true));
+    assert!(!trust.set_trusted(true));
+    let mut config = Config::load(&path).unwrap();
+    assert!(trust.apply(&mut config).is_empty());
+    assert_eq!(config.backend.command, "./tools/clangd-wrapper.sh");
+    assert_eq!(trust.message(), None);
+
+    // 命令行参数信任工作区
+    assert!(WorkspaceTrust::discover(Some(&path), dir.path(), true).is_trusted());
+    // 工作区之外的配置文件由用户指定，总是受信任
+    let other = tempfile::tempdir().unwrap();
+    assert!(WorkspaceTrust::discover(Some(&path), other.path(), false).is_trusted());
+    assert!(WorkspaceTrust::discover(None, dir.path(), false).is_trusted());
+}
+
+#[test]
+fn test_trusted_workspaces() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("codefuse/trusted.json");
+    let root = PathBuf::from("/src/project");
+
+    let mut trusted = TrustedWorkspaces::load(&path);
+    assert!(!trusted.contains(&root));
+    assert!(trusted.set(&path, &root, true).unwrap());
+    assert!(!trusted.set(&path, &root, true).unwrap());
+    assert!(TrustedWorkspaces::load(&path).contains(&root));
+
+    assert!(trusted.set(&path, &root, false).unwrap());
+    assert!(!TrustedWorkspaces::load(&path).contains(&root));
+}
+
+#[test]
+fn test_cli_trust() {
+    let args = CliArgs::parse(["--trust-workspace"].map(String::from)).unwrap();
+    assert!(args.trust_workspace);
+
+    let args = CliArgs::parse(["trust", "--revoke", "/src/project"].map(String::from)).unwrap();
+    assert_eq!(
+        args.command,
+        Some(Command::Trust(TrustCommand {
+            root: Some(PathBuf::from("/src/project")),
+            revoke: true,
+        }))
+    );
+    assert!(CliArgs::parse(["trust", "--all"].map(String::from)).is_err());
+}
+
+#[tokio::test]
+async fn test_client_trust_signal_relaunches_backend() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".codefuse.toml");
+    std::fs::write(&path, PROJECT_CONFIG).unwrap();
+    let trust = Arc::new(WorkspaceTrust::discover(Some(&path), dir.path(), false));
+    let mut config = Config::load(&path).unwrap();
+    trust.apply(&mut config);
+    let source = Arc::new(
+        ConfigSource::new(Some(path.clone()), None, config.clone(), |_| {})
+            .with_trust(Arc::clone(&trust)),
+    );
+
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let dispatcher = Arc::new(
+        Dispatcher::new(backend_tx, frontend_tx)
+            .with_config(config)
+            .with_config_source(source),
+    );
+    let restart = dispatcher.subscribe_restart();
+    let launch = dispatcher.subscribe_backend_launch();
+
+    // 初始化时告诉用户忽略了哪些设置
+    let initialize = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize",
+        "params": {"capabilities": {}}});
+    dispatcher.handle_from_frontend(initialize).await.unwrap();
+    backend_rx.recv().await.unwrap();
+    let message = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(message["method"], "window/showMessage");
+    assert_eq!(message["params"]["type"], 2);
+    assert!(
+        message["params"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("[backend] command")
+    );
+
+    let request = json!({"jsonrpc": "2.0", "id": 2, "method": "workspace/trust",
+        "params": {"trusted": true}});
+    dispatcher.handle_from_frontend(request).await.unwrap();
+    let message = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(message["method"], "window/showMessage");
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["result"], json!({"trusted": true, "withheld": []}));
+    // 后端按工作区的配置重启，信任的信号不转发给后端
+    assert!(restart.has_changed().unwrap());
+    let next = launch.borrow().clone().unwrap();
+    assert_eq!(next.config.command, "./tools/clangd-wrapper.sh");
+    assert!(backend_rx.try_recv().is_err());
+
+    let request = json!({"jsonrpc": "2.0", "id": 3, "method": "workspace/trust",
+        "params": {"trusted": "yes"}});
+    dispatcher.handle_from_frontend(request).await.unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert!(response["error"]["message"].is_string());
+}
@@ -0,0 +1,53 @@
+use lsp_proxy::cli::CliArgs;
+use lsp_proxy::validate::{Part, ValidateMode, validate, validate_message};
+use serde_json::json;
+
+#[test]
+fn test_validate_flags() {
+    let args = CliArgs::parse(["--validate"].map(String::from)).unwrap();
+    assert_eq!(args.validate, Some(ValidateMode::Report));
+    let args = CliArgs::parse(["--validate=strict"].map(String::from)).unwrap();
+    assert_eq!(args.validate, Some(ValidateMode::Strict));
+}
+
+#[test]
+fn test_well_formed_and_unknown_messages_pass() {
+    let hover = json!({
+        "jsonrpc": "2.0", "id": 1, "method": "textDocument/hover",
+        "params": {
+            "textDocument": {"uri": "file:///a.cpp"},
+            "position": {"line": 1, "character": 2}
+        }
+    });
+    assert_eq!(validate_message("textDocument/hover", &hover), None);
+    assert_eq!(
+        validate_message(
+            "codefuse/renamePreview",
+            &json!({"method": "x", "params": 1})
+        ),
+        None
+    );
+}
+
+#[test]
+fn test_violations_are_reported_as_diff() {
+    let missing = validate(
+        "textDocument/hover",
+        Part::Params,
+        &json!({"textDocument": {"uri": "file:///a.cpp"}}),
+    )
+    .unwrap();
+    assert!(missing.is_error());
+    assert!(missing.report().contains("position"));
+
+    // clangd 的扩展字段能被解析，但会在 lsp_types 中丢失
+    let extension = validate(
+        "textDocument/hover",
+        Part::Result,
+        &json!({"contents": {"kind": "markdown", "value": "int x"}, "score": 1.5}),
+    )
+    .unwrap();
+    assert!(!extension.is_error());
+    assert_eq!(extension.diff, vec!["- /score: 1.5".to_string()]);
+    assert!(extension.report().contains("--- 收到的消息"));
+}
@@ -0,0 +1,105 @@
+use lsp_proxy::config::Config;
+use lsp_proxy::variables::Variables;
+use std::path::{Path, PathBuf};
+
+fn variables() -> Variables {
+    Variables {
+        workspace: PathBuf::from("/work/repo"),
+        home: Some(PathBuf::from("/home/dev")),
+        env: [("LLVM_HOME".to_string(), "/opt/llvm".to_string())].into(),
+    }
+}
+
+#[test]
+fn test_expand_variables() {
+    let variables = variables();
+    assert_eq!(
+        variables.expand("${env:LLVM_HOME}/bin/clangd").unwrap(),
+        "/opt/llvm/bin/clangd"
+    );
+    assert_eq!(
+        variables
+            .expand("--compile-commands-dir=${workspaceFolder}/build")
+            .unwrap(),
+        "--compile-commands-dir=/work/repo/build"
+    );
+    assert_eq!(variables.expand("~/.cache").unwrap(), "/home/dev/.cache");
+    assert_eq!(variables.expand("a~b$HOME").unwrap(), "a~b$HOME");
+    assert_eq!(
+        variables.expand_path(Path::new("~")).unwrap(),
+        PathBuf::from("/home/dev")
+    );
+
+    let error = |value: &str| variables.expand(value).unwrap_err().to_string();
+    assert_eq!(error("${env:MISSING}"), "环境变量 MISSING 没有设置");
+    assert!(error("${workspaceRoot}").contains("未知的变量 ${workspaceRoot}"));
+    assert!(error("${env:LLVM_HOME").contains("没有对应的"));
+    let homeless = Variables {
+        home: None,
+        ..variables.clone()
+    };
+    assert!(homeless.expand("~/x").is_err());
+}
+
+#[test]
+fn test_config_expands_path_and_command_fields() {
+    let mut config = Config::parse(
+        r#"
+        [backend]
+        command = "${env:LLVM_HOME}/bin/clangd"
+        args = ["--compile-commands-dir=${workspaceFolder}/out"]
+
+        [cache]
+        dir = "~/.cache/codefuse"
+
+        [[tidy]]
+        path = "${workspaceFolder}/legacy"
+        checks = "-*"
+        "#,
+    )
+    .unwrap();
+    config.expand_variables(&variables()).unwrap();
+    assert_eq!(config.backend.command, "/opt/llvm/bin/clangd");
+    assert_eq!(
+        config.backend.args,
+        ["--compile-commands-dir=/work/repo/out"]
+    );
+    assert_eq!(
+        config.cache.dir,
+        Some(PathBuf::from("/home/dev/.cache/codefuse"))
+    );
+    assert_eq!(
+        config.tidy[0].path,
+        Some(PathBuf::from("/work/repo/legacy"))
+    );
+
+    let mut config = Config::parse("[cmake]\ncommand = \"${env:CMAKE}\"\n").unwrap();
+    let error = config.expand_variables(&variables()).unwrap_err();
+    assert_eq!(
+        format!("{:#}", error),
+        "配置项 cmake.command: 环境变量 CMAKE 没有设置"
+    );
+}
+
+#[test]
+fn test_load_reports_unexpandable_variables() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".codefuse.toml");
+    std::fs::write(&path, "[flags]\ncompiler = \"${workspaceFolder}/bin/cc\"\n").unwrap();
+    let config = Config::load(&path).unwrap();
+    let workspace = std::env::current_dir().unwrap();
+    assert_eq!(
+        config.flags.compiler,
+        format!("{}/bin/cc", workspace.display())
+    );
+
+    std::fs::write(
+        &path,
+        "[[shards]]\npath = \"${env:CODEFUSE_TEST_UNSET_VARIABLE}/services\"\n",
+    )
+    .unwrap();
+    let error = format!("{:#}", Config::load(&path).unwrap_err());
+    assert!(error.contains(
+        "中的变量无法展开: 配置项 shards.path: 环境变量 CODEFUSE_TEST_UNSET_VARIABLE 没有设置"
+    ));
+}
@@ -0,0 +1,49 @@
+use lsp_proxy::config::WarmupConfig;
+use lsp_proxy::document_store::DocumentStore;
+use lsp_proxy::warmup::{RECENT_FILES_PATH, Warmup};
+use tower_lsp::lsp_types::Url;
+
+fn enabled() -> WarmupConfig {
+    WarmupConfig {
+        enabled: true,
+        max_files: 2,
+    }
+}
+
+#[test]
+fn test_recent_files_are_warmed_up_next_session() {
+    let root = tempfile::tempdir().unwrap();
+    let roots = vec![root.path().to_path_buf()];
+    for name in ["a.cpp", "b.c", "c.h"] {
+        std::fs::write(root.path().join(name), "int x;\n").unwrap();
+    }
+    let uri = |name: &str| Url::from_file_path(root.path().join(name)).unwrap();
+
+    // 第一个会话：依次编辑三个文件，只保留最近的两个
+    let session = Warmup::new(enabled());
+    session.load(&roots);
+    for name in ["a.cpp", "b.c", "c.h"] {
+        assert!(session.record(&uri(name)).is_none());
+    }
+    assert!(root.path().join(RECENT_FILES_PATH).is_file());
+
+    // 第二个会话：预热最近的两个文件，用户已经打开的跳过
+    let session = Warmup::new(enabled());
+    session.load(&roots);
+    let documents = DocumentStore::new();
+    let opened: Vec<Url> = session
+        .warm_up(&documents)
+        .into_iter()
+        .map(|(uri, did_open)| {
+            assert_eq!(did_open["method"], "textDocument/didOpen");
+            uri
+        })
+        .collect();
+    assert_eq!(opened, vec![uri("c.h"), uri("b.c")]);
+    assert!(session.is_warm(&uri("b.c")));
+
+    // 用户打开预热文档时先关闭它
+    let close = session.record(&uri("b.c")).unwrap();
+    assert_eq!(close["method"], "textDocument/didClose");
+    assert!(!session.is_warm(&uri("b.c")));
+}
@@ -0,0 +1,88 @@
+use lsp_proxy::completion_sources::{CompletionProvider, CompletionRequest};
+use lsp_proxy::config::Config;
+use lsp_proxy::dispatcher::Dispatcher;
+use lsp_proxy::document_store::{self, DocumentStore};
+use lsp_proxy::message::Message;
+use lsp_proxy::word_completion::WordCompletion;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower_lsp::lsp_types::Url;
+
+fn did_open(uri: &str, text: &str) -> Value {
+    json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": {"uri": uri, "languageId": "cpp", "version": 1, "text": text},
+    }})
+}
+
+fn request(store: &DocumentStore, uri: &str, line: u32, character: u32) -> CompletionRequest {
+    CompletionRequest {
+        params: serde_json::from_value(json!({
+            "textDocument": {"uri": uri},
+            "position": {"line": line, "character": character},
+        }))
+        .unwrap(),
+        document: store.get(&Url::parse(uri).unwrap()),
+        backend_ready: false,
+    }
+}
+
+fn labels(items: &[Value]) -> Vec<&str> {
+    items
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_identifiers() {
+    let words: Vec<_> = document_store::identifiers("int x2 = 3u + _tmp->field_1;").collect();
+    assert_eq!(words, vec!["int", "x2", "_tmp", "field_1"]);
+    assert_eq!(document_store::word_before("  foo.ba"), "ba");
+    assert_eq!(document_store::word_before("foo."), "");
+}
+
+#[tokio::test]
+async fn test_words_from_open_documents() {
+    let store = Arc::new(DocumentStore::new());
+    let a = did_open("file:///a.cpp", "int counter = 0;\nco");
+    let b = did_open("file:///b.cpp", "void compute();\nint count_all;");
+    store.apply("textDocument/didOpen", &a["params"]);
+    store.apply("textDocument/didOpen", &b["params"]);
+    let words = WordCompletion::new(Arc::clone(&store));
+
+    // 当前文档的单词在前，正在输入的单词本身不算
+    let mut request = request(&store, "file:///a.cpp", 1, 2);
+    let items = words.complete(&request).await;
+    assert_eq!(labels(&items), vec!["counter", "compute", "count_all"]);
+    assert_eq!(items[0]["kind"], 1);
+
+    // 后端就绪时不提供
+    request.backend_ready = true;
+    assert!(words.complete(&request).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_answers_while_backend_is_down() {
+    let (backend_tx, mut backend_rx) = mpsc::unbounded_channel::<Message>();
+    let (frontend_tx, mut frontend_rx) = mpsc::unbounded_channel::<Message>();
+    let config = Config::parse("[completion]\nwords = true").unwrap();
+    let dispatcher = Arc::new(Dispatcher::new(backend_tx, frontend_tx).with_config(config));
+    dispatcher
+        .handle_from_frontend(did_open("file:///a.cpp", "int widget_count;\nwid"))
+        .await
+        .unwrap();
+    backend_rx.recv().await.unwrap();
+
+    // 后端没有运行，代理不等待后端
+    let completion = json!({"jsonrpc": "2.0", "id": 4, "method": "textDocument/completion",
+        "params": {"textDocument": {"uri": "file:///a.cpp"}, "position": {"line": 1, "character": 3}}});
+    dispatcher.handle_from_frontend(completion).await.unwrap();
+    let response = frontend_rx.recv().await.unwrap().into_body();
+    assert_eq!(response["id"], 4);
+    assert_eq!(response["result"]["isIncomplete"], true);
+    assert_eq!(
+        labels(response["result"]["items"].as_array().unwrap()),
+        vec!["widget_count"]
+    );
+}
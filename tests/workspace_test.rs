@@ -0,0 +1,49 @@
+use lsp_proxy::workspace::WorkspaceFolders;
+use serde_json::json;
+use std::path::PathBuf;
+use tower_lsp::lsp_types::{Url, WorkspaceFolder, WorkspaceFoldersChangeEvent};
+
+#[test]
+fn test_initialize_folders_and_changes() {
+    let workspace = WorkspaceFolders::new();
+    workspace.set_from_initialize(&json!({
+        "workspaceFolders": [
+            {"uri": "file:///repo", "name": "main repo"},
+            {"uri": "file:///repo/third_party/lib", "name": "vendored lib"}
+        ],
+        "rootUri": "file:///ignored"
+    }));
+    // 客户端给出的名字不被目录名代替
+    let names: Vec<String> = workspace.folders().into_iter().map(|f| f.name).collect();
+    assert_eq!(names, vec!["main repo", "vendored lib"]);
+    assert_eq!(
+        workspace.roots(),
+        vec![
+            PathBuf::from("/repo"),
+            PathBuf::from("/repo/third_party/lib")
+        ]
+    );
+
+    workspace.apply_change(WorkspaceFoldersChangeEvent {
+        added: vec![WorkspaceFolder {
+            uri: Url::parse("file:///elsewhere").unwrap(),
+            name: "elsewhere".into(),
+        }],
+        removed: vec![WorkspaceFolder {
+            uri: Url::parse("file:///repo/third_party/lib/").unwrap(),
+            name: "vendored lib".into(),
+        }],
+    });
+    let names: Vec<String> = workspace.folders().into_iter().map(|f| f.name).collect();
+    assert_eq!(names, vec!["main repo", "elsewhere"]);
+}
+
+#[test]
+fn test_root_uri_is_named_after_directory() {
+    let workspace = WorkspaceFolders::new();
+    workspace.set_from_initialize(&json!({"workspaceFolders": null, "rootUri": "file:///src/app"}));
+    let folders = workspace.folders();
+    assert_eq!(folders.len(), 1);
+    assert_eq!(folders[0].name, "app");
+    assert_eq!(workspace.roots(), vec![PathBuf::from("/src/app")]);
+}